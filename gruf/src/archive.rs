@@ -1,8 +1,8 @@
 use std::io::Write;
 
 use crate::{GrufError, Result};
-use encoding::label::encoding_from_whatwg_label;
-use encoding::EncoderTrap;
+use encoding::label::{encoding_from_whatwg_label, encoding_from_windows_code_page};
+use encoding::{DecoderTrap, EncoderTrap};
 
 pub struct GenericFileEntry {
     pub offset: u64,
@@ -38,3 +38,41 @@ pub fn serialize_to_win1252(string: &str) -> Result<Vec<u8>> {
         .encode(string, EncoderTrap::Strict)
         .map_err(|_| GrufError::serialization_error("Encoding failed"))
 }
+
+/// Converts the path of an archive's entry into the path the game client
+/// uses on a system whose ANSI code page is `code_page`.
+///
+/// Entries' paths are stored as raw bytes, which are decoded as win1252 chars
+/// when reading archives. Clients open files with the system's code page
+/// though, so CP949 (Korean) paths are only readable on systems using that
+/// code page.
+pub fn transcode_relative_path(relative_path: &str, code_page: usize) -> Result<String> {
+    let raw_relative_path = serialize_to_win1252(relative_path)?;
+    let decoder = encoding_from_windows_code_page(code_page)
+        .ok_or_else(|| GrufError::parsing_error(format!("Unsupported code page {}", code_page)))?;
+    decoder
+        .decode(raw_relative_path.as_slice(), DecoderTrap::Strict)
+        .map_err(GrufError::parsing_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transcode_cp949_relative_path() {
+        // CP949 path decoded as win1252 chars, as found in `tiny.thor`
+        let relative_path = "data\\texture\\\u{c0}\u{af}\u{c0}\u{fa}\u{c0}\u{ce}\u{c5}\u{cd}\u{c6}\u{e4}\u{c0}\u{cc}\u{bd}\u{ba}\\inventory\\icon_num.bmp";
+        assert_eq!(
+            transcode_relative_path(relative_path, 949).unwrap(),
+            "data\\texture\\\u{c720}\u{c800}\u{c778}\u{d130}\u{d398}\u{c774}\u{c2a4}\\inventory\\icon_num.bmp"
+        );
+        // Western code pages keep the path as it's stored
+        assert_eq!(
+            transcode_relative_path(relative_path, 1252).unwrap(),
+            relative_path
+        );
+        // Paths which can't be represented in the code page
+        assert!(transcode_relative_path("data\\\u{c720}.txt", 949).is_err());
+    }
+}
@@ -48,7 +48,7 @@ struct SerializableGrfFileEntry200 {
 
 impl<W: Write + Seek> GrfArchiveBuilder<W> {
     pub fn create(mut obj: W, version_major: u32, version_minor: u32) -> Result<Self> {
-        let start_offset = obj.seek(io::SeekFrom::Current(0)).unwrap_or(0);
+        let start_offset = obj.stream_position().unwrap_or(0);
         // Placeholder for the GRF header
         obj.write_all(&[0; GRF_HEADER_SIZE])?;
         Ok(Self {
@@ -199,7 +199,9 @@ impl<W: Write + Seek> GrfArchiveBuilder<W> {
             (file_table_offset - GRF_HEADER_SIZE as u64) as u32,
             v_file_count,
             &mut self.obj,
-        )
+        )?;
+        // Buffered writers might still hold part of the archive
+        Ok(self.obj.flush()?)
     }
 
     fn write_grf_table_200(&mut self) -> Result<u64> {
@@ -213,7 +215,7 @@ impl<W: Write + Seek> GrfArchiveBuilder<W> {
                 entry_type: 1,
                 offset: (entry.offset - GRF_HEADER_SIZE as u64) as u32,
             };
-            serialize_as_win1252_cstr_into(&mut table, &relative_path)?;
+            serialize_as_win1252_cstr_into(&mut table, relative_path)?;
             bincode::serialize_into(&mut table, &grf_file_entry)?;
         }
         // Compress the table
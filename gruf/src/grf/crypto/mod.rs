@@ -24,7 +24,7 @@ pub fn decrypt_file_content(data: &mut Vec<u8>, cycle: usize) {
 
 fn swap_nibbles(buffer: &mut Vec<u8>) {
     for b in buffer {
-        *b = (*b << 4) | (*b >> 4);
+        *b = (*b).rotate_right(4);
     }
 }
 
@@ -22,7 +22,7 @@ pub fn list_available_chunks(archive: &mut GrfArchive) -> Result<AvailableChunkL
     }
 
     let mut entries: Vec<&GrfFileEntry> = archive.get_entries().collect();
-    entries.sort_unstable_by(|a, b| a.offset.cmp(&b.offset));
+    entries.sort_unstable_by_key(|a| a.offset);
     let mut chunks_sizes = BTreeSet::new();
     let mut available_chunks = BTreeMap::new();
     for i in 0..entries.len() - 1 {
@@ -34,6 +34,10 @@ pub fn list_available_chunks(archive: &mut GrfArchive) -> Result<AvailableChunkL
             .checked_sub(expected_entry_offset)
             .ok_or_else(|| GrufError::parsing_error("Archive is malformed"))?;
         let space_between_entries = usize::try_from(space_between_entries)?;
+        // Entries which are next to each other leave no space
+        if space_between_entries == 0 {
+            continue;
+        }
         chunks_sizes.insert((space_between_entries, expected_entry_offset));
         available_chunks.insert(
             expected_entry_offset,
@@ -146,8 +150,10 @@ impl AvailableChunkList {
         }
         // Check right merge
         if chunk_end_offset == self.end_offset {
-            // "Merge" to the right
+            // "Merge" to the right, the chunk isn't listed since allocations
+            // start from the end offset
             self.end_offset = new_chunk_offset;
+            return Ok(());
         } else if self.chunks.contains_key(&chunk_end_offset) {
             // Merge to the right with another chunk
             let chunk = self
@@ -268,6 +274,22 @@ mod tests {
         assert_eq!(offset5, offset1);
     }
 
+    #[test]
+    fn test_chunk_list_free_last_chunk() {
+        let chunk_size: usize = 64;
+        let mut chunk_list = AvailableChunkList::new();
+        let offset1 = chunk_list.alloc_chunk(chunk_size).unwrap();
+        let offset2 = chunk_list.alloc_chunk(chunk_size).unwrap();
+
+        // Free the last chunk, its space must only be handed out once
+        chunk_list.free_chunk(offset2, chunk_size).unwrap();
+        let offset3 = chunk_list.alloc_chunk(chunk_size).unwrap();
+        assert_eq!(offset3, offset2);
+        let offset4 = chunk_list.alloc_chunk(chunk_size).unwrap();
+        assert_eq!(offset4, offset3 + chunk_size as u64);
+        assert_ne!(offset4, offset1);
+    }
+
     #[test]
     fn test_chunk_list_left_merge() {
         let chunk_size: usize = 64;
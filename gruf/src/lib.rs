@@ -3,4 +3,5 @@ mod error;
 pub mod grf;
 pub mod thor;
 
+pub use archive::transcode_relative_path;
 pub use error::{GrufError, Result};
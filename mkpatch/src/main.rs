@@ -37,6 +37,12 @@ struct Opt {
         help = "Path to the output archive (default: <patch_definition_file_name>.thor)"
     )]
     output_file: Option<PathBuf>,
+    #[structopt(
+        short,
+        long,
+        help = "Embed the CRC32 of every entry, which patchers check before applying the patch (overrides 'include_checksums')"
+    )]
+    include_checksums: bool,
 }
 
 fn run(cli_args: Opt) -> Result<()> {
@@ -56,8 +62,9 @@ fn run(cli_args: Opt) -> Result<()> {
         "Processing '{}'",
         cli_args.patch_definition_file.to_string_lossy()
     );
-    let patch_definition = parse_patch_definition(&cli_args.patch_definition_file)
+    let mut patch_definition = parse_patch_definition(&cli_args.patch_definition_file)
         .context("Failed to parse the patch definition")?;
+    patch_definition.include_checksums |= cli_args.include_checksums;
 
     // Display patch info
     log::info!("GRF merging: {}", patch_definition.use_grf_merging);
@@ -0,0 +1,40 @@
+//! Patching engine of RPatchur: fetches the list of pending patches from
+//! patch servers, downloads them and applies them to the game's GRFs and
+//! files.
+//!
+//! Launchers which have their own frontend can embed it through
+//! [`patcher::Patcher`]:
+//!
+//! ```no_run
+//! use futures::StreamExt;
+//! use rpatchur_core::patcher::{retrieve_patcher_configuration, Patcher};
+//! use rpatchur_core::status::PatchingEvent;
+//!
+//! # async fn update_game() -> anyhow::Result<()> {
+//! let config = retrieve_patcher_configuration(None)?;
+//! let mut patcher = Patcher::new(config);
+//! let mut events = patcher.subscribe();
+//! tokio::spawn(async move {
+//!     while let Some(event) = events.next().await {
+//!         if let PatchingEvent::InstallationProgress {
+//!             installed_patches,
+//!             total_patches,
+//!         } = event
+//!         {
+//!             println!("{}/{} patches applied", installed_patches, total_patches);
+//!         }
+//!     }
+//! });
+//! if patcher.check().await? > 0 {
+//!     patcher.update().await;
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod patcher;
+pub mod process;
+pub mod status;
+
+const PKG_NAME: &str = env!("CARGO_PKG_NAME");
+const PKG_VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -0,0 +1,95 @@
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+
+use super::config::AccountConfiguration;
+use super::core::build_tokio_runtime;
+
+#[derive(Deserialize)]
+struct LoginResponse {
+    token: Option<String>,
+    error: Option<String>, // Message to show to the player when logging in failed
+}
+
+/// Exchanges the player's credentials for a session token, by sending them
+/// to the operator's login endpoint.
+///
+/// This blocks the calling thread until the endpoint has answered.
+pub fn authenticate(
+    config: &AccountConfiguration,
+    username: &str,
+    password: &str,
+) -> Result<String> {
+    let tokio_rt = build_tokio_runtime()?;
+    tokio_rt.block_on(request_session_token(&config.login_url, username, password))
+}
+
+async fn request_session_token(login_url: &str, username: &str, password: &str) -> Result<String> {
+    let body = serde_json::json!({
+        "username": username,
+        "password": password,
+    });
+    let resp = reqwest::Client::new()
+        .post(login_url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(body.to_string())
+        .send()
+        .await
+        .with_context(|| format!("Failed to reach '{}'", login_url))?;
+    let status = resp.status();
+    let content = resp
+        .text()
+        .await
+        .context("Failed to read the login response")?;
+    // Error responses might not be JSON at all
+    let login_response = serde_json::from_str::<LoginResponse>(&content).ok();
+    if !status.is_success() {
+        let message = login_response
+            .and_then(|r| r.error)
+            .unwrap_or_else(|| status.to_string());
+        return Err(anyhow!("Login failed: {}", message));
+    }
+    login_response
+        .and_then(|r| r.token)
+        .filter(|token| !token.is_empty())
+        .ok_or_else(|| anyhow!("Login response doesn't contain a session token"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use httptest::{matchers::*, responders::*, Expectation, Server};
+
+    #[tokio::test]
+    async fn test_request_session_token() {
+        let server = Server::run();
+        server.expect(
+            Expectation::matching(all_of![
+                request::method_path("POST", "/login"),
+                request::body(json_decoded(eq(serde_json::json!({
+                    "username": "player",
+                    "password": "hunter2",
+                })))),
+            ])
+            .respond_with(status_code(200).body(r#"{"token":"abc123"}"#)),
+        );
+        server.expect(
+            Expectation::matching(all_of![
+                request::method_path("POST", "/login"),
+                request::body(json_decoded(eq(serde_json::json!({
+                    "username": "player",
+                    "password": "wrong",
+                })))),
+            ])
+            .respond_with(status_code(401).body(r#"{"error":"Invalid password"}"#)),
+        );
+        let login_url = server.url_str("/login");
+
+        let token = request_session_token(&login_url, "player", "hunter2").await;
+        assert_eq!(token.unwrap(), "abc123");
+        let error = request_session_token(&login_url, "player", "wrong").await;
+        assert_eq!(
+            error.unwrap_err().to_string(),
+            "Login failed: Invalid password"
+        );
+    }
+}
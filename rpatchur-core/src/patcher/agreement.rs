@@ -0,0 +1,60 @@
+use anyhow::{anyhow, Result};
+
+use super::config::AgreementConfiguration;
+use super::state::{retrieve_patcher_state, update_patcher_state};
+
+/// Returns whether the player has yet to accept the current version of the
+/// operator's agreement.
+pub fn is_agreement_pending(agreement: Option<&AgreementConfiguration>) -> bool {
+    let agreement = match agreement {
+        Some(agreement) => agreement,
+        None => return false,
+    };
+    match retrieve_patcher_state() {
+        Ok(state) => !is_accepted(agreement, state.accepted_agreement.as_deref()),
+        Err(e) => {
+            log::warn!("Failed to read the patcher state: {:#}", e);
+            true
+        }
+    }
+}
+
+/// Remembers that the player accepted `agreement`, until its version
+/// changes.
+pub fn accept_agreement(agreement: &AgreementConfiguration) -> Result<()> {
+    log::info!("Agreement '{}' accepted", agreement.version);
+    update_patcher_state(|state| state.accepted_agreement = Some(agreement.version.clone()))
+}
+
+/// Fails if the player has yet to accept the operator's agreement, which
+/// has to be done before updating the game.
+pub(super) fn ensure_agreement_is_accepted(
+    agreement: Option<&AgreementConfiguration>,
+) -> Result<()> {
+    if is_agreement_pending(agreement) {
+        return Err(anyhow!("The agreement has to be accepted first"));
+    }
+    Ok(())
+}
+
+fn is_accepted(agreement: &AgreementConfiguration, accepted_version: Option<&str>) -> bool {
+    accepted_version == Some(agreement.version.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_accepted() {
+        let agreement = AgreementConfiguration {
+            version: "2".to_string(),
+            text: None,
+            url: None,
+        };
+        assert!(is_accepted(&agreement, Some("2")));
+        // Players are asked again when the terms change
+        assert!(!is_accepted(&agreement, Some("1")));
+        assert!(!is_accepted(&agreement, None));
+    }
+}
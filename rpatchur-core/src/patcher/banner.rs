@@ -0,0 +1,180 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+
+use super::config::{BannerConfiguration, BannerImageConfiguration};
+use super::paths::get_banner_cache_directory_path;
+use anyhow::{anyhow, Context, Result};
+
+/// Banner image which has been downloaded (or found in the cache).
+#[derive(Debug, PartialEq)]
+pub struct BannerImage {
+    pub path: PathBuf,
+    pub link: Option<String>,
+}
+
+/// Entry point of the thread which fetches the banner's images.
+///
+/// Images are sent to `images_tx` all at once, once they're all available.
+pub fn banner_thread_routine(
+    config: BannerConfiguration,
+    images_tx: mpsc::Sender<Vec<BannerImage>>,
+) -> Result<()> {
+    let tokio_rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .with_context(|| "Failed to build a tokio runtime")?;
+    let cache_directory_path = get_banner_cache_directory_path()?;
+    let images = tokio_rt.block_on(fetch_banner_images(&config, &cache_directory_path))?;
+    // The UI might be gone already
+    let _ = images_tx.send(images);
+    Ok(())
+}
+
+async fn fetch_banner_images(
+    config: &BannerConfiguration,
+    cache_directory_path: &Path,
+) -> Result<Vec<BannerImage>> {
+    let client = reqwest::Client::new();
+    let mut image_configs = config.images.clone();
+    if let Some(images_url) = &config.images_url {
+        match fetch_banner_image_list(&client, images_url).await {
+            Ok(remote_images) => image_configs.extend(remote_images),
+            Err(e) => log::warn!("Failed to retrieve banner image list: {:#}", e),
+        }
+    }
+
+    tokio::fs::create_dir_all(cache_directory_path)
+        .await
+        .with_context(|| "Failed to create banner cache directory")?;
+    let mut images = Vec::with_capacity(image_configs.len());
+    for image_config in image_configs {
+        let image_path = cache_directory_path.join(cached_file_name(&image_config.url));
+        if let Err(e) = download_banner_image(&client, &image_config.url, &image_path).await {
+            // Use the cached version if the server is unreachable
+            if !image_path.exists() {
+                log::warn!("Failed to download banner image: {:#}", e);
+                continue;
+            }
+        }
+        images.push(BannerImage {
+            path: image_path,
+            link: image_config.link,
+        });
+    }
+    remove_stale_images(cache_directory_path, &images).await;
+    Ok(images)
+}
+
+async fn fetch_banner_image_list(
+    client: &reqwest::Client,
+    images_url: &str,
+) -> Result<Vec<BannerImageConfiguration>> {
+    let resp = client
+        .get(images_url)
+        .send()
+        .await
+        .with_context(|| "Failed to GET URL")?;
+    if !resp.status().is_success() {
+        return Err(anyhow!("Banner image list not found on the remote server"));
+    }
+    let image_list_content = resp.text().await.with_context(|| "Invalid response body")?;
+    serde_json::from_str(&image_list_content).with_context(|| "Invalid banner image list")
+}
+
+async fn download_banner_image(
+    client: &reqwest::Client,
+    image_url: &str,
+    image_path: &Path,
+) -> Result<()> {
+    let resp = client
+        .get(image_url)
+        .send()
+        .await
+        .with_context(|| format!("Failed to download '{}'", image_url))?;
+    if !resp.status().is_success() {
+        return Err(anyhow!("'{}' not found on the remote server", image_url));
+    }
+    let content = resp
+        .bytes()
+        .await
+        .with_context(|| format!("Failed to download '{}'", image_url))?;
+    tokio::fs::write(image_path, content)
+        .await
+        .with_context(|| format!("Failed to write '{}'", image_path.display()))
+}
+
+/// Removes the cached images which aren't part of the banner anymore.
+async fn remove_stale_images(cache_directory_path: &Path, images: &[BannerImage]) {
+    let mut entries = match tokio::fs::read_dir(cache_directory_path).await {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let path = entry.path();
+        if images.iter().all(|image| image.path != path) {
+            if let Err(e) = tokio::fs::remove_file(&path).await {
+                log::warn!("Failed to remove '{}': {}", path.display(), e);
+            }
+        }
+    }
+}
+
+/// Derives a file name from an image's URL, so that different URLs don't
+/// overwrite each other's cached files.
+fn cached_file_name(image_url: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    image_url.hash(&mut hasher);
+    format!("{:016x}.png", hasher.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use httptest::{matchers::*, responders::*, Expectation, Server};
+
+    #[tokio::test]
+    async fn test_fetch_banner_images() {
+        let server = Server::run();
+        server.expect(
+            Expectation::matching(request::method_path("GET", "/banner.json")).respond_with(
+                json_encoded(serde_json::json!([
+                    { "url": server.url_str("/event.png"), "link": "https://example.com" },
+                    { "url": server.url_str("/missing.png") },
+                ])),
+            ),
+        );
+        for image_name in &["/news.png", "/event.png"] {
+            server.expect(
+                Expectation::matching(request::method_path("GET", *image_name))
+                    .respond_with(status_code(200).body(*image_name)),
+            );
+        }
+        server.expect(
+            Expectation::matching(request::method_path("GET", "/missing.png"))
+                .respond_with(status_code(404)),
+        );
+
+        let cache_directory = tempfile::tempdir().unwrap();
+        let stale_image_path = cache_directory.path().join("stale.png");
+        std::fs::write(&stale_image_path, b"").unwrap();
+        let config = BannerConfiguration {
+            images: vec![BannerImageConfiguration {
+                url: server.url_str("/news.png"),
+                link: None,
+            }],
+            images_url: Some(server.url_str("/banner.json")),
+            ..Default::default()
+        };
+        let images = fetch_banner_images(&config, cache_directory.path())
+            .await
+            .unwrap();
+        assert_eq!(images.len(), 2);
+        assert_eq!(images[0].link, None);
+        assert_eq!(std::fs::read(&images[0].path).unwrap(), b"/news.png");
+        assert_eq!(images[1].link.as_deref(), Some("https://example.com"));
+        assert_eq!(std::fs::read(&images[1].path).unwrap(), b"/event.png");
+        assert!(!stale_image_path.exists());
+    }
+}
@@ -0,0 +1,481 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use gruf::thor::{ThorPatchInfo, ThorPatchList};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::mpsc::UnboundedReceiver;
+
+use super::cancellation::InterruptibleFnError;
+use super::core::{
+    apply_patches, build_http_client, ensure_manifest_is_supported, enter_background_mode,
+    fetch_pending_patch_list, is_patch_for_client, last_applied_patch_index,
+    update_cancelled_error, wait_for_game_client_exit, UiController,
+};
+use super::download::{download_patches_concurrent, PendingPatch, StagingDirectory};
+use super::error::{ErrorKind, PatcherError};
+use super::patch_servers::{find_available_patch_server, manifest_entries};
+use super::paths::is_plain_file_name;
+use super::publish::{manifest_entry, to_hex, PatchManifest, PatchManifestEntry};
+use super::scripting::ScriptHooks;
+use super::squash::squash_bundled_patches;
+use super::update_lock::take_update_lock;
+use super::zip::{ZipArchive, ZipWriter};
+use super::{PatcherCommand, PatcherConfiguration};
+use crate::status::PatchingStatus;
+
+/// Extension of offline bundles, which are ZIP archives (the patches they
+/// contain mustn't be mistaken for the game's files).
+pub const BUNDLE_FILE_EXTENSION: &str = "rpb";
+const BUNDLE_MANIFEST_NAME: &str = "bundle.json";
+const BUNDLED_PATCHES_DIRECTORY: &str = "patches";
+
+/// Description of the patches packed in an offline bundle.
+#[derive(Serialize, Deserialize, Debug)]
+struct BundleManifest {
+    pub server_id: String, // Patch server the patches come from
+    #[serde(flatten)]
+    pub manifest: PatchManifest,
+}
+
+/// Packs patches into an offline bundle at `bundle_path`, along with the
+/// entries which describe them.
+fn write_bundle(
+    bundle_path: &Path,
+    server_id: &str,
+    min_patcher_version: Option<String>,
+    patches: Vec<(PatchManifestEntry, PathBuf)>,
+) -> Result<()> {
+    let res = write_bundle_file(bundle_path, server_id, min_patcher_version, patches);
+    if res.is_err() {
+        let _ = fs::remove_file(bundle_path);
+    }
+    res
+}
+
+fn write_bundle_file(
+    bundle_path: &Path,
+    server_id: &str,
+    min_patcher_version: Option<String>,
+    patches: Vec<(PatchManifestEntry, PathBuf)>,
+) -> Result<()> {
+    let bundle_file = File::create(bundle_path)
+        .with_context(|| format!("Failed to create '{}'", bundle_path.display()))?;
+    let mut writer = ZipWriter::new(BufWriter::new(bundle_file));
+    let (entries, patch_paths): (Vec<_>, Vec<_>) = patches.into_iter().unzip();
+    let manifest = BundleManifest {
+        server_id: server_id.to_string(),
+        manifest: PatchManifest {
+            min_patcher_version,
+            patches: entries,
+        },
+    };
+    writer.add_file(BUNDLE_MANIFEST_NAME, &serde_json::to_vec_pretty(&manifest)?)?;
+    for (entry, patch_path) in manifest.manifest.patches.iter().zip(patch_paths) {
+        let mut patch_file = File::open(&patch_path)
+            .with_context(|| format!("Failed to open '{}'", patch_path.display()))?;
+        // Patches are compressed already
+        writer
+            .add_stored_file(&bundled_patch_name(&entry.file_name), &mut patch_file)
+            .with_context(|| format!("Failed to add '{}' to the bundle", entry.file_name))?;
+    }
+    writer.finish()?.flush()?;
+    Ok(())
+}
+
+/// Offline bundle, whose patches are applied without reaching the patch
+/// servers.
+struct PatchBundle {
+    archive: ZipArchive<BufReader<File>>,
+    pub manifest: BundleManifest,
+}
+
+impl PatchBundle {
+    fn open(bundle_path: &Path) -> Result<Self> {
+        let mut archive = ZipArchive::open(bundle_path)?;
+        let manifest_entry = archive
+            .entries()
+            .iter()
+            .find(|entry| entry.name == BUNDLE_MANIFEST_NAME)
+            .cloned()
+            .ok_or_else(|| anyhow!("'{}' isn't a patch bundle", bundle_path.display()))?;
+        let manifest: BundleManifest =
+            serde_json::from_slice(&archive.read_file_content(&manifest_entry)?)
+                .with_context(|| "Invalid bundle manifest")?;
+        // Patches are extracted under their name, which mustn't lead them out
+        // of the staging directory
+        for entry in &manifest.manifest.patches {
+            if !is_plain_file_name(&entry.file_name) {
+                return Err(anyhow!(
+                    "Invalid patch name '{}' in the bundle",
+                    entry.file_name
+                ));
+            }
+        }
+        Ok(Self { archive, manifest })
+    }
+
+    /// Extracts one of the bundle's patches to `patch_path`, checking it
+    /// against its entry in the manifest.
+    fn extract_patch(&mut self, entry: &PatchManifestEntry, patch_path: &Path) -> Result<()> {
+        let bundled_name = bundled_patch_name(&entry.file_name);
+        let zip_entry = self
+            .archive
+            .entries()
+            .iter()
+            .find(|zip_entry| zip_entry.name == bundled_name)
+            .cloned()
+            .ok_or_else(|| anyhow!("'{}' is missing from the bundle", entry.file_name))?;
+        let mut patch_file = File::create(patch_path)
+            .with_context(|| format!("Failed to create '{}'", patch_path.display()))?;
+        self.archive
+            .extract_file_to(&zip_entry, &mut patch_file)
+            .map_err(|e| corrupt_patch_error(entry, e))?;
+
+        let mut hasher = Sha256::new();
+        let size = io::copy(&mut File::open(patch_path)?, &mut hasher)?;
+        if size != entry.size || !to_hex(&hasher.finalize()).eq_ignore_ascii_case(&entry.sha256) {
+            return Err(corrupt_patch_error(
+                entry,
+                anyhow!("Checksum mismatch with the manifest"),
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn corrupt_patch_error(entry: &PatchManifestEntry, error: anyhow::Error) -> anyhow::Error {
+    anyhow!(PatcherError::new(
+        ErrorKind::Integrity,
+        format!(
+            "Patch '{}' of the bundle is corrupt: {:#}",
+            entry.file_name, error
+        ),
+    ))
+}
+
+fn bundled_patch_name(file_name: &str) -> String {
+    format!("{}/{}", BUNDLED_PATCHES_DIRECTORY, file_name)
+}
+
+/// Downloads the pending patches, or all of them, and packs them into an
+/// offline bundle for game clients which can't reach the patch servers.
+pub(super) async fn export_bundle(
+    config: &PatcherConfiguration,
+    ui_controller: &UiController,
+    patching_thread_rx: &mut UnboundedReceiver<PatcherCommand>,
+    bundle_path: &Path,
+    all_patches: bool,
+    squash: bool,
+) {
+    ui_controller.set_patching_in_progress(true);
+    let res = download_bundle_patches(
+        config,
+        ui_controller,
+        patching_thread_rx,
+        bundle_path,
+        all_patches,
+        squash,
+    )
+    .await;
+    match res {
+        Ok(patch_count) => {
+            tracing::info!(
+                "{} patch(es) exported to '{}'",
+                patch_count,
+                bundle_path.display()
+            );
+            ui_controller.dispatch_patching_status(PatchingStatus::BundleExported(
+                bundle_path.to_path_buf(),
+            ));
+        }
+        Err(err) => {
+            tracing::error!("Failed to export the bundle: {:#}", err);
+            ui_controller.dispatch_patching_status(PatchingStatus::Error((&err).into()));
+        }
+    }
+}
+
+/// Returns the number of patches written to the bundle.
+async fn download_bundle_patches(
+    config: &PatcherConfiguration,
+    ui_controller: &UiController,
+    patching_thread_rx: &mut UnboundedReceiver<PatcherCommand>,
+    bundle_path: &Path,
+    all_patches: bool,
+    squash: bool,
+) -> Result<usize> {
+    let client = build_http_client(&config.web)?;
+    let listing = if all_patches {
+        find_available_patch_server(
+            &client,
+            &config.web,
+            config.patching.channel(),
+            patching_thread_rx,
+        )
+        .await
+        .map_err(|e| match e {
+            InterruptibleFnError::Err(e) => e,
+            InterruptibleFnError::Interrupted => anyhow!("Export was canceled"),
+        })?
+    } else {
+        fetch_pending_patch_list(config, &client, patching_thread_rx).await?
+    };
+    let patch_manifest = manifest_entries(listing.manifest.as_ref());
+    // Requirements go along with the patches, bundles are applied offline
+    let manifest = listing.manifest.unwrap_or_default();
+
+    let staging_directory = StagingDirectory::create(config.patching.staging_directory())
+        .with_context(|| "Failed to create staging directory")?;
+    let (downloaded_patches, failed_patches) = download_patches_concurrent(
+        &client,
+        listing.patch_url,
+        &patch_manifest,
+        listing.patch_list,
+        staging_directory.path(),
+        config,
+        ui_controller,
+        patching_thread_rx,
+    )
+    .await
+    .map_err(|e| match e {
+        InterruptibleFnError::Err(e) => e.context("Failed to download patches"),
+        InterruptibleFnError::Interrupted => anyhow!("Export was canceled"),
+    })?;
+    // Bundles with holes couldn't be applied
+    if let Some(failed_patch) = failed_patches.into_iter().next() {
+        return Err(failed_patch.error.context(format!(
+            "Failed to download '{}'",
+            failed_patch.info.file_name
+        )));
+    }
+
+    let mut bundled_patches = Vec::with_capacity(downloaded_patches.len());
+    for patch in downloaded_patches {
+        let mut entry = manifest_entry(
+            patch.info.index,
+            &patch.info.file_name,
+            &patch.local_file_path,
+        )?;
+        if let Some(manifest_entry) = manifest
+            .patches
+            .iter()
+            .find(|manifest_entry| manifest_entry.file_name == entry.file_name)
+        {
+            entry.min_launcher_version = manifest_entry.min_launcher_version.clone();
+            entry.tags = manifest_entry.tags.clone();
+        }
+        bundled_patches.push((entry, patch.local_file_path));
+    }
+    if squash {
+        bundled_patches = squash_bundled_patches(bundled_patches, staging_directory.path())
+            .with_context(|| "Failed to squash patches")?;
+    }
+    let patch_count = bundled_patches.len();
+    write_bundle(
+        bundle_path,
+        &config.web.server_id(),
+        manifest.min_patcher_version,
+        bundled_patches,
+    )
+    .with_context(|| format!("Failed to write '{}'", bundle_path.display()))?;
+    Ok(patch_count)
+}
+
+/// Applies the patches of an offline bundle which haven't been applied yet,
+/// like an update would.
+pub(super) async fn apply_bundle(
+    config: &PatcherConfiguration,
+    ui_controller: &UiController,
+    patching_thread_rx: &mut UnboundedReceiver<PatcherCommand>,
+    bundle_path: &Path,
+) {
+    let lock_file = match take_update_lock().with_context(|| "Failed to take the update lock") {
+        Ok(lock_file) => lock_file,
+        Err(err) => {
+            tracing::error!("{:#}", err);
+            ui_controller.dispatch_patching_status(PatchingStatus::Error((&err).into()));
+            return;
+        }
+    };
+    // Tell the UI and other processes that we're currently working
+    ui_controller.set_patching_in_progress(true);
+    let _background_mode = enter_background_mode(config);
+    let _guard = scopeguard::guard((), |_| {
+        drop(lock_file);
+        ui_controller.set_patching_in_progress(false);
+    });
+
+    let hooks = ScriptHooks::load(&config.scripting);
+    let res = apply_bundle_patches(
+        config,
+        &hooks,
+        ui_controller,
+        patching_thread_rx,
+        bundle_path,
+    )
+    .await;
+    match res {
+        Ok(patch_count) => {
+            tracing::info!(
+                "{} patch(es) applied from '{}'",
+                patch_count,
+                bundle_path.display()
+            );
+            ui_controller.dispatch_patching_status(PatchingStatus::BundleApplied(patch_count));
+        }
+        Err(err) if ErrorKind::of(&err) == ErrorKind::Cancelled => {
+            ui_controller.dispatch_cancellation();
+        }
+        Err(err) => {
+            tracing::error!("Failed to apply the bundle: {:#}", err);
+            ui_controller.dispatch_patching_status(PatchingStatus::Error((&err).into()));
+        }
+    }
+}
+
+/// Returns the number of patches that have been applied.
+async fn apply_bundle_patches(
+    config: &PatcherConfiguration,
+    hooks: &ScriptHooks,
+    ui_controller: &UiController,
+    patching_thread_rx: &mut UnboundedReceiver<PatcherCommand>,
+    bundle_path: &Path,
+) -> Result<usize> {
+    let mut bundle = PatchBundle::open(bundle_path)?;
+    let server_id = config.web.server_id();
+    if bundle.manifest.server_id != server_id {
+        return Err(anyhow!(
+            "The bundle contains the patches of another server ('{}')",
+            bundle.manifest.server_id
+        ));
+    }
+    let mut entries = bundle.manifest.manifest.patches.clone();
+    entries.sort_by_key(|entry| entry.index);
+    entries.retain(|entry| is_patch_for_client(entry, &config.client));
+    if let Some(last_patch_index) = last_applied_patch_index(config) {
+        entries.retain(|entry| entry.index > last_patch_index);
+    }
+    // Hold the game at the pinned patch
+    if let Some(pin_index) = config.patching.pin_index {
+        entries.retain(|entry| entry.index <= pin_index);
+    }
+    let patch_list: ThorPatchList = entries
+        .iter()
+        .map(|entry| ThorPatchInfo {
+            index: entry.index,
+            file_name: entry.file_name.clone(),
+        })
+        .collect();
+    ensure_manifest_is_supported(&bundle.manifest.manifest, &patch_list)?;
+    tracing::info!("{} patch(es) to apply from the bundle", patch_list.len());
+
+    let staging_directory = StagingDirectory::create(config.patching.staging_directory())
+        .with_context(|| "Failed to create staging directory")?;
+    let mut pending_patch_queue = Vec::with_capacity(entries.len());
+    for (entry, patch_info) in entries.iter().zip(patch_list) {
+        let local_file_path = staging_directory.path().join(&entry.file_name);
+        bundle.extract_patch(entry, &local_file_path)?;
+        pending_patch_queue.push(PendingPatch {
+            info: patch_info,
+            local_file_path,
+            // Checked against the bundle's manifest when extracted
+            unverified: false,
+        });
+    }
+    let patch_count = pending_patch_queue.len();
+    if patch_count == 0 {
+        return Ok(0);
+    }
+
+    // Patching files the game client holds open would corrupt them
+    wait_for_game_client_exit(config, ui_controller, patching_thread_rx)
+        .await
+        .map_err(|e| match e {
+            InterruptibleFnError::Err(e) => e,
+            InterruptibleFnError::Interrupted => update_cancelled_error(),
+        })?;
+    apply_patches(
+        pending_patch_queue,
+        config,
+        hooks,
+        ui_controller,
+        patching_thread_rx,
+    )
+    .await
+    .map_err(|e| match e {
+        InterruptibleFnError::Err(e) => e.context("Failed to apply patches"),
+        InterruptibleFnError::Interrupted => update_cancelled_error(),
+    })?;
+    Ok(patch_count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_and_open_bundle() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let mut patches = vec![];
+        for (index, content) in [b"ASSF patch 1".to_vec(), b"ASSF patch 2".to_vec()]
+            .iter()
+            .enumerate()
+        {
+            let file_name = format!("{}.thor", index);
+            let patch_path = tmp_dir.path().join(&file_name);
+            fs::write(&patch_path, content).unwrap();
+            let entry = PatchManifestEntry {
+                index,
+                file_name,
+                size: content.len() as u64,
+                sha256: to_hex(&Sha256::digest(content)),
+                ..Default::default()
+            };
+            patches.push((entry, patch_path));
+        }
+        let bundle_path = tmp_dir.path().join("offline.rpb");
+        write_bundle(&bundle_path, "server", None, patches).unwrap();
+
+        let mut bundle = PatchBundle::open(&bundle_path).unwrap();
+        assert_eq!(bundle.manifest.server_id, "server");
+        let mut entries = bundle.manifest.manifest.patches.clone();
+        assert_eq!(entries.len(), 2);
+        let extracted_path = tmp_dir.path().join("extracted.thor");
+        bundle.extract_patch(&entries[1], &extracted_path).unwrap();
+        assert_eq!(fs::read(&extracted_path).unwrap(), b"ASSF patch 2");
+
+        // Patches which don't match the manifest are rejected
+        entries[0].sha256 = entries[1].sha256.clone();
+        let error = bundle
+            .extract_patch(&entries[0], &extracted_path)
+            .unwrap_err();
+        assert_eq!(ErrorKind::of(&error), ErrorKind::Integrity);
+
+        // Patch names can't lead out of the staging directory
+        for file_name in ["../evil.dll", "/tmp/evil.dll", "..\\evil.dll", "C:evil.dll"] {
+            let mut entry = entries[0].clone();
+            entry.file_name = file_name.to_string();
+            let traversal_bundle_path = tmp_dir.path().join("traversal.rpb");
+            write_bundle(
+                &traversal_bundle_path,
+                "server",
+                None,
+                vec![(entry, tmp_dir.path().join("0.thor"))],
+            )
+            .unwrap();
+            assert!(PatchBundle::open(&traversal_bundle_path).is_err());
+        }
+
+        // Regular ZIP archives aren't bundles
+        let zip_path = tmp_dir.path().join("patch.zip");
+        let mut writer = ZipWriter::new(File::create(&zip_path).unwrap());
+        writer
+            .add_file("data/clientinfo.xml", b"<clientinfo/>")
+            .unwrap();
+        writer.finish().unwrap();
+        assert!(PatchBundle::open(&zip_path).is_err());
+    }
+}
@@ -0,0 +1,83 @@
+use crate::patcher::PatcherCommand;
+use std::fmt;
+use tokio::sync::mpsc::error::TryRecvError;
+use tokio::sync::mpsc::UnboundedReceiver;
+
+#[derive(Debug)]
+pub enum InterruptibleFnError {
+    Err(anyhow::Error),
+    Interrupted,
+}
+
+impl fmt::Display for InterruptibleFnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InterruptibleFnError::Err(e) => write!(f, "{:#}", e),
+            InterruptibleFnError::Interrupted => write!(f, "Operation was interrupted"),
+        }
+    }
+}
+
+pub type InterruptibleFnResult<T> = Result<T, InterruptibleFnError>;
+
+pub fn process_incoming_commands(
+    patching_thread_rx: &mut UnboundedReceiver<PatcherCommand>,
+) -> Result<(), InterruptibleFnError> {
+    match patching_thread_rx.try_recv() {
+        Ok(PatcherCommand::CancelUpdate) => Err(InterruptibleFnError::Interrupted),
+        Ok(PatcherCommand::ForceCancelUpdate) => Err(InterruptibleFnError::Interrupted),
+        Ok(PatcherCommand::Quit) => Err(InterruptibleFnError::Interrupted),
+        Ok(_) => Ok(()),
+        Err(TryRecvError::Empty) => Ok(()),
+        Err(TryRecvError::Disconnected) => Err(InterruptibleFnError::Interrupted),
+    }
+}
+
+/// Waits until the task is canceled, without blocking the runtime, so that
+/// it can be raced against the task (e.g. with `tokio::select!`).
+///
+/// The task's future is dropped as soon as the command is received, which
+/// aborts pending requests right away.
+pub async fn wait_for_cancellation(
+    patching_thread_rx: &mut UnboundedReceiver<PatcherCommand>,
+) -> InterruptibleFnError {
+    loop {
+        match patching_thread_rx.recv().await {
+            Some(PatcherCommand::CancelUpdate)
+            | Some(PatcherCommand::ForceCancelUpdate)
+            | Some(PatcherCommand::Quit)
+            | None => return InterruptibleFnError::Interrupted,
+            // Other commands make no sense while the task is running
+            Some(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::sync::mpsc::unbounded_channel;
+
+    #[tokio::test]
+    async fn test_wait_for_cancellation() {
+        let (patching_thread_tx, mut patching_thread_rx) = unbounded_channel();
+        // Other commands don't cancel the task
+        patching_thread_tx.send(PatcherCommand::ResetCache).unwrap();
+        let wait = wait_for_cancellation(&mut patching_thread_rx);
+        assert!(tokio::time::timeout(Duration::from_millis(50), wait)
+            .await
+            .is_err());
+
+        // Commands sent from other threads wake the task up
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            let _ = patching_thread_tx.send(PatcherCommand::CancelUpdate);
+        });
+        let res = tokio::select! {
+            cancel_res = wait_for_cancellation(&mut patching_thread_rx) => Err(cancel_res),
+            _ = tokio::time::sleep(Duration::from_secs(10)) => Ok(()),
+        };
+        assert!(matches!(res, Err(InterruptibleFnError::Interrupted)));
+    }
+}
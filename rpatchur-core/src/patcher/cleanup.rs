@@ -0,0 +1,208 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use walkdir::WalkDir;
+
+use super::config::CleanupConfiguration;
+use super::state::InstalledFileManifest;
+use crate::status::OrphanedFile;
+
+/// Looks for the files of `game_directory` which neither the base client
+/// nor any of the applied patches contain, and are thus leftovers of removed
+/// content.
+pub(super) async fn find_orphaned_files(
+    client: &reqwest::Client,
+    config: &CleanupConfiguration,
+    installed_files: &InstalledFileManifest,
+    game_directory: &Path,
+) -> Result<Vec<OrphanedFile>> {
+    let base_manifest_url = config
+        .base_manifest_url
+        .as_ref()
+        .ok_or_else(|| anyhow!("No base manifest is configured ('cleanup.base_manifest_url')"))?;
+    let base_manifest = client
+        .get(base_manifest_url)
+        .send()
+        .await
+        .and_then(|resp| resp.error_for_status())
+        .with_context(|| format!("Failed to fetch '{}'", base_manifest_url))?
+        .text()
+        .await?;
+
+    let mut known_files: HashSet<String> = base_manifest
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(normalize_path)
+        .collect();
+    known_files.extend(
+        installed_files
+            .files
+            .keys()
+            .map(|path| normalize_path(path)),
+    );
+    // Patched GRFs are files of the game directory, unlike their entries
+    known_files.extend(
+        installed_files
+            .grf_entries
+            .keys()
+            .map(|path| normalize_path(path)),
+    );
+    Ok(list_unknown_files(
+        game_directory,
+        &config.directories(),
+        &known_files,
+    ))
+}
+
+/// Removes the orphaned files, returning how many have been removed and the
+/// number of bytes freed.
+pub(super) fn remove_orphaned_files(
+    game_directory: &Path,
+    orphaned_files: &[OrphanedFile],
+) -> (usize, u64) {
+    let mut removed_count = 0;
+    let mut freed_bytes = 0;
+    for orphaned_file in orphaned_files {
+        let file_path = game_directory.join(orphaned_file.relative_path.replace('\\', "/"));
+        match fs::remove_file(&file_path) {
+            Ok(()) => {
+                tracing::info!("Removed '{}'", orphaned_file.relative_path);
+                removed_count += 1;
+                freed_bytes += orphaned_file.size;
+            }
+            Err(e) => tracing::warn!("Failed to remove '{}': {}", file_path.display(), e),
+        }
+    }
+    (removed_count, freed_bytes)
+}
+
+/// Lists the files contained in `directories` (relative to `game_directory`)
+/// which aren't in `known_files`, by relative path.
+fn list_unknown_files(
+    game_directory: &Path,
+    directories: &[String],
+    known_files: &HashSet<String>,
+) -> Vec<OrphanedFile> {
+    let mut unknown_files = vec![];
+    for directory in directories {
+        for entry in WalkDir::new(game_directory.join(directory))
+            .into_iter()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_file())
+        {
+            let relative_path = match entry.path().strip_prefix(game_directory) {
+                Ok(relative_path) => relative_path,
+                Err(_) => continue,
+            };
+            // Windows-style, like the paths of patches
+            let relative_path: Vec<String> = relative_path
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy().to_string())
+                .collect();
+            let relative_path = relative_path.join("\\");
+            if known_files.contains(&normalize_path(&relative_path)) {
+                continue;
+            }
+            let size = entry.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+            unknown_files.push(OrphanedFile {
+                relative_path,
+                size,
+            });
+        }
+    }
+    unknown_files.sort_by(|a, b| a.relative_path.cmp(&b.relative_path));
+    unknown_files.dedup();
+    unknown_files
+}
+
+/// Returns the key paths are compared with, since paths are case-insensitive
+/// on Windows and lists might use either separator.
+fn normalize_path(path: &str) -> String {
+    path.trim_start_matches("./")
+        .trim_start_matches(".\\")
+        .replace('/', "\\")
+        .to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use httptest::{matchers::*, responders::*, Expectation, Server};
+
+    use crate::patcher::InstalledFile;
+
+    #[tokio::test]
+    async fn test_find_and_remove_orphaned_files() {
+        let game_directory = tempfile::tempdir().unwrap();
+        for relative_path in [
+            "data/clientinfo.xml",
+            "data/texture/base.bmp",
+            "data/texture/patched.bmp",
+            "data/texture/removed.bmp",
+            "data/sprite/Old.spr",
+            "screenshots/shot.jpg",
+        ] {
+            let file_path = game_directory.path().join(relative_path);
+            fs::create_dir_all(file_path.parent().unwrap()).unwrap();
+            fs::write(&file_path, relative_path).unwrap();
+        }
+        let server = Server::run();
+        server.expect(
+            Expectation::matching(request::method_path("GET", "/base.txt")).respond_with(
+                status_code(200)
+                    .body("# Base client\ndata/clientinfo.xml\r\nDATA\\texture\\base.bmp\n"),
+            ),
+        );
+        let config = CleanupConfiguration {
+            base_manifest_url: Some(server.url_str("/base.txt")),
+            directories: vec![],
+        };
+        let mut installed_files = InstalledFileManifest::default();
+        installed_files.files.insert(
+            "data\\texture\\patched.bmp".to_string(),
+            InstalledFile {
+                size: 0,
+                crc32: 0,
+                patch: "1.thor".to_string(),
+            },
+        );
+        let client = reqwest::Client::new();
+
+        let orphaned_files =
+            find_orphaned_files(&client, &config, &installed_files, game_directory.path())
+                .await
+                .unwrap();
+        // Only the 'data' directory is checked by default
+        assert_eq!(
+            orphaned_files,
+            [
+                OrphanedFile {
+                    relative_path: "data\\sprite\\Old.spr".to_string(),
+                    size: 19,
+                },
+                OrphanedFile {
+                    relative_path: "data\\texture\\removed.bmp".to_string(),
+                    size: 24,
+                },
+            ]
+        );
+
+        assert_eq!(
+            remove_orphaned_files(game_directory.path(), &orphaned_files),
+            (2, 43)
+        );
+        assert!(!game_directory.path().join("data/sprite/Old.spr").exists());
+        assert!(game_directory.path().join("data/texture/base.bmp").exists());
+
+        // Nothing can be told apart without the base client's files
+        let config = CleanupConfiguration::default();
+        assert!(
+            find_orphaned_files(&client, &config, &installed_files, game_directory.path())
+                .await
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,968 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use super::notifications::{default_webhook_events, WebhookEvent};
+use super::paths::get_configuration_file_path;
+use crate::process::current_platform;
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use url::Url;
+
+#[derive(Deserialize, Clone)]
+pub struct PatcherConfiguration {
+    pub window: WindowConfiguration,
+    pub play: PlayConfiguration,
+    pub setup: SetupConfiguration,
+    pub web: WebConfiguration,
+    pub client: ClientConfiguration,
+    pub patching: PatchingConfiguration,
+    #[serde(default)]
+    pub localization: LocalizationConfiguration,
+    #[serde(default)]
+    pub tray: TrayConfiguration,
+    #[serde(default)]
+    pub buttons: Vec<LaunchButtonConfiguration>, // Additional launch buttons
+    #[serde(default)]
+    pub banner: BannerConfiguration,
+    #[serde(default)]
+    pub logging: LoggingConfiguration,
+    pub account: Option<AccountConfiguration>, // Log players in before launching the game
+    pub agreement: Option<AgreementConfiguration>, // Terms players accept before playing or updating
+    #[serde(default)]
+    pub variables: VariablesConfiguration, // Placeholders usable in launch arguments
+    #[serde(default)]
+    pub notifications: NotificationsConfiguration,
+    #[serde(default)]
+    pub control_api: ControlApiConfiguration,
+    #[serde(default)]
+    pub scripting: ScriptingConfiguration,
+    #[serde(default)]
+    pub maintenance: MaintenanceConfiguration,
+    #[serde(default)]
+    pub motd: MotdConfiguration,
+    pub installer: Option<InstallerConfiguration>, // Installs the game on machines which don't have it yet
+    #[serde(default)]
+    pub peer_cache: PeerCacheConfiguration,
+    #[serde(default)]
+    pub storage: StorageConfiguration,
+    #[serde(default)]
+    pub cleanup: CleanupConfiguration,
+    #[serde(default)]
+    pub postprocess: PostprocessConfiguration,
+    #[serde(default)]
+    pub wine: WineConfiguration,
+    #[serde(default)]
+    pub advanced: AdvancedConfiguration,
+}
+
+impl PatcherConfiguration {
+    /// Returns the launch buttons to display, in order.
+    ///
+    /// "Play" and "Setup" come first, followed by the operator-defined buttons.
+    pub fn launch_buttons(&self) -> Vec<LaunchButtonConfiguration> {
+        // The game client and its setup have always been run elevated
+        let play_button = LaunchButtonConfiguration {
+            label: "button.play".to_string(),
+            icon: None,
+            path: self.play.path.clone(),
+            arguments: self.play.arguments.clone(),
+            elevated: true,
+            exit_on_success: Some(
+                self.play.exit_on_success.unwrap_or(false) || self.play.exit_patcher_on_launch,
+            ),
+            block_until_patched: self.play.block_until_patched,
+            block_during_maintenance: self.maintenance.block_play,
+            pass_session_token: self.account.is_some(),
+            return_on_exit: self.play.return_on_exit,
+            check_for_updates_on_return: self.play.check_for_updates_on_return,
+            working_directory: self.play.working_directory.clone(),
+            environment: self.play.environment.clone(),
+        };
+        let setup_button = LaunchButtonConfiguration {
+            label: "button.setup".to_string(),
+            icon: None,
+            path: self.setup.path.clone(),
+            arguments: self.setup.arguments.clone(),
+            elevated: true,
+            exit_on_success: self.setup.exit_on_success,
+            block_until_patched: false,
+            block_during_maintenance: false,
+            pass_session_token: false,
+            return_on_exit: false,
+            check_for_updates_on_return: false,
+            working_directory: self.setup.working_directory.clone(),
+            environment: self.setup.environment.clone(),
+        };
+        let mut buttons = vec![play_button, setup_button];
+        buttons.extend(self.buttons.iter().cloned());
+        buttons
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct WindowConfiguration {
+    pub title: String,
+    pub width: i32,
+    pub height: i32,
+    pub resizable: bool,
+    pub min_width: Option<i32>,  // Minimum width of resizable windows
+    pub min_height: Option<i32>, // Minimum height of resizable windows
+    #[serde(default)]
+    pub close_to_tray: bool, // Hide the window instead of quitting, if `tray` is enabled
+    #[serde(default = "default_decorations")]
+    pub decorations: bool, // Set to false for a frameless window, drawn by the patcher
+    #[serde(default)]
+    pub transparent: bool, // Per-pixel transparency, for frameless windows
+    pub skin: Option<SkinConfiguration>, // Appearance of frameless windows
+}
+
+fn default_decorations() -> bool {
+    true
+}
+
+#[derive(Deserialize, Clone, Default)]
+pub struct SkinConfiguration {
+    pub background: Option<String>, // PNG image drawn behind the content, its alpha defines the window's shape
+    #[serde(default)]
+    pub drag_regions: Vec<SkinRegion>, // Areas used to move the window, the whole window if empty
+    pub close_button: Option<SkinRegion>, // Area of the background acting as a close button
+    pub minimize_button: Option<SkinRegion>, // Area of the background acting as a minimize button
+}
+
+/// Rectangle in logical pixels, relative to the top-left corner of the window.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct SkinRegion {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl SkinRegion {
+    pub fn contains(&self, x: f32, y: f32) -> bool {
+        x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct PlayConfiguration {
+    pub path: String,
+    pub arguments: Vec<String>,
+    pub exit_on_success: Option<bool>,
+    #[serde(default)]
+    pub exit_patcher_on_launch: bool, // Close the patcher once the game has been started
+    #[serde(default)]
+    pub block_until_patched: bool, // Disable the button until the game is up to date
+    #[serde(default)]
+    pub return_on_exit: bool, // Hide the patcher while the game runs and show it again once it exits
+    #[serde(default)]
+    pub check_for_updates_on_return: bool, // Check for updates once the game has exited
+    pub working_directory: Option<String>, // Relative to the game directory, which is the default
+    #[serde(default)]
+    pub environment: HashMap<String, String>, // Extra environment variables (e.g. __COMPAT_LAYER)
+}
+
+#[derive(Deserialize, Clone)]
+pub struct SetupConfiguration {
+    pub path: String,
+    pub arguments: Vec<String>,
+    pub exit_on_success: Option<bool>,
+    pub working_directory: Option<String>, // Relative to the game directory, which is the default
+    #[serde(default)]
+    pub environment: HashMap<String, String>, // Extra environment variables (e.g. __COMPAT_LAYER)
+}
+
+#[derive(Deserialize, Clone)]
+pub struct LaunchButtonConfiguration {
+    pub label: String,        // Text of the button, can be a translation key
+    pub icon: Option<String>, // Text displayed before the label (e.g. an emoji)
+    pub path: String,         // Executable to start or URL to open
+    #[serde(default)]
+    pub arguments: Vec<String>,
+    #[serde(default)]
+    pub elevated: bool, // Run the executable as administrator (Windows only)
+    pub exit_on_success: Option<bool>,
+    #[serde(default)]
+    pub block_until_patched: bool, // Disable the button until the game is up to date
+    #[serde(default)]
+    pub block_during_maintenance: bool, // Disable the button while the game server is in maintenance
+    #[serde(default)]
+    pub pass_session_token: bool, // Require players to log in and pass their session token
+    #[serde(default)]
+    pub return_on_exit: bool, // Hide the patcher while the program runs and show it again once it exits
+    #[serde(default)]
+    pub check_for_updates_on_return: bool, // Check for updates once the program has exited
+    pub working_directory: Option<String>, // Relative to the game directory, which is the default
+    #[serde(default)]
+    pub environment: HashMap<String, String>, // Extra environment variables (e.g. __COMPAT_LAYER)
+}
+
+impl LaunchButtonConfiguration {
+    pub fn is_url(&self) -> bool {
+        self.path.starts_with("http://") || self.path.starts_with("https://")
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct WebConfiguration {
+    pub preferred_patch_server: Option<String>, // Name of the patch server to use in priority
+    pub patch_servers: Vec<PatchServerInfo>,
+    pub server_id: Option<String>, // Identifies the patches served by `patch_servers`, the first server's host by default
+    #[serde(default)]
+    pub require_https: bool, // Rejects patch servers' 'http://' URLs, as well as redirects to them
+    pub max_redirects: Option<usize>, // Redirects followed per request, 10 by default
+    #[serde(default)]
+    pub same_host_redirects_only: bool, // Rejects redirects to other hosts than the requested one
+    pub probe_cache_ttl: Option<u64>, // In seconds, reuses the last working server's patch list for that long
+    #[serde(skip)]
+    pub forced_patch_server: Option<String>, // Chosen by the user, used in priority over the preferred one
+}
+
+impl WebConfiguration {
+    /// Returns the name of the patch server to use in priority, if any.
+    pub fn preferred_patch_server(&self) -> &Option<String> {
+        if self.forced_patch_server.is_some() {
+            &self.forced_patch_server
+        } else {
+            &self.preferred_patch_server
+        }
+    }
+
+    /// Returns the identity of the patch servers, under which their patch
+    /// index and downloaded patches are stored.
+    ///
+    /// Mirrors serve the same patches, so they share the same identity.
+    pub fn server_id(&self) -> String {
+        let server_id = match &self.server_id {
+            Some(server_id) => server_id.clone(),
+            None => self
+                .patch_servers
+                .first()
+                .and_then(|server| Url::parse(&server.plist_url).ok())
+                .and_then(|url| {
+                    let host = url.host_str()?;
+                    Some(match url.port() {
+                        Some(port) => format!("{}_{}", host, port),
+                        None => host.to_string(),
+                    })
+                })
+                .unwrap_or_else(|| "default".to_string()),
+        };
+        // Used in file names
+        server_id
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' || c == '.' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect()
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct PatchServerInfo {
+    pub name: String,      // Name of that identifies the patch server
+    pub plist_url: String, // URL of the plist.txt file
+    pub patch_url: String, // URL of the directory containing .thor files
+    // Format of the patch list ("thor", "json", "xml", ...), guessed from its
+    // content if not set
+    pub plist_format: Option<String>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct ClientConfiguration {
+    pub default_grf_name: String, // GRF file to patch by default
+    pub language: Option<String>, // Language of the client (e.g. "pt-BR"), patches tagged for other languages are skipped
+    pub platform: Option<String>, // Platform of the client (e.g. "win32", "win64" or "linux"), detected if not set
+}
+
+impl ClientConfiguration {
+    /// Returns the platform patches are selected for.
+    pub fn platform(&self) -> &str {
+        self.platform.as_deref().unwrap_or(current_platform())
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct PatchingConfiguration {
+    pub in_place: bool,        // In-place GRF patching
+    pub check_integrity: bool, // Check THOR archives' integrity
+    pub create_grf: bool,      // Create new GRFs if they don't exist
+    #[serde(default)]
+    pub auto_start: bool, // Start updating as soon as the patcher is opened
+    pub bandwidth_limit: Option<u64>, // Maximum download speed in KB/s
+    #[serde(default)]
+    pub preview_patches: bool, // Let users review pending patches before downloading them
+    #[serde(default)]
+    pub allow_elevation: bool, // Restart as administrator if the game's files aren't writable (Windows only)
+    pub staging_directory: Option<String>, // Where patches are downloaded before being applied
+    #[serde(default)]
+    pub background_mode: bool, // Update with a low CPU and I/O priority, to keep the game responsive
+    pub locked_file_retries: Option<u32>, // Attempts made at patching GRFs the game holds open, 5 by default
+    pub locked_file_retry_delay: Option<u64>, // Seconds before the first of these, doubled after each one, 2 by default
+    #[serde(default = "default_clear_read_only")]
+    pub clear_read_only: bool, // Make read-only files writable before replacing them
+    #[serde(default)]
+    pub strip_motw: bool, // Remove the "Mark of the Web" of extracted files, so that Windows doesn't warn before running them
+    pub channel: Option<String>, // Release channel whose patches are received besides stable ones (e.g. "beta")
+    #[serde(default)]
+    pub channels: Vec<String>, // Channels players can switch to from the settings
+    pub pin_index: Option<usize>, // Hold the game at this patch, rolling back more recent ones if possible
+    #[serde(default)]
+    pub create_rollback_patches: bool, // Save what patches replace, so that they can be rolled back
+    #[serde(default = "default_prefetch")]
+    pub prefetch: bool, // Download pending patches in the background when updates don't start on their own
+    #[serde(default)]
+    pub integrity_policy: IntegrityPolicy, // What happens to archives without integrity data, when checking integrity
+    #[serde(default)]
+    pub pipelined: bool, // Apply each patch as soon as it's downloaded, while the following ones are still downloading
+    #[serde(default)]
+    pub download_order: DownloadOrder, // Order in which patch downloads are started
+}
+
+/// Order in which the downloads of pending patches are started.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DownloadOrder {
+    #[default]
+    Index, // Patches come in the order they're applied, which suits pipelined updates
+    SmallestFirst, // Patches complete sooner, which shows progress early, patches of unknown size come last
+}
+
+/// Treatment of the archives which can't be verified, because they have no
+/// integrity data (and no entry in the patch manifest).
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum IntegrityPolicy {
+    #[default]
+    Lenient, // Applied as if they were valid
+    Warn,   // Applied, but players are warned about them
+    Strict, // Rejected like corrupt archives
+}
+
+/// Channel of the patches which don't specify one, received by everyone.
+pub const STABLE_CHANNEL: &str = "stable";
+
+fn default_clear_read_only() -> bool {
+    true
+}
+
+fn default_prefetch() -> bool {
+    true
+}
+
+impl PatchingConfiguration {
+    /// Returns the release channel patches are received from.
+    pub fn channel(&self) -> &str {
+        self.channel.as_deref().unwrap_or(STABLE_CHANNEL)
+    }
+
+    /// Returns the directory where patches are downloaded before being
+    /// applied.
+    ///
+    /// It defaults to a directory in the game's, so that patches aren't
+    /// copied across drives while they're applied.
+    pub fn staging_directory(&self) -> PathBuf {
+        PathBuf::from(self.staging_directory.as_deref().unwrap_or(".patching"))
+    }
+
+    /// Returns how many times patching a GRF which another program holds
+    /// open is retried.
+    pub fn locked_file_retries(&self) -> u32 {
+        self.locked_file_retries.unwrap_or(5)
+    }
+
+    /// Returns how long to wait before the retry following `attempt` (0 for
+    /// the first one), which grows exponentially up to a minute.
+    pub fn locked_file_retry_delay(&self, attempt: u32) -> Duration {
+        const MAX_DELAY_SECS: u64 = 60;
+        let initial_delay_secs = self.locked_file_retry_delay.unwrap_or(2);
+        let delay_secs = initial_delay_secs.saturating_mul(1 << attempt.min(16));
+        Duration::from_secs(delay_secs.min(MAX_DELAY_SECS))
+    }
+}
+
+#[derive(Deserialize, Clone, Default)]
+pub struct LocalizationConfiguration {
+    pub language: Option<String>, // Language tag (e.g. "pt-BR"), detected from the OS if unset
+    pub translations_directory: Option<String>, // Directory containing '<language>.yml' files
+}
+
+#[derive(Deserialize, Clone, Default)]
+pub struct LoggingConfiguration {
+    pub level: Option<String>, // One of "off", "error", "warn", "info", "debug" or "trace"
+    #[serde(default)]
+    pub json: bool, // Write the log file as newline-delimited JSON, for telemetry collectors
+}
+
+#[derive(Deserialize, Clone)]
+pub struct AccountConfiguration {
+    pub login_url: String, // Endpoint which exchanges credentials for a session token
+    #[serde(default = "default_token_argument")]
+    pub token_argument: String, // Argument passed to the game client, can contain placeholders
+    #[serde(default)]
+    pub allow_token: bool, // Let players paste a session token instead of their password
+}
+
+/// Terms of service or EULA, which players are asked to accept again when
+/// its version changes.
+#[derive(Deserialize, Clone)]
+pub struct AgreementConfiguration {
+    pub version: String,      // e.g. '2024-01'
+    pub text: Option<String>, // Shown in the agreement window
+    pub url: Option<String>,  // Page with the full terms
+}
+
+fn default_token_argument() -> String {
+    "-t:{token}".to_string()
+}
+
+/// Values of the `{name}` placeholders of launch buttons' arguments.
+///
+/// `{token}` and `{username}` are set once players have logged in.
+#[derive(Deserialize, Clone, Default)]
+pub struct VariablesConfiguration {
+    #[serde(default)]
+    pub values: HashMap<String, String>, // e.g. 'server_ip: 127.0.0.1'
+    pub url: Option<String>, // URL of a JSON object of additional values, fetched on startup
+}
+
+/// Webhooks called once updates are over, which let operators keep an eye on
+/// a fleet of machines (e.g. in internet cafes).
+#[derive(Deserialize, Clone, Default)]
+pub struct NotificationsConfiguration {
+    #[serde(default)]
+    pub webhooks: Vec<WebhookConfiguration>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct WebhookConfiguration {
+    pub url: String,              // Endpoint which receives a POST request
+    pub template: Option<String>, // JSON body with placeholders (e.g. '{error}'), all the values by default
+    #[serde(default = "default_webhook_events")]
+    pub events: Vec<WebhookEvent>, // 'success', 'failure' or a kind of error (e.g. 'integrity')
+}
+
+/// Local HTTP server which external tools (e.g. stream overlays) use to
+/// follow and drive the patcher.
+#[derive(Deserialize, Clone, Default)]
+pub struct ControlApiConfiguration {
+    #[serde(default)]
+    pub enabled: bool,
+    pub port: Option<u16>,     // Port listened to on localhost, 7878 by default
+    pub token: Option<String>, // Required in an 'Authorization: Bearer' header if set
+}
+
+impl ControlApiConfiguration {
+    pub fn port(&self) -> u16 {
+        self.port.unwrap_or(7878)
+    }
+}
+
+#[derive(Deserialize, Clone, Default)]
+pub struct ScriptingConfiguration {
+    pub path: Option<String>, // Rhai script whose functions are called at specific points (e.g. 'fn before_update()')
+}
+
+/// Status endpoint telling whether the game server is in maintenance, polled
+/// while the patcher is open.
+#[derive(Deserialize, Clone, Default)]
+pub struct MaintenanceConfiguration {
+    pub url: Option<String>, // URL of a JSON object like '{"maintenance": true, "message": "..."}'
+    pub poll_interval: Option<u64>, // Interval between two polls, in seconds, 60 by default
+    #[serde(default)]
+    pub block_play: bool, // Disable "Play" during maintenance
+}
+
+impl MaintenanceConfiguration {
+    pub fn poll_interval(&self) -> Duration {
+        Duration::from_secs(self.poll_interval.unwrap_or(60).max(1))
+    }
+}
+
+/// Message of the day, which lets operators warn players without them having
+/// to visit a website.
+#[derive(Deserialize, Clone, Default)]
+pub struct MotdConfiguration {
+    pub url: Option<String>, // URL of plain text or of a JSON object like '{"message": "...", "severity": "warning"}'
+}
+
+/// Base client installed on machines which don't have the game yet, before
+/// it's patched as usual.
+#[derive(Deserialize, Clone)]
+pub struct InstallerConfiguration {
+    pub manifest_url: String, // URL of a manifest like the patch servers' ('patches.json') listing the client's THOR archives
+    #[serde(default)]
+    pub create_shortcut: bool, // Add a shortcut to the installed patcher on the desktop
+}
+
+/// Sharing of applied patches with the other patchers of the LAN (e.g. in
+/// internet cafés), which download them from there before the patch server.
+#[derive(Deserialize, Clone, Default)]
+pub struct PeerCacheConfiguration {
+    #[serde(default)]
+    pub enabled: bool,
+    pub port: Option<u16>,     // Port patches are served on, 7879 by default
+    pub max_size: Option<u64>, // Size of the patches kept for peers, in MiB, 2048 by default
+}
+
+/// Limits on the files the patcher keeps around, which are enforced when it
+/// starts.
+#[derive(Deserialize, Clone, Default)]
+pub struct StorageConfiguration {
+    #[serde(default)]
+    pub downloads: RetentionPolicy, // Patches downloaded ahead of time, or kept for peers
+    #[serde(default)]
+    pub backups: RetentionPolicy, // Rollback patches
+    #[serde(default)]
+    pub logs: RetentionPolicy, // Rotated logs and crash reports
+}
+
+/// Files exceeding either limit are removed, oldest first.
+#[derive(Deserialize, Clone, Default)]
+pub struct RetentionPolicy {
+    pub max_size: Option<u64>, // In MiB, unlimited if not set
+    pub max_age: Option<u64>,  // In days, unlimited if not set
+}
+
+/// Detection of the files left over by removed content, which are neither
+/// part of the base client nor installed by a patch.
+#[derive(Deserialize, Clone, Default)]
+pub struct CleanupConfiguration {
+    pub base_manifest_url: Option<String>, // URL of a plain-text list of the base client's files, one relative path per line
+    #[serde(default)]
+    pub directories: Vec<String>, // Directories of the game checked for leftovers, 'data' by default
+}
+
+impl CleanupConfiguration {
+    pub fn directories(&self) -> Vec<String> {
+        if self.directories.is_empty() {
+            vec!["data".to_string()]
+        } else {
+            self.directories.clone()
+        }
+    }
+}
+
+/// Files generated once updates succeed, so that operators don't have to ship
+/// them in patches which would overwrite players' edits.
+#[derive(Deserialize, Clone, Default)]
+pub struct PostprocessConfiguration {
+    #[serde(default)]
+    pub templates: Vec<TemplateConfiguration>,
+}
+
+/// File rendered from a template whose `{name}` placeholders are replaced with
+/// the values of the `variables` section (e.g. 'sclientinfo.xml' with the
+/// server's address).
+#[derive(Deserialize, Clone)]
+pub struct TemplateConfiguration {
+    pub template: String, // Path of the template, relative to the game's directory
+    pub output: String,   // Path of the generated file, relative to the game's directory
+}
+
+/// Compatibility layer the game's Windows executables are started through on
+/// other platforms (e.g. macOS, with Wine or CrossOver).
+#[derive(Deserialize, Clone, Default)]
+pub struct WineConfiguration {
+    pub command: Option<String>, // Program starting Windows executables (e.g. "wine64" or CrossOver's "wine"), nothing is wrapped if unset
+    pub bottle: Option<String>,  // CrossOver bottle the game is installed in
+    pub prefix: Option<String>,  // Wine prefix the game is installed in (WINEPREFIX)
+}
+
+impl WineConfiguration {
+    /// Returns the program and arguments which start `exe_path` through the
+    /// compatibility layer, if it's configured and `exe_path` is a Windows
+    /// executable which can't be started natively.
+    ///
+    /// The variables the layer needs are added to `environment`.
+    pub fn wrap(
+        &self,
+        exe_path: &str,
+        arguments: &[String],
+        environment: &mut HashMap<String, String>,
+    ) -> Option<(String, Vec<String>)> {
+        let command = self.command.as_ref()?;
+        let is_windows_executable = Path::new(exe_path)
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("exe"));
+        if cfg!(windows) || !is_windows_executable {
+            return None;
+        }
+        let mut wrapped_arguments = vec![];
+        if let Some(bottle) = &self.bottle {
+            wrapped_arguments.extend(["--bottle".to_string(), bottle.clone()]);
+        }
+        wrapped_arguments.push(exe_path.to_string());
+        wrapped_arguments.extend_from_slice(arguments);
+        if let Some(prefix) = &self.prefix {
+            environment.insert("WINEPREFIX".to_string(), prefix.clone());
+        }
+        Some((command.clone(), wrapped_arguments))
+    }
+}
+
+/// Tuning for setups where the defaults perform poorly (e.g. network shares,
+/// antivirus software scanning every write).
+#[derive(Deserialize, Clone, Default)]
+pub struct AdvancedConfiguration {
+    pub download_buffer_size: Option<usize>, // Size of the writes made when downloading patches, in KiB, 256 by default
+    pub grf_buffer_size: Option<usize>, // Size of the writes made when rebuilding GRFs, in KiB, 1024 by default
+    #[serde(default)]
+    pub preallocate_downloads: bool, // Reserve the size of downloaded patches before writing them, to limit fragmentation on HDDs
+    pub download_sync_interval: Option<u64>, // Data written between two syncs of downloaded patches to the disk, in MB, only once complete by default
+}
+
+impl AdvancedConfiguration {
+    /// Returns the size of the buffer downloaded patches go through, in
+    /// bytes.
+    pub fn download_buffer_size(&self) -> usize {
+        1024 * self.download_buffer_size.unwrap_or(256).max(1)
+    }
+
+    /// Returns the size of the buffer GRFs go through when they're rebuilt
+    /// out-of-place, in bytes.
+    pub fn grf_buffer_size(&self) -> usize {
+        1024 * self.grf_buffer_size.unwrap_or(1024).max(1)
+    }
+
+    /// Returns the number of bytes written to downloaded patches between two
+    /// syncs, if they're synced before being complete.
+    pub fn download_sync_interval(&self) -> Option<u64> {
+        self.download_sync_interval
+            .map(|interval| 1024 * 1024 * interval.max(1))
+    }
+}
+
+#[derive(Deserialize, Clone, Default)]
+pub struct BannerConfiguration {
+    #[serde(default)]
+    pub images: Vec<BannerImageConfiguration>,
+    pub images_url: Option<String>, // URL of a JSON list of additional images
+    pub height: Option<f32>,        // Height of the banner, 120 by default
+    pub interval: Option<u64>,      // Time each image is shown, in seconds, 5 by default
+}
+
+impl BannerConfiguration {
+    pub fn is_enabled(&self) -> bool {
+        !self.images.is_empty() || self.images_url.is_some()
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct BannerImageConfiguration {
+    pub url: String,          // URL of a PNG image
+    pub link: Option<String>, // URL opened when the image is clicked
+}
+
+#[derive(Deserialize, Clone, Default)]
+pub struct TrayConfiguration {
+    #[serde(default)]
+    pub enabled: bool, // Show an icon in the system tray
+    #[serde(default)]
+    pub minimize_to_tray: bool, // Hide the window instead of minimizing it
+    pub check_interval: Option<u64>, // Interval between background update checks, in minutes
+}
+
+pub fn retrieve_patcher_configuration(
+    config_file_path: Option<PathBuf>,
+) -> Result<PatcherConfiguration> {
+    // Use given configuration path if present
+    let config_file_path = match config_file_path {
+        Some(config_file_path) => config_file_path,
+        None => get_configuration_file_path()?,
+    };
+    // Read the YAML content of the file as an instance of `PatcherConfiguration`.
+    parse_configuration(config_file_path)
+}
+
+fn parse_configuration(config_file_path: impl AsRef<Path>) -> Result<PatcherConfiguration> {
+    let config_file = File::open(config_file_path)?;
+    let config_reader = BufReader::new(config_file);
+    let config = serde_yaml::from_reader(config_reader).context("Invalid configuration")?;
+    validate_configuration(&config).context("Invalid configuration")?;
+    Ok(config)
+}
+
+/// Checks the constraints the types of the configuration can't express.
+fn validate_configuration(config: &PatcherConfiguration) -> Result<()> {
+    if config.web.require_https {
+        for server in &config.web.patch_servers {
+            let urls = [&server.plist_url, &server.patch_url];
+            // Invalid URLs are reported when they're used
+            let insecure_url = urls
+                .iter()
+                .find(|url| Url::parse(url).is_ok_and(|url| url.scheme() != "https"));
+            if let Some(insecure_url) = insecure_url {
+                return Err(anyhow!(
+                    "'{}' doesn't use HTTPS, which is required (patch server '{}')",
+                    insecure_url,
+                    server.name
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINIMAL_CONFIGURATION: &str = r#"
+window:
+  title: RPatchur
+  width: 780
+  height: 580
+  resizable: false
+play:
+  path: ragexe.exe
+  arguments: ["1rag1"]
+  exit_on_success: true
+  block_until_patched: true
+setup:
+  path: Setup.exe
+  arguments: []
+web:
+  patch_servers:
+    - name: main
+      plist_url: http://127.0.0.1/plist.txt
+      patch_url: http://127.0.0.1/data/
+client:
+  default_grf_name: data.grf
+patching:
+  in_place: true
+  check_integrity: true
+  create_grf: true
+buttons:
+  - label: Register
+    path: https://example.com/register
+  - label: Full Client
+    icon: "⬇"
+    path: FullClient.exe
+    elevated: true
+"#;
+
+    #[test]
+    fn test_launch_buttons() {
+        let config: PatcherConfiguration = serde_yaml::from_str(MINIMAL_CONFIGURATION).unwrap();
+        let buttons = config.launch_buttons();
+        let labels: Vec<&str> = buttons.iter().map(|b| b.label.as_str()).collect();
+        assert_eq!(
+            labels,
+            ["button.play", "button.setup", "Register", "Full Client"]
+        );
+        assert_eq!(buttons[0].arguments, ["1rag1"]);
+        assert_eq!(buttons[0].exit_on_success, Some(true));
+        assert!(buttons[0].block_until_patched);
+        assert!(!buttons[1].block_until_patched);
+        assert!(buttons[2].is_url());
+        assert!(!buttons[2].elevated);
+        assert!(!buttons[3].is_url());
+        assert!(buttons[3].elevated);
+        assert!(buttons[3].working_directory.is_none());
+        assert!(buttons[3].environment.is_empty());
+        assert!(!buttons[0].block_during_maintenance);
+
+        let configuration = MINIMAL_CONFIGURATION.replace(
+            "    elevated: true\n",
+            "    elevated: true\n    working_directory: Tools\n    environment:\n      __COMPAT_LAYER: RunAsInvoker\n",
+        );
+        let configuration = format!("{}maintenance:\n  block_play: true\n", configuration);
+        let config: PatcherConfiguration = serde_yaml::from_str(&configuration).unwrap();
+        let buttons = config.launch_buttons();
+        assert!(buttons[0].block_during_maintenance);
+        assert!(!buttons[1].block_during_maintenance);
+        assert_eq!(buttons[3].working_directory.as_deref(), Some("Tools"));
+        assert_eq!(buttons[3].environment["__COMPAT_LAYER"], "RunAsInvoker");
+    }
+
+    #[test]
+    fn test_server_id() {
+        let config: PatcherConfiguration = serde_yaml::from_str(MINIMAL_CONFIGURATION).unwrap();
+        assert_eq!(config.web.server_id(), "127.0.0.1");
+
+        let mut web_config = config.web;
+        web_config.patch_servers[0].plist_url = "http://example.com:8080/plist.txt".to_string();
+        assert_eq!(web_config.server_id(), "example.com_8080");
+        web_config.server_id = Some("Live Server".to_string());
+        assert_eq!(web_config.server_id(), "Live_Server");
+        web_config.server_id = None;
+        web_config.patch_servers.clear();
+        assert_eq!(web_config.server_id(), "default");
+    }
+
+    #[test]
+    fn test_skin_configuration() {
+        let config: PatcherConfiguration = serde_yaml::from_str(MINIMAL_CONFIGURATION).unwrap();
+        assert!(config.window.decorations);
+        assert!(config.window.skin.is_none());
+
+        let skinned_configuration = MINIMAL_CONFIGURATION.replace(
+            "  resizable: false\n",
+            "  resizable: false\n  decorations: false\n  transparent: true\n  skin:\n    \
+             background: skin.png\n    close_button: { x: 750, y: 8, width: 22, height: 22 }\n",
+        );
+        let config: PatcherConfiguration = serde_yaml::from_str(&skinned_configuration).unwrap();
+        assert!(!config.window.decorations);
+        assert!(config.window.transparent);
+        let skin = config.window.skin.unwrap();
+        assert_eq!(skin.background.as_deref(), Some("skin.png"));
+        assert!(skin.drag_regions.is_empty());
+        let close_button = skin.close_button.unwrap();
+        assert!(close_button.contains(760.0, 10.0));
+        assert!(!close_button.contains(772.0, 10.0));
+        assert!(skin.minimize_button.is_none());
+    }
+
+    #[test]
+    fn test_account_configuration() {
+        let config: PatcherConfiguration = serde_yaml::from_str(MINIMAL_CONFIGURATION).unwrap();
+        assert!(config.account.is_none());
+        assert!(!config.launch_buttons()[0].pass_session_token);
+
+        let account_configuration = format!(
+            "{}account:\n  login_url: https://example.com/login\n",
+            MINIMAL_CONFIGURATION
+        );
+        let config: PatcherConfiguration = serde_yaml::from_str(&account_configuration).unwrap();
+        let account = config.account.as_ref().unwrap();
+        assert_eq!(account.token_argument, "-t:{token}");
+        assert!(!account.allow_token);
+        let buttons = config.launch_buttons();
+        assert!(buttons[0].pass_session_token);
+        assert!(!buttons[1].pass_session_token);
+        assert!(!buttons[2].pass_session_token);
+    }
+
+    #[test]
+    fn test_require_https() {
+        let config: PatcherConfiguration = serde_yaml::from_str(MINIMAL_CONFIGURATION).unwrap();
+        assert!(validate_configuration(&config).is_ok());
+
+        let configuration =
+            MINIMAL_CONFIGURATION.replace("web:\n", "web:\n  require_https: true\n");
+        let mut config: PatcherConfiguration = serde_yaml::from_str(&configuration).unwrap();
+        assert!(validate_configuration(&config).is_err());
+        config.web.patch_servers[0].plist_url = "https://127.0.0.1/plist.txt".to_string();
+        assert!(validate_configuration(&config).is_err());
+        config.web.patch_servers[0].patch_url = "https://127.0.0.1/data/".to_string();
+        assert!(validate_configuration(&config).is_ok());
+    }
+
+    #[test]
+    fn test_forced_patch_server() {
+        let mut config: PatcherConfiguration = serde_yaml::from_str(MINIMAL_CONFIGURATION).unwrap();
+        config.web.preferred_patch_server = Some("backup".to_string());
+        assert_eq!(
+            config.web.preferred_patch_server().as_deref(),
+            Some("backup")
+        );
+
+        let mut user_settings = crate::patcher::UserSettings {
+            patch_server: Some("main".to_string()),
+            ..Default::default()
+        };
+        user_settings.apply_to(&mut config);
+        assert_eq!(config.web.preferred_patch_server().as_deref(), Some("main"));
+        // Servers the operator removed are ignored
+        user_settings.patch_server = Some("removed".to_string());
+        user_settings.apply_to(&mut config);
+        assert_eq!(
+            config.web.preferred_patch_server().as_deref(),
+            Some("backup")
+        );
+    }
+
+    #[test]
+    fn test_advanced_configuration() {
+        let config: PatcherConfiguration = serde_yaml::from_str(MINIMAL_CONFIGURATION).unwrap();
+        assert_eq!(config.advanced.download_buffer_size(), 256 * 1024);
+        assert_eq!(config.advanced.grf_buffer_size(), 1024 * 1024);
+        assert!(!config.advanced.preallocate_downloads);
+        assert_eq!(config.advanced.download_sync_interval(), None);
+
+        let configuration = format!(
+            "{}advanced:\n  grf_buffer_size: 64\n  preallocate_downloads: true\n  download_sync_interval: 8\n",
+            MINIMAL_CONFIGURATION
+        );
+        let config: PatcherConfiguration = serde_yaml::from_str(&configuration).unwrap();
+        assert_eq!(config.advanced.grf_buffer_size(), 64 * 1024);
+        assert!(config.advanced.preallocate_downloads);
+        assert_eq!(
+            config.advanced.download_sync_interval(),
+            Some(8 * 1024 * 1024)
+        );
+    }
+
+    #[test]
+    fn test_wine_configuration() {
+        let config: PatcherConfiguration = serde_yaml::from_str(MINIMAL_CONFIGURATION).unwrap();
+        let arguments = vec!["1rag1".to_string()];
+        let mut environment = HashMap::new();
+        assert!(config
+            .wine
+            .wrap("ragexe.exe", &arguments, &mut environment)
+            .is_none());
+
+        let configuration = format!(
+            "{}wine:\n  command: /Applications/CrossOver.app/Contents/SharedSupport/CrossOver/bin/wine\n  bottle: Ragnarok\n  prefix: /tmp/prefix\n",
+            MINIMAL_CONFIGURATION
+        );
+        let config: PatcherConfiguration = serde_yaml::from_str(&configuration).unwrap();
+        assert!(config
+            .wine
+            .wrap("game.sh", &arguments, &mut environment)
+            .is_none());
+        let wrapped = config.wine.wrap("ragexe.exe", &arguments, &mut environment);
+        if cfg!(windows) {
+            assert!(wrapped.is_none());
+        } else {
+            let (command, wrapped_arguments) = wrapped.unwrap();
+            assert!(command.ends_with("/bin/wine"));
+            assert_eq!(
+                wrapped_arguments,
+                ["--bottle", "Ragnarok", "ragexe.exe", "1rag1"]
+            );
+            assert_eq!(environment["WINEPREFIX"], "/tmp/prefix");
+        }
+    }
+
+    #[test]
+    fn test_locked_file_retry_delay() {
+        let config: PatcherConfiguration = serde_yaml::from_str(MINIMAL_CONFIGURATION).unwrap();
+        assert_eq!(config.patching.locked_file_retries(), 5);
+        assert!(config.patching.clear_read_only);
+        assert_eq!(config.patching.channel(), "stable");
+        assert_eq!(
+            config.patching.locked_file_retry_delay(0),
+            Duration::from_secs(2)
+        );
+        assert_eq!(
+            config.patching.locked_file_retry_delay(2),
+            Duration::from_secs(8)
+        );
+        assert_eq!(
+            config.patching.locked_file_retry_delay(40),
+            Duration::from_secs(60)
+        );
+    }
+    #[test]
+    fn test_integrity_policy() {
+        let config: PatcherConfiguration = serde_yaml::from_str(MINIMAL_CONFIGURATION).unwrap();
+        assert_eq!(config.patching.integrity_policy, IntegrityPolicy::Lenient);
+
+        let configuration = MINIMAL_CONFIGURATION.replace(
+            "  check_integrity: true\n",
+            "  check_integrity: true\n  integrity_policy: strict\n",
+        );
+        let config: PatcherConfiguration = serde_yaml::from_str(&configuration).unwrap();
+        assert_eq!(config.patching.integrity_policy, IntegrityPolicy::Strict);
+    }
+}
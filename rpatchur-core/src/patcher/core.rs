@@ -0,0 +1,2500 @@
+use std::collections::HashMap;
+use std::env;
+use std::io::{Read, Seek};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context, Result};
+use gruf::thor::{ThorArchive, ThorPatchInfo, ThorPatchList};
+use tokio::sync::mpsc::{self as tokio_mpsc, UnboundedReceiver};
+use url::Url;
+
+use super::agreement::ensure_agreement_is_accepted;
+use super::bundle::{apply_bundle, export_bundle};
+use super::cancellation::{
+    process_incoming_commands, wait_for_cancellation, InterruptibleFnError, InterruptibleFnResult,
+};
+use super::cleanup::{find_orphaned_files, remove_orphaned_files};
+use super::config::{ClientConfiguration, IntegrityPolicy, WebConfiguration};
+use super::download::{
+    download_patches_concurrent, download_patches_concurrent_inner, fetch_patch_sizes,
+    fetch_total_download_size, log_failed_downloads, take_cached_patches, FailedPatch,
+    PendingPatch, StagingDirectory,
+};
+use super::error::{ErrorKind, PatcherError};
+use super::history::{record_patch_history_entry, PatchHistoryEntry};
+use super::installer::install_game;
+use super::notifications::notify_update_result;
+use super::patch_servers::{
+    find_available_patch_server, manifest_entries, probe_patch_servers, PatchListing,
+};
+use super::patching::{
+    apply_patch_to_disk, apply_patch_to_grf, apply_zip_patch_to_disk, verify_patch_in_grf,
+    verify_patch_on_disk, DiskPatchingOptions, FileChange, GrfPatchingMethod,
+};
+use super::paths::get_download_cache_directory_path;
+use super::peer_cache::keep_patch_for_peers;
+use super::postprocess::render_templates;
+use super::publish::{PatchManifest, PatchManifestEntry};
+use super::remote_update::fetch_remote_update;
+use super::repair::repair_grf;
+use super::rollback::{roll_back_to_pinned_index, save_rollback_patch};
+use super::scripting::ScriptHooks;
+use super::state::{
+    file_crc32, retrieve_patcher_state, update_patcher_state, AppliedPatch, DownloadSession,
+};
+use super::storage::clean_up_storage;
+use super::update_lock::{is_update_lock_taken, take_update_lock};
+use super::zip::{is_zip_file, ZipArchive};
+use super::{PatcherCommand, PatcherConfiguration};
+use crate::process::{
+    find_running_processes, is_elevated, set_background_priority, terminate_process,
+};
+use crate::status::{
+    human_readable_duration, human_readable_size, ErrorReport, OrphanedFile, PatchOutcome,
+    PatchPreview, PatchResult, PatchingStatus, ReleaseNote, UpdateProgress,
+};
+
+/// Patches of an update which still have to be downloaded or applied.
+///
+/// Updates which fail to download some of their patches are kept around, so
+/// that retrying doesn't download the other patches again.
+pub(super) struct UnfinishedUpdate {
+    client: reqwest::Client,
+    patch_url: Url,
+    patch_manifest: HashMap<String, PatchManifestEntry>, // Fetched once for the whole update
+    download_directory: PathBuf,
+    _staging_directory: Option<StagingDirectory>, // Removed along with the update
+    apply_downloaded_patches: bool,
+    patches_to_download: ThorPatchList,
+    pending_patch_queue: Vec<PendingPatch>, // Sorted by index
+}
+
+/// Result of an update that didn't fail outright
+enum UpdateOutcome {
+    Applied,
+    DownloadedOnly(usize), // Number of patches kept for later
+    Unfinished {
+        update: Box<UnfinishedUpdate>,
+        results: Vec<PatchResult>,
+        error: anyhow::Error, // Why the first of the failed patches couldn't be downloaded
+    },
+}
+
+/// Entry point of the patching task.
+///
+/// This waits for a `PatcherCommand::Start` command before starting an
+/// interruptible patching task.
+pub fn patcher_thread_routine(
+    mut config: PatcherConfiguration,
+    patching_thread_rx: UnboundedReceiver<PatcherCommand>,
+    status_tx: mpsc::Sender<PatchingStatus>,
+) -> Result<()> {
+    let mut ui_controller = UiController::new(status_tx, true);
+    let mut patching_thread_rx = patching_thread_rx;
+    let mut unfinished_update = None;
+    let tokio_rt = build_tokio_runtime()?;
+
+    // No update has started yet, expired files are unused
+    clean_up_storage(&config.storage);
+
+    // Periodic update checks only make sense when the patcher lives in the tray
+    let check_interval = config
+        .tray
+        .check_interval
+        .filter(|_| config.tray.enabled)
+        .map(|minutes| Duration::from_secs(60 * minutes));
+
+    // Block on the patching task from our synchronous function
+    tokio_rt.block_on(async {
+        // Download pending patches until players start the update, which
+        // stops prefetching right away
+        let mut first_command = None;
+        if config.patching.prefetch && !config.patching.auto_start {
+            first_command = tokio::select! {
+                command = patching_thread_rx.recv() => Some(command),
+                res = prefetch_patches(&config) => {
+                    if let Err(e) = res {
+                        tracing::warn!("Failed to prefetch patches: {:#}", e);
+                    }
+                    None
+                }
+            };
+        }
+        loop {
+            let command = match (first_command.take(), check_interval) {
+                (Some(command), _) => command,
+                (None, None) => patching_thread_rx.recv().await,
+                (None, Some(check_interval)) => {
+                    tokio::time::timeout(check_interval, patching_thread_rx.recv())
+                        .await
+                        .unwrap_or(Some(PatcherCommand::CheckForUpdates))
+                }
+            };
+            match command {
+                Some(PatcherCommand::Quit) => break,
+                Some(command) => {
+                    execute_command(
+                        command,
+                        &mut config,
+                        &mut ui_controller,
+                        &mut patching_thread_rx,
+                        &mut unfinished_update,
+                    )
+                    .await;
+                }
+                None => {
+                    ui_controller.dispatch_patching_status(PatchingStatus::Error(
+                        ErrorReport::new("error.channel_disconnected"),
+                    ));
+                    break;
+                }
+            }
+        }
+
+        Ok(())
+    })
+}
+
+/// Runs a single command to completion on the current thread, without
+/// listening to other commands (e.g. when running headless).
+pub fn run_patcher_command(
+    mut config: PatcherConfiguration,
+    command: PatcherCommand,
+    status_tx: mpsc::Sender<PatchingStatus>,
+) -> Result<()> {
+    // Nobody's there to answer questions
+    let mut ui_controller = UiController::new(status_tx, false);
+    // Keep the sender alive, since a disconnection means cancellation
+    let (_patching_thread_tx, mut patching_thread_rx) = tokio_mpsc::unbounded_channel();
+    let tokio_rt = build_tokio_runtime()?;
+    tokio_rt.block_on(async {
+        match command {
+            // Unlike periodic checks, explicit checks have to report failures,
+            // as well as how much would be downloaded
+            PatcherCommand::CheckForUpdates => {
+                if let Err(e) =
+                    check_for_updates(&config, &ui_controller, &mut patching_thread_rx, true).await
+                {
+                    tracing::error!("Failed to check for updates: {:#}", e);
+                    ui_controller.dispatch_patching_status(PatchingStatus::Error((&e).into()));
+                }
+            }
+            command => {
+                execute_command(
+                    command,
+                    &mut config,
+                    &mut ui_controller,
+                    &mut patching_thread_rx,
+                    &mut None,
+                )
+                .await
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Builds a tokio runtime that runs a scheduler on the current thread and a reactor
+pub(super) fn build_tokio_runtime() -> Result<tokio::runtime::Runtime> {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .with_context(|| "Failed to build a tokio runtime")
+}
+
+/// Builds the HTTP client used to talk to patch servers.
+///
+/// When HTTPS is required, requests and redirects to other schemes fail.
+pub(super) fn build_http_client(web_config: &WebConfiguration) -> Result<reqwest::Client> {
+    const DEFAULT_MAX_REDIRECTS: usize = 10;
+
+    let max_redirects = web_config.max_redirects.unwrap_or(DEFAULT_MAX_REDIRECTS);
+    let require_https = web_config.require_https;
+    let same_host_redirects_only = web_config.same_host_redirects_only;
+    let redirect_policy = reqwest::redirect::Policy::custom(move |attempt| {
+        // The first URL is the requested one
+        let requested_host = attempt
+            .previous()
+            .first()
+            .and_then(|url| url.host_str().map(str::to_string));
+        if require_https && attempt.url().scheme() != "https" {
+            let error = format!("Refusing insecure redirect to '{}'", attempt.url());
+            attempt.error(error)
+        } else if same_host_redirects_only && attempt.url().host_str() != requested_host.as_deref()
+        {
+            let error = format!("Refusing redirect to another host ('{}')", attempt.url());
+            attempt.error(error)
+        } else if attempt.previous().len() > max_redirects {
+            attempt.error("Too many redirects")
+        } else {
+            attempt.follow()
+        }
+    });
+    reqwest::Client::builder()
+        .https_only(require_https)
+        .redirect(redirect_policy)
+        .build()
+        .with_context(|| "Failed to build the HTTP client")
+}
+
+/// Logs the URL a response comes from when the request has been redirected
+/// (e.g. by a link shortener in front of a CDN).
+pub(super) fn log_final_url(requested_url: &Url, resp: &reqwest::Response) {
+    if resp.url() != requested_url {
+        tracing::info!("'{}' redirected to '{}'", requested_url, resp.url());
+    }
+}
+
+pub(super) async fn execute_command(
+    command: PatcherCommand,
+    config: &mut PatcherConfiguration,
+    ui_controller: &mut UiController,
+    patching_thread_rx: &mut UnboundedReceiver<PatcherCommand>,
+    unfinished_update: &mut Option<UnfinishedUpdate>,
+) {
+    match command {
+        PatcherCommand::StartUpdate => {
+            // Start over, even if the last update didn't complete
+            *unfinished_update = None;
+            if let Err(e) =
+                start_update(config, ui_controller, patching_thread_rx, unfinished_update).await
+            {
+                ui_controller.dispatch_patching_status(PatchingStatus::Error((&e).into()));
+            }
+        }
+        PatcherCommand::RetryFailedPatches => {
+            if unfinished_update.is_none() {
+                tracing::warn!("No failed patches to retry");
+                return;
+            }
+            if let Err(e) =
+                start_update(config, ui_controller, patching_thread_rx, unfinished_update).await
+            {
+                ui_controller.dispatch_patching_status(PatchingStatus::Error((&e).into()));
+            }
+        }
+        PatcherCommand::CheckForUpdates => {
+            if let Err(e) =
+                check_for_updates(config, ui_controller, patching_thread_rx, false).await
+            {
+                tracing::warn!("Failed to check for updates: {:#}", e);
+            }
+        }
+        PatcherCommand::CancelUpdate
+        | PatcherCommand::ForceCancelUpdate
+        | PatcherCommand::SelectPatches { .. }
+        | PatcherCommand::CloseGameClient
+        | PatcherCommand::RetryPatch => {
+            // Nothing to do here, the patching task is already over
+        }
+        PatcherCommand::ResetCache => {
+            if let Err(e) = reset_cache() {
+                ui_controller.dispatch_patching_status(PatchingStatus::Error((&e).into()));
+            }
+        }
+        PatcherCommand::ManualPatchFile(patch_file_path) => {
+            apply_single_patch(patch_file_path, ui_controller, config);
+        }
+        PatcherCommand::ApplySettings(user_settings) => {
+            user_settings.apply_to(config);
+        }
+        PatcherCommand::Install(install_directory) => {
+            install_game(
+                config,
+                ui_controller,
+                patching_thread_rx,
+                &install_directory,
+            )
+            .await;
+        }
+        PatcherCommand::ExportBundle {
+            path,
+            all_patches,
+            squash,
+        } => {
+            export_bundle(
+                config,
+                ui_controller,
+                patching_thread_rx,
+                &path,
+                all_patches,
+                squash,
+            )
+            .await;
+        }
+        PatcherCommand::ApplyBundle(bundle_path) => {
+            apply_bundle(config, ui_controller, patching_thread_rx, &bundle_path).await;
+        }
+        PatcherCommand::FindOrphanedFiles => match list_orphaned_files(config).await {
+            Ok(orphaned_files) => {
+                tracing::info!("Found {} orphaned file(s)", orphaned_files.len());
+                ui_controller
+                    .dispatch_patching_status(PatchingStatus::OrphanedFilesFound(orphaned_files));
+            }
+            Err(err) => {
+                tracing::error!("Failed to look for orphaned files: {:#}", err);
+                ui_controller.dispatch_patching_status(PatchingStatus::Error((&err).into()));
+            }
+        },
+        PatcherCommand::DeleteOrphanedFiles(selection) => {
+            match delete_orphaned_files(config, selection).await {
+                Ok((deleted_count, freed_bytes)) => ui_controller.dispatch_patching_status(
+                    PatchingStatus::OrphanedFilesDeleted(deleted_count, freed_bytes),
+                ),
+                Err(err) => {
+                    tracing::error!("Failed to delete orphaned files: {:#}", err);
+                    ui_controller.dispatch_patching_status(PatchingStatus::Error((&err).into()));
+                }
+            }
+        }
+        PatcherCommand::ProbePatchServers => match build_http_client(&config.web) {
+            Ok(client) => {
+                let server_health = probe_patch_servers(&client, config).await;
+                ui_controller
+                    .dispatch_patching_status(PatchingStatus::PatchServersProbed(server_health));
+            }
+            Err(err) => {
+                tracing::error!("Failed to probe the patch servers: {:#}", err);
+                ui_controller.dispatch_patching_status(PatchingStatus::Error((&err).into()));
+            }
+        },
+        PatcherCommand::RepairGrf(grf_name) => {
+            repair_grf(config, ui_controller, patching_thread_rx, &grf_name).await;
+        }
+        PatcherCommand::Quit => {
+            // Handled by the caller
+        }
+    }
+}
+
+/// A simple UI controller that can be used to update the UI from the patcher thread
+pub(super) struct UiController {
+    dispatch: Box<dyn Fn(PatchingStatus) + Send + Sync>,
+    interactive: bool, // Whether users can answer questions (e.g. to close the game)
+    progress: Mutex<UpdateProgress>, // Progress of the running operation, as dispatched so far
+    started_at: Mutex<(Instant, i64)>, // When the running operation started, and its Unix timestamp
+}
+
+impl UiController {
+    pub(super) fn new(status_tx: mpsc::Sender<PatchingStatus>, interactive: bool) -> Self {
+        Self::with_dispatcher(
+            move |status| {
+                let _ = status_tx.send(status);
+            },
+            interactive,
+        )
+    }
+
+    /// Creates a controller which hands statuses over to `dispatch`, for
+    /// frontends which don't receive them through a channel.
+    pub(super) fn with_dispatcher(
+        dispatch: impl Fn(PatchingStatus) + Send + Sync + 'static,
+        interactive: bool,
+    ) -> Self {
+        Self {
+            dispatch: Box::new(dispatch),
+            interactive,
+            progress: Mutex::new(UpdateProgress::default()),
+            started_at: Mutex::new((Instant::now(), chrono::Utc::now().timestamp())),
+        }
+    }
+
+    pub(super) fn dispatch_patching_status(&self, status: PatchingStatus) {
+        {
+            let mut progress = self.progress.lock().unwrap_or_else(|e| e.into_inner());
+            match status {
+                // Patches are counted as they're downloaded, speed updates may
+                // lag behind
+                PatchingStatus::DownloadInProgress(nb_downloaded, nb_total, _) if nb_total > 0 => {
+                    progress.downloaded_patches = progress.downloaded_patches.max(nb_downloaded);
+                    progress.total_patches = progress.total_patches.max(nb_total);
+                }
+                PatchingStatus::InstallationInProgress(nb_installed, nb_total) if nb_total > 0 => {
+                    progress.applied_patches = nb_installed;
+                    progress.total_patches = progress.total_patches.max(nb_total);
+                }
+                _ => {}
+            }
+        }
+        (self.dispatch)(status);
+    }
+
+    pub(super) fn set_patching_in_progress(&self, value: bool) {
+        let status = if value {
+            *self.progress.lock().unwrap_or_else(|e| e.into_inner()) = UpdateProgress::default();
+            *self.started_at.lock().unwrap_or_else(|e| e.into_inner()) =
+                (Instant::now(), chrono::Utc::now().timestamp());
+            PatchingStatus::DownloadInProgress(0, 0, 0)
+        } else {
+            PatchingStatus::Ready
+        };
+        self.dispatch_patching_status(status);
+    }
+
+    pub(super) fn add_downloaded_bytes(&self, bytes: u64) {
+        self.progress
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .downloaded_bytes += bytes;
+    }
+
+    pub(super) fn add_downloaded_patch(&self) {
+        self.progress
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .downloaded_patches += 1;
+    }
+
+    /// Returns the progress of the running operation so far.
+    fn progress(&self) -> UpdateProgress {
+        let mut progress = *self.progress.lock().unwrap_or_else(|e| e.into_inner());
+        let (started_at, _) = *self.started_at.lock().unwrap_or_else(|e| e.into_inner());
+        progress.elapsed_ms = started_at.elapsed().as_millis() as u64;
+        progress
+    }
+
+    /// Records the statistics of the update which just ended in the
+    /// patcher's state, if it downloaded anything.
+    ///
+    /// Returns its progress then, for the UI to show.
+    fn record_download_session(&self) -> Option<UpdateProgress> {
+        let progress = self.progress();
+        if progress.downloaded_bytes == 0 {
+            return None;
+        }
+        let (_, started_at) = *self.started_at.lock().unwrap_or_else(|e| e.into_inner());
+        let session = DownloadSession {
+            started_at,
+            duration_ms: progress.elapsed_ms,
+            downloaded_bytes: progress.downloaded_bytes,
+            patch_count: progress.downloaded_patches,
+        };
+        tracing::info!(
+            "Downloaded {} in {}",
+            human_readable_size(session.downloaded_bytes),
+            human_readable_duration(session.duration_ms)
+        );
+        if let Err(e) = update_patcher_state(|state| state.record_download_session(session)) {
+            tracing::warn!("Failed to record the download session: {:#}", e);
+        }
+        Some(progress)
+    }
+
+    /// Tells the UI how far the running operation got before it was
+    /// canceled.
+    pub(super) fn dispatch_cancellation(&self) {
+        let progress = self.progress();
+        tracing::info!(
+            "Canceled after downloading {} and applying {} of {} patch(es)",
+            progress.downloaded_patches,
+            progress.applied_patches,
+            progress.total_patches
+        );
+        self.dispatch_patching_status(PatchingStatus::Cancelled(progress));
+    }
+}
+
+/// Error returned by updates canceled by the user.
+pub(super) fn update_cancelled_error() -> anyhow::Error {
+    anyhow!(PatcherError::new(
+        ErrorKind::Cancelled,
+        "Patching was canceled"
+    ))
+}
+
+/// Starts the automatic update process (download + patching)
+///
+/// The update resumes `unfinished_update` if set, which is replaced by the
+/// update if some of its patches fail to download.
+async fn update_game(
+    ui_controller: &UiController,
+    config: &PatcherConfiguration,
+    patcher_thread_rx: &mut UnboundedReceiver<PatcherCommand>,
+    unfinished_update: &mut Option<UnfinishedUpdate>,
+) {
+    let hooks = ScriptHooks::load(&config.scripting);
+    if !hooks.before_update() {
+        tracing::info!("Update skipped by script");
+        ui_controller.dispatch_patching_status(PatchingStatus::Ready);
+        return;
+    }
+
+    // Files in protected locations (e.g. 'Program Files') can only be patched
+    // by administrators
+    if let Some(unwritable_path) = find_unwritable_path(config) {
+        if config.patching.allow_elevation && ui_controller.interactive && !is_elevated() {
+            tracing::info!("'{}' isn't writable, elevating", unwritable_path.display());
+            ui_controller.dispatch_patching_status(PatchingStatus::ElevationRequired);
+            return;
+        }
+        let err = anyhow!(
+            "'{}' isn't writable, the patcher has to be run as administrator",
+            unwritable_path.display()
+        );
+        tracing::error!("{:#}", err);
+        ui_controller.dispatch_patching_status(PatchingStatus::Error((&err).into()));
+        report_update_failure(config, &hooks, &err).await;
+        return;
+    }
+
+    // Try taking the update lock
+    match take_update_lock().with_context(|| "Failed to take the update lock") {
+        // Another instance is already updating the game, show its progress
+        Err(err) if config.control_api.enabled && is_update_lock_taken(&err) => {
+            match follow_remote_update(ui_controller, config, patcher_thread_rx, err).await {
+                Ok(()) => {
+                    ui_controller.dispatch_patching_status(PatchingStatus::Ready);
+                    tracing::info!("The other instance finished updating the game");
+                }
+                Err(err) => {
+                    tracing::error!("{:#}", err);
+                    ui_controller.dispatch_patching_status(PatchingStatus::Error((&err).into()));
+                }
+            }
+        }
+        Err(err) => {
+            tracing::error!("{:#}", err);
+            ui_controller.dispatch_patching_status(PatchingStatus::Error((&err).into()));
+            report_update_failure(config, &hooks, &err).await;
+        }
+        Ok(lock_file) => {
+            // Tell the UI and other processes that we're currently working
+            ui_controller.set_patching_in_progress(true);
+            let _background_mode = enter_background_mode(config);
+            let _guard = scopeguard::guard((), |_| {
+                drop(lock_file);
+                ui_controller.set_patching_in_progress(false);
+            });
+
+            let res = interruptible_update_routine(
+                ui_controller,
+                config,
+                &hooks,
+                patcher_thread_rx,
+                unfinished_update.take(),
+            )
+            .await;
+            let download_summary = ui_controller.record_download_session();
+            match res {
+                // Not a failure, the user asked for it
+                Err(err) if ErrorKind::of(&err) == ErrorKind::Cancelled => {
+                    ui_controller.dispatch_cancellation();
+                }
+                Err(err) => {
+                    tracing::error!("{:#}", err);
+                    ui_controller.dispatch_patching_status(PatchingStatus::Error((&err).into()));
+                    report_update_failure(config, &hooks, &err).await;
+                }
+                Ok(UpdateOutcome::Applied) => {
+                    if let Some(progress) = download_summary {
+                        ui_controller
+                            .dispatch_patching_status(PatchingStatus::DownloadSummary(progress));
+                    }
+                    ui_controller.dispatch_patching_status(PatchingStatus::Ready);
+                    tracing::info!("Patching finished!");
+                    notify_update_result(&config.notifications, Ok(())).await;
+                }
+                Ok(UpdateOutcome::DownloadedOnly(patch_count)) => {
+                    if let Some(progress) = download_summary {
+                        ui_controller
+                            .dispatch_patching_status(PatchingStatus::DownloadSummary(progress));
+                    }
+                    ui_controller
+                        .dispatch_patching_status(PatchingStatus::PatchesDownloaded(patch_count));
+                    tracing::info!("Patches have been downloaded for later");
+                }
+                Ok(UpdateOutcome::Unfinished {
+                    update,
+                    results,
+                    error,
+                }) => {
+                    tracing::error!("{:#}", error);
+                    ui_controller.dispatch_patching_status(PatchingStatus::PatchResults(results));
+                    ui_controller.dispatch_patching_status(PatchingStatus::Error((&error).into()));
+                    report_update_failure(config, &hooks, &error).await;
+                    *unfinished_update = Some(*update);
+                }
+            }
+        }
+    }
+}
+
+/// Shows the progress of the update run by the instance of the patcher which
+/// holds the update lock, until it releases it.
+///
+/// Progress is read from the other instance's control API. `lock_error` is
+/// returned as it is if it can't be reached.
+async fn follow_remote_update(
+    ui_controller: &UiController,
+    config: &PatcherConfiguration,
+    patching_thread_rx: &mut UnboundedReceiver<PatcherCommand>,
+    lock_error: anyhow::Error,
+) -> Result<()> {
+    const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+    let client = reqwest::Client::new();
+    let mut remote_update = match fetch_remote_update(&client, &config.control_api).await {
+        Ok(remote_update) => remote_update,
+        Err(e) => {
+            tracing::debug!("Failed to reach the other instance: {:#}", e);
+            return Err(lock_error);
+        }
+    };
+    tracing::info!("{:#}", lock_error);
+    tracing::info!("Following the update run by the other instance");
+    ui_controller.set_patching_in_progress(true);
+    ui_controller.dispatch_patching_status(PatchingStatus::UpdateRunningElsewhere);
+    loop {
+        for status in remote_update.statuses.drain(..) {
+            ui_controller.dispatch_patching_status(status);
+        }
+        match tokio::time::timeout(POLL_INTERVAL, patching_thread_rx.recv()).await {
+            // The other instance's update goes on
+            Ok(Some(PatcherCommand::CancelUpdate))
+            | Ok(Some(PatcherCommand::ForceCancelUpdate))
+            | Ok(Some(PatcherCommand::Quit))
+            | Ok(None) => {
+                return Err(anyhow!(
+                    "Stopped following the update run by the other instance"
+                ))
+            }
+            // Other commands make no sense while waiting
+            Ok(Some(_)) | Err(_) => {}
+        }
+        let update_finished = take_update_lock().is_ok();
+        // The other instance might have exited right after its update, its
+        // last known state is kept then
+        match fetch_remote_update(&client, &config.control_api).await {
+            Ok(update) => remote_update = update,
+            Err(e) => tracing::debug!("Failed to reach the other instance: {:#}", e),
+        }
+        if update_finished {
+            return match remote_update.error {
+                Some(error) => Err(anyhow!(
+                    "The other instance failed to update the game: {}",
+                    error
+                )),
+                None => Ok(()),
+            };
+        }
+    }
+}
+
+/// Lowers the priority of the patcher's thread until the returned guard is
+/// dropped, if background mode is enabled.
+pub(super) fn enter_background_mode(config: &PatcherConfiguration) -> impl Drop {
+    let enabled = config.patching.background_mode;
+    if enabled {
+        if let Err(e) = set_background_priority(true) {
+            tracing::warn!("Failed to enable background mode: {:#}", e);
+        }
+    }
+    scopeguard::guard(enabled, |enabled| {
+        if enabled {
+            let _ = set_background_priority(false);
+        }
+    })
+}
+
+/// Tells the operator's webhooks and script that updating the game failed.
+async fn report_update_failure(
+    config: &PatcherConfiguration,
+    hooks: &ScriptHooks,
+    err: &anyhow::Error,
+) {
+    hooks.on_error(err);
+    notify_update_result(&config.notifications, Err(err)).await;
+}
+
+/// Applies a manual patch given by the user
+fn apply_single_patch(
+    patch_file_path: impl AsRef<Path>,
+    ui_controller: &UiController,
+    config: &PatcherConfiguration,
+) {
+    // Try taking the update lock
+    match take_update_lock().with_context(|| "Failed to take the update lock") {
+        Err(err) => {
+            tracing::error!("{:#}", err);
+            ui_controller.dispatch_patching_status(PatchingStatus::Error((&err).into()));
+        }
+        Ok(lock_file) => {
+            // Tell the UI and other processes that we're currently working
+            ui_controller.set_patching_in_progress(true);
+            let _background_mode = enter_background_mode(config);
+            let _guard = scopeguard::guard((), |_| {
+                drop(lock_file);
+                ui_controller.set_patching_in_progress(false);
+            });
+
+            let current_working_dir =
+                env::current_dir().with_context(|| "Failed to resolve current working directory");
+            match current_working_dir {
+                Err(err) => {
+                    tracing::error!("{:#}", err);
+                    ui_controller.dispatch_patching_status(PatchingStatus::Error((&err).into()));
+                }
+                Ok(current_working_dir) => {
+                    let patch_file_name = patch_file_path
+                        .as_ref()
+                        .file_name()
+                        .unwrap_or_default()
+                        .to_str()
+                        .unwrap_or_default()
+                        .to_string();
+                    tracing::info!("Applying patch '{}'", patch_file_name);
+                    let started_at = Instant::now();
+                    let res = ensure_game_client_is_closed(config).and_then(|_| {
+                        apply_patch(
+                            &patch_file_path,
+                            config,
+                            current_working_dir,
+                            &AtomicBool::new(false),
+                        )
+                    });
+                    record_patch_application(
+                        None,
+                        None,
+                        &patch_file_name,
+                        &patch_file_path,
+                        started_at,
+                        &res,
+                    );
+                    match res {
+                        Err(err) => {
+                            tracing::error!("{:#}", err);
+                            ui_controller
+                                .dispatch_patching_status(PatchingStatus::Error((&err).into()));
+                        }
+                        Ok(()) => {
+                            tracing::info!("Done");
+                            ScriptHooks::load(&config.scripting)
+                                .after_patch(&patch_file_name, None);
+                            ui_controller.dispatch_patching_status(
+                                PatchingStatus::ManualPatchApplied(patch_file_name),
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Returns the first of the game directory and its default GRF that the
+/// patcher isn't allowed to write to, if any.
+///
+/// Other errors are ignored, since they'll be reported while patching.
+fn find_unwritable_path(config: &PatcherConfiguration) -> Option<PathBuf> {
+    if is_permission_denied(&tempfile::tempfile_in(".")) {
+        return Some(env::current_dir().unwrap_or_default());
+    }
+    let grf_path = PathBuf::from(&config.client.default_grf_name);
+    if is_permission_denied(&std::fs::OpenOptions::new().write(true).open(&grf_path)) {
+        return Some(grf_path);
+    }
+    None
+}
+
+fn is_permission_denied<T>(res: &std::io::Result<T>) -> bool {
+    matches!(res, Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied)
+}
+
+/// Main routine of the patching task.
+///
+/// This routine is written in a way that makes it interuptible (or cancellable)
+/// with a relatively low latency.
+#[tracing::instrument(name = "update", skip_all)]
+async fn interruptible_update_routine(
+    ui_controller: &UiController,
+    config: &PatcherConfiguration,
+    hooks: &ScriptHooks,
+    patcher_thread_rx: &mut UnboundedReceiver<PatcherCommand>,
+    unfinished_update: Option<UnfinishedUpdate>,
+) -> Result<UpdateOutcome> {
+    let mut update = match unfinished_update {
+        Some(update) => {
+            tracing::info!(
+                "Retrying {} failed patches",
+                update.patches_to_download.len()
+            );
+            update
+        }
+        None => {
+            tracing::info!("Start patching");
+            roll_back_to_pinned_index(config, ui_controller)?;
+            prepare_update(ui_controller, config, patcher_thread_rx).await?
+        }
+    };
+
+    let mut results = vec![];
+    let failed_patches = if update.apply_downloaded_patches && config.patching.pipelined {
+        tracing::info!("Downloading and applying patches ...");
+        let (applied_patches, failed_patches) = download_and_apply_patches(
+            &mut update,
+            config,
+            hooks,
+            ui_controller,
+            patcher_thread_rx,
+        )
+        .await
+        .map_err(|e| match e {
+            InterruptibleFnError::Err(e) => e.context("Failed to apply patches"),
+            InterruptibleFnError::Interrupted => update_cancelled_error(),
+        })?;
+        log_failed_downloads(&failed_patches);
+        results.extend(applied_patches.into_iter().map(|patch_info| PatchResult {
+            index: patch_info.index,
+            file_name: patch_info.file_name,
+            outcome: PatchOutcome::Applied,
+        }));
+        failed_patches
+    } else {
+        // Try fetching patch files
+        tracing::info!("Downloading patches ...");
+        let (downloaded_patches, failed_patches) = download_patches_concurrent(
+            &update.client,
+            update.patch_url.clone(),
+            &update.patch_manifest,
+            std::mem::take(&mut update.patches_to_download),
+            &update.download_directory,
+            config,
+            ui_controller,
+            patcher_thread_rx,
+        )
+        .await
+        .map_err(|e| match e {
+            InterruptibleFnError::Err(e) => e.context("Failed to download patches"),
+            InterruptibleFnError::Interrupted => update_cancelled_error(),
+        })?;
+        log_failed_downloads(&failed_patches);
+        update.pending_patch_queue.extend(downloaded_patches);
+        update
+            .pending_patch_queue
+            .sort_unstable_by_key(|l| l.info.index);
+        if !update.apply_downloaded_patches && failed_patches.is_empty() {
+            return Ok(UpdateOutcome::DownloadedOnly(
+                update.pending_patch_queue.len(),
+            ));
+        }
+
+        if update.apply_downloaded_patches {
+            let applicable_patches =
+                take_applicable_patches(&mut update.pending_patch_queue, &failed_patches);
+            // Don't bother users when nothing can be applied
+            if failed_patches.is_empty() || !applicable_patches.is_empty() {
+                // Patching files the game client holds open would corrupt them
+                wait_for_game_client_exit(config, ui_controller, patcher_thread_rx)
+                    .await
+                    .map_err(|e| match e {
+                        InterruptibleFnError::Err(e) => e,
+                        InterruptibleFnError::Interrupted => update_cancelled_error(),
+                    })?;
+
+                // Proceed with actual patching
+                tracing::info!("Applying patches ...");
+                results.extend(applicable_patches.iter().map(|patch| PatchResult {
+                    index: patch.info.index,
+                    file_name: patch.info.file_name.clone(),
+                    outcome: PatchOutcome::Applied,
+                }));
+                apply_patches(
+                    applicable_patches,
+                    config,
+                    hooks,
+                    ui_controller,
+                    patcher_thread_rx,
+                )
+                .await
+                .map_err(|e| match e {
+                    InterruptibleFnError::Err(e) => e.context("Failed to apply patches"),
+                    InterruptibleFnError::Interrupted => update_cancelled_error(),
+                })?;
+            }
+        }
+        failed_patches
+    };
+    if !results.is_empty() {
+        tracing::info!("Patches have been applied");
+        let release_notes = fetch_release_notes(
+            &update.client,
+            &update.patch_url,
+            &update.patch_manifest,
+            &results,
+        )
+        .await;
+        if !release_notes.is_empty() {
+            ui_controller.dispatch_patching_status(PatchingStatus::ReleaseNotes(release_notes));
+        }
+    }
+    if failed_patches.is_empty() {
+        let game_directory =
+            env::current_dir().with_context(|| "Failed to resolve current working directory")?;
+        render_templates(
+            &config.postprocess,
+            &config.variables.values,
+            &game_directory,
+        )
+        .with_context(|| "Failed to generate files from templates")?;
+        return Ok(UpdateOutcome::Applied);
+    }
+
+    // Keep the update around so that failed patches can be retried
+    results.extend(update.pending_patch_queue.iter().map(|patch| PatchResult {
+        index: patch.info.index,
+        file_name: patch.info.file_name.clone(),
+        outcome: PatchOutcome::Pending,
+    }));
+    let failed_patch_count = failed_patches.len();
+    let mut first_error = None;
+    for failed_patch in failed_patches {
+        results.push(PatchResult {
+            index: failed_patch.info.index,
+            file_name: failed_patch.info.file_name.clone(),
+            outcome: PatchOutcome::Failed(format!("{:#}", failed_patch.error)),
+        });
+        first_error.get_or_insert(failed_patch.error);
+        update.patches_to_download.push(failed_patch.info);
+    }
+    results.sort_unstable_by_key(|result| result.index);
+    let error = first_error
+        .unwrap_or_else(|| anyhow!("Failed to download patches"))
+        .context(format!("{} patches failed to download", failed_patch_count));
+    Ok(UpdateOutcome::Unfinished {
+        update: Box::new(update),
+        results,
+        error,
+    })
+}
+
+/// Fetches the list of pending patches, and prepares the directory they're
+/// downloaded to.
+async fn prepare_update(
+    ui_controller: &UiController,
+    config: &PatcherConfiguration,
+    patcher_thread_rx: &mut UnboundedReceiver<PatcherCommand>,
+) -> Result<UnfinishedUpdate> {
+    let client = build_http_client(&config.web)?;
+    let listing = fetch_pending_patch_list(config, &client, patcher_thread_rx).await?;
+    let patch_manifest = manifest_entries(listing.manifest.as_ref());
+    let PatchListing {
+        mut patch_list,
+        patch_url,
+        ..
+    } = listing;
+
+    // Let the user review pending patches if needed
+    let mut apply_downloaded_patches = true;
+    if config.patching.preview_patches && !patch_list.is_empty() {
+        let (selected_patches, apply) = select_pending_patches(
+            &client,
+            &patch_url,
+            &patch_manifest,
+            patch_list,
+            ui_controller,
+            patcher_thread_rx,
+        )
+        .await
+        .map_err(|_| update_cancelled_error())?;
+        patch_list = selected_patches;
+        apply_downloaded_patches = apply;
+    }
+
+    // Patches that have been downloaded ahead of time are kept in a
+    // dedicated directory until they're applied
+    // Patch servers might serve different patches under the same names
+    let download_cache_directory = get_download_cache_directory_path()
+        .with_context(|| "Failed to resolve patcher name")?
+        .join(config.web.server_id());
+    let (pending_patch_queue, patches_to_download) = take_cached_patches(
+        patch_list,
+        &download_cache_directory,
+        config.patching.check_integrity,
+        config.patching.integrity_policy,
+    );
+    let (download_directory, staging_directory) = if apply_downloaded_patches {
+        let staging_directory = StagingDirectory::create(config.patching.staging_directory())
+            .with_context(|| "Failed to create staging directory")?;
+        (
+            staging_directory.path().to_path_buf(),
+            Some(staging_directory),
+        )
+    } else {
+        std::fs::create_dir_all(&download_cache_directory)
+            .with_context(|| "Failed to create download directory")?;
+        (download_cache_directory, None)
+    };
+    Ok(UnfinishedUpdate {
+        client,
+        patch_url,
+        patch_manifest,
+        download_directory,
+        _staging_directory: staging_directory,
+        apply_downloaded_patches,
+        patches_to_download,
+        pending_patch_queue,
+    })
+}
+
+/// Quietly downloads the pending patches to the download cache, so that most
+/// of them are already local once players start the update.
+///
+/// This is meant to be dropped as soon as a command is received, patches
+/// which are fully downloaded by then are kept.
+async fn prefetch_patches(config: &PatcherConfiguration) -> Result<()> {
+    let _lock_file = take_update_lock().with_context(|| "Failed to take the update lock")?;
+    let client = build_http_client(&config.web)?;
+    // Prefetching is canceled by dropping it instead
+    let (_patching_thread_tx, mut patching_thread_rx) = tokio_mpsc::unbounded_channel();
+    let listing = fetch_pending_patch_list(config, &client, &mut patching_thread_rx).await?;
+    let patch_manifest = manifest_entries(listing.manifest.as_ref());
+    let download_cache_directory = get_download_cache_directory_path()
+        .with_context(|| "Failed to resolve patcher name")?
+        .join(config.web.server_id());
+    let (_, patches_to_download) = take_cached_patches(
+        listing.patch_list,
+        &download_cache_directory,
+        config.patching.check_integrity,
+        config.patching.integrity_policy,
+    );
+    if patches_to_download.is_empty() {
+        return Ok(());
+    }
+    std::fs::create_dir_all(&download_cache_directory)
+        .with_context(|| "Failed to create download directory")?;
+
+    tracing::info!("Prefetching {} patch(es)", patches_to_download.len());
+    // Players haven't asked for an update, don't report its progress
+    let ui_controller = UiController::with_dispatcher(|_| {}, false);
+    let results = download_patches_concurrent_inner(
+        &client,
+        listing.patch_url,
+        &patch_manifest,
+        patches_to_download,
+        &download_cache_directory,
+        config,
+        &ui_controller,
+        None,
+    )
+    .await;
+    let failed_count = results.iter().filter(|res| res.is_err()).count();
+    if failed_count > 0 {
+        tracing::warn!("{} patch(es) failed to prefetch", failed_count);
+    }
+    Ok(())
+}
+
+/// Takes the pending patches which can be applied, given that patches have
+/// to be applied in order and some of them failed to download.
+fn take_applicable_patches(
+    pending_patch_queue: &mut Vec<PendingPatch>,
+    failed_patches: &[FailedPatch],
+) -> Vec<PendingPatch> {
+    let first_failed_index = failed_patches.iter().map(|patch| patch.info.index).min();
+    let applicable_count = match first_failed_index {
+        Some(first_failed_index) => {
+            pending_patch_queue.partition_point(|patch| patch.info.index < first_failed_index)
+        }
+        None => pending_patch_queue.len(),
+    };
+    pending_patch_queue.drain(..applicable_count).collect()
+}
+
+/// Lets the user review the list of pending patches before downloading them.
+///
+/// Returns the patches that have been selected and whether they should be
+/// applied once downloaded.
+async fn select_pending_patches(
+    client: &reqwest::Client,
+    patch_url: &Url,
+    patch_manifest: &HashMap<String, PatchManifestEntry>,
+    patch_list: ThorPatchList,
+    ui_controller: &UiController,
+    patching_thread_rx: &mut UnboundedReceiver<PatcherCommand>,
+) -> InterruptibleFnResult<(ThorPatchList, bool)> {
+    let patch_sizes = fetch_patch_sizes(client, patch_url, &patch_list, patch_manifest).await;
+    let patch_previews = patch_list
+        .iter()
+        .zip(patch_sizes)
+        .map(|(patch_info, size)| PatchPreview {
+            index: patch_info.index,
+            file_name: patch_info.file_name.clone(),
+            size,
+        })
+        .collect();
+    ui_controller.dispatch_patching_status(PatchingStatus::PatchSelectionRequired(patch_previews));
+
+    loop {
+        match patching_thread_rx.recv().await {
+            Some(PatcherCommand::SelectPatches { selection, apply }) => {
+                let selected_patches = patch_list
+                    .into_iter()
+                    .zip(selection)
+                    .filter_map(|(patch_info, selected)| Some(patch_info).filter(|_| selected))
+                    .collect();
+                return Ok((selected_patches, apply));
+            }
+            Some(PatcherCommand::CancelUpdate)
+            | Some(PatcherCommand::ForceCancelUpdate)
+            | Some(PatcherCommand::Quit)
+            | None => return Err(InterruptibleFnError::Interrupted),
+            // Other commands make no sense while the user is choosing
+            Some(_) => {}
+        }
+    }
+}
+
+/// Returns the IDs of the game client's running processes.
+fn find_game_client_processes(config: &PatcherConfiguration) -> Vec<u32> {
+    // Failing to list processes shouldn't prevent players from patching
+    find_running_processes(&config.play.path).unwrap_or_else(|e| {
+        tracing::warn!("{:#}", e);
+        vec![]
+    })
+}
+
+pub(super) fn ensure_game_client_is_closed(config: &PatcherConfiguration) -> Result<()> {
+    if find_game_client_processes(config).is_empty() {
+        return Ok(());
+    }
+    Err(anyhow!(PatcherError::new(
+        ErrorKind::GameClientRunning,
+        format!(
+            "'{}' is running, close the game before patching it",
+            config.play.path
+        ),
+    )))
+}
+
+/// Waits until the game client isn't running anymore.
+///
+/// Users are offered to close the game (when interactive), patching goes on
+/// as soon as it's gone.
+pub(super) async fn wait_for_game_client_exit(
+    config: &PatcherConfiguration,
+    ui_controller: &UiController,
+    patching_thread_rx: &mut UnboundedReceiver<PatcherCommand>,
+) -> InterruptibleFnResult<()> {
+    if !ui_controller.interactive {
+        return ensure_game_client_is_closed(config).map_err(InterruptibleFnError::Err);
+    }
+    const POLL_INTERVAL: Duration = Duration::from_secs(1);
+    let mut user_notified = false;
+    loop {
+        let pids = find_game_client_processes(config);
+        if pids.is_empty() {
+            return Ok(());
+        }
+        if !user_notified {
+            tracing::info!("Waiting for the game client to exit");
+            ui_controller.dispatch_patching_status(PatchingStatus::GameClientRunning);
+            user_notified = true;
+        }
+        match tokio::time::timeout(POLL_INTERVAL, patching_thread_rx.recv()).await {
+            Ok(Some(PatcherCommand::CloseGameClient)) => {
+                for pid in pids {
+                    terminate_process(pid).map_err(InterruptibleFnError::Err)?;
+                }
+            }
+            Ok(Some(PatcherCommand::CancelUpdate))
+            | Ok(Some(PatcherCommand::ForceCancelUpdate))
+            | Ok(Some(PatcherCommand::Quit))
+            | Ok(None) => return Err(InterruptibleFnError::Interrupted),
+            // Other commands make no sense while waiting
+            Ok(Some(_)) | Err(_) => {}
+        }
+    }
+}
+
+/// Checks whether new patches are available, without downloading them.
+///
+/// If `estimate_size` is true, the total size of the pending patches is
+/// retrieved as well, which requires one HEAD request per patch.
+#[tracing::instrument(skip_all)]
+async fn check_for_updates(
+    config: &PatcherConfiguration,
+    ui_controller: &UiController,
+    patcher_thread_rx: &mut UnboundedReceiver<PatcherCommand>,
+    estimate_size: bool,
+) -> Result<()> {
+    let client = build_http_client(&config.web)?;
+    let listing = fetch_pending_patch_list(config, &client, patcher_thread_rx).await?;
+    let patch_list = &listing.patch_list;
+    tracing::info!("{} patch(es) available", patch_list.len());
+    if estimate_size {
+        let patch_manifest = manifest_entries(listing.manifest.as_ref());
+        match fetch_total_download_size(&client, &listing.patch_url, patch_list, &patch_manifest)
+            .await
+        {
+            Some(total_bytes) => ui_controller
+                .dispatch_patching_status(PatchingStatus::DownloadSizeEstimated(total_bytes)),
+            None => tracing::warn!("Failed to estimate the size of the pending patches"),
+        }
+    }
+    ui_controller.dispatch_patching_status(PatchingStatus::UpdatesAvailable(patch_list.len()));
+    Ok(())
+}
+
+/// Finds an available patch server and returns the list of patches that
+/// haven't been applied yet, as well as the URL to download them from and
+/// the manifest describing them.
+pub(super) async fn fetch_pending_patch_list(
+    config: &PatcherConfiguration,
+    client: &reqwest::Client,
+    patcher_thread_rx: &mut UnboundedReceiver<PatcherCommand>,
+) -> Result<PatchListing> {
+    // Find a patch server that we can connect to
+    tracing::info!("Looking for an available patch server ...");
+    let mut listing = find_available_patch_server(
+        client,
+        &config.web,
+        config.patching.channel(),
+        patcher_thread_rx,
+    )
+    .await
+    .map_err(|e| match e {
+        InterruptibleFnError::Err(e) => e,
+        InterruptibleFnError::Interrupted => update_cancelled_error(),
+    })?;
+    let patch_list = &mut listing.patch_list;
+    tracing::debug!("Successfully fetched patch list: {:?}", patch_list);
+
+    if let Some(last_patch_index) = last_applied_patch_index(config) {
+        // Ignore already applied patches if needed
+        // First we verify that our cached index looks relevant
+        let should_filter_patch_list = patch_list.iter().any(|x| x.index == last_patch_index);
+        if should_filter_patch_list {
+            patch_list.retain(|x| x.index > last_patch_index);
+        }
+    }
+    // Hold the game at the pinned patch
+    if let Some(pin_index) = config.patching.pin_index {
+        patch_list.retain(|x| x.index <= pin_index);
+    }
+    // Servers without a manifest have no requirement, nor patches meant for
+    // some clients only
+    if let Some(manifest) = &listing.manifest {
+        retain_client_patches(&mut listing.patch_list, manifest, &config.client);
+        ensure_manifest_is_supported(manifest, &listing.patch_list)?;
+    }
+
+    Ok(listing)
+}
+
+/// Reads the index of the last applied patch.
+///
+/// Indices can't be trusted after the state has been lost, all patches are
+/// verified then.
+pub(super) fn last_applied_patch_index(config: &PatcherConfiguration) -> Option<usize> {
+    match retrieve_patcher_state() {
+        Ok(state) if !state.verification_pending => state.last_patch_index(&config.web.server_id()),
+        Ok(_) => None,
+        Err(e) => {
+            tracing::warn!("Failed to read the patcher state: {:#}", e);
+            None
+        }
+    }
+}
+
+/// Gathers the release notes that the manifest gives for the applied
+/// patches of `results`, in the order they were applied.
+///
+/// Notes which fail to download are replaced with a link to them.
+async fn fetch_release_notes(
+    client: &reqwest::Client,
+    patch_url: &Url,
+    patch_manifest: &HashMap<String, PatchManifestEntry>,
+    results: &[PatchResult],
+) -> Vec<ReleaseNote> {
+    let mut release_notes = vec![];
+    for result in results {
+        if result.outcome != PatchOutcome::Applied {
+            continue;
+        }
+        let entry = match patch_manifest.get(&result.file_name) {
+            Some(entry) => entry,
+            None => continue,
+        };
+        let mut note = ReleaseNote {
+            index: result.index,
+            file_name: result.file_name.clone(),
+            text: entry.notes.clone(),
+            url: None,
+        };
+        if let (None, Some(notes_url)) = (&note.text, &entry.notes_url) {
+            let notes_url = match patch_url.join(notes_url) {
+                Ok(url) => url,
+                Err(e) => {
+                    tracing::warn!("Invalid release notes URL '{}': {}", notes_url, e);
+                    continue;
+                }
+            };
+            match fetch_text(client, notes_url.clone()).await {
+                Ok(text) => note.text = Some(text),
+                Err(e) => {
+                    tracing::warn!("Failed to download release notes: {:#}", e);
+                    note.url = Some(notes_url.to_string());
+                }
+            }
+        }
+        if note.text.is_some() || note.url.is_some() {
+            release_notes.push(note);
+        }
+    }
+    release_notes
+}
+
+async fn fetch_text(client: &reqwest::Client, url: Url) -> Result<String> {
+    let resp = client.get(url).send().await?.error_for_status()?;
+    Ok(resp.text().await?)
+}
+
+/// Refuses to go on if `manifest` requires a more recent patcher than the
+/// running one, either for the whole server or for one of the patches of
+/// `patch_list`, since older patchers could mis-apply new patch formats.
+pub(super) fn ensure_manifest_is_supported(
+    manifest: &PatchManifest,
+    patch_list: &ThorPatchList,
+) -> Result<()> {
+    match required_patcher_version(manifest, patch_list) {
+        Some(required_version) if is_version_older(crate::PKG_VERSION, required_version) => {
+            Err(anyhow!(PatcherError::new(
+                ErrorKind::PatcherOutdated,
+                format!(
+                    "Patcher {} is too old to patch the game, version {} or newer is required",
+                    crate::PKG_VERSION,
+                    required_version
+                ),
+            )))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Returns the most recent of the patcher versions that the manifest
+/// requires to apply the patches of `patch_list`.
+fn required_patcher_version<'a>(
+    manifest: &'a PatchManifest,
+    patch_list: &ThorPatchList,
+) -> Option<&'a str> {
+    let pending_entries = manifest.patches.iter().filter(|entry| {
+        patch_list
+            .iter()
+            .any(|patch_info| patch_info.file_name == entry.file_name)
+    });
+    manifest
+        .min_patcher_version
+        .as_deref()
+        .into_iter()
+        .chain(pending_entries.filter_map(|entry| entry.min_launcher_version.as_deref()))
+        .filter(|version| {
+            let is_valid = parse_version(version).is_some();
+            if !is_valid {
+                tracing::warn!("Ignoring invalid patcher version '{}'", version);
+            }
+            is_valid
+        })
+        .max_by_key(|version| parse_version(version))
+}
+
+/// Parses the numbers of a version, ignoring its pre-release and build
+/// metadata (e.g. "1.2.0-beta" is [1, 2]).
+///
+/// Trailing zeros are dropped so that "1.2" and "1.2.0" are equal.
+fn parse_version(version: &str) -> Option<Vec<u64>> {
+    let version = version.trim().trim_start_matches('v');
+    let numbers = version.split(['-', '+']).next()?;
+    let mut numbers = numbers
+        .split('.')
+        .map(|number| number.parse().ok())
+        .collect::<Option<Vec<u64>>>()?;
+    while numbers.last() == Some(&0) {
+        numbers.pop();
+    }
+    Some(numbers)
+}
+
+/// Returns whether `version` is older than `other_version`. Versions that
+/// can't be parsed aren't considered older.
+pub(super) fn is_version_older(version: &str, other_version: &str) -> bool {
+    match (parse_version(version), parse_version(other_version)) {
+        (Some(version), Some(other_version)) => version < other_version,
+        _ => false,
+    }
+}
+
+/// Removes the patches of `patch_list` whose manifest entry restricts them to
+/// other clients than the one described by `client_config`.
+///
+/// Skipped patches aren't applied later on, even if the client's
+/// configuration changes, unless the cache is reset.
+fn retain_client_patches(
+    patch_list: &mut ThorPatchList,
+    manifest: &PatchManifest,
+    client_config: &ClientConfiguration,
+) {
+    patch_list.retain(|patch_info| {
+        let entry = manifest
+            .patches
+            .iter()
+            .find(|entry| entry.file_name == patch_info.file_name);
+        match entry {
+            Some(entry) if !is_patch_for_client(entry, client_config) => {
+                tracing::info!("Skipping '{}', meant for other clients", entry.file_name);
+                false
+            }
+            _ => true,
+        }
+    });
+}
+
+/// Tells whether the patch of a manifest's `entry` is meant for the client
+/// described by `client_config`, according to the entry's tags.
+pub(super) fn is_patch_for_client(
+    entry: &PatchManifestEntry,
+    client_config: &ClientConfiguration,
+) -> bool {
+    let is_for_language = match entry.tag("lang") {
+        Some(patch_language) => client_config
+            .language
+            .as_deref()
+            .is_some_and(|client_language| language_matches(patch_language, client_language)),
+        None => true,
+    };
+    // Patches may list several platforms (e.g. "win32,win64")
+    let is_for_platform = entry.tag("platform").is_none_or(|patch_platforms| {
+        patch_platforms
+            .split(',')
+            .any(|patch_platform| platform_matches(patch_platform, client_config.platform()))
+    });
+    is_for_language && is_for_platform
+}
+
+/// Tells whether patches for `patch_platform` suit clients running on
+/// `client_platform`, "windows" standing for both "win32" and "win64".
+fn platform_matches(patch_platform: &str, client_platform: &str) -> bool {
+    let patch_platform = patch_platform.trim();
+    patch_platform.eq_ignore_ascii_case(client_platform)
+        || (patch_platform.eq_ignore_ascii_case("windows")
+            && ["win32", "win64"]
+                .iter()
+                .any(|platform| platform.eq_ignore_ascii_case(client_platform)))
+}
+
+/// Tells whether patches for `patch_language` suit clients in
+/// `client_language`, e.g. "pt" patches suit "pt-BR" clients but "pt-BR"
+/// patches don't suit "pt-PT" clients.
+fn language_matches(patch_language: &str, client_language: &str) -> bool {
+    let patch_language = patch_language.to_ascii_lowercase().replace('_', "-");
+    let client_language = client_language.to_ascii_lowercase().replace('_', "-");
+    client_language == patch_language
+        || client_language.starts_with(&format!("{}-", patch_language))
+}
+
+/// Adds the result of a patch application to the patch history.
+fn record_patch_application(
+    patch_index: Option<usize>,
+    server_id: Option<&str>,
+    patch_name: &str,
+    patch_file_path: impl AsRef<Path>,
+    started_at: Instant,
+    result: &Result<()>,
+) {
+    let elapsed = started_at.elapsed();
+    let entry = PatchHistoryEntry {
+        index: patch_index,
+        name: patch_name.to_string(),
+        timestamp: chrono::Utc::now().timestamp() - elapsed.as_secs() as i64,
+        duration_ms: elapsed.as_millis() as u64,
+        error: result.as_ref().err().map(|e| format!("{:#}", e)),
+    };
+    if let Err(e) = record_patch_history_entry(entry) {
+        tracing::warn!("Failed to update patch history: {:#}", e);
+    }
+    if result.is_err() {
+        return;
+    }
+
+    // Keep track of the successfully applied patches in the patcher state
+    let crc32 = match file_crc32(&patch_file_path) {
+        Ok(crc32) => crc32,
+        Err(e) => {
+            tracing::warn!("Failed to compute checksum of '{}': {}", patch_name, e);
+            0
+        }
+    };
+    let applied_patch = AppliedPatch {
+        index: patch_index,
+        server_id: server_id.map(str::to_string),
+        name: patch_name.to_string(),
+        applied_at: chrono::Utc::now().timestamp(),
+        crc32,
+    };
+    if let Err(e) = update_patcher_state(|state| state.record_applied_patch(applied_patch)) {
+        tracing::warn!("Failed to update patcher state: {:#}", e);
+    }
+}
+
+/// Parses and applies a list of patches to GRFs and/or to the game client's
+/// files.
+///
+/// This function is interruptible. Canceling lets the patch being applied
+/// complete, so that the game's files stay consistent, unless cancellation
+/// is forced.
+pub(super) async fn apply_patches(
+    pending_patch_queue: Vec<PendingPatch>,
+    config: &PatcherConfiguration,
+    hooks: &ScriptHooks,
+    ui_controller: &UiController,
+    patching_thread_rx: &mut UnboundedReceiver<PatcherCommand>,
+) -> InterruptibleFnResult<()> {
+    let patch_application =
+        PatchApplication::start(pending_patch_queue.len(), config, hooks, ui_controller)
+            .map_err(InterruptibleFnError::Err)?;
+    let unverified_patches = pending_patch_queue
+        .iter()
+        .filter(|pending_patch| pending_patch.unverified)
+        .map(|pending_patch| pending_patch.info.file_name.clone())
+        .collect();
+    warn_about_unverified_patches(unverified_patches, config, ui_controller);
+    for (patch_number, pending_patch) in pending_patch_queue.into_iter().enumerate() {
+        patch_application
+            .apply(pending_patch, patch_number, patching_thread_rx)
+            .await?;
+    }
+    patch_application.finish();
+    Ok(())
+}
+
+/// Downloads the pending patches of `update` and applies each of them as soon
+/// as it and the patches before it are available, so that patches are
+/// applied while the following ones are still being downloaded.
+///
+/// Patches which follow one that failed to download are left in the update's
+/// queue. Returns the patches which have been applied, in order, along with
+/// the ones which failed to download.
+///
+/// This function is interruptible.
+async fn download_and_apply_patches(
+    update: &mut UnfinishedUpdate,
+    config: &PatcherConfiguration,
+    hooks: &ScriptHooks,
+    ui_controller: &UiController,
+    patching_thread_rx: &mut UnboundedReceiver<PatcherCommand>,
+) -> InterruptibleFnResult<(Vec<ThorPatchInfo>, Vec<FailedPatch>)> {
+    let patches_to_download = std::mem::take(&mut update.patches_to_download);
+    let mut ready_patches: HashMap<usize, PendingPatch> =
+        std::mem::take(&mut update.pending_patch_queue)
+            .into_iter()
+            .map(|pending_patch| (pending_patch.info.index, pending_patch))
+            .collect();
+    let mut patch_indices: Vec<usize> = patches_to_download
+        .iter()
+        .map(|patch_info| patch_info.index)
+        .chain(ready_patches.keys().copied())
+        .collect();
+    patch_indices.sort_unstable();
+    ui_controller.dispatch_patching_status(PatchingStatus::DownloadInProgress(
+        0,
+        patches_to_download.len(),
+        0,
+    ));
+
+    let (downloaded_patch_tx, mut downloaded_patch_rx) = tokio_mpsc::unbounded_channel();
+    let downloads = async {
+        Ok(download_patches_concurrent_inner(
+            &update.client,
+            update.patch_url.clone(),
+            &update.patch_manifest,
+            patches_to_download,
+            &update.download_directory,
+            config,
+            ui_controller,
+            Some(downloaded_patch_tx),
+        )
+        .await)
+    };
+    let application = async {
+        let mut patch_application = None;
+        let mut applied_patches = vec![];
+        let mut unverified_patches = vec![];
+        loop {
+            // Apply the patches which are ready, in order
+            while let Some(pending_patch) = patch_indices
+                .get(applied_patches.len())
+                .and_then(|patch_index| ready_patches.remove(patch_index))
+            {
+                let patch_application = match &mut patch_application {
+                    Some(patch_application) => patch_application,
+                    None => {
+                        // Patching files the game client holds open would
+                        // corrupt them
+                        wait_for_game_client_exit(config, ui_controller, patching_thread_rx)
+                            .await?;
+                        tracing::info!("Applying patches ...");
+                        patch_application.insert(
+                            PatchApplication::start(
+                                patch_indices.len(),
+                                config,
+                                hooks,
+                                ui_controller,
+                            )
+                            .map_err(InterruptibleFnError::Err)?,
+                        )
+                    }
+                };
+                if pending_patch.unverified {
+                    unverified_patches.push(pending_patch.info.file_name.clone());
+                }
+                let patch_info = ThorPatchInfo {
+                    index: pending_patch.info.index,
+                    file_name: pending_patch.info.file_name.clone(),
+                };
+                patch_application
+                    .apply(pending_patch, applied_patches.len(), patching_thread_rx)
+                    .await?;
+                applied_patches.push(patch_info);
+            }
+            if applied_patches.len() == patch_indices.len() {
+                break;
+            }
+            // Patches following one that failed to download are received
+            // all the same, so that they're kept for the next update
+            tokio::select! {
+                cancel_res = wait_for_cancellation(patching_thread_rx) => return Err(cancel_res),
+                pending_patch = downloaded_patch_rx.recv() => match pending_patch {
+                    Some(pending_patch) => {
+                        ready_patches.insert(pending_patch.info.index, pending_patch);
+                    }
+                    None => break,
+                }
+            }
+        }
+        if let Some(patch_application) = patch_application {
+            patch_application.finish();
+        }
+        warn_about_unverified_patches(unverified_patches, config, ui_controller);
+        Ok(applied_patches)
+    };
+    let (results, applied_patches) = futures::future::try_join(downloads, application).await?;
+
+    let mut failed_patches = vec![];
+    for result in results {
+        match result {
+            // Only patches which couldn't be sent are returned
+            Ok(pending_patch) => {
+                ready_patches.insert(pending_patch.info.index, pending_patch);
+            }
+            Err(failed_patch) => failed_patches.push(failed_patch),
+        }
+    }
+    failed_patches.sort_unstable_by_key(|l| l.info.index);
+    update.pending_patch_queue = ready_patches.into_values().collect();
+    update
+        .pending_patch_queue
+        .sort_unstable_by_key(|l| l.info.index);
+    Ok((applied_patches, failed_patches))
+}
+
+/// Warns players about the patches applied without integrity data, if the
+/// integrity policy says so.
+fn warn_about_unverified_patches(
+    unverified_patches: Vec<String>,
+    config: &PatcherConfiguration,
+    ui_controller: &UiController,
+) {
+    if config.patching.integrity_policy != IntegrityPolicy::Warn {
+        return;
+    }
+    if !unverified_patches.is_empty() {
+        tracing::warn!(
+            "Applying patches without integrity data: {}",
+            unverified_patches.join(", ")
+        );
+        ui_controller
+            .dispatch_patching_status(PatchingStatus::UnverifiedPatches(unverified_patches));
+    }
+}
+
+/// Application of the patches of an update, one at a time and in order.
+struct PatchApplication<'a> {
+    config: &'a PatcherConfiguration,
+    hooks: &'a ScriptHooks,
+    ui_controller: &'a UiController,
+    current_working_dir: PathBuf,
+    server_id: String,
+    patch_count: usize,
+    // Patches which are already installed are skipped when the patcher
+    // doesn't know which ones have been applied
+    verify_installed_patches: bool,
+}
+
+impl<'a> PatchApplication<'a> {
+    fn start(
+        patch_count: usize,
+        config: &'a PatcherConfiguration,
+        hooks: &'a ScriptHooks,
+        ui_controller: &'a UiController,
+    ) -> Result<Self> {
+        let current_working_dir =
+            env::current_dir().with_context(|| "Failed to resolve current working directory")?;
+        let verify_installed_patches = match retrieve_patcher_state() {
+            Ok(state) => state.verification_pending,
+            Err(_) => true,
+        };
+        if verify_installed_patches {
+            tracing::info!("Verifying installed patches");
+        }
+        ui_controller
+            .dispatch_patching_status(PatchingStatus::InstallationInProgress(0, patch_count));
+        Ok(Self {
+            config,
+            hooks,
+            ui_controller,
+            current_working_dir,
+            server_id: config.web.server_id(),
+            patch_count,
+            verify_installed_patches,
+        })
+    }
+
+    /// Applies the `patch_number`-th patch of the update.
+    ///
+    /// This function is interruptible.
+    async fn apply(
+        &self,
+        pending_patch: PendingPatch,
+        patch_number: usize,
+        patching_thread_rx: &mut UnboundedReceiver<PatcherCommand>,
+    ) -> InterruptibleFnResult<()> {
+        let config = self.config;
+        let ui_controller = self.ui_controller;
+        let patch_count = self.patch_count;
+        // Cancel the patching process if we've been asked to or if the other
+        // end of the channel has been disconnected
+        match process_incoming_commands(patching_thread_rx) {
+            Ok(_) => {}
+            Err(InterruptibleFnError::Interrupted) => {
+                tracing::info!("Update cancelled by user");
+                return Err(InterruptibleFnError::Interrupted);
+            }
+            Err(InterruptibleFnError::Err(e)) => {
+                return Err(InterruptibleFnError::Err(
+                    e.context("Error while checking for cancellation"),
+                ));
+            }
+        }
+
+        let patch_name = pending_patch.info.file_name;
+        tracing::info!("Processing {}", patch_name);
+        if config.patching.create_rollback_patches {
+            if let Err(e) = save_rollback_patch(
+                &pending_patch.local_file_path,
+                pending_patch.info.index,
+                config,
+                &self.current_working_dir,
+            ) {
+                tracing::warn!(
+                    "Failed to save the rollback patch of '{}': {:#}",
+                    patch_name,
+                    e
+                );
+            }
+        }
+        let started_at = Instant::now();
+        let mut attempt = 0;
+        let (res, cancel_requested) = loop {
+            let (res, cancel_requested) = apply_patch_interruptibly(
+                &pending_patch.local_file_path,
+                config,
+                &self.current_working_dir,
+                self.verify_installed_patches,
+                patching_thread_rx,
+            )
+            .await;
+            // The game client might hold the GRFs open
+            let is_locked = res
+                .as_ref()
+                .is_err_and(|e| ErrorKind::of(e) == ErrorKind::GameClientRunning);
+            if !is_locked || cancel_requested || attempt >= config.patching.locked_file_retries() {
+                break (res, cancel_requested);
+            }
+            if let Err(e) = &res {
+                tracing::warn!("{:#}", e);
+            }
+            let retry_delay = config.patching.locked_file_retry_delay(attempt);
+            attempt += 1;
+            match wait_for_locked_file_retry(ui_controller, patching_thread_rx, retry_delay).await {
+                Ok(()) => ui_controller.dispatch_patching_status(
+                    PatchingStatus::InstallationInProgress(patch_number, patch_count),
+                ),
+                Err(_) => break (res, true),
+            }
+        };
+        record_patch_application(
+            Some(pending_patch.info.index),
+            Some(&self.server_id),
+            &patch_name,
+            &pending_patch.local_file_path,
+            started_at,
+            &res,
+        );
+        match res {
+            // Errors are expected once patching has been interrupted
+            Err(_) if cancel_requested => {
+                tracing::info!("Update cancelled by user, '{}' was interrupted", patch_name);
+                return Err(InterruptibleFnError::Interrupted);
+            }
+            res => res.map_err(|e| {
+                InterruptibleFnError::Err(
+                    e.context(format!("Failed to apply patch '{}'", patch_name)),
+                )
+            })?,
+        }
+        self.hooks
+            .after_patch(&patch_name, Some(pending_patch.info.index));
+        if config.peer_cache.enabled {
+            if let Err(e) = keep_patch_for_peers(&config.peer_cache, &pending_patch.local_file_path)
+            {
+                tracing::warn!("Failed to keep '{}' for peers: {:#}", patch_name, e);
+            }
+        }
+        // Patches downloaded ahead of time aren't needed anymore
+        let _ = std::fs::remove_file(&pending_patch.local_file_path);
+        // Update status
+        ui_controller.dispatch_patching_status(PatchingStatus::InstallationInProgress(
+            1 + patch_number,
+            patch_count,
+        ));
+        if cancel_requested {
+            tracing::info!("Update cancelled by user, after '{}'", patch_name);
+            return Err(InterruptibleFnError::Interrupted);
+        }
+        Ok(())
+    }
+
+    /// Records that the installed patches have been verified, once all the
+    /// patches have been applied.
+    fn finish(self) {
+        if self.verify_installed_patches {
+            if let Err(e) = update_patcher_state(|state| state.verification_pending = false) {
+                tracing::warn!("Failed to update patcher state: {:#}", e);
+            }
+        }
+    }
+}
+
+/// Waits before patching files which another program held open again, until
+/// `retry_delay` has elapsed or the user asks to retry.
+///
+/// This function is interruptible.
+async fn wait_for_locked_file_retry(
+    ui_controller: &UiController,
+    patching_thread_rx: &mut UnboundedReceiver<PatcherCommand>,
+    retry_delay: Duration,
+) -> InterruptibleFnResult<()> {
+    tracing::info!("Retrying in {}s", retry_delay.as_secs());
+    ui_controller.dispatch_patching_status(PatchingStatus::FilesLocked);
+    let retry_timer = tokio::time::sleep(retry_delay);
+    tokio::pin!(retry_timer);
+    loop {
+        tokio::select! {
+            _ = &mut retry_timer => return Ok(()),
+            command = patching_thread_rx.recv() => match command {
+                Some(PatcherCommand::RetryPatch) => return Ok(()),
+                Some(PatcherCommand::CancelUpdate)
+                | Some(PatcherCommand::ForceCancelUpdate)
+                | Some(PatcherCommand::Quit)
+                | None => return Err(InterruptibleFnError::Interrupted),
+                // Other commands make no sense while waiting
+                Some(_) => {}
+            }
+        }
+    }
+}
+
+/// Applies a patch on the blocking thread pool, so that commands can still be
+/// received meanwhile.
+///
+/// `CancelUpdate` lets the patch be applied completely, `ForceCancelUpdate`
+/// interrupts it between two of its files. Returns whether cancellation has
+/// been requested along with the result.
+async fn apply_patch_interruptibly(
+    thor_archive_path: &Path,
+    config: &PatcherConfiguration,
+    current_working_dir: &Path,
+    verify_installed_patch: bool,
+    patching_thread_rx: &mut UnboundedReceiver<PatcherCommand>,
+) -> (Result<()>, bool) {
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let mut patching_task = {
+        let thor_archive_path = thor_archive_path.to_path_buf();
+        let config = config.clone();
+        let current_working_dir = current_working_dir.to_path_buf();
+        let interrupted = interrupted.clone();
+        let span = tracing::Span::current();
+        tokio::task::spawn_blocking(move || {
+            let _entered = span.enter();
+            match verify_installed_patch {
+                true => verify_or_apply_patch(
+                    &thor_archive_path,
+                    &config,
+                    &current_working_dir,
+                    &interrupted,
+                ),
+                false => apply_patch(
+                    &thor_archive_path,
+                    &config,
+                    &current_working_dir,
+                    &interrupted,
+                ),
+            }
+        })
+    };
+    let mut cancel_requested = false;
+    loop {
+        tokio::select! {
+            res = &mut patching_task => {
+                let res = res
+                    .map_err(|e| anyhow!("Patching task failed: {}", e))
+                    .and_then(|res| res);
+                return (res, cancel_requested);
+            }
+            command = patching_thread_rx.recv(), if !interrupted.load(Ordering::SeqCst) => {
+                match command {
+                    Some(PatcherCommand::CancelUpdate) | Some(PatcherCommand::Quit) => {
+                        if !cancel_requested {
+                            tracing::info!("Canceling once the current patch has been applied");
+                        }
+                        cancel_requested = true;
+                    }
+                    Some(PatcherCommand::ForceCancelUpdate) | None => {
+                        tracing::warn!("Interrupting the current patch");
+                        cancel_requested = true;
+                        interrupted.store(true, Ordering::SeqCst);
+                    }
+                    // Other commands make no sense while patching
+                    Some(_) => {}
+                }
+            }
+        }
+    }
+}
+
+#[tracing::instrument(skip_all, fields(patch = %thor_archive_path.as_ref().display()))]
+pub(super) fn apply_patch(
+    thor_archive_path: impl AsRef<Path>,
+    config: &PatcherConfiguration,
+    current_working_dir: impl AsRef<Path>,
+    interrupted: &AtomicBool,
+) -> Result<()> {
+    if is_zip_file(thor_archive_path.as_ref()) {
+        return apply_zip_patch(thor_archive_path, config, current_working_dir, interrupted);
+    }
+    let mut thor_archive = ThorArchive::open(thor_archive_path.as_ref())?;
+    let (target_grf_name, file_changes) = if thor_archive.use_grf_merging() {
+        // Patch GRF file
+        let target_grf_name = get_target_grf_name(&thor_archive, config);
+        tracing::trace!("Target GRF: {:?}", target_grf_name);
+        let grf_patching_method = match config.patching.in_place {
+            true => GrfPatchingMethod::InPlace,
+            false => GrfPatchingMethod::OutOfPlace {
+                buffer_size: config.advanced.grf_buffer_size(),
+            },
+        };
+        let target_grf_path = current_working_dir.as_ref().join(&target_grf_name);
+        let file_changes = apply_patch_to_grf(
+            grf_patching_method,
+            config.patching.create_grf,
+            target_grf_path,
+            &mut thor_archive,
+            interrupted,
+        )?;
+        (Some(target_grf_name), file_changes)
+    } else {
+        // Patch root directory
+        (
+            None,
+            apply_patch_to_disk(
+                current_working_dir,
+                &mut thor_archive,
+                &DiskPatchingOptions {
+                    clear_read_only: config.patching.clear_read_only,
+                    strip_motw: config.patching.strip_motw,
+                },
+                interrupted,
+            )?,
+        )
+    };
+
+    record_installed_files(thor_archive_path, target_grf_name, file_changes);
+    Ok(())
+}
+
+/// Extracts a ZIP patch into the game client's directory.
+fn apply_zip_patch(
+    zip_archive_path: impl AsRef<Path>,
+    config: &PatcherConfiguration,
+    current_working_dir: impl AsRef<Path>,
+    interrupted: &AtomicBool,
+) -> Result<()> {
+    let mut zip_archive = ZipArchive::open(zip_archive_path.as_ref())?;
+    let file_changes = apply_zip_patch_to_disk(
+        current_working_dir,
+        &mut zip_archive,
+        &DiskPatchingOptions {
+            clear_read_only: config.patching.clear_read_only,
+            strip_motw: config.patching.strip_motw,
+        },
+        interrupted,
+    )?;
+    record_installed_files(zip_archive_path, None, file_changes);
+    Ok(())
+}
+
+/// Applies a patch unless its changes are already present in the game's
+/// files.
+#[tracing::instrument(skip_all, fields(patch = %thor_archive_path.as_ref().display()))]
+fn verify_or_apply_patch(
+    thor_archive_path: impl AsRef<Path>,
+    config: &PatcherConfiguration,
+    current_working_dir: impl AsRef<Path>,
+    interrupted: &AtomicBool,
+) -> Result<()> {
+    // ZIP patches can't remove files, applying them again is harmless
+    if is_zip_file(thor_archive_path.as_ref()) {
+        return apply_patch(thor_archive_path, config, current_working_dir, interrupted);
+    }
+    let mut thor_archive = ThorArchive::open(thor_archive_path.as_ref())?;
+    let (target_grf_name, file_changes) = if thor_archive.use_grf_merging() {
+        let target_grf_name = get_target_grf_name(&thor_archive, config);
+        let target_grf_path = current_working_dir.as_ref().join(&target_grf_name);
+        (
+            Some(target_grf_name),
+            verify_patch_in_grf(target_grf_path, &mut thor_archive)?,
+        )
+    } else {
+        (
+            None,
+            verify_patch_on_disk(&current_working_dir, &mut thor_archive)?,
+        )
+    };
+    match file_changes {
+        Some(file_changes) => {
+            tracing::info!("Patch is already installed");
+            record_installed_files(thor_archive_path, target_grf_name, file_changes);
+            Ok(())
+        }
+        None => apply_patch(thor_archive_path, config, current_working_dir, interrupted),
+    }
+}
+
+pub(super) fn get_target_grf_name<R: Read + Seek>(
+    thor_archive: &ThorArchive<R>,
+    config: &PatcherConfiguration,
+) -> String {
+    if thor_archive.target_grf_name().is_empty() {
+        config.client.default_grf_name.clone()
+    } else {
+        thor_archive.target_grf_name()
+    }
+}
+
+/// Keeps track of the files installed by a patch in the manifest.
+fn record_installed_files(
+    thor_archive_path: impl AsRef<Path>,
+    target_grf_name: Option<String>,
+    file_changes: Vec<FileChange>,
+) {
+    let patch_name = thor_archive_path
+        .as_ref()
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy();
+    if let Err(e) = update_patcher_state(|state| {
+        state
+            .manifest
+            .record_changes(target_grf_name.as_deref(), &patch_name, file_changes)
+    }) {
+        tracing::warn!("Failed to update the manifest: {:#}", e);
+    }
+}
+
+/// Starts the update process
+async fn start_update(
+    config: &PatcherConfiguration,
+    ui_controller: &mut UiController,
+    patching_thread_rx: &mut UnboundedReceiver<PatcherCommand>,
+    unfinished_update: &mut Option<UnfinishedUpdate>,
+) -> Result<()> {
+    ensure_agreement_is_accepted(config.agreement.as_ref())?;
+    update_game(ui_controller, config, patching_thread_rx, unfinished_update).await;
+    Ok(())
+}
+
+/// Lists the files of the game directory left over by removed content.
+async fn list_orphaned_files(config: &PatcherConfiguration) -> Result<Vec<OrphanedFile>> {
+    let client = build_http_client(&config.web)?;
+    let game_directory =
+        env::current_dir().with_context(|| "Failed to resolve current working directory")?;
+    let state = retrieve_patcher_state()?;
+    find_orphaned_files(&client, &config.cleanup, &state.manifest, &game_directory).await
+}
+
+/// Deletes the orphaned files of `selection`, or all of them if not set.
+///
+/// Files are only deleted if they're still orphaned, since patches might
+/// have been applied in the meantime.
+async fn delete_orphaned_files(
+    config: &PatcherConfiguration,
+    selection: Option<Vec<String>>,
+) -> Result<(usize, u64)> {
+    let _lock_file = take_update_lock().with_context(|| "Failed to take the update lock")?;
+    let mut orphaned_files = list_orphaned_files(config).await?;
+    if let Some(selection) = selection {
+        orphaned_files.retain(|orphaned_file| selection.contains(&orphaned_file.relative_path));
+    }
+    let game_directory =
+        env::current_dir().with_context(|| "Failed to resolve current working directory")?;
+    Ok(remove_orphaned_files(&game_directory, &orphaned_files))
+}
+
+/// Resets the patcher cache
+fn reset_cache() -> Result<()> {
+    update_patcher_state(|state| state.reset_patch_records())
+        .context("Failed to reset the patcher state")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::config::STABLE_CHANNEL;
+    use super::super::patch_servers::{fetch_patch_list, fetch_patch_manifest};
+    use super::*;
+    use httptest::{matchers::*, responders::*, Expectation, Server};
+
+    #[test]
+    fn test_take_applicable_patches() {
+        let patch_info = |index: usize| ThorPatchInfo {
+            index,
+            file_name: format!("{}.thor", index),
+        };
+        let mut pending_patch_queue: Vec<PendingPatch> = [1, 2, 4, 5]
+            .iter()
+            .map(|&index| PendingPatch {
+                info: patch_info(index),
+                local_file_path: PathBuf::from(format!("{}.thor", index)),
+                unverified: false,
+            })
+            .collect();
+        let failed_patches = vec![FailedPatch {
+            info: patch_info(3),
+            error: anyhow!("Not found"),
+        }];
+        // Patches following the one that failed have to wait for it
+        let applicable_patches = take_applicable_patches(&mut pending_patch_queue, &failed_patches);
+        let applicable_indices: Vec<usize> =
+            applicable_patches.iter().map(|p| p.info.index).collect();
+        assert_eq!(applicable_indices, [1, 2]);
+        let pending_indices: Vec<usize> =
+            pending_patch_queue.iter().map(|p| p.info.index).collect();
+        assert_eq!(pending_indices, [4, 5]);
+
+        let applicable_patches = take_applicable_patches(&mut pending_patch_queue, &[]);
+        assert_eq!(applicable_patches.len(), 2);
+        assert!(pending_patch_queue.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_fetch_release_notes() {
+        let server = Server::run();
+        server.expect(
+            Expectation::matching(request::method_path("GET", "/patches/patches.json"))
+                .respond_with(status_code(200).body(
+                    r##"{"patches":[
+                        {"index":1,"file_name":"1.thor","size":3,"sha256":"ab","notes":"# Fixes"},
+                        {"index":2,"file_name":"2.thor","size":3,"sha256":"cd","notes_url":"notes/2.md"},
+                        {"index":3,"file_name":"3.thor","size":3,"sha256":"ef","notes_url":"notes/3.md"},
+                        {"index":4,"file_name":"4.thor","size":3,"sha256":"01","notes":"Pending"},
+                        {"index":5,"file_name":"5.thor","size":3,"sha256":"23"}
+                    ]}"##,
+                )),
+        );
+        server.expect(
+            Expectation::matching(request::method_path("GET", "/patches/notes/2.md"))
+                .respond_with(status_code(200).body("- New map")),
+        );
+        server.expect(
+            Expectation::matching(request::method_path("GET", "/patches/notes/3.md"))
+                .respond_with(status_code(404)),
+        );
+        let client = reqwest::Client::new();
+        let patch_url = Url::parse(&server.url_str("/patches/")).unwrap();
+        let result = |index: usize, outcome: PatchOutcome| PatchResult {
+            index,
+            file_name: format!("{}.thor", index),
+            outcome,
+        };
+        let results = vec![
+            result(1, PatchOutcome::Applied),
+            result(2, PatchOutcome::Applied),
+            result(3, PatchOutcome::Applied),
+            result(4, PatchOutcome::Pending),
+            result(5, PatchOutcome::Applied),
+        ];
+
+        let manifest = fetch_patch_manifest(&client, &patch_url).await;
+        let patch_manifest = manifest_entries(manifest.as_ref());
+        let release_notes =
+            fetch_release_notes(&client, &patch_url, &patch_manifest, &results).await;
+        assert_eq!(
+            release_notes,
+            vec![
+                ReleaseNote {
+                    index: 1,
+                    file_name: "1.thor".to_string(),
+                    text: Some("# Fixes".to_string()),
+                    url: None,
+                },
+                ReleaseNote {
+                    index: 2,
+                    file_name: "2.thor".to_string(),
+                    text: Some("- New map".to_string()),
+                    url: None,
+                },
+                // Players can still read notes which failed to download
+                ReleaseNote {
+                    index: 3,
+                    file_name: "3.thor".to_string(),
+                    text: None,
+                    url: Some(server.url_str("/patches/notes/3.md")),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_required_patcher_version() {
+        let manifest: PatchManifest = serde_json::from_str(
+            r#"{"min_patcher_version":"1.2","patches":[
+                {"index":1,"file_name":"1.thor","size":3,"sha256":"ab","target_grf":null,"min_launcher_version":"9.0.0"},
+                {"index":2,"file_name":"2.thor","size":3,"sha256":"cd","target_grf":null,"min_launcher_version":"1.10.0-beta"},
+                {"index":3,"file_name":"3.thor","size":3,"sha256":"ef","target_grf":null,"min_launcher_version":"latest"}
+            ]}"#,
+        )
+        .unwrap();
+        let patch_list = (2..=3)
+            .map(|index| ThorPatchInfo {
+                index,
+                file_name: format!("{}.thor", index),
+            })
+            .collect();
+        // Only the pending patches' requirements matter
+        assert_eq!(
+            required_patcher_version(&manifest, &patch_list),
+            Some("1.10.0-beta")
+        );
+        assert_eq!(required_patcher_version(&manifest, &vec![]), Some("1.2"));
+
+        assert!(is_version_older("1.2.0", "1.10"));
+        assert!(!is_version_older("1.2.0", "1.2"));
+        assert!(!is_version_older("v2.0.1", "2.0.0-rc1"));
+        assert!(!is_version_older("1.0.0", "latest"));
+    }
+
+    #[test]
+    fn test_retain_client_patches() {
+        let manifest: PatchManifest = serde_json::from_str(
+            r#"{"patches":[
+                {"index":1,"file_name":"1.thor","size":3,"sha256":"ab","target_grf":null},
+                {"index":2,"file_name":"2.thor","size":3,"sha256":"cd","target_grf":null,"tags":["lang=pt-BR"]},
+                {"index":3,"file_name":"3.thor","size":3,"sha256":"ef","target_grf":null,"tags":["lang = es"]}
+            ]}"#,
+        )
+        .unwrap();
+        let retained_file_names = |language: Option<&str>| {
+            let client_config = ClientConfiguration {
+                default_grf_name: "data.grf".to_string(),
+                language: language.map(str::to_string),
+                platform: None,
+            };
+            let mut patch_list: ThorPatchList = (1..=4)
+                .map(|index| ThorPatchInfo {
+                    index,
+                    file_name: format!("{}.thor", index),
+                })
+                .collect();
+            retain_client_patches(&mut patch_list, &manifest, &client_config);
+            patch_list
+                .into_iter()
+                .map(|patch_info| patch_info.file_name)
+                .collect::<Vec<_>>()
+        };
+        // Patches missing from the manifest are kept
+        assert_eq!(retained_file_names(None), ["1.thor", "4.thor"]);
+        assert_eq!(
+            retained_file_names(Some("pt_br")),
+            ["1.thor", "2.thor", "4.thor"]
+        );
+        assert_eq!(retained_file_names(Some("pt-PT")), ["1.thor", "4.thor"]);
+        assert_eq!(
+            retained_file_names(Some("es-MX")),
+            ["1.thor", "3.thor", "4.thor"]
+        );
+    }
+
+    #[test]
+    fn test_is_patch_for_platform() {
+        let mut entry = PatchManifestEntry {
+            index: 1,
+            file_name: "dinput.thor".to_string(),
+            ..Default::default()
+        };
+        let client_config = |platform: &str| ClientConfiguration {
+            default_grf_name: "data.grf".to_string(),
+            language: None,
+            platform: Some(platform.to_string()),
+        };
+        assert!(is_patch_for_client(&entry, &client_config("linux")));
+        entry.tags = vec!["platform=win32, Win64".to_string()];
+        assert!(is_patch_for_client(&entry, &client_config("win32")));
+        assert!(is_patch_for_client(&entry, &client_config("win64")));
+        assert!(!is_patch_for_client(&entry, &client_config("linux")));
+        entry.tags = vec!["platform=windows".to_string()];
+        assert!(is_patch_for_client(&entry, &client_config("win64")));
+        assert!(!is_patch_for_client(&entry, &client_config("linux")));
+        // Every tag has to match
+        entry.tags.push("lang=pt-BR".to_string());
+        assert!(!is_patch_for_client(&entry, &client_config("win64")));
+    }
+
+    #[tokio::test]
+    async fn test_build_http_client() {
+        let server = Server::run();
+        server.expect(
+            Expectation::matching(request::method_path("GET", "/plist.txt"))
+                .respond_with(status_code(200).body("1 1.thor\n")),
+        );
+        let plist_url = Url::parse(&server.url_str("/plist.txt")).unwrap();
+        let mut web_config = WebConfiguration {
+            preferred_patch_server: None,
+            patch_servers: vec![],
+            server_id: None,
+            require_https: false,
+            max_redirects: None,
+            same_host_redirects_only: false,
+            probe_cache_ttl: None,
+            forced_patch_server: None,
+        };
+
+        let client = build_http_client(&web_config).unwrap();
+        let patch_list = fetch_patch_list(&client, plist_url.clone(), None, STABLE_CHANNEL)
+            .await
+            .unwrap();
+        assert_eq!(patch_list.len(), 1);
+        // The server doesn't get any other request
+        web_config.require_https = true;
+        let client = build_http_client(&web_config).unwrap();
+        assert!(fetch_patch_list(&client, plist_url, None, STABLE_CHANNEL)
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn test_dispatch_cancellation() {
+        let (status_tx, status_rx) = mpsc::channel();
+        let ui_controller = UiController::new(status_tx, false);
+        ui_controller.set_patching_in_progress(true);
+        ui_controller.dispatch_patching_status(PatchingStatus::DownloadInProgress(3, 4, 0));
+        ui_controller.dispatch_patching_status(PatchingStatus::InstallationInProgress(1, 3));
+        ui_controller.add_downloaded_bytes(1024);
+        ui_controller.dispatch_cancellation();
+        let progress = status_rx
+            .try_iter()
+            .find_map(|status| match status {
+                PatchingStatus::Cancelled(progress) => Some(progress),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(
+            progress,
+            UpdateProgress {
+                downloaded_patches: 3,
+                applied_patches: 1,
+                total_patches: 4,
+                downloaded_bytes: 1024,
+                elapsed_ms: progress.elapsed_ms,
+            }
+        );
+
+        // Progress is tracked per operation
+        ui_controller.set_patching_in_progress(true);
+        ui_controller.dispatch_cancellation();
+        assert!(matches!(
+            status_rx.try_iter().last(),
+            Some(PatchingStatus::Cancelled(progress)) if progress == UpdateProgress {
+                elapsed_ms: progress.elapsed_ms,
+                ..Default::default()
+            }
+        ));
+        assert_eq!(
+            ErrorKind::of(&update_cancelled_error().context("Failed to apply patches")),
+            ErrorKind::Cancelled
+        );
+    }
+
+    #[tokio::test]
+    async fn test_build_http_client_redirects() {
+        let server = Server::run();
+        // Same server, reached through another host name
+        let other_host_url = server
+            .url_str("/plist.txt")
+            .replace("127.0.0.1", "localhost");
+        server.expect(
+            Expectation::matching(request::method_path("GET", "/short"))
+                .times(3)
+                .respond_with(status_code(302).insert_header("Location", other_host_url.as_str())),
+        );
+        server.expect(
+            Expectation::matching(request::method_path("GET", "/plist.txt"))
+                .respond_with(status_code(200).body("1 1.thor\n")),
+        );
+        let short_url = Url::parse(&server.url_str("/short")).unwrap();
+        let mut web_config = WebConfiguration {
+            preferred_patch_server: None,
+            patch_servers: vec![],
+            server_id: None,
+            require_https: false,
+            max_redirects: Some(0),
+            same_host_redirects_only: false,
+            probe_cache_ttl: None,
+            forced_patch_server: None,
+        };
+
+        let client = build_http_client(&web_config).unwrap();
+        assert!(
+            fetch_patch_list(&client, short_url.clone(), None, STABLE_CHANNEL)
+                .await
+                .is_err()
+        );
+        web_config.max_redirects = None;
+        web_config.same_host_redirects_only = true;
+        let client = build_http_client(&web_config).unwrap();
+        assert!(
+            fetch_patch_list(&client, short_url.clone(), None, STABLE_CHANNEL)
+                .await
+                .is_err()
+        );
+        web_config.same_host_redirects_only = false;
+        let client = build_http_client(&web_config).unwrap();
+        let patch_list = fetch_patch_list(&client, short_url, None, STABLE_CHANNEL)
+            .await
+            .unwrap();
+        assert_eq!(patch_list.len(), 1);
+    }
+}
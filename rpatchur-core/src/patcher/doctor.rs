@@ -0,0 +1,396 @@
+use std::fs::OpenOptions;
+use std::io;
+use std::path::Path;
+
+use advisory_lock::FileLockError;
+use chrono::{DateTime, Utc};
+
+use super::config::PatchServerInfo;
+use super::core::{build_http_client, build_tokio_runtime};
+use super::patch_servers::probe_patch_server;
+use super::paths::get_configuration_file_path;
+use super::update_lock::take_update_lock;
+use super::{retrieve_patcher_configuration, PatcherConfiguration};
+use crate::process::find_running_processes;
+use crate::status::human_readable_size;
+
+// Below these thresholds, updates are likely to fail midway
+const LOW_DISK_SPACE: u64 = 1000 * 1000 * 1000;
+const CRITICAL_DISK_SPACE: u64 = 100 * 1000 * 1000;
+// Beyond this, TLS certificates might be rejected
+const MAX_CLOCK_SKEW_SECS: i64 = 5 * 60;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CheckStatus {
+    Pass,
+    Warning, // Updates might fail
+    Failure, // Updates will fail
+}
+
+/// Outcome of one of the checks run by `doctor`.
+#[derive(Debug)]
+pub struct CheckResult {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub details: String,
+}
+
+impl CheckResult {
+    fn new(name: &'static str, status: CheckStatus, details: impl Into<String>) -> Self {
+        Self {
+            name,
+            status,
+            details: details.into(),
+        }
+    }
+}
+
+/// Runs the checks which cover the most common causes of failed updates.
+pub fn run_health_checks() -> Vec<CheckResult> {
+    let mut results = vec![];
+    let config = check_configuration(&mut results);
+    if let Some(config) = &config {
+        let server_results = build_tokio_runtime().and_then(|tokio_rt| {
+            let client = build_http_client(&config.web)?;
+            Ok(tokio_rt.block_on(check_patch_servers(
+                &client,
+                &config.web.patch_servers,
+                config.patching.channel(),
+            )))
+        });
+        match server_results {
+            Ok(server_results) => results.extend(server_results),
+            Err(e) => results.push(CheckResult::new(
+                "patch_servers",
+                CheckStatus::Failure,
+                format!("{:#}", e),
+            )),
+        }
+    }
+    results.push(check_game_directory());
+    if let Some(config) = &config {
+        results.push(check_grf(config));
+        results.push(check_game_client(config));
+    }
+    results.push(check_disk_space());
+    results.push(check_update_lock());
+    results
+}
+
+fn check_configuration(results: &mut Vec<CheckResult>) -> Option<PatcherConfiguration> {
+    let config_file_name = get_configuration_file_path()
+        .map(|path| path.display().to_string())
+        .unwrap_or_default();
+    match retrieve_patcher_configuration(None) {
+        Ok(config) => {
+            results.push(CheckResult::new(
+                "configuration",
+                CheckStatus::Pass,
+                format!("'{}' is valid", config_file_name),
+            ));
+            Some(config)
+        }
+        Err(e) => {
+            results.push(CheckResult::new(
+                "configuration",
+                CheckStatus::Failure,
+                format!("'{}' couldn't be loaded: {:#}", config_file_name, e),
+            ));
+            None
+        }
+    }
+}
+
+/// Probes every patch server, and compares the local clock with the first
+/// available server's.
+async fn check_patch_servers(
+    client: &reqwest::Client,
+    patch_servers: &[PatchServerInfo],
+    channel: &str,
+) -> Vec<CheckResult> {
+    let mut server_reports = vec![];
+    let mut available_server = None;
+    let mut available_count = 0;
+    for server in patch_servers {
+        match probe_patch_server(client, server, channel).await {
+            Ok(listing) => {
+                server_reports.push(format!(
+                    "'{}' is available ({} patch(es))",
+                    server.name,
+                    listing.patch_list.len()
+                ));
+                available_server = available_server.or(Some(server));
+                available_count += 1;
+            }
+            Err(e) => server_reports.push(format!("'{}' is unavailable: {:#}", server.name, e)),
+        }
+    }
+    let status = if available_server.is_none() {
+        CheckStatus::Failure
+    } else if available_count < patch_servers.len() {
+        CheckStatus::Warning
+    } else {
+        CheckStatus::Pass
+    };
+    if server_reports.is_empty() {
+        server_reports.push("No patch server is configured".to_string());
+    }
+    let servers_result = CheckResult::new("patch_servers", status, server_reports.join("; "));
+
+    let clock_result = match available_server {
+        Some(server) => check_clock_skew(client, server).await,
+        None => CheckResult::new(
+            "clock",
+            CheckStatus::Warning,
+            "No patch server to compare the clock with",
+        ),
+    };
+    vec![servers_result, clock_result]
+}
+
+async fn check_clock_skew(client: &reqwest::Client, server: &PatchServerInfo) -> CheckResult {
+    let server_time = fetch_server_time(client, &server.plist_url).await;
+    match server_time {
+        Some(server_time) => {
+            let skew_secs = Utc::now().signed_duration_since(server_time).num_seconds();
+            let details = format!(
+                "Local clock is {}s {} '{}'",
+                skew_secs.abs(),
+                if skew_secs >= 0 { "ahead of" } else { "behind" },
+                server.name
+            );
+            if skew_secs.abs() > MAX_CLOCK_SKEW_SECS {
+                CheckResult::new("clock", CheckStatus::Warning, details)
+            } else {
+                CheckResult::new("clock", CheckStatus::Pass, details)
+            }
+        }
+        None => CheckResult::new(
+            "clock",
+            CheckStatus::Warning,
+            format!("'{}' didn't send its time", server.name),
+        ),
+    }
+}
+
+/// Reads the time from the 'Date' header of the server's response.
+async fn fetch_server_time(client: &reqwest::Client, url: &str) -> Option<DateTime<Utc>> {
+    let resp = client.head(url).send().await.ok()?;
+    let date = resp.headers().get(reqwest::header::DATE)?.to_str().ok()?;
+    DateTime::parse_from_rfc2822(date)
+        .ok()
+        .map(|date| date.with_timezone(&Utc))
+}
+
+fn check_game_directory() -> CheckResult {
+    match tempfile::tempfile_in(".") {
+        Ok(_) => CheckResult::new(
+            "game_directory",
+            CheckStatus::Pass,
+            "The game directory is writable",
+        ),
+        Err(e) => CheckResult::new(
+            "game_directory",
+            CheckStatus::Failure,
+            format!("The game directory isn't writable: {}", e),
+        ),
+    }
+}
+
+fn check_grf(config: &PatcherConfiguration) -> CheckResult {
+    let grf_path = Path::new(&config.client.default_grf_name);
+    if !grf_path.exists() {
+        return if config.patching.create_grf {
+            CheckResult::new(
+                "grf",
+                CheckStatus::Pass,
+                format!("'{}' will be created", grf_path.display()),
+            )
+        } else {
+            CheckResult::new(
+                "grf",
+                CheckStatus::Failure,
+                format!("'{}' doesn't exist", grf_path.display()),
+            )
+        };
+    }
+    // Fails if the GRF is read-only or in use by the game client
+    match OpenOptions::new().write(true).open(grf_path) {
+        Ok(_) => CheckResult::new(
+            "grf",
+            CheckStatus::Pass,
+            format!("'{}' is writable", grf_path.display()),
+        ),
+        Err(e) => CheckResult::new(
+            "grf",
+            CheckStatus::Failure,
+            format!("'{}' isn't writable: {}", grf_path.display(), e),
+        ),
+    }
+}
+
+fn check_game_client(config: &PatcherConfiguration) -> CheckResult {
+    match find_running_processes(&config.play.path) {
+        Ok(pids) if pids.is_empty() => CheckResult::new(
+            "game_client",
+            CheckStatus::Pass,
+            format!("'{}' isn't running", config.play.path),
+        ),
+        Ok(_) => CheckResult::new(
+            "game_client",
+            CheckStatus::Warning,
+            format!(
+                "'{}' is running and has to be closed before patching",
+                config.play.path
+            ),
+        ),
+        Err(e) => CheckResult::new("game_client", CheckStatus::Warning, format!("{:#}", e)),
+    }
+}
+
+fn check_disk_space() -> CheckResult {
+    match available_disk_space(".") {
+        Ok(available_bytes) => {
+            let status = if available_bytes < CRITICAL_DISK_SPACE {
+                CheckStatus::Failure
+            } else if available_bytes < LOW_DISK_SPACE {
+                CheckStatus::Warning
+            } else {
+                CheckStatus::Pass
+            };
+            let details = format!("{} available", human_readable_size(available_bytes));
+            CheckResult::new("disk_space", status, details)
+        }
+        Err(e) => CheckResult::new(
+            "disk_space",
+            CheckStatus::Warning,
+            format!("Failed to retrieve the available disk space: {}", e),
+        ),
+    }
+}
+
+fn check_update_lock() -> CheckResult {
+    match take_update_lock() {
+        // The lock is released as soon as the file is closed
+        Ok(_) => CheckResult::new(
+            "update_lock",
+            CheckStatus::Pass,
+            "No other instance is updating the game",
+        ),
+        Err(e) => match e.downcast_ref::<FileLockError>() {
+            Some(FileLockError::AlreadyLocked) => CheckResult::new(
+                "update_lock",
+                CheckStatus::Warning,
+                "Another instance of the patcher is updating the game",
+            ),
+            _ => CheckResult::new(
+                "update_lock",
+                CheckStatus::Failure,
+                format!("Failed to take the update lock: {:#}", e),
+            ),
+        },
+    }
+}
+
+/// Returns the number of bytes available to the current user on the disk
+/// containing `path`.
+#[cfg(windows)]
+fn available_disk_space(path: impl AsRef<Path>) -> io::Result<u64> {
+    use std::os::windows::ffi::OsStrExt;
+    use winapi::shared::ntdef::ULARGE_INTEGER;
+    use winapi::um::fileapi::GetDiskFreeSpaceExW;
+
+    let path: Vec<u16> = path
+        .as_ref()
+        .as_os_str()
+        .encode_wide()
+        .chain(std::iter::once(0))
+        .collect();
+    unsafe {
+        let mut available_bytes: ULARGE_INTEGER = std::mem::zeroed();
+        let res = GetDiskFreeSpaceExW(
+            path.as_ptr(),
+            &mut available_bytes,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+        );
+        if res == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(*available_bytes.QuadPart())
+    }
+}
+
+/// Returns the number of bytes available to the current user on the disk
+/// containing `path`.
+#[cfg(unix)]
+fn available_disk_space(path: impl AsRef<Path>) -> io::Result<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(path.as_ref().as_os_str().as_bytes())?;
+    unsafe {
+        let mut stat: libc::statvfs = std::mem::zeroed();
+        if libc::statvfs(path.as_ptr(), &mut stat) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use httptest::{matchers::*, responders::*, Expectation, Server};
+
+    #[tokio::test]
+    async fn test_check_patch_servers() {
+        let server = Server::run();
+        server.expect(
+            Expectation::matching(request::method_path("GET", "/plist.txt"))
+                .respond_with(status_code(200).body("1 1.thor\n")),
+        );
+        server.expect(
+            Expectation::matching(request::method_path("GET", "/patches.json"))
+                .respond_with(status_code(404)),
+        );
+        server.expect(
+            Expectation::matching(request::method_path("HEAD", "/1.thor"))
+                .respond_with(status_code(200)),
+        );
+        // Used to compare clocks
+        let now = Utc::now().to_rfc2822();
+        server.expect(
+            Expectation::matching(request::method_path("HEAD", "/plist.txt"))
+                .respond_with(status_code(200).insert_header("Date", now.as_str())),
+        );
+        let patch_servers = vec![
+            PatchServerInfo {
+                name: "primary".to_string(),
+                plist_url: server.url_str("/plist.txt"),
+                patch_url: server.url_str("/"),
+                plist_format: None,
+            },
+            PatchServerInfo {
+                name: "backup".to_string(),
+                plist_url: "http://127.0.0.1:1/plist.txt".to_string(),
+                patch_url: "http://127.0.0.1:1/".to_string(),
+                plist_format: None,
+            },
+        ];
+
+        let client = reqwest::Client::new();
+        let results = check_patch_servers(&client, &patch_servers, "stable").await;
+        assert_eq!(results[0].name, "patch_servers");
+        assert_eq!(results[0].status, CheckStatus::Warning);
+        assert!(results[0]
+            .details
+            .starts_with("'primary' is available (1 patch(es)); 'backup' is unavailable"));
+        assert_eq!(results[1].name, "clock");
+        assert_eq!(results[1].status, CheckStatus::Pass);
+
+        let results = check_patch_servers(&client, &patch_servers[1..], "stable").await;
+        assert_eq!(results[0].status, CheckStatus::Failure);
+        assert_eq!(results[1].status, CheckStatus::Warning);
+    }
+}
@@ -0,0 +1,1396 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{
+    atomic::{AtomicU64, AtomicUsize, Ordering},
+    Arc,
+};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context, Result};
+use futures::executor::block_on;
+use futures::stream::StreamExt;
+use gruf::thor::{self, ThorArchive, ThorPatchInfo, ThorPatchList};
+use gruf::GrufError;
+use sha2::{Digest, Sha256};
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufWriter};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use url::Url;
+
+use super::cancellation::{wait_for_cancellation, InterruptibleFnResult};
+use super::config::{AdvancedConfiguration, DownloadOrder, IntegrityPolicy};
+use super::core::{log_final_url, UiController};
+use super::error::{ErrorKind, PatcherError};
+use super::peer_cache::Peers;
+use super::publish::{to_hex, PatchManifestEntry};
+use super::simulation::{check_chunk_loss, get_network_simulation};
+use super::zip::{is_zip_file, ZipArchive};
+use super::{PatcherCommand, PatcherConfiguration};
+use crate::status::PatchingStatus;
+
+/// Representation of a pending patch (a patch that's been downloaded but has
+/// not been applied yet).
+#[derive(Debug)]
+pub(super) struct PendingPatch {
+    pub(super) info: thor::ThorPatchInfo,
+    pub(super) local_file_path: PathBuf,
+    pub(super) unverified: bool, // Accepted without integrity data to check it against
+}
+
+/// Patch which couldn't be downloaded.
+#[derive(Debug)]
+pub(super) struct FailedPatch {
+    pub(super) info: thor::ThorPatchInfo,
+    pub(super) error: anyhow::Error,
+}
+
+pub(super) fn log_failed_downloads(failed_patches: &[FailedPatch]) {
+    if failed_patches.is_empty() {
+        tracing::info!("Patches have been downloaded");
+    }
+    for failed_patch in failed_patches {
+        tracing::warn!(
+            "Failed to download '{}': {:#}",
+            failed_patch.info.file_name,
+            failed_patch.error
+        );
+    }
+}
+
+/// Splits `patch_list` into patches that have already been downloaded to
+/// `download_cache_directory` and patches that still have to be downloaded.
+///
+/// Invalid archives are removed from the cache, as well as the ones without
+/// integrity data when `integrity_policy` is strict.
+pub(super) fn take_cached_patches(
+    patch_list: ThorPatchList,
+    download_cache_directory: impl AsRef<Path>,
+    ensure_integrity: bool,
+    integrity_policy: IntegrityPolicy,
+) -> (Vec<PendingPatch>, ThorPatchList) {
+    let mut cached_patches = vec![];
+    let mut remaining_patches = vec![];
+    for patch_info in patch_list {
+        let local_file_path = download_cache_directory
+            .as_ref()
+            .join(patch_info.file_name.as_str());
+        if !local_file_path.is_file() {
+            remaining_patches.push(patch_info);
+            continue;
+        }
+        let integrity = if ensure_integrity {
+            check_archive_integrity(&local_file_path).unwrap_or(ArchiveIntegrity::Corrupt)
+        } else {
+            ArchiveIntegrity::Valid
+        };
+        let is_valid = match integrity {
+            ArchiveIntegrity::Valid => true,
+            ArchiveIntegrity::Corrupt => false,
+            ArchiveIntegrity::Unverifiable => integrity_policy != IntegrityPolicy::Strict,
+        };
+        if is_valid {
+            tracing::info!("Using previously downloaded '{}'", patch_info.file_name);
+            cached_patches.push(PendingPatch {
+                info: patch_info,
+                local_file_path,
+                unverified: integrity == ArchiveIntegrity::Unverifiable,
+            });
+        } else {
+            tracing::warn!("Discarding corrupt '{}'", patch_info.file_name);
+            let _ = std::fs::remove_file(&local_file_path);
+            remaining_patches.push(patch_info);
+        }
+    }
+    (cached_patches, remaining_patches)
+}
+
+/// Downloads a list of patches (described with a `ThorPatchList`).
+///
+/// Files are downloaded from the remote directory located at the URL
+/// contained in the 'patch_url' argument.
+///
+/// This function is interruptible. Patches which fail to download don't stop
+/// the others from being downloaded, they're returned along with the
+/// downloaded ones.
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn download_patches_concurrent(
+    client: &reqwest::Client,
+    patch_url: Url,
+    patch_manifest: &HashMap<String, PatchManifestEntry>,
+    patch_list: ThorPatchList,
+    download_directory: impl AsRef<Path>,
+    config: &PatcherConfiguration,
+    ui_controller: &UiController,
+    patching_thread_rx: &mut UnboundedReceiver<PatcherCommand>,
+) -> InterruptibleFnResult<(Vec<PendingPatch>, Vec<FailedPatch>)> {
+    let patch_count = patch_list.len();
+    ui_controller.dispatch_patching_status(PatchingStatus::DownloadInProgress(0, patch_count, 0));
+    // Download files in a cancelable manner
+    let results = tokio::select! {
+        cancel_res = wait_for_cancellation(patching_thread_rx) => return Err(cancel_res),
+        download_res = download_patches_concurrent_inner(client, patch_url, patch_manifest, patch_list, download_directory, config, ui_controller, None) => download_res,
+    };
+    let mut downloaded_patches = vec![];
+    let mut failed_patches = vec![];
+    for result in results {
+        match result {
+            Ok(pending_patch) => downloaded_patches.push(pending_patch),
+            Err(failed_patch) => failed_patches.push(failed_patch),
+        }
+    }
+    // Sort patches by index before returning
+    downloaded_patches.sort_unstable_by_key(|l| l.info.index);
+    failed_patches.sort_unstable_by_key(|l| l.info.index);
+    Ok((downloaded_patches, failed_patches))
+}
+
+/// Actual implementation of the concurrent file download
+///
+/// Returns the unordered results of the downloads. Patches are sent to
+/// `downloaded_patch_tx` as soon as they're downloaded instead, if set.
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn download_patches_concurrent_inner(
+    client: &reqwest::Client,
+    patch_url: Url,
+    patch_manifest: &HashMap<String, PatchManifestEntry>,
+    patch_list: ThorPatchList,
+    download_directory: impl AsRef<Path>,
+    config: &PatcherConfiguration,
+    ui_controller: &UiController,
+    downloaded_patch_tx: Option<UnboundedSender<PendingPatch>>,
+) -> Vec<Result<PendingPatch, FailedPatch>> {
+    const CONCURRENT_DOWNLOADS: usize = 32;
+    const ONE_SECOND: Duration = Duration::from_secs(1);
+    const FILE_PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+    // Shared value that contains the number of downloaded patches
+    let shared_patch_number = AtomicUsize::new(0_usize);
+    // Shared tuple that's used to compute the download speed
+    let shared_progress_state = Arc::new(std::sync::Mutex::new((Instant::now(), 0_u64)));
+    // Shared value that contains the number of bytes downloaded for all patches
+    let shared_downloaded_bytes = AtomicU64::new(0_u64);
+    // Shared instant of the last per-file progress update, used for throttling
+    let shared_file_progress_instant = std::sync::Mutex::new(Instant::now());
+    // Shared limiter, so that the limit applies to all downloads combined
+    let bandwidth_limiter = config
+        .patching
+        .bandwidth_limit
+        .map(|limit| BandwidthLimiter::new(1000 * limit));
+    let ensure_integrity = config.patching.check_integrity;
+    let integrity_policy = config.patching.integrity_policy;
+    let write_options = DownloadWriteOptions::new(&config.advanced);
+
+    let peers = if config.peer_cache.enabled {
+        Some(Peers::discover().await)
+    } else {
+        None
+    };
+    let peers = &peers;
+
+    // Estimate the total download size so that the UI can display an ETA
+    let patch_sizes = fetch_patch_sizes(client, &patch_url, &patch_list, patch_manifest).await;
+    if let Some(total_bytes) = patch_sizes.iter().copied().sum::<Option<u64>>() {
+        ui_controller.dispatch_patching_status(PatchingStatus::DownloadSizeEstimated(total_bytes));
+    }
+    let patch_list = order_downloads(patch_list, patch_sizes, config.patching.download_order);
+
+    // Collect stream of "PendingPatch" concurrently with an unordered_buffer
+    let patch_count = patch_list.len();
+    futures::stream::iter(patch_list.into_iter().map(|patch_info| async {
+        let res = async {
+            let bandwidth_limiter = bandwidth_limiter.as_ref();
+            let patch_file_url = patch_url
+                .join(patch_info.file_name.as_str())
+                .with_context(|| "Failed to generate URL for patch file")?;
+            let local_file_path = download_directory
+                .as_ref()
+                .join(patch_info.file_name.as_str());
+            // Download to a separate file so that interrupted downloads can't be
+            // mistaken for complete ones
+            let partial_file_path = download_directory
+                .as_ref()
+                .join(format!("{}.part", patch_info.file_name));
+            let mut tmp_file = File::create(&partial_file_path)
+                .await
+                .with_context(|| "Failed to create temporary file")?;
+
+            // Setup a progress callback that'll send the download progress and speed to the UI
+            let shared_patch_number_ref = &shared_patch_number;
+            let shared_downloaded_bytes_ref = &shared_downloaded_bytes;
+            let shared_file_progress_instant_ref = &shared_file_progress_instant;
+            let shared_state = shared_progress_state.clone();
+            let patch_file_name = &patch_info.file_name;
+            let mut last_downloaded_bytes: u64 = 0;
+            let mut progress_callback = move |dl_now: u64, dl_total| {
+                // Downloads start over when a peer fails to provide a patch
+                let dl_delta = dl_now.saturating_sub(last_downloaded_bytes);
+                let overall_downloaded_bytes =
+                    dl_delta + shared_downloaded_bytes_ref.fetch_add(dl_delta, Ordering::SeqCst);
+                ui_controller.add_downloaded_bytes(dl_delta);
+                // Report the current file's progress at most every 100ms
+                let should_report_file_progress = {
+                    if let Ok(mut last_report) = shared_file_progress_instant_ref.lock() {
+                        let should_report =
+                            last_report.elapsed() >= FILE_PROGRESS_INTERVAL || dl_now == dl_total;
+                        if should_report {
+                            *last_report = Instant::now();
+                        }
+                        should_report
+                    } else {
+                        false
+                    }
+                };
+                if should_report_file_progress {
+                    ui_controller.dispatch_patching_status(
+                        PatchingStatus::FileDownloadInProgress {
+                            file_name: patch_file_name.clone(),
+                            downloaded_bytes: dl_now,
+                            total_bytes: dl_total,
+                            overall_downloaded_bytes,
+                        },
+                    );
+                }
+                // Return download speed if the required time has elapsed (1s)
+                let downloaded_bytes_per_sec = {
+                    if let Ok(mut shared_state) = shared_state.lock() {
+                        shared_state.1 += dl_delta;
+                        if shared_state.0.elapsed() >= ONE_SECOND {
+                            let downloaded_bytes_per_sec =
+                                (shared_state.1 as f32 / shared_state.0.elapsed().as_secs_f32())
+                                    .round() as u64;
+                            shared_state.0 = Instant::now();
+                            shared_state.1 = 0;
+                            Some(downloaded_bytes_per_sec)
+                        } else {
+                            None
+                        }
+                    } else {
+                        None
+                    }
+                };
+                // If speed is "available", update UI
+                if let Some(downloaded_bytes_per_sec) = downloaded_bytes_per_sec {
+                    block_on(async {
+                        ui_controller.dispatch_patching_status(PatchingStatus::DownloadInProgress(
+                            shared_patch_number_ref.load(Ordering::SeqCst),
+                            patch_count,
+                            downloaded_bytes_per_sec,
+                        ));
+                    });
+                }
+                last_downloaded_bytes = dl_now;
+            };
+
+            let manifest_entry = patch_manifest.get(&patch_info.file_name);
+            // Copies held by peers can only be trusted if the manifest tells
+            // what they should be
+            let peer_digest = match (peers, manifest_entry) {
+                (Some(peers), Some(entry)) => {
+                    peers
+                        .download_patch(entry, &mut tmp_file, write_options, &mut progress_callback)
+                        .await?
+                }
+                _ => None,
+            };
+            let patch_parts = match peer_digest {
+                Some(_) => None,
+                None => find_patch_parts(client, &patch_url, &patch_info, manifest_entry).await?,
+            };
+            let digest = match (peer_digest, patch_parts) {
+                (Some(digest), _) => digest,
+                (None, Some(parts)) => {
+                    download_split_patch_to_file(
+                        client,
+                        &patch_url,
+                        &parts,
+                        download_directory.as_ref(),
+                        &mut tmp_file,
+                        bandwidth_limiter,
+                        write_options,
+                        &mut progress_callback,
+                    )
+                    .await?
+                }
+                (None, None) => {
+                    download_patch_to_file(
+                        client,
+                        &patch_file_url,
+                        &patch_info,
+                        &mut tmp_file,
+                        bandwidth_limiter,
+                        write_options,
+                        &mut progress_callback,
+                    )
+                    .await?
+                }
+            };
+            drop(tmp_file);
+            tokio::fs::rename(&partial_file_path, &local_file_path)
+                .await
+                .with_context(|| format!("Failed to rename '{}'", partial_file_path.display()))?;
+
+            // Check the archive's integrity if required
+            let context = || {
+                format!(
+                    "Failed to check archive's integrity: '{}'",
+                    patch_info.file_name
+                )
+            };
+            let mut unverified = false;
+            if ensure_integrity {
+                let integrity = match patch_manifest.get(&patch_info.file_name) {
+                    // Already hashed while downloading, no need to read it again
+                    Some(entry)
+                        if entry.size == digest.size
+                            && entry.sha256.eq_ignore_ascii_case(&digest.sha256) =>
+                    {
+                        ArchiveIntegrity::Valid
+                    }
+                    Some(_) => ArchiveIntegrity::Corrupt,
+                    None => check_archive_integrity(&local_file_path).with_context(context)?,
+                };
+                let error_message = match integrity {
+                    ArchiveIntegrity::Valid => None,
+                    ArchiveIntegrity::Corrupt => {
+                        Some(format!("Archive '{}' is corrupt", patch_info.file_name))
+                    }
+                    ArchiveIntegrity::Unverifiable => {
+                        unverified = true;
+                        (integrity_policy == IntegrityPolicy::Strict).then(|| {
+                            format!("Archive '{}' has no integrity data", patch_info.file_name)
+                        })
+                    }
+                };
+                if let Some(error_message) = error_message {
+                    let _ = tokio::fs::remove_file(&local_file_path).await;
+                    return Err(anyhow!(PatcherError::new(
+                        ErrorKind::Integrity,
+                        error_message
+                    )));
+                }
+            }
+
+            // Update status
+            shared_patch_number_ref.fetch_add(1, Ordering::SeqCst);
+            ui_controller.add_downloaded_patch();
+
+            Ok((local_file_path, unverified)) as Result<(PathBuf, bool)>
+        }
+        .await;
+        match res {
+            // File's been downloaded, add it to the queue
+            Ok((local_file_path, unverified)) => Ok(PendingPatch {
+                info: patch_info,
+                local_file_path,
+                unverified,
+            }),
+            Err(error) => Err(FailedPatch {
+                info: patch_info,
+                error,
+            }),
+        }
+    }))
+    .buffer_unordered(CONCURRENT_DOWNLOADS)
+    .filter_map(|result| {
+        let result = match (result, &downloaded_patch_tx) {
+            // Patches are returned if they can't be sent
+            (Ok(pending_patch), Some(downloaded_patch_tx)) => downloaded_patch_tx
+                .send(pending_patch)
+                .err()
+                .map(|unsent_patch| Ok(unsent_patch.0)),
+            (result, _) => Some(result),
+        };
+        futures::future::ready(result)
+    })
+    .collect()
+    .await
+}
+
+/// Sums the sizes of the patches in `patch_list`, using HEAD requests for
+/// the patches which aren't listed in `patch_manifest`.
+///
+/// Returns `None` if the size of at least one patch couldn't be determined.
+pub(super) async fn fetch_total_download_size(
+    client: &reqwest::Client,
+    patch_url: &Url,
+    patch_list: &[ThorPatchInfo],
+    patch_manifest: &HashMap<String, PatchManifestEntry>,
+) -> Option<u64> {
+    fetch_patch_sizes(client, patch_url, patch_list, patch_manifest)
+        .await
+        .into_iter()
+        .sum()
+}
+
+/// Sorts `patch_list` in the order its downloads should be started, given the
+/// sizes of its patches (in the same order).
+fn order_downloads(
+    patch_list: ThorPatchList,
+    patch_sizes: Vec<Option<u64>>,
+    download_order: DownloadOrder,
+) -> ThorPatchList {
+    let mut patches: Vec<(ThorPatchInfo, Option<u64>)> =
+        patch_list.into_iter().zip(patch_sizes).collect();
+    match download_order {
+        DownloadOrder::Index => patches.sort_by_key(|(patch_info, _)| patch_info.index),
+        DownloadOrder::SmallestFirst => patches.sort_by_key(|(patch_info, size)| {
+            (size.is_none(), size.unwrap_or_default(), patch_info.index)
+        }),
+    }
+    patches
+        .into_iter()
+        .map(|(patch_info, _)| patch_info)
+        .collect()
+}
+
+/// Retrieves the sizes of the patches in `patch_list`, in order, using HEAD
+/// requests for the patches which aren't listed in `patch_manifest`.
+pub(super) async fn fetch_patch_sizes(
+    client: &reqwest::Client,
+    patch_url: &Url,
+    patch_list: &[ThorPatchInfo],
+    patch_manifest: &HashMap<String, PatchManifestEntry>,
+) -> Vec<Option<u64>> {
+    const CONCURRENT_REQUESTS: usize = 32;
+    futures::stream::iter(patch_list.iter().map(|patch_info| async move {
+        if let Some(entry) = patch_manifest.get(&patch_info.file_name) {
+            return Some(entry.size);
+        }
+        let patch_file_url = patch_url.join(patch_info.file_name.as_str()).ok()?;
+        let resp = client.head(patch_file_url).send().await.ok()?;
+        // Note: `Response::content_length` describes the (empty) body of
+        // HEAD responses, read the header instead
+        resp.error_for_status()
+            .ok()?
+            .headers()
+            .get(reqwest::header::CONTENT_LENGTH)?
+            .to_str()
+            .ok()?
+            .parse()
+            .ok()
+    }))
+    .buffered(CONCURRENT_REQUESTS)
+    .collect()
+    .await
+}
+
+/// Outcome of the check of an archive's integrity.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ArchiveIntegrity {
+    Valid,
+    Corrupt,
+    Unverifiable, // The archive has no integrity data
+}
+
+fn check_archive_integrity(archive_path: impl AsRef<Path>) -> Result<ArchiveIntegrity> {
+    let to_integrity = |is_valid| {
+        if is_valid {
+            ArchiveIntegrity::Valid
+        } else {
+            ArchiveIntegrity::Corrupt
+        }
+    };
+    if is_zip_file(archive_path.as_ref()) {
+        let mut archive =
+            ZipArchive::open(archive_path.as_ref()).with_context(|| "Failed to open archive")?;
+        return Ok(to_integrity(archive.is_valid()));
+    }
+    let mut archive =
+        ThorArchive::open(archive_path.as_ref()).with_context(|| "Failed to open archive")?;
+    match archive.is_valid() {
+        Err(e) => {
+            if let GrufError::EntryNotFound = e {
+                // No integrity file present, what to do about it is up to
+                // the integrity policy
+                Ok(ArchiveIntegrity::Unverifiable)
+            } else {
+                // Only consider this an error if the integrity file was found
+                Err(anyhow!(PatcherError::new(
+                    ErrorKind::Integrity,
+                    format!("Archive's integrity file is invalid: {}", e),
+                )))
+            }
+        }
+        Ok(is_valid) => Ok(to_integrity(is_valid)),
+    }
+}
+
+/// Size and hash of a downloaded patch, computed as it's downloaded.
+#[derive(Debug, PartialEq)]
+pub(super) struct DownloadDigest {
+    pub size: u64,
+    pub sha256: String, // Hex-encoded
+}
+
+/// How downloaded patches are written to the disk.
+#[derive(Clone, Copy, Debug)]
+pub(super) struct DownloadWriteOptions {
+    pub buffer_size: usize,
+    pub preallocate: bool, // Set files to their expected size before writing them
+    pub sync_interval: Option<u64>, // Bytes written between syncs, files are synced once complete regardless
+}
+
+impl DownloadWriteOptions {
+    pub fn new(config: &AdvancedConfiguration) -> Self {
+        Self {
+            buffer_size: config.download_buffer_size(),
+            preallocate: config.preallocate_downloads,
+            sync_interval: config.download_sync_interval(),
+        }
+    }
+}
+
+/// Buffered writer of downloaded data, which syncs it to the disk as often
+/// as the write options say.
+struct DownloadWriter<'a> {
+    writer: BufWriter<&'a mut File>,
+    preallocated: bool,
+    sync_interval: Option<u64>,
+    unsynced_bytes: u64,
+}
+
+impl<'a> DownloadWriter<'a> {
+    /// Creates a writer of `file`, which is preallocated to `expected_size`
+    /// if it's known and the options say so.
+    ///
+    /// On Windows, this reserves contiguous space for the file when possible.
+    async fn new(
+        file: &'a mut File,
+        write_options: DownloadWriteOptions,
+        expected_size: Option<u64>,
+    ) -> std::io::Result<DownloadWriter<'a>> {
+        let expected_size = expected_size.filter(|&size| write_options.preallocate && size > 0);
+        if let Some(expected_size) = expected_size {
+            file.set_len(expected_size).await?;
+        }
+        Ok(Self {
+            // Chunks are usually small, writing each of them is slow on some setups
+            writer: BufWriter::with_capacity(write_options.buffer_size, file),
+            preallocated: expected_size.is_some(),
+            sync_interval: write_options.sync_interval,
+            unsynced_bytes: 0,
+        })
+    }
+
+    async fn write_all(&mut self, data: &[u8]) -> std::io::Result<()> {
+        self.writer.write_all(data).await?;
+        self.unsynced_bytes += data.len() as u64;
+        if let Some(sync_interval) = self.sync_interval {
+            if self.unsynced_bytes >= sync_interval {
+                self.writer.flush().await?;
+                self.writer.get_ref().sync_data().await?;
+                self.unsynced_bytes = 0;
+            }
+        }
+        Ok(())
+    }
+
+    /// Flushes the data and syncs the file, which is cut to the `size` bytes
+    /// written in case it was preallocated to a different size.
+    async fn finish(mut self, size: u64) -> std::io::Result<()> {
+        self.writer.flush().await?;
+        let file = self.writer.into_inner();
+        if self.preallocated {
+            file.set_len(size).await?;
+        }
+        file.sync_all().await
+    }
+}
+
+/// Downloads a single patch described with a `ThorPatchInfo`.
+#[tracing::instrument(
+    name = "download",
+    skip_all,
+    fields(patch = %patch.file_name, bytes = tracing::field::Empty),
+)]
+pub(super) async fn download_patch_to_file<CB: FnMut(u64, u64)>(
+    client: &reqwest::Client,
+    patch_url: &Url,
+    patch: &ThorPatchInfo,
+    tmp_file: &mut File,
+    bandwidth_limiter: Option<&BandwidthLimiter>,
+    write_options: DownloadWriteOptions,
+    mut progress_callback: CB,
+) -> Result<DownloadDigest> {
+    let patch_file_url = patch_url.join(patch.file_name.as_str()).with_context(|| {
+        format!(
+            "Invalid file name '{}' given in patch list file",
+            patch.file_name
+        )
+    })?;
+    let simulation = get_network_simulation();
+    if let Some(latency) = simulation.and_then(|simulation| simulation.latency) {
+        tokio::time::sleep(latency).await;
+    }
+    // Each download is throttled separately, like by a congested link
+    let simulated_throttle = simulation
+        .and_then(|simulation| simulation.throttle)
+        .map(BandwidthLimiter::new);
+    let mut resp = client
+        .get(patch_file_url.clone())
+        .send()
+        .await
+        .with_context(|| format!("Failed to download file '{}'", patch.file_name))?;
+    log_final_url(&patch_file_url, &resp);
+    if !resp.status().is_success() {
+        return Err(anyhow!(
+            "Patch file '{}' not found on the remote server",
+            patch.file_name
+        ));
+    }
+    let bytes_to_download = resp.content_length().unwrap_or(0);
+    let mut downloaded_bytes: u64 = 0;
+    let mut hasher = Sha256::new();
+    let mut writer = DownloadWriter::new(tmp_file, write_options, resp.content_length())
+        .await
+        .with_context(|| format!("Failed to preallocate '{}'", patch.file_name))?;
+    while let Some(chunk) = resp
+        .chunk()
+        .await
+        .with_context(|| format!("Failed to download file '{}'", patch.file_name))?
+    {
+        writer
+            .write_all(&chunk[..])
+            .await
+            .with_context(|| format!("Failed to download file '{}'", patch.file_name))?;
+        hasher.update(&chunk[..]);
+        downloaded_bytes += chunk.len() as u64;
+        progress_callback(downloaded_bytes, bytes_to_download);
+        for bandwidth_limiter in bandwidth_limiter.into_iter().chain(&simulated_throttle) {
+            let delay = bandwidth_limiter.consume(chunk.len() as u64);
+            if !delay.is_zero() {
+                tokio::time::sleep(delay).await;
+            }
+        }
+        if let Some(simulation) = simulation {
+            check_chunk_loss(simulation)
+                .with_context(|| format!("Failed to download file '{}'", patch.file_name))?;
+        }
+    }
+    writer
+        .finish(downloaded_bytes)
+        .await
+        .with_context(|| format!("Failed to sync downloaded file '{}'", patch.file_name,))?;
+    tracing::Span::current().record("bytes", downloaded_bytes);
+    Ok(DownloadDigest {
+        size: downloaded_bytes,
+        sha256: to_hex(&hasher.finalize()),
+    })
+}
+
+/// Returns the names of the files `patch_info` is split into, in order, or
+/// `None` if the patch is downloaded as a single file.
+///
+/// Parts are either listed in the manifest, or named after the patch with a
+/// numbered extension (e.g. 'big.thor.001', 'big.thor.002', ...), in which
+/// case the patch list only names the first one and the others are looked up
+/// on the patch server.
+async fn find_patch_parts(
+    client: &reqwest::Client,
+    patch_url: &Url,
+    patch_info: &ThorPatchInfo,
+    manifest_entry: Option<&PatchManifestEntry>,
+) -> Result<Option<Vec<String>>> {
+    const MAX_PART_COUNT: usize = 999;
+
+    if let Some(entry) = manifest_entry.filter(|entry| !entry.parts.is_empty()) {
+        return Ok(Some(entry.parts.clone()));
+    }
+    let base_name = match patch_info.file_name.strip_suffix(".001") {
+        Some(base_name) => base_name,
+        None => return Ok(None),
+    };
+    let mut parts = vec![patch_info.file_name.clone()];
+    while parts.len() < MAX_PART_COUNT {
+        let part_name = format!("{}.{:03}", base_name, parts.len() + 1);
+        let part_url = patch_url
+            .join(&part_name)
+            .with_context(|| format!("Invalid part name '{}'", part_name))?;
+        let resp = client
+            .head(part_url)
+            .send()
+            .await
+            .with_context(|| format!("Failed to look up '{}'", part_name))?;
+        if resp.status() == reqwest::StatusCode::NOT_FOUND {
+            break;
+        }
+        resp.error_for_status()
+            .with_context(|| format!("Failed to look up '{}'", part_name))?;
+        parts.push(part_name);
+    }
+    Ok(Some(parts))
+}
+
+/// Downloads the parts of a split patch concurrently, then joins them into
+/// `tmp_file`.
+///
+/// Parts are downloaded to `download_directory`, and removed once joined.
+/// Their file names are numbered so they can't collide with the patch's own
+/// partial file when the patch list names the first part. The digest is the
+/// one of the joined patch.
+#[allow(clippy::too_many_arguments)]
+async fn download_split_patch_to_file<CB: FnMut(u64, u64)>(
+    client: &reqwest::Client,
+    patch_url: &Url,
+    parts: &[String],
+    download_directory: &Path,
+    tmp_file: &mut File,
+    bandwidth_limiter: Option<&BandwidthLimiter>,
+    write_options: DownloadWriteOptions,
+    progress_callback: CB,
+) -> Result<DownloadDigest> {
+    const CONCURRENT_PART_DOWNLOADS: usize = 4;
+
+    let part_paths: Vec<PathBuf> = parts
+        .iter()
+        .enumerate()
+        .map(|(i, part_name)| download_directory.join(format!("{}.part{}", part_name, i + 1)))
+        .collect();
+    let _guard = scopeguard::guard(&part_paths, |part_paths| {
+        for part_path in part_paths {
+            let _ = std::fs::remove_file(part_path);
+        }
+    });
+    // Parts report their progress separately, the patch's is their sum
+    let progress_callback = std::cell::RefCell::new(progress_callback);
+    let part_progress = std::cell::RefCell::new(vec![(0_u64, 0_u64); parts.len()]);
+    futures::stream::iter(parts.iter().zip(&part_paths).enumerate().map(
+        |(i, (part_name, part_path))| {
+            let progress_callback = &progress_callback;
+            let part_progress = &part_progress;
+            async move {
+                let mut part_file = File::create(part_path)
+                    .await
+                    .with_context(|| format!("Failed to create '{}'", part_path.display()))?;
+                let part_info = ThorPatchInfo {
+                    index: i,
+                    file_name: part_name.clone(),
+                };
+                download_patch_to_file(
+                    client,
+                    patch_url,
+                    &part_info,
+                    &mut part_file,
+                    bandwidth_limiter,
+                    write_options,
+                    |downloaded_bytes, total_bytes| {
+                        let (downloaded_bytes, total_bytes) = {
+                            let mut part_progress = part_progress.borrow_mut();
+                            part_progress[i] = (downloaded_bytes, total_bytes);
+                            let downloaded_bytes = part_progress.iter().map(|p| p.0).sum();
+                            // Unknown until the size of every part is known
+                            let total_bytes = match part_progress.iter().all(|p| p.1 > 0) {
+                                true => part_progress.iter().map(|p| p.1).sum(),
+                                false => 0,
+                            };
+                            (downloaded_bytes, total_bytes)
+                        };
+                        (progress_callback.borrow_mut())(downloaded_bytes, total_bytes);
+                    },
+                )
+                .await
+                .map(|_| ())
+            }
+        },
+    ))
+    .buffer_unordered(CONCURRENT_PART_DOWNLOADS)
+    .collect::<Vec<Result<()>>>()
+    .await
+    .into_iter()
+    .collect::<Result<()>>()?;
+
+    let mut total_size = 0;
+    for part_path in &part_paths {
+        total_size += tokio::fs::metadata(part_path)
+            .await
+            .with_context(|| format!("Failed to open '{}'", part_path.display()))?
+            .len();
+    }
+    let mut hasher = Sha256::new();
+    let mut size: u64 = 0;
+    let mut buffer = vec![0; write_options.buffer_size];
+    let mut writer = DownloadWriter::new(tmp_file, write_options, Some(total_size)).await?;
+    for part_path in &part_paths {
+        let mut part_file = File::open(part_path)
+            .await
+            .with_context(|| format!("Failed to open '{}'", part_path.display()))?;
+        loop {
+            let read_bytes = part_file.read(&mut buffer).await?;
+            if read_bytes == 0 {
+                break;
+            }
+            writer.write_all(&buffer[..read_bytes]).await?;
+            hasher.update(&buffer[..read_bytes]);
+            size += read_bytes as u64;
+        }
+    }
+    writer.finish(size).await?;
+    Ok(DownloadDigest {
+        size,
+        sha256: to_hex(&hasher.finalize()),
+    })
+}
+
+/// Temporary directory where patches are downloaded before being applied,
+/// which is removed along with its parent once the update is over, if the
+/// parent's empty.
+pub(super) struct StagingDirectory {
+    tmp_dir: Option<tempfile::TempDir>,
+    parent_directory: PathBuf,
+}
+
+impl StagingDirectory {
+    pub(super) fn create(parent_directory: PathBuf) -> std::io::Result<Self> {
+        std::fs::create_dir_all(&parent_directory)?;
+        let tmp_dir = tempfile::Builder::new()
+            .prefix("download-")
+            .tempdir_in(&parent_directory)?;
+        Ok(Self {
+            tmp_dir: Some(tmp_dir),
+            parent_directory,
+        })
+    }
+
+    pub(super) fn path(&self) -> &Path {
+        match &self.tmp_dir {
+            Some(tmp_dir) => tmp_dir.path(),
+            None => &self.parent_directory,
+        }
+    }
+}
+
+impl Drop for StagingDirectory {
+    fn drop(&mut self) {
+        if let Some(tmp_dir) = self.tmp_dir.take() {
+            let _ = tmp_dir.close();
+        }
+        // Fails if the operator's directory contains other files
+        let _ = std::fs::remove_dir(&self.parent_directory);
+    }
+}
+
+/// Caps the overall download speed of concurrent downloads.
+pub(super) struct BandwidthLimiter {
+    bytes_per_sec: u64,
+    // Start of the current measurement window and bytes consumed since then
+    state: std::sync::Mutex<(Instant, u64)>,
+}
+
+impl BandwidthLimiter {
+    fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            bytes_per_sec: bytes_per_sec.max(1),
+            state: std::sync::Mutex::new((Instant::now(), 0)),
+        }
+    }
+
+    /// Records `byte_count` downloaded bytes and returns how long the caller
+    /// should wait for the download speed to stay below the limit.
+    fn consume(&self, byte_count: u64) -> Duration {
+        const WINDOW_DURATION: Duration = Duration::from_secs(1);
+        let mut state = match self.state.lock() {
+            Ok(state) => state,
+            Err(_) => return Duration::ZERO,
+        };
+        state.1 += byte_count;
+        let expected_duration = Duration::from_secs_f64(state.1 as f64 / self.bytes_per_sec as f64);
+        let elapsed = state.0.elapsed();
+        if elapsed >= WINDOW_DURATION && expected_duration <= elapsed {
+            // Start a new window, so that idle periods don't allow bursts
+            *state = (Instant::now(), 0);
+        }
+        expected_duration.saturating_sub(elapsed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use httptest::{matchers::*, responders::*, Expectation, Server};
+    use std::io::SeekFrom;
+    use std::sync::mpsc;
+    use tokio::io::AsyncSeekExt;
+
+    // Ends with the `patching` section, for tests to complete it
+    const TEST_CONFIGURATION: &str = r#"
+window:
+  title: RPatchur
+  width: 780
+  height: 580
+  resizable: false
+play:
+  path: ragexe.exe
+  arguments: []
+setup:
+  path: Setup.exe
+  arguments: []
+web:
+  patch_servers: []
+client:
+  default_grf_name: data.grf
+patching:
+  in_place: true
+  create_grf: true
+"#;
+
+    #[tokio::test]
+    async fn test_fetch_total_download_size() {
+        let server = Server::run();
+        for (patch_name, patch_size) in &[("patch1.thor", 1024_usize), ("patch2.thor", 42)] {
+            server.expect(
+                Expectation::matching(request::method_path("HEAD", format!("/{}", patch_name)))
+                    .respond_with(status_code(200).body(vec![0_u8; *patch_size])),
+            );
+        }
+        server.expect(
+            Expectation::matching(request::method_path("HEAD", "/missing.thor"))
+                .respond_with(status_code(404)),
+        );
+
+        let from_url = Url::parse(server.url("/").to_string().as_str()).unwrap();
+        let patch_list: ThorPatchList = ["patch1.thor", "patch2.thor"]
+            .iter()
+            .enumerate()
+            .map(|(index, file_name)| ThorPatchInfo {
+                index,
+                file_name: file_name.to_string(),
+            })
+            .collect();
+        let client = reqwest::Client::new();
+        let total_size =
+            fetch_total_download_size(&client, &from_url, &patch_list, &HashMap::new()).await;
+        assert_eq!(total_size, Some(1024 + 42));
+
+        let patch_list = vec![ThorPatchInfo {
+            index: 0,
+            file_name: "missing.thor".to_string(),
+        }];
+        let total_size =
+            fetch_total_download_size(&client, &from_url, &patch_list, &HashMap::new()).await;
+        assert_eq!(total_size, None);
+
+        // Sizes listed in the manifest don't have to be requested
+        let patch_manifest = vec![(
+            "missing.thor".to_string(),
+            PatchManifestEntry {
+                file_name: "missing.thor".to_string(),
+                size: 7,
+                ..Default::default()
+            },
+        )]
+        .into_iter()
+        .collect();
+        let total_size =
+            fetch_total_download_size(&client, &from_url, &patch_list, &patch_manifest).await;
+        assert_eq!(total_size, Some(7));
+    }
+
+    #[test]
+    fn test_order_downloads() {
+        let patch_list = || -> ThorPatchList {
+            (1..=4)
+                .map(|index| ThorPatchInfo {
+                    index,
+                    file_name: format!("{}.thor", index),
+                })
+                .collect()
+        };
+        let patch_sizes = vec![Some(300), None, Some(100), Some(300)];
+        let indices = |patch_list: ThorPatchList| -> Vec<usize> {
+            patch_list
+                .iter()
+                .map(|patch_info| patch_info.index)
+                .collect()
+        };
+
+        let ordered = order_downloads(patch_list(), patch_sizes.clone(), DownloadOrder::Index);
+        assert_eq!(indices(ordered), [1, 2, 3, 4]);
+        let ordered = order_downloads(patch_list(), patch_sizes, DownloadOrder::SmallestFirst);
+        assert_eq!(indices(ordered), [3, 1, 4, 2]);
+    }
+
+    #[test]
+    fn test_take_cached_patches() {
+        let download_cache_directory = tempfile::tempdir().unwrap();
+        std::fs::write(download_cache_directory.path().join("2.thor"), b"").unwrap();
+        let patch_list = (1..=3)
+            .map(|index| ThorPatchInfo {
+                index,
+                file_name: format!("{}.thor", index),
+            })
+            .collect();
+        let (cached_patches, remaining_patches) = take_cached_patches(
+            patch_list,
+            download_cache_directory.path(),
+            false,
+            IntegrityPolicy::Lenient,
+        );
+        assert_eq!(cached_patches.len(), 1);
+        assert_eq!(cached_patches[0].info.index, 2);
+        let remaining_indices: Vec<usize> = remaining_patches.iter().map(|p| p.index).collect();
+        assert_eq!(remaining_indices, [1, 3]);
+    }
+
+    #[test]
+    fn test_take_unverifiable_cached_patches() {
+        let download_cache_directory = tempfile::tempdir().unwrap();
+        for (file_name, include_checksums) in [("1.thor", true), ("2.thor", false)] {
+            let file =
+                std::fs::File::create(download_cache_directory.path().join(file_name)).unwrap();
+            let mut builder =
+                thor::ThorArchiveBuilder::new(file, false, None, include_checksums).unwrap();
+            builder
+                .append_file_update("data\\test.txt".to_string(), &b"test"[..])
+                .unwrap();
+            builder.finish().unwrap();
+        }
+        let patch_list = || -> ThorPatchList {
+            (1..=2)
+                .map(|index| ThorPatchInfo {
+                    index,
+                    file_name: format!("{}.thor", index),
+                })
+                .collect()
+        };
+
+        let (cached_patches, _) = take_cached_patches(
+            patch_list(),
+            download_cache_directory.path(),
+            true,
+            IntegrityPolicy::Warn,
+        );
+        let unverified: Vec<bool> = cached_patches.iter().map(|p| p.unverified).collect();
+        assert_eq!(unverified, [false, true]);
+
+        let (cached_patches, remaining_patches) = take_cached_patches(
+            patch_list(),
+            download_cache_directory.path(),
+            true,
+            IntegrityPolicy::Strict,
+        );
+        assert_eq!(cached_patches.len(), 1);
+        assert_eq!(remaining_patches[0].index, 2);
+        assert!(!download_cache_directory.path().join("2.thor").exists());
+    }
+
+    #[test]
+    fn test_bandwidth_limiter() {
+        let limiter = BandwidthLimiter::new(1000);
+        let delay = limiter.consume(2000);
+        assert!(delay > Duration::from_millis(1500));
+        assert!(delay <= Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn test_download_path_to_file() {
+        // Generate 200MiB of data
+        let data_size: usize = 200 * 1024 * 1024;
+        let body_content: Vec<u8> = (0..data_size).map(|x| x as u8).collect();
+
+        let patch_name = "patch_archive";
+        let patch_path = format!("/{}", patch_name);
+        // Setup a local web server
+        let server = Server::run();
+        // Configure the server to expect a single GET request and respond
+        // with a 200 status code.
+        server.expect(
+            Expectation::matching(request::method_path("GET", patch_path.clone()))
+                .respond_with(status_code(200).body(body_content.clone())),
+        );
+
+        // "Download" the file
+        let from_url = Url::parse(server.url("/").to_string().as_str()).unwrap();
+        let patch_info = ThorPatchInfo {
+            index: 0,
+            file_name: patch_name.to_string(),
+        };
+        let mut tmp_file = File::from_std(tempfile::tempfile().unwrap());
+        let digest = download_patch_to_file(
+            &reqwest::Client::new(),
+            &from_url,
+            &patch_info,
+            &mut tmp_file,
+            None,
+            DownloadWriteOptions {
+                buffer_size: 4096,
+                preallocate: true,
+                sync_interval: Some(64 * 1024 * 1024),
+            },
+            |_, _| {},
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            digest,
+            DownloadDigest {
+                size: data_size as u64,
+                sha256: to_hex(&Sha256::digest(&body_content)),
+            }
+        );
+
+        tmp_file.seek(SeekFrom::Start(0)).await.unwrap();
+        let mut file_content = Vec::with_capacity(data_size);
+        tmp_file.read_to_end(&mut file_content).await.unwrap();
+        // Size check
+        assert_eq!(data_size as u64, tmp_file.metadata().await.unwrap().len());
+        assert_eq!(data_size, file_content.len());
+        // Content check
+        assert_eq!(body_content, file_content);
+    }
+
+    #[tokio::test]
+    async fn test_download_split_patch_to_file() {
+        let parts: [&[u8]; 3] = [b"THOR", b"split", b"patch"];
+        let server = Server::run();
+        for (i, part) in parts.iter().enumerate() {
+            let part_path = format!("/big.thor.{:03}", i + 1);
+            server.expect(
+                Expectation::matching(request::method_path("HEAD", part_path.clone()))
+                    .times(0..)
+                    .respond_with(status_code(200)),
+            );
+            server.expect(
+                Expectation::matching(request::method_path("GET", part_path))
+                    .respond_with(status_code(200).body(part.to_vec())),
+            );
+        }
+        server.expect(
+            Expectation::matching(request::method_path("HEAD", "/big.thor.004"))
+                .respond_with(status_code(404)),
+        );
+        let client = reqwest::Client::new();
+        let patch_url = Url::parse(&server.url_str("/")).unwrap();
+
+        // Parts are looked up when the patch list names the first one
+        let patch_info = ThorPatchInfo {
+            index: 1,
+            file_name: "big.thor.001".to_string(),
+        };
+        let part_names = find_patch_parts(&client, &patch_url, &patch_info, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(part_names, ["big.thor.001", "big.thor.002", "big.thor.003"]);
+        // Or listed in the manifest
+        let patch_info = ThorPatchInfo {
+            index: 1,
+            file_name: "big.thor".to_string(),
+        };
+        assert_eq!(
+            find_patch_parts(&client, &patch_url, &patch_info, None)
+                .await
+                .unwrap(),
+            None
+        );
+        let manifest_entry = PatchManifestEntry {
+            index: 1,
+            file_name: "big.thor".to_string(),
+            size: 14,
+            parts: part_names.clone(),
+            ..Default::default()
+        };
+        assert_eq!(
+            find_patch_parts(&client, &patch_url, &patch_info, Some(&manifest_entry))
+                .await
+                .unwrap(),
+            Some(part_names.clone())
+        );
+
+        let download_directory = tempfile::tempdir().unwrap();
+        let mut tmp_file = File::from_std(tempfile::tempfile().unwrap());
+        let mut last_progress = (0, 0);
+        let digest = download_split_patch_to_file(
+            &client,
+            &patch_url,
+            &part_names,
+            download_directory.path(),
+            &mut tmp_file,
+            None,
+            DownloadWriteOptions {
+                buffer_size: 4096,
+                preallocate: true,
+                sync_interval: None,
+            },
+            |downloaded_bytes, total_bytes| last_progress = (downloaded_bytes, total_bytes),
+        )
+        .await
+        .unwrap();
+        let joined_content = parts.concat();
+        assert_eq!(
+            digest,
+            DownloadDigest {
+                size: joined_content.len() as u64,
+                sha256: to_hex(&Sha256::digest(&joined_content)),
+            }
+        );
+        assert_eq!(last_progress.0, joined_content.len() as u64);
+        tmp_file.seek(SeekFrom::Start(0)).await.unwrap();
+        let mut file_content = vec![];
+        tmp_file.read_to_end(&mut file_content).await.unwrap();
+        assert_eq!(file_content, joined_content);
+        // Parts are removed once joined
+        assert_eq!(
+            std::fs::read_dir(download_directory.path())
+                .unwrap()
+                .count(),
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn test_download_split_patch_named_after_first_part() {
+        let parts: [&[u8]; 2] = [b"THOR", b"split patch"];
+        let server = Server::run();
+        for (i, part) in parts.iter().enumerate() {
+            let part_path = format!("/big.thor.{:03}", i + 1);
+            server.expect(
+                Expectation::matching(request::method_path("HEAD", part_path.clone()))
+                    .times(0..)
+                    .respond_with(status_code(200).body(part.to_vec())),
+            );
+            server.expect(
+                Expectation::matching(request::method_path("GET", part_path))
+                    .respond_with(status_code(200).body(part.to_vec())),
+            );
+        }
+        server.expect(
+            Expectation::matching(request::method_path("HEAD", "/big.thor.003"))
+                .respond_with(status_code(404)),
+        );
+        let client = reqwest::Client::new();
+        let patch_url = Url::parse(&server.url_str("/")).unwrap();
+        let configuration = format!(
+            "{}  check_integrity: false\nadvanced:\n  preallocate_downloads: true\n",
+            TEST_CONFIGURATION
+        );
+        let config: PatcherConfiguration = serde_yaml::from_str(&configuration).unwrap();
+        let (status_tx, _status_rx) = mpsc::channel();
+        let ui_controller = UiController::new(status_tx, false);
+        let download_directory = tempfile::tempdir().unwrap();
+
+        // The patch's partial file is named after the first part's name
+        let patch_list = vec![ThorPatchInfo {
+            index: 1,
+            file_name: "big.thor.001".to_string(),
+        }];
+        let mut results = download_patches_concurrent_inner(
+            &client,
+            patch_url,
+            &HashMap::new(),
+            patch_list,
+            download_directory.path(),
+            &config,
+            &ui_controller,
+            None,
+        )
+        .await;
+        assert_eq!(results.len(), 1);
+        let pending_patch = match results.pop().unwrap() {
+            Ok(pending_patch) => pending_patch,
+            Err(failed_patch) => panic!("{:#}", failed_patch.error),
+        };
+        assert_eq!(
+            pending_patch.local_file_path,
+            download_directory.path().join("big.thor.001")
+        );
+        assert_eq!(
+            std::fs::read(&pending_patch.local_file_path).unwrap(),
+            parts.concat()
+        );
+        // Only the joined patch is left
+        assert_eq!(
+            std::fs::read_dir(download_directory.path())
+                .unwrap()
+                .count(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_download_patches_checked_against_manifest() {
+        let body_content = b"THOR patch".to_vec();
+        let server = Server::run();
+        for patch_name in &["1.thor", "2.thor"] {
+            server.expect(
+                Expectation::matching(request::method_path("GET", format!("/{}", patch_name)))
+                    .respond_with(status_code(200).body(body_content.clone())),
+            );
+        }
+        let client = reqwest::Client::new();
+        let patch_url = Url::parse(&server.url_str("/")).unwrap();
+        let configuration = format!("{}  check_integrity: true\n", TEST_CONFIGURATION);
+        let config: PatcherConfiguration = serde_yaml::from_str(&configuration).unwrap();
+        let (status_tx, _status_rx) = mpsc::channel();
+        let ui_controller = UiController::new(status_tx, false);
+        let download_directory = tempfile::tempdir().unwrap();
+
+        // Hashes may be written in uppercase
+        let sha256 = to_hex(&Sha256::digest(&body_content));
+        let patch_manifest: HashMap<String, PatchManifestEntry> = vec![
+            PatchManifestEntry {
+                index: 1,
+                file_name: "1.thor".to_string(),
+                size: body_content.len() as u64,
+                sha256: sha256.to_ascii_uppercase(),
+                ..Default::default()
+            },
+            PatchManifestEntry {
+                index: 2,
+                file_name: "2.thor".to_string(),
+                size: body_content.len() as u64,
+                sha256: "0".repeat(64),
+                ..Default::default()
+            },
+        ]
+        .into_iter()
+        .map(|entry| (entry.file_name.clone(), entry))
+        .collect();
+        let patch_list = (1..=2)
+            .map(|index| ThorPatchInfo {
+                index,
+                file_name: format!("{}.thor", index),
+            })
+            .collect();
+        let results = download_patches_concurrent_inner(
+            &client,
+            patch_url,
+            &patch_manifest,
+            patch_list,
+            download_directory.path(),
+            &config,
+            &ui_controller,
+            None,
+        )
+        .await;
+        assert_eq!(results.len(), 2);
+        for result in results {
+            match result {
+                Ok(pending_patch) => assert_eq!(pending_patch.info.file_name, "1.thor"),
+                Err(failed_patch) => {
+                    assert_eq!(failed_patch.info.file_name, "2.thor");
+                    assert_eq!(ErrorKind::of(&failed_patch.error), ErrorKind::Integrity);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_staging_directory() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let parent_directory = temp_dir.path().join(".patching");
+        let staging_directory = StagingDirectory::create(parent_directory.clone()).unwrap();
+        let download_path = staging_directory.path().join("1.thor");
+        std::fs::write(&download_path, b"").unwrap();
+        assert!(download_path.starts_with(&parent_directory));
+        drop(staging_directory);
+        assert!(!parent_directory.exists());
+
+        // Directories that contain other files are kept
+        std::fs::create_dir(&parent_directory).unwrap();
+        std::fs::write(parent_directory.join("readme.txt"), b"").unwrap();
+        let staging_directory = StagingDirectory::create(parent_directory.clone()).unwrap();
+        let staging_path = staging_directory.path().to_path_buf();
+        drop(staging_directory);
+        assert!(!staging_path.exists());
+        assert!(parent_directory.join("readme.txt").exists());
+    }
+}
@@ -0,0 +1,121 @@
+use std::sync::{Arc, Mutex, PoisonError};
+
+use anyhow::Result;
+use futures::Stream;
+use tokio::sync::mpsc::{self as tokio_mpsc, UnboundedReceiver, UnboundedSender};
+
+use super::config::PatcherConfiguration;
+use super::core::{
+    build_http_client, execute_command, fetch_pending_patch_list, UiController, UnfinishedUpdate,
+};
+use super::PatcherCommand;
+use crate::status::PatchingEvent;
+
+type Subscribers = Arc<Mutex<Vec<UnboundedSender<PatchingEvent>>>>;
+
+/// Patching engine driven through async calls, for launchers which embed it
+/// instead of using the patcher's own window.
+///
+/// The game is expected to be in the working directory. Progress is reported
+/// as events to the streams returned by `Patcher::subscribe`.
+///
+/// Operations are run one at a time, and nobody's asked questions along the
+/// way (e.g. to close the game), like in headless mode.
+pub struct Patcher {
+    config: PatcherConfiguration,
+    ui_controller: UiController,
+    subscribers: Subscribers,
+    command_tx: UnboundedSender<PatcherCommand>,
+    command_rx: UnboundedReceiver<PatcherCommand>,
+    unfinished_update: Option<UnfinishedUpdate>,
+}
+
+impl Patcher {
+    pub fn new(config: PatcherConfiguration) -> Self {
+        let subscribers = Subscribers::default();
+        let dispatch_subscribers = subscribers.clone();
+        let ui_controller = UiController::with_dispatcher(
+            move |status| {
+                if let Some(event) = PatchingEvent::from_status(status) {
+                    let mut subscribers = dispatch_subscribers
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner);
+                    // Dropped streams are forgotten
+                    subscribers.retain(|subscriber| subscriber.send(event.clone()).is_ok());
+                }
+            },
+            false,
+        );
+        let (command_tx, command_rx) = tokio_mpsc::unbounded_channel();
+        Self {
+            config,
+            ui_controller,
+            subscribers,
+            command_tx,
+            command_rx,
+            unfinished_update: None,
+        }
+    }
+
+    /// Returns a stream of the events of the operations run from now on.
+    ///
+    /// The stream ends once the patcher is dropped.
+    pub fn subscribe(&self) -> impl Stream<Item = PatchingEvent> + Send + Unpin + 'static {
+        let (event_tx, mut event_rx) = tokio_mpsc::unbounded_channel();
+        self.subscribers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(event_tx);
+        futures::stream::poll_fn(move |cx| event_rx.poll_recv(cx))
+    }
+
+    /// Returns the number of patches which haven't been applied yet.
+    pub async fn check(&mut self) -> Result<usize> {
+        self.discard_stale_commands();
+        let client = build_http_client(&self.config.web)?;
+        let listing = fetch_pending_patch_list(&self.config, &client, &mut self.command_rx).await?;
+        Ok(listing.patch_list.len())
+    }
+
+    /// Downloads and applies the pending patches.
+    ///
+    /// The outcome is reported through events: `PatchingEvent::Completed` once
+    /// the game is up to date, `PatchingEvent::Cancelled` if the update has
+    /// been canceled, `PatchingEvent::Error` otherwise. Patches which failed
+    /// to download are downloaded again by the next update only.
+    pub async fn update(&mut self) {
+        self.discard_stale_commands();
+        execute_command(
+            PatcherCommand::StartUpdate,
+            &mut self.config,
+            &mut self.ui_controller,
+            &mut self.command_rx,
+            &mut self.unfinished_update,
+        )
+        .await;
+    }
+
+    /// Returns a handle which cancels the running operation, to be used from
+    /// another task or thread.
+    pub fn cancel_handle(&self) -> CancelHandle {
+        CancelHandle(self.command_tx.clone())
+    }
+
+    /// Discards the cancellations sent while no operation was running, which
+    /// would cancel the next one otherwise.
+    fn discard_stale_commands(&mut self) {
+        while self.command_rx.try_recv().is_ok() {}
+    }
+}
+
+/// Cancels the operation a `Patcher` is running, if any.
+#[derive(Clone)]
+pub struct CancelHandle(UnboundedSender<PatcherCommand>);
+
+impl CancelHandle {
+    /// Stops the operation once the patch being applied, if any, has been
+    /// applied completely.
+    pub fn cancel(&self) {
+        let _ = self.0.send(PatcherCommand::CancelUpdate);
+    }
+}
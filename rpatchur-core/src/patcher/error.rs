@@ -0,0 +1,95 @@
+use std::fmt;
+
+use advisory_lock::FileLockError;
+
+/// Broad category of an error, which lets callers react to some failures
+/// (e.g. by picking an exit code).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorKind {
+    Network,           // Patch servers couldn't be reached or stopped responding
+    Integrity,         // A downloaded patch is corrupt
+    LockContention,    // Another instance of the patcher is updating the game
+    GameClientRunning, // The game client holds files that would be patched
+    PatcherOutdated,   // The patch server requires a more recent patcher
+    Cancelled,         // The operation was canceled by the user
+    Other,
+}
+
+impl ErrorKind {
+    /// Finds the category of an error by looking through its causes.
+    pub fn of(error: &anyhow::Error) -> Self {
+        for cause in error.chain() {
+            if let Some(patcher_error) = cause.downcast_ref::<PatcherError>() {
+                return patcher_error.kind;
+            }
+            if cause.is::<reqwest::Error>() {
+                return ErrorKind::Network;
+            }
+            if let Some(FileLockError::AlreadyLocked) = cause.downcast_ref::<FileLockError>() {
+                return ErrorKind::LockContention;
+            }
+        }
+        ErrorKind::Other
+    }
+
+    /// Returns the name used to refer to the category in machine-readable
+    /// outputs.
+    pub fn name(self) -> &'static str {
+        match self {
+            ErrorKind::Network => "network",
+            ErrorKind::Integrity => "integrity",
+            ErrorKind::LockContention => "lock_contention",
+            ErrorKind::GameClientRunning => "game_client_running",
+            ErrorKind::PatcherOutdated => "patcher_outdated",
+            ErrorKind::Cancelled => "cancelled",
+            ErrorKind::Other => "other",
+        }
+    }
+}
+
+/// Error raised by the patcher itself, tagged with its category.
+#[derive(Debug)]
+pub struct PatcherError {
+    kind: ErrorKind,
+    message: String,
+}
+
+impl PatcherError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for PatcherError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for PatcherError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{anyhow, Context};
+
+    #[test]
+    fn test_error_kind_of() {
+        let error = anyhow!(PatcherError::new(
+            ErrorKind::Integrity,
+            "Archive is corrupt"
+        ))
+        .context("Failed to download patches");
+        assert_eq!(ErrorKind::of(&error), ErrorKind::Integrity);
+        assert_eq!(error.root_cause().to_string(), "Archive is corrupt");
+
+        let error = Err::<(), _>(FileLockError::AlreadyLocked)
+            .context("Failed to take the update lock")
+            .unwrap_err();
+        assert_eq!(ErrorKind::of(&error), ErrorKind::LockContention);
+        assert_eq!(ErrorKind::of(&anyhow!("Failed")), ErrorKind::Other);
+    }
+}
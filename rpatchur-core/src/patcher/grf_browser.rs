@@ -0,0 +1,177 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use gruf::grf::reader::GrfFileEncryption;
+use gruf::grf::GrfArchive;
+
+// Entries without it are directories
+const GRF_FILE_FLAG: u8 = 0x01;
+const GRF_PATH_SEPARATOR: char = '\\';
+
+/// File contained in a GRF, as listed by `GrfIndex`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GrfEntryInfo {
+    pub relative_path: String,
+    pub size: usize,
+    pub size_compressed: usize,
+    pub encrypted: bool,
+}
+
+impl GrfEntryInfo {
+    /// Returns the name of the file, without its directory.
+    pub fn file_name(&self) -> &str {
+        self.relative_path
+            .rsplit(GRF_PATH_SEPARATOR)
+            .next()
+            .unwrap_or(&self.relative_path)
+    }
+}
+
+/// Read-only listing of the files of a GRF, which lets modders check that
+/// patches landed in it.
+///
+/// The GRF is only held open while reading it, so that it can still be
+/// patched.
+pub struct GrfIndex {
+    grf_path: PathBuf,
+    entries: Vec<GrfEntryInfo>, // Sorted by path, case-insensitively
+}
+
+impl GrfIndex {
+    pub fn open(grf_path: impl AsRef<Path>) -> Result<Self> {
+        let grf_path = grf_path.as_ref().to_path_buf();
+        let grf_archive = GrfArchive::open(&grf_path)
+            .with_context(|| format!("Failed to open '{}'", grf_path.display()))?;
+        let mut entries: Vec<GrfEntryInfo> = grf_archive
+            .get_entries()
+            .filter(|entry| entry.entry_type & GRF_FILE_FLAG != 0)
+            .map(|entry| GrfEntryInfo {
+                relative_path: entry.relative_path.clone(),
+                size: entry.size,
+                size_compressed: entry.size_compressed,
+                encrypted: !matches!(entry.encryption, GrfFileEncryption::Unencrypted),
+            })
+            .collect();
+        entries.sort_by_cached_key(|entry| entry.relative_path.to_lowercase());
+        Ok(Self { grf_path, entries })
+    }
+
+    pub fn file_count(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Lists the subdirectories and files directly contained in `directory`
+    /// (e.g. 'data\texture', empty for the GRF's root), by name.
+    pub fn list_directory(&self, directory: &str) -> (Vec<String>, Vec<GrfEntryInfo>) {
+        let prefix = if directory.is_empty() {
+            String::new()
+        } else {
+            format!("{}{}", directory.to_lowercase(), GRF_PATH_SEPARATOR)
+        };
+        // Keyed by lowercase name, since paths are case-insensitive
+        let mut subdirectories = BTreeMap::new();
+        let mut files = vec![];
+        for entry in &self.entries {
+            let relative_path = &entry.relative_path;
+            let is_contained = relative_path
+                .get(..prefix.len())
+                .is_some_and(|start| start.to_lowercase() == prefix);
+            if !is_contained {
+                continue;
+            }
+            let remainder = &relative_path[prefix.len()..];
+            match remainder.split_once(GRF_PATH_SEPARATOR) {
+                Some((subdirectory, _)) => {
+                    subdirectories
+                        .entry(subdirectory.to_lowercase())
+                        .or_insert_with(|| subdirectory.to_string());
+                }
+                None => files.push(entry.clone()),
+            }
+        }
+        (subdirectories.into_values().collect(), files)
+    }
+
+    /// Lists the files whose path contains `query`, case-insensitively.
+    pub fn search(&self, query: &str) -> Vec<GrfEntryInfo> {
+        let query = query.to_lowercase();
+        self.entries
+            .iter()
+            .filter(|entry| entry.relative_path.to_lowercase().contains(&query))
+            .cloned()
+            .collect()
+    }
+
+    /// Writes the content of one of the GRF's files to `destination_path`.
+    pub fn extract_file(&self, relative_path: &str, destination_path: &Path) -> Result<()> {
+        let mut grf_archive = GrfArchive::open(&self.grf_path)
+            .with_context(|| format!("Failed to open '{}'", self.grf_path.display()))?;
+        let content = grf_archive
+            .read_file_content(relative_path)
+            .with_context(|| format!("Failed to read '{}'", relative_path))?;
+        fs::write(destination_path, content)
+            .with_context(|| format!("Failed to write '{}'", destination_path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gruf::grf::GrfArchiveBuilder;
+    use std::fs::File;
+
+    #[test]
+    fn test_grf_index() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let grf_path = tmp_dir.path().join("data.grf");
+        let mut builder =
+            GrfArchiveBuilder::create(File::create(&grf_path).unwrap(), 2, 0).unwrap();
+        for relative_path in [
+            "data\\clientinfo.xml",
+            "data\\texture\\a.bmp",
+            "data\\Texture\\b.bmp",
+            "data\\sprite\\c.spr",
+            "readme.txt",
+        ] {
+            builder
+                .add_file(relative_path.to_string(), relative_path.as_bytes())
+                .unwrap();
+        }
+        builder.finish().unwrap();
+
+        let grf_index = GrfIndex::open(&grf_path).unwrap();
+        assert_eq!(grf_index.file_count(), 5);
+        let (subdirectories, files) = grf_index.list_directory("");
+        assert_eq!(subdirectories, ["data"]);
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].file_name(), "readme.txt");
+        let (subdirectories, files) = grf_index.list_directory("DATA");
+        assert_eq!(subdirectories, ["sprite", "texture"]);
+        assert_eq!(files[0].relative_path, "data\\clientinfo.xml");
+        let (subdirectories, files) = grf_index.list_directory("data\\texture");
+        assert!(subdirectories.is_empty());
+        let file_names: Vec<&str> = files.iter().map(GrfEntryInfo::file_name).collect();
+        assert_eq!(file_names, ["a.bmp", "b.bmp"]);
+
+        let found_paths: Vec<String> = grf_index
+            .search("BMP")
+            .into_iter()
+            .map(|entry| entry.relative_path)
+            .collect();
+        assert_eq!(
+            found_paths,
+            ["data\\texture\\a.bmp", "data\\Texture\\b.bmp"]
+        );
+
+        let extracted_path = tmp_dir.path().join("c.spr");
+        grf_index
+            .extract_file("data\\sprite\\c.spr", &extracted_path)
+            .unwrap();
+        assert_eq!(fs::read(&extracted_path).unwrap(), b"data\\sprite\\c.spr");
+        assert!(grf_index
+            .extract_file("data\\missing.txt", &extracted_path)
+            .is_err());
+    }
+}
@@ -0,0 +1,259 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use gruf::grf::GrfArchive;
+
+use super::publish::write_thor_patch;
+
+// Entries without it are directories
+const GRF_FILE_FLAG: u8 = 0x01;
+
+/// Two releases of a GRF to build a THOR patch from.
+pub struct GrfDiffOptions {
+    pub old_grf_path: PathBuf,
+    pub new_grf_path: PathBuf,
+    pub patch_path: PathBuf,
+    pub target_grf_name: Option<String>, // The client's default GRF if not set
+}
+
+/// Changes between two GRFs, contained in the patch built by `diff_grfs`.
+#[derive(Debug, PartialEq)]
+pub struct GrfDiff {
+    pub added_files: usize,
+    pub changed_files: usize,
+    pub removed_files: usize,
+}
+
+#[derive(Debug, PartialEq)]
+enum GrfChange {
+    Added(String),
+    Changed(String),
+    Removed(String),
+}
+
+/// Builds a THOR patch which turns the old GRF into the new one, containing
+/// only the entries that have been added, changed or removed.
+pub fn diff_grfs(options: &GrfDiffOptions) -> Result<GrfDiff> {
+    let mut old_grf = GrfArchive::open(&options.old_grf_path)
+        .with_context(|| format!("Failed to open '{}'", options.old_grf_path.display()))?;
+    let mut new_grf = GrfArchive::open(&options.new_grf_path)
+        .with_context(|| format!("Failed to open '{}'", options.new_grf_path.display()))?;
+    let changes = list_grf_changes(&mut old_grf, &mut new_grf)?;
+    if changes.is_empty() {
+        return Err(anyhow!("The GRFs are identical"));
+    }
+
+    tracing::info!("Building '{}'", options.patch_path.display());
+    if let Err(e) = build_grf_patch(
+        &options.patch_path,
+        &mut new_grf,
+        options.target_grf_name.clone(),
+        &changes,
+    ) {
+        let _ = fs::remove_file(&options.patch_path);
+        return Err(e.context(format!(
+            "Failed to build '{}'",
+            options.patch_path.display()
+        )));
+    }
+
+    let mut diff = GrfDiff {
+        added_files: 0,
+        changed_files: 0,
+        removed_files: 0,
+    };
+    for change in &changes {
+        match change {
+            GrfChange::Added(_) => diff.added_files += 1,
+            GrfChange::Changed(_) => diff.changed_files += 1,
+            GrfChange::Removed(_) => diff.removed_files += 1,
+        }
+    }
+    Ok(diff)
+}
+
+/// Lists the entries that differ between two GRFs, by their path in the new
+/// GRF (or the old one for removed entries).
+fn list_grf_changes(old_grf: &mut GrfArchive, new_grf: &mut GrfArchive) -> Result<Vec<GrfChange>> {
+    // Paths are case-insensitive, like on Windows
+    let list_files = |grf: &GrfArchive| -> BTreeMap<String, String> {
+        grf.get_entries()
+            .filter(|entry| entry.entry_type & GRF_FILE_FLAG != 0)
+            .map(|entry| {
+                (
+                    entry.relative_path.to_lowercase(),
+                    entry.relative_path.clone(),
+                )
+            })
+            .collect()
+    };
+    let old_files = list_files(old_grf);
+    let new_files = list_files(new_grf);
+
+    let mut changes = Vec::new();
+    for (key, relative_path) in &new_files {
+        match old_files.get(key) {
+            None => changes.push(GrfChange::Added(relative_path.clone())),
+            Some(old_relative_path) => {
+                if entries_differ(old_grf, old_relative_path, new_grf, relative_path)? {
+                    changes.push(GrfChange::Changed(relative_path.clone()));
+                }
+            }
+        }
+    }
+    changes.extend(
+        old_files
+            .iter()
+            .filter(|(key, _)| !new_files.contains_key(*key))
+            .map(|(_, relative_path)| GrfChange::Removed(relative_path.clone())),
+    );
+    Ok(changes)
+}
+
+fn entries_differ(
+    old_grf: &mut GrfArchive,
+    old_relative_path: &str,
+    new_grf: &mut GrfArchive,
+    new_relative_path: &str,
+) -> Result<bool> {
+    let (old_entry, new_entry) = match (
+        old_grf.get_file_entry(old_relative_path),
+        new_grf.get_file_entry(new_relative_path),
+    ) {
+        (Some(old_entry), Some(new_entry)) => (old_entry.clone(), new_entry.clone()),
+        _ => return Ok(true),
+    };
+    if old_entry.size != new_entry.size {
+        return Ok(true);
+    }
+    // Identical raw data means identical content, which saves decompressing
+    // entries that have just been copied from one GRF to the other
+    if old_entry.size_compressed == new_entry.size_compressed
+        && old_entry.encryption == new_entry.encryption
+        && old_grf.get_entry_raw_data(old_relative_path)?
+            == new_grf.get_entry_raw_data(new_relative_path)?
+    {
+        return Ok(false);
+    }
+    let old_content = old_grf
+        .read_file_content(old_relative_path)
+        .with_context(|| format!("Failed to read '{}'", old_relative_path))?;
+    let new_content = new_grf
+        .read_file_content(new_relative_path)
+        .with_context(|| format!("Failed to read '{}'", new_relative_path))?;
+    Ok(old_content != new_content)
+}
+
+fn build_grf_patch(
+    patch_path: &Path,
+    new_grf: &mut GrfArchive,
+    target_grf_name: Option<String>,
+    changes: &[GrfChange],
+) -> Result<()> {
+    write_thor_patch(patch_path, true, target_grf_name, |builder| {
+        for change in changes {
+            match change {
+                GrfChange::Added(relative_path) | GrfChange::Changed(relative_path) => {
+                    tracing::debug!("'{}' will be UPDATED", relative_path);
+                    let content = new_grf
+                        .read_file_content(relative_path)
+                        .with_context(|| format!("Failed to read '{}'", relative_path))?;
+                    builder.append_file_update(relative_path.clone(), content.as_slice())?;
+                }
+                GrfChange::Removed(relative_path) => {
+                    tracing::debug!("'{}' will be REMOVED", relative_path);
+                    builder.append_file_removal(relative_path.clone());
+                }
+            }
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gruf::grf::GrfArchiveBuilder;
+    use gruf::thor::ThorArchive;
+    use std::fs::File;
+
+    fn build_grf(path: &Path, files: &[(&str, &[u8])]) {
+        let mut builder = GrfArchiveBuilder::create(File::create(path).unwrap(), 2, 0).unwrap();
+        for (relative_path, content) in files {
+            builder
+                .add_file(relative_path.to_string(), *content)
+                .unwrap();
+        }
+        builder.finish().unwrap();
+    }
+
+    #[test]
+    fn test_diff_grfs() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let old_grf_path = tmp_dir.path().join("old.grf");
+        let new_grf_path = tmp_dir.path().join("new.grf");
+        build_grf(
+            &old_grf_path,
+            &[
+                ("data\\same.txt", b"same"),
+                ("data\\changed.txt", b"old"),
+                ("data\\removed.txt", b"removed"),
+            ],
+        );
+        build_grf(
+            &new_grf_path,
+            &[
+                ("data\\Same.txt", b"same"),
+                ("data\\changed.txt", b"new"),
+                ("data\\added.txt", b"added"),
+            ],
+        );
+
+        let patch_path = tmp_dir.path().join("patch.thor");
+        let diff = diff_grfs(&GrfDiffOptions {
+            old_grf_path: old_grf_path.clone(),
+            new_grf_path: new_grf_path.clone(),
+            patch_path: patch_path.clone(),
+            target_grf_name: Some("data.grf".to_string()),
+        })
+        .unwrap();
+        assert_eq!(
+            diff,
+            GrfDiff {
+                added_files: 1,
+                changed_files: 1,
+                removed_files: 1,
+            }
+        );
+        let mut archive = ThorArchive::open(&patch_path).unwrap();
+        assert!(archive.use_grf_merging());
+        assert_eq!(archive.target_grf_name(), "data.grf");
+        assert!(archive.is_valid().unwrap());
+        assert_eq!(
+            archive.read_file_content("data\\changed.txt").unwrap(),
+            b"new"
+        );
+        assert_eq!(
+            archive.read_file_content("data\\added.txt").unwrap(),
+            b"added"
+        );
+        assert!(
+            archive
+                .get_file_entry("data\\removed.txt")
+                .unwrap()
+                .is_removed
+        );
+        assert!(archive.get_file_entry("data\\Same.txt").is_none());
+
+        // There's nothing to patch between identical GRFs
+        assert!(diff_grfs(&GrfDiffOptions {
+            old_grf_path: new_grf_path.clone(),
+            new_grf_path,
+            patch_path,
+            target_grf_name: None,
+        })
+        .is_err());
+    }
+}
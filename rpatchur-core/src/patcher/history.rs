@@ -0,0 +1,142 @@
+use std::fs::File;
+use std::io::{BufReader, Write};
+use std::path::Path;
+
+use super::paths::get_patch_history_file_path;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+// Older entries are discarded to keep the history file small
+const MAX_HISTORY_ENTRIES: usize = 1000;
+
+/// Record of a patch application attempt.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PatchHistoryEntry {
+    pub index: Option<usize>, // None for manual patches
+    pub name: String,
+    pub timestamp: i64, // Unix timestamp of the beginning of the application
+    pub duration_ms: u64,
+    pub error: Option<String>, // None if the patch was applied successfully
+}
+
+/// Reads the patch history, oldest entries first.
+pub fn retrieve_patch_history() -> Result<Vec<PatchHistoryEntry>> {
+    let history_file_path = get_patch_history_file_path()?;
+    if !history_file_path.exists() {
+        return Ok(vec![]);
+    }
+    read_patch_history(history_file_path)
+}
+
+/// Appends `entry` to the patch history.
+pub fn record_patch_history_entry(entry: PatchHistoryEntry) -> Result<()> {
+    let history_file_path = get_patch_history_file_path()?;
+    let mut history = if history_file_path.exists() {
+        read_patch_history(&history_file_path)?
+    } else {
+        vec![]
+    };
+    push_history_entry(&mut history, entry);
+    write_patch_history(history_file_path, &history)
+}
+
+fn push_history_entry(history: &mut Vec<PatchHistoryEntry>, entry: PatchHistoryEntry) {
+    history.push(entry);
+    if history.len() > MAX_HISTORY_ENTRIES {
+        history.drain(..history.len() - MAX_HISTORY_ENTRIES);
+    }
+}
+
+fn read_patch_history(history_file_path: impl AsRef<Path>) -> Result<Vec<PatchHistoryEntry>> {
+    let file = File::open(history_file_path)?;
+    serde_json::from_reader(BufReader::new(file)).context("Failed to deserialize patch history")
+}
+
+fn write_patch_history(
+    history_file_path: impl AsRef<Path>,
+    history: &[PatchHistoryEntry],
+) -> Result<()> {
+    let file = File::create(history_file_path)?;
+    serde_json::to_writer(file, history).context("Failed to serialize patch history")
+}
+
+pub fn export_patch_history_to_json(
+    export_file_path: impl AsRef<Path>,
+    history: &[PatchHistoryEntry],
+) -> Result<()> {
+    let file = File::create(export_file_path)?;
+    serde_json::to_writer_pretty(file, history).context("Failed to export patch history")
+}
+
+pub fn export_patch_history_to_csv(
+    export_file_path: impl AsRef<Path>,
+    history: &[PatchHistoryEntry],
+) -> Result<()> {
+    let mut file = File::create(export_file_path)?;
+    file.write_all(patch_history_to_csv(history).as_bytes())
+        .context("Failed to export patch history")
+}
+
+fn patch_history_to_csv(history: &[PatchHistoryEntry]) -> String {
+    let mut csv = String::from("index,name,timestamp,duration_ms,result\n");
+    for entry in history {
+        let index = entry.index.map(|i| i.to_string()).unwrap_or_default();
+        let result = entry.error.as_deref().unwrap_or("success");
+        csv += &format!(
+            "{},{},{},{},{}\n",
+            index,
+            escape_csv_field(&entry.name),
+            entry.timestamp,
+            entry.duration_ms,
+            escape_csv_field(result)
+        );
+    }
+    csv
+}
+
+fn escape_csv_field(field: &str) -> String {
+    if field.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history_entry(index: usize, error: Option<&str>) -> PatchHistoryEntry {
+        PatchHistoryEntry {
+            index: Some(index),
+            name: format!("{}.thor", index),
+            timestamp: 1_600_000_000,
+            duration_ms: 42,
+            error: error.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_push_history_entry() {
+        let mut history = vec![];
+        for index in 0..MAX_HISTORY_ENTRIES + 10 {
+            push_history_entry(&mut history, history_entry(index, None));
+        }
+        assert_eq!(history.len(), MAX_HISTORY_ENTRIES);
+        assert_eq!(history[0].index, Some(10));
+    }
+
+    #[test]
+    fn test_patch_history_to_csv() {
+        let history = vec![
+            history_entry(1, None),
+            history_entry(2, Some("Failed to open \"data.grf\", access denied")),
+        ];
+        assert_eq!(
+            patch_history_to_csv(&history),
+            "index,name,timestamp,duration_ms,result\n\
+             1,1.thor,1600000000,42,success\n\
+             2,2.thor,1600000000,42,\"Failed to open \"\"data.grf\"\", access denied\"\n"
+        );
+    }
+}
@@ -0,0 +1,424 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+
+use anyhow::{anyhow, Context, Result};
+use gruf::thor::{ThorArchive, ThorPatchInfo};
+use tokio::sync::mpsc::UnboundedReceiver;
+use url::Url;
+
+use super::cancellation::{wait_for_cancellation, InterruptibleFnError};
+use super::config::InstallerConfiguration;
+use super::core::{build_http_client, UiController};
+use super::download::{download_patch_to_file, DownloadWriteOptions};
+use super::error::{ErrorKind, PatcherError};
+use super::patching::{
+    apply_patch_to_disk, apply_patch_to_grf, DiskPatchingOptions, GrfPatchingMethod,
+};
+use super::paths::get_configuration_file_path;
+use super::publish::{PatchManifest, PatchManifestEntry};
+use super::repair::may_target_grf;
+use super::{PatcherCommand, PatcherConfiguration};
+use crate::process::create_desktop_shortcut;
+use crate::status::PatchingStatus;
+
+/// Installs the game client in `install_directory`.
+///
+/// The archives listed in the installer's manifest are downloaded and
+/// extracted one after the other, then the patcher and its configuration are
+/// copied next to the game, so that the game is patched from there.
+///
+/// Returns the path of the installed patcher.
+async fn install_game_client(
+    config: &PatcherConfiguration,
+    installer_config: &InstallerConfiguration,
+    install_directory: &Path,
+    dispatch_status: impl Fn(PatchingStatus),
+) -> Result<PathBuf> {
+    let client = build_http_client(&config.web)?;
+    let manifest_url = installer_manifest_url(installer_config)?;
+    let mut archives = fetch_installer_manifest(&client, &manifest_url)
+        .await?
+        .patches;
+    archives.sort_by_key(|entry| entry.index);
+    tracing::info!(
+        "Installing {} archive(s) in '{}'",
+        archives.len(),
+        install_directory.display()
+    );
+
+    fs::create_dir_all(install_directory)
+        .with_context(|| format!("Failed to create '{}'", install_directory.display()))?;
+    // Archives are removed once extracted, the client would need twice as
+    // much space otherwise
+    let download_directory = tempfile::Builder::new()
+        .prefix("install-")
+        .tempdir_in(install_directory)
+        .with_context(|| "Failed to create the download directory")?;
+    let archive_count = archives.len();
+    dispatch_status(PatchingStatus::DownloadSizeEstimated(
+        archives.iter().map(|entry| entry.size).sum(),
+    ));
+    let mut overall_downloaded_bytes = 0;
+    for (i, entry) in archives.iter().enumerate() {
+        dispatch_status(PatchingStatus::DownloadInProgress(i, archive_count, 0));
+        let archive_path = download_directory.path().join(&entry.file_name);
+        download_archive(
+            &client,
+            &manifest_url,
+            entry,
+            &archive_path,
+            DownloadWriteOptions::new(&config.advanced),
+            |downloaded_bytes, total_bytes| {
+                dispatch_status(PatchingStatus::FileDownloadInProgress {
+                    file_name: entry.file_name.clone(),
+                    downloaded_bytes,
+                    total_bytes,
+                    overall_downloaded_bytes: overall_downloaded_bytes + downloaded_bytes,
+                })
+            },
+        )
+        .await?;
+        overall_downloaded_bytes += entry.size;
+
+        dispatch_status(PatchingStatus::InstallationInProgress(i, archive_count));
+        tracing::info!("Extracting '{}'", entry.file_name);
+        extract_archive(&archive_path, install_directory, config)
+            .with_context(|| format!("Failed to extract '{}'", entry.file_name))?;
+        let _ = fs::remove_file(&archive_path);
+    }
+
+    let patcher_path = install_patcher(install_directory)?;
+    if installer_config.create_shortcut {
+        let shortcut_name = shortcut_file_name(&config.window.title);
+        if let Err(e) = create_desktop_shortcut(&shortcut_name, &patcher_path) {
+            // The game can still be started from its directory
+            tracing::warn!("Failed to create a shortcut: {:#}", e);
+        }
+    }
+    Ok(patcher_path)
+}
+
+/// Downloads the archives of the client which may contain files of the GRF
+/// named `grf_name` to `download_directory`, so that the GRF can be rebuilt
+/// from them.
+///
+/// Returns the paths of the downloaded archives, in the order they're
+/// extracted in.
+pub(super) async fn download_client_grf_archives(
+    config: &PatcherConfiguration,
+    installer_config: &InstallerConfiguration,
+    grf_name: &str,
+    download_directory: &Path,
+    dispatch_status: impl Fn(PatchingStatus),
+) -> Result<Vec<PathBuf>> {
+    let client = build_http_client(&config.web)?;
+    let manifest_url = installer_manifest_url(installer_config)?;
+    let mut archives = fetch_installer_manifest(&client, &manifest_url)
+        .await?
+        .patches;
+    archives.retain(|entry| may_target_grf(entry, grf_name, &config.client.default_grf_name));
+    archives.sort_by_key(|entry| entry.index);
+    tracing::info!(
+        "Downloading {} archive(s) of the client for '{}'",
+        archives.len(),
+        grf_name
+    );
+
+    let archive_count = archives.len();
+    dispatch_status(PatchingStatus::DownloadSizeEstimated(
+        archives.iter().map(|entry| entry.size).sum(),
+    ));
+    let mut archive_paths = Vec::with_capacity(archive_count);
+    let mut overall_downloaded_bytes = 0;
+    for (i, entry) in archives.iter().enumerate() {
+        dispatch_status(PatchingStatus::DownloadInProgress(i, archive_count, 0));
+        let archive_path = download_directory.join(&entry.file_name);
+        download_archive(
+            &client,
+            &manifest_url,
+            entry,
+            &archive_path,
+            DownloadWriteOptions::new(&config.advanced),
+            |downloaded_bytes, total_bytes| {
+                dispatch_status(PatchingStatus::FileDownloadInProgress {
+                    file_name: entry.file_name.clone(),
+                    downloaded_bytes,
+                    total_bytes,
+                    overall_downloaded_bytes: overall_downloaded_bytes + downloaded_bytes,
+                })
+            },
+        )
+        .await?;
+        overall_downloaded_bytes += entry.size;
+        archive_paths.push(archive_path);
+    }
+    Ok(archive_paths)
+}
+
+fn installer_manifest_url(installer_config: &InstallerConfiguration) -> Result<Url> {
+    Url::parse(&installer_config.manifest_url).with_context(|| {
+        format!(
+            "Invalid installer manifest URL '{}'",
+            installer_config.manifest_url
+        )
+    })
+}
+
+/// Downloads and parses the manifest listing the archives of the client.
+async fn fetch_installer_manifest(
+    client: &reqwest::Client,
+    manifest_url: &Url,
+) -> Result<PatchManifest> {
+    let resp = client
+        .get(manifest_url.clone())
+        .send()
+        .await
+        .and_then(|resp| resp.error_for_status())
+        .map_err(|e| {
+            anyhow!(PatcherError::new(
+                ErrorKind::Network,
+                format!("Failed to download the installer manifest: {}", e),
+            ))
+        })?;
+    serde_json::from_str(&resp.text().await?).with_context(|| "Invalid installer manifest")
+}
+
+/// Downloads one of the archives of the client to `archive_path`, checking
+/// it against its entry in the manifest.
+async fn download_archive<CB: FnMut(u64, u64)>(
+    client: &reqwest::Client,
+    manifest_url: &Url,
+    entry: &PatchManifestEntry,
+    archive_path: &Path,
+    write_options: DownloadWriteOptions,
+    progress_callback: CB,
+) -> Result<()> {
+    let mut archive_file = tokio::fs::File::create(archive_path)
+        .await
+        .with_context(|| format!("Failed to create '{}'", archive_path.display()))?;
+    // Archive names are relative to the manifest
+    let archive_info = ThorPatchInfo {
+        index: entry.index,
+        file_name: entry.file_name.clone(),
+    };
+    let digest = download_patch_to_file(
+        client,
+        manifest_url,
+        &archive_info,
+        &mut archive_file,
+        None,
+        write_options,
+        progress_callback,
+    )
+    .await?;
+    if digest.size != entry.size || !digest.sha256.eq_ignore_ascii_case(&entry.sha256) {
+        return Err(anyhow!(PatcherError::new(
+            ErrorKind::Integrity,
+            format!("Archive '{}' is corrupt", entry.file_name),
+        )));
+    }
+    Ok(())
+}
+
+/// Extracts a THOR archive of the client into `install_directory`.
+///
+/// Nothing is recorded in the patcher's state, which belongs to the game
+/// directory the patcher runs from.
+fn extract_archive(
+    archive_path: &Path,
+    install_directory: &Path,
+    config: &PatcherConfiguration,
+) -> Result<()> {
+    let mut thor_archive = ThorArchive::open(archive_path)?;
+    // Installations can't be resumed anyway
+    let interrupted = AtomicBool::new(false);
+    if thor_archive.use_grf_merging() {
+        let target_grf_name = if thor_archive.target_grf_name().is_empty() {
+            config.client.default_grf_name.clone()
+        } else {
+            thor_archive.target_grf_name()
+        };
+        // GRFs are new, there's nothing to preserve by patching out of place
+        apply_patch_to_grf(
+            GrfPatchingMethod::InPlace,
+            true,
+            install_directory.join(target_grf_name),
+            &mut thor_archive,
+            &interrupted,
+        )?;
+    } else {
+        apply_patch_to_disk(
+            install_directory,
+            &mut thor_archive,
+            &DiskPatchingOptions {
+                clear_read_only: true,
+                strip_motw: config.patching.strip_motw,
+            },
+            &interrupted,
+        )?;
+    }
+    Ok(())
+}
+
+/// Copies the patcher and its configuration to `install_directory`, unless
+/// the client has been installed next to them.
+///
+/// Returns the path of the installed patcher.
+fn install_patcher(install_directory: &Path) -> Result<PathBuf> {
+    let current_exe_path = env::current_exe()?;
+    let patcher_file_name = current_exe_path
+        .file_name()
+        .context("Current executable path is invalid")?;
+    let installed_patcher_path = install_directory.join(patcher_file_name);
+    if is_same_file(&current_exe_path, &installed_patcher_path) {
+        return Ok(installed_patcher_path);
+    }
+    fs::copy(&current_exe_path, &installed_patcher_path).with_context(|| {
+        format!(
+            "Failed to copy the patcher to '{}'",
+            installed_patcher_path.display()
+        )
+    })?;
+    // Relative to the working directory
+    let configuration_file_path = get_configuration_file_path()?;
+    fs::copy(
+        &configuration_file_path,
+        install_directory.join(&configuration_file_path),
+    )
+    .with_context(|| {
+        format!(
+            "Failed to copy '{}' to '{}'",
+            configuration_file_path.display(),
+            install_directory.display()
+        )
+    })?;
+    Ok(installed_patcher_path)
+}
+
+fn is_same_file(path: &Path, other_path: &Path) -> bool {
+    match (path.canonicalize(), other_path.canonicalize()) {
+        (Ok(path), Ok(other_path)) => path == other_path,
+        _ => false,
+    }
+}
+
+/// Returns a name for the game's shortcut made of the characters of
+/// `window_title` which are allowed in file names.
+fn shortcut_file_name(window_title: &str) -> String {
+    const FORBIDDEN_CHARS: &[char] = &['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+    let file_name: String = window_title
+        .chars()
+        .filter(|c| !FORBIDDEN_CHARS.contains(c) && !c.is_control())
+        .collect();
+    let file_name = file_name.trim().trim_end_matches('.');
+    if file_name.is_empty() {
+        "Game".to_string()
+    } else {
+        file_name.to_string()
+    }
+}
+
+/// Installs the game client in `install_directory`, then lets the UI hand
+/// over to the installed patcher, which patches the game.
+pub(super) async fn install_game(
+    config: &PatcherConfiguration,
+    ui_controller: &UiController,
+    patching_thread_rx: &mut UnboundedReceiver<PatcherCommand>,
+    install_directory: &Path,
+) {
+    let installer_config = match &config.installer {
+        Some(installer_config) => installer_config,
+        None => {
+            tracing::warn!("No installer is configured");
+            return;
+        }
+    };
+    ui_controller.set_patching_in_progress(true);
+    let dispatch_status = |status| ui_controller.dispatch_patching_status(status);
+    let res = tokio::select! {
+        cancel_res = wait_for_cancellation(patching_thread_rx) => Err(cancel_res),
+        install_res = install_game_client(config, installer_config, install_directory, dispatch_status) => {
+            install_res.map_err(InterruptibleFnError::Err)
+        }
+    };
+    match res {
+        Ok(patcher_path) => {
+            tracing::info!("Game installed in '{}'", install_directory.display());
+            ui_controller.dispatch_patching_status(PatchingStatus::GameInstalled(patcher_path));
+        }
+        Err(InterruptibleFnError::Interrupted) => {
+            tracing::info!("Installation canceled");
+            ui_controller.set_patching_in_progress(false);
+        }
+        Err(InterruptibleFnError::Err(err)) => {
+            tracing::error!("Failed to install the game: {:#}", err);
+            ui_controller.dispatch_patching_status(PatchingStatus::Error((&err).into()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::patcher::publish::to_hex;
+    use httptest::{matchers::*, responders::*, Expectation, Server};
+    use sha2::{Digest, Sha256};
+
+    #[test]
+    fn test_shortcut_file_name() {
+        assert_eq!(shortcut_file_name("My RO: Renewal"), "My RO Renewal");
+        assert_eq!(shortcut_file_name(" Server/Test? "), "ServerTest");
+        assert_eq!(shortcut_file_name("Ragnarok..."), "Ragnarok");
+        assert_eq!(shortcut_file_name("***"), "Game");
+    }
+
+    #[tokio::test]
+    async fn test_download_archive() {
+        let archive_content = b"THOR archive".to_vec();
+        let server = Server::run();
+        server.expect(
+            Expectation::matching(request::method_path("GET", "/client/data.thor"))
+                .times(2)
+                .respond_with(status_code(200).body(archive_content.clone())),
+        );
+        let client = reqwest::Client::new();
+        let manifest_url = Url::parse(&server.url_str("/client/patches.json")).unwrap();
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let archive_path = tmp_dir.path().join("data.thor");
+        let mut entry = PatchManifestEntry {
+            index: 1,
+            file_name: "data.thor".to_string(),
+            size: archive_content.len() as u64,
+            sha256: to_hex(&Sha256::digest(&archive_content)),
+            ..Default::default()
+        };
+
+        download_archive(
+            &client,
+            &manifest_url,
+            &entry,
+            &archive_path,
+            DownloadWriteOptions::new(&Default::default()),
+            |_, _| {},
+        )
+        .await
+        .unwrap();
+        assert_eq!(fs::read(&archive_path).unwrap(), archive_content);
+
+        // Archives which don't match the manifest are rejected
+        entry.sha256 = "ab".to_string();
+        let error = download_archive(
+            &client,
+            &manifest_url,
+            &entry,
+            &archive_path,
+            DownloadWriteOptions::new(&Default::default()),
+            |_, _| {},
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(ErrorKind::of(&error), ErrorKind::Integrity);
+    }
+}
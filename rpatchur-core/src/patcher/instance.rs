@@ -0,0 +1,248 @@
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+pub use self::platform::InstanceListener;
+
+/// Request forwarded to the running instance of the patcher by the ones
+/// started after it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum InstanceMessage {
+    ShowWindow,
+    StartUpdate,
+    ManualPatchFile { path: PathBuf }, // Absolute path of a THOR patch
+}
+
+/// Outcome of `claim_instance`.
+pub enum InstanceClaim {
+    Primary(InstanceListener), // No other instance is running for the game directory
+    Secondary,                 // Another instance is running, requests should be forwarded to it
+}
+
+/// Makes the current process the only instance of the patcher running
+/// for the game directory, unless another one already is.
+pub fn claim_instance() -> Result<InstanceClaim> {
+    platform::claim_instance().context("Failed to check for other instances")
+}
+
+/// Sends `messages` to the instance of the patcher that's already running.
+pub fn forward_to_running_instance(messages: &[InstanceMessage]) -> Result<()> {
+    let mut stream = platform::connect().context("Failed to reach the running instance")?;
+    for message in messages {
+        serde_json::to_writer(&mut stream, message)?;
+        stream.write_all(b"\n")?;
+    }
+    stream.flush()?;
+    Ok(())
+}
+
+impl InstanceListener {
+    /// Calls `on_message` with the messages sent by other instances, from a
+    /// background thread.
+    pub fn spawn(self, on_message: impl Fn(InstanceMessage) + Send + 'static) {
+        std::thread::spawn(move || {
+            if let Err(e) = self.listen(|stream| read_messages(stream, &on_message)) {
+                log::warn!("Stopped listening to other instances: {:#}", e);
+            }
+        });
+    }
+}
+
+/// Reads the newline-delimited JSON messages sent by another instance.
+fn read_messages(stream: impl std::io::Read, on_message: &impl Fn(InstanceMessage)) {
+    for line in BufReader::new(stream).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                log::warn!("Failed to read instance message: {}", e);
+                return;
+            }
+        };
+        match serde_json::from_str(&line) {
+            Ok(message) => on_message(message),
+            Err(e) => log::warn!("Invalid instance message '{}': {}", line, e),
+        }
+    }
+}
+
+/// Windows version, backed by a named pipe.
+#[cfg(windows)]
+mod platform {
+    use std::ffi::OsStr;
+    use std::fs::{File, OpenOptions};
+    use std::io;
+    use std::os::windows::ffi::OsStrExt;
+    use std::os::windows::io::{AsRawHandle, FromRawHandle};
+    use std::path::Path;
+    use std::ptr;
+    use std::time::Duration;
+
+    use anyhow::Result;
+    use winapi::shared::winerror::{ERROR_ACCESS_DENIED, ERROR_PIPE_BUSY, ERROR_PIPE_CONNECTED};
+    use winapi::um::handleapi::INVALID_HANDLE_VALUE;
+    use winapi::um::namedpipeapi::{ConnectNamedPipe, CreateNamedPipeW};
+    use winapi::um::winbase::{
+        FILE_FLAG_FIRST_PIPE_INSTANCE, PIPE_ACCESS_INBOUND, PIPE_READMODE_BYTE,
+        PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_BYTE, PIPE_UNLIMITED_INSTANCES, PIPE_WAIT,
+    };
+
+    use super::super::paths::get_instance_pipe_path;
+    use super::InstanceClaim;
+
+    const BUFFER_SIZE: u32 = 4096;
+
+    pub struct InstanceListener {
+        pipe: File, // Instance of the pipe waiting for the next client
+    }
+
+    impl InstanceListener {
+        pub(super) fn listen(self, mut on_client: impl FnMut(File)) -> Result<()> {
+            let pipe_path = get_instance_pipe_path()?;
+            let mut pipe = self.pipe;
+            loop {
+                let connected = unsafe { ConnectNamedPipe(pipe.as_raw_handle(), ptr::null_mut()) };
+                if connected == 0 {
+                    let e = io::Error::last_os_error();
+                    if e.raw_os_error() != Some(ERROR_PIPE_CONNECTED as i32) {
+                        return Err(e.into());
+                    }
+                }
+                // Always keep an instance of the pipe around, so that no
+                // other process can claim its name
+                let next_pipe = create_pipe(&pipe_path, false)?;
+                on_client(pipe);
+                pipe = next_pipe;
+            }
+        }
+    }
+
+    pub fn claim_instance() -> Result<InstanceClaim> {
+        match create_pipe(&get_instance_pipe_path()?, true) {
+            Ok(pipe) => Ok(InstanceClaim::Primary(InstanceListener { pipe })),
+            Err(e) if e.raw_os_error() == Some(ERROR_ACCESS_DENIED as i32) => {
+                Ok(InstanceClaim::Secondary)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn connect() -> Result<File> {
+        let pipe_path = get_instance_pipe_path()?;
+        // The running instance might be busy with another client
+        for _ in 0..10 {
+            match OpenOptions::new().write(true).open(&pipe_path) {
+                Err(e) if e.raw_os_error() == Some(ERROR_PIPE_BUSY as i32) => {
+                    std::thread::sleep(Duration::from_millis(100))
+                }
+                res => return Ok(res?),
+            }
+        }
+        Ok(OpenOptions::new().write(true).open(&pipe_path)?)
+    }
+
+    fn create_pipe(pipe_path: &Path, first_instance: bool) -> io::Result<File> {
+        let pipe_name: Vec<u16> = OsStr::new(pipe_path).encode_wide().chain(Some(0)).collect();
+        let mut open_mode = PIPE_ACCESS_INBOUND;
+        if first_instance {
+            open_mode |= FILE_FLAG_FIRST_PIPE_INSTANCE;
+        }
+        let handle = unsafe {
+            CreateNamedPipeW(
+                pipe_name.as_ptr(),
+                open_mode,
+                PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+                PIPE_UNLIMITED_INSTANCES,
+                BUFFER_SIZE,
+                BUFFER_SIZE,
+                0,
+                ptr::null_mut(),
+            )
+        };
+        if handle == INVALID_HANDLE_VALUE {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(unsafe { File::from_raw_handle(handle) })
+    }
+}
+
+/// Unix version, backed by a Unix domain socket.
+#[cfg(unix)]
+mod platform {
+    use std::fs;
+    use std::io;
+    use std::os::unix::net::{UnixListener, UnixStream};
+
+    use anyhow::Result;
+
+    use super::super::paths::get_instance_socket_path;
+    use super::InstanceClaim;
+
+    pub struct InstanceListener {
+        listener: UnixListener,
+    }
+
+    impl InstanceListener {
+        pub(super) fn listen(self, mut on_client: impl FnMut(UnixStream)) -> Result<()> {
+            for stream in self.listener.incoming() {
+                on_client(stream?);
+            }
+            Ok(())
+        }
+    }
+
+    pub fn claim_instance() -> Result<InstanceClaim> {
+        let socket_path = get_instance_socket_path()?;
+        match UnixListener::bind(&socket_path) {
+            Ok(listener) => Ok(InstanceClaim::Primary(InstanceListener { listener })),
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse => {
+                if UnixStream::connect(&socket_path).is_ok() {
+                    return Ok(InstanceClaim::Secondary);
+                }
+                // Left behind by an instance which didn't exit cleanly
+                fs::remove_file(&socket_path)?;
+                let listener = UnixListener::bind(&socket_path)?;
+                Ok(InstanceClaim::Primary(InstanceListener { listener }))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn connect() -> Result<UnixStream> {
+        Ok(UnixStream::connect(get_instance_socket_path()?)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_read_messages() {
+        let (message_tx, message_rx) = mpsc::channel();
+        let stream = concat!(
+            r#"{"type":"show_window"}"#,
+            "\n",
+            r#"{"type":"unknown"}"#,
+            "\n",
+            r#"{"type":"manual_patch_file","path":"/tmp/1.thor"}"#,
+            "\n",
+        );
+        read_messages(stream.as_bytes(), &|message| {
+            message_tx.send(message).unwrap()
+        });
+        let messages: Vec<InstanceMessage> = message_rx.try_iter().collect();
+        assert_eq!(
+            messages,
+            [
+                InstanceMessage::ShowWindow,
+                InstanceMessage::ManualPatchFile {
+                    path: PathBuf::from("/tmp/1.thor")
+                }
+            ]
+        );
+    }
+}
@@ -0,0 +1,169 @@
+use anyhow::Result;
+
+use super::get_patcher_name;
+
+/// Whether passwords can be stored in the OS' keychain on this platform.
+pub const KEYCHAIN_SUPPORTED: bool = cfg!(any(windows, target_os = "macos"));
+
+/// Name under which the current 'instance' of the patcher stores passwords.
+fn keychain_service_name() -> Result<String> {
+    Ok(get_patcher_name()?.to_string_lossy().to_string())
+}
+
+/// Stores the password of `account` in the OS' keychain, replacing the
+/// previous one.
+pub fn store_password(account: &str, password: &str) -> Result<()> {
+    platform::store_password(&keychain_service_name()?, account, password)
+}
+
+/// Retrieves the password of `account` from the OS' keychain, if any.
+pub fn find_password(account: &str) -> Result<Option<String>> {
+    platform::find_password(&keychain_service_name()?, account)
+}
+
+/// Removes the password of `account` from the OS' keychain.
+///
+/// Missing passwords are not considered an error.
+pub fn delete_password(account: &str) -> Result<()> {
+    platform::delete_password(&keychain_service_name()?, account)
+}
+
+/// Windows version, backed by the Credential Manager.
+#[cfg(windows)]
+mod platform {
+    use std::ffi::OsStr;
+    use std::os::windows::ffi::OsStrExt;
+    use std::ptr;
+
+    use anyhow::{anyhow, Context, Result};
+    use winapi::shared::winerror::ERROR_NOT_FOUND;
+    use winapi::um::errhandlingapi::GetLastError;
+    use winapi::um::wincred::{
+        CredDeleteW, CredFree, CredReadW, CredWriteW, CREDENTIALW, CRED_PERSIST_LOCAL_MACHINE,
+        CRED_TYPE_GENERIC, PCREDENTIALW,
+    };
+
+    fn to_u16s(s: &str) -> Vec<u16> {
+        OsStr::new(s)
+            .encode_wide()
+            .chain(std::iter::once(0))
+            .collect()
+    }
+
+    fn target_name(service: &str, account: &str) -> Vec<u16> {
+        to_u16s(&format!("{}:{}", service, account))
+    }
+
+    pub fn store_password(service: &str, account: &str, password: &str) -> Result<()> {
+        let mut target_name = target_name(service, account);
+        let mut user_name = to_u16s(account);
+        let mut blob = password.as_bytes().to_vec();
+        let mut credential: CREDENTIALW = unsafe { std::mem::zeroed() };
+        credential.Type = CRED_TYPE_GENERIC;
+        credential.TargetName = target_name.as_mut_ptr();
+        credential.UserName = user_name.as_mut_ptr();
+        credential.CredentialBlobSize = blob.len() as u32;
+        credential.CredentialBlob = blob.as_mut_ptr();
+        credential.Persist = CRED_PERSIST_LOCAL_MACHINE;
+        if unsafe { CredWriteW(&mut credential, 0) } == 0 {
+            return Err(std::io::Error::last_os_error())
+                .context("Failed to store the password in the Credential Manager");
+        }
+        Ok(())
+    }
+
+    pub fn find_password(service: &str, account: &str) -> Result<Option<String>> {
+        let target_name = target_name(service, account);
+        let mut credential: PCREDENTIALW = ptr::null_mut();
+        unsafe {
+            if CredReadW(target_name.as_ptr(), CRED_TYPE_GENERIC, 0, &mut credential) == 0 {
+                if GetLastError() == ERROR_NOT_FOUND {
+                    return Ok(None);
+                }
+                return Err(std::io::Error::last_os_error())
+                    .context("Failed to read the password from the Credential Manager");
+            }
+            let blob = std::slice::from_raw_parts(
+                (*credential).CredentialBlob,
+                (*credential).CredentialBlobSize as usize,
+            );
+            let password = String::from_utf8(blob.to_vec());
+            CredFree(credential as *mut _);
+            password
+                .map(Some)
+                .map_err(|_| anyhow!("Stored password is invalid"))
+        }
+    }
+
+    pub fn delete_password(service: &str, account: &str) -> Result<()> {
+        let target_name = target_name(service, account);
+        unsafe {
+            if CredDeleteW(target_name.as_ptr(), CRED_TYPE_GENERIC, 0) == 0
+                && GetLastError() != ERROR_NOT_FOUND
+            {
+                return Err(std::io::Error::last_os_error())
+                    .context("Failed to delete the password from the Credential Manager");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// macOS version, backed by the login keychain.
+#[cfg(target_os = "macos")]
+mod platform {
+    use anyhow::{anyhow, Context, Result};
+    use security_framework::os::macos::keychain::SecKeychain;
+    use security_framework::os::macos::passwords::find_generic_password;
+
+    // errSecItemNotFound
+    const ITEM_NOT_FOUND: i32 = -25300;
+
+    pub fn store_password(service: &str, account: &str, password: &str) -> Result<()> {
+        SecKeychain::default()
+            .and_then(|keychain| {
+                keychain.set_generic_password(service, account, password.as_bytes())
+            })
+            .context("Failed to store the password in the keychain")
+    }
+
+    pub fn find_password(service: &str, account: &str) -> Result<Option<String>> {
+        match find_generic_password(None, service, account) {
+            Ok((password, _)) => String::from_utf8(password.to_vec())
+                .map(Some)
+                .map_err(|_| anyhow!("Stored password is invalid")),
+            Err(e) if e.code() == ITEM_NOT_FOUND => Ok(None),
+            Err(e) => Err(e).context("Failed to read the password from the keychain"),
+        }
+    }
+
+    pub fn delete_password(service: &str, account: &str) -> Result<()> {
+        match find_generic_password(None, service, account) {
+            Ok((_, item)) => {
+                item.delete();
+                Ok(())
+            }
+            Err(e) if e.code() == ITEM_NOT_FOUND => Ok(()),
+            Err(e) => Err(e).context("Failed to delete the password from the keychain"),
+        }
+    }
+}
+
+/// Version for platforms without a supported keychain, where passwords are
+/// never remembered.
+#[cfg(not(any(windows, target_os = "macos")))]
+mod platform {
+    use anyhow::{anyhow, Result};
+
+    pub fn store_password(_service: &str, _account: &str, _password: &str) -> Result<()> {
+        Err(anyhow!("No keychain is available on this platform"))
+    }
+
+    pub fn find_password(_service: &str, _account: &str) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    pub fn delete_password(_service: &str, _account: &str) -> Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+use std::sync::mpsc;
+
+use anyhow::{anyhow, Context, Result};
+
+use super::config::VariablesConfiguration;
+use super::core::build_tokio_runtime;
+
+/// Entry point of the thread which fetches the launch variables advertised
+/// by the operator's status endpoint.
+pub fn launch_variables_thread_routine(
+    config: VariablesConfiguration,
+    variables_tx: mpsc::Sender<HashMap<String, String>>,
+) -> Result<()> {
+    let variables_url = match &config.url {
+        Some(variables_url) => variables_url,
+        None => return Ok(()),
+    };
+    let tokio_rt = build_tokio_runtime()?;
+    let variables = tokio_rt.block_on(fetch_launch_variables(variables_url))?;
+    // The UI might be gone already
+    let _ = variables_tx.send(variables);
+    Ok(())
+}
+
+async fn fetch_launch_variables(variables_url: &str) -> Result<HashMap<String, String>> {
+    let resp = reqwest::get(variables_url)
+        .await
+        .with_context(|| format!("Failed to fetch '{}'", variables_url))?;
+    if !resp.status().is_success() {
+        return Err(anyhow!(
+            "Failed to fetch '{}': {}",
+            variables_url,
+            resp.status()
+        ));
+    }
+    let content = resp.text().await?;
+    let values: HashMap<String, serde_json::Value> =
+        serde_json::from_str(&content).context("Invalid launch variables")?;
+    // Numbers (e.g. ports) are as useful as strings in command lines
+    Ok(values
+        .into_iter()
+        .filter_map(|(name, value)| match value {
+            serde_json::Value::String(value) => Some((name, value)),
+            serde_json::Value::Number(value) => Some((name, value.to_string())),
+            serde_json::Value::Bool(value) => Some((name, value.to_string())),
+            _ => None,
+        })
+        .collect())
+}
+
+/// Replaces the `{name}` placeholders of `template` with the value of the
+/// corresponding variable.
+///
+/// Braces which don't surround a variable name are kept as is.
+pub fn expand_template(template: &str, variables: &HashMap<String, String>) -> Result<String> {
+    let mut expanded = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        expanded.push_str(&rest[..start]);
+        let after_brace = &rest[start + 1..];
+        let name = after_brace
+            .find('}')
+            .map(|end| &after_brace[..end])
+            .filter(|name| is_variable_name(name));
+        match name {
+            Some(name) => {
+                let value = variables
+                    .get(name)
+                    .ok_or_else(|| anyhow!("Variable '{}' isn't set", name))?;
+                expanded.push_str(value);
+                rest = &after_brace[name.len() + 1..];
+            }
+            None => {
+                expanded.push('{');
+                rest = after_brace;
+            }
+        }
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+fn is_variable_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use httptest::{matchers::*, responders::*, Expectation, Server};
+
+    #[test]
+    fn test_expand_template() {
+        let variables: HashMap<String, String> = vec![
+            ("server_ip".to_string(), "127.0.0.1".to_string()),
+            ("token".to_string(), "abc123".to_string()),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(expand_template("1rag1", &variables).unwrap(), "1rag1");
+        assert_eq!(
+            expand_template("-t:{token}", &variables).unwrap(),
+            "-t:abc123"
+        );
+        assert_eq!(
+            expand_template("{server_ip}:{token}", &variables).unwrap(),
+            "127.0.0.1:abc123"
+        );
+        assert_eq!(
+            expand_template("{\"a\": {}} {", &variables).unwrap(),
+            "{\"a\": {}} {"
+        );
+        assert_eq!(
+            expand_template("-u:{username}", &variables)
+                .unwrap_err()
+                .to_string(),
+            "Variable 'username' isn't set"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_launch_variables() {
+        let server = Server::run();
+        server.expect(
+            Expectation::matching(request::method_path("GET", "/status.json")).respond_with(
+                status_code(200).body(r#"{"server_ip":"10.0.0.1","port":6900,"motd":null}"#),
+            ),
+        );
+
+        let variables = fetch_launch_variables(&server.url_str("/status.json"))
+            .await
+            .unwrap();
+        assert_eq!(variables.len(), 2);
+        assert_eq!(variables["server_ip"], "10.0.0.1");
+        assert_eq!(variables["port"], "6900");
+    }
+}
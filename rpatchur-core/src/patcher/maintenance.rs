@@ -0,0 +1,108 @@
+use std::sync::mpsc;
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+
+use super::config::MaintenanceConfiguration;
+use super::core::build_tokio_runtime;
+
+/// Whether the game server is in maintenance, as advertised by the
+/// operator's status endpoint.
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct MaintenanceStatus {
+    #[serde(default)]
+    pub maintenance: bool,
+    pub message: Option<String>, // Shown to players during maintenance
+}
+
+/// Entry point of the thread which polls the maintenance status endpoint.
+///
+/// Statuses are sent to `status_tx` when they change, `on_change` is called
+/// right after. The thread exits once the receiver has been dropped.
+pub fn maintenance_thread_routine(
+    config: MaintenanceConfiguration,
+    status_tx: mpsc::Sender<MaintenanceStatus>,
+    on_change: impl Fn(),
+) -> Result<()> {
+    let status_url = match &config.url {
+        Some(status_url) => status_url,
+        None => return Ok(()),
+    };
+    let tokio_rt = build_tokio_runtime()?;
+    let client = reqwest::Client::new();
+    let mut last_status = MaintenanceStatus::default();
+    loop {
+        match tokio_rt.block_on(fetch_maintenance_status(&client, status_url)) {
+            Ok(status) if status != last_status => {
+                if status.maintenance {
+                    log::info!("The game server is in maintenance");
+                } else if last_status.maintenance {
+                    log::info!("Maintenance is over");
+                }
+                // The UI might be gone already
+                if status_tx.send(status.clone()).is_err() {
+                    return Ok(());
+                }
+                on_change();
+                last_status = status;
+            }
+            Ok(_) => {}
+            // Keep the last known status, the endpoint might be down for a
+            // moment
+            Err(e) => log::warn!("Failed to retrieve the maintenance status: {:#}", e),
+        }
+        std::thread::sleep(config.poll_interval());
+    }
+}
+
+async fn fetch_maintenance_status(
+    client: &reqwest::Client,
+    status_url: &str,
+) -> Result<MaintenanceStatus> {
+    let resp = client
+        .get(status_url)
+        .send()
+        .await
+        .with_context(|| format!("Failed to fetch '{}'", status_url))?;
+    if !resp.status().is_success() {
+        return Err(anyhow!(
+            "Failed to fetch '{}': {}",
+            status_url,
+            resp.status()
+        ));
+    }
+    let content = resp.text().await?;
+    serde_json::from_str(&content).context("Invalid maintenance status")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use httptest::{matchers::*, responders::*, Expectation, Server};
+
+    #[tokio::test]
+    async fn test_fetch_maintenance_status() {
+        let server = Server::run();
+        server.expect(
+            Expectation::matching(request::method_path("GET", "/status.json")).respond_with(
+                status_code(200)
+                    .body(r#"{"maintenance":true,"message":"Back at 10:00","players":0}"#),
+            ),
+        );
+        server.expect(
+            Expectation::matching(request::method_path("GET", "/empty.json"))
+                .respond_with(status_code(200).body("{}")),
+        );
+        let client = reqwest::Client::new();
+
+        let status = fetch_maintenance_status(&client, &server.url_str("/status.json"))
+            .await
+            .unwrap();
+        assert!(status.maintenance);
+        assert_eq!(status.message.as_deref(), Some("Back at 10:00"));
+        let status = fetch_maintenance_status(&client, &server.url_str("/empty.json"))
+            .await
+            .unwrap();
+        assert_eq!(status, MaintenanceStatus::default());
+    }
+}
@@ -0,0 +1,277 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Cursor, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use gruf::thor::ThorArchiveBuilder;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_REQUEST_HEADERS: usize = 64;
+// Throttled downloads are sent in chunks, this many times per second
+const THROTTLE_TICKS_PER_SEC: u64 = 10;
+
+/// Patches served by the mock patch server and the way it misbehaves.
+pub struct MockServerOptions {
+    pub port: u16,                   // 0 picks any free port
+    pub patch_count: usize,          // Patches are numbered from 1
+    pub patch_size: usize,           // Size of the file added by each patch, in bytes
+    pub bytes_per_sec: Option<u64>,  // Bandwidth of each download, unlimited if not set
+    pub failing_patches: Vec<usize>, // Indices of the patches whose downloads fail
+    pub corrupt_patches: Vec<usize>, // Indices of the patches served truncated
+}
+
+/// Local patch server serving a synthetic 'plist.txt' and generated THOR
+/// patches, so that the whole patching pipeline can be exercised without a
+/// real server (e.g. by skin developers).
+pub struct MockPatchServer {
+    listener: TcpListener,
+    content: Arc<MockContent>,
+}
+
+struct MockContent {
+    patch_list: String,
+    patches: HashMap<String, MockPatch>,
+    bytes_per_sec: Option<u64>,
+}
+
+struct MockPatch {
+    data: Vec<u8>,
+    failing: bool,
+}
+
+impl MockPatchServer {
+    /// Generates the patches and binds the server's socket, which only
+    /// accepts local connections.
+    pub fn bind(options: &MockServerOptions) -> Result<Self> {
+        let mut patch_list = String::new();
+        let mut patches = HashMap::new();
+        for index in 1..=options.patch_count {
+            let file_name = format!("mock_{:04}.thor", index);
+            let mut data = generate_patch(index, options.patch_size)
+                .with_context(|| format!("Failed to generate '{}'", file_name))?;
+            if options.corrupt_patches.contains(&index) {
+                data.truncate(data.len() / 2);
+            }
+            patch_list.push_str(&format!("{} {}\n", index, file_name));
+            patches.insert(
+                file_name,
+                MockPatch {
+                    data,
+                    failing: options.failing_patches.contains(&index),
+                },
+            );
+        }
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, options.port))
+            .with_context(|| format!("Failed to listen on port {}", options.port))?;
+        Ok(Self {
+            listener,
+            content: Arc::new(MockContent {
+                patch_list,
+                patches,
+                bytes_per_sec: options.bytes_per_sec,
+            }),
+        })
+    }
+
+    /// Returns the URL of the server's 'plist.txt', for `plist_url`.
+    pub fn plist_url(&self) -> Result<String> {
+        Ok(format!("{}plist.txt", self.base_url()?))
+    }
+
+    /// Returns the URL of the directory of the server's patches, for
+    /// `patch_url`.
+    pub fn patch_url(&self) -> Result<String> {
+        Ok(format!("{}data/", self.base_url()?))
+    }
+
+    fn base_url(&self) -> Result<String> {
+        let port = self.listener.local_addr()?.port();
+        Ok(format!("http://{}:{}/", Ipv4Addr::LOCALHOST, port))
+    }
+
+    /// Serves requests until the process exits.
+    pub fn serve(self) {
+        for stream in self.listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    log::warn!("Failed to accept a connection: {}", e);
+                    continue;
+                }
+            };
+            let content = self.content.clone();
+            std::thread::spawn(move || {
+                if let Err(e) = serve_request(stream, &content) {
+                    log::debug!("Failed to serve a request: {:#}", e);
+                }
+            });
+        }
+    }
+}
+
+/// Builds a patch adding a single file of `size` bytes, which differs from
+/// one patch to the other and doesn't compress well, like real game files.
+fn generate_patch(index: usize, size: usize) -> Result<Vec<u8>> {
+    let mut state = 0x9e37_79b9_7f4a_7c15_u64 ^ index as u64;
+    let file_content: Vec<u8> = (0..size)
+        .map(|_| {
+            // xorshift64
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect();
+    let mut archive = Cursor::new(Vec::new());
+    let mut builder = ThorArchiveBuilder::new(&mut archive, false, None, true)?;
+    builder.append_file_update(
+        format!("data\\mock\\patch_{:04}.bin", index),
+        file_content.as_slice(),
+    )?;
+    builder.finish()?;
+    drop(builder);
+    Ok(archive.into_inner())
+}
+
+fn serve_request(mut stream: TcpStream, content: &MockContent) -> Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Headers don't matter
+    let mut header = String::new();
+    for _ in 0..MAX_REQUEST_HEADERS {
+        header.clear();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (method, path) = match (parts.next(), parts.next()) {
+        (Some(method), Some(path)) => (method, path),
+        _ => return write_status(&stream, "400 Bad Request"),
+    };
+    let with_body = match method {
+        "GET" => true,
+        "HEAD" => false,
+        _ => return write_status(&stream, "405 Method Not Allowed"),
+    };
+    log::info!("{} {}", method, path);
+    let body = match path {
+        "/plist.txt" => content.patch_list.as_bytes(),
+        _ => match path
+            .strip_prefix("/data/")
+            .and_then(|file_name| content.patches.get(file_name))
+        {
+            // Failures are limited to downloads, the server looks healthy
+            // when it's probed
+            Some(patch) if patch.failing && with_body => {
+                return write_status(&stream, "500 Internal Server Error")
+            }
+            Some(patch) => patch.data.as_slice(),
+            None => return write_status(&stream, "404 Not Found"),
+        },
+    };
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    )?;
+    if with_body {
+        write_body(&mut stream, body, content.bytes_per_sec)?;
+    }
+    stream.flush()?;
+    Ok(())
+}
+
+fn write_body(stream: &mut TcpStream, body: &[u8], bytes_per_sec: Option<u64>) -> io::Result<()> {
+    let bytes_per_sec = match bytes_per_sec {
+        Some(bytes_per_sec) => bytes_per_sec,
+        None => return stream.write_all(body),
+    };
+    let chunk_size = (bytes_per_sec / THROTTLE_TICKS_PER_SEC).max(1) as usize;
+    for chunk in body.chunks(chunk_size) {
+        stream.write_all(chunk)?;
+        std::thread::sleep(Duration::from_millis(1000 / THROTTLE_TICKS_PER_SEC));
+    }
+    Ok(())
+}
+
+fn write_status(mut stream: &TcpStream, status: &str) -> Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        status
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gruf::thor::{self, ThorArchive};
+    use std::io::Read;
+
+    fn send_request(port: u16, request_line: &str) -> (String, Vec<u8>) {
+        let mut stream = TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap();
+        write!(stream, "{}\r\n\r\n", request_line).unwrap();
+        let mut response = vec![];
+        stream.read_to_end(&mut response).unwrap();
+        let header_end = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let status_line = String::from_utf8_lossy(&response[..header_end])
+            .lines()
+            .next()
+            .unwrap()
+            .to_string();
+        (status_line, response[header_end + 4..].to_vec())
+    }
+
+    #[test]
+    fn test_mock_patch_server() {
+        let options = MockServerOptions {
+            port: 0,
+            patch_count: 3,
+            patch_size: 4096,
+            bytes_per_sec: None,
+            failing_patches: vec![2],
+            corrupt_patches: vec![3],
+        };
+        let server = MockPatchServer::bind(&options).unwrap();
+        let plist_url = server.plist_url().unwrap();
+        assert!(plist_url.starts_with("http://127.0.0.1:"));
+        assert!(server.patch_url().unwrap().ends_with("/data/"));
+        let port = server.listener.local_addr().unwrap().port();
+        std::thread::spawn(move || server.serve());
+
+        let (status_line, body) = send_request(port, "GET /plist.txt HTTP/1.1");
+        assert_eq!(status_line, "HTTP/1.1 200 OK");
+        let patch_list = thor::patch_list_from_string(&String::from_utf8(body).unwrap());
+        let file_names: Vec<&str> = patch_list.iter().map(|p| p.file_name.as_str()).collect();
+        assert_eq!(
+            file_names,
+            ["mock_0001.thor", "mock_0002.thor", "mock_0003.thor"]
+        );
+
+        let (status_line, body) = send_request(port, "GET /data/mock_0001.thor HTTP/1.1");
+        assert_eq!(status_line, "HTTP/1.1 200 OK");
+        let mut archive = ThorArchive::new(Cursor::new(body)).unwrap();
+        assert!(archive.is_valid().unwrap());
+        let file_content = archive
+            .read_file_content("data\\mock\\patch_0001.bin")
+            .unwrap();
+        assert_eq!(file_content.len(), 4096);
+
+        let (status_line, _) = send_request(port, "HEAD /data/mock_0002.thor HTTP/1.1");
+        assert_eq!(status_line, "HTTP/1.1 200 OK");
+        let (status_line, _) = send_request(port, "GET /data/mock_0002.thor HTTP/1.1");
+        assert_eq!(status_line, "HTTP/1.1 500 Internal Server Error");
+        let (_, body) = send_request(port, "GET /data/mock_0003.thor HTTP/1.1");
+        assert!(ThorArchive::new(Cursor::new(body)).is_err());
+        let (status_line, _) = send_request(port, "GET /data/patch_manifest.json HTTP/1.1");
+        assert_eq!(status_line, "HTTP/1.1 404 Not Found");
+    }
+}
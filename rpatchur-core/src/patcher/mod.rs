@@ -0,0 +1,168 @@
+mod account;
+mod agreement;
+mod banner;
+mod bundle;
+mod cancellation;
+mod cleanup;
+mod config;
+mod core;
+mod diagnostics;
+mod doctor;
+mod download;
+mod engine;
+mod error;
+mod grf_browser;
+mod grf_diff;
+mod history;
+mod installer;
+mod instance;
+mod keychain;
+mod launch;
+mod maintenance;
+#[cfg(feature = "mock-server")]
+mod mock_server;
+mod motd;
+mod notifications;
+mod patch_list;
+mod patch_servers;
+mod patching;
+mod paths;
+mod peer_cache;
+mod postprocess;
+mod publish;
+mod remote_update;
+mod repair;
+mod rollback;
+mod scripting;
+mod settings;
+mod simulation;
+mod squash;
+mod state;
+mod storage;
+mod update_lock;
+mod zip;
+
+use std::env;
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+pub use self::account::authenticate;
+pub use self::agreement::{accept_agreement, is_agreement_pending};
+pub use self::banner::{banner_thread_routine, BannerImage};
+pub use self::bundle::BUNDLE_FILE_EXTENSION;
+pub use self::config::{
+    retrieve_patcher_configuration, AccountConfiguration, AgreementConfiguration,
+    BannerConfiguration, ControlApiConfiguration, DownloadOrder, IntegrityPolicy,
+    LaunchButtonConfiguration, LocalizationConfiguration, PatcherConfiguration, SkinConfiguration,
+    WineConfiguration, STABLE_CHANNEL,
+};
+pub use self::core::{patcher_thread_routine, run_patcher_command};
+pub use self::diagnostics::{create_support_bundle, write_crash_report};
+pub use self::doctor::{run_health_checks, CheckResult, CheckStatus};
+pub use self::engine::{CancelHandle, Patcher};
+pub use self::error::ErrorKind;
+pub use self::grf_browser::{GrfEntryInfo, GrfIndex};
+pub use self::grf_diff::{diff_grfs, GrfDiffOptions};
+pub use self::history::{
+    export_patch_history_to_csv, export_patch_history_to_json, retrieve_patch_history,
+    PatchHistoryEntry,
+};
+pub use self::instance::{
+    claim_instance, forward_to_running_instance, InstanceClaim, InstanceListener, InstanceMessage,
+};
+pub use self::keychain::{delete_password, find_password, store_password, KEYCHAIN_SUPPORTED};
+pub use self::launch::{expand_template, launch_variables_thread_routine};
+pub use self::maintenance::{maintenance_thread_routine, MaintenanceStatus};
+#[cfg(feature = "mock-server")]
+pub use self::mock_server::{MockPatchServer, MockServerOptions};
+pub use self::motd::{motd_thread_routine, Motd, MotdSeverity};
+pub use self::patch_list::{register_patch_list_source, PatchListSource};
+pub use self::paths::{
+    get_app_bundle_directory, get_log_file_path, init_data_directory, is_portable,
+    rotated_file_path,
+};
+pub use self::peer_cache::serve_peer_cache;
+pub use self::publish::{publish_release, PublishOptions};
+pub use self::scripting::ScriptHooks;
+pub use self::settings::{
+    retrieve_user_settings, save_user_settings, UserSettings, WindowGeometry,
+};
+pub use self::simulation::{simulate_network_conditions, NetworkSimulation};
+pub use self::squash::{squash_published_patches, SquashOptions};
+pub use self::state::{retrieve_patcher_state, DownloadSession, InstalledFile};
+pub use self::storage::{clear_storage, retrieve_storage_usage, StorageCategory, StorageUsage};
+use anyhow::{anyhow, Context, Result};
+
+// Set at startup, before any of the instance's files is used
+static PROFILE: OnceLock<String> = OnceLock::new();
+
+#[derive(Debug)]
+pub enum PatcherCommand {
+    StartUpdate,
+    RetryFailedPatches, // Download the patches the last update failed to download, and go on
+    CheckForUpdates,
+    CancelUpdate,      // Stop once the patch being applied has been applied completely
+    ForceCancelUpdate, // Stop right away, even in the middle of a patch
+    ResetCache,
+    ManualPatchFile(PathBuf), // Patch picked or dropped onto the window by the user
+    ApplySettings(UserSettings),
+    CloseGameClient, // Terminate the game client so that patching can go on
+    RetryPatch,      // Try patching the files the game held open again
+    SelectPatches {
+        selection: Vec<bool>, // Whether each of the previewed patches should be downloaded
+        apply: bool,          // Apply patches right away or keep them for later
+    },
+    Install(PathBuf), // Install the game client in the given directory
+    ExportBundle {
+        path: PathBuf,
+        all_patches: bool, // Export every patch instead of the pending ones only
+        squash: bool,      // Merge consecutive patches into cumulative ones
+    },
+    ApplyBundle(PathBuf), // Apply the pending patches of an offline bundle
+    FindOrphanedFiles,    // Look for the files left over by removed content
+    DeleteOrphanedFiles(Option<Vec<String>>), // Delete the given leftovers (by relative path), all of them if not set
+    ProbePatchServers,                        // Check which of the patch servers are available
+    RepairGrf(String), // Check the given GRF (by name), and rebuild it from the client's archives and the applied patches if it's corrupt
+    Quit,
+}
+
+/// Returns the file name of the patcher's executable, without its extension.
+fn get_executable_stem() -> Result<OsString> {
+    let current_exe_path = env::current_exe()?;
+    Ok(current_exe_path
+        .file_stem()
+        .context("Current executable path is invalid")?
+        .to_os_string())
+}
+
+/// Selects the profile of the patcher, which has its own configuration,
+/// state, logs, ... so that one executable can be used for several servers.
+pub fn set_profile(profile: &str) -> Result<()> {
+    let is_valid = !profile.is_empty()
+        && profile
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !is_valid {
+        return Err(anyhow!("Invalid profile name '{}'", profile));
+    }
+    PROFILE
+        .set(profile.to_string())
+        .map_err(|_| anyhow!("Profile is already set"))
+}
+
+pub fn get_profile() -> Option<&'static str> {
+    PROFILE.get().map(String::as_str)
+}
+
+/// Returns the name which the files of the current 'instance' of the patcher
+/// are named after.
+pub fn get_patcher_name() -> Result<OsString> {
+    let mut patcher_name = get_executable_stem()?;
+    // Extensions are replaced by the callers, hence the dash
+    if let Some(profile) = PROFILE.get() {
+        patcher_name.push("-");
+        patcher_name.push(profile);
+    }
+    Ok(patcher_name)
+}
@@ -0,0 +1,94 @@
+use std::sync::mpsc;
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+
+use super::config::MotdConfiguration;
+use super::core::build_tokio_runtime;
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MotdSeverity {
+    #[default]
+    Info,
+    Warning,
+    Critical,
+}
+
+/// Message of the day published by the operator.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct Motd {
+    pub message: String,
+    #[serde(default)]
+    pub severity: MotdSeverity,
+}
+
+/// Entry point of the thread which fetches the message of the day.
+pub fn motd_thread_routine(config: MotdConfiguration, motd_tx: mpsc::Sender<Motd>) -> Result<()> {
+    let motd_url = match &config.url {
+        Some(motd_url) => motd_url,
+        None => return Ok(()),
+    };
+    let tokio_rt = build_tokio_runtime()?;
+    if let Some(motd) = tokio_rt.block_on(fetch_motd(motd_url))? {
+        // The UI might be gone already
+        let _ = motd_tx.send(motd);
+    }
+    Ok(())
+}
+
+/// Returns `None` if there's no message at the moment.
+async fn fetch_motd(motd_url: &str) -> Result<Option<Motd>> {
+    let resp = reqwest::get(motd_url)
+        .await
+        .with_context(|| format!("Failed to fetch '{}'", motd_url))?;
+    if !resp.status().is_success() {
+        return Err(anyhow!("Failed to fetch '{}': {}", motd_url, resp.status()));
+    }
+    let content = resp.text().await?;
+    Ok(parse_motd(&content))
+}
+
+/// Parses a message of the day, which is either a JSON object or plain
+/// text.
+fn parse_motd(content: &str) -> Option<Motd> {
+    let content = content.trim();
+    let motd = match serde_json::from_str(content) {
+        Ok(motd) => motd,
+        // Plain text is informative
+        Err(_) => Motd {
+            message: content.to_string(),
+            severity: MotdSeverity::Info,
+        },
+    };
+    Some(motd).filter(|motd| !motd.message.trim().is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_motd() {
+        assert_eq!(
+            parse_motd(r#"{"message":"Login servers are down","severity":"critical"}"#),
+            Some(Motd {
+                message: "Login servers are down".to_string(),
+                severity: MotdSeverity::Critical,
+            })
+        );
+        assert_eq!(
+            parse_motd(r#"{"message":"Double EXP weekend"}"#).map(|motd| motd.severity),
+            Some(MotdSeverity::Info)
+        );
+        assert_eq!(
+            parse_motd("Welcome back!\n"),
+            Some(Motd {
+                message: "Welcome back!".to_string(),
+                severity: MotdSeverity::Info,
+            })
+        );
+        assert_eq!(parse_motd(r#"{"message":""}"#), None);
+        assert_eq!(parse_motd("  \n"), None);
+    }
+}
@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+
+use super::config::{NotificationsConfiguration, WebhookConfiguration};
+use super::error::ErrorKind;
+use super::get_profile;
+use super::launch::expand_template;
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Events webhooks can be subscribed to.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent {
+    Success, // The game has been updated
+    Failure, // Updating failed, whatever the reason
+    // Updating failed because of a specific kind of error
+    Network,
+    Integrity,
+    LockContention,
+    GameClientRunning,
+    PatcherOutdated,
+}
+
+impl WebhookEvent {
+    fn of_error_kind(kind: ErrorKind) -> Option<Self> {
+        match kind {
+            ErrorKind::Network => Some(WebhookEvent::Network),
+            ErrorKind::Integrity => Some(WebhookEvent::Integrity),
+            ErrorKind::LockContention => Some(WebhookEvent::LockContention),
+            ErrorKind::GameClientRunning => Some(WebhookEvent::GameClientRunning),
+            ErrorKind::PatcherOutdated => Some(WebhookEvent::PatcherOutdated),
+            // Cancellations aren't reported as failures
+            ErrorKind::Cancelled | ErrorKind::Other => None,
+        }
+    }
+}
+
+pub fn default_webhook_events() -> Vec<WebhookEvent> {
+    vec![WebhookEvent::Success, WebhookEvent::Failure]
+}
+
+/// Tells the operator's webhooks how an update went.
+///
+/// Failing to reach a webhook doesn't affect the update, errors are only
+/// logged.
+pub async fn notify_update_result(
+    config: &NotificationsConfiguration,
+    result: Result<(), &anyhow::Error>,
+) {
+    let error_kind = result.err().map(ErrorKind::of);
+    let webhooks = config
+        .webhooks
+        .iter()
+        .filter(|webhook| is_subscribed(webhook, error_kind));
+    let mut variables = None;
+    for webhook in webhooks {
+        let variables = variables.get_or_insert_with(|| webhook_variables(result));
+        if let Err(e) = call_webhook(webhook, variables).await {
+            tracing::warn!("Failed to notify '{}': {:#}", webhook.url, e);
+        }
+    }
+}
+
+/// Whether `webhook` should be called for the outcome of an update, which
+/// failed if `error_kind` is set.
+fn is_subscribed(webhook: &WebhookConfiguration, error_kind: Option<ErrorKind>) -> bool {
+    match error_kind {
+        None => webhook.events.contains(&WebhookEvent::Success),
+        Some(error_kind) => {
+            webhook.events.contains(&WebhookEvent::Failure)
+                || WebhookEvent::of_error_kind(error_kind)
+                    .is_some_and(|event| webhook.events.contains(&event))
+        }
+    }
+}
+
+/// Values of the placeholders of webhooks' templates.
+fn webhook_variables(result: Result<(), &anyhow::Error>) -> HashMap<String, String> {
+    let (event, error, error_kind) = match result {
+        Ok(()) => ("success", String::new(), ""),
+        Err(e) => ("failure", format!("{:#}", e), ErrorKind::of(e).name()),
+    };
+    vec![
+        ("event", event.to_string()),
+        ("error", error),
+        ("error_kind", error_kind.to_string()),
+        ("machine", machine_name()),
+        ("profile", get_profile().unwrap_or_default().to_string()),
+        ("timestamp", chrono::Utc::now().to_rfc3339()),
+    ]
+    .into_iter()
+    .map(|(name, value)| (name.to_string(), value))
+    .collect()
+}
+
+/// Returns the name of the computer, which tells machines of a fleet apart.
+fn machine_name() -> String {
+    ["COMPUTERNAME", "HOSTNAME"]
+        .iter()
+        .find_map(|name| std::env::var(name).ok())
+        .or_else(|| {
+            std::fs::read_to_string("/etc/hostname")
+                .ok()
+                .map(|hostname| hostname.trim().to_string())
+        })
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Builds the body of a webhook's request.
+///
+/// Values are escaped so that they can be placed in JSON strings.
+fn webhook_body(
+    webhook: &WebhookConfiguration,
+    variables: &HashMap<String, String>,
+) -> Result<String> {
+    let template = match &webhook.template {
+        Some(template) => template,
+        None => return Ok(serde_json::to_string(variables)?),
+    };
+    let escaped_variables = variables
+        .iter()
+        .map(|(name, value)| {
+            let quoted_value = serde_json::Value::String(value.clone()).to_string();
+            (
+                name.clone(),
+                quoted_value[1..quoted_value.len() - 1].to_string(),
+            )
+        })
+        .collect();
+    expand_template(template, &escaped_variables)
+}
+
+async fn call_webhook(
+    webhook: &WebhookConfiguration,
+    variables: &HashMap<String, String>,
+) -> Result<()> {
+    let body = webhook_body(webhook, variables).context("Invalid webhook template")?;
+    let resp = reqwest::Client::builder()
+        .timeout(WEBHOOK_TIMEOUT)
+        .build()?
+        .post(&webhook.url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(body)
+        .send()
+        .await?;
+    if !resp.status().is_success() {
+        return Err(anyhow!("Webhook answered {}", resp.status()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::patcher::error::PatcherError;
+    use httptest::{matchers::*, responders::*, Expectation, Server};
+
+    fn webhook(
+        url: String,
+        template: Option<&str>,
+        events: Vec<WebhookEvent>,
+    ) -> WebhookConfiguration {
+        WebhookConfiguration {
+            url,
+            template: template.map(str::to_string),
+            events,
+        }
+    }
+
+    #[test]
+    fn test_is_subscribed() {
+        let url = "http://127.0.0.1/hook".to_string();
+        let all = webhook(url.clone(), None, default_webhook_events());
+        assert!(is_subscribed(&all, None));
+        assert!(is_subscribed(&all, Some(ErrorKind::Other)));
+
+        let integrity = webhook(url, None, vec![WebhookEvent::Integrity]);
+        assert!(!is_subscribed(&integrity, None));
+        assert!(!is_subscribed(&integrity, Some(ErrorKind::Network)));
+        assert!(is_subscribed(&integrity, Some(ErrorKind::Integrity)));
+    }
+
+    #[tokio::test]
+    async fn test_notify_update_result() {
+        let server = Server::run();
+        server.expect(
+            Expectation::matching(all_of![
+                request::method_path("POST", "/failure"),
+                request::body(json_decoded(eq(serde_json::json!({
+                    "text": "Update failed: Archive \"1.thor\" is corrupt",
+                })))),
+            ])
+            .respond_with(status_code(204)),
+        );
+        server.expect(
+            Expectation::matching(all_of![
+                request::method_path("POST", "/success"),
+                request::body(json_decoded(|body: &serde_json::Value| {
+                    body["event"] == "success" && body["machine"].is_string()
+                })),
+            ])
+            .respond_with(status_code(200)),
+        );
+        let config = NotificationsConfiguration {
+            webhooks: vec![
+                webhook(
+                    server.url_str("/failure"),
+                    Some(r#"{"text": "Update failed: {error}"}"#),
+                    vec![WebhookEvent::Integrity],
+                ),
+                webhook(
+                    server.url_str("/success"),
+                    None,
+                    vec![WebhookEvent::Success],
+                ),
+            ],
+        };
+
+        let error = anyhow!(PatcherError::new(
+            ErrorKind::Integrity,
+            "Archive \"1.thor\" is corrupt"
+        ));
+        notify_update_result(&config, Err(&error)).await;
+        notify_update_result(&config, Ok(())).await;
+        // Failures aren't sent to webhooks subscribed to other errors
+        let error = anyhow!(PatcherError::new(ErrorKind::Network, "Timed out"));
+        notify_update_result(&config, Err(&error)).await;
+    }
+}
@@ -0,0 +1,273 @@
+use std::sync::{Arc, RwLock};
+
+use anyhow::{anyhow, Context, Result};
+use gruf::thor::{self, ThorPatchInfo, ThorPatchList};
+use xml::reader::{EventReader, XmlEvent};
+
+use super::config::STABLE_CHANNEL;
+use super::publish::PatchManifest;
+
+// Formats registered by launchers embedding the patcher, tried before the
+// built-in ones
+static CUSTOM_SOURCES: RwLock<Vec<Arc<dyn PatchListSource>>> = RwLock::new(Vec::new());
+
+/// Format of the patch lists served by patch servers.
+pub trait PatchListSource: Send + Sync {
+    /// Name which selects the format in the configuration (e.g. "thor").
+    fn name(&self) -> &str;
+
+    /// Tells whether `content` looks like a list in this format, for patch
+    /// servers whose format isn't configured.
+    fn sniff(&self, content: &str) -> bool;
+
+    /// Returns the index and file name of the patches listed in `content`
+    /// for clients on the release `channel`.
+    fn parse(&self, content: &str, channel: &str) -> Result<Vec<(usize, String)>>;
+}
+
+/// Classic 'plist.txt', with one "<index> <file name>" line per patch.
+struct ThorPlistSource;
+
+impl PatchListSource for ThorPlistSource {
+    fn name(&self) -> &str {
+        "thor"
+    }
+
+    fn sniff(&self, content: &str) -> bool {
+        !thor::patch_list_from_string(content).is_empty()
+    }
+
+    fn parse(&self, content: &str, _channel: &str) -> Result<Vec<(usize, String)>> {
+        Ok(thor::patch_list_from_string(content)
+            .into_iter()
+            .map(|patch_info| (patch_info.index, patch_info.file_name))
+            .collect())
+    }
+}
+
+/// Patch manifest ('patches.json'), served in place of 'plist.txt'.
+struct ManifestSource;
+
+impl PatchListSource for ManifestSource {
+    fn name(&self) -> &str {
+        "json"
+    }
+
+    fn sniff(&self, content: &str) -> bool {
+        content.trim_start().starts_with('{')
+    }
+
+    fn parse(&self, content: &str, channel: &str) -> Result<Vec<(usize, String)>> {
+        let manifest: PatchManifest =
+            serde_json::from_str(content).with_context(|| "Invalid patch manifest")?;
+        Ok(patch_list_from_manifest(&manifest, channel)
+            .into_iter()
+            .map(|patch_info| (patch_info.index, patch_info.file_name))
+            .collect())
+    }
+}
+
+/// XML list used by some hosts, with one `<patch>` element per patch, e.g.
+/// `<patch index="1" file="2021-01-01data.thor"/>`.
+///
+/// The index can also be given as `id`, and the file name as `name` or as the
+/// element's text.
+struct XmlSource;
+
+impl PatchListSource for XmlSource {
+    fn name(&self) -> &str {
+        "xml"
+    }
+
+    fn sniff(&self, content: &str) -> bool {
+        content.trim_start().starts_with('<')
+    }
+
+    fn parse(&self, content: &str, _channel: &str) -> Result<Vec<(usize, String)>> {
+        let mut patches = vec![];
+        // Index and file name of the `<patch>` element being read
+        let mut current_patch: Option<(usize, Option<String>)> = None;
+        for event in EventReader::from_str(content) {
+            match event.with_context(|| "Invalid XML patch list")? {
+                XmlEvent::StartElement {
+                    name, attributes, ..
+                } if name.local_name == "patch" => {
+                    let attribute = |names: &[&str]| {
+                        attributes
+                            .iter()
+                            .find(|attribute| names.contains(&attribute.name.local_name.as_str()))
+                            .map(|attribute| attribute.value.trim().to_string())
+                    };
+                    let index = attribute(&["index", "id"])
+                        .context("Patch without an index")?
+                        .parse()
+                        .with_context(|| "Invalid patch index")?;
+                    current_patch = Some((index, attribute(&["file", "name"])));
+                }
+                XmlEvent::Characters(text) => {
+                    if let Some((_, file_name @ None)) = &mut current_patch {
+                        *file_name = Some(text.trim().to_string());
+                    }
+                }
+                XmlEvent::EndElement { name } if name.local_name == "patch" => {
+                    let (index, file_name) = current_patch.take().context("Unexpected </patch>")?;
+                    let file_name = file_name
+                        .filter(|file_name| !file_name.is_empty())
+                        .ok_or_else(|| anyhow!("Patch {} has no file name", index))?;
+                    patches.push((index, file_name));
+                }
+                _ => {}
+            }
+        }
+        patches.sort_by_key(|(index, _)| *index);
+        Ok(patches)
+    }
+}
+
+/// Adds a format of patch lists, which takes precedence over the built-in
+/// ones ("thor", "json" and "xml") as well as the ones registered before.
+pub fn register_patch_list_source(source: impl PatchListSource + 'static) {
+    CUSTOM_SOURCES
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(0, Arc::new(source));
+}
+
+fn patch_list_sources() -> Vec<Arc<dyn PatchListSource>> {
+    let custom_sources = CUSTOM_SOURCES.read().unwrap_or_else(|e| e.into_inner());
+    let builtin_sources: [Arc<dyn PatchListSource>; 3] = [
+        Arc::new(ManifestSource),
+        Arc::new(XmlSource),
+        Arc::new(ThorPlistSource),
+    ];
+    custom_sources
+        .iter()
+        .cloned()
+        .chain(builtin_sources)
+        .collect()
+}
+
+/// Parses the patch list `content`, in the format named `format` or in the
+/// one its content looks like if not set.
+///
+/// Content no format recognizes is read as a classic 'plist.txt'.
+pub(super) fn parse_patch_list(
+    content: &str,
+    format: Option<&str>,
+    channel: &str,
+) -> Result<ThorPatchList> {
+    let sources = patch_list_sources();
+    let source = match format {
+        Some(format) => sources
+            .iter()
+            .find(|source| source.name().eq_ignore_ascii_case(format))
+            .ok_or_else(|| anyhow!("Unknown patch list format '{}'", format))?,
+        None => sources
+            .iter()
+            .find(|source| source.sniff(content))
+            .unwrap_or(&sources[sources.len() - 1]),
+    };
+    tracing::debug!("Parsing the patch list as '{}'", source.name());
+    let mut patch_list: ThorPatchList = source
+        .parse(content, channel)?
+        .into_iter()
+        .map(|(index, file_name)| ThorPatchInfo { index, file_name })
+        .collect();
+    patch_list.sort_by_key(|patch_info| patch_info.index);
+    Ok(patch_list)
+}
+
+/// Lists the patches of a manifest offered on `channel`, sorted by index
+/// like in 'plist.txt'.
+///
+/// Stable patches are offered on every channel, so that testers don't miss
+/// them.
+pub(super) fn patch_list_from_manifest(manifest: &PatchManifest, channel: &str) -> ThorPatchList {
+    let mut patch_list: ThorPatchList = manifest
+        .patches
+        .iter()
+        .filter(|entry| match entry.channel.as_deref() {
+            None | Some(STABLE_CHANNEL) => true,
+            Some(patch_channel) => patch_channel == channel,
+        })
+        .map(|entry| ThorPatchInfo {
+            index: entry.index,
+            file_name: entry.file_name.clone(),
+        })
+        .collect();
+    patch_list.sort_by_key(|patch_info| patch_info.index);
+    patch_list
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file_names(patch_list: ThorPatchList) -> Vec<String> {
+        patch_list
+            .into_iter()
+            .map(|patch_info| patch_info.file_name)
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_patch_list() {
+        let plist = "2 2.thor\n1 1.thor\n";
+        let manifest = r#"{"patches":[
+            {"index":1,"file_name":"1.thor","size":3,"sha256":"ab","target_grf":null},
+            {"index":2,"file_name":"2.thor","size":3,"sha256":"cd","target_grf":null,"channel":"beta"}
+        ]}"#;
+        let xml = r#"<?xml version="1.0"?>
+            <patches>
+                <patch index="2" file="2.thor"/>
+                <patch id="1">1.thor</patch>
+            </patches>"#;
+        for content in [plist, xml] {
+            let patch_list = parse_patch_list(content, None, STABLE_CHANNEL).unwrap();
+            assert_eq!(file_names(patch_list), ["1.thor", "2.thor"]);
+        }
+        let patch_list = parse_patch_list(manifest, None, STABLE_CHANNEL).unwrap();
+        assert_eq!(file_names(patch_list), ["1.thor"]);
+        let patch_list = parse_patch_list(manifest, Some("JSON"), "beta").unwrap();
+        assert_eq!(file_names(patch_list), ["1.thor", "2.thor"]);
+
+        // Empty lists are classic ones
+        assert!(parse_patch_list("", None, STABLE_CHANNEL)
+            .unwrap()
+            .is_empty());
+        assert!(parse_patch_list(plist, Some("xml"), STABLE_CHANNEL).is_err());
+        assert!(parse_patch_list("<patch id=\"1\"/>", None, STABLE_CHANNEL).is_err());
+        assert!(parse_patch_list(plist, Some("yaml"), STABLE_CHANNEL).is_err());
+    }
+
+    struct CsvSource;
+
+    impl PatchListSource for CsvSource {
+        fn name(&self) -> &str {
+            "csv"
+        }
+
+        fn sniff(&self, content: &str) -> bool {
+            content.contains(';')
+        }
+
+        fn parse(&self, content: &str, _channel: &str) -> Result<Vec<(usize, String)>> {
+            content
+                .lines()
+                .map(|line| {
+                    let (index, file_name) = line.split_once(';').context("Invalid line")?;
+                    Ok((index.parse()?, file_name.to_string()))
+                })
+                .collect()
+        }
+    }
+
+    #[test]
+    fn test_register_patch_list_source() {
+        register_patch_list_source(CsvSource);
+        let patch_list = parse_patch_list("3;3.thor\n1;1.thor", None, STABLE_CHANNEL).unwrap();
+        assert_eq!(file_names(patch_list), ["1.thor", "3.thor"]);
+        let patch_list = parse_patch_list("1 1.thor", Some("thor"), STABLE_CHANNEL).unwrap();
+        assert_eq!(file_names(patch_list), ["1.thor"]);
+    }
+}
@@ -0,0 +1,554 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::Instant;
+
+use anyhow::{anyhow, Context, Result};
+use gruf::thor::{ThorPatchInfo, ThorPatchList};
+use tokio::sync::mpsc::UnboundedReceiver;
+use url::Url;
+
+use super::cancellation::{wait_for_cancellation, InterruptibleFnError, InterruptibleFnResult};
+use super::config::{PatchServerInfo, WebConfiguration};
+use super::core::log_final_url;
+use super::error::{ErrorKind, PatcherError};
+use super::patch_list::{parse_patch_list, patch_list_from_manifest};
+use super::publish::{PatchManifest, PatchManifestEntry, MANIFEST_FILE_NAME};
+use super::state::{
+    retrieve_patcher_state, update_patcher_state, CachedPatchList, ServerBenchmark,
+};
+use super::{PatcherCommand, PatcherConfiguration};
+use crate::status::PatchServerHealth;
+
+/// Patches offered by a patch server.
+pub(super) struct PatchListing {
+    pub patch_list: ThorPatchList,
+    pub patch_url: Url, // URL of the directory containing the patches
+    pub manifest: Option<PatchManifest>, // Unless the server doesn't publish one
+}
+
+/// Iterates through the patch servers of `web_config` and returns the first
+/// available server's info. The preferred server is checked first if present.
+///
+/// With `probe_cache_ttl` set, the patch list of the last working server is
+/// reused for that long, and the servers which answered recently are probed
+/// first, fastest first, while those which didn't are probed last.
+pub(super) async fn find_available_patch_server(
+    client: &reqwest::Client,
+    web_config: &WebConfiguration,
+    channel: &str,
+    patching_thread_rx: &mut UnboundedReceiver<PatcherCommand>,
+) -> InterruptibleFnResult<PatchListing> {
+    let server_list = web_config.patch_servers.as_slice();
+    let preferred_server_name = web_config.preferred_patch_server();
+    if let Some(preferred_server_name) = preferred_server_name {
+        if !server_list.iter().any(|s| &s.name == preferred_server_name) {
+            tracing::warn!(
+                "'{}' isn't in the list of patch servers",
+                preferred_server_name
+            );
+        }
+    }
+    // Probes older than this are ignored
+    let recent_since = web_config
+        .probe_cache_ttl
+        .map(|ttl| chrono::Utc::now().timestamp() - ttl as i64);
+    let state = match recent_since {
+        Some(_) => retrieve_patcher_state().unwrap_or_else(|e| {
+            tracing::warn!("Failed to read the patcher state: {:#}", e);
+            Default::default()
+        }),
+        None => Default::default(),
+    };
+    if let Some(recent_since) = recent_since {
+        if let Some(cached_patch_list) = state.cached_patch_list.as_ref() {
+            let res = reuse_cached_patch_list(
+                cached_patch_list,
+                server_list,
+                preferred_server_name,
+                channel,
+                recent_since,
+            );
+            if let Some((patch_list, patch_url)) = res {
+                tracing::info!("Reusing the patch list of '{}'", cached_patch_list.server);
+                let manifest = fetch_patch_manifest(client, &patch_url).await;
+                return Ok(PatchListing {
+                    patch_list,
+                    patch_url,
+                    manifest,
+                });
+            }
+        }
+    }
+
+    let servers = order_patch_servers(
+        server_list,
+        preferred_server_name,
+        &state.server_benchmarks,
+        recent_since,
+    );
+    let mut benchmarks = vec![];
+    let mut available_server = None;
+    for server in servers {
+        // Unresponsive servers can take a while to time out, don't make
+        // users wait for them to cancel
+        let (res, benchmark) = tokio::select! {
+            cancel_res = wait_for_cancellation(patching_thread_rx) => return Err(cancel_res),
+            res = benchmark_patch_server(client, server, channel) => res,
+        };
+        benchmarks.push((server.name.clone(), benchmark));
+        match res {
+            Ok(listing) => {
+                available_server = Some((server, listing));
+                break;
+            }
+            Err(_) => tracing::warn!("'{}' is unavailable", server.name),
+        }
+    }
+    // Probes are only reused with a TTL
+    if recent_since.is_some() {
+        let cached_patch_list = available_server
+            .as_ref()
+            .map(|(server, listing)| cached_patch_list(&server.name, channel, &listing.patch_list));
+        if let Err(e) = update_patcher_state(|state| {
+            state.server_benchmarks.extend(benchmarks);
+            if let Some(cached_patch_list) = cached_patch_list {
+                state.cached_patch_list = Some(cached_patch_list);
+            }
+        }) {
+            tracing::warn!("Failed to update patcher state: {:#}", e);
+        }
+    }
+
+    match available_server {
+        Some((_, listing)) => Ok(listing),
+        None => Err(InterruptibleFnError::Err(anyhow!(PatcherError::new(
+            ErrorKind::Network,
+            "None of the patch servers are available at the moment",
+        )))),
+    }
+}
+
+/// Returns the patch servers in the order they should be probed in.
+///
+/// Servers are probed in the order of the configuration, unless
+/// `recent_since` is set: the servers which have answered to a probe made
+/// since then come first, fastest first, and those which haven't come last.
+/// The preferred server always comes first.
+fn order_patch_servers<'a>(
+    server_list: &'a [PatchServerInfo],
+    preferred_server_name: &Option<String>,
+    server_benchmarks: &BTreeMap<String, ServerBenchmark>,
+    recent_since: Option<i64>,
+) -> Vec<&'a PatchServerInfo> {
+    let mut servers: Vec<&PatchServerInfo> = server_list.iter().collect();
+    if let Some(recent_since) = recent_since {
+        // Sorting is stable, other servers stay in the configuration's order
+        servers.sort_by_key(|server| {
+            match server_benchmarks
+                .get(&server.name)
+                .filter(|benchmark| benchmark.probed_at >= recent_since)
+            {
+                Some(benchmark) if benchmark.available => (0, benchmark.latency_ms),
+                Some(_) => (2, 0),
+                None => (1, 0),
+            }
+        });
+    }
+    if let Some(preferred_server_name) = preferred_server_name {
+        if let Some(position) = servers
+            .iter()
+            .position(|s| &s.name == preferred_server_name)
+        {
+            let preferred_server = servers.remove(position);
+            servers.insert(0, preferred_server);
+        }
+    }
+    servers
+}
+
+/// Returns the cached patch list, along with the URL to download the patches
+/// from, unless it's outdated or another server should be used.
+fn reuse_cached_patch_list(
+    cached_patch_list: &CachedPatchList,
+    server_list: &[PatchServerInfo],
+    preferred_server_name: &Option<String>,
+    channel: &str,
+    recent_since: i64,
+) -> Option<(ThorPatchList, Url)> {
+    if cached_patch_list.fetched_at < recent_since || cached_patch_list.channel != channel {
+        return None;
+    }
+    // The preferred server has to be probed, it may be back up
+    if let Some(preferred_server_name) = preferred_server_name {
+        if preferred_server_name != &cached_patch_list.server {
+            return None;
+        }
+    }
+    let server = server_list
+        .iter()
+        .find(|server| server.name == cached_patch_list.server)?;
+    let patch_url = Url::parse(&server.patch_url).ok()?;
+    let patch_list = cached_patch_list
+        .patches
+        .iter()
+        .map(|(index, file_name)| ThorPatchInfo {
+            index: *index,
+            file_name: file_name.clone(),
+        })
+        .collect();
+    Some((patch_list, patch_url))
+}
+
+pub(super) fn cached_patch_list(
+    server_name: &str,
+    channel: &str,
+    patch_list: &ThorPatchList,
+) -> CachedPatchList {
+    CachedPatchList {
+        server: server_name.to_string(),
+        channel: channel.to_string(),
+        patches: patch_list
+            .iter()
+            .map(|patch_info| (patch_info.index, patch_info.file_name.clone()))
+            .collect(),
+        fetched_at: chrono::Utc::now().timestamp(),
+    }
+}
+
+/// Probes all of the patch servers at once, for users to see which ones are
+/// down.
+pub(super) async fn probe_patch_servers(
+    client: &reqwest::Client,
+    config: &PatcherConfiguration,
+) -> Vec<PatchServerHealth> {
+    let probes = config.web.patch_servers.iter().map(|server| async move {
+        let (res, benchmark) =
+            benchmark_patch_server(client, server, config.patching.channel()).await;
+        let health = PatchServerHealth {
+            name: server.name.clone(),
+            latency_ms: benchmark.latency_ms,
+            error: res.err().map(|e| format!("{:#}", e)),
+        };
+        (health, benchmark)
+    });
+    let (server_health, benchmarks): (Vec<_>, Vec<_>) =
+        futures::future::join_all(probes).await.into_iter().unzip();
+    // Probes are only reused with a TTL
+    if config.web.probe_cache_ttl.is_some() {
+        let benchmarks = server_health
+            .iter()
+            .map(|health| health.name.clone())
+            .zip(benchmarks);
+        if let Err(e) = update_patcher_state(|state| state.server_benchmarks.extend(benchmarks)) {
+            tracing::warn!("Failed to update patcher state: {:#}", e);
+        }
+    }
+    server_health
+}
+
+/// Probes a patch server and measures how long it took to answer.
+async fn benchmark_patch_server(
+    client: &reqwest::Client,
+    server_info: &PatchServerInfo,
+    channel: &str,
+) -> (Result<PatchListing>, ServerBenchmark) {
+    let started_at = Instant::now();
+    let res = probe_patch_server(client, server_info, channel).await;
+    let benchmark = ServerBenchmark {
+        available: res.is_ok(),
+        latency_ms: started_at.elapsed().as_millis() as u64,
+        probed_at: chrono::Utc::now().timestamp(),
+    };
+    (res, benchmark)
+}
+
+/// Checks whether a patch server is up or not.
+/// Returns the list of patches served by the server on `channel` as well as
+/// the URL to download them from and the manifest describing them.
+#[tracing::instrument(skip_all, fields(server = %server_info.name))]
+pub(super) async fn probe_patch_server(
+    client: &reqwest::Client,
+    server_info: &PatchServerInfo,
+    channel: &str,
+) -> Result<PatchListing> {
+    // Parse URLs
+    let patch_list_url = Url::parse(server_info.plist_url.as_str())
+        .with_context(|| "Failed to parse 'plist_url'")?;
+    let patch_url = Url::parse(server_info.patch_url.as_str())
+        .with_context(|| "Failed to parse 'patch_url'")?;
+
+    // Prefer the manifest, which describes patches better than 'plist.txt'
+    let manifest = fetch_patch_manifest(client, &patch_url).await;
+    let patch_list = match &manifest {
+        Some(manifest) => patch_list_from_manifest(manifest, channel),
+        None => fetch_patch_list(
+            client,
+            patch_list_url,
+            server_info.plist_format.as_deref(),
+            channel,
+        )
+        .await
+        .with_context(|| "Failed to retrieve the patch list")?,
+    };
+
+    // Ensure that the server serves the patches (check the first patch of the list)
+    if let Some(patch_info) = patch_list.first() {
+        let patch_resp = client
+            .head(patch_url.join(patch_info.file_name.as_str())?)
+            .send()
+            .await
+            .with_context(|| "Failed to HEAD URL")?;
+        // Return on error
+        patch_resp.error_for_status()?;
+    }
+
+    Ok(PatchListing {
+        patch_list,
+        patch_url,
+        manifest,
+    })
+}
+
+/// Downloads and parses a 'plist.txt' file located as the URL contained in the
+/// `patch_list_url` argument.
+///
+/// The list is read in the format named `format`, or in the one its content
+/// looks like if not set. Returns a vector of `ThorPatchInfo` in case of
+/// success.
+pub(super) async fn fetch_patch_list(
+    client: &reqwest::Client,
+    patch_list_url: Url,
+    format: Option<&str>,
+    channel: &str,
+) -> Result<ThorPatchList> {
+    let resp = client
+        .get(patch_list_url.clone())
+        .send()
+        .await
+        .with_context(|| "Failed to GET URL")?;
+    log_final_url(&patch_list_url, &resp);
+    if !resp.status().is_success() {
+        return Err(anyhow!("Patch list file not found on the remote server"));
+    }
+    let patch_index_content = resp.text().await.with_context(|| "Invalid responde body")?;
+    tracing::info!("Parsing patch index...");
+
+    parse_patch_list(&patch_index_content, format, channel)
+}
+
+/// Returns the entries of `manifest`, indexed by file name.
+pub(super) fn manifest_entries(
+    manifest: Option<&PatchManifest>,
+) -> HashMap<String, PatchManifestEntry> {
+    manifest
+        .iter()
+        .flat_map(|manifest| manifest.patches.iter())
+        .map(|entry| (entry.file_name.clone(), entry.clone()))
+        .collect()
+}
+
+/// Retrieves the manifest published next to the patches (see `rpatchur
+/// publish`).
+///
+/// Returns `None` if the patch server doesn't publish one.
+pub(super) async fn fetch_patch_manifest(
+    client: &reqwest::Client,
+    patch_url: &Url,
+) -> Option<PatchManifest> {
+    match fetch_patch_manifest_file(client, patch_url).await {
+        Ok(manifest) => Some(manifest),
+        Err(e) => {
+            tracing::debug!("No patch manifest available: {:#}", e);
+            None
+        }
+    }
+}
+
+/// Downloads and parses the manifest published next to the patches.
+async fn fetch_patch_manifest_file(
+    client: &reqwest::Client,
+    patch_url: &Url,
+) -> Result<PatchManifest> {
+    let manifest_url = patch_url.join(MANIFEST_FILE_NAME)?;
+    let resp = client
+        .get(manifest_url.clone())
+        .send()
+        .await?
+        .error_for_status()?;
+    log_final_url(&manifest_url, &resp);
+    Ok(serde_json::from_str(&resp.text().await?)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::config::STABLE_CHANNEL;
+    use super::*;
+    use httptest::{matchers::*, responders::*, Expectation, Server};
+
+    #[tokio::test]
+    async fn test_fetch_patch_manifest() {
+        let server = Server::run();
+        server.expect(
+            Expectation::matching(request::method_path("GET", "/patches/patches.json"))
+                .respond_with(status_code(200).body(
+                    r#"{"patches":[{"index":1,"file_name":"1.thor","size":3,"sha256":"ab","target_grf":null}]}"#,
+                )),
+        );
+        server.expect(
+            Expectation::matching(request::method_path("GET", "/missing/patches.json"))
+                .respond_with(status_code(404)),
+        );
+        let client = reqwest::Client::new();
+
+        let patch_url = Url::parse(&server.url_str("/patches/")).unwrap();
+        let manifest = fetch_patch_manifest(&client, &patch_url).await;
+        let patch_manifest = manifest_entries(manifest.as_ref());
+        assert_eq!(patch_manifest.len(), 1);
+        assert_eq!(patch_manifest["1.thor"].size, 3);
+        // Patches are checked by opening them then
+        let patch_url = Url::parse(&server.url_str("/missing/")).unwrap();
+        assert!(fetch_patch_manifest(&client, &patch_url).await.is_none());
+    }
+
+    #[test]
+    fn test_order_patch_servers() {
+        let server_list: Vec<PatchServerInfo> = ["a", "b", "c", "d"]
+            .iter()
+            .map(|name| PatchServerInfo {
+                name: name.to_string(),
+                plist_url: format!("http://{}/plist.txt", name),
+                patch_url: format!("http://{}/data/", name),
+                plist_format: None,
+            })
+            .collect();
+        let benchmark = |available, latency_ms, probed_at| ServerBenchmark {
+            available,
+            latency_ms,
+            probed_at,
+        };
+        let server_benchmarks: BTreeMap<String, ServerBenchmark> = vec![
+            ("a", benchmark(false, 10, 100)),
+            ("b", benchmark(true, 300, 100)),
+            ("c", benchmark(true, 50, 100)),
+            ("d", benchmark(true, 10, 10)), // Outdated
+        ]
+        .into_iter()
+        .map(|(name, benchmark)| (name.to_string(), benchmark))
+        .collect();
+        let names = |servers: Vec<&PatchServerInfo>| -> Vec<String> {
+            servers.iter().map(|server| server.name.clone()).collect()
+        };
+
+        // Without a TTL, probes are ignored
+        let servers = order_patch_servers(&server_list, &None, &server_benchmarks, None);
+        assert_eq!(names(servers), ["a", "b", "c", "d"]);
+        let servers = order_patch_servers(&server_list, &None, &server_benchmarks, Some(50));
+        assert_eq!(names(servers), ["c", "b", "d", "a"]);
+        let preferred_server_name = Some("a".to_string());
+        let servers = order_patch_servers(
+            &server_list,
+            &preferred_server_name,
+            &server_benchmarks,
+            Some(50),
+        );
+        assert_eq!(names(servers), ["a", "c", "b", "d"]);
+    }
+
+    #[test]
+    fn test_reuse_cached_patch_list() {
+        let server_list = vec![PatchServerInfo {
+            name: "main".to_string(),
+            plist_url: "http://127.0.0.1/plist.txt".to_string(),
+            patch_url: "http://127.0.0.1/data/".to_string(),
+            plist_format: None,
+        }];
+        let cached_patch_list = CachedPatchList {
+            server: "main".to_string(),
+            channel: STABLE_CHANNEL.to_string(),
+            patches: vec![(1, "1.thor".to_string()), (2, "2.thor".to_string())],
+            fetched_at: 100,
+        };
+
+        let (patch_list, patch_url) =
+            reuse_cached_patch_list(&cached_patch_list, &server_list, &None, STABLE_CHANNEL, 50)
+                .unwrap();
+        let file_names: Vec<&str> = patch_list.iter().map(|p| p.file_name.as_str()).collect();
+        assert_eq!(file_names, ["1.thor", "2.thor"]);
+        assert_eq!(patch_url.as_str(), "http://127.0.0.1/data/");
+        // Outdated
+        assert!(reuse_cached_patch_list(
+            &cached_patch_list,
+            &server_list,
+            &None,
+            STABLE_CHANNEL,
+            150
+        )
+        .is_none());
+        // Fetched for another channel
+        assert!(
+            reuse_cached_patch_list(&cached_patch_list, &server_list, &None, "beta", 50).is_none()
+        );
+        // Another server is preferred
+        let preferred_server_name = Some("mirror".to_string());
+        assert!(reuse_cached_patch_list(
+            &cached_patch_list,
+            &server_list,
+            &preferred_server_name,
+            STABLE_CHANNEL,
+            50
+        )
+        .is_none());
+        // The server has been removed
+        assert!(
+            reuse_cached_patch_list(&cached_patch_list, &[], &None, STABLE_CHANNEL, 50).is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_probe_patch_server_prefers_manifest() {
+        let server = Server::run();
+        server.expect(
+            Expectation::matching(request::method_path("GET", "/patches/patches.json"))
+                .times(2)
+                .respond_with(status_code(200).body(
+                    r#"{"patches":[
+                        {"index":2,"file_name":"2.thor","size":3,"sha256":"ab","target_grf":null,"channel":"beta"},
+                        {"index":1,"file_name":"1.thor","size":3,"sha256":"cd","target_grf":"data.grf"}
+                    ]}"#,
+                )),
+        );
+        server.expect(
+            Expectation::matching(request::method_path("HEAD", "/patches/1.thor"))
+                .times(2)
+                .respond_with(status_code(200)),
+        );
+        let server_info = PatchServerInfo {
+            name: "main".to_string(),
+            plist_url: server.url_str("/plist.txt"),
+            patch_url: server.url_str("/patches/"),
+            plist_format: None,
+        };
+        let client = reqwest::Client::new();
+
+        // 'plist.txt' isn't requested
+        let listing = probe_patch_server(&client, &server_info, "beta")
+            .await
+            .unwrap();
+        assert!(listing.manifest.is_some());
+        let file_names: Vec<&str> = listing
+            .patch_list
+            .iter()
+            .map(|p| p.file_name.as_str())
+            .collect();
+        assert_eq!(file_names, ["1.thor", "2.thor"]);
+        // Beta patches aren't offered on the stable channel
+        let listing = probe_patch_server(&client, &server_info, "stable")
+            .await
+            .unwrap();
+        let file_names: Vec<&str> = listing
+            .patch_list
+            .iter()
+            .map(|p| p.file_name.as_str())
+            .collect();
+        assert_eq!(file_names, ["1.thor"]);
+    }
+}
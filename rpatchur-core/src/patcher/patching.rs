@@ -0,0 +1,1211 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufWriter, Read, Seek};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::{anyhow, Result};
+use gruf::grf::{GrfArchive, GrfArchiveBuilder};
+use gruf::thor::{ThorArchive, ThorArchiveBuilder, ThorFileEntry};
+
+use super::error::{ErrorKind, PatcherError};
+use super::zip::ZipArchive;
+
+/// Indicates the method that should be used when patching GRF files.
+pub enum GrfPatchingMethod {
+    OutOfPlace { buffer_size: usize }, // Size of the writes made to the new GRF
+    InPlace,
+}
+
+/// Options used when patching files located in the game client's directory.
+pub struct DiskPatchingOptions {
+    pub clear_read_only: bool, // Make read-only files writable before replacing them
+    pub strip_motw: bool,      // Remove the "Mark of the Web" of extracted files
+}
+
+/// Indicates the type of archive a "file" comes from.
+enum MergeEntrySource {
+    GrfArchive,
+    ThorArchive,
+}
+
+struct MergeEntry {
+    pub source: MergeEntrySource,
+}
+
+/// Change made to one of the game's files by a patch.
+#[derive(Debug, PartialEq)]
+pub enum FileChange {
+    Written {
+        relative_path: String,
+        size: u64,
+        crc32: u32, // Checksum of the uncompressed content
+    },
+    Removed {
+        relative_path: String,
+    },
+}
+
+impl FileChange {
+    fn written(relative_path: String, content: &[u8]) -> Self {
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(content);
+        FileChange::Written {
+            relative_path,
+            size: content.len() as u64,
+            crc32: hasher.finalize(),
+        }
+    }
+}
+
+/// Patches a GRF file with a THOR archive/patch.
+///
+/// Patching stops between two entries once `interrupted` is set. GRFs which
+/// another program (e.g. the game client) holds open fail with a
+/// `GameClientRunning` error, so that patching can be retried once they're
+/// closed.
+///
+/// Returns the changes made to the GRF's entries.
+pub fn apply_patch_to_grf<R: Read + Seek>(
+    patching_method: GrfPatchingMethod,
+    create_if_needed: bool,
+    grf_file_path: impl AsRef<Path>,
+    thor_archive: &mut ThorArchive<R>,
+    interrupted: &AtomicBool,
+) -> Result<Vec<FileChange>> {
+    if !grf_file_path.as_ref().exists() && create_if_needed {
+        // Create a new GRF file if needed
+        let new_grf = fs::File::create(&grf_file_path)?;
+        GrfArchiveBuilder::create(new_grf, 2, 0)?;
+    }
+    let patching_result = match patching_method {
+        GrfPatchingMethod::InPlace => {
+            apply_patch_to_grf_ip(&grf_file_path, thor_archive, interrupted)
+        }
+        GrfPatchingMethod::OutOfPlace { buffer_size } => {
+            apply_patch_to_grf_oop(&grf_file_path, thor_archive, buffer_size, interrupted)
+        }
+    };
+    match patching_result {
+        Err(e) if is_sharing_violation(&e) => {
+            return Err(anyhow!(PatcherError::new(
+                ErrorKind::GameClientRunning,
+                format!(
+                    "'{}' is used by another program: {:#}",
+                    grf_file_path.as_ref().display(),
+                    e
+                ),
+            )))
+        }
+        res => res?,
+    }
+    grf_file_changes(thor_archive)
+}
+
+/// Lists the changes made to a GRF's entries by a THOR archive.
+///
+/// Entries are imported compressed, so they have to be decompressed to be
+/// hashed.
+fn grf_file_changes<R: Read + Seek>(thor_archive: &mut ThorArchive<R>) -> Result<Vec<FileChange>> {
+    let thor_entries: Vec<ThorFileEntry> = thor_archive
+        .get_entries()
+        .filter(|e| !e.is_internal())
+        .cloned()
+        .collect();
+    let mut file_changes = Vec::with_capacity(thor_entries.len());
+    for entry in thor_entries {
+        if entry.is_removed {
+            file_changes.push(FileChange::Removed {
+                relative_path: entry.relative_path,
+            });
+        } else {
+            let content = thor_archive.read_file_content(&entry.relative_path)?;
+            file_changes.push(FileChange::written(entry.relative_path, &content));
+        }
+    }
+    Ok(file_changes)
+}
+
+/// Patches a GRF in an in-place manner.
+///
+/// This is faster but produces output of bigger size and can corrupt file in
+/// case of error.
+fn apply_patch_to_grf_ip<R: Read + Seek>(
+    grf_file_path: impl AsRef<Path>,
+    thor_archive: &mut ThorArchive<R>,
+    interrupted: &AtomicBool,
+) -> Result<()> {
+    let mut builder = GrfArchiveBuilder::open(grf_file_path)?;
+    let mut thor_entries: Vec<ThorFileEntry> = thor_archive
+        .get_entries()
+        .filter(|e| !e.is_internal())
+        .cloned()
+        .collect();
+    thor_entries.sort_unstable_by_key(|a| a.offset);
+    for entry in thor_entries {
+        check_interrupted(interrupted)?;
+        if entry.is_removed {
+            let _ = builder.remove_file(&entry.relative_path);
+        } else {
+            builder.import_raw_entry_from_thor(thor_archive, entry.relative_path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Patches a GRF in an out-of-place manner.
+///
+/// This is safer and produces output of smaller size but slower. The original
+/// GRF is restored if patching fails or is interrupted.
+fn apply_patch_to_grf_oop<R: Read + Seek>(
+    grf_file_path: impl AsRef<Path>,
+    thor_archive: &mut ThorArchive<R>,
+    buffer_size: usize,
+    interrupted: &AtomicBool,
+) -> Result<()> {
+    // Rename file to back it up
+    let mut backup_file_path = grf_file_path.as_ref().to_path_buf();
+    backup_file_path.set_extension("grf.bak");
+    fs::rename(grf_file_path.as_ref(), &backup_file_path)?;
+
+    // Prepare file entries that'll be used to make the patched GRF
+    let mut merge_entries: HashMap<String, MergeEntry> = HashMap::new();
+    // Add files from the original archive while discarding files remove in the patch
+    let mut grf_archive = GrfArchive::open(&backup_file_path)?;
+    for entry in grf_archive.get_entries() {
+        if let Some(e) = thor_archive.get_file_entry(&entry.relative_path) {
+            if e.is_removed {
+                continue;
+            }
+        }
+        merge_entries.insert(
+            entry.relative_path.clone(),
+            MergeEntry {
+                source: MergeEntrySource::GrfArchive,
+            },
+        );
+    }
+    // Add files from the patch
+    for entry in thor_archive.get_entries() {
+        if entry.is_removed || entry.is_internal() {
+            continue;
+        }
+        merge_entries.insert(
+            entry.relative_path.clone(),
+            MergeEntry {
+                source: MergeEntrySource::ThorArchive,
+            },
+        );
+    }
+
+    let build_result = (|| -> Result<()> {
+        let grf_file = BufWriter::with_capacity(buffer_size, fs::File::create(&grf_file_path)?);
+        let mut builder = GrfArchiveBuilder::create(grf_file, 2, 0)?;
+        for (relative_path, entry) in merge_entries {
+            check_interrupted(interrupted)?;
+            match entry.source {
+                MergeEntrySource::GrfArchive => {
+                    builder.import_raw_entry_from_grf(&mut grf_archive, relative_path)?;
+                }
+                MergeEntrySource::ThorArchive => {
+                    builder.import_raw_entry_from_thor(thor_archive, relative_path)?;
+                }
+            }
+        }
+        Ok(builder.finish()?)
+    })();
+    drop(grf_archive);
+    if let Err(e) = build_result {
+        // Put the original GRF back in place of the partial one
+        let _ = fs::remove_file(grf_file_path.as_ref());
+        fs::rename(&backup_file_path, grf_file_path.as_ref())?;
+        return Err(e);
+    }
+    // Remove backup file once the patched GRF has been built
+    Ok(fs::remove_file(backup_file_path)?)
+}
+
+/// Patches files located in the game client's directory with a THOR
+/// archive/patch.
+///
+/// Patching stops between two files once `interrupted` is set. Read-only
+/// files are made writable before being replaced or removed if
+/// `clear_read_only` is set, and replaced files are made read-only again.
+/// Extracted files' "Mark of the Web" is removed if `strip_motw` is set.
+///
+/// Returns the changes made to the game client's files.
+pub fn apply_patch_to_disk<R: Read + Seek>(
+    root_directory: impl AsRef<Path>,
+    thor_archive: &mut ThorArchive<R>,
+    options: &DiskPatchingOptions,
+    interrupted: &AtomicBool,
+) -> Result<Vec<FileChange>> {
+    // TODO(LinkZ): Save original files before updating/removing them in order
+    // to be able to restore them in case of failure
+    // TODO(LinkZ): Make async?
+    let mut file_entries: Vec<ThorFileEntry> = thor_archive
+        .get_entries()
+        .filter(|e| !e.is_internal())
+        .cloned()
+        .collect();
+    file_entries.sort_unstable_by_key(|a| a.offset);
+    let mut file_changes = Vec::with_capacity(file_entries.len());
+    for entry in file_entries {
+        check_interrupted(interrupted)?;
+        ensure_path_is_contained(&entry.relative_path)?;
+        let dest_path = game_file_path(root_directory.as_ref(), &entry.relative_path);
+        if entry.is_removed {
+            if options.clear_read_only {
+                make_writable(&dest_path)?;
+            }
+            // Try to remove file and ignore errors (file might not exist)
+            let _ignore = fs::remove_file(dest_path);
+            file_changes.push(FileChange::Removed {
+                relative_path: entry.relative_path,
+            });
+        } else {
+            let content = thor_archive.read_file_content(&entry.relative_path)?;
+            write_game_file(&dest_path, &content, options)?;
+            file_changes.push(FileChange::written(entry.relative_path, &content));
+        }
+    }
+    Ok(file_changes)
+}
+
+/// Extracts the files of a ZIP archive/patch into the game client's
+/// directory, the same way `apply_patch_to_disk` does.
+///
+/// Unlike THOR patches, ZIP archives can't remove files.
+pub fn apply_zip_patch_to_disk<R: Read + Seek>(
+    root_directory: impl AsRef<Path>,
+    zip_archive: &mut ZipArchive<R>,
+    options: &DiskPatchingOptions,
+    interrupted: &AtomicBool,
+) -> Result<Vec<FileChange>> {
+    let file_entries: Vec<_> = zip_archive
+        .entries()
+        .iter()
+        .filter(|entry| !entry.is_dir())
+        .cloned()
+        .collect();
+    let mut file_changes = Vec::with_capacity(file_entries.len());
+    for entry in file_entries {
+        check_interrupted(interrupted)?;
+        let relative_path = zip_entry_relative_path(&entry.name);
+        ensure_path_is_contained(&relative_path)?;
+        let dest_path = game_file_path(root_directory.as_ref(), &relative_path);
+        let content = zip_archive.read_file_content(&entry)?;
+        write_game_file(&dest_path, &content, options)?;
+        file_changes.push(FileChange::written(relative_path, &content));
+    }
+    Ok(file_changes)
+}
+
+/// Converts the path of a ZIP entry into the Windows-style path THOR entries
+/// use.
+pub fn zip_entry_relative_path(zip_entry_name: &str) -> String {
+    zip_entry_name.replace('/', "\\")
+}
+
+/// Writes one of the game client's files, creating its parent directories if
+/// needed.
+fn write_game_file(dest_path: &Path, content: &[u8], options: &DiskPatchingOptions) -> Result<()> {
+    let original_permissions = match options.clear_read_only {
+        true => make_writable(dest_path)?,
+        false => None,
+    };
+    // Create parent directory if needed
+    if let Some(parent_dir) = dest_path.parent() {
+        fs::create_dir_all(parent_dir)?
+    }
+    fs::write(dest_path, content)?;
+    if options.strip_motw {
+        remove_mark_of_the_web(dest_path)?;
+    }
+    if let Some(permissions) = original_permissions {
+        fs::set_permissions(dest_path, permissions)?;
+    }
+    Ok(())
+}
+
+/// Builds a THOR archive which reverts the changes a THOR archive/patch makes
+/// to the GRF named `target_grf_name` or, if it's `None`, to the game
+/// client's directory.
+///
+/// Files the patch adds are removed by the rollback patch, and files it
+/// replaces or removes are restored.
+pub fn create_rollback_patch<R: Read + Seek>(
+    root_directory: impl AsRef<Path>,
+    target_grf_name: Option<&str>,
+    thor_archive: &ThorArchive<R>,
+    rollback_patch_path: impl AsRef<Path>,
+) -> Result<()> {
+    let patched_files = thor_archive
+        .get_entries()
+        .filter(|e| !e.is_internal())
+        .map(|e| (e.relative_path.clone(), e.is_removed));
+    build_rollback_patch(
+        root_directory,
+        target_grf_name,
+        patched_files,
+        rollback_patch_path,
+    )
+}
+
+/// Builds a THOR archive which reverts the changes a ZIP archive/patch makes
+/// to the game client's directory.
+pub fn create_zip_rollback_patch<R: Read + Seek>(
+    root_directory: impl AsRef<Path>,
+    zip_archive: &ZipArchive<R>,
+    rollback_patch_path: impl AsRef<Path>,
+) -> Result<()> {
+    let patched_files = zip_archive
+        .entries()
+        .iter()
+        .filter(|e| !e.is_dir())
+        .map(|e| (zip_entry_relative_path(&e.name), false));
+    build_rollback_patch(root_directory, None, patched_files, rollback_patch_path)
+}
+
+/// Builds the rollback patch of a patch which changes `patched_files`, given
+/// as pairs of relative paths and whether the patch removes them.
+fn build_rollback_patch(
+    root_directory: impl AsRef<Path>,
+    target_grf_name: Option<&str>,
+    patched_files: impl Iterator<Item = (String, bool)>,
+    rollback_patch_path: impl AsRef<Path>,
+) -> Result<()> {
+    let mut grf_archive = match target_grf_name {
+        Some(grf_name) => {
+            let grf_file_path = root_directory.as_ref().join(grf_name);
+            match grf_file_path.exists() {
+                true => Some(GrfArchive::open(grf_file_path)?),
+                false => None,
+            }
+        }
+        None => None,
+    };
+    let mut read_original_file = |relative_path: &str| -> Result<Option<Vec<u8>>> {
+        if target_grf_name.is_some() {
+            return match grf_archive.as_mut() {
+                Some(grf_archive) if grf_archive.contains_file(relative_path) => {
+                    Ok(Some(grf_archive.read_file_content(relative_path)?))
+                }
+                _ => Ok(None),
+            };
+        }
+        match fs::read(game_file_path(root_directory.as_ref(), relative_path)) {
+            Ok(content) => Ok(Some(content)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    };
+
+    let rollback_patch_file = fs::File::create(rollback_patch_path)?;
+    let mut builder = ThorArchiveBuilder::new(
+        rollback_patch_file,
+        target_grf_name.is_some(),
+        target_grf_name.map(str::to_string),
+        true,
+    )?;
+    for (relative_path, is_removed) in patched_files {
+        match read_original_file(&relative_path)? {
+            Some(content) => builder.append_file_update(relative_path, content.as_slice())?,
+            None if !is_removed => builder.append_file_removal(relative_path),
+            // Nothing to restore
+            None => {}
+        }
+    }
+    Ok(builder.finish()?)
+}
+
+/// Checks whether the changes of a THOR archive/patch are present in a GRF
+/// file.
+///
+/// Returns the changes the patch made if that's the case.
+pub fn verify_patch_in_grf<R: Read + Seek>(
+    grf_file_path: impl AsRef<Path>,
+    thor_archive: &mut ThorArchive<R>,
+) -> Result<Option<Vec<FileChange>>> {
+    if !grf_file_path.as_ref().exists() {
+        return Ok(None);
+    }
+    let mut grf_archive = GrfArchive::open(grf_file_path)?;
+    verify_patch_entries(thor_archive, |relative_path| {
+        if !grf_archive.contains_file(relative_path) {
+            return Ok(None);
+        }
+        Ok(Some(grf_archive.read_file_content(relative_path)?))
+    })
+}
+
+/// Checks whether the changes of a THOR archive/patch are present in the
+/// game client's directory.
+///
+/// Returns the changes the patch made if that's the case.
+pub fn verify_patch_on_disk<R: Read + Seek>(
+    root_directory: impl AsRef<Path>,
+    thor_archive: &mut ThorArchive<R>,
+) -> Result<Option<Vec<FileChange>>> {
+    verify_patch_entries(thor_archive, |relative_path| {
+        let file_path = game_file_path(root_directory.as_ref(), relative_path);
+        match fs::read(file_path) {
+            Ok(content) => Ok(Some(content)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    })
+}
+
+/// Compares the entries of a THOR archive with the installed files, which
+/// `read_installed_file` returns the content of.
+fn verify_patch_entries<R: Read + Seek>(
+    thor_archive: &mut ThorArchive<R>,
+    mut read_installed_file: impl FnMut(&str) -> Result<Option<Vec<u8>>>,
+) -> Result<Option<Vec<FileChange>>> {
+    let thor_entries: Vec<ThorFileEntry> = thor_archive
+        .get_entries()
+        .filter(|e| !e.is_internal())
+        .cloned()
+        .collect();
+    let mut file_changes = Vec::with_capacity(thor_entries.len());
+    for entry in thor_entries {
+        let installed_content = read_installed_file(&entry.relative_path)?;
+        if entry.is_removed {
+            if installed_content.is_some() {
+                return Ok(None);
+            }
+            file_changes.push(FileChange::Removed {
+                relative_path: entry.relative_path,
+            });
+        } else {
+            let content = thor_archive.read_file_content(&entry.relative_path)?;
+            if installed_content.as_ref() != Some(&content) {
+                return Ok(None);
+            }
+            file_changes.push(FileChange::written(entry.relative_path, &content));
+        }
+    }
+    Ok(Some(file_changes))
+}
+
+/// Checks whether an error was caused by another program holding a file
+/// open, which Windows doesn't let other processes write to.
+fn is_sharing_violation(error: &anyhow::Error) -> bool {
+    error
+        .chain()
+        .filter_map(|cause| cause.downcast_ref::<io::Error>())
+        .any(is_sharing_violation_io_error)
+}
+
+#[cfg(windows)]
+fn is_sharing_violation_io_error(error: &io::Error) -> bool {
+    use winapi::shared::winerror::{ERROR_LOCK_VIOLATION, ERROR_SHARING_VIOLATION};
+
+    matches!(
+        error.raw_os_error(),
+        Some(code) if code == ERROR_SHARING_VIOLATION as i32 || code == ERROR_LOCK_VIOLATION as i32
+    )
+}
+
+#[cfg(not(windows))]
+fn is_sharing_violation_io_error(error: &io::Error) -> bool {
+    error.kind() == io::ErrorKind::ResourceBusy
+}
+
+/// Makes a read-only file writable.
+///
+/// Returns the file's original permissions if they've been changed.
+fn make_writable(file_path: &Path) -> io::Result<Option<fs::Permissions>> {
+    let permissions = match fs::metadata(file_path) {
+        Ok(metadata) => metadata.permissions(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    if !permissions.readonly() {
+        return Ok(None);
+    }
+    fs::set_permissions(file_path, writable_permissions(permissions.clone()))?;
+    Ok(Some(permissions))
+}
+
+#[cfg(windows)]
+fn writable_permissions(mut permissions: fs::Permissions) -> fs::Permissions {
+    // Clears the file's read-only attribute
+    #[allow(clippy::permissions_set_readonly_false)]
+    permissions.set_readonly(false);
+    permissions
+}
+
+#[cfg(not(windows))]
+fn writable_permissions(permissions: fs::Permissions) -> fs::Permissions {
+    use std::os::unix::fs::PermissionsExt;
+
+    // Only lets the owner write, rather than everyone
+    fs::Permissions::from_mode(permissions.mode() | 0o200)
+}
+
+/// Removes the "Mark of the Web" of a file, which makes Windows warn users
+/// before running executables that come from the internet.
+///
+/// The mark is stored in the file's `Zone.Identifier` alternate data stream.
+#[cfg(windows)]
+fn remove_mark_of_the_web(file_path: &Path) -> io::Result<()> {
+    let mut stream_path = file_path.as_os_str().to_os_string();
+    stream_path.push(":Zone.Identifier");
+    match fs::remove_file(stream_path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(not(windows))]
+fn remove_mark_of_the_web(_file_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+fn check_interrupted(interrupted: &AtomicBool) -> Result<()> {
+    if interrupted.load(Ordering::SeqCst) {
+        return Err(anyhow!("Patching was interrupted"));
+    }
+    Ok(())
+}
+
+/// Refuses entries whose path points outside of the game client's directory
+/// (e.g. '..\\..\\Windows\\System32\\file.dll' or 'C:\\file.dll'), which a
+/// malicious or broken patch could contain.
+fn ensure_path_is_contained(windows_relative_path: &str) -> Result<()> {
+    let is_contained = !windows_relative_path.starts_with(['\\', '/'])
+        && windows_relative_path
+            .split(['\\', '/'])
+            .all(|component| component != ".." && !component.contains(':'));
+    if !is_contained {
+        return Err(anyhow!(
+            "Refusing to patch '{}', which is outside of the game directory",
+            windows_relative_path
+        ));
+    }
+    Ok(())
+}
+
+/// Returns the path of one of the game client's files on disk, from the path
+/// of the THOR entry which patches it.
+///
+/// Entries' paths are transcoded to the system's code page, which the client
+/// uses to open its files, and extended-length paths are used on Windows so
+/// that deep paths don't hit the 260 chars limit.
+fn game_file_path(root_directory: &Path, windows_relative_path: &str) -> PathBuf {
+    extended_length_path(join_windows_relative_path(
+        root_directory,
+        &transcode_relative_path(windows_relative_path, ansi_code_page()),
+    ))
+}
+
+/// Keeps the path as it's stored if it can't be represented in `code_page`.
+fn transcode_relative_path(windows_relative_path: &str, code_page: usize) -> String {
+    gruf::transcode_relative_path(windows_relative_path, code_page)
+        .unwrap_or_else(|_| windows_relative_path.to_string())
+}
+
+#[cfg(windows)]
+fn ansi_code_page() -> usize {
+    unsafe { winapi::um::winnls::GetACP() as usize }
+}
+
+/// Entries' paths are decoded as win1252 chars, which keeps them as they are.
+#[cfg(not(windows))]
+fn ansi_code_page() -> usize {
+    1252
+}
+
+/// Prefixes an absolute path with `\\?\` so that Windows doesn't limit its
+/// length to `MAX_PATH`.
+#[cfg(windows)]
+fn extended_length_path(path: PathBuf) -> PathBuf {
+    let path = match std::path::absolute(&path) {
+        Ok(v) => v,
+        Err(_) => return path,
+    };
+    let path_str = path.as_os_str().to_string_lossy();
+    if path_str.starts_with(r"\\?\") {
+        path
+    } else if let Some(unc_path) = path_str.strip_prefix(r"\\") {
+        PathBuf::from(format!(r"\\?\UNC\{}", unc_path))
+    } else {
+        PathBuf::from(format!(r"\\?\{}", path_str))
+    }
+}
+
+#[cfg(not(windows))]
+fn extended_length_path(path: PathBuf) -> PathBuf {
+    path
+}
+
+/// Utility function used to join path-like segments the same way it's done in
+/// the GRF file format (Windows style).
+fn join_windows_relative_path(path: &Path, windows_relative_path: &str) -> PathBuf {
+    let mut result = PathBuf::from(path);
+    for component in windows_relative_path.split('\\') {
+        result.push(component);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::patcher::zip::ZipWriter;
+    use gruf::thor::ThorArchiveBuilder;
+    use gruf::GrufError;
+    use tempfile::tempdir;
+    use walkdir::WalkDir;
+
+    /// Returns the directory containing the THOR and GRF test fixtures, which
+    /// are shared with gruf.
+    fn fixtures_directory() -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../gruf/resources/tests")
+    }
+
+    #[test]
+    fn test_apply_patch_to_disk() {
+        let thor_dir_path = fixtures_directory().join("thor");
+        let temp_dir = tempdir().unwrap();
+        {
+            let count_files = |dir_path| {
+                WalkDir::new(dir_path)
+                    .into_iter()
+                    .filter_map(|e| e.ok())
+                    .filter_map(|entry| entry.metadata().ok())
+                    .filter(|metadata| metadata.is_file())
+                    .count()
+            };
+            let expected_file_path = temp_dir
+                .path()
+                .join("data/wav/se_subterranean_rustyengine.wav");
+            let thor_archive_path = thor_dir_path.join("small.thor");
+            let mut thor_archive = ThorArchive::open(&thor_archive_path).unwrap();
+            let nb_of_added_files = thor_archive.file_count() - 1;
+
+            // Before patching
+            assert!(!expected_file_path.exists());
+            assert_eq!(0, count_files(temp_dir.path()));
+
+            apply_patch_to_disk(
+                temp_dir.path(),
+                &mut thor_archive,
+                &DiskPatchingOptions {
+                    clear_read_only: true,
+                    strip_motw: false,
+                },
+                &AtomicBool::new(false),
+            )
+            .unwrap();
+
+            // After patching
+            assert!(expected_file_path.exists());
+            assert_eq!(nb_of_added_files, count_files(temp_dir.path()));
+            // TODO(LinkZ): Check content
+        }
+    }
+
+    #[test]
+    fn test_verify_patch_on_disk() {
+        let thor_dir_path = fixtures_directory().join("thor");
+        let temp_dir = tempdir().unwrap();
+        let thor_archive_path = thor_dir_path.join("small.thor");
+        let mut thor_archive = ThorArchive::open(&thor_archive_path).unwrap();
+
+        assert_eq!(
+            verify_patch_on_disk(temp_dir.path(), &mut thor_archive).unwrap(),
+            None
+        );
+        let file_changes = apply_patch_to_disk(
+            temp_dir.path(),
+            &mut thor_archive,
+            &DiskPatchingOptions {
+                clear_read_only: true,
+                strip_motw: false,
+            },
+            &AtomicBool::new(false),
+        )
+        .unwrap();
+        assert_eq!(
+            verify_patch_on_disk(temp_dir.path(), &mut thor_archive).unwrap(),
+            Some(file_changes)
+        );
+
+        // Modified files have to be patched again
+        let modified_file_path = temp_dir
+            .path()
+            .join("data/wav/se_subterranean_rustyengine.wav");
+        fs::write(modified_file_path, b"RIFF").unwrap();
+        assert_eq!(
+            verify_patch_on_disk(temp_dir.path(), &mut thor_archive).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_apply_patch_to_grf_ip_empty() {
+        let grf_dir_path = fixtures_directory().join("grf");
+        let thor_dir_path = fixtures_directory().join("thor");
+        let temp_dir = tempdir().unwrap();
+        let thor_archive_path = thor_dir_path.join("small.thor");
+        let grf_archive_path = temp_dir.path().join("empty.grf");
+        {
+            fs::copy(grf_dir_path.join("200-empty.grf"), &grf_archive_path).unwrap();
+
+            // Before patching
+            let grf_archive = GrfArchive::open(&grf_archive_path).unwrap();
+            assert_eq!(0, grf_archive.file_count());
+            let grf_version_major = grf_archive.version_major();
+            let grf_version_minor = grf_archive.version_minor();
+
+            let mut thor_archive = ThorArchive::open(&thor_archive_path).unwrap();
+            let nb_of_added_files = thor_archive.file_count() - 1;
+            apply_patch_to_grf(
+                GrfPatchingMethod::InPlace,
+                false,
+                &grf_archive_path,
+                &mut thor_archive,
+                &AtomicBool::new(false),
+            )
+            .unwrap();
+
+            // After patching
+            let grf_archive = GrfArchive::open(&grf_archive_path).unwrap();
+            assert_eq!(nb_of_added_files, grf_archive.file_count());
+            assert_eq!(grf_archive.version_major(), grf_version_major);
+            assert_eq!(grf_archive.version_minor(), grf_version_minor);
+        }
+        assert!(patch_maintained_integrity(&thor_archive_path, &grf_archive_path).unwrap());
+    }
+
+    #[test]
+    fn test_apply_patch_to_grf_ip_empty_create() {
+        let thor_dir_path = fixtures_directory().join("thor");
+        let temp_dir = tempdir().unwrap();
+        let grf_archive_path = temp_dir.path().join("empty.grf");
+        let thor_archive_path = thor_dir_path.join("small.thor");
+        {
+            let mut thor_archive = ThorArchive::open(&thor_archive_path).unwrap();
+            let nb_of_added_files = thor_archive.file_count() - 1;
+            apply_patch_to_grf(
+                GrfPatchingMethod::InPlace,
+                true,
+                &grf_archive_path,
+                &mut thor_archive,
+                &AtomicBool::new(false),
+            )
+            .unwrap();
+
+            // After patching
+            let grf_archive = GrfArchive::open(&grf_archive_path).unwrap();
+            assert_eq!(nb_of_added_files, grf_archive.file_count());
+            assert_eq!(grf_archive.version_major(), 2);
+            assert_eq!(grf_archive.version_minor(), 0);
+        }
+        assert!(patch_maintained_integrity(&thor_archive_path, &grf_archive_path).unwrap());
+    }
+
+    #[test]
+    fn test_apply_patch_to_grf_oop_empty() {
+        let grf_dir_path = fixtures_directory().join("grf");
+        let thor_dir_path = fixtures_directory().join("thor");
+        let temp_dir = tempdir().unwrap();
+        let thor_archive_path = thor_dir_path.join("small.thor");
+        let grf_archive_path = temp_dir.path().join("empty.grf");
+        {
+            fs::copy(grf_dir_path.join("200-empty.grf"), &grf_archive_path).unwrap();
+
+            // Before patching
+            let grf_archive = GrfArchive::open(&grf_archive_path).unwrap();
+            assert_eq!(0, grf_archive.file_count());
+            let grf_version_major = grf_archive.version_major();
+            let grf_version_minor = grf_archive.version_minor();
+
+            let mut thor_archive = ThorArchive::open(&thor_archive_path).unwrap();
+            let nb_of_added_files = thor_archive.file_count() - 1;
+            apply_patch_to_grf(
+                GrfPatchingMethod::OutOfPlace { buffer_size: 4096 },
+                false,
+                &grf_archive_path,
+                &mut thor_archive,
+                &AtomicBool::new(false),
+            )
+            .unwrap();
+
+            // After patching
+            let grf_archive = GrfArchive::open(&grf_archive_path).unwrap();
+            assert_eq!(nb_of_added_files, grf_archive.file_count());
+            assert_eq!(grf_archive.version_major(), grf_version_major);
+            assert_eq!(grf_archive.version_minor(), grf_version_minor);
+        }
+        assert!(patch_maintained_integrity(&thor_archive_path, &grf_archive_path).unwrap());
+    }
+
+    #[test]
+    fn test_apply_patch_to_grf_oop_empty_create() {
+        let thor_dir_path = fixtures_directory().join("thor");
+        let temp_dir = tempdir().unwrap();
+        let thor_archive_path = thor_dir_path.join("small.thor");
+        let grf_archive_path = temp_dir.path().join("empty.grf");
+        {
+            let mut thor_archive = ThorArchive::open(&thor_archive_path).unwrap();
+            let nb_of_added_files = thor_archive.file_count() - 1;
+            apply_patch_to_grf(
+                GrfPatchingMethod::OutOfPlace { buffer_size: 4096 },
+                true,
+                &grf_archive_path,
+                &mut thor_archive,
+                &AtomicBool::new(false),
+            )
+            .unwrap();
+
+            // After patching
+            let grf_archive = GrfArchive::open(&grf_archive_path).unwrap();
+            assert_eq!(nb_of_added_files, grf_archive.file_count());
+            assert_eq!(grf_archive.version_major(), 2);
+            assert_eq!(grf_archive.version_minor(), 0);
+        }
+        assert!(patch_maintained_integrity(&thor_archive_path, &grf_archive_path).unwrap());
+    }
+
+    #[test]
+    fn test_apply_patch_to_grf_oop_interrupted() {
+        let temp_dir = tempdir().unwrap();
+        let grf_archive_path = temp_dir.path().join("data.grf");
+        let thor_archive_path = temp_dir.path().join("patch.thor");
+        {
+            let grf_file = fs::File::create(&grf_archive_path).unwrap();
+            let mut builder = GrfArchiveBuilder::create(grf_file, 2, 0).unwrap();
+            builder
+                .add_file("data\\a.txt".to_string(), &b"original"[..])
+                .unwrap();
+            let thor_file = fs::File::create(&thor_archive_path).unwrap();
+            let mut builder = ThorArchiveBuilder::new(thor_file, true, None, false).unwrap();
+            builder
+                .append_file_update("data\\a.txt".to_string(), &b"patched"[..])
+                .unwrap();
+        }
+
+        let mut thor_archive = ThorArchive::open(&thor_archive_path).unwrap();
+        assert!(apply_patch_to_grf(
+            GrfPatchingMethod::OutOfPlace { buffer_size: 4096 },
+            false,
+            &grf_archive_path,
+            &mut thor_archive,
+            &AtomicBool::new(true),
+        )
+        .is_err());
+
+        // The original GRF is restored
+        let mut grf_archive = GrfArchive::open(&grf_archive_path).unwrap();
+        assert_eq!(
+            grf_archive.read_file_content("data\\a.txt").unwrap(),
+            b"original"
+        );
+        assert!(!grf_archive_path.with_extension("grf.bak").exists());
+    }
+
+    #[test]
+    fn test_apply_patch_to_disk_long_and_cp949_paths() {
+        let temp_dir = tempdir().unwrap();
+        let thor_archive_path = temp_dir.path().join("patch.thor");
+        // Entries' paths are limited to 255 bytes, the game's directory makes
+        // up for the rest
+        let game_directory = temp_dir.path().join("game_directory_".repeat(8));
+        let deep_relative_path = format!("{}file.txt", "deep_directory\\".repeat(14));
+        // CP949 path decoded as win1252 chars
+        let cp949_relative_path = "data\\\u{c0}\u{af}\u{c0}\u{fa}\\a.txt";
+        {
+            let thor_file = fs::File::create(&thor_archive_path).unwrap();
+            let mut builder = ThorArchiveBuilder::new(thor_file, false, None, false).unwrap();
+            builder
+                .append_file_update(deep_relative_path.clone(), &b"deep"[..])
+                .unwrap();
+            builder
+                .append_file_update(cp949_relative_path.to_string(), &b"cp949"[..])
+                .unwrap();
+            builder.finish().unwrap();
+        }
+        let deep_file_path = game_file_path(&game_directory, &deep_relative_path);
+        assert!(deep_file_path.as_os_str().len() > 260);
+
+        let mut thor_archive = ThorArchive::open(&thor_archive_path).unwrap();
+        let file_changes = apply_patch_to_disk(
+            &game_directory,
+            &mut thor_archive,
+            &DiskPatchingOptions {
+                clear_read_only: true,
+                strip_motw: false,
+            },
+            &AtomicBool::new(false),
+        )
+        .unwrap();
+        assert_eq!(fs::read(deep_file_path).unwrap(), b"deep");
+        assert_eq!(
+            fs::read(game_file_path(&game_directory, cp949_relative_path)).unwrap(),
+            b"cp949"
+        );
+        let verified_changes = verify_patch_on_disk(&game_directory, &mut thor_archive)
+            .unwrap()
+            .unwrap();
+        assert_eq!(verified_changes.len(), file_changes.len());
+        assert!(verified_changes.iter().all(|c| file_changes.contains(c)));
+    }
+
+    #[test]
+    fn test_apply_patch_to_disk_read_only() {
+        let temp_dir = tempdir().unwrap();
+        let thor_archive_path = temp_dir.path().join("patch.thor");
+        let read_only_file_path = temp_dir.path().join("client.exe");
+        {
+            let thor_file = fs::File::create(&thor_archive_path).unwrap();
+            let mut builder = ThorArchiveBuilder::new(thor_file, false, None, false).unwrap();
+            builder
+                .append_file_update("client.exe".to_string(), &b"patched"[..])
+                .unwrap();
+            builder.finish().unwrap();
+        }
+        fs::write(&read_only_file_path, b"original").unwrap();
+        let mut permissions = fs::metadata(&read_only_file_path).unwrap().permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(&read_only_file_path, permissions).unwrap();
+
+        let mut thor_archive = ThorArchive::open(&thor_archive_path).unwrap();
+        apply_patch_to_disk(
+            temp_dir.path(),
+            &mut thor_archive,
+            &DiskPatchingOptions {
+                clear_read_only: true,
+                strip_motw: false,
+            },
+            &AtomicBool::new(false),
+        )
+        .unwrap();
+
+        // The file is replaced and stays read-only
+        assert_eq!(fs::read(&read_only_file_path).unwrap(), b"patched");
+        assert!(fs::metadata(&read_only_file_path)
+            .unwrap()
+            .permissions()
+            .readonly());
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_apply_patch_to_disk_strip_motw() {
+        let temp_dir = tempdir().unwrap();
+        let thor_archive_path = temp_dir.path().join("patch.thor");
+        let exe_file_path = temp_dir.path().join("client.exe");
+        let zone_identifier_path = temp_dir.path().join("client.exe:Zone.Identifier");
+        {
+            let thor_file = fs::File::create(&thor_archive_path).unwrap();
+            let mut builder = ThorArchiveBuilder::new(thor_file, false, None, false).unwrap();
+            builder
+                .append_file_update("client.exe".to_string(), &b"patched"[..])
+                .unwrap();
+            builder.finish().unwrap();
+        }
+        fs::write(&exe_file_path, b"original").unwrap();
+        fs::write(&zone_identifier_path, b"[ZoneTransfer]\r\nZoneId=3\r\n").unwrap();
+
+        let mut thor_archive = ThorArchive::open(&thor_archive_path).unwrap();
+        apply_patch_to_disk(
+            temp_dir.path(),
+            &mut thor_archive,
+            &DiskPatchingOptions {
+                clear_read_only: true,
+                strip_motw: true,
+            },
+            &AtomicBool::new(false),
+        )
+        .unwrap();
+
+        assert_eq!(fs::read(&exe_file_path).unwrap(), b"patched");
+        assert!(!zone_identifier_path.exists());
+    }
+
+    #[test]
+    fn test_apply_zip_patch_to_disk() {
+        let temp_dir = tempdir().unwrap();
+        let zip_archive_path = temp_dir.path().join("patch.zip");
+        {
+            let zip_file = fs::File::create(&zip_archive_path).unwrap();
+            let mut writer = ZipWriter::new(zip_file);
+            writer
+                .add_file("data/luafiles514/lua files/skillinfo.lub", b"skills")
+                .unwrap();
+            writer.add_file("client.exe", b"patched").unwrap();
+            writer.finish().unwrap();
+        }
+        let game_dir = temp_dir.path().join("game");
+        fs::create_dir(&game_dir).unwrap();
+        fs::write(game_dir.join("client.exe"), b"original").unwrap();
+
+        let mut zip_archive = ZipArchive::open(&zip_archive_path).unwrap();
+        let file_changes = apply_zip_patch_to_disk(
+            &game_dir,
+            &mut zip_archive,
+            &DiskPatchingOptions {
+                clear_read_only: true,
+                strip_motw: false,
+            },
+            &AtomicBool::new(false),
+        )
+        .unwrap();
+
+        assert_eq!(file_changes.len(), 2);
+        assert_eq!(
+            fs::read(game_dir.join("data/luafiles514/lua files/skillinfo.lub")).unwrap(),
+            b"skills"
+        );
+        assert_eq!(fs::read(game_dir.join("client.exe")).unwrap(), b"patched");
+    }
+
+    #[test]
+    fn test_apply_patch_to_disk_outside_of_game_directory() {
+        let temp_dir = tempdir().unwrap();
+        let game_dir = temp_dir.path().join("game");
+        fs::create_dir(&game_dir).unwrap();
+        for relative_path in &[
+            "..\\evil.dll",
+            "C:\\evil.dll",
+            "\\evil.dll",
+            "data/../../evil.dll",
+        ] {
+            let zip_archive_path = temp_dir.path().join("patch.zip");
+            {
+                let zip_file = fs::File::create(&zip_archive_path).unwrap();
+                let mut writer = ZipWriter::new(zip_file);
+                writer.add_file(relative_path, b"evil").unwrap();
+                writer.finish().unwrap();
+            }
+
+            let mut zip_archive = ZipArchive::open(&zip_archive_path).unwrap();
+            let res = apply_zip_patch_to_disk(
+                &game_dir,
+                &mut zip_archive,
+                &DiskPatchingOptions {
+                    clear_read_only: true,
+                    strip_motw: false,
+                },
+                &AtomicBool::new(false),
+            );
+            assert!(res.is_err(), "{} was extracted", relative_path);
+        }
+        assert!(!temp_dir.path().join("evil.dll").exists());
+
+        // Files named like parent directories are fine
+        assert!(ensure_path_is_contained("data\\..texture.bmp").is_ok());
+    }
+
+    #[test]
+    fn test_transcode_relative_path() {
+        // Paths which can't be transcoded are kept as they are
+        assert_eq!(
+            transcode_relative_path("data\\\u{c720}.txt", 949),
+            "data\\\u{c720}.txt"
+        );
+    }
+
+    #[test]
+    fn test_create_rollback_patch() {
+        let temp_dir = tempdir().unwrap();
+        let grf_archive_path = temp_dir.path().join("data.grf");
+        let thor_archive_path = temp_dir.path().join("patch.thor");
+        let rollback_patch_path = temp_dir.path().join("rollback.thor");
+        {
+            let grf_file = fs::File::create(&grf_archive_path).unwrap();
+            let mut builder = GrfArchiveBuilder::create(grf_file, 2, 0).unwrap();
+            builder
+                .add_file("data\\a.txt".to_string(), &b"original"[..])
+                .unwrap();
+            builder
+                .add_file("data\\b.txt".to_string(), &b"removed"[..])
+                .unwrap();
+            let thor_file = fs::File::create(&thor_archive_path).unwrap();
+            let mut builder =
+                ThorArchiveBuilder::new(thor_file, true, Some("data.grf".to_string()), false)
+                    .unwrap();
+            builder
+                .append_file_update("data\\a.txt".to_string(), &b"patched"[..])
+                .unwrap();
+            builder.append_file_removal("data\\b.txt".to_string());
+            builder
+                .append_file_update("data\\c.txt".to_string(), &b"added"[..])
+                .unwrap();
+            builder.finish().unwrap();
+        }
+
+        let mut thor_archive = ThorArchive::open(&thor_archive_path).unwrap();
+        create_rollback_patch(
+            temp_dir.path(),
+            Some("data.grf"),
+            &thor_archive,
+            &rollback_patch_path,
+        )
+        .unwrap();
+        apply_patch_to_grf(
+            GrfPatchingMethod::InPlace,
+            false,
+            &grf_archive_path,
+            &mut thor_archive,
+            &AtomicBool::new(false),
+        )
+        .unwrap();
+        let mut rollback_patch = ThorArchive::open(&rollback_patch_path).unwrap();
+        assert!(rollback_patch.is_valid().unwrap());
+        apply_patch_to_grf(
+            GrfPatchingMethod::InPlace,
+            false,
+            &grf_archive_path,
+            &mut rollback_patch,
+            &AtomicBool::new(false),
+        )
+        .unwrap();
+
+        // The GRF is back to its original state
+        let mut grf_archive = GrfArchive::open(&grf_archive_path).unwrap();
+        assert_eq!(
+            grf_archive.read_file_content("data\\a.txt").unwrap(),
+            b"original"
+        );
+        assert_eq!(
+            grf_archive.read_file_content("data\\b.txt").unwrap(),
+            b"removed"
+        );
+        assert!(!grf_archive.contains_file("data\\c.txt"));
+    }
+
+    #[test]
+    fn test_is_sharing_violation() {
+        #[cfg(windows)]
+        let io_error = io::Error::from_raw_os_error(32);
+        #[cfg(not(windows))]
+        let io_error = io::Error::from(io::ErrorKind::ResourceBusy);
+        let error = anyhow!(GrufError::from(io_error)).context("Failed to open GRF");
+        assert!(is_sharing_violation(&error));
+        assert!(!is_sharing_violation(&anyhow!(io::Error::from(
+            io::ErrorKind::NotFound
+        ))));
+    }
+
+    fn patch_maintained_integrity(thor_file_path: &Path, grf_file_path: &Path) -> Result<bool> {
+        let mut thor_archive = ThorArchive::open(thor_file_path)?;
+        let mut grf_archive = GrfArchive::open(grf_file_path)?;
+        let thor_entries: Vec<ThorFileEntry> = thor_archive.get_entries().cloned().collect();
+        for file_entry in thor_entries {
+            if file_entry.is_internal() || file_entry.is_removed {
+                continue;
+            }
+            let expected_content = thor_archive.read_file_content(&file_entry.relative_path)?;
+            let file_content = grf_archive.read_file_content(&file_entry.relative_path)?;
+            if expected_content != file_content {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
@@ -0,0 +1,286 @@
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use anyhow::{anyhow, Context, Result};
+
+use super::{get_executable_stem, get_patcher_name};
+
+// Empty (i.e. the working directory) until `init_data_directory` is called
+static DATA_DIRECTORY: OnceLock<PathBuf> = OnceLock::new();
+
+/// Selects the directory where the patcher keeps its runtime files (state,
+/// logs, downloads, ...).
+///
+/// Portable patchers keep them in the working directory, others in the
+/// user's local data directory, where the files written by previous versions
+/// are moved.
+pub fn init_data_directory(portable: bool) -> Result<()> {
+    let data_directory = if portable {
+        PathBuf::new()
+    } else {
+        let data_directory = platform_data_directory()?;
+        fs::create_dir_all(&data_directory)
+            .with_context(|| format!("Failed to create '{}'", data_directory.display()))?;
+        migrate_working_directory_files(&data_directory);
+        data_directory
+    };
+    DATA_DIRECTORY
+        .set(data_directory)
+        .map_err(|_| anyhow!("Data directory is already set"))
+}
+
+pub fn is_portable() -> bool {
+    get_data_directory().as_os_str().is_empty()
+}
+
+pub(super) fn get_data_directory() -> PathBuf {
+    DATA_DIRECTORY.get().cloned().unwrap_or_default()
+}
+
+/// Returns `%LOCALAPPDATA%\<patcher>\<game directory>` on Windows and its
+/// equivalents on other platforms (i.e. `~/Library/Application Support` on
+/// macOS, `~/.local/share` on Linux).
+///
+/// Several copies of the game can be patched by patchers with the same name,
+/// hence the game directory.
+fn platform_data_directory() -> Result<PathBuf> {
+    let local_data_directory =
+        dirs::data_local_dir().context("Failed to find the local data directory")?;
+    // Profiles share the directory, their files are named after them
+    Ok(local_data_directory
+        .join(get_executable_stem()?)
+        .join(game_directory_id()?))
+}
+
+/// Returns the directory containing the app bundle the patcher runs from, if
+/// any (i.e. `<directory>/<name>.app/Contents/MacOS/<executable>`).
+///
+/// Apps started from the Finder run from `/`, whereas the game is expected
+/// next to the bundle.
+pub fn get_app_bundle_directory() -> Option<PathBuf> {
+    let current_exe_path = std::env::current_exe().ok()?;
+    app_bundle_directory(&current_exe_path)
+}
+
+fn app_bundle_directory(exe_path: &Path) -> Option<PathBuf> {
+    let macos_directory = exe_path.parent()?;
+    let contents_directory = macos_directory.parent()?;
+    let bundle_directory = contents_directory.parent()?;
+    let in_bundle = macos_directory.file_name()? == "MacOS"
+        && contents_directory.file_name()? == "Contents"
+        && bundle_directory.extension()? == "app";
+    if in_bundle {
+        bundle_directory.parent().map(Path::to_path_buf)
+    } else {
+        None
+    }
+}
+
+/// Returns a name which identifies the game directory (i.e. the working
+/// directory), made of its name and a hash of its path.
+fn game_directory_id() -> Result<String> {
+    let current_dir = std::env::current_dir()?;
+    let current_dir = current_dir.canonicalize().unwrap_or(current_dir);
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(current_dir.to_string_lossy().as_bytes());
+    let game_directory_name = current_dir
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    Ok(format!("{}-{:08x}", game_directory_name, hasher.finalize()))
+}
+
+/// Moves the runtime files written next to the game by previous versions to
+/// `data_directory`, unless it already has its own.
+fn migrate_working_directory_files(data_directory: &Path) {
+    let file_names = ["state.json", "history.json", "downloads"]
+        .iter()
+        .filter_map(|extension| instance_file_name(extension).ok());
+    for file_name in file_names {
+        let destination_path = data_directory.join(&file_name);
+        if !file_name.exists() || destination_path.exists() {
+            continue;
+        }
+        tracing::info!(
+            "Moving '{}' to '{}'",
+            file_name.display(),
+            data_directory.display()
+        );
+        if let Err(e) = move_file(&file_name, &destination_path) {
+            tracing::warn!("Failed to move '{}': {}", file_name.display(), e);
+        }
+    }
+}
+
+fn move_file(source_path: &Path, destination_path: &Path) -> io::Result<()> {
+    if fs::rename(source_path, destination_path).is_ok() {
+        return Ok(());
+    }
+    // Renaming fails across volumes, downloaded patches are left behind then
+    if source_path.is_dir() {
+        return Err(io::Error::other(
+            "directories can't be moved to another volume",
+        ));
+    }
+    fs::copy(source_path, destination_path)?;
+    fs::remove_file(source_path)
+}
+
+/// Returns the name of a file associated with the current 'instance' of the
+/// patcher, which is relative to the working directory.
+fn instance_file_name(extension: &str) -> Result<PathBuf> {
+    let patcher_name = get_patcher_name()?;
+    Ok(PathBuf::from(patcher_name).with_extension(extension))
+}
+
+/// Returns the path of a runtime file associated with the current 'instance'
+/// of the patcher.
+fn runtime_file_path(extension: &str) -> Result<PathBuf> {
+    Ok(get_data_directory().join(instance_file_name(extension)?))
+}
+
+/// Returns the path of the configuration file, which the operator ships with
+/// the patcher.
+pub(super) fn get_configuration_file_path() -> Result<PathBuf> {
+    instance_file_name("yml")
+}
+
+pub fn get_log_file_path() -> Result<PathBuf> {
+    runtime_file_path("log")
+}
+
+/// Returns the path of the `index`-th rotated version of `path` (e.g.
+/// "rpatchur.log.1").
+pub fn rotated_file_path(path: &Path, index: usize) -> PathBuf {
+    let mut rotated_file_path = OsString::from(path);
+    rotated_file_path.push(format!(".{}", index));
+    PathBuf::from(rotated_file_path)
+}
+
+pub(super) fn get_state_file_path() -> Result<PathBuf> {
+    runtime_file_path("state.json")
+}
+
+pub(super) fn get_patch_history_file_path() -> Result<PathBuf> {
+    runtime_file_path("history.json")
+}
+
+/// Returns the path of the directory where patches downloaded ahead of time
+/// are kept.
+pub(super) fn get_download_cache_directory_path() -> Result<PathBuf> {
+    runtime_file_path("downloads")
+}
+
+/// Returns the path of the directory where the patches reverting applied
+/// patches are kept.
+pub(super) fn get_rollback_directory_path() -> Result<PathBuf> {
+    runtime_file_path("rollback")
+}
+
+/// Returns the path of the directory where applied patches are kept for the
+/// other patchers of the LAN, named after their SHA-256.
+pub(super) fn get_peer_cache_directory_path() -> Result<PathBuf> {
+    runtime_file_path("peers")
+}
+
+/// Returns the path of the directory where banner images are cached.
+pub(super) fn get_banner_cache_directory_path() -> Result<PathBuf> {
+    runtime_file_path("banner")
+}
+
+pub(super) fn get_update_lock_file_path() -> Result<PathBuf> {
+    runtime_file_path("lock")
+}
+
+/// Returns the path of the named pipe the running instance of the patcher
+/// listens to, which is shared by the whole session.
+#[cfg(windows)]
+pub(super) fn get_instance_pipe_path() -> Result<PathBuf> {
+    let patcher_name = get_patcher_name()?;
+    Ok(PathBuf::from(format!(
+        r"\\.\pipe\{}-{}",
+        patcher_name.to_string_lossy(),
+        game_directory_id()?
+    )))
+}
+
+/// Returns the path of the socket the running instance of the patcher
+/// listens to.
+#[cfg(unix)]
+pub(super) fn get_instance_socket_path() -> Result<PathBuf> {
+    runtime_file_path("sock")
+}
+
+/// Returns the path of a new crash report.
+pub(super) fn get_crash_report_file_path() -> Result<PathBuf> {
+    let timestamp = chrono::Local::now().format("%Y%m%d-%H%M%S");
+    runtime_file_path(&format!("crash-{}.txt", timestamp))
+}
+
+/// Returns the paths of the crash reports that have been written, the most
+/// recent one last.
+pub(super) fn list_crash_report_file_paths() -> Result<Vec<PathBuf>> {
+    let prefix = instance_file_name("crash-")?.to_string_lossy().to_string();
+    let data_directory = get_data_directory();
+    let directory = if data_directory.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        data_directory.as_path()
+    };
+    let mut file_paths: Vec<PathBuf> = fs::read_dir(directory)?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with(&prefix))
+        .map(|entry| entry.path())
+        .collect();
+    // Named after the time they were written at
+    file_paths.sort();
+    Ok(file_paths)
+}
+
+/// Returns the path of the cache file written by previous versions, which is
+/// migrated to the patcher's state.
+pub(super) fn get_legacy_cache_file_path() -> Result<PathBuf> {
+    instance_file_name("dat")
+}
+
+/// Returns the path of the settings file written by previous versions, which
+/// is migrated to the patcher's state.
+pub(super) fn get_legacy_user_settings_file_path() -> Result<PathBuf> {
+    instance_file_name("settings.yml")
+}
+
+/// Tells whether `file_name` is a single, regular path component, which
+/// can't lead out of the directory it's joined to.
+pub(super) fn is_plain_file_name(file_name: &str) -> bool {
+    !matches!(file_name, "" | "." | "..") && !file_name.contains(['\\', '/', ':'])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_plain_file_name() {
+        assert!(is_plain_file_name("data.grf"));
+        assert!(is_plain_file_name("..data.grf"));
+        for file_name in ["", ".", "..", "../data.grf", "/data.grf", "..\\data.grf"] {
+            assert!(!is_plain_file_name(file_name));
+        }
+        assert!(!is_plain_file_name("C:data.grf"));
+    }
+
+    #[test]
+    fn test_app_bundle_directory() {
+        assert_eq!(
+            app_bundle_directory(Path::new(
+                "/Applications/Ragnarok/Patcher.app/Contents/MacOS/rpatchur"
+            )),
+            Some(PathBuf::from("/Applications/Ragnarok"))
+        );
+        assert!(app_bundle_directory(Path::new("/Applications/Ragnarok/rpatchur")).is_none());
+        assert!(app_bundle_directory(Path::new("/opt/Patcher/Contents/MacOS/rpatchur")).is_none());
+    }
+}
@@ -0,0 +1,636 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, TcpListener, TcpStream, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use gruf::thor::ThorPatchInfo;
+use sha2::{Digest, Sha256};
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::fs::File;
+use tokio::io::AsyncSeekExt;
+use url::Url;
+
+use super::config::PeerCacheConfiguration;
+use super::download::{download_patch_to_file, DownloadDigest, DownloadWriteOptions};
+use super::paths::get_peer_cache_directory_path;
+use super::publish::{to_hex, PatchManifestEntry};
+
+const DEFAULT_PORT: u16 = 7879;
+const DEFAULT_MAX_SIZE_MIB: u64 = 2048;
+const MDNS_ADDRESS: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+const MDNS_PORT: u16 = 5353;
+const SERVICE_NAME: &str = "_rpatchur._tcp.local";
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(1);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_REQUEST_HEADERS: usize = 64;
+const RECORD_TTL: u32 = 120;
+const DNS_TYPE_PTR: u16 = 12;
+const DNS_TYPE_SRV: u16 = 33;
+const DNS_TYPE_ANY: u16 = 255;
+const DNS_CLASS_IN: u16 = 1;
+const DNS_FLAG_RESPONSE: u16 = 0x8000;
+const DNS_FLAG_AUTHORITATIVE: u16 = 0x0400;
+
+/// Shares the patches kept in the peer cache with the other patchers of the
+/// LAN, which find this one through mDNS.
+pub fn serve_peer_cache(config: &PeerCacheConfiguration) -> Result<()> {
+    let port = config.port.unwrap_or(DEFAULT_PORT);
+    let cache_directory = get_peer_cache_directory_path()?;
+    let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, port))
+        .with_context(|| format!("Failed to listen on port {}", port))?;
+    let mdns_socket = bind_mdns_socket()?;
+    std::thread::spawn(move || serve_patches(listener, cache_directory));
+    std::thread::spawn(move || answer_mdns_queries(mdns_socket, &instance_name(), port));
+    tracing::info!("Sharing patches with the LAN on port {}", port);
+    Ok(())
+}
+
+/// Keeps an applied patch in the peer cache, then evicts the oldest patches
+/// in excess of the cache's size.
+pub(super) fn keep_patch_for_peers(
+    config: &PeerCacheConfiguration,
+    patch_path: &Path,
+) -> Result<()> {
+    let cache_directory = get_peer_cache_directory_path()?;
+    fs::create_dir_all(&cache_directory)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut fs::File::open(patch_path)?, &mut hasher)?;
+    let cached_patch_path = cache_directory.join(to_hex(&hasher.finalize()));
+    // Renaming fails across volumes
+    if fs::rename(patch_path, &cached_patch_path).is_err() {
+        fs::copy(patch_path, &cached_patch_path)?;
+        fs::remove_file(patch_path)?;
+    }
+    let max_size = config.max_size.unwrap_or(DEFAULT_MAX_SIZE_MIB) * 1024 * 1024;
+    evict_patches(&cache_directory, max_size)
+}
+
+fn evict_patches(cache_directory: &Path, max_size: u64) -> Result<()> {
+    let mut patches: Vec<(SystemTime, u64, PathBuf)> = fs::read_dir(cache_directory)?
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let metadata = entry.metadata().ok()?;
+            Some((metadata.modified().ok()?, metadata.len(), entry.path()))
+        })
+        .collect();
+    // Most recent first
+    patches.sort_by_key(|(modified, _, _)| std::cmp::Reverse(*modified));
+    let mut total_size = 0;
+    for (_, size, path) in patches {
+        total_size += size;
+        if total_size > max_size {
+            // Patches being served can't be removed on Windows, they will be
+            // next time
+            let _ = fs::remove_file(&path);
+        }
+    }
+    Ok(())
+}
+
+/// Patchers of the LAN which share their patches.
+pub(super) struct Peers {
+    client: reqwest::Client,
+    addresses: Vec<SocketAddr>,
+}
+
+impl Peers {
+    /// Looks for peers through mDNS, there are none if that fails.
+    pub(super) async fn discover() -> Self {
+        let addresses = match tokio::task::spawn_blocking(query_peers).await {
+            Ok(Ok(addresses)) => addresses,
+            Ok(Err(e)) => {
+                tracing::warn!("Failed to look for peers: {:#}", e);
+                vec![]
+            }
+            Err(e) => {
+                tracing::warn!("Failed to look for peers: {}", e);
+                vec![]
+            }
+        };
+        tracing::info!("{} peer(s) found on the LAN", addresses.len());
+        Self::new(addresses)
+    }
+
+    fn new(addresses: Vec<SocketAddr>) -> Self {
+        // Peers are plain HTTP servers, which the patch server's requirements
+        // (e.g. HTTPS only) don't apply to
+        let client = reqwest::Client::builder()
+            .connect_timeout(REQUEST_TIMEOUT)
+            .build()
+            .unwrap_or_default();
+        Self { client, addresses }
+    }
+
+    /// Downloads a patch from the first peer which has a copy matching its
+    /// manifest entry.
+    ///
+    /// Returns `None` if none has, in which case `tmp_file` is left empty.
+    pub(super) async fn download_patch<CB: FnMut(u64, u64)>(
+        &self,
+        entry: &PatchManifestEntry,
+        tmp_file: &mut File,
+        write_options: DownloadWriteOptions,
+        mut progress_callback: CB,
+    ) -> Result<Option<DownloadDigest>> {
+        // Peers serve patches by hash, names are the patch server's
+        let patch_info = ThorPatchInfo {
+            index: entry.index,
+            file_name: entry.sha256.to_ascii_lowercase(),
+        };
+        for address in &self.addresses {
+            let peer_url = Url::parse(&format!("http://{}/patches/", address))?;
+            match download_patch_to_file(
+                &self.client,
+                &peer_url,
+                &patch_info,
+                tmp_file,
+                None,
+                write_options,
+                &mut progress_callback,
+            )
+            .await
+            {
+                Ok(digest)
+                    if digest.size == entry.size
+                        && digest.sha256.eq_ignore_ascii_case(&entry.sha256) =>
+                {
+                    tracing::info!("Downloaded '{}' from {}", entry.file_name, address);
+                    return Ok(Some(digest));
+                }
+                Ok(_) => tracing::warn!("{} has a corrupt copy of '{}'", address, entry.file_name),
+                Err(e) => tracing::debug!(
+                    "Failed to download '{}' from {}: {:#}",
+                    entry.file_name,
+                    address,
+                    e
+                ),
+            }
+            tmp_file.set_len(0).await?;
+            tmp_file.rewind().await?;
+        }
+        Ok(None)
+    }
+}
+
+fn serve_patches(listener: TcpListener, cache_directory: PathBuf) {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                tracing::warn!("Failed to accept a peer: {}", e);
+                continue;
+            }
+        };
+        let cache_directory = cache_directory.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = serve_patch(stream, &cache_directory) {
+                tracing::debug!("Failed to serve a peer: {:#}", e);
+            }
+        });
+    }
+}
+
+fn serve_patch(mut stream: TcpStream, cache_directory: &Path) -> Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Headers don't matter
+    let mut header = String::new();
+    for _ in 0..MAX_REQUEST_HEADERS {
+        header.clear();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+    }
+
+    let patch_file = parse_patch_request(&request_line)
+        .and_then(|sha256| fs::File::open(cache_directory.join(sha256)).ok());
+    match patch_file {
+        Some(mut patch_file) => {
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                patch_file.metadata()?.len()
+            )?;
+            io::copy(&mut patch_file, &mut stream)?;
+        }
+        None => write!(
+            stream,
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+        )?,
+    }
+    Ok(())
+}
+
+/// Returns the SHA-256 of the patch requested with `GET /patches/<sha256>`.
+fn parse_patch_request(request_line: &str) -> Option<&str> {
+    let mut parts = request_line.split_whitespace();
+    if parts.next()? != "GET" {
+        return None;
+    }
+    let sha256 = parts.next()?.strip_prefix("/patches/")?;
+    // Anything else could point outside of the cache
+    let is_sha256 = sha256.len() == 64
+        && sha256
+            .bytes()
+            .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b));
+    Some(sha256).filter(|_| is_sha256)
+}
+
+fn bind_mdns_socket() -> Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    // The system's responder (e.g. Bonjour, Avahi) may listen already
+    socket.set_reuse_address(true)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(true)?;
+    socket
+        .bind(&SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, MDNS_PORT).into())
+        .with_context(|| "Failed to bind the mDNS port")?;
+    socket.join_multicast_v4(&MDNS_ADDRESS, &Ipv4Addr::UNSPECIFIED)?;
+    Ok(socket.into())
+}
+
+fn answer_mdns_queries(socket: UdpSocket, instance_name: &str, port: u16) {
+    let mut buffer = [0; 4096];
+    loop {
+        let (size, source) = match socket.recv_from(&mut buffer) {
+            Ok(received) => received,
+            Err(e) => {
+                tracing::warn!("Stopped answering mDNS queries: {}", e);
+                return;
+            }
+        };
+        let query = &buffer[..size];
+        if !is_service_query(query) {
+            continue;
+        }
+        let id = u16::from_be_bytes([query[0], query[1]]);
+        // Queries sent from other ports expect unicast answers
+        let destination = if source.port() == MDNS_PORT {
+            SocketAddr::from((MDNS_ADDRESS, MDNS_PORT))
+        } else {
+            source
+        };
+        let _ = socket.send_to(&build_response(id, instance_name, port), destination);
+    }
+}
+
+/// Sends a one-shot mDNS query and collects the answers of the peers.
+fn query_peers() -> Result<Vec<SocketAddr>> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.send_to(&build_query(), (MDNS_ADDRESS, MDNS_PORT))?;
+    let deadline = Instant::now() + DISCOVERY_TIMEOUT;
+    let mut addresses = vec![];
+    let mut buffer = [0; 4096];
+    loop {
+        let remaining_time = deadline.saturating_duration_since(Instant::now());
+        if remaining_time.is_zero() {
+            break;
+        }
+        socket.set_read_timeout(Some(remaining_time))?;
+        match socket.recv_from(&mut buffer) {
+            Ok((size, source)) => {
+                if let Some(port) = parse_response(&buffer[..size]) {
+                    let address = SocketAddr::new(source.ip(), port);
+                    if !addresses.contains(&address) {
+                        addresses.push(address);
+                    }
+                }
+            }
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                break
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(addresses)
+}
+
+fn instance_name() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.subsec_nanos())
+        .unwrap_or_default();
+    format!("rpatchur-{:x}-{:x}", std::process::id(), nanos)
+}
+
+fn build_query() -> Vec<u8> {
+    let mut packet = vec![];
+    // ID, flags, then a single question
+    for value in [0, 0, 1, 0, 0, 0] {
+        push_u16(&mut packet, value);
+    }
+    push_name(&mut packet, SERVICE_NAME);
+    push_u16(&mut packet, DNS_TYPE_PTR);
+    push_u16(&mut packet, DNS_CLASS_IN);
+    packet
+}
+
+fn build_response(id: u16, instance_name: &str, port: u16) -> Vec<u8> {
+    let service_instance_name = format!("{}.{}", instance_name, SERVICE_NAME);
+    let mut packet = vec![];
+    // ID, flags, then the PTR and SRV records
+    for value in [id, DNS_FLAG_RESPONSE | DNS_FLAG_AUTHORITATIVE, 0, 2, 0, 0] {
+        push_u16(&mut packet, value);
+    }
+
+    let mut ptr_data = vec![];
+    push_name(&mut ptr_data, &service_instance_name);
+    push_record(&mut packet, SERVICE_NAME, DNS_TYPE_PTR, &ptr_data);
+
+    let mut srv_data = vec![];
+    // Priority, weight and port
+    for value in [0, 0, port] {
+        push_u16(&mut srv_data, value);
+    }
+    push_name(&mut srv_data, &format!("{}.local", instance_name));
+    push_record(&mut packet, &service_instance_name, DNS_TYPE_SRV, &srv_data);
+    packet
+}
+
+fn push_record(packet: &mut Vec<u8>, name: &str, record_type: u16, data: &[u8]) {
+    push_name(packet, name);
+    push_u16(packet, record_type);
+    push_u16(packet, DNS_CLASS_IN);
+    packet.extend_from_slice(&RECORD_TTL.to_be_bytes());
+    push_u16(packet, data.len() as u16);
+    packet.extend_from_slice(data);
+}
+
+fn push_name(packet: &mut Vec<u8>, name: &str) {
+    for label in name.split('.') {
+        packet.push(label.len() as u8);
+        packet.extend_from_slice(label.as_bytes());
+    }
+    packet.push(0);
+}
+
+fn push_u16(packet: &mut Vec<u8>, value: u16) {
+    packet.extend_from_slice(&value.to_be_bytes());
+}
+
+fn is_service_query(packet: &[u8]) -> bool {
+    let mut reader = PacketReader::new(packet);
+    let mut is_service_query = || -> Option<bool> {
+        let header = reader.header()?;
+        if header.flags & DNS_FLAG_RESPONSE != 0 {
+            return Some(false);
+        }
+        for _ in 0..header.question_count {
+            let name = reader.name()?;
+            let question_type = reader.u16()?;
+            reader.u16()?;
+            if name.eq_ignore_ascii_case(SERVICE_NAME)
+                && (question_type == DNS_TYPE_PTR || question_type == DNS_TYPE_ANY)
+            {
+                return Some(true);
+            }
+        }
+        Some(false)
+    };
+    is_service_query().unwrap_or(false)
+}
+
+/// Returns the port a peer serves its patches on, given its answer.
+fn parse_response(packet: &[u8]) -> Option<u16> {
+    let mut reader = PacketReader::new(packet);
+    let header = reader.header()?;
+    if header.flags & DNS_FLAG_RESPONSE == 0 {
+        return None;
+    }
+    for _ in 0..header.question_count {
+        reader.name()?;
+        reader.skip(4)?;
+    }
+    let service_suffix = format!(".{}", SERVICE_NAME.to_ascii_lowercase());
+    for _ in 0..header.record_count {
+        let name = reader.name()?;
+        let record_type = reader.u16()?;
+        // Class and TTL
+        reader.skip(6)?;
+        let data_length = reader.u16()? as usize;
+        let data = reader.bytes(data_length)?;
+        if record_type == DNS_TYPE_SRV
+            && name.to_ascii_lowercase().ends_with(&service_suffix)
+            && data.len() >= 6
+        {
+            return Some(u16::from_be_bytes([data[4], data[5]]));
+        }
+    }
+    None
+}
+
+struct DnsHeader {
+    flags: u16,
+    question_count: u16,
+    record_count: u16, // Answers, authority and additional records
+}
+
+struct PacketReader<'a> {
+    packet: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> PacketReader<'a> {
+    fn new(packet: &'a [u8]) -> Self {
+        Self { packet, offset: 0 }
+    }
+
+    fn header(&mut self) -> Option<DnsHeader> {
+        let _id = self.u16()?;
+        let flags = self.u16()?;
+        let question_count = self.u16()?;
+        let mut record_count = 0u16;
+        for _ in 0..3 {
+            record_count = record_count.saturating_add(self.u16()?);
+        }
+        Some(DnsHeader {
+            flags,
+            question_count,
+            record_count,
+        })
+    }
+
+    fn bytes(&mut self, count: usize) -> Option<&'a [u8]> {
+        let bytes = self
+            .packet
+            .get(self.offset..self.offset.checked_add(count)?)?;
+        self.offset += count;
+        Some(bytes)
+    }
+
+    fn skip(&mut self, count: usize) -> Option<()> {
+        self.bytes(count).map(|_| ())
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.bytes(2)
+            .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    /// Reads a domain name, following compression pointers.
+    fn name(&mut self) -> Option<String> {
+        // Pointers can loop
+        const MAX_POINTERS: usize = 16;
+        let mut labels = vec![];
+        let mut offset = self.offset;
+        let mut end_offset = None;
+        let mut pointer_count = 0;
+        loop {
+            let length = *self.packet.get(offset)? as usize;
+            if length == 0 {
+                offset += 1;
+                break;
+            }
+            if length & 0xC0 == 0xC0 {
+                pointer_count += 1;
+                if pointer_count > MAX_POINTERS {
+                    return None;
+                }
+                // Reading goes on after the first pointer
+                end_offset.get_or_insert(offset + 2);
+                offset = ((length & 0x3F) << 8) | *self.packet.get(offset + 1)? as usize;
+                continue;
+            }
+            let label = self.packet.get(offset + 1..offset + 1 + length)?;
+            labels.push(String::from_utf8_lossy(label).into_owned());
+            offset += 1 + length;
+        }
+        self.offset = end_offset.unwrap_or(offset);
+        Some(labels.join("."))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mdns_query_and_response() {
+        let query = build_query();
+        assert!(is_service_query(&query));
+        assert_eq!(parse_response(&query), None);
+
+        let response = build_response(42, "rpatchur-1", 7879);
+        assert!(!is_service_query(&response));
+        assert_eq!(parse_response(&response), Some(7879));
+
+        // Names may point to the ones that come before them
+        let mut response = vec![];
+        for value in [0, DNS_FLAG_RESPONSE, 0, 2, 0, 0] {
+            push_u16(&mut response, value);
+        }
+        let mut ptr_data = vec![4];
+        ptr_data.extend_from_slice(b"peer");
+        ptr_data.extend_from_slice(&[0xC0, 12]);
+        push_record(&mut response, SERVICE_NAME, DNS_TYPE_PTR, &ptr_data);
+        let instance_name_offset = response.len() - ptr_data.len();
+        response.extend_from_slice(&[0xC0, instance_name_offset as u8]);
+        push_u16(&mut response, DNS_TYPE_SRV);
+        push_u16(&mut response, DNS_CLASS_IN);
+        response.extend_from_slice(&RECORD_TTL.to_be_bytes());
+        push_u16(&mut response, 6);
+        for value in [0, 0, 8000] {
+            push_u16(&mut response, value);
+        }
+        assert_eq!(parse_response(&response), Some(8000));
+
+        // Unrelated or truncated packets are ignored
+        assert!(!is_service_query(&query[..query.len() - 3]));
+        assert_eq!(parse_response(&build_response(1, "x", 1)[..20]), None);
+    }
+
+    #[test]
+    fn test_parse_patch_request() {
+        let sha256 = to_hex(&Sha256::digest(b"patch"));
+        assert_eq!(
+            parse_patch_request(&format!("GET /patches/{} HTTP/1.1\r\n", sha256)),
+            Some(sha256.as_str())
+        );
+        assert_eq!(
+            parse_patch_request(&format!("POST /patches/{} HTTP/1.1\r\n", sha256)),
+            None
+        );
+        assert_eq!(
+            parse_patch_request("GET /patches/../state.json HTTP/1.1"),
+            None
+        );
+        assert_eq!(
+            parse_patch_request(&format!(
+                "GET /patches/{} HTTP/1.1",
+                sha256.to_ascii_uppercase()
+            )),
+            None
+        );
+    }
+
+    #[test]
+    fn test_evict_patches() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        for (i, name) in ["old", "new"].iter().enumerate() {
+            let path = tmp_dir.path().join(name);
+            fs::write(&path, [0u8; 10]).unwrap();
+            let modified = SystemTime::now() - Duration::from_secs(100 - i as u64);
+            fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+        }
+        evict_patches(tmp_dir.path(), 15).unwrap();
+        assert!(!tmp_dir.path().join("old").exists());
+        assert!(tmp_dir.path().join("new").exists());
+    }
+
+    #[tokio::test]
+    async fn test_download_patch_from_peers() {
+        let patch_content = b"ASSF patch".to_vec();
+        let cache_dir = tempfile::tempdir().unwrap();
+        let sha256 = to_hex(&Sha256::digest(&patch_content));
+        fs::write(cache_dir.path().join(&sha256), &patch_content).unwrap();
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let address = listener.local_addr().unwrap();
+        let cache_directory = cache_dir.path().to_path_buf();
+        std::thread::spawn(move || serve_patches(listener, cache_directory));
+
+        let peers = Peers::new(vec![address]);
+        let write_options = DownloadWriteOptions::new(&Default::default());
+        let mut entry = PatchManifestEntry {
+            index: 1,
+            file_name: "1.thor".to_string(),
+            size: patch_content.len() as u64,
+            sha256: sha256.to_ascii_uppercase(),
+            ..Default::default()
+        };
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let patch_path = tmp_dir.path().join("1.thor");
+        let mut tmp_file = File::create(&patch_path).await.unwrap();
+        let digest = peers
+            .download_patch(&entry, &mut tmp_file, write_options, |_, _| {})
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(digest.sha256.eq_ignore_ascii_case(&sha256));
+        assert_eq!(fs::read(&patch_path).unwrap(), patch_content);
+
+        // Patches peers don't have are left to the patch server
+        entry.sha256 = to_hex(&Sha256::digest(b"other patch"));
+        let mut tmp_file = File::create(&patch_path).await.unwrap();
+        let digest = peers
+            .download_patch(&entry, &mut tmp_file, write_options, |_, _| {})
+            .await
+            .unwrap();
+        assert!(digest.is_none());
+        assert_eq!(fs::metadata(&patch_path).unwrap().len(), 0);
+    }
+}
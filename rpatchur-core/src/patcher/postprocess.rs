@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use super::config::PostprocessConfiguration;
+use super::launch::expand_template;
+
+/// Renders the templates of `config` found in `game_directory`, with the
+/// given `variables`.
+///
+/// Outputs whose content is already up to date are left untouched. Returns
+/// the number of files which have been written.
+pub(super) fn render_templates(
+    config: &PostprocessConfiguration,
+    variables: &HashMap<String, String>,
+    game_directory: &Path,
+) -> Result<usize> {
+    let mut written_file_count = 0;
+    for template_config in &config.templates {
+        let template_path = game_directory.join(&template_config.template);
+        let output_path = game_directory.join(&template_config.output);
+        let template = fs::read_to_string(&template_path)
+            .with_context(|| format!("Failed to read '{}'", template_path.display()))?;
+        let content = expand_template(&template, variables)
+            .with_context(|| format!("Failed to render '{}'", template_config.template))?;
+        if fs::read_to_string(&output_path).ok().as_deref() == Some(content.as_str()) {
+            continue;
+        }
+        if let Some(parent) = output_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&output_path, content)
+            .with_context(|| format!("Failed to write '{}'", output_path.display()))?;
+        tracing::info!(
+            "Generated '{}' from '{}'",
+            template_config.output,
+            template_config.template
+        );
+        written_file_count += 1;
+    }
+    Ok(written_file_count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::config::TemplateConfiguration;
+    use super::*;
+
+    #[test]
+    fn test_render_templates() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        fs::write(
+            temp_dir.path().join("clientinfo.xml.in"),
+            "<address>{server_ip}</address><port>{server_port}</port>",
+        )
+        .unwrap();
+        let config = PostprocessConfiguration {
+            templates: vec![TemplateConfiguration {
+                template: "clientinfo.xml.in".to_string(),
+                output: "data/clientinfo.xml".to_string(),
+            }],
+        };
+        let mut variables: HashMap<String, String> = vec![
+            ("server_ip".to_string(), "127.0.0.1".to_string()),
+            ("server_port".to_string(), "6900".to_string()),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(
+            render_templates(&config, &variables, temp_dir.path()).unwrap(),
+            1
+        );
+        let output_path = temp_dir.path().join("data").join("clientinfo.xml");
+        assert_eq!(
+            fs::read_to_string(&output_path).unwrap(),
+            "<address>127.0.0.1</address><port>6900</port>"
+        );
+        // Nothing changed
+        assert_eq!(
+            render_templates(&config, &variables, temp_dir.path()).unwrap(),
+            0
+        );
+
+        variables.remove("server_port");
+        assert!(render_templates(&config, &variables, temp_dir.path()).is_err());
+        // The last output is kept
+        assert!(fs::read_to_string(&output_path).unwrap().contains("6900"));
+    }
+}
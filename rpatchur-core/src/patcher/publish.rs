@@ -0,0 +1,633 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use anyhow::{anyhow, Context, Result};
+use chrono::Local;
+use ed25519_dalek::pkcs8::DecodePrivateKey;
+use ed25519_dalek::{Signer, SigningKey};
+use gruf::thor::{self, ThorArchive, ThorArchiveBuilder, ThorPatchList};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use url::Url;
+use walkdir::WalkDir;
+
+use super::zip::is_zip_file;
+
+pub(super) const PATCH_LIST_FILE_NAME: &str = "plist.txt";
+pub(super) const MANIFEST_FILE_NAME: &str = "patches.json";
+pub(super) const SIGNATURE_FILE_NAME: &str = "patches.json.sig";
+const COMPARISON_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Release of the game client to publish on a patch server.
+pub struct PublishOptions {
+    pub client_directory: PathBuf,
+    pub previous_client_directory: Option<PathBuf>, // Everything is patched if not set
+    pub patch_server_directory: PathBuf,            // Directory containing 'plist.txt'
+    pub target_grf_name: Option<String>,            // Patches the game directory if not set
+    pub signing_key_path: Option<PathBuf>,          // PEM-encoded Ed25519 private key
+    pub upload_url: Option<String>,                 // 's3://bucket/path' or 'sftp://user@host/path'
+    pub channel: Option<String>,                    // Release channel, stable if not set
+    pub min_patcher_version: Option<String>,        // Kept from the previous release if not set
+}
+
+/// Patch added to the patch server by `publish_release`.
+#[derive(Debug)]
+pub struct PublishedPatch {
+    pub index: usize,
+    pub file_name: String,
+    pub updated_files: usize,
+    pub removed_files: usize,
+}
+
+/// Patch archive listed in the manifest, which lets clients check what they
+/// download.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct PatchManifestEntry {
+    pub index: usize,
+    pub file_name: String,
+    pub size: u64,
+    pub sha256: String,             // Hex-encoded
+    pub target_grf: Option<String>, // Patches the game directory if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel: Option<String>, // Release channel the patch belongs to (e.g. "beta")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_launcher_version: Option<String>, // Oldest version of the patcher able to apply the patch
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub parts: Vec<String>, // Files the patch is split into, joined in order (e.g. for hosts limiting file sizes)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>, // Release notes in Markdown, shown once the patch has been applied
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes_url: Option<String>, // Same, downloaded from this URL (relative to the manifest) instead
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>, // 'name=value' pairs restricting which clients get the patch (e.g. "lang=pt-BR", "platform=win32,win64")
+}
+
+impl PatchManifestEntry {
+    /// Returns the value of the entry's tag named `name`, if any.
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags.iter().find_map(|tag| match tag.split_once('=') {
+            Some((tag_name, value)) if tag_name.trim() == name => Some(value.trim()),
+            _ => None,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct PatchManifest {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_patcher_version: Option<String>, // Older patchers refuse to patch the game
+    pub patches: Vec<PatchManifestEntry>,
+}
+
+#[derive(Debug, PartialEq)]
+enum ClientChange {
+    Updated(String), // Relative path of a file that's been added or modified
+    Removed(String),
+}
+
+/// Builds a THOR patch containing the differences between two releases of
+/// the client and adds it to the patch server's directory (patch list,
+/// manifest and its signature), uploading the new files if asked to.
+pub fn publish_release(options: &PublishOptions) -> Result<PublishedPatch> {
+    let changes = diff_client_directories(
+        &options.client_directory,
+        options.previous_client_directory.as_deref(),
+    )?;
+    if changes.is_empty() {
+        return Err(anyhow!(
+            "The client hasn't changed since the previous release"
+        ));
+    }
+
+    let patch_list_path = options.patch_server_directory.join(PATCH_LIST_FILE_NAME);
+    let patch_list_content = match fs::read_to_string(&patch_list_path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => {
+            return Err(e)
+                .with_context(|| format!("Failed to read '{}'", patch_list_path.display()))
+        }
+    };
+    let mut patch_list = thor::patch_list_from_string(&patch_list_content);
+    let index = patch_list
+        .last()
+        .map_or(1, |patch_info| patch_info.index + 1);
+    let file_name = format!("{}-{}.thor", Local::now().format("%Y%m%d"), index);
+    let patch_path = options.patch_server_directory.join(&file_name);
+    if patch_path.exists() {
+        return Err(anyhow!("'{}' already exists", patch_path.display()));
+    }
+
+    tracing::info!("Building '{}'", file_name);
+    if let Err(e) = build_patch(
+        &patch_path,
+        &options.client_directory,
+        options.target_grf_name.clone(),
+        &changes,
+    ) {
+        let _ = fs::remove_file(&patch_path);
+        return Err(e.context(format!("Failed to build '{}'", patch_path.display())));
+    }
+
+    // Clients only see the patch once it's in the list, which is written last
+    let mut patch_list_line = format!("{} {}\n", index, file_name);
+    if !patch_list_content.is_empty() && !patch_list_content.ends_with('\n') {
+        patch_list_line.insert(0, '\n');
+    }
+    patch_list.push(thor::ThorPatchInfo {
+        index,
+        file_name: file_name.clone(),
+    });
+    let manifest_path = options.patch_server_directory.join(MANIFEST_FILE_NAME);
+    let manifest = update_manifest(
+        &manifest_path,
+        &options.patch_server_directory,
+        &patch_list,
+        options.channel.as_deref(),
+        options.min_patcher_version.as_deref(),
+    )?;
+    let mut published_files = vec![patch_path, manifest_path];
+    if let Some(signing_key_path) = &options.signing_key_path {
+        let signature_path = options.patch_server_directory.join(SIGNATURE_FILE_NAME);
+        sign_manifest(&manifest, signing_key_path, &signature_path)?;
+        published_files.push(signature_path);
+    }
+    fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&patch_list_path)
+        .and_then(|mut file| file.write_all(patch_list_line.as_bytes()))
+        .with_context(|| format!("Failed to update '{}'", patch_list_path.display()))?;
+    published_files.push(patch_list_path);
+
+    if let Some(upload_url) = &options.upload_url {
+        tracing::info!("Uploading to '{}'", upload_url);
+        upload_files(upload_url, &published_files).context("Failed to upload the patch")?;
+    }
+
+    let updated_files = changes
+        .iter()
+        .filter(|change| matches!(change, ClientChange::Updated(_)))
+        .count();
+    Ok(PublishedPatch {
+        index,
+        file_name,
+        updated_files,
+        removed_files: changes.len() - updated_files,
+    })
+}
+
+/// Lists the files that differ between two releases of the client.
+fn diff_client_directories(
+    client_directory: &Path,
+    previous_client_directory: Option<&Path>,
+) -> Result<Vec<ClientChange>> {
+    let client_files = list_client_files(client_directory)?;
+    let previous_client_files = match previous_client_directory {
+        Some(previous_client_directory) => list_client_files(previous_client_directory)?,
+        None => BTreeMap::new(),
+    };
+    let mut changes = Vec::new();
+    for (relative_path, file_path) in &client_files {
+        let unchanged = match previous_client_files.get(relative_path) {
+            Some(previous_file_path) => files_are_identical(file_path, previous_file_path)?,
+            None => false,
+        };
+        if !unchanged {
+            changes.push(ClientChange::Updated(relative_path.clone()));
+        }
+    }
+    changes.extend(
+        previous_client_files
+            .keys()
+            .filter(|relative_path| !client_files.contains_key(*relative_path))
+            .map(|relative_path| ClientChange::Removed(relative_path.clone())),
+    );
+    Ok(changes)
+}
+
+/// Returns the files contained in `directory`, by their Windows-style
+/// relative path (i.e. the way THOR archives store them).
+fn list_client_files(directory: &Path) -> Result<BTreeMap<String, PathBuf>> {
+    let mut files = BTreeMap::new();
+    for entry in WalkDir::new(directory).follow_links(false) {
+        let entry = entry?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let relative_path = entry
+            .path()
+            .strip_prefix(directory)?
+            .to_str()
+            .ok_or_else(|| anyhow!("Invalid file path '{}'", entry.path().display()))?
+            .replace('/', "\\");
+        files.insert(relative_path, entry.into_path());
+    }
+    Ok(files)
+}
+
+fn files_are_identical(file_path: &Path, other_file_path: &Path) -> Result<bool> {
+    let size = fs::metadata(file_path)?.len();
+    if size != fs::metadata(other_file_path)?.len() {
+        return Ok(false);
+    }
+    let mut file = BufReader::new(File::open(file_path)?);
+    let mut other_file = BufReader::new(File::open(other_file_path)?);
+    let mut buffer = vec![0; COMPARISON_CHUNK_SIZE];
+    let mut other_buffer = vec![0; COMPARISON_CHUNK_SIZE];
+    let mut remaining = size;
+    while remaining > 0 {
+        let chunk_size = remaining.min(COMPARISON_CHUNK_SIZE as u64) as usize;
+        file.read_exact(&mut buffer[..chunk_size])?;
+        other_file.read_exact(&mut other_buffer[..chunk_size])?;
+        if buffer[..chunk_size] != other_buffer[..chunk_size] {
+            return Ok(false);
+        }
+        remaining -= chunk_size as u64;
+    }
+    Ok(true)
+}
+
+fn build_patch(
+    patch_path: &Path,
+    client_directory: &Path,
+    target_grf_name: Option<String>,
+    changes: &[ClientChange],
+) -> Result<()> {
+    let use_grf_merging = target_grf_name.is_some();
+    write_thor_patch(patch_path, use_grf_merging, target_grf_name, |builder| {
+        for change in changes {
+            match change {
+                ClientChange::Updated(relative_path) => {
+                    tracing::debug!("'{}' will be UPDATED", relative_path);
+                    let file_path = client_directory.join(relative_path.replace('\\', "/"));
+                    let file = File::open(&file_path)
+                        .with_context(|| format!("Failed to open '{}'", file_path.display()))?;
+                    builder.append_file_update(relative_path.clone(), BufReader::new(file))?;
+                }
+                ClientChange::Removed(relative_path) => {
+                    tracing::debug!("'{}' will be REMOVED", relative_path);
+                    builder.append_file_removal(relative_path.clone());
+                }
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Writes a THOR patch to `patch_path`, made of the changes `append_changes`
+/// appends to the builder it's given.
+///
+/// Patches merging into a GRF (`use_grf_merging`) target the client's
+/// default GRF if `target_grf_name` isn't set, and the game directory
+/// otherwise.
+pub(super) fn write_thor_patch(
+    patch_path: &Path,
+    use_grf_merging: bool,
+    target_grf_name: Option<String>,
+    append_changes: impl FnOnce(&mut ThorArchiveBuilder<File>) -> Result<()>,
+) -> Result<()> {
+    let patch_file = File::create(patch_path)
+        .with_context(|| format!("Failed to create '{}'", patch_path.display()))?;
+    // Checksums let clients validate the patch before applying it
+    let mut builder = ThorArchiveBuilder::new(patch_file, use_grf_merging, target_grf_name, true)?;
+    append_changes(&mut builder)?;
+    builder.finish()?;
+    Ok(())
+}
+
+/// Builds a THOR patch out of `updates` and `removals`, for tests.
+#[cfg(test)]
+pub(super) fn build_thor_patch(
+    path: &Path,
+    target_grf_name: Option<&str>,
+    updates: &[(&str, &[u8])],
+    removals: &[&str],
+) {
+    let use_grf_merging = target_grf_name.is_some();
+    let target_grf_name = target_grf_name.map(str::to_string);
+    write_thor_patch(path, use_grf_merging, target_grf_name, |builder| {
+        for (relative_path, content) in updates {
+            builder.append_file_update(relative_path.to_string(), *content)?;
+        }
+        for relative_path in removals {
+            builder.append_file_removal(relative_path.to_string());
+        }
+        Ok(())
+    })
+    .unwrap();
+}
+
+/// Writes the manifest of the patches in `patch_list` and returns its
+/// content.
+///
+/// Archives which are already in the manifest aren't hashed again, the ones
+/// which aren't are put on `channel`.
+pub(super) fn update_manifest(
+    manifest_path: &Path,
+    patch_server_directory: &Path,
+    patch_list: &ThorPatchList,
+    channel: Option<&str>,
+    min_patcher_version: Option<&str>,
+) -> Result<Vec<u8>> {
+    let previous_manifest: PatchManifest = match fs::read(manifest_path) {
+        Ok(content) => serde_json::from_slice(&content)
+            .with_context(|| format!("Invalid manifest '{}'", manifest_path.display()))?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => PatchManifest::default(),
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to read '{}'", manifest_path.display()))
+        }
+    };
+    let mut previous_entries: HashMap<String, PatchManifestEntry> = previous_manifest
+        .patches
+        .into_iter()
+        .map(|entry| (entry.file_name.clone(), entry))
+        .collect();
+
+    let mut manifest = PatchManifest {
+        min_patcher_version: min_patcher_version
+            .map(str::to_string)
+            .or(previous_manifest.min_patcher_version),
+        patches: vec![],
+    };
+    for patch_info in patch_list {
+        let patch_path = patch_server_directory.join(&patch_info.file_name);
+        let size = match fs::metadata(&patch_path) {
+            Ok(metadata) => metadata.len(),
+            Err(e) => {
+                // Only listing the patches clients can download
+                tracing::warn!("Skipping '{}': {}", patch_path.display(), e);
+                continue;
+            }
+        };
+        let entry = match previous_entries.remove(&patch_info.file_name) {
+            Some(entry) if entry.index == patch_info.index && entry.size == size => entry,
+            _ => PatchManifestEntry {
+                channel: channel.map(str::to_string),
+                ..manifest_entry(patch_info.index, &patch_info.file_name, &patch_path)?
+            },
+        };
+        manifest.patches.push(entry);
+    }
+    let content = serde_json::to_vec_pretty(&manifest)?;
+    fs::write(manifest_path, &content)
+        .with_context(|| format!("Failed to write '{}'", manifest_path.display()))?;
+    Ok(content)
+}
+
+/// Describes the patch at `patch_path`, as listed in the manifest.
+pub(super) fn manifest_entry(
+    index: usize,
+    file_name: &str,
+    patch_path: &Path,
+) -> Result<PatchManifestEntry> {
+    let mut file = File::open(patch_path)
+        .with_context(|| format!("Failed to open '{}'", patch_path.display()))?;
+    let mut hasher = Sha256::new();
+    let size = io::copy(&mut file, &mut hasher)?;
+    // ZIP patches are always extracted to the game client's directory
+    let target_grf = if is_zip_file(patch_path) {
+        None
+    } else {
+        let thor_archive = ThorArchive::open(patch_path)
+            .with_context(|| format!("Invalid patch '{}'", patch_path.display()))?;
+        if thor_archive.use_grf_merging() {
+            Some(thor_archive.target_grf_name())
+                .filter(|target_grf_name| !target_grf_name.is_empty())
+        } else {
+            None
+        }
+    };
+    Ok(PatchManifestEntry {
+        index,
+        file_name: file_name.to_string(),
+        size,
+        sha256: to_hex(&hasher.finalize()),
+        target_grf,
+        channel: None,
+        min_launcher_version: None,
+        parts: vec![],
+        notes: None,
+        notes_url: None,
+        tags: vec![],
+    })
+}
+
+/// Writes the raw Ed25519 signature of the manifest, which can be checked
+/// with `openssl pkeyutl -verify -rawin`.
+pub(super) fn sign_manifest(
+    manifest: &[u8],
+    signing_key_path: &Path,
+    signature_path: &Path,
+) -> Result<()> {
+    let signing_key_pem = fs::read_to_string(signing_key_path)
+        .with_context(|| format!("Failed to read '{}'", signing_key_path.display()))?;
+    let signing_key = SigningKey::from_pkcs8_pem(&signing_key_pem)
+        .map_err(|e| anyhow!("Invalid signing key: {}", e))?;
+    let signature = signing_key.sign(manifest);
+    fs::write(signature_path, signature.to_bytes())
+        .with_context(|| format!("Failed to write '{}'", signature_path.display()))
+}
+
+/// Uploads files with the tools of the storage's provider, which have to be
+/// installed and configured (`aws` for S3, `sftp` for SFTP).
+pub(super) fn upload_files(upload_url: &str, file_paths: &[PathBuf]) -> Result<()> {
+    let url = Url::parse(upload_url).context("Invalid upload URL")?;
+    let file_names = file_paths
+        .iter()
+        .map(|file_path| {
+            file_path
+                .file_name()
+                .and_then(|file_name| file_name.to_str())
+                .ok_or_else(|| anyhow!("Invalid file name '{}'", file_path.display()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    match url.scheme() {
+        "s3" => {
+            for (file_path, file_name) in file_paths.iter().zip(file_names) {
+                let destination = format!("{}/{}", upload_url.trim_end_matches('/'), file_name);
+                let mut command = Command::new("aws");
+                command.arg("s3").arg("cp").arg(file_path).arg(destination);
+                run_upload_command(command, None)?;
+            }
+            Ok(())
+        }
+        "sftp" => {
+            let host = url
+                .host_str()
+                .ok_or_else(|| anyhow!("The upload URL has no host"))?;
+            let destination = match url.username() {
+                "" => host.to_string(),
+                user_name => format!("{}@{}", user_name, host),
+            };
+            let mut command = Command::new("sftp");
+            if let Some(port) = url.port() {
+                command.arg("-P").arg(port.to_string());
+            }
+            command.arg("-b").arg("-").arg(destination);
+            let remote_directory = url.path().trim_end_matches('/');
+            let batch: String = file_paths
+                .iter()
+                .zip(file_names)
+                .map(|(file_path, file_name)| {
+                    format!(
+                        "put \"{}\" \"{}/{}\"\n",
+                        file_path.display(),
+                        remote_directory,
+                        file_name
+                    )
+                })
+                .collect();
+            run_upload_command(command, Some(batch))
+        }
+        scheme => Err(anyhow!("Unsupported upload URL scheme '{}'", scheme)),
+    }
+}
+
+fn run_upload_command(mut command: Command, input: Option<String>) -> Result<()> {
+    let program = command.get_program().to_string_lossy().to_string();
+    if input.is_some() {
+        command.stdin(Stdio::piped());
+    }
+    let mut child = command
+        .spawn()
+        .with_context(|| format!("Failed to run '{}'", program))?;
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        stdin.write_all(input.as_bytes())?;
+    }
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(anyhow!("'{}' failed ({})", program, status));
+    }
+    Ok(())
+}
+
+pub(super) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::pkcs8::EncodePrivateKey;
+    use ed25519_dalek::{Signature, Verifier};
+    use tempfile::tempdir;
+
+    fn write_file(path: PathBuf, content: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    #[test]
+    fn test_diff_client_directories() {
+        let temp_dir = tempdir().unwrap();
+        let previous = temp_dir.path().join("previous");
+        let client = temp_dir.path().join("client");
+        write_file(previous.join("data/unchanged.txt"), "same");
+        write_file(client.join("data/unchanged.txt"), "same");
+        write_file(previous.join("data/modified.txt"), "old");
+        write_file(client.join("data/modified.txt"), "new");
+        write_file(previous.join("removed.dll"), "gone");
+        write_file(client.join("added.exe"), "added");
+
+        let changes = diff_client_directories(&client, Some(&previous)).unwrap();
+        assert_eq!(
+            changes,
+            [
+                ClientChange::Updated("added.exe".to_string()),
+                ClientChange::Updated("data\\modified.txt".to_string()),
+                ClientChange::Removed("removed.dll".to_string()),
+            ]
+        );
+        // Without a previous release
+        assert_eq!(diff_client_directories(&client, None).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_publish_release() {
+        let temp_dir = tempdir().unwrap();
+        let previous = temp_dir.path().join("previous");
+        let client = temp_dir.path().join("client");
+        let server = temp_dir.path().join("server");
+        write_file(previous.join("data/modified.txt"), "old");
+        write_file(client.join("data/modified.txt"), "new");
+        write_file(previous.join("removed.dll"), "gone");
+        write_file(server.join(PATCH_LIST_FILE_NAME), "1 old.thor");
+        let signing_key = SigningKey::from_bytes(&[7; 32]);
+        let signing_key_path = temp_dir.path().join("key.pem");
+        let signing_key_pem = signing_key.to_pkcs8_pem(Default::default()).unwrap();
+        fs::write(&signing_key_path, signing_key_pem.as_bytes()).unwrap();
+
+        let options = PublishOptions {
+            client_directory: client.clone(),
+            previous_client_directory: Some(previous),
+            patch_server_directory: server.clone(),
+            target_grf_name: Some("data.grf".to_string()),
+            signing_key_path: Some(signing_key_path),
+            upload_url: None,
+            channel: Some("beta".to_string()),
+            min_patcher_version: Some("1.2.0".to_string()),
+        };
+        let published_patch = publish_release(&options).unwrap();
+        assert_eq!(published_patch.index, 2);
+        assert_eq!(published_patch.updated_files, 1);
+        assert_eq!(published_patch.removed_files, 1);
+
+        let patch_list = fs::read_to_string(server.join(PATCH_LIST_FILE_NAME)).unwrap();
+        assert_eq!(
+            patch_list,
+            format!("1 old.thor\n2 {}\n", published_patch.file_name)
+        );
+        let patch_path = server.join(&published_patch.file_name);
+        let mut thor_archive = ThorArchive::open(&patch_path).unwrap();
+        assert!(thor_archive.is_valid().unwrap());
+        assert_eq!(thor_archive.target_grf_name(), "data.grf");
+        assert_eq!(
+            thor_archive
+                .read_file_content("data\\modified.txt")
+                .unwrap(),
+            b"new"
+        );
+        assert!(
+            thor_archive
+                .get_file_entry("removed.dll")
+                .unwrap()
+                .is_removed
+        );
+
+        // The missing 'old.thor' isn't listed
+        let manifest = fs::read(server.join(MANIFEST_FILE_NAME)).unwrap();
+        let parsed_manifest: PatchManifest = serde_json::from_slice(&manifest).unwrap();
+        assert_eq!(parsed_manifest.patches.len(), 1);
+        let entry = &parsed_manifest.patches[0];
+        assert_eq!(entry.index, 2);
+        assert_eq!(entry.size, fs::metadata(&patch_path).unwrap().len());
+        assert_eq!(
+            entry.sha256,
+            to_hex(&Sha256::digest(fs::read(&patch_path).unwrap()))
+        );
+        assert_eq!(entry.target_grf.as_deref(), Some("data.grf"));
+        assert_eq!(entry.channel.as_deref(), Some("beta"));
+        assert_eq!(
+            parsed_manifest.min_patcher_version.as_deref(),
+            Some("1.2.0")
+        );
+        let signature = fs::read(server.join(SIGNATURE_FILE_NAME)).unwrap();
+        let signature = Signature::from_slice(&signature).unwrap();
+        assert!(signing_key
+            .verifying_key()
+            .verify(&manifest, &signature)
+            .is_ok());
+
+        // Nothing changed since the last release
+        let options = PublishOptions {
+            previous_client_directory: Some(client),
+            signing_key_path: None,
+            ..options
+        };
+        assert!(publish_release(&options).is_err());
+    }
+}
@@ -0,0 +1,151 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use super::config::ControlApiConfiguration;
+use crate::status::PatchingStatus;
+
+/// Response of the control API's `GET /status` endpoint.
+#[derive(Deserialize)]
+struct StatusSnapshot {
+    status: RemoteStatus,
+    file_download: Option<RemoteFileDownload>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+enum RemoteStatus {
+    Downloading {
+        downloaded: usize,
+        total: usize,
+        bytes_per_sec: u64,
+    },
+    Installing {
+        installed: usize,
+        total: usize,
+    },
+    Error {
+        message: String,
+    },
+    // Other states don't tell how far the update is
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize)]
+struct RemoteFileDownload {
+    file_name: String,
+    downloaded_bytes: u64,
+    total_bytes: u64,
+    overall_downloaded_bytes: u64,
+}
+
+/// Update run by another instance of the patcher, as reported by its control
+/// API.
+pub(super) struct RemoteUpdate {
+    pub statuses: Vec<PatchingStatus>, // Progress of the update, to show as it is
+    pub error: Option<String>,         // Set if the last update failed
+}
+
+/// Fetches the state of the update run by the instance of the patcher
+/// serving the control API described by `config`.
+pub(super) async fn fetch_remote_update(
+    client: &reqwest::Client,
+    config: &ControlApiConfiguration,
+) -> Result<RemoteUpdate> {
+    let status_url = format!("http://127.0.0.1:{}/status", config.port());
+    let mut request = client.get(&status_url);
+    if let Some(token) = &config.token {
+        request = request.bearer_auth(token);
+    }
+    let resp = request
+        .send()
+        .await
+        .with_context(|| format!("Failed to GET '{}'", status_url))?
+        .error_for_status()?;
+    let snapshot = serde_json::from_str(&resp.text().await?)
+        .with_context(|| "Failed to parse the other instance's status")?;
+    Ok(remote_update_from_snapshot(snapshot))
+}
+
+fn remote_update_from_snapshot(snapshot: StatusSnapshot) -> RemoteUpdate {
+    let mut statuses = vec![];
+    let mut error = None;
+    match snapshot.status {
+        RemoteStatus::Downloading {
+            downloaded,
+            total,
+            bytes_per_sec,
+        } => statuses.push(PatchingStatus::DownloadInProgress(
+            downloaded,
+            total,
+            bytes_per_sec,
+        )),
+        RemoteStatus::Installing { installed, total } => {
+            statuses.push(PatchingStatus::InstallationInProgress(installed, total))
+        }
+        RemoteStatus::Error { message } => error = Some(message),
+        RemoteStatus::Other => {}
+    }
+    if let Some(file_download) = snapshot.file_download {
+        statuses.push(PatchingStatus::FileDownloadInProgress {
+            file_name: file_download.file_name,
+            downloaded_bytes: file_download.downloaded_bytes,
+            total_bytes: file_download.total_bytes,
+            overall_downloaded_bytes: file_download.overall_downloaded_bytes,
+        });
+    }
+    RemoteUpdate { statuses, error }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_snapshot(json: &str) -> RemoteUpdate {
+        remote_update_from_snapshot(serde_json::from_str(json).unwrap())
+    }
+
+    #[test]
+    fn test_remote_update_from_snapshot() {
+        let update = parse_snapshot(
+            r#"{
+                "status": {"state": "downloading", "downloaded": 1, "total": 3, "bytes_per_sec": 42},
+                "file_download": {
+                    "file_name": "2.thor",
+                    "downloaded_bytes": 10,
+                    "total_bytes": 20,
+                    "overall_downloaded_bytes": 30
+                }
+            }"#,
+        );
+        assert!(update.error.is_none());
+        assert!(matches!(
+            update.statuses[..],
+            [
+                PatchingStatus::DownloadInProgress(1, 3, 42),
+                PatchingStatus::FileDownloadInProgress {
+                    downloaded_bytes: 10,
+                    ..
+                }
+            ]
+        ));
+
+        let update = parse_snapshot(
+            r#"{"status": {"state": "installing", "installed": 2, "total": 3}, "file_download": null}"#,
+        );
+        assert!(matches!(
+            update.statuses[..],
+            [PatchingStatus::InstallationInProgress(2, 3)]
+        ));
+
+        let update = parse_snapshot(
+            r#"{"status": {"state": "error", "message": "Disk full", "causes": []}, "file_download": null}"#,
+        );
+        assert!(update.statuses.is_empty());
+        assert_eq!(update.error.as_deref(), Some("Disk full"));
+
+        let update = parse_snapshot(r#"{"status": {"state": "ready"}, "file_download": null}"#);
+        assert!(update.statuses.is_empty());
+        assert!(update.error.is_none());
+    }
+}
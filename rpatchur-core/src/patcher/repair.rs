@@ -0,0 +1,452 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+
+use anyhow::{anyhow, Context, Result};
+use gruf::grf::GrfArchive;
+use gruf::thor::ThorArchive;
+use tokio::sync::mpsc::UnboundedReceiver;
+
+use super::cancellation::{wait_for_cancellation, InterruptibleFnError};
+use super::core::{
+    build_http_client, last_applied_patch_index, wait_for_game_client_exit, UiController,
+};
+use super::download::{download_patches_concurrent, StagingDirectory};
+use super::installer::download_client_grf_archives;
+use super::patch_servers::{find_available_patch_server, manifest_entries};
+use super::patching::{apply_patch_to_grf, GrfPatchingMethod};
+use super::paths::{get_peer_cache_directory_path, is_plain_file_name};
+use super::publish::PatchManifestEntry;
+use super::update_lock::take_update_lock;
+use super::zip::is_zip_file;
+use super::{PatcherCommand, PatcherConfiguration};
+use crate::status::PatchingStatus;
+
+/// Returns the paths of the files of the GRF at `grf_path` which can't be
+/// read back.
+///
+/// Fails if the GRF's header or file table can't be read at all.
+fn find_unreadable_grf_entries(grf_path: &Path) -> Result<Vec<String>> {
+    let mut grf_archive = GrfArchive::open(grf_path)?;
+    let relative_paths: Vec<String> = grf_archive
+        .get_entries()
+        .map(|entry| entry.relative_path.clone())
+        .collect();
+    Ok(relative_paths
+        .into_iter()
+        .filter(|relative_path| grf_archive.read_file_content(relative_path).is_err())
+        .collect())
+}
+
+/// Tells whether the archive of a manifest's `entry` may contain files of
+/// the GRF named `grf_name`, without downloading it.
+///
+/// Entries without a target GRF either patch the game directory or the
+/// default GRF.
+pub(super) fn may_target_grf(
+    entry: &PatchManifestEntry,
+    grf_name: &str,
+    default_grf_name: &str,
+) -> bool {
+    entry
+        .target_grf
+        .as_deref()
+        .unwrap_or(default_grf_name)
+        .eq_ignore_ascii_case(grf_name)
+}
+
+/// Builds the GRF at `grf_path` again from scratch, out of the client's
+/// archives (`client_archive_paths`) and then the patches (`patch_paths`, in
+/// the order they were applied) which target it.
+///
+/// Fails if none of the client's archives targets the GRF, as a GRF built
+/// from the patches alone would lack every file of the base client. The GRF
+/// is only replaced once the new one is complete. Returns the number of
+/// archives which have been merged into it.
+fn rebuild_grf(
+    grf_path: &Path,
+    default_grf_name: &str,
+    client_archive_paths: &[PathBuf],
+    patch_paths: &[PathBuf],
+    mut progress_callback: impl FnMut(usize, usize),
+) -> Result<usize> {
+    let grf_file_name = grf_path
+        .file_name()
+        .context("Invalid GRF path")?
+        .to_string_lossy()
+        .to_string();
+    let grf_directory = match grf_path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    // Next to the GRF, so that it can be renamed over it
+    let rebuild_directory = tempfile::Builder::new()
+        .prefix("repair-")
+        .tempdir_in(grf_directory)
+        .with_context(|| "Failed to create the repair directory")?;
+    let rebuilt_grf_path = rebuild_directory.path().join(&grf_file_name);
+    let archive_count = client_archive_paths.len() + patch_paths.len();
+    let mut merged_archive_count = 0;
+    for (i, archive_path) in client_archive_paths.iter().enumerate() {
+        progress_callback(i, archive_count);
+        if merge_archive(archive_path, &rebuilt_grf_path, default_grf_name)? {
+            merged_archive_count += 1;
+        }
+    }
+    // Replacing the GRF with one missing the client's files would break the
+    // game for good
+    if merged_archive_count == 0 {
+        return Err(anyhow!(
+            "None of the client's archives contains files of '{}'",
+            grf_file_name
+        ));
+    }
+    for (i, archive_path) in patch_paths.iter().enumerate() {
+        progress_callback(client_archive_paths.len() + i, archive_count);
+        if merge_archive(archive_path, &rebuilt_grf_path, default_grf_name)? {
+            merged_archive_count += 1;
+        }
+    }
+    progress_callback(archive_count, archive_count);
+    fs::rename(&rebuilt_grf_path, grf_path)
+        .with_context(|| format!("Failed to replace '{}'", grf_path.display()))?;
+    Ok(merged_archive_count)
+}
+
+/// Merges the archive at `archive_path` into the GRF at `grf_path`, if it
+/// targets it. Returns whether it did.
+fn merge_archive(archive_path: &Path, grf_path: &Path, default_grf_name: &str) -> Result<bool> {
+    // ZIP patches are always extracted to the game client's directory
+    if is_zip_file(archive_path) {
+        return Ok(false);
+    }
+    let mut thor_archive = ThorArchive::open(archive_path)
+        .with_context(|| format!("Failed to open '{}'", archive_path.display()))?;
+    if !thor_archive.use_grf_merging() {
+        return Ok(false);
+    }
+    let target_grf_name = if thor_archive.target_grf_name().is_empty() {
+        default_grf_name.to_string()
+    } else {
+        thor_archive.target_grf_name()
+    };
+    let grf_file_name = grf_path.file_name().unwrap_or_default().to_string_lossy();
+    if !target_grf_name.eq_ignore_ascii_case(&grf_file_name) {
+        return Ok(false);
+    }
+    // The GRF is new, there's nothing to preserve by patching out of place.
+    // Repairs can't be resumed anyway.
+    apply_patch_to_grf(
+        GrfPatchingMethod::InPlace,
+        true,
+        grf_path,
+        &mut thor_archive,
+        &AtomicBool::new(false),
+    )
+    .with_context(|| format!("Failed to merge '{}'", archive_path.display()))?;
+    Ok(true)
+}
+
+/// Checks the GRF named `grf_name` and rebuilds it from scratch if some of
+/// its files can't be read (e.g. after the game crashed while it was being
+/// patched), so that players don't have to reinstall the whole game.
+pub(super) async fn repair_grf(
+    config: &PatcherConfiguration,
+    ui_controller: &UiController,
+    patching_thread_rx: &mut UnboundedReceiver<PatcherCommand>,
+    grf_name: &str,
+) {
+    let lock_file = match take_update_lock().with_context(|| "Failed to take the update lock") {
+        Ok(lock_file) => lock_file,
+        Err(err) => {
+            tracing::error!("{:#}", err);
+            ui_controller.dispatch_patching_status(PatchingStatus::Error((&err).into()));
+            return;
+        }
+    };
+    // Tell the UI and other processes that we're currently working
+    ui_controller.set_patching_in_progress(true);
+    let _guard = scopeguard::guard((), |_| {
+        drop(lock_file);
+        ui_controller.set_patching_in_progress(false);
+    });
+
+    match check_and_rebuild_grf(config, ui_controller, patching_thread_rx, grf_name).await {
+        Ok(rebuilt) => {
+            if rebuilt {
+                tracing::info!("'{}' has been rebuilt", grf_name);
+            } else {
+                tracing::info!("'{}' is intact", grf_name);
+            }
+            ui_controller.dispatch_patching_status(PatchingStatus::GrfRepaired(
+                grf_name.to_string(),
+                rebuilt,
+            ));
+        }
+        Err(err) => {
+            tracing::error!("Failed to repair '{}': {:#}", grf_name, err);
+            ui_controller.dispatch_patching_status(PatchingStatus::Error((&err).into()));
+        }
+    }
+}
+
+/// Returns whether the GRF had to be rebuilt.
+async fn check_and_rebuild_grf(
+    config: &PatcherConfiguration,
+    ui_controller: &UiController,
+    patching_thread_rx: &mut UnboundedReceiver<PatcherCommand>,
+    grf_name: &str,
+) -> Result<bool> {
+    // GRFs are looked up in the game's directory only
+    if !is_plain_file_name(grf_name) {
+        return Err(anyhow!("Invalid GRF name '{}'", grf_name));
+    }
+    let current_working_dir =
+        env::current_dir().with_context(|| "Failed to resolve current working directory")?;
+    let grf_path = current_working_dir.join(grf_name);
+    // Missing GRFs are rebuilt as well
+    if grf_path.exists() {
+        match find_unreadable_grf_entries(&grf_path) {
+            Ok(unreadable_entries) if unreadable_entries.is_empty() => return Ok(false),
+            Ok(unreadable_entries) => tracing::warn!(
+                "{} file(s) of '{}' can't be read",
+                unreadable_entries.len(),
+                grf_name
+            ),
+            Err(e) => tracing::warn!("'{}' can't be read: {:#}", grf_name, e),
+        }
+    }
+
+    // The patches alone would lose the files of the base client
+    let installer_config = config.installer.as_ref().ok_or_else(|| {
+        anyhow!(
+            "'{}' can't be rebuilt without the client's archives, which requires an installer",
+            grf_name
+        )
+    })?;
+    let staging_directory = StagingDirectory::create(config.patching.staging_directory())
+        .with_context(|| "Failed to create staging directory")?;
+    // The client's archives and the patches may have the same names
+    let client_directory = staging_directory.path().join("client");
+    std::fs::create_dir_all(&client_directory)?;
+    let dispatch_status = |status| ui_controller.dispatch_patching_status(status);
+    let client_archive_paths = tokio::select! {
+        _ = wait_for_cancellation(patching_thread_rx) => {
+            return Err(anyhow!("Repair was canceled"));
+        }
+        download_res = download_client_grf_archives(
+            config,
+            installer_config,
+            grf_name,
+            &client_directory,
+            dispatch_status,
+        ) => download_res?,
+    };
+    let patch_paths = gather_applied_grf_patches(
+        config,
+        ui_controller,
+        patching_thread_rx,
+        grf_name,
+        staging_directory.path(),
+    )
+    .await?;
+
+    // Replacing a GRF the game client holds open would corrupt it
+    wait_for_game_client_exit(config, ui_controller, patching_thread_rx)
+        .await
+        .map_err(|e| match e {
+            InterruptibleFnError::Err(e) => e,
+            InterruptibleFnError::Interrupted => anyhow!("Repair was canceled"),
+        })?;
+    tracing::info!(
+        "Rebuilding '{}' out of {} archive(s)",
+        grf_name,
+        client_archive_paths.len() + patch_paths.len()
+    );
+    rebuild_grf(
+        &grf_path,
+        &config.client.default_grf_name,
+        &client_archive_paths,
+        &patch_paths,
+        |merged_count, archive_count| {
+            ui_controller.dispatch_patching_status(PatchingStatus::InstallationInProgress(
+                merged_count,
+                archive_count,
+            ))
+        },
+    )?;
+    Ok(true)
+}
+
+/// Gathers the patches applied to the game which may contain files of the
+/// GRF named `grf_name`, from the peer cache for those kept there, and
+/// downloads the other ones to `download_directory`.
+///
+/// Returns their paths, in the order they've been applied in.
+async fn gather_applied_grf_patches(
+    config: &PatcherConfiguration,
+    ui_controller: &UiController,
+    patching_thread_rx: &mut UnboundedReceiver<PatcherCommand>,
+    grf_name: &str,
+    download_directory: &Path,
+) -> Result<Vec<PathBuf>> {
+    let last_patch_index = match last_applied_patch_index(config) {
+        Some(last_patch_index) => last_patch_index,
+        // The next update will apply all of them anyway
+        None => return Ok(vec![]),
+    };
+    let client = build_http_client(&config.web)?;
+    let listing = find_available_patch_server(
+        &client,
+        &config.web,
+        config.patching.channel(),
+        patching_thread_rx,
+    )
+    .await
+    .map_err(|e| match e {
+        InterruptibleFnError::Err(e) => e,
+        InterruptibleFnError::Interrupted => anyhow!("Repair was canceled"),
+    })?;
+    let patch_manifest = manifest_entries(listing.manifest.as_ref());
+    let mut patch_list = listing.patch_list;
+    patch_list.retain(|patch_info| patch_info.index <= last_patch_index);
+
+    // Patches which aren't listed in the manifest are checked once downloaded
+    let peer_cache_directory = get_peer_cache_directory_path()?;
+    let mut patch_paths = vec![];
+    patch_list.retain(|patch_info| {
+        let entry = match patch_manifest.get(&patch_info.file_name) {
+            Some(entry) => entry,
+            None => return true,
+        };
+        if !may_target_grf(entry, grf_name, &config.client.default_grf_name) {
+            return false;
+        }
+        let cached_patch_path = peer_cache_directory.join(entry.sha256.to_ascii_lowercase());
+        if cached_patch_path.is_file() {
+            patch_paths.push((patch_info.index, cached_patch_path));
+            return false;
+        }
+        true
+    });
+
+    let (downloaded_patches, failed_patches) = download_patches_concurrent(
+        &client,
+        listing.patch_url,
+        &patch_manifest,
+        patch_list,
+        download_directory,
+        config,
+        ui_controller,
+        patching_thread_rx,
+    )
+    .await
+    .map_err(|e| match e {
+        InterruptibleFnError::Err(e) => e.context("Failed to download patches"),
+        InterruptibleFnError::Interrupted => anyhow!("Repair was canceled"),
+    })?;
+    // The GRF would miss some of its files otherwise
+    if let Some(failed_patch) = failed_patches.into_iter().next() {
+        return Err(failed_patch.error.context(format!(
+            "Failed to download '{}'",
+            failed_patch.info.file_name
+        )));
+    }
+    patch_paths.extend(
+        downloaded_patches
+            .into_iter()
+            .map(|patch| (patch.info.index, patch.local_file_path)),
+    );
+    patch_paths.sort_by_key(|(index, _)| *index);
+    Ok(patch_paths.into_iter().map(|(_, path)| path).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::publish::build_thor_patch;
+    use super::*;
+
+    #[test]
+    fn test_may_target_grf() {
+        let mut entry = PatchManifestEntry {
+            index: 1,
+            file_name: "1.thor".to_string(),
+            ..Default::default()
+        };
+        assert!(may_target_grf(&entry, "data.grf", "data.grf"));
+        assert!(!may_target_grf(&entry, "custom.grf", "data.grf"));
+        entry.target_grf = Some("Custom.grf".to_string());
+        assert!(may_target_grf(&entry, "custom.grf", "data.grf"));
+        assert!(!may_target_grf(&entry, "data.grf", "data.grf"));
+    }
+
+    #[test]
+    fn test_rebuild_grf() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let archive_paths: Vec<PathBuf> = (1..=4)
+            .map(|i| temp_dir.path().join(format!("{}.thor", i)))
+            .collect();
+        build_thor_patch(&archive_paths[0], Some(""), &[("data\\a.txt", b"a")], &[]);
+        build_thor_patch(&archive_paths[1], None, &[("b.txt", b"b")], &[]);
+        build_thor_patch(
+            &archive_paths[2],
+            Some("other.grf"),
+            &[("data\\c.txt", b"c")],
+            &[],
+        );
+        build_thor_patch(
+            &archive_paths[3],
+            Some("data.grf"),
+            &[("data\\a.txt", b"d")],
+            &[],
+        );
+
+        // Corrupt GRF
+        let grf_path = temp_dir.path().join("data.grf");
+        fs::write(&grf_path, b"Master of Magic").unwrap();
+        assert!(find_unreadable_grf_entries(&grf_path).is_err());
+
+        // Patches alone would leave the client's files out
+        assert!(rebuild_grf(&grf_path, "data.grf", &[], &archive_paths, |_, _| {}).is_err());
+        assert!(rebuild_grf(
+            &grf_path,
+            "data.grf",
+            &archive_paths[2..3],
+            &archive_paths,
+            |_, _| {}
+        )
+        .is_err());
+        assert_eq!(fs::read(&grf_path).unwrap(), b"Master of Magic");
+
+        let mut progress = vec![];
+        let merged_archive_count = rebuild_grf(
+            &grf_path,
+            "data.grf",
+            &archive_paths[..2],
+            &archive_paths[2..],
+            |i, n| progress.push((i, n)),
+        )
+        .unwrap();
+        assert_eq!(merged_archive_count, 2);
+        assert_eq!(progress.last(), Some(&(4, 4)));
+        assert!(find_unreadable_grf_entries(&grf_path).unwrap().is_empty());
+        let mut grf_archive = GrfArchive::open(&grf_path).unwrap();
+        assert_eq!(grf_archive.file_count(), 1);
+        assert_eq!(grf_archive.read_file_content("data\\a.txt").unwrap(), b"d");
+        // The repair directory is gone
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 5);
+
+        // Nothing to rebuild 'missing.grf' with, it's left as it is
+        let missing_grf_path = temp_dir.path().join("missing.grf");
+        assert!(rebuild_grf(
+            &missing_grf_path,
+            "data.grf",
+            &archive_paths[..2],
+            &archive_paths[2..],
+            |_, _| {}
+        )
+        .is_err());
+        assert!(!missing_grf_path.exists());
+    }
+}
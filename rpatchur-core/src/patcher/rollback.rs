@@ -0,0 +1,137 @@
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+
+use anyhow::{anyhow, Context, Result};
+use gruf::thor::ThorArchive;
+
+use super::core::{apply_patch, ensure_game_client_is_closed, get_target_grf_name, UiController};
+use super::patching::{create_rollback_patch, create_zip_rollback_patch};
+use super::paths::get_rollback_directory_path;
+use super::state::{retrieve_patcher_state, update_patcher_state};
+use super::zip::{is_zip_file, ZipArchive};
+use super::PatcherConfiguration;
+use crate::status::PatchingStatus;
+
+/// Saves the patch which reverts the changes of the patch with index
+/// `patch_index`, so that the game can be rolled back to an earlier patch.
+pub(super) fn save_rollback_patch(
+    thor_archive_path: impl AsRef<Path>,
+    patch_index: usize,
+    config: &PatcherConfiguration,
+    current_working_dir: impl AsRef<Path>,
+) -> Result<()> {
+    let rollback_patch_path = get_rollback_patch_path(config, patch_index)?;
+    if let Some(rollback_directory) = rollback_patch_path.parent() {
+        std::fs::create_dir_all(rollback_directory)?;
+    }
+    let res = if is_zip_file(thor_archive_path.as_ref()) {
+        let zip_archive = ZipArchive::open(thor_archive_path.as_ref())?;
+        create_zip_rollback_patch(current_working_dir, &zip_archive, &rollback_patch_path)
+    } else {
+        let thor_archive = ThorArchive::open(thor_archive_path.as_ref())?;
+        let target_grf_name = if thor_archive.use_grf_merging() {
+            Some(get_target_grf_name(&thor_archive, config))
+        } else {
+            None
+        };
+        create_rollback_patch(
+            current_working_dir,
+            target_grf_name.as_deref(),
+            &thor_archive,
+            &rollback_patch_path,
+        )
+    };
+    if res.is_err() {
+        let _ = std::fs::remove_file(&rollback_patch_path);
+    }
+    res
+}
+
+fn get_rollback_patch_path(config: &PatcherConfiguration, patch_index: usize) -> Result<PathBuf> {
+    Ok(get_rollback_directory_path()
+        .with_context(|| "Failed to resolve patcher name")?
+        .join(config.web.server_id())
+        .join(format!("{}.thor", patch_index)))
+}
+
+/// Reverts the patches applied after the pinned patch, with the rollback
+/// patches saved when they were applied.
+///
+/// Nothing is reverted unless all of these patches can be.
+pub(super) fn roll_back_to_pinned_index(
+    config: &PatcherConfiguration,
+    ui_controller: &UiController,
+) -> Result<()> {
+    let pin_index = match config.patching.pin_index {
+        Some(pin_index) => pin_index,
+        None => return Ok(()),
+    };
+    let server_id = config.web.server_id();
+    let state = retrieve_patcher_state()?;
+    let mut applied_indices: Vec<usize> = state
+        .applied_patches
+        .iter()
+        .filter(|applied_patch| applied_patch.server_id.as_deref() == Some(server_id.as_str()))
+        .filter_map(|applied_patch| applied_patch.index)
+        .collect();
+    applied_indices.sort_unstable();
+    applied_indices.dedup();
+    let kept_count = applied_indices.partition_point(|&index| index <= pin_index);
+    // Already applied patches aren't applied again after the rollback
+    let kept_index = applied_indices[..kept_count]
+        .last()
+        .copied()
+        .unwrap_or(pin_index);
+    let reverted_indices: Vec<usize> = applied_indices[kept_count..]
+        .iter()
+        .rev()
+        .copied()
+        .collect();
+    if reverted_indices.is_empty() {
+        return Ok(());
+    }
+    let mut rollback_patch_paths = Vec::with_capacity(reverted_indices.len());
+    for &index in &reverted_indices {
+        let rollback_patch_path = get_rollback_patch_path(config, index)?;
+        if !rollback_patch_path.is_file() {
+            return Err(anyhow!(
+                "Can't roll back to patch {}, patch {} can't be reverted",
+                pin_index,
+                index
+            ));
+        }
+        rollback_patch_paths.push(rollback_patch_path);
+    }
+
+    ensure_game_client_is_closed(config)?;
+    let current_working_dir =
+        env::current_dir().with_context(|| "Failed to resolve current working directory")?;
+    tracing::info!("Rolling back to patch {}", pin_index);
+    let patch_count = reverted_indices.len();
+    ui_controller.dispatch_patching_status(PatchingStatus::InstallationInProgress(0, patch_count));
+    for (patch_number, (&index, rollback_patch_path)) in reverted_indices
+        .iter()
+        .zip(&rollback_patch_paths)
+        .enumerate()
+    {
+        apply_patch(
+            rollback_patch_path,
+            config,
+            &current_working_dir,
+            &AtomicBool::new(false),
+        )
+        .with_context(|| format!("Failed to roll back patch {}", index))?;
+        let last_patch_index = reverted_indices
+            .get(patch_number + 1)
+            .copied()
+            .unwrap_or(kept_index);
+        update_patcher_state(|state| state.record_rollback(&server_id, last_patch_index))?;
+        let _ = std::fs::remove_file(rollback_patch_path);
+        ui_controller.dispatch_patching_status(PatchingStatus::InstallationInProgress(
+            1 + patch_number,
+            patch_count,
+        ));
+    }
+    Ok(())
+}
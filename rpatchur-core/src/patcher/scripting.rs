@@ -0,0 +1,277 @@
+use std::fs::File;
+use std::path::Path;
+use std::process::Command;
+
+use anyhow::{Context, Result};
+use rhai::{Array, CallFnOptions, Dynamic, Engine, FuncArgs, Map, Scope, AST};
+
+use super::config::ScriptingConfiguration;
+use super::error::ErrorKind;
+use super::paths::get_configuration_file_path;
+
+/// Functions of the operator's script which are called at specific points
+/// (e.g. `fn before_update()`), to customize the patcher without forking it.
+///
+/// Missing functions are simply skipped, and errors raised by the script are
+/// logged without affecting the patcher.
+pub struct ScriptHooks {
+    script: Option<(Engine, AST)>,
+}
+
+impl ScriptHooks {
+    /// Compiles the script configured by the operator, if any.
+    pub fn load(config: &ScriptingConfiguration) -> Self {
+        let script = config.path.as_ref().and_then(|script_path| {
+            let configuration = read_raw_configuration().unwrap_or_else(|e| {
+                tracing::warn!("Failed to read the configuration for scripts: {:#}", e);
+                Dynamic::UNIT
+            });
+            let engine = build_engine(configuration);
+            match engine.compile_file(script_path.into()) {
+                Ok(ast) => Some((engine, ast)),
+                Err(e) => {
+                    tracing::error!("Failed to load script '{}': {}", script_path, e);
+                    None
+                }
+            }
+        });
+        Self { script }
+    }
+
+    /// Called before updating the game, which is skipped if the script
+    /// returns `false`.
+    pub fn before_update(&self) -> bool {
+        match self.call("before_update", ()) {
+            Some(result) => result.as_bool().unwrap_or(true),
+            None => true,
+        }
+    }
+
+    /// Called once a patch has been applied.
+    pub fn after_patch(&self, patch_name: &str, patch_index: Option<usize>) {
+        let patch_index = patch_index.map_or(Dynamic::UNIT, |index| Dynamic::from(index as i64));
+        self.call("after_patch", (patch_name.to_string(), patch_index));
+    }
+
+    /// Called before starting a program, whose arguments are replaced by the
+    /// array returned by the script, if any.
+    pub fn before_launch(&self, label: &str, path: &str, arguments: Vec<String>) -> Vec<String> {
+        let script_arguments: Array = arguments.iter().cloned().map(Dynamic::from).collect();
+        let result = self.call(
+            "before_launch",
+            (label.to_string(), path.to_string(), script_arguments),
+        );
+        match result.map(|result| result.try_cast::<Array>()) {
+            Some(Some(script_arguments)) => script_arguments
+                .into_iter()
+                .map(|argument| argument.to_string())
+                .collect(),
+            _ => arguments,
+        }
+    }
+
+    /// Called when updating the game failed.
+    pub fn on_error(&self, error: &anyhow::Error) {
+        let message = format!("{:#}", error);
+        let kind = ErrorKind::of(error).name().to_string();
+        self.call("on_error", (message, kind));
+    }
+
+    /// Calls the script's function named `name`, if it's defined with the
+    /// right number of parameters.
+    fn call(&self, name: &str, args: impl FuncArgs) -> Option<Dynamic> {
+        let (engine, ast) = self.script.as_ref()?;
+        let mut arg_values = Vec::new();
+        args.parse(&mut arg_values);
+        let is_defined = ast
+            .iter_functions()
+            .any(|f| f.name == name && f.params.len() == arg_values.len());
+        if !is_defined {
+            return None;
+        }
+        // Top-level statements are only meant to define functions
+        let options = CallFnOptions::new().eval_ast(false);
+        let result = engine.call_fn_with_options::<Dynamic>(
+            options,
+            &mut Scope::new(),
+            ast,
+            name,
+            arg_values,
+        );
+        match result {
+            Ok(result) => Some(result),
+            Err(e) => {
+                tracing::warn!("Script hook '{}' failed: {}", name, e);
+                None
+            }
+        }
+    }
+}
+
+/// Builds the engine which runs the operator's script, along with the API
+/// available to it:
+///
+/// - `config(path)` returns a value of the configuration file (e.g.
+///   `config("web.patch_servers")`), `()` if it's not set.
+/// - `run(program, arguments)` runs a program to completion and returns its
+///   exit code, -1 if it couldn't be run.
+/// - `print(message)` writes a message to the logs.
+fn build_engine(configuration: Dynamic) -> Engine {
+    let mut engine = Engine::new();
+    engine.on_print(|message| tracing::info!("[script] {}", message));
+    engine.on_debug(|message, _, position| tracing::debug!("[script] {}: {}", position, message));
+    engine.register_fn("config", move |path: &str| {
+        config_value(&configuration, path)
+    });
+    engine.register_fn("run", |program: &str, arguments: Array| {
+        run_program(program, arguments)
+    });
+    engine.register_fn("run", |program: &str| run_program(program, Array::new()));
+    engine
+}
+
+/// Looks a value up in the configuration, by its dot-separated path.
+fn config_value(configuration: &Dynamic, path: &str) -> Dynamic {
+    let mut value = configuration.clone();
+    for key in path.split('.') {
+        value = match value.try_cast::<Map>() {
+            Some(map) => map.get(key).cloned().unwrap_or(Dynamic::UNIT),
+            None => return Dynamic::UNIT,
+        };
+    }
+    value
+}
+
+fn run_program(program: &str, arguments: Array) -> i64 {
+    let arguments: Vec<String> = arguments.into_iter().map(|a| a.to_string()).collect();
+    match Command::new(program).args(&arguments).status() {
+        Ok(status) => status.code().map_or(-1, i64::from),
+        Err(e) => {
+            tracing::warn!("Script failed to run '{}': {}", program, e);
+            -1
+        }
+    }
+}
+
+/// Reads the configuration file as is, so that scripts can use the values
+/// the patcher doesn't know about as well.
+fn read_raw_configuration() -> Result<Dynamic> {
+    let config_file_path = get_configuration_file_path()?;
+    read_yaml_file(&config_file_path)
+}
+
+fn read_yaml_file(file_path: &Path) -> Result<Dynamic> {
+    let file = File::open(file_path)
+        .with_context(|| format!("Failed to open '{}'", file_path.display()))?;
+    let value: serde_yaml::Value = serde_yaml::from_reader(file)?;
+    yaml_to_dynamic(value)
+}
+
+fn yaml_to_dynamic(value: serde_yaml::Value) -> Result<Dynamic> {
+    let value = match value {
+        serde_yaml::Value::Null => Dynamic::UNIT,
+        serde_yaml::Value::Bool(value) => Dynamic::from(value),
+        serde_yaml::Value::Number(number) => match number.as_i64() {
+            Some(value) => Dynamic::from(value),
+            None => Dynamic::from(number.as_f64().unwrap_or_default()),
+        },
+        serde_yaml::Value::String(value) => Dynamic::from(value),
+        serde_yaml::Value::Sequence(values) => Dynamic::from(
+            values
+                .into_iter()
+                .map(yaml_to_dynamic)
+                .collect::<Result<Array>>()?,
+        ),
+        serde_yaml::Value::Mapping(mapping) => {
+            let mut map = Map::new();
+            for (key, value) in mapping {
+                let key = match key {
+                    serde_yaml::Value::String(key) => key,
+                    key => serde_yaml::to_string(&key)?
+                        .trim_start_matches("---")
+                        .trim()
+                        .to_string(),
+                };
+                map.insert(key.into(), yaml_to_dynamic(value)?);
+            }
+            Dynamic::from(map)
+        }
+    };
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn load_script(script: &str, configuration: &str) -> ScriptHooks {
+        let temp_dir = tempdir().unwrap();
+        let script_path = temp_dir.path().join("hooks.rhai");
+        let config_path = temp_dir.path().join("rpatchur.yml");
+        fs::write(&script_path, script).unwrap();
+        fs::write(&config_path, configuration).unwrap();
+        let engine = build_engine(read_yaml_file(&config_path).unwrap());
+        let ast = engine.compile_file(script_path).unwrap();
+        ScriptHooks {
+            script: Some((engine, ast)),
+        }
+    }
+
+    #[test]
+    fn test_script_hooks() {
+        let hooks = load_script(
+            r#"
+            fn before_update() {
+                config("patching.in_place")
+            }
+            fn before_launch(label, path, arguments) {
+                if label == "Play" {
+                    arguments.push("-server:" + config("web.patch_servers")[0].name);
+                    arguments
+                }
+            }
+            fn after_patch(name) {
+                throw "Not called, the parameters don't match";
+            }
+            "#,
+            "patching:\n  in_place: false\nweb:\n  patch_servers:\n    - name: main\n",
+        );
+        assert!(!hooks.before_update());
+        assert_eq!(
+            hooks.before_launch("Play", "ragexe.exe", vec!["1rag1".to_string()]),
+            ["1rag1", "-server:main"]
+        );
+        // Arguments are kept as is when nothing is returned
+        assert_eq!(
+            hooks.before_launch("Setup", "Setup.exe", vec!["/s".to_string()]),
+            ["/s"]
+        );
+        hooks.after_patch("1.thor", Some(1));
+        hooks.on_error(&anyhow!("Failed"));
+
+        // Without script
+        let hooks = ScriptHooks { script: None };
+        assert!(hooks.before_update());
+        assert_eq!(
+            hooks.before_launch("Play", "ragexe.exe", vec![]),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn test_config_value() {
+        let configuration = yaml_to_dynamic(
+            serde_yaml::from_str("client:\n  default_grf_name: data.grf\n").unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            config_value(&configuration, "client.default_grf_name").to_string(),
+            "data.grf"
+        );
+        assert!(config_value(&configuration, "client.missing").is_unit());
+        assert!(config_value(&configuration, "client.default_grf_name.x").is_unit());
+    }
+}
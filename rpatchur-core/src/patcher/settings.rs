@@ -0,0 +1,130 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use super::config::STABLE_CHANNEL;
+use super::state::{retrieve_patcher_state, update_patcher_state};
+use super::PatcherConfiguration;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+/// Options that users can change from the settings panel.
+///
+/// These are stored in the patcher's state and override the operator's
+/// configuration. Unset values leave the configuration untouched.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct UserSettings {
+    pub in_place: Option<bool>,
+    pub check_integrity: Option<bool>,
+    pub bandwidth_limit: Option<u64>, // In KB/s, 0 means unlimited
+    pub language: Option<String>,
+    pub auto_start: Option<bool>,
+    pub preview_patches: Option<bool>,
+    pub background_mode: Option<bool>,
+    pub channel: Option<String>, // One of the channels offered by the operator
+    pub window: Option<WindowGeometry>, // Last known window geometry
+    pub account: Option<String>, // Account whose password is stored in the OS' keychain
+    #[serde(default)]
+    pub patch_server: Option<String>, // Name of the patch server to use, picked automatically if not set
+}
+
+/// Position and size of the window, in points.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct WindowGeometry {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    #[serde(default)]
+    pub maximized: bool,
+}
+
+impl UserSettings {
+    /// Overrides the values of `config` with the ones that have been set
+    pub fn apply_to(&self, config: &mut PatcherConfiguration) {
+        if let Some(in_place) = self.in_place {
+            config.patching.in_place = in_place;
+        }
+        if let Some(check_integrity) = self.check_integrity {
+            config.patching.check_integrity = check_integrity;
+        }
+        if let Some(bandwidth_limit) = self.bandwidth_limit {
+            config.patching.bandwidth_limit = Some(bandwidth_limit).filter(|&limit| limit > 0);
+        }
+        if let Some(language) = &self.language {
+            config.localization.language = Some(language.clone()).filter(|l| !l.is_empty());
+        }
+        if let Some(auto_start) = self.auto_start {
+            config.patching.auto_start = auto_start;
+        }
+        if let Some(preview_patches) = self.preview_patches {
+            config.patching.preview_patches = preview_patches;
+        }
+        if let Some(background_mode) = self.background_mode {
+            config.patching.background_mode = background_mode;
+        }
+        // Operators can stop offering a channel
+        if let Some(channel) = &self.channel {
+            if channel == STABLE_CHANNEL || config.patching.channels.contains(channel) {
+                config.patching.channel = Some(channel.clone());
+            }
+        }
+        // Not part of the operator's configuration, so unset values do
+        // override it. Operators can remove servers as well.
+        config.web.forced_patch_server = self.patch_server.clone().filter(|name| {
+            config
+                .web
+                .patch_servers
+                .iter()
+                .any(|server| &server.name == name)
+        });
+    }
+}
+
+/// Reads the user settings, if any.
+pub fn retrieve_user_settings() -> Result<UserSettings> {
+    Ok(retrieve_patcher_state()?.settings)
+}
+
+pub fn save_user_settings(settings: &UserSettings) -> Result<()> {
+    update_patcher_state(|state| state.settings = settings.clone())
+}
+
+pub(super) fn read_user_settings(settings_file_path: impl AsRef<Path>) -> Result<UserSettings> {
+    let file = File::open(settings_file_path)?;
+    serde_yaml::from_reader(BufReader::new(file)).context("Invalid user settings")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_user_settings_round_trip() {
+        let settings = UserSettings {
+            in_place: Some(false),
+            check_integrity: None,
+            bandwidth_limit: Some(512),
+            language: Some("fr".to_string()),
+            auto_start: Some(true),
+            preview_patches: None,
+            background_mode: Some(true),
+            channel: Some("beta".to_string()),
+            window: Some(WindowGeometry {
+                x: 10.0,
+                y: 20.0,
+                width: 800.0,
+                height: 600.0,
+                maximized: false,
+            }),
+            account: Some("player".to_string()),
+            patch_server: Some("mirror".to_string()),
+        };
+        // Settings are stored in the JSON state file
+        let serialized_settings = serde_json::to_string(&settings).unwrap();
+        assert_eq!(
+            serde_json::from_str::<UserSettings>(&serialized_settings).unwrap(),
+            settings
+        );
+    }
+}
@@ -0,0 +1,75 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Result};
+
+// Unset unless the patcher has been started with one of the '--simulate-*'
+// flags
+static NETWORK_SIMULATION: OnceLock<NetworkSimulation> = OnceLock::new();
+// State of the generator deciding which chunks are lost
+static LOSS_RNG_STATE: AtomicU64 = AtomicU64::new(0);
+
+/// Degraded network conditions applied to patch downloads, so that operators
+/// can check how their setup copes with them before launch day.
+#[derive(Clone, Debug, Default)]
+pub struct NetworkSimulation {
+    pub latency: Option<Duration>, // Delay before each download starts
+    pub packet_loss: f64, // Percentage of downloaded chunks lost, which interrupts downloads
+    pub throttle: Option<u64>, // Maximum speed of each download, in bytes per second
+}
+
+impl NetworkSimulation {
+    fn is_chunk_lost(&self, random_value: u64) -> bool {
+        let threshold = (self.packet_loss.clamp(0.0, 100.0) / 100.0 * u64::MAX as f64) as u64;
+        self.packet_loss > 0.0 && random_value <= threshold
+    }
+}
+
+/// Applies `simulation` to the downloads made from now on.
+pub fn simulate_network_conditions(simulation: NetworkSimulation) -> Result<()> {
+    let seed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos() as u64)
+        .unwrap_or_default();
+    LOSS_RNG_STATE.store(seed | 1, Ordering::Relaxed);
+    log::warn!("Simulating network conditions: {:?}", simulation);
+    NETWORK_SIMULATION
+        .set(simulation)
+        .map_err(|_| anyhow!("Network simulation is already set"))
+}
+
+pub(super) fn get_network_simulation() -> Option<&'static NetworkSimulation> {
+    NETWORK_SIMULATION.get()
+}
+
+/// Fails if the chunk that's just been downloaded is to be lost.
+pub(super) fn check_chunk_loss(simulation: &NetworkSimulation) -> Result<()> {
+    // xorshift64, good enough to drop chunks at random
+    let mut random_value = LOSS_RNG_STATE.load(Ordering::Relaxed);
+    random_value ^= random_value << 13;
+    random_value ^= random_value >> 7;
+    random_value ^= random_value << 17;
+    LOSS_RNG_STATE.store(random_value, Ordering::Relaxed);
+    if simulation.is_chunk_lost(random_value) {
+        return Err(anyhow!("Connection lost (simulated packet loss)"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunk_loss() {
+        let mut simulation = NetworkSimulation::default();
+        assert!(!simulation.is_chunk_lost(0));
+        assert!(!simulation.is_chunk_lost(u64::MAX));
+        simulation.packet_loss = 100.0;
+        assert!(simulation.is_chunk_lost(u64::MAX));
+        simulation.packet_loss = 25.0;
+        assert!(simulation.is_chunk_lost(u64::MAX / 5));
+        assert!(!simulation.is_chunk_lost(u64::MAX / 3));
+    }
+}
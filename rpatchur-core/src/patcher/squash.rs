@@ -0,0 +1,386 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use gruf::thor::{self, ThorArchive};
+
+use super::core::is_version_older;
+use super::publish::{
+    manifest_entry, sign_manifest, update_manifest, upload_files, write_thor_patch, PatchManifest,
+    PatchManifestEntry, MANIFEST_FILE_NAME, PATCH_LIST_FILE_NAME, SIGNATURE_FILE_NAME,
+};
+use super::zip::is_zip_file;
+
+/// Range of a patch server's patches to merge into a single patch.
+pub struct SquashOptions {
+    pub patch_server_directory: PathBuf, // Directory containing 'plist.txt'
+    pub first_index: Option<usize>,      // First patch of the list if not set
+    pub last_index: Option<usize>,       // Last patch of the list if not set
+    pub signing_key_path: Option<PathBuf>, // PEM-encoded Ed25519 private key
+    pub upload_url: Option<String>,      // 's3://bucket/path' or 'sftp://user@host/path'
+}
+
+/// Patch which replaced a range of patches in the patch list.
+#[derive(Debug)]
+pub struct SquashedPatch {
+    pub index: usize,
+    pub file_name: String,
+    pub squashed_patches: usize,
+    pub updated_files: usize,
+    pub removed_files: usize,
+}
+
+/// Merges a range of the patches listed in a patch server's 'plist.txt' into
+/// a single patch, which takes their place in the list and the manifest.
+///
+/// The squashed patch gets the index of the last patch of the range, so that
+/// clients which applied part of the range apply it too. The patches it
+/// replaces are left in place for clients in the middle of an update.
+pub fn squash_published_patches(options: &SquashOptions) -> Result<SquashedPatch> {
+    let patch_list_path = options.patch_server_directory.join(PATCH_LIST_FILE_NAME);
+    let patch_list_content = fs::read_to_string(&patch_list_path)
+        .with_context(|| format!("Failed to read '{}'", patch_list_path.display()))?;
+    let patch_list = thor::patch_list_from_string(&patch_list_content);
+    let in_range = |index: usize| {
+        options.first_index.is_none_or(|first| index >= first)
+            && options.last_index.is_none_or(|last| index <= last)
+    };
+    let squashed_list: Vec<_> = patch_list
+        .iter()
+        .filter(|patch_info| in_range(patch_info.index))
+        .collect();
+    let (first_patch, last_patch) = match (squashed_list.first(), squashed_list.last()) {
+        (Some(first_patch), Some(last_patch)) if squashed_list.len() > 1 => {
+            (first_patch, last_patch)
+        }
+        _ => return Err(anyhow!("There must be at least 2 patches to squash")),
+    };
+    let index = last_patch.index;
+    let file_name = format!("squashed-{}-{}.thor", first_patch.index, last_patch.index);
+    let patch_path = options.patch_server_directory.join(&file_name);
+    if patch_path.exists() {
+        return Err(anyhow!("'{}' already exists", patch_path.display()));
+    }
+
+    // Patches of different channels mustn't leak into each other
+    let manifest_path = options.patch_server_directory.join(MANIFEST_FILE_NAME);
+    let manifest: PatchManifest = match fs::read(&manifest_path) {
+        Ok(content) => serde_json::from_slice(&content)
+            .with_context(|| format!("Invalid manifest '{}'", manifest_path.display()))?,
+        Err(_) => PatchManifest::default(),
+    };
+    let channel_of = |file_name: &str| {
+        manifest
+            .patches
+            .iter()
+            .find(|entry| entry.file_name == file_name)
+            .and_then(|entry| entry.channel.clone())
+    };
+    let channel = channel_of(&first_patch.file_name);
+    if squashed_list
+        .iter()
+        .any(|patch_info| channel_of(&patch_info.file_name) != channel)
+    {
+        return Err(anyhow!("Patches of different channels can't be squashed"));
+    }
+
+    tracing::info!("Building '{}'", file_name);
+    let patch_paths: Vec<PathBuf> = squashed_list
+        .iter()
+        .map(|patch_info| options.patch_server_directory.join(&patch_info.file_name))
+        .collect();
+    let summary = squash_patches(&patch_paths, &patch_path)
+        .map_err(|e| e.context(format!("Failed to build '{}'", patch_path.display())))?;
+
+    // Clients only see the patch once it's in the list, which is written last
+    let mut new_patch_list_content = String::with_capacity(patch_list_content.len());
+    for line in patch_list_content.lines() {
+        match thor::patch_list_from_string(line).first() {
+            Some(patch_info) if in_range(patch_info.index) => {
+                if patch_info.index == index {
+                    new_patch_list_content.push_str(&format!("{} {}\n", index, file_name));
+                }
+            }
+            _ => {
+                new_patch_list_content.push_str(line);
+                new_patch_list_content.push('\n');
+            }
+        }
+    }
+    let new_patch_list = thor::patch_list_from_string(&new_patch_list_content);
+    let manifest = update_manifest(
+        &manifest_path,
+        &options.patch_server_directory,
+        &new_patch_list,
+        channel.as_deref(),
+        None,
+    )?;
+    let mut published_files = vec![patch_path, manifest_path];
+    if let Some(signing_key_path) = &options.signing_key_path {
+        let signature_path = options.patch_server_directory.join(SIGNATURE_FILE_NAME);
+        sign_manifest(&manifest, signing_key_path, &signature_path)?;
+        published_files.push(signature_path);
+    }
+    fs::write(&patch_list_path, new_patch_list_content)
+        .with_context(|| format!("Failed to update '{}'", patch_list_path.display()))?;
+    published_files.push(patch_list_path);
+
+    if let Some(upload_url) = &options.upload_url {
+        tracing::info!("Uploading to '{}'", upload_url);
+        upload_files(upload_url, &published_files).context("Failed to upload the patch")?;
+    }
+
+    Ok(SquashedPatch {
+        index,
+        file_name,
+        squashed_patches: squashed_list.len(),
+        updated_files: summary.updated_files,
+        removed_files: summary.removed_files,
+    })
+}
+
+/// Number of files a squashed patch updates and removes.
+#[derive(Debug, PartialEq)]
+pub(super) struct SquashSummary {
+    pub updated_files: usize,
+    pub removed_files: usize,
+}
+
+/// Merges THOR patches into a single patch at `output_path`, each file
+/// ending up the way the last patch which contains it leaves it.
+///
+/// All the patches must target the same GRF, or the game directory.
+pub(super) fn squash_patches(patch_paths: &[PathBuf], output_path: &Path) -> Result<SquashSummary> {
+    let res = build_squashed_patch(patch_paths, output_path);
+    if res.is_err() {
+        let _ = fs::remove_file(output_path);
+    }
+    res
+}
+
+fn build_squashed_patch(patch_paths: &[PathBuf], output_path: &Path) -> Result<SquashSummary> {
+    let mut archives = Vec::with_capacity(patch_paths.len());
+    for patch_path in patch_paths {
+        let archive = ThorArchive::open(patch_path)
+            .with_context(|| format!("Failed to open '{}'", patch_path.display()))?;
+        archives.push(archive);
+    }
+    let (use_grf_merging, target_grf_name) = match archives.first() {
+        Some(archive) => (archive.use_grf_merging(), archive.target_grf_name()),
+        None => return Err(anyhow!("There are no patches to squash")),
+    };
+    if let Some(i) = archives.iter().position(|archive| {
+        archive.use_grf_merging() != use_grf_merging
+            || !archive
+                .target_grf_name()
+                .eq_ignore_ascii_case(&target_grf_name)
+    }) {
+        return Err(anyhow!(
+            "'{}' doesn't patch the same GRF as the patches before it",
+            patch_paths[i].display()
+        ));
+    }
+
+    // Last writer wins, paths are case-insensitive like on Windows
+    let mut last_changes: BTreeMap<String, (usize, String, bool)> = BTreeMap::new();
+    for (i, archive) in archives.iter().enumerate() {
+        for entry in archive.get_entries().filter(|entry| !entry.is_internal()) {
+            last_changes.insert(
+                entry.relative_path.to_lowercase(),
+                (i, entry.relative_path.clone(), entry.is_removed),
+            );
+        }
+    }
+
+    let target_grf_name = Some(target_grf_name).filter(|name| !name.is_empty());
+    let mut summary = SquashSummary {
+        updated_files: 0,
+        removed_files: 0,
+    };
+    write_thor_patch(output_path, use_grf_merging, target_grf_name, |builder| {
+        for (i, relative_path, is_removed) in last_changes.into_values() {
+            if is_removed {
+                builder.append_file_removal(relative_path);
+                summary.removed_files += 1;
+            } else {
+                let content = archives[i]
+                    .read_file_content(&relative_path)
+                    .with_context(|| format!("Failed to read '{}'", relative_path))?;
+                builder.append_file_update(relative_path, content.as_slice())?;
+                summary.updated_files += 1;
+            }
+        }
+        Ok(())
+    })?;
+    Ok(summary)
+}
+
+/// Merges the consecutive THOR patches of a bundle which target the same GRF
+/// into single patches, written to `squash_directory`.
+///
+/// Each squashed patch takes the index of the last patch it replaces, and
+/// the strictest version requirement of its patches.
+pub(super) fn squash_bundled_patches(
+    patches: Vec<(PatchManifestEntry, PathBuf)>,
+    squash_directory: &Path,
+) -> Result<Vec<(PatchManifestEntry, PathBuf)>> {
+    let mut runs: Vec<Vec<(PatchManifestEntry, PathBuf)>> = vec![];
+    let mut last_target = None;
+    for (entry, patch_path) in patches {
+        // ZIP patches can't be merged into THOR ones
+        let target = if is_zip_file(&patch_path) {
+            None
+        } else {
+            ThorArchive::open(&patch_path)
+                .ok()
+                .map(|archive| (archive.use_grf_merging(), archive.target_grf_name()))
+        };
+        match runs.last_mut() {
+            Some(run) if target.is_some() && target == last_target => run.push((entry, patch_path)),
+            _ => runs.push(vec![(entry, patch_path)]),
+        }
+        last_target = target;
+    }
+
+    let mut squashed_patches = Vec::with_capacity(runs.len());
+    for mut run in runs {
+        if run.len() == 1 {
+            squashed_patches.append(&mut run);
+            continue;
+        }
+        let (first_entry, last_entry) = (&run[0].0, &run[run.len() - 1].0);
+        let file_name = format!("squashed-{}-{}.thor", first_entry.index, last_entry.index);
+        let patch_path = squash_directory.join(&file_name);
+        let patch_paths: Vec<PathBuf> = run.iter().map(|(_, path)| path.clone()).collect();
+        squash_patches(&patch_paths, &patch_path)?;
+        let min_launcher_version = run
+            .iter()
+            .filter_map(|(entry, _)| entry.min_launcher_version.as_deref())
+            .fold(None, |strictest: Option<&str>, version| match strictest {
+                Some(strictest) if !is_version_older(strictest, version) => Some(strictest),
+                _ => Some(version),
+            })
+            .map(str::to_string);
+        let entry = PatchManifestEntry {
+            min_launcher_version,
+            ..manifest_entry(last_entry.index, &file_name, &patch_path)?
+        };
+        tracing::info!("Squashed {} patches into '{}'", run.len(), file_name);
+        squashed_patches.push((entry, patch_path));
+    }
+    Ok(squashed_patches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::publish::build_thor_patch;
+    use super::*;
+
+    #[test]
+    fn test_squash_patches() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let patch_paths = vec![
+            tmp_dir.path().join("1.thor"),
+            tmp_dir.path().join("2.thor"),
+            tmp_dir.path().join("3.thor"),
+        ];
+        build_thor_patch(
+            &patch_paths[0],
+            None,
+            &[("data\\a.txt", b"a1"), ("data\\b.txt", b"b1")],
+            &[],
+        );
+        build_thor_patch(
+            &patch_paths[1],
+            None,
+            &[("DATA\\A.txt", b"a2"), ("data\\c.txt", b"c2")],
+            &["data\\b.txt"],
+        );
+        build_thor_patch(
+            &patch_paths[2],
+            None,
+            &[("data\\b.txt", b"b3")],
+            &["data\\c.txt"],
+        );
+
+        let output_path = tmp_dir.path().join("squashed.thor");
+        let summary = squash_patches(&patch_paths, &output_path).unwrap();
+        assert_eq!(
+            summary,
+            SquashSummary {
+                updated_files: 2,
+                removed_files: 1,
+            }
+        );
+        let mut archive = ThorArchive::open(&output_path).unwrap();
+        assert!(archive.is_valid().unwrap());
+        assert_eq!(archive.read_file_content("DATA\\A.txt").unwrap(), b"a2");
+        assert_eq!(archive.read_file_content("data\\b.txt").unwrap(), b"b3");
+        assert!(archive.get_file_entry("data\\c.txt").unwrap().is_removed);
+        assert!(archive.get_file_entry("data\\a.txt").is_none());
+
+        // Patches targeting different GRFs can't be merged
+        build_thor_patch(&patch_paths[2], Some("data.grf"), &[("b.txt", b"b3")], &[]);
+        assert!(squash_patches(&patch_paths, &output_path).is_err());
+        assert!(!output_path.exists());
+    }
+
+    #[test]
+    fn test_squash_published_patches() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        for (index, content) in [(1, b"1"), (2, b"2"), (3, b"3")] {
+            build_thor_patch(
+                &tmp_dir.path().join(format!("{}.thor", index)),
+                None,
+                &[("data\\version.txt", content)],
+                &[],
+            );
+        }
+        fs::write(
+            tmp_dir.path().join(PATCH_LIST_FILE_NAME),
+            "// Patches\n1 1.thor\n2 2.thor\n3 3.thor\n",
+        )
+        .unwrap();
+
+        let squashed_patch = squash_published_patches(&SquashOptions {
+            patch_server_directory: tmp_dir.path().to_path_buf(),
+            first_index: Some(2),
+            last_index: None,
+            signing_key_path: None,
+            upload_url: None,
+        })
+        .unwrap();
+        assert_eq!(squashed_patch.index, 3);
+        assert_eq!(squashed_patch.file_name, "squashed-2-3.thor");
+        assert_eq!(squashed_patch.squashed_patches, 2);
+        assert_eq!(
+            fs::read_to_string(tmp_dir.path().join(PATCH_LIST_FILE_NAME)).unwrap(),
+            "// Patches\n1 1.thor\n3 squashed-2-3.thor\n"
+        );
+        let manifest: PatchManifest =
+            serde_json::from_slice(&fs::read(tmp_dir.path().join(MANIFEST_FILE_NAME)).unwrap())
+                .unwrap();
+        let file_names: Vec<_> = manifest
+            .patches
+            .iter()
+            .map(|entry| entry.file_name.as_str())
+            .collect();
+        assert_eq!(file_names, ["1.thor", "squashed-2-3.thor"]);
+        let mut archive = ThorArchive::open(&tmp_dir.path().join("squashed-2-3.thor")).unwrap();
+        assert_eq!(
+            archive.read_file_content("data\\version.txt").unwrap(),
+            b"3"
+        );
+
+        // A single patch is left, there's nothing to squash
+        assert!(squash_published_patches(&SquashOptions {
+            patch_server_directory: tmp_dir.path().to_path_buf(),
+            first_index: Some(3),
+            last_index: None,
+            signing_key_path: None,
+            upload_url: None,
+        })
+        .is_err());
+    }
+}
@@ -0,0 +1,506 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::Mutex;
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use super::patching::FileChange;
+use super::paths::{
+    get_legacy_cache_file_path, get_legacy_user_settings_file_path, get_state_file_path,
+};
+use super::settings::{read_user_settings, UserSettings};
+
+// Bumped whenever the layout of the state file changes
+const STATE_FORMAT_VERSION: u32 = 1;
+
+// Oldest download sessions are forgotten past this number
+const MAX_DOWNLOAD_SESSIONS: usize = 100;
+
+// The UI and the patching threads both update the state file
+static STATE_FILE_LOCK: Mutex<()> = Mutex::new(());
+
+/// Everything the patcher remembers between runs.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct PatcherState {
+    pub version: u32,
+    pub last_patch_index: Option<usize>, // Recorded before patch servers were told apart
+    pub patch_indices: BTreeMap<String, usize>, // Index of the last applied patch, by patch server identity
+    pub applied_patches: Vec<AppliedPatch>,     // Oldest first
+    pub server_benchmarks: BTreeMap<String, ServerBenchmark>, // By patch server name
+    pub cached_patch_list: Option<CachedPatchList>, // Patch list of the last working server
+    pub settings: UserSettings,
+    pub manifest: InstalledFileManifest,
+    pub verification_pending: bool, // Set when the state file was lost, until installed patches are verified
+    pub accepted_agreement: Option<String>, // Version of the operator's agreement accepted last
+    pub download_sessions: Vec<DownloadSession>, // Oldest first
+}
+
+/// Record of a successfully applied patch.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AppliedPatch {
+    pub index: Option<usize>,      // None for manual patches
+    pub server_id: Option<String>, // Identity of the patch servers the patch comes from
+    pub name: String,
+    pub applied_at: i64, // Unix timestamp
+    pub crc32: u32,      // Checksum of the THOR archive
+}
+
+/// Statistics of an update which downloaded patches.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DownloadSession {
+    pub started_at: i64, // Unix timestamp
+    pub duration_ms: u64,
+    pub downloaded_bytes: u64,
+    pub patch_count: usize, // Patches downloaded during the session
+}
+
+/// Outcome of the last probe of a patch server.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ServerBenchmark {
+    pub available: bool,
+    pub latency_ms: u64, // Time taken to fetch the patch list and check the first patch
+    pub probed_at: i64,  // Unix timestamp
+}
+
+/// Patch list served by the patch server which answered last, reused for a
+/// while instead of probing the servers again.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct CachedPatchList {
+    pub server: String,                // Name of the patch server
+    pub channel: String,               // Release channel the list was fetched for
+    pub patches: Vec<(usize, String)>, // Index and file name of each patch
+    pub fetched_at: i64,               // Unix timestamp
+}
+
+/// Files installed by patches, which make it possible to verify or clean up
+/// the game's files.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct InstalledFileManifest {
+    pub files: BTreeMap<String, InstalledFile>, // Files of the game directory, by relative path
+    pub grf_entries: BTreeMap<String, BTreeMap<String, InstalledFile>>, // By GRF, then by relative path
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct InstalledFile {
+    pub size: u64,
+    pub crc32: u32,    // Checksum of the uncompressed content
+    pub patch: String, // Name of the patch which installed the file
+}
+
+impl InstalledFileManifest {
+    /// Records the changes made by the patch named `patch_name`, to the GRF
+    /// named `grf_name` or to the game directory.
+    pub fn record_changes(
+        &mut self,
+        grf_name: Option<&str>,
+        patch_name: &str,
+        file_changes: Vec<FileChange>,
+    ) {
+        let files = match grf_name {
+            Some(grf_name) => self.grf_entries.entry(grf_name.to_string()).or_default(),
+            None => &mut self.files,
+        };
+        for file_change in file_changes {
+            match file_change {
+                FileChange::Written {
+                    relative_path,
+                    size,
+                    crc32,
+                } => {
+                    let installed_file = InstalledFile {
+                        size,
+                        crc32,
+                        patch: patch_name.to_string(),
+                    };
+                    files.insert(relative_path, installed_file);
+                }
+                FileChange::Removed { relative_path } => {
+                    files.remove(&relative_path);
+                }
+            }
+        }
+    }
+}
+
+impl PatcherState {
+    /// Returns the index of the last patch applied from the patch servers
+    /// identified by `server_id`.
+    pub fn last_patch_index(&self, server_id: &str) -> Option<usize> {
+        match self.patch_indices.get(server_id) {
+            Some(&index) => Some(index),
+            // Older indices belong to the first patch servers used since then
+            None => self
+                .last_patch_index
+                .filter(|_| self.patch_indices.is_empty()),
+        }
+    }
+
+    pub fn record_applied_patch(&mut self, applied_patch: AppliedPatch) {
+        if let (Some(index), Some(server_id)) = (applied_patch.index, &applied_patch.server_id) {
+            self.patch_indices.insert(server_id.clone(), index);
+            self.last_patch_index = None;
+        }
+        self.applied_patches.push(applied_patch);
+    }
+
+    pub fn record_download_session(&mut self, session: DownloadSession) {
+        self.download_sessions.push(session);
+        if self.download_sessions.len() > MAX_DOWNLOAD_SESSIONS {
+            self.download_sessions
+                .drain(..self.download_sessions.len() - MAX_DOWNLOAD_SESSIONS);
+        }
+    }
+
+    /// Forgets the patches applied from the patch servers identified by
+    /// `server_id` after `index`, once they've been rolled back.
+    pub fn record_rollback(&mut self, server_id: &str, index: usize) {
+        self.patch_indices.insert(server_id.to_string(), index);
+        self.last_patch_index = None;
+        self.applied_patches.retain(|applied_patch| {
+            applied_patch.server_id.as_deref() != Some(server_id)
+                || applied_patch.index.is_none_or(|i| i <= index)
+        });
+    }
+
+    /// Forgets which patches have been applied, so that all of them are
+    /// applied again on the next update, and which ones are available.
+    pub fn reset_patch_records(&mut self) {
+        self.last_patch_index = None;
+        self.patch_indices.clear();
+        self.applied_patches.clear();
+        self.cached_patch_list = None;
+    }
+}
+
+/// Layout of the state file, whose checksum detects truncated or tampered
+/// files.
+#[derive(Serialize, Deserialize)]
+struct StateFile {
+    checksum: u32, // CRC32 of `state`, serialized as compact JSON
+    state: serde_json::Value,
+}
+
+/// Format of the '.dat' cache files written by previous versions.
+#[derive(Deserialize)]
+struct LegacyPatcherCache {
+    last_patch_index: usize,
+}
+
+/// Reads the patcher's state, migrating the files of previous versions if
+/// needed.
+pub fn retrieve_patcher_state() -> Result<PatcherState> {
+    let _guard = STATE_FILE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    load_patcher_state(
+        get_state_file_path()?,
+        get_legacy_cache_file_path()?,
+        get_legacy_user_settings_file_path()?,
+    )
+}
+
+/// Applies `update` to the patcher's state and saves the result.
+pub fn update_patcher_state(update: impl FnOnce(&mut PatcherState)) -> Result<()> {
+    let _guard = STATE_FILE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let state_file_path = get_state_file_path()?;
+    let mut state = load_patcher_state(
+        &state_file_path,
+        get_legacy_cache_file_path()?,
+        get_legacy_user_settings_file_path()?,
+    )?;
+    update(&mut state);
+    write_patcher_state(state_file_path, &state)
+}
+
+fn load_patcher_state(
+    state_file_path: impl AsRef<Path>,
+    legacy_cache_file_path: impl AsRef<Path>,
+    legacy_settings_file_path: impl AsRef<Path>,
+) -> Result<PatcherState> {
+    let state_file_path = state_file_path.as_ref();
+    if state_file_path.exists() {
+        let content = fs::read(state_file_path)?;
+        return match parse_patcher_state(&content) {
+            Ok(state) => Ok(state),
+            Err(e) => {
+                tracing::error!("Patcher state is corrupted: {:#}", e);
+                recover_patcher_state(state_file_path, &content)
+            }
+        };
+    }
+
+    let legacy_cache_file_path = legacy_cache_file_path.as_ref();
+    let legacy_settings_file_path = legacy_settings_file_path.as_ref();
+    let mut state = PatcherState {
+        version: STATE_FORMAT_VERSION,
+        ..Default::default()
+    };
+    if !legacy_cache_file_path.exists() && !legacy_settings_file_path.exists() {
+        return Ok(state);
+    }
+    // Unreadable legacy files are left alone, so that nothing is lost
+    if legacy_cache_file_path.exists() {
+        tracing::info!("Migrating '{}'", legacy_cache_file_path.display());
+        state.last_patch_index = Some(read_legacy_cache_file(legacy_cache_file_path)?);
+    }
+    if legacy_settings_file_path.exists() {
+        tracing::info!("Migrating '{}'", legacy_settings_file_path.display());
+        state.settings = read_user_settings(legacy_settings_file_path)?;
+    }
+    write_patcher_state(state_file_path, &state)?;
+    for legacy_file_path in &[legacy_cache_file_path, legacy_settings_file_path] {
+        if let Err(e) = fs::remove_file(legacy_file_path) {
+            if e.kind() != io::ErrorKind::NotFound {
+                tracing::warn!("Failed to remove '{}': {}", legacy_file_path.display(), e);
+            }
+        }
+    }
+    Ok(state)
+}
+
+fn read_legacy_cache_file(cache_file_path: impl AsRef<Path>) -> Result<usize> {
+    let file = File::open(cache_file_path)?;
+    let cache: LegacyPatcherCache =
+        serde_json::from_reader(file).context("Failed to deserialize patcher cache")?;
+    Ok(cache.last_patch_index)
+}
+
+/// Replaces a corrupted state file with a new state, which makes the next
+/// update verify every patch instead of trusting the recorded indices.
+///
+/// The corrupted file is kept next to the new one for troubleshooting.
+fn recover_patcher_state(state_file_path: &Path, corrupted_content: &[u8]) -> Result<PatcherState> {
+    let backup_file_path = state_file_path.with_extension("json.corrupted");
+    if let Err(e) = fs::write(&backup_file_path, corrupted_content) {
+        tracing::warn!("Failed to back up '{}': {}", state_file_path.display(), e);
+    }
+    // Settings are harmless, don't make players choose them again
+    let settings = serde_json::from_slice::<StateFile>(corrupted_content)
+        .ok()
+        .and_then(|state_file| state_file.state.get("settings").cloned())
+        .and_then(|settings| serde_json::from_value(settings).ok())
+        .unwrap_or_default();
+    let state = PatcherState {
+        version: STATE_FORMAT_VERSION,
+        settings,
+        verification_pending: true,
+        ..Default::default()
+    };
+    write_patcher_state(state_file_path, &state)?;
+    Ok(state)
+}
+
+fn parse_patcher_state(content: &[u8]) -> Result<PatcherState> {
+    let state_file: StateFile =
+        serde_json::from_slice(content).context("Failed to deserialize patcher state")?;
+    let checksum = state_checksum(&state_file.state)?;
+    if checksum != state_file.checksum {
+        return Err(anyhow!(
+            "Checksum mismatch (expected {:08x}, got {:08x})",
+            state_file.checksum,
+            checksum
+        ));
+    }
+    serde_json::from_value(state_file.state).context("Failed to deserialize patcher state")
+}
+
+/// Writes the state to a temporary file first, so that the previous state is
+/// kept intact if the patcher is interrupted.
+fn write_patcher_state(state_file_path: impl AsRef<Path>, state: &PatcherState) -> Result<()> {
+    let state_file_path = state_file_path.as_ref();
+    let state = serde_json::to_value(state).context("Failed to serialize patcher state")?;
+    let state_file = StateFile {
+        checksum: state_checksum(&state)?,
+        state,
+    };
+    let tmp_file_path = state_file_path.with_extension("json.tmp");
+    let mut file = File::create(&tmp_file_path)?;
+    serde_json::to_writer_pretty(&mut file, &state_file)
+        .context("Failed to serialize patcher state")?;
+    file.sync_all()?;
+    drop(file);
+    fs::rename(&tmp_file_path, state_file_path)
+        .with_context(|| format!("Failed to replace '{}'", state_file_path.display()))
+}
+
+fn state_checksum(state: &serde_json::Value) -> Result<u32> {
+    let content = serde_json::to_vec(state).context("Failed to serialize patcher state")?;
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&content);
+    Ok(hasher.finalize())
+}
+
+/// Computes the CRC32 of the file located at `file_path`.
+pub(super) fn file_crc32(file_path: impl AsRef<Path>) -> io::Result<u32> {
+    let mut file = File::open(file_path)?;
+    let mut hasher = crc32fast::Hasher::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read_bytes = file.read(&mut buffer)?;
+        if read_bytes == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read_bytes]);
+    }
+    Ok(hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_legacy_files_migration() {
+        let temp_dir = tempdir().unwrap();
+        let state_file_path = temp_dir.path().join("rpatchur.state.json");
+        let cache_file_path = temp_dir.path().join("rpatchur.dat");
+        let settings_file_path = temp_dir.path().join("rpatchur.settings.yml");
+        fs::write(&cache_file_path, r#"{"last_patch_index":42}"#).unwrap();
+        fs::write(&settings_file_path, "language: fr\n").unwrap();
+
+        let state =
+            load_patcher_state(&state_file_path, &cache_file_path, &settings_file_path).unwrap();
+        assert_eq!(state.version, STATE_FORMAT_VERSION);
+        assert_eq!(state.last_patch_index, Some(42));
+        assert_eq!(state.settings.language.as_deref(), Some("fr"));
+        assert!(!cache_file_path.exists());
+        assert!(!settings_file_path.exists());
+        // Migrated state is read back from the new file
+        assert_eq!(
+            load_patcher_state(&state_file_path, &cache_file_path, &settings_file_path).unwrap(),
+            state
+        );
+    }
+
+    #[test]
+    fn test_corrupted_state_recovery() {
+        let temp_dir = tempdir().unwrap();
+        let state_file_path = temp_dir.path().join("rpatchur.state.json");
+        let cache_file_path = temp_dir.path().join("rpatchur.dat");
+        let settings_file_path = temp_dir.path().join("rpatchur.settings.yml");
+        let mut state = PatcherState {
+            version: STATE_FORMAT_VERSION,
+            last_patch_index: Some(42),
+            ..Default::default()
+        };
+        state.settings.language = Some("fr".to_string());
+        write_patcher_state(&state_file_path, &state).unwrap();
+        assert_eq!(
+            load_patcher_state(&state_file_path, &cache_file_path, &settings_file_path).unwrap(),
+            state
+        );
+
+        // Tamper with the index
+        let content = fs::read_to_string(&state_file_path).unwrap();
+        let tampered_content =
+            content.replace(r#""last_patch_index": 42"#, r#""last_patch_index": 4200"#);
+        fs::write(&state_file_path, tampered_content).unwrap();
+        let recovered_state =
+            load_patcher_state(&state_file_path, &cache_file_path, &settings_file_path).unwrap();
+        assert!(recovered_state.verification_pending);
+        assert_eq!(recovered_state.last_patch_index, None);
+        assert_eq!(recovered_state.settings.language.as_deref(), Some("fr"));
+        assert!(state_file_path.with_extension("json.corrupted").exists());
+
+        // Truncated file
+        fs::write(&state_file_path, &content[..content.len() / 2]).unwrap();
+        let recovered_state =
+            load_patcher_state(&state_file_path, &cache_file_path, &settings_file_path).unwrap();
+        assert!(recovered_state.verification_pending);
+        assert_eq!(recovered_state.settings.language, None);
+        // The recovered state is valid
+        assert_eq!(
+            load_patcher_state(&state_file_path, &cache_file_path, &settings_file_path).unwrap(),
+            recovered_state
+        );
+    }
+
+    #[test]
+    fn test_record_applied_patch() {
+        let mut state = PatcherState {
+            last_patch_index: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(state.last_patch_index("127.0.0.1"), Some(2));
+        state.record_applied_patch(AppliedPatch {
+            index: Some(3),
+            server_id: Some("127.0.0.1".to_string()),
+            name: "2021-01-01data.thor".to_string(),
+            applied_at: 1609459200,
+            crc32: 0xdeadbeef,
+        });
+        state.record_applied_patch(AppliedPatch {
+            index: None,
+            server_id: None,
+            name: "custom.thor".to_string(),
+            applied_at: 1609459300,
+            crc32: 0xcafebabe,
+        });
+        assert_eq!(state.last_patch_index("127.0.0.1"), Some(3));
+        // Other patch servers' patches are all pending
+        assert_eq!(state.last_patch_index("example.com"), None);
+        assert_eq!(state.applied_patches.len(), 2);
+
+        // Manual patches are kept
+        state.record_rollback("127.0.0.1", 2);
+        assert_eq!(state.last_patch_index("127.0.0.1"), Some(2));
+        assert_eq!(state.applied_patches.len(), 1);
+
+        state.reset_patch_records();
+        assert_eq!(state.last_patch_index("127.0.0.1"), None);
+        assert!(state.applied_patches.is_empty());
+    }
+
+    #[test]
+    fn test_record_download_session() {
+        let mut state = PatcherState::default();
+        for i in 0..MAX_DOWNLOAD_SESSIONS + 2 {
+            state.record_download_session(DownloadSession {
+                started_at: i as i64,
+                duration_ms: 1000,
+                downloaded_bytes: 1024,
+                patch_count: 1,
+            });
+        }
+        assert_eq!(state.download_sessions.len(), MAX_DOWNLOAD_SESSIONS);
+        assert_eq!(state.download_sessions[0].started_at, 2);
+    }
+
+    #[test]
+    fn test_manifest_record_changes() {
+        let mut manifest = InstalledFileManifest::default();
+        let written = |relative_path: &str, crc32| FileChange::Written {
+            relative_path: relative_path.to_string(),
+            size: 4,
+            crc32,
+        };
+        manifest.record_changes(
+            None,
+            "1.thor",
+            vec![written("Ragexe.exe", 1), written("System\\iteminfo.lub", 2)],
+        );
+        manifest.record_changes(Some("data.grf"), "2.thor", vec![written("data\\a.txt", 3)]);
+        manifest.record_changes(
+            None,
+            "3.thor",
+            vec![
+                written("Ragexe.exe", 4),
+                FileChange::Removed {
+                    relative_path: "System\\iteminfo.lub".to_string(),
+                },
+            ],
+        );
+
+        assert_eq!(manifest.files.len(), 1);
+        assert_eq!(manifest.files["Ragexe.exe"].crc32, 4);
+        assert_eq!(manifest.files["Ragexe.exe"].patch, "3.thor");
+        assert_eq!(
+            manifest.grf_entries["data.grf"]["data\\a.txt"].patch,
+            "2.thor"
+        );
+    }
+}
@@ -0,0 +1,270 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{Context, Result};
+use walkdir::WalkDir;
+
+use super::config::{RetentionPolicy, StorageConfiguration};
+use super::paths::{
+    get_download_cache_directory_path, get_log_file_path, get_peer_cache_directory_path,
+    get_rollback_directory_path, list_crash_report_file_paths,
+};
+use super::update_lock::take_update_lock;
+
+/// Files written by the patcher which players are allowed to clear.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StorageCategory {
+    Downloads, // Patches downloaded ahead of time, or kept for the LAN's patchers
+    Backups,   // Rollback patches, which revert applied patches
+    Logs,      // Rotated logs and crash reports, the current log is left alone
+}
+
+impl StorageCategory {
+    pub const ALL: [StorageCategory; 3] = [
+        StorageCategory::Downloads,
+        StorageCategory::Backups,
+        StorageCategory::Logs,
+    ];
+
+    /// Returns the name used to refer to the category in translations.
+    pub fn name(&self) -> &'static str {
+        match self {
+            StorageCategory::Downloads => "downloads",
+            StorageCategory::Backups => "backups",
+            StorageCategory::Logs => "logs",
+        }
+    }
+
+    fn list_files(&self) -> Result<Vec<StoredFile>> {
+        let files = match self {
+            StorageCategory::Downloads => {
+                let mut files = list_directory_files(&get_download_cache_directory_path()?);
+                files.extend(list_directory_files(&get_peer_cache_directory_path()?));
+                files
+            }
+            StorageCategory::Backups => list_directory_files(&get_rollback_directory_path()?),
+            StorageCategory::Logs => {
+                let mut file_paths = list_rotated_log_file_paths()?;
+                file_paths.extend(list_crash_report_file_paths().unwrap_or_default());
+                file_paths
+                    .iter()
+                    .filter_map(|path| stat_file(path))
+                    .collect()
+            }
+        };
+        Ok(files)
+    }
+}
+
+/// Space taken by the files of a category.
+#[derive(Clone, Debug)]
+pub struct StorageUsage {
+    pub category: StorageCategory,
+    pub size: u64, // In bytes
+    pub file_count: usize,
+}
+
+#[derive(Debug, PartialEq)]
+struct StoredFile {
+    path: PathBuf,
+    size: u64,
+    modified: SystemTime,
+}
+
+/// Returns the space taken by each category of files.
+pub fn retrieve_storage_usage() -> Result<Vec<StorageUsage>> {
+    StorageCategory::ALL
+        .iter()
+        .map(|category| {
+            let files = category.list_files()?;
+            Ok(StorageUsage {
+                category: *category,
+                size: files.iter().map(|file| file.size).sum(),
+                file_count: files.len(),
+            })
+        })
+        .collect()
+}
+
+/// Removes the files of a category, returning the number of bytes freed.
+///
+/// Fails if the game is being updated, since the update might use them.
+pub fn clear_storage(category: StorageCategory) -> Result<u64> {
+    let _lock = take_update_lock().context("Failed to take the update lock")?;
+    let files = category.list_files()?;
+    tracing::info!("Clearing {} {} file(s)", files.len(), category.name());
+    Ok(remove_files(&files))
+}
+
+/// Removes the files which the operator's retention policies don't allow to
+/// keep anymore.
+///
+/// This runs before any update is started, so that the files are unused.
+pub(super) fn clean_up_storage(config: &StorageConfiguration) {
+    let policies = [
+        (StorageCategory::Downloads, &config.downloads),
+        (StorageCategory::Backups, &config.backups),
+        (StorageCategory::Logs, &config.logs),
+    ];
+    for (category, policy) in policies.iter() {
+        if policy.max_size.is_none() && policy.max_age.is_none() {
+            continue;
+        }
+        let files = match category.list_files() {
+            Ok(files) => files,
+            Err(e) => {
+                tracing::warn!("Failed to list {} files: {:#}", category.name(), e);
+                continue;
+            }
+        };
+        let expired_files = select_expired_files(files, policy, SystemTime::now());
+        if !expired_files.is_empty() {
+            let freed_bytes = remove_files(&expired_files);
+            tracing::info!(
+                "Removed {} expired {} file(s), {} bytes freed",
+                expired_files.len(),
+                category.name(),
+                freed_bytes
+            );
+        }
+    }
+}
+
+/// Returns the files in excess of the policy's limits, keeping the most
+/// recent ones.
+fn select_expired_files(
+    mut files: Vec<StoredFile>,
+    policy: &RetentionPolicy,
+    now: SystemTime,
+) -> Vec<StoredFile> {
+    let max_size = policy
+        .max_size
+        .map(|max_size| max_size * 1024 * 1024)
+        .unwrap_or(u64::MAX);
+    let max_age = policy
+        .max_age
+        .map(|max_age| Duration::from_secs(max_age * 24 * 60 * 60));
+    files.sort_by_key(|file| std::cmp::Reverse(file.modified));
+    let mut total_size: u64 = 0;
+    files
+        .into_iter()
+        .filter(|file| {
+            total_size = total_size.saturating_add(file.size);
+            // Files from the future are considered brand new
+            let age = now.duration_since(file.modified).unwrap_or_default();
+            total_size > max_size || max_age.is_some_and(|max_age| age > max_age)
+        })
+        .collect()
+}
+
+fn remove_files(files: &[StoredFile]) -> u64 {
+    let mut freed_bytes = 0;
+    for file in files {
+        match fs::remove_file(&file.path) {
+            Ok(()) => freed_bytes += file.size,
+            // Files in use can't be removed on Windows
+            Err(e) => tracing::warn!("Failed to remove '{}': {}", file.path.display(), e),
+        }
+    }
+    freed_bytes
+}
+
+fn list_directory_files(directory_path: &Path) -> Vec<StoredFile> {
+    WalkDir::new(directory_path)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file())
+        .filter_map(|entry| stat_file(entry.path()))
+        .collect()
+}
+
+fn stat_file(path: &Path) -> Option<StoredFile> {
+    let metadata = fs::metadata(path).ok()?;
+    Some(StoredFile {
+        path: path.to_path_buf(),
+        size: metadata.len(),
+        modified: metadata.modified().ok()?,
+    })
+}
+
+/// Returns the paths of the log files that have been rotated (e.g.
+/// 'rpatchur.log.1').
+fn list_rotated_log_file_paths() -> Result<Vec<PathBuf>> {
+    let log_file_path = get_log_file_path()?;
+    let prefix = match log_file_path.file_name() {
+        Some(file_name) => format!("{}.", file_name.to_string_lossy()),
+        None => return Ok(vec![]),
+    };
+    let directory = match log_file_path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let entries = fs::read_dir(directory)
+        .with_context(|| format!("Failed to read '{}'", directory.display()))?;
+    Ok(entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with(&prefix))
+        .map(|entry| entry.path())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select_expired_files() {
+        const DAY: u64 = 24 * 60 * 60;
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(100 * DAY);
+        let files = || {
+            vec![
+                StoredFile {
+                    path: PathBuf::from("old"),
+                    size: 1024 * 1024,
+                    modified: now - Duration::from_secs(10 * DAY),
+                },
+                StoredFile {
+                    path: PathBuf::from("recent"),
+                    size: 1024 * 1024,
+                    modified: now - Duration::from_secs(DAY),
+                },
+                StoredFile {
+                    path: PathBuf::from("new"),
+                    size: 1024 * 1024,
+                    modified: now,
+                },
+            ]
+        };
+        let expired_paths = |policy: RetentionPolicy| -> Vec<PathBuf> {
+            select_expired_files(files(), &policy, now)
+                .into_iter()
+                .map(|file| file.path)
+                .collect()
+        };
+
+        assert!(expired_paths(RetentionPolicy::default()).is_empty());
+        // The most recent files are kept
+        assert_eq!(
+            expired_paths(RetentionPolicy {
+                max_size: Some(2),
+                max_age: None,
+            }),
+            [PathBuf::from("old")]
+        );
+        assert_eq!(
+            expired_paths(RetentionPolicy {
+                max_size: None,
+                max_age: Some(0),
+            }),
+            [PathBuf::from("recent"), PathBuf::from("old")]
+        );
+        assert_eq!(
+            expired_paths(RetentionPolicy {
+                max_size: Some(10),
+                max_age: Some(5),
+            }),
+            [PathBuf::from("old")]
+        );
+    }
+}
@@ -0,0 +1,142 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use advisory_lock::{AdvisoryFileLock, FileLockError, FileLockMode};
+use anyhow::{Context, Result};
+use chrono::{Local, TimeZone};
+use serde::{Deserialize, Serialize};
+
+use super::paths::get_update_lock_file_path;
+
+/// Process holding the update lock, which is written to the lock file.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct LockOwner {
+    pid: u32,
+    locked_at: i64, // Unix timestamp
+}
+
+/// Advisory lock that prevents multiple instances of the patcher to update
+/// the game at the same time, which is released when dropped.
+pub(super) struct UpdateLock {
+    file: File,
+}
+
+impl Drop for UpdateLock {
+    fn drop(&mut self) {
+        // An empty lock file means it was released properly
+        let _ = self.file.set_len(0);
+        let _ = self.file.unlock();
+    }
+}
+
+/// Takes the update lock.
+///
+/// The OS releases the lock once its owner exits, even if it crashed. Lock
+/// files which still name their owner are then reclaimed.
+pub(super) fn take_update_lock() -> Result<UpdateLock> {
+    take_lock(&get_update_lock_file_path()?)
+}
+
+/// Tells whether `err`, returned by `take_update_lock`, means that another
+/// process holds the update lock.
+pub(super) fn is_update_lock_taken(err: &anyhow::Error) -> bool {
+    matches!(
+        err.downcast_ref::<FileLockError>(),
+        Some(FileLockError::AlreadyLocked)
+    )
+}
+
+fn take_lock(lock_file_path: &Path) -> Result<UpdateLock> {
+    // The file isn't truncated before holding the lock, to keep its owner
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(lock_file_path)
+        .with_context(|| format!("Failed to open '{}'", lock_file_path.display()))?;
+    if let Err(e) = AdvisoryFileLock::try_lock(&file, FileLockMode::Exclusive) {
+        // Other processes might not be allowed to read the file on Windows
+        return match (&e, read_lock_owner(&mut file)) {
+            (FileLockError::AlreadyLocked, Some(owner)) => {
+                Err(anyhow::Error::new(e).context(format!(
+                    "Process {} has been updating the game since {}",
+                    owner.pid,
+                    format_timestamp(owner.locked_at)
+                )))
+            }
+            _ => Err(e.into()),
+        };
+    }
+    let mut lock = UpdateLock { file };
+    if let Some(owner) = read_lock_owner(&mut lock.file) {
+        tracing::warn!(
+            "Reclaiming the update lock taken by process {} at {}, which didn't exit cleanly",
+            owner.pid,
+            format_timestamp(owner.locked_at)
+        );
+    }
+    let owner = LockOwner {
+        pid: std::process::id(),
+        locked_at: chrono::Utc::now().timestamp(),
+    };
+    write_lock_owner(&mut lock.file, &owner)
+        .with_context(|| format!("Failed to write '{}'", lock_file_path.display()))?;
+    Ok(lock)
+}
+
+fn read_lock_owner(file: &mut File) -> Option<LockOwner> {
+    let mut content = String::new();
+    file.seek(SeekFrom::Start(0)).ok()?;
+    file.read_to_string(&mut content).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+fn write_lock_owner(file: &mut File, owner: &LockOwner) -> Result<()> {
+    file.set_len(0)?;
+    file.seek(SeekFrom::Start(0))?;
+    serde_json::to_writer(&mut *file, owner)?;
+    file.flush()?;
+    Ok(())
+}
+
+fn format_timestamp(timestamp: i64) -> String {
+    Local
+        .timestamp_opt(timestamp, 0)
+        .single()
+        .map(|date| date.format("%Y-%m-%d %H:%M:%S").to_string())
+        .unwrap_or_else(|| "an unknown date".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_take_lock() {
+        let temp_dir = tempdir().unwrap();
+        let lock_file_path = temp_dir.path().join("rpatchur.lock");
+
+        let lock = take_lock(&lock_file_path).unwrap();
+        let err = take_lock(&lock_file_path).err().unwrap();
+        assert!(err
+            .to_string()
+            .starts_with(&format!("Process {} ", std::process::id())));
+        assert!(is_update_lock_taken(
+            &err.context("Failed to take the update lock")
+        ));
+        drop(lock);
+        assert_eq!(fs::read_to_string(&lock_file_path).unwrap(), "");
+
+        // Left behind by a crashed instance
+        fs::write(&lock_file_path, r#"{"pid":1234,"locked_at":1600000000}"#).unwrap();
+        let mut lock = take_lock(&lock_file_path).unwrap();
+        assert_eq!(
+            read_lock_owner(&mut lock.file).unwrap().pid,
+            std::process::id()
+        );
+    }
+}
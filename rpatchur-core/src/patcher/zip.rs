@@ -0,0 +1,460 @@
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{Datelike, Local, Timelike};
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+
+const LOCAL_FILE_HEADER_SIGNATURE: u32 = 0x0403_4b50;
+const CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x0201_4b50;
+const END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x0605_4b50;
+const END_OF_CENTRAL_DIRECTORY_SIZE: usize = 22;
+const MAX_COMMENT_SIZE: usize = u16::MAX as usize;
+const METHOD_STORED: u16 = 0;
+const METHOD_DEFLATE: u16 = 8;
+const COPY_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Returns whether the file at `path` is a ZIP archive, judging from its
+/// first bytes (e.g. patches can be named after their parts).
+pub fn is_zip_file(path: impl AsRef<Path>) -> bool {
+    let mut signature = [0; 4];
+    File::open(path)
+        .and_then(|mut file| file.read_exact(&mut signature))
+        .map(|_| u32::from_le_bytes(signature) == LOCAL_FILE_HEADER_SIGNATURE)
+        .unwrap_or(false)
+}
+
+/// File or directory stored in a ZIP archive.
+#[derive(Debug, Clone)]
+pub struct ZipEntry {
+    pub name: String, // Relative path, with '/' separators
+    method: u16,
+    crc32: u32,
+    compressed_size: u64,
+    size: u64,
+    local_header_offset: u64,
+}
+
+impl ZipEntry {
+    pub fn is_dir(&self) -> bool {
+        self.name.ends_with('/')
+    }
+}
+
+/// Minimal ZIP archive reader, which supports stored and deflated entries
+/// (i.e. what common tools produce), but not ZIP64 and encrypted archives.
+pub struct ZipArchive<R: Read + Seek> {
+    reader: R,
+    entries: Vec<ZipEntry>,
+}
+
+impl ZipArchive<BufReader<File>> {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file =
+            File::open(path).with_context(|| format!("Failed to open '{}'", path.display()))?;
+        Self::new(BufReader::new(file))
+            .with_context(|| format!("Invalid ZIP archive '{}'", path.display()))
+    }
+}
+
+impl<R: Read + Seek> ZipArchive<R> {
+    pub fn new(mut reader: R) -> Result<Self> {
+        let (entry_count, central_directory_offset) = read_end_of_central_directory(&mut reader)?;
+        reader.seek(SeekFrom::Start(central_directory_offset))?;
+        let mut entries = Vec::with_capacity(entry_count);
+        for _ in 0..entry_count {
+            entries.push(read_central_directory_entry(&mut reader)?);
+        }
+        Ok(Self { reader, entries })
+    }
+
+    pub fn entries(&self) -> &[ZipEntry] {
+        &self.entries
+    }
+
+    /// Reads and decompresses the content of `entry`, checking its CRC32.
+    pub fn read_file_content(&mut self, entry: &ZipEntry) -> Result<Vec<u8>> {
+        let mut content = Vec::with_capacity(usize::try_from(entry.size)?);
+        self.extract_file_to(entry, &mut content)?;
+        Ok(content)
+    }
+
+    /// Decompresses the content of `entry` into `writer` without holding it
+    /// in memory, checking its CRC32 (what's been written is left as is if
+    /// it doesn't match).
+    pub fn extract_file_to<W: Write>(&mut self, entry: &ZipEntry, writer: &mut W) -> Result<()> {
+        self.reader
+            .seek(SeekFrom::Start(entry.local_header_offset))?;
+        let mut local_header = [0; 30];
+        self.reader.read_exact(&mut local_header)?;
+        if read_u32(&local_header, 0) != LOCAL_FILE_HEADER_SIGNATURE {
+            return Err(anyhow!("Invalid local header for '{}'", entry.name));
+        }
+        // Names and extra fields may differ from the central directory's
+        let data_offset =
+            u64::from(read_u16(&local_header, 26)) + u64::from(read_u16(&local_header, 28));
+        self.reader.seek(SeekFrom::Current(data_offset as i64))?;
+
+        let compressed_content = (&mut self.reader).take(entry.compressed_size);
+        let mut content: Box<dyn Read + '_> = match entry.method {
+            METHOD_STORED => Box::new(compressed_content.take(entry.size)),
+            METHOD_DEFLATE => Box::new(DeflateDecoder::new(compressed_content).take(entry.size)),
+            method => {
+                return Err(anyhow!(
+                    "'{}' uses an unsupported compression method ({})",
+                    entry.name,
+                    method
+                ))
+            }
+        };
+        let mut hasher = crc32fast::Hasher::new();
+        let mut size = 0;
+        let mut buffer = vec![0; COPY_BUFFER_SIZE];
+        loop {
+            let read_size = content.read(&mut buffer)?;
+            if read_size == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read_size]);
+            writer.write_all(&buffer[..read_size])?;
+            size += read_size as u64;
+        }
+        if size != entry.size || hasher.finalize() != entry.crc32 {
+            return Err(anyhow!("'{}' is corrupt", entry.name));
+        }
+        Ok(())
+    }
+
+    /// Returns whether the content of every entry matches its CRC32.
+    pub fn is_valid(&mut self) -> bool {
+        let entries = self.entries.clone();
+        entries
+            .iter()
+            .filter(|entry| !entry.is_dir())
+            .all(|entry| self.read_file_content(entry).is_ok())
+    }
+}
+
+/// Finds the end of central directory record, which is followed by a
+/// comment of variable size, and returns the number of entries and the
+/// offset of the central directory.
+fn read_end_of_central_directory<R: Read + Seek>(reader: &mut R) -> Result<(usize, u64)> {
+    let file_size = reader.seek(SeekFrom::End(0))?;
+    let tail_size = file_size.min((END_OF_CENTRAL_DIRECTORY_SIZE + MAX_COMMENT_SIZE) as u64);
+    reader.seek(SeekFrom::Start(file_size - tail_size))?;
+    let mut tail = vec![0; tail_size as usize];
+    reader.read_exact(&mut tail)?;
+    if tail.len() < END_OF_CENTRAL_DIRECTORY_SIZE {
+        return Err(anyhow!("Archive is truncated"));
+    }
+    let record_offset = (0..=tail.len() - END_OF_CENTRAL_DIRECTORY_SIZE)
+        .rev()
+        .find(|&offset| read_u32(&tail, offset) == END_OF_CENTRAL_DIRECTORY_SIGNATURE)
+        .ok_or_else(|| anyhow!("End of central directory not found"))?;
+    let record = &tail[record_offset..];
+    let entry_count = read_u16(record, 10);
+    let central_directory_offset = read_u32(record, 16);
+    if entry_count == u16::MAX || central_directory_offset == u32::MAX {
+        return Err(anyhow!("ZIP64 archives aren't supported"));
+    }
+    Ok((entry_count as usize, u64::from(central_directory_offset)))
+}
+
+fn read_central_directory_entry<R: Read>(reader: &mut R) -> Result<ZipEntry> {
+    let mut header = [0; 46];
+    reader.read_exact(&mut header)?;
+    if read_u32(&header, 0) != CENTRAL_DIRECTORY_SIGNATURE {
+        return Err(anyhow!("Invalid central directory"));
+    }
+    let mut name = vec![0; read_u16(&header, 28) as usize];
+    reader.read_exact(&mut name)?;
+    // Skip the extra field and the comment
+    let skipped_size = u64::from(read_u16(&header, 30)) + u64::from(read_u16(&header, 32));
+    io::copy(&mut reader.take(skipped_size), &mut io::sink())?;
+
+    let name = String::from_utf8_lossy(&name).to_string();
+    if read_u16(&header, 8) & 1 != 0 {
+        return Err(anyhow!("'{}' is encrypted", name));
+    }
+    let compressed_size = read_u32(&header, 20);
+    let size = read_u32(&header, 24);
+    let local_header_offset = read_u32(&header, 42);
+    if [compressed_size, size, local_header_offset].contains(&u32::MAX) {
+        return Err(anyhow!("ZIP64 archives aren't supported"));
+    }
+    Ok(ZipEntry {
+        name,
+        method: read_u16(&header, 10),
+        crc32: read_u32(&header, 16),
+        compressed_size: u64::from(compressed_size),
+        size: u64::from(size),
+        local_header_offset: u64::from(local_header_offset),
+    })
+}
+
+/// Minimal ZIP archive writer, which compresses each entry in memory, or
+/// streams entries which are stored as is.
+pub(super) struct ZipWriter<W: Write> {
+    writer: W,
+    offset: u32, // Number of bytes written so far
+    central_directory: Vec<u8>,
+    entry_count: u16,
+    dos_time: u16,
+    dos_date: u16,
+}
+
+impl<W: Write> ZipWriter<W> {
+    pub(super) fn new(writer: W) -> Self {
+        let now = Local::now();
+        Self {
+            writer,
+            offset: 0,
+            central_directory: vec![],
+            entry_count: 0,
+            dos_time: ((now.hour() << 11) | (now.minute() << 5) | (now.second() / 2)) as u16,
+            dos_date: (((now.year() - 1980).max(0) << 9) as u32 | (now.month() << 5) | now.day())
+                as u16,
+        }
+    }
+
+    pub(super) fn add_file(&mut self, name: &str, content: &[u8]) -> Result<()> {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(content)?;
+        let compressed_content = encoder.finish()?;
+        self.write_entry(
+            name,
+            METHOD_DEFLATE,
+            crc32(content),
+            content.len() as u64,
+            compressed_content.len() as u64,
+            &mut compressed_content.as_slice(),
+        )
+    }
+
+    /// Adds the content of `reader` without compressing it (e.g. for files
+    /// which are compressed already), reading it twice instead of holding it
+    /// in memory.
+    pub(super) fn add_stored_file<R: Read + Seek>(
+        &mut self,
+        name: &str,
+        reader: &mut R,
+    ) -> Result<()> {
+        // The CRC32 comes before the content
+        let start_offset = reader.stream_position()?;
+        let mut hasher = crc32fast::Hasher::new();
+        let mut size = 0;
+        let mut buffer = vec![0; COPY_BUFFER_SIZE];
+        loop {
+            let read_size = reader.read(&mut buffer)?;
+            if read_size == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read_size]);
+            size += read_size as u64;
+        }
+        reader.seek(SeekFrom::Start(start_offset))?;
+        self.write_entry(
+            name,
+            METHOD_STORED,
+            hasher.finalize(),
+            size,
+            size,
+            &mut reader.take(size),
+        )
+    }
+
+    fn write_entry(
+        &mut self,
+        name: &str,
+        method: u16,
+        crc: u32,
+        size: u64,
+        compressed_size: u64,
+        compressed_content: &mut dyn Read,
+    ) -> Result<()> {
+        let too_large = || anyhow!("'{}' is too large", name);
+        let compressed_size = u32::try_from(compressed_size).map_err(|_| too_large())?;
+        let size = u32::try_from(size).map_err(|_| too_large())?;
+
+        // Fields shared by the local file header and the central directory
+        let mut common_fields = vec![];
+        push_u16(&mut common_fields, 20); // Version needed to extract (2.0)
+        push_u16(&mut common_fields, 1 << 11); // Flags: UTF-8 file name
+        push_u16(&mut common_fields, method);
+        push_u16(&mut common_fields, self.dos_time);
+        push_u16(&mut common_fields, self.dos_date);
+        push_u32(&mut common_fields, crc);
+        push_u32(&mut common_fields, compressed_size);
+        push_u32(&mut common_fields, size);
+        push_u16(&mut common_fields, name.len() as u16);
+        push_u16(&mut common_fields, 0); // Extra field length
+
+        let mut local_header = vec![];
+        push_u32(&mut local_header, LOCAL_FILE_HEADER_SIGNATURE);
+        local_header.extend_from_slice(&common_fields);
+        local_header.extend_from_slice(name.as_bytes());
+        self.writer.write_all(&local_header)?;
+        let written_size = io::copy(compressed_content, &mut self.writer)?;
+        if written_size != u64::from(compressed_size) {
+            return Err(anyhow!("'{}' has been truncated", name));
+        }
+
+        let central_directory = &mut self.central_directory;
+        push_u32(central_directory, CENTRAL_DIRECTORY_SIGNATURE);
+        push_u16(central_directory, 20); // Version made by
+        central_directory.extend_from_slice(&common_fields);
+        push_u16(central_directory, 0); // Comment length
+        push_u16(central_directory, 0); // Disk number
+        push_u16(central_directory, 0); // Internal attributes
+        push_u32(central_directory, 0); // External attributes
+        push_u32(central_directory, self.offset);
+        central_directory.extend_from_slice(name.as_bytes());
+
+        self.offset = (local_header.len() as u32)
+            .checked_add(compressed_size)
+            .and_then(|size| self.offset.checked_add(size))
+            .ok_or_else(|| anyhow!("Archive is too large"))?;
+        self.entry_count += 1;
+        Ok(())
+    }
+
+    /// Writes the central directory and returns the underlying writer.
+    pub(super) fn finish(mut self) -> Result<W> {
+        let mut end_of_central_directory = vec![];
+        push_u32(
+            &mut end_of_central_directory,
+            END_OF_CENTRAL_DIRECTORY_SIGNATURE,
+        );
+        push_u16(&mut end_of_central_directory, 0); // Disk number
+        push_u16(&mut end_of_central_directory, 0); // Disk where the central directory starts
+        push_u16(&mut end_of_central_directory, self.entry_count);
+        push_u16(&mut end_of_central_directory, self.entry_count);
+        push_u32(
+            &mut end_of_central_directory,
+            self.central_directory.len() as u32,
+        );
+        push_u32(&mut end_of_central_directory, self.offset);
+        push_u16(&mut end_of_central_directory, 0); // Comment length
+        self.writer.write_all(&self.central_directory)?;
+        self.writer.write_all(&end_of_central_directory)?;
+        Ok(self.writer)
+    }
+}
+
+fn crc32(content: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(content);
+    hasher.finalize()
+}
+
+fn read_u16(buffer: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([buffer[offset], buffer[offset + 1]])
+}
+
+fn read_u32(buffer: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        buffer[offset],
+        buffer[offset + 1],
+        buffer[offset + 2],
+        buffer[offset + 3],
+    ])
+}
+
+fn push_u16(buffer: &mut Vec<u8>, value: u16) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+fn push_u32(buffer: &mut Vec<u8>, value: u32) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_zip_writer() {
+        let content = b"2021-01-01 00:00:00.000 INFO  [rpatchur] Start patching\n".repeat(10);
+        let mut bundle = ZipWriter::new(vec![]);
+        bundle.add_file("rpatchur.log", &content).unwrap();
+        bundle.add_file("system.txt", b"rpatchur").unwrap();
+        let archive = bundle.finish().unwrap();
+
+        // Local file header of the first entry
+        assert_eq!(archive[0..4], [0x50, 0x4b, 0x03, 0x04]);
+        let read_u32 = |offset: usize| {
+            u32::from_le_bytes([
+                archive[offset],
+                archive[offset + 1],
+                archive[offset + 2],
+                archive[offset + 3],
+            ])
+        };
+        assert_eq!(read_u32(14), crc32(&content));
+        let compressed_size = read_u32(18) as usize;
+        assert_eq!(read_u32(22) as usize, content.len());
+        assert_eq!(&archive[30..42], b"rpatchur.log");
+        let mut decoder = flate2::read::DeflateDecoder::new(&archive[42..42 + compressed_size]);
+        let mut decompressed_content = vec![];
+        decoder.read_to_end(&mut decompressed_content).unwrap();
+        assert_eq!(decompressed_content, content);
+
+        // End of central directory record
+        let end_of_central_directory = &archive[archive.len() - 22..];
+        assert_eq!(end_of_central_directory[0..4], [0x50, 0x4b, 0x05, 0x06]);
+        assert_eq!(end_of_central_directory[10..12], [2, 0]);
+    }
+
+    #[test]
+    fn test_zip_archive() {
+        let content = b"[General]\nLanguage=English\n".repeat(10);
+        let mut writer = ZipWriter::new(vec![]);
+        writer.add_file("data/config.ini", &content).unwrap();
+        writer.add_file("readme.txt", b"").unwrap();
+        writer
+            .add_stored_file("patch.thor", &mut Cursor::new(b"ASSF (C) 2007 Aeomin DEV"))
+            .unwrap();
+        let mut archive_content = writer.finish().unwrap();
+        // Archives can end with a comment
+        let comment_size_offset = archive_content.len() - 2;
+        archive_content[comment_size_offset..].copy_from_slice(&7_u16.to_le_bytes());
+        archive_content.extend_from_slice(b"comment");
+
+        let mut archive = ZipArchive::new(Cursor::new(archive_content.clone())).unwrap();
+        let names: Vec<&str> = archive.entries().iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["data/config.ini", "readme.txt", "patch.thor"]);
+        let entry = archive.entries()[0].clone();
+        assert_eq!(archive.read_file_content(&entry).unwrap(), content);
+        let stored_entry = archive.entries()[2].clone();
+        let mut stored_content = vec![];
+        archive
+            .extract_file_to(&stored_entry, &mut stored_content)
+            .unwrap();
+        assert_eq!(stored_content, b"ASSF (C) 2007 Aeomin DEV");
+        assert!(archive.is_valid());
+
+        // Corrupt the first entry's data, right after its name
+        archive_content[30 + entry.name.len()] ^= 0xff;
+        let mut archive = ZipArchive::new(Cursor::new(archive_content)).unwrap();
+        assert!(archive.read_file_content(&entry).is_err());
+        assert!(!archive.is_valid());
+
+        assert!(ZipArchive::new(Cursor::new(b"ASSF (C) 2007 Aeomin DEV".to_vec())).is_err());
+    }
+
+    #[test]
+    fn test_truncated_zip_archive() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let archive_path = temp_dir.path().join("truncated.zip");
+        for size in 0..=3 {
+            std::fs::write(&archive_path, &b"PK\x05\x06"[..size]).unwrap();
+            assert!(ZipArchive::open(&archive_path).is_err());
+        }
+    }
+}
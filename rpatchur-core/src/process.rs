@@ -0,0 +1,685 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+
+/// Options of the processes started with `start_executable`.
+#[derive(Debug, Default)]
+pub struct LaunchOptions {
+    pub elevated: bool, // Request administrator privileges through UAC (Windows only)
+    pub working_directory: Option<PathBuf>, // The patcher's working directory by default
+    pub environment: HashMap<String, String>, // Added to the patcher's environment
+}
+
+/// Starts an executable file in a cross-platform way.
+///
+/// This is the Windows version. Since `ShellExecuteEx` can't be given an
+/// environment, the patcher's own environment is extended while the process
+/// starts. Elevated processes don't inherit it.
+#[cfg(windows)]
+pub fn start_executable<I, S>(
+    exe_path: &str,
+    exe_arguments: I,
+    options: &LaunchOptions,
+) -> Result<bool>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    // Fold parameter list into a String
+    let exe_parameter = exe_arguments
+        .into_iter()
+        .fold(String::new(), |a: String, b| a + " " + b.as_ref() + "");
+    if options.elevated && !options.environment.is_empty() {
+        log::warn!("Environment variables can't be passed to elevated programs");
+    }
+    let _environment_guard = EnvironmentGuard::extend(&options.environment);
+    windows::win32_spawn_process(
+        exe_path,
+        &exe_parameter,
+        options.working_directory.as_deref(),
+        options.elevated,
+    )
+}
+
+/// Starts an executable file in a cross-platform way.
+///
+/// This is the non-Windows version, `options.elevated` is ignored.
+#[cfg(not(windows))]
+pub fn start_executable<I, S>(
+    exe_path: &str,
+    exe_arguments: I,
+    options: &LaunchOptions,
+) -> Result<bool>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    use std::process::Command;
+
+    if options.elevated {
+        log::debug!("Elevation isn't supported on this platform, ignoring it");
+    }
+    let exe_arguments: Vec<String> = exe_arguments
+        .into_iter()
+        .map(|e| e.as_ref().into())
+        .collect();
+    let mut exe_path = PathBuf::from(exe_path);
+    if options.working_directory.is_some() && exe_path.is_relative() && exe_path.exists() {
+        // The child would otherwise look for it in its own working directory
+        exe_path = std::env::current_dir()?.join(exe_path);
+    }
+    let mut command = Command::new(exe_path);
+    command.args(exe_arguments).envs(&options.environment);
+    if let Some(working_directory) = &options.working_directory {
+        command.current_dir(working_directory);
+    }
+    command.spawn().map(|_| Ok(true))?
+}
+
+/// Extends the current process' environment until dropped, which restores
+/// the previous values.
+#[cfg(windows)]
+struct EnvironmentGuard {
+    previous_values: Vec<(String, Option<std::ffi::OsString>)>,
+}
+
+#[cfg(windows)]
+impl EnvironmentGuard {
+    fn extend(environment: &HashMap<String, String>) -> Self {
+        let previous_values = environment
+            .iter()
+            .map(|(name, value)| {
+                let previous_value = std::env::var_os(name);
+                std::env::set_var(name, value);
+                (name.clone(), previous_value)
+            })
+            .collect();
+        Self { previous_values }
+    }
+}
+
+#[cfg(windows)]
+impl Drop for EnvironmentGuard {
+    fn drop(&mut self) {
+        for (name, previous_value) in &self.previous_values {
+            match previous_value {
+                Some(previous_value) => std::env::set_var(name, previous_value),
+                None => std::env::remove_var(name),
+            }
+        }
+    }
+}
+
+/// Returns the IDs of the running processes whose executable has the same
+/// file name as `exe_path` (case-insensitive).
+pub fn find_running_processes(exe_path: &str) -> Result<Vec<u32>> {
+    let exe_name = executable_file_name(exe_path);
+    let processes = list_processes().context("Failed to list running processes")?;
+    Ok(processes
+        .into_iter()
+        .filter(|(_, process_path)| {
+            executable_file_name(process_path).eq_ignore_ascii_case(exe_name)
+        })
+        .map(|(pid, _)| pid)
+        .collect())
+}
+
+/// Blocks until all the processes started from `exe_path` have exited.
+///
+/// Since launchers might take a while to start the game, this waits for up
+/// to `startup_timeout` for one to show up first.
+pub fn wait_for_processes_exit(exe_path: &str, startup_timeout: Duration) -> Result<()> {
+    const POLL_INTERVAL: Duration = Duration::from_secs(1);
+    let started_at = Instant::now();
+    let mut process_seen = false;
+    loop {
+        if !find_running_processes(exe_path)?.is_empty() {
+            process_seen = true;
+        } else if process_seen || started_at.elapsed() >= startup_timeout {
+            return Ok(());
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Returns the file name of an executable path, which might come from
+/// another platform (e.g. Windows paths of programs run through Wine).
+fn executable_file_name(exe_path: &str) -> &str {
+    exe_path.rsplit(['/', '\\']).next().unwrap_or(exe_path)
+}
+
+/// Lists running processes' IDs along with their executable's path (or
+/// name).
+///
+/// This is the Windows version.
+#[cfg(windows)]
+fn list_processes() -> std::io::Result<Vec<(u32, String)>> {
+    use winapi::um::handleapi::{CloseHandle, INVALID_HANDLE_VALUE};
+    use winapi::um::tlhelp32::{
+        CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, PROCESSENTRY32W,
+        TH32CS_SNAPPROCESS,
+    };
+
+    let mut processes = vec![];
+    unsafe {
+        let snapshot = CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0);
+        if snapshot == INVALID_HANDLE_VALUE {
+            return Err(std::io::Error::last_os_error());
+        }
+        let mut entry: PROCESSENTRY32W = std::mem::zeroed();
+        entry.dwSize = std::mem::size_of::<PROCESSENTRY32W>() as u32;
+        let mut has_entry = Process32FirstW(snapshot, &mut entry) != 0;
+        while has_entry {
+            let name_len = entry
+                .szExeFile
+                .iter()
+                .position(|&c| c == 0)
+                .unwrap_or(entry.szExeFile.len());
+            let name = String::from_utf16_lossy(&entry.szExeFile[..name_len]);
+            processes.push((entry.th32ProcessID, name));
+            has_entry = Process32NextW(snapshot, &mut entry) != 0;
+        }
+        CloseHandle(snapshot);
+    }
+    Ok(processes)
+}
+
+/// Lists running processes' IDs along with their executable's path (or
+/// name).
+///
+/// This is the Linux version, the path comes from the processes' command
+/// lines since programs run through Wine all share the same executable.
+#[cfg(target_os = "linux")]
+fn list_processes() -> std::io::Result<Vec<(u32, String)>> {
+    let mut processes = vec![];
+    for entry in std::fs::read_dir("/proc")? {
+        let entry = entry?;
+        let pid = match entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.parse().ok())
+        {
+            Some(pid) => pid,
+            None => continue,
+        };
+        // Processes might exit while we're iterating
+        if let Ok(cmdline) = std::fs::read(entry.path().join("cmdline")) {
+            let exe_path = cmdline.split(|&c| c == 0).next().unwrap_or_default();
+            processes.push((pid, String::from_utf8_lossy(exe_path).to_string()));
+        }
+    }
+    Ok(processes)
+}
+
+/// Lists running processes' IDs along with their executable's path (or
+/// name).
+///
+/// This is the version for Unix platforms without procfs.
+#[cfg(all(unix, not(target_os = "linux")))]
+fn list_processes() -> std::io::Result<Vec<(u32, String)>> {
+    let output = std::process::Command::new("ps")
+        .args(&["-A", "-o", "pid=", "-o", "comm="])
+        .output()?;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.trim().splitn(2, ' ');
+            let pid = fields.next()?.parse().ok()?;
+            Some((pid, fields.next()?.trim().to_string()))
+        })
+        .collect())
+}
+
+/// Forcefully terminates a process.
+///
+/// This is the Windows version.
+#[cfg(windows)]
+pub fn terminate_process(pid: u32) -> Result<()> {
+    use winapi::um::handleapi::CloseHandle;
+    use winapi::um::processthreadsapi::{OpenProcess, TerminateProcess};
+    use winapi::um::winnt::PROCESS_TERMINATE;
+
+    unsafe {
+        let process = OpenProcess(PROCESS_TERMINATE, 0, pid);
+        if process.is_null() {
+            return Err(std::io::Error::last_os_error())
+                .with_context(|| format!("Failed to open process {}", pid));
+        }
+        let terminated = TerminateProcess(process, 1) != 0;
+        let error = std::io::Error::last_os_error();
+        CloseHandle(process);
+        if !terminated {
+            return Err(error).with_context(|| format!("Failed to terminate process {}", pid));
+        }
+    }
+    Ok(())
+}
+
+/// Terminates a process.
+///
+/// This is the Unix version, which lets the process exit gracefully.
+#[cfg(unix)]
+pub fn terminate_process(pid: u32) -> Result<()> {
+    if unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM) } != 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("Failed to terminate process {}", pid));
+    }
+    Ok(())
+}
+
+/// Starts another instance of the patcher with administrator privileges,
+/// which starts updating the game right away.
+///
+/// Returns `false` if the user declined to elevate the patcher.
+#[cfg(windows)]
+pub fn restart_elevated() -> Result<bool> {
+    let current_exe = std::env::current_exe()?;
+    let current_dir = std::env::current_dir()?;
+    // Elevated processes don't inherit the working directory
+    let mut arguments = vec![
+        "--working-directory".to_string(),
+        format!("\"{}\"", current_dir.display()),
+        "--start-update".to_string(),
+    ];
+    if let Some(profile) = crate::patcher::get_profile() {
+        arguments.extend(["--profile".to_string(), profile.to_string()]);
+    }
+    if crate::patcher::is_portable() {
+        arguments.push("--portable".to_string());
+    }
+    let options = LaunchOptions {
+        elevated: true,
+        ..Default::default()
+    };
+    start_executable(&current_exe.to_string_lossy(), &arguments, &options)
+}
+
+/// Starts another instance of the patcher with administrator privileges.
+///
+/// This is the non-Windows version, which always fails.
+#[cfg(not(windows))]
+pub fn restart_elevated() -> Result<bool> {
+    Err(anyhow::anyhow!("Elevation is only supported on Windows"))
+}
+
+/// Returns whether the current process runs with administrator privileges.
+///
+/// This is the Windows version, which checks whether the process' token is
+/// elevated.
+#[cfg(windows)]
+pub fn is_elevated() -> bool {
+    use winapi::um::handleapi::CloseHandle;
+    use winapi::um::processthreadsapi::{GetCurrentProcess, OpenProcessToken};
+    use winapi::um::securitybaseapi::GetTokenInformation;
+    use winapi::um::winnt::{TokenElevation, HANDLE, TOKEN_ELEVATION, TOKEN_QUERY};
+
+    unsafe {
+        let mut token: HANDLE = std::ptr::null_mut();
+        if OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token) == 0 {
+            return false;
+        }
+        let mut elevation: TOKEN_ELEVATION = std::mem::zeroed();
+        let mut returned_size = 0;
+        let res = GetTokenInformation(
+            token,
+            TokenElevation,
+            &mut elevation as *mut TOKEN_ELEVATION as *mut _,
+            std::mem::size_of::<TOKEN_ELEVATION>() as u32,
+            &mut returned_size,
+        );
+        CloseHandle(token);
+        res != 0 && elevation.TokenIsElevated != 0
+    }
+}
+
+/// Returns whether the current process runs with administrator privileges.
+///
+/// This is the Unix version, which checks whether the process runs as root.
+#[cfg(unix)]
+pub fn is_elevated() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+/// Lowers the CPU and I/O priority of the current thread while `enabled`, so
+/// that updates don't slow the game and other programs down.
+///
+/// This is the Windows version, which uses the thread background mode.
+#[cfg(windows)]
+pub fn set_background_priority(enabled: bool) -> Result<()> {
+    use winapi::um::processthreadsapi::{GetCurrentThread, SetThreadPriority};
+    use winapi::um::winbase::{THREAD_MODE_BACKGROUND_BEGIN, THREAD_MODE_BACKGROUND_END};
+
+    let mode = match enabled {
+        true => THREAD_MODE_BACKGROUND_BEGIN,
+        false => THREAD_MODE_BACKGROUND_END,
+    };
+    if unsafe { SetThreadPriority(GetCurrentThread(), mode as i32) } == 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| "Failed to change the thread's priority");
+    }
+    Ok(())
+}
+
+/// Lowers the I/O priority of the current thread while `enabled`, so that
+/// updates don't slow the game and other programs down.
+///
+/// This is the Linux version, which uses the idle I/O scheduling class (i.e.
+/// `ionice -c 3`).
+#[cfg(target_os = "linux")]
+pub fn set_background_priority(enabled: bool) -> Result<()> {
+    // See `ioprio_set(2)`, which libc doesn't wrap
+    const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+    const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+    const IOPRIO_CLASS_IDLE: libc::c_int = 3;
+
+    // Without a class, the I/O priority follows the CPU priority again
+    let priority = match enabled {
+        true => IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT,
+        false => 0,
+    };
+    // The ID 0 designates the calling thread
+    let res = unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, priority) };
+    if res != 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| "Failed to change the thread's I/O priority");
+    }
+    Ok(())
+}
+
+/// Lowers the priority of the current thread while `enabled`.
+///
+/// This is the version for other platforms, which does nothing.
+#[cfg(all(unix, not(target_os = "linux")))]
+pub fn set_background_priority(_enabled: bool) -> Result<()> {
+    Ok(())
+}
+
+/// Returns the name of the platform the patcher runs on (e.g. "win64"), as
+/// used in the `platform` tag of patches.
+///
+/// This is the Windows version, which tells 64-bit systems apart even from
+/// 32-bit builds.
+#[cfg(windows)]
+pub fn current_platform() -> &'static str {
+    // Only set for 32-bit processes running on 64-bit systems
+    if cfg!(target_pointer_width = "64") || std::env::var_os("PROCESSOR_ARCHITEW6432").is_some() {
+        "win64"
+    } else {
+        "win32"
+    }
+}
+
+/// Returns the name of the platform the patcher runs on (e.g. "linux"), as
+/// used in the `platform` tag of patches.
+///
+/// This is the version for other platforms, which are named after the OS.
+#[cfg(not(windows))]
+pub fn current_platform() -> &'static str {
+    std::env::consts::OS
+}
+
+/// Shows the window titled `window_title` directly through the Win32 API,
+/// since eframe doesn't process viewport commands while the window is hidden.
+///
+/// This also works for windows of other processes (e.g. another instance of
+/// the patcher).
+#[cfg(windows)]
+pub fn restore_window(window_title: &str) {
+    use std::ffi::OsStr;
+    use std::os::windows::ffi::OsStrExt;
+    use std::ptr;
+    use winapi::um::winuser::{FindWindowW, SetForegroundWindow, ShowWindow, SW_RESTORE};
+
+    let window_title: Vec<u16> = OsStr::new(window_title)
+        .encode_wide()
+        .chain(Some(0))
+        .collect();
+    unsafe {
+        let hwnd = FindWindowW(ptr::null(), window_title.as_ptr());
+        if !hwnd.is_null() {
+            ShowWindow(hwnd, SW_RESTORE);
+            SetForegroundWindow(hwnd);
+        }
+    }
+}
+
+/// Shows the window titled `window_title`.
+///
+/// This is the non-Windows version, which does nothing since the patcher
+/// shows its window itself when asked to.
+#[cfg(not(windows))]
+pub fn restore_window(_window_title: &str) {}
+
+/// Adds a shortcut named `name` to the user's desktop, which starts the
+/// executable at `exe_path` from its directory.
+///
+/// This is the Windows version, which has PowerShell create the '.lnk' file
+/// through the `WScript.Shell` COM object.
+#[cfg(windows)]
+pub fn create_desktop_shortcut(name: &str, exe_path: &std::path::Path) -> Result<()> {
+    use std::os::windows::process::CommandExt;
+    use std::process::Command;
+    use winapi::um::winbase::CREATE_NO_WINDOW;
+
+    let desktop_directory = dirs::desktop_dir().context("Failed to find the desktop directory")?;
+    let shortcut_path = desktop_directory.join(format!("{}.lnk", name));
+    let working_directory = exe_path.parent().unwrap_or(exe_path);
+    // Single-quoted strings are literal, except for their quotes
+    let quote =
+        |value: &std::path::Path| format!("'{}'", value.to_string_lossy().replace('\'', "''"));
+    let script = format!(
+        "$shortcut = (New-Object -ComObject WScript.Shell).CreateShortcut({}); \
+         $shortcut.TargetPath = {}; $shortcut.WorkingDirectory = {}; $shortcut.Save()",
+        quote(&shortcut_path),
+        quote(exe_path),
+        quote(working_directory),
+    );
+    let status = Command::new("powershell")
+        .args(["-NoProfile", "-NonInteractive", "-Command", &script])
+        .creation_flags(CREATE_NO_WINDOW)
+        .status()
+        .context("Failed to run PowerShell")?;
+    if !status.success() {
+        return Err(anyhow::anyhow!(
+            "Failed to create '{}'",
+            shortcut_path.display()
+        ));
+    }
+    Ok(())
+}
+
+/// Adds a shortcut named `name` to the user's desktop, which starts the
+/// executable at `exe_path` from its directory.
+///
+/// This is the non-Windows version, which writes a desktop entry.
+#[cfg(not(windows))]
+pub fn create_desktop_shortcut(name: &str, exe_path: &std::path::Path) -> Result<()> {
+    let desktop_directory = dirs::desktop_dir().context("Failed to find the desktop directory")?;
+    let shortcut_path = desktop_directory.join(format!("{}.desktop", name));
+    let working_directory = exe_path.parent().unwrap_or(exe_path);
+    let desktop_entry = format!(
+        "[Desktop Entry]\nType=Application\nName={}\nExec=\"{}\"\nPath={}\nTerminal=false\n",
+        name,
+        exe_path.display(),
+        working_directory.display()
+    );
+    std::fs::write(&shortcut_path, desktop_entry)
+        .with_context(|| format!("Failed to create '{}'", shortcut_path.display()))?;
+    // Desktop environments only start trusted, executable entries
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&shortcut_path, std::fs::Permissions::from_mode(0o755))?;
+    }
+    Ok(())
+}
+
+/// Opens the file manager on the directory containing `path`, with the file
+/// selected.
+///
+/// This is the Windows version, which relies on Explorer's `/select` switch.
+#[cfg(windows)]
+pub fn reveal_in_file_manager(path: &std::path::Path) -> Result<()> {
+    use std::os::windows::process::CommandExt;
+    use std::process::Command;
+
+    // Explorer exits with an error code even when it succeeds
+    Command::new("explorer")
+        .raw_arg(format!("/select,\"{}\"", path.display()))
+        .spawn()
+        .context("Failed to start Explorer")?;
+    Ok(())
+}
+
+/// Opens the file manager on the directory containing `path`, with the file
+/// selected.
+///
+/// This is the macOS version, which relies on `open -R`.
+#[cfg(target_os = "macos")]
+pub fn reveal_in_file_manager(path: &std::path::Path) -> Result<()> {
+    std::process::Command::new("open")
+        .arg("-R")
+        .arg(path)
+        .spawn()
+        .context("Failed to start Finder")?;
+    Ok(())
+}
+
+/// Opens the file manager on the directory containing `path`.
+///
+/// This is the version for other platforms, whose file managers can't be
+/// told which file to select.
+#[cfg(not(any(windows, target_os = "macos")))]
+pub fn reveal_in_file_manager(path: &std::path::Path) -> Result<()> {
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => std::path::Path::new("."),
+    };
+    open::that(directory).with_context(|| format!("Failed to open '{}'", directory.display()))?;
+    Ok(())
+}
+
+// Note: Taken from the rustup project
+#[cfg(windows)]
+mod windows {
+    use anyhow::{anyhow, Result};
+    use std::ffi::OsStr;
+    use std::os::windows::ffi::OsStrExt;
+    use std::path::Path;
+
+    fn to_u16s<S: AsRef<OsStr>>(s: S) -> Result<Vec<u16>> {
+        fn inner(s: &OsStr) -> Result<Vec<u16>> {
+            let mut maybe_result: Vec<u16> = s.encode_wide().collect();
+            if maybe_result.contains(&0) {
+                return Err(anyhow!("strings passed to WinAPI cannot contain NULs"));
+            }
+            maybe_result.push(0);
+            Ok(maybe_result)
+        }
+        inner(s.as_ref())
+    }
+
+    /// This function is required to start processes that require elevation, from
+    /// a non-elevated process.
+    pub fn win32_spawn_process(
+        path: &str,
+        parameter: &str,
+        working_directory: Option<&Path>,
+        elevated: bool,
+    ) -> Result<bool> {
+        use std::ptr;
+        use winapi::ctypes::c_int;
+        use winapi::shared::minwindef::{BOOL, ULONG};
+        use winapi::um::shellapi::SHELLEXECUTEINFOW;
+        extern "system" {
+            pub fn ShellExecuteExW(pExecInfo: *mut SHELLEXECUTEINFOW) -> BOOL;
+        }
+        const SEE_MASK_CLASSNAME: ULONG = 1;
+        const SW_SHOW: c_int = 5;
+
+        // Note: It seems `path` has to be absolute for the class overwrite to work
+        let current_dir = std::env::current_dir()?;
+        let exe_path = current_dir.join(path);
+        let exe_path = to_u16s(exe_path.to_str().unwrap_or(""))?;
+        let parameter = to_u16s(parameter)?;
+        let working_directory = working_directory
+            .map(|working_directory| to_u16s(current_dir.join(working_directory)))
+            .transpose()?;
+        let operation = to_u16s(if elevated { "runas" } else { "open" })?;
+        let class = to_u16s("exefile")?;
+        let mut execute_info = SHELLEXECUTEINFOW {
+            cbSize: std::mem::size_of::<SHELLEXECUTEINFOW>() as u32,
+            fMask: SEE_MASK_CLASSNAME,
+            hwnd: ptr::null_mut(),
+            lpVerb: operation.as_ptr(),
+            lpFile: exe_path.as_ptr(),
+            lpParameters: parameter.as_ptr(),
+            lpDirectory: working_directory
+                .as_ref()
+                .map_or(ptr::null(), |working_directory| working_directory.as_ptr()),
+            nShow: SW_SHOW,
+            hInstApp: ptr::null_mut(),
+            lpIDList: ptr::null_mut(),
+            lpClass: class.as_ptr(),
+            hkeyClass: ptr::null_mut(),
+            dwHotKey: 0,
+            hMonitor: ptr::null_mut(),
+            hProcess: ptr::null_mut(),
+        };
+
+        let result = unsafe { ShellExecuteExW(&mut execute_info) };
+        Ok(result != 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_executable_file_name() {
+        assert_eq!(executable_file_name("ragexe.exe"), "ragexe.exe");
+        assert_eq!(executable_file_name("bin/ragexe.exe"), "ragexe.exe");
+        assert_eq!(
+            executable_file_name("C:\\Games\\RO\\Ragexe.exe"),
+            "Ragexe.exe"
+        );
+    }
+
+    #[test]
+    fn test_find_running_processes() {
+        let current_exe = std::env::current_exe().unwrap();
+        let current_exe = current_exe.to_str().unwrap();
+        let pids = find_running_processes(current_exe).unwrap();
+        assert!(pids.contains(&std::process::id()));
+        assert!(find_running_processes("not-running.exe")
+            .unwrap()
+            .is_empty());
+        wait_for_processes_exit("not-running.exe", Duration::from_secs(0)).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_start_executable_with_options() {
+        let working_directory = tempfile::tempdir().unwrap();
+        let options = LaunchOptions {
+            working_directory: Some(working_directory.path().to_path_buf()),
+            environment: vec![("RPATCHUR_TEST".to_string(), "1rag1".to_string())]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        };
+        let arguments = ["-c", "echo \"$RPATCHUR_TEST\" > out.txt"];
+        assert!(start_executable("sh", arguments, &options).unwrap());
+
+        let out_path = working_directory.path().join("out.txt");
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !out_path.exists() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(std::fs::read_to_string(out_path).unwrap(), "1rag1\n");
+    }
+}
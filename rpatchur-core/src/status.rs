@@ -0,0 +1,354 @@
+use std::path::PathBuf;
+
+use crate::patcher::ErrorKind;
+
+/// Progress of the patcher's operations, sent to the frontend as they go.
+pub enum PatchingStatus {
+    Ready,
+    Error(ErrorReport),
+    DownloadInProgress(usize, usize, u64),
+    DownloadSizeEstimated(u64),
+    PatchSelectionRequired(Vec<PatchPreview>), // Pending patches to choose from
+    GameClientRunning,                         // Patching is on hold until the game client exits
+    FilesLocked,       // Patching is retried once the game closes the files it holds open
+    ElevationRequired, // The game's files can only be patched by an elevated instance
+    PatchesDownloaded(usize), // Patches downloaded without being applied
+    PatchResults(Vec<PatchResult>), // Sent before the error, when some patches failed to download
+    UnverifiedPatches(Vec<String>), // Patches applied without integrity data, when warning about them
+    ReleaseNotes(Vec<ReleaseNote>), // Notes of the patches that have just been applied
+    FileDownloadInProgress {
+        file_name: String,
+        downloaded_bytes: u64,
+        total_bytes: u64, // 0 if unknown
+        overall_downloaded_bytes: u64,
+    },
+    InstallationInProgress(usize, usize),
+    UpdatesAvailable(usize),
+    ManualPatchApplied(String),
+    GameInstalled(PathBuf), // Path of the patcher installed along with the game
+    BundleExported(PathBuf),
+    BundleApplied(usize), // Number of patches applied from the bundle
+    OrphanedFilesFound(Vec<OrphanedFile>),
+    OrphanedFilesDeleted(usize, u64), // Number of files deleted and bytes freed
+    GrfRepaired(String, bool),        // Name of the GRF, and whether it had to be rebuilt
+    PatchServersProbed(Vec<PatchServerHealth>), // In the order of the configuration
+    UpdateRunningElsewhere, // Another instance is updating the game, the progress sent next is its own
+    Cancelled(UpdateProgress), // Sent instead of an error when the operation has been canceled
+    DownloadSummary(UpdateProgress), // Sent before the outcome of updates which downloaded patches
+}
+
+/// Progress of the operations of an embedded `Patcher`.
+///
+/// Unlike statuses, events only cover what launchers embedding the patcher
+/// need to show (i.e. not the questions asked by the patcher's own window).
+#[derive(Clone, Debug)]
+pub enum PatchingEvent {
+    UpdatesAvailable {
+        patch_count: usize,
+    },
+    DownloadProgress {
+        downloaded_patches: usize,
+        total_patches: usize,
+        bytes_per_sec: u64,
+    },
+    DownloadSizeEstimated {
+        total_bytes: u64,
+    },
+    FileDownloadProgress {
+        file_name: String,
+        downloaded_bytes: u64,
+        total_bytes: Option<u64>,
+    },
+    InstallationProgress {
+        installed_patches: usize,
+        total_patches: usize,
+    },
+    GameClientRunning, // Patching is on hold until the game client exits
+    FilesLocked,       // Patching is retried once the game closes the files it holds open
+    PatchesDownloaded {
+        patch_count: usize, // Patches downloaded without being applied
+    },
+    ReleaseNotes(Vec<ReleaseNote>),
+    UnverifiedPatches {
+        file_names: Vec<String>, // Patches applied without integrity data
+    },
+    Error(ErrorReport),
+    Cancelled(UpdateProgress),
+    Completed, // The game is up to date, unless an error was sent before
+}
+
+impl PatchingEvent {
+    /// Returns the event matching `status`, if any.
+    pub fn from_status(status: PatchingStatus) -> Option<Self> {
+        let event = match status {
+            PatchingStatus::Ready => PatchingEvent::Completed,
+            PatchingStatus::Error(report) => PatchingEvent::Error(report),
+            PatchingStatus::UpdatesAvailable(patch_count) => {
+                PatchingEvent::UpdatesAvailable { patch_count }
+            }
+            PatchingStatus::DownloadInProgress(
+                downloaded_patches,
+                total_patches,
+                bytes_per_sec,
+            ) => PatchingEvent::DownloadProgress {
+                downloaded_patches,
+                total_patches,
+                bytes_per_sec,
+            },
+            PatchingStatus::DownloadSizeEstimated(total_bytes) => {
+                PatchingEvent::DownloadSizeEstimated { total_bytes }
+            }
+            PatchingStatus::FileDownloadInProgress {
+                file_name,
+                downloaded_bytes,
+                total_bytes,
+                ..
+            } => PatchingEvent::FileDownloadProgress {
+                file_name,
+                downloaded_bytes,
+                total_bytes: Some(total_bytes).filter(|&total_bytes| total_bytes > 0),
+            },
+            PatchingStatus::InstallationInProgress(installed_patches, total_patches) => {
+                PatchingEvent::InstallationProgress {
+                    installed_patches,
+                    total_patches,
+                }
+            }
+            PatchingStatus::GameClientRunning => PatchingEvent::GameClientRunning,
+            PatchingStatus::FilesLocked => PatchingEvent::FilesLocked,
+            PatchingStatus::PatchesDownloaded(patch_count) => {
+                PatchingEvent::PatchesDownloaded { patch_count }
+            }
+            PatchingStatus::ReleaseNotes(notes) => PatchingEvent::ReleaseNotes(notes),
+            PatchingStatus::UnverifiedPatches(file_names) => {
+                PatchingEvent::UnverifiedPatches { file_names }
+            }
+            PatchingStatus::Cancelled(progress) => PatchingEvent::Cancelled(progress),
+            _ => return None,
+        };
+        Some(event)
+    }
+}
+
+/// How far an operation got, e.g. before it was canceled.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct UpdateProgress {
+    pub downloaded_patches: usize,
+    pub applied_patches: usize,
+    pub total_patches: usize, // 0 if the pending patches weren't known yet
+    pub downloaded_bytes: u64,
+    pub elapsed_ms: u64, // Time spent since the operation started
+}
+
+/// Error message along with its chain of causes, outermost context first.
+#[derive(Clone, Debug)]
+pub struct ErrorReport {
+    chain: Vec<String>,
+    kind: ErrorKind,
+}
+
+impl ErrorReport {
+    /// Creates a report from a single message, which can be a translation key.
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            chain: vec![message.into()],
+            kind: ErrorKind::Other,
+        }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// Returns the causes of the error, outermost first.
+    pub fn causes(&self) -> &[String] {
+        self.chain.get(1..).unwrap_or_default()
+    }
+
+    pub fn summary(&self) -> &str {
+        self.chain.first().map(String::as_str).unwrap_or_default()
+    }
+
+    /// Formats the whole chain, one cause per line.
+    pub fn details(&self) -> String {
+        let mut details = self.summary().to_string();
+        for (i, cause) in self.chain.iter().skip(1).enumerate() {
+            if i == 0 {
+                details += "\n\nCaused by:";
+            }
+            details += &format!("\n    {}: {}", i, cause);
+        }
+        details
+    }
+}
+
+impl From<&anyhow::Error> for ErrorReport {
+    fn from(error: &anyhow::Error) -> Self {
+        Self {
+            chain: error.chain().map(|cause| cause.to_string()).collect(),
+            kind: ErrorKind::of(error),
+        }
+    }
+}
+
+/// Summary of a pending patch, shown to users before downloading it.
+pub struct PatchPreview {
+    pub index: usize,
+    pub file_name: String,
+    pub size: Option<u64>,
+}
+
+/// File of the game directory which neither the base client nor any patch
+/// contains.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OrphanedFile {
+    pub relative_path: String,
+    pub size: u64,
+}
+
+/// Outcome of the probe of one of the patch servers.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PatchServerHealth {
+    pub name: String,
+    pub latency_ms: u64,       // Time taken to answer, or to fail
+    pub error: Option<String>, // Why the server is unavailable
+}
+
+impl PatchServerHealth {
+    pub fn is_available(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// What became of one of the patches of an update which didn't complete.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PatchResult {
+    pub index: usize,
+    pub file_name: String,
+    pub outcome: PatchOutcome,
+}
+
+/// Release notes of a patch applied during the last update.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReleaseNote {
+    pub index: usize,
+    pub file_name: String,
+    pub text: Option<String>, // Markdown
+    pub url: Option<String>,  // Set when the notes couldn't be downloaded
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PatchOutcome {
+    Applied,
+    Pending,        // Downloaded, but waiting for a patch which failed before it
+    Failed(String), // Why the patch couldn't be downloaded
+}
+
+impl PatchOutcome {
+    /// Returns the name used to refer to the outcome in machine-readable
+    /// outputs.
+    pub fn name(&self) -> &'static str {
+        match self {
+            PatchOutcome::Applied => "applied",
+            PatchOutcome::Pending => "pending",
+            PatchOutcome::Failed(_) => "failed",
+        }
+    }
+}
+
+/// Formats a number of bytes with the most appropriate (decimal) unit.
+pub fn human_readable_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1000 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64;
+    let mut unit = UNITS[0];
+    for u in UNITS.iter() {
+        size /= 1000.0;
+        unit = u;
+        if size < 1000.0 {
+            break;
+        }
+    }
+    format!("{:.1} {}", size, unit)
+}
+
+/// Formats a duration compactly (e.g. "3m12s"), down to the second.
+pub fn human_readable_duration(duration_ms: u64) -> String {
+    let secs = duration_ms / 1000;
+    match (secs / 3600, secs / 60 % 60, secs % 60) {
+        (0, 0, secs) => format!("{}s", secs),
+        (0, mins, secs) => format!("{}m{:02}s", mins, secs),
+        (hours, mins, secs) => format!("{}h{:02}m{:02}s", hours, mins, secs),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn test_human_readable_size() {
+        assert_eq!(human_readable_size(0), "0 B");
+        assert_eq!(human_readable_size(999), "999 B");
+        assert_eq!(human_readable_size(1_500), "1.5 KB");
+        assert_eq!(human_readable_size(3_400_000_000), "3.4 GB");
+        assert_eq!(human_readable_size(5_000_000_000_000_000), "5000.0 TB");
+    }
+
+    #[test]
+    fn test_human_readable_duration() {
+        assert_eq!(human_readable_duration(0), "0s");
+        assert_eq!(human_readable_duration(42_900), "42s");
+        assert_eq!(human_readable_duration(192_000), "3m12s");
+        assert_eq!(human_readable_duration(3_605_000), "1h00m05s");
+    }
+
+    #[test]
+    fn test_patching_event_from_status() {
+        let status = PatchingStatus::FileDownloadInProgress {
+            file_name: "1.thor".to_string(),
+            downloaded_bytes: 10,
+            total_bytes: 0,
+            overall_downloaded_bytes: 10,
+        };
+        match PatchingEvent::from_status(status) {
+            Some(PatchingEvent::FileDownloadProgress {
+                file_name,
+                downloaded_bytes,
+                total_bytes,
+            }) => {
+                assert_eq!(file_name, "1.thor");
+                assert_eq!(downloaded_bytes, 10);
+                assert_eq!(total_bytes, None);
+            }
+            event => panic!("Unexpected event: {:?}", event),
+        }
+        assert!(matches!(
+            PatchingEvent::from_status(PatchingStatus::Ready),
+            Some(PatchingEvent::Completed)
+        ));
+        // Only the patcher's window can answer
+        assert!(PatchingEvent::from_status(PatchingStatus::ElevationRequired).is_none());
+    }
+
+    #[test]
+    fn test_error_report_details() {
+        let error = std::fs::File::open("/nonexistent/rpatchur.yml")
+            .context("Failed to open configuration")
+            .context("Failed to start")
+            .unwrap_err();
+        let report = ErrorReport::from(&error);
+        assert_eq!(report.summary(), "Failed to start");
+        assert_eq!(report.causes().len(), 2);
+        let details = report.details();
+        assert!(details.starts_with(
+            "Failed to start\n\nCaused by:\n    0: Failed to open configuration\n    1: "
+        ));
+        assert!(ErrorReport::new("error.channel_disconnected")
+            .causes()
+            .is_empty());
+    }
+}
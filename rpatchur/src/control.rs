@@ -0,0 +1,441 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serde_json::json;
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::patcher::{ControlApiConfiguration, PatcherCommand};
+use crate::ui::PatchingStatus;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_HEADER_COUNT: usize = 64;
+
+/// Last statuses sent by the patching thread, as returned by `GET /status`.
+#[derive(Default)]
+struct StatusSnapshot {
+    status: Option<serde_json::Value>,
+    file_download: Option<serde_json::Value>, // Progress of the file being downloaded, if any
+}
+
+impl StatusSnapshot {
+    fn update(&mut self, status: &PatchingStatus) {
+        let json = status_json(status);
+        match status {
+            PatchingStatus::FileDownloadInProgress { .. } => self.file_download = Some(json),
+            PatchingStatus::DownloadInProgress(..) => self.status = Some(json),
+            _ => {
+                self.file_download = None;
+                self.status = Some(json);
+            }
+        }
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        json!({
+            "status": self.status.clone().unwrap_or_else(|| json!({ "state": "ready" })),
+            "file_download": self.file_download,
+        })
+    }
+}
+
+/// Binds the control API's socket, which only accepts local connections.
+pub fn bind_control_api(config: &ControlApiConfiguration) -> Result<TcpListener> {
+    let port = config.port();
+    TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+        .with_context(|| format!("Failed to listen on port {}", port))
+}
+
+/// Serves the control API, which lets external tools (e.g. stream overlays)
+/// follow and drive the patcher.
+///
+/// Statuses received on `status_rx` are forwarded to the returned receiver,
+/// which takes its place.
+pub fn serve_control_api(
+    listener: TcpListener,
+    config: &ControlApiConfiguration,
+    patching_thread_tx: UnboundedSender<PatcherCommand>,
+    status_rx: mpsc::Receiver<PatchingStatus>,
+) -> mpsc::Receiver<PatchingStatus> {
+    let snapshot = Arc::new(Mutex::new(StatusSnapshot::default()));
+    let (forwarded_status_tx, forwarded_status_rx) = mpsc::channel();
+    {
+        let snapshot = snapshot.clone();
+        std::thread::spawn(move || {
+            for status in status_rx {
+                snapshot
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .update(&status);
+                if forwarded_status_tx.send(status).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    let token = config.token.clone();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    log::warn!("Failed to accept control API connection: {}", e);
+                    continue;
+                }
+            };
+            if let Err(e) =
+                handle_connection(stream, token.as_deref(), &snapshot, &patching_thread_tx)
+            {
+                log::debug!("Control API request failed: {:#}", e);
+            }
+        }
+    });
+    forwarded_status_rx
+}
+
+struct Request {
+    method: String,
+    path: String,
+    authorization: Option<String>,
+    has_origin: bool, // Set by browsers, which shouldn't be able to control the patcher
+}
+
+fn handle_connection(
+    stream: TcpStream,
+    token: Option<&str>,
+    snapshot: &Mutex<StatusSnapshot>,
+    patching_thread_tx: &UnboundedSender<PatcherCommand>,
+) -> Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut reader = BufReader::new(&stream);
+    let request = read_request(&mut reader)?;
+    let (status_code, body) = match &request {
+        Some(request) => handle_request(request, token, snapshot, patching_thread_tx),
+        None => (400, json!({ "error": "Malformed request" })),
+    };
+    write_response(&stream, status_code, &body)
+}
+
+/// Reads the request line and headers, skipping the body since no endpoint
+/// needs one.
+fn read_request(reader: &mut impl BufRead) -> Result<Option<Request>> {
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let (method, path) = match (parts.next(), parts.next()) {
+        (Some(method), Some(path)) => (method.to_string(), path.to_string()),
+        _ => return Ok(None),
+    };
+    let mut request = Request {
+        method,
+        path,
+        authorization: None,
+        has_origin: false,
+    };
+    let mut content_length = 0;
+    for _ in 0..MAX_HEADER_COUNT {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        let (name, value) = match header.split_once(':') {
+            Some((name, value)) => (name.trim().to_ascii_lowercase(), value.trim()),
+            None => return Ok(None),
+        };
+        match name.as_str() {
+            "authorization" => request.authorization = Some(value.to_string()),
+            "origin" => request.has_origin = true,
+            "content-length" => content_length = value.parse().unwrap_or(0),
+            _ => {}
+        }
+    }
+    std::io::copy(&mut reader.take(content_length), &mut std::io::sink())?;
+    Ok(Some(request))
+}
+
+fn handle_request(
+    request: &Request,
+    token: Option<&str>,
+    snapshot: &Mutex<StatusSnapshot>,
+    patching_thread_tx: &UnboundedSender<PatcherCommand>,
+) -> (u16, serde_json::Value) {
+    if request.has_origin {
+        return (
+            403,
+            json!({ "error": "Requests from web pages aren't allowed" }),
+        );
+    }
+    if let Some(token) = token {
+        let expected_authorization = format!("Bearer {}", token);
+        if request.authorization.as_deref() != Some(expected_authorization.as_str()) {
+            return (401, json!({ "error": "Invalid token" }));
+        }
+    }
+    // Query strings aren't used
+    let path = request.path.split('?').next().unwrap_or_default();
+    let command = match (request.method.as_str(), path) {
+        ("GET", "/status") => {
+            let snapshot = snapshot.lock().unwrap_or_else(|e| e.into_inner());
+            return (200, snapshot.to_json());
+        }
+        ("POST", "/update") => PatcherCommand::StartUpdate,
+        ("POST", "/check") => PatcherCommand::CheckForUpdates,
+        ("POST", "/cancel") => PatcherCommand::CancelUpdate,
+        ("POST", "/force-cancel") => PatcherCommand::ForceCancelUpdate,
+        (_, "/status") | (_, "/update") | (_, "/check") | (_, "/cancel") | (_, "/force-cancel") => {
+            return (405, json!({ "error": "Method not allowed" }))
+        }
+        _ => return (404, json!({ "error": "Not found" })),
+    };
+    match patching_thread_tx.send(command) {
+        Ok(()) => (202, json!({ "accepted": true })),
+        Err(_) => (503, json!({ "error": "The patcher is shutting down" })),
+    }
+}
+
+fn write_response(
+    mut stream: &TcpStream,
+    status_code: u16,
+    body: &serde_json::Value,
+) -> Result<()> {
+    let reason = match status_code {
+        200 => "OK",
+        202 => "Accepted",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Service Unavailable",
+    };
+    let body = body.to_string();
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status_code,
+        reason,
+        body.len(),
+        body
+    )?;
+    stream.flush()?;
+    Ok(())
+}
+
+/// Returns the JSON representation of a status, which mirrors
+/// `PatchingStatus`.
+fn status_json(status: &PatchingStatus) -> serde_json::Value {
+    match status {
+        PatchingStatus::Ready => json!({ "state": "ready" }),
+        PatchingStatus::Error(report) => json!({
+            "state": "error",
+            "message": report.summary(),
+            "causes": report.causes(),
+            "kind": report.kind().name(),
+        }),
+        PatchingStatus::DownloadInProgress(nb_downloaded, nb_total, bytes_per_sec) => json!({
+            "state": "downloading",
+            "downloaded": nb_downloaded,
+            "total": nb_total,
+            "bytes_per_sec": bytes_per_sec,
+        }),
+        PatchingStatus::DownloadSizeEstimated(total_bytes) => json!({
+            "state": "download_size_estimated",
+            "total_bytes": total_bytes,
+        }),
+        PatchingStatus::PatchSelectionRequired(patches) => json!({
+            "state": "patch_selection_required",
+            "patches": patches
+                .iter()
+                .map(|patch| json!({ "name": patch.file_name, "size": patch.size }))
+                .collect::<Vec<_>>(),
+        }),
+        PatchingStatus::GameClientRunning => json!({ "state": "game_client_running" }),
+        PatchingStatus::FilesLocked => json!({ "state": "files_locked" }),
+        PatchingStatus::ElevationRequired => json!({ "state": "elevation_required" }),
+        PatchingStatus::PatchesDownloaded(patch_count) => json!({
+            "state": "patches_downloaded",
+            "count": patch_count,
+        }),
+        PatchingStatus::PatchResults(results) => json!({
+            "state": "patch_results",
+            "patches": results
+                .iter()
+                .map(|result| json!({
+                    "index": result.index,
+                    "name": result.file_name,
+                    "outcome": result.outcome.name(),
+                }))
+                .collect::<Vec<_>>(),
+        }),
+        PatchingStatus::OrphanedFilesFound(files) => json!({
+            "state": "orphaned_files",
+            "files": files
+                .iter()
+                .map(|file| json!({
+                    "path": file.relative_path,
+                    "size": file.size,
+                }))
+                .collect::<Vec<_>>(),
+        }),
+        PatchingStatus::UnverifiedPatches(file_names) => json!({
+            "state": "unverified_patches",
+            "patches": file_names,
+        }),
+        PatchingStatus::OrphanedFilesDeleted(file_count, freed_bytes) => json!({
+            "state": "orphaned_files_deleted",
+            "count": file_count,
+            "freed_bytes": freed_bytes,
+        }),
+        PatchingStatus::ReleaseNotes(notes) => json!({
+            "state": "release_notes",
+            "patches": notes
+                .iter()
+                .map(|note| json!({
+                    "index": note.index,
+                    "name": note.file_name,
+                    "text": note.text,
+                    "url": note.url,
+                }))
+                .collect::<Vec<_>>(),
+        }),
+        PatchingStatus::FileDownloadInProgress {
+            file_name,
+            downloaded_bytes,
+            total_bytes,
+            overall_downloaded_bytes,
+        } => json!({
+            "file_name": file_name,
+            "downloaded_bytes": downloaded_bytes,
+            "total_bytes": total_bytes,
+            "overall_downloaded_bytes": overall_downloaded_bytes,
+        }),
+        PatchingStatus::InstallationInProgress(nb_installed, nb_total) => json!({
+            "state": "installing",
+            "installed": nb_installed,
+            "total": nb_total,
+        }),
+        PatchingStatus::UpdatesAvailable(patch_count) => json!({
+            "state": "updates_available",
+            "count": patch_count,
+        }),
+        PatchingStatus::ManualPatchApplied(name) => json!({
+            "state": "manual_patch_applied",
+            "name": name,
+        }),
+        PatchingStatus::GameInstalled(patcher_path) => json!({
+            "state": "game_installed",
+            "patcher_path": patcher_path,
+        }),
+        PatchingStatus::BundleExported(bundle_path) => json!({
+            "state": "bundle_exported",
+            "path": bundle_path,
+        }),
+        PatchingStatus::BundleApplied(patch_count) => json!({
+            "state": "bundle_applied",
+            "count": patch_count,
+        }),
+        PatchingStatus::PatchServersProbed(health) => json!({
+            "state": "patch_servers_probed",
+            "servers": health
+                .iter()
+                .map(|server| json!({
+                    "name": server.name,
+                    "available": server.is_available(),
+                    "latency_ms": server.latency_ms,
+                    "error": server.error,
+                }))
+                .collect::<Vec<_>>(),
+        }),
+        PatchingStatus::GrfRepaired(grf_name, rebuilt) => json!({
+            "state": "grf_repaired",
+            "name": grf_name,
+            "rebuilt": rebuilt,
+        }),
+        PatchingStatus::UpdateRunningElsewhere => json!({ "state": "update_running_elsewhere" }),
+        PatchingStatus::DownloadSummary(progress) => json!({
+            "state": "download_summary",
+            "downloaded_bytes": progress.downloaded_bytes,
+            "duration_ms": progress.elapsed_ms,
+        }),
+        PatchingStatus::Cancelled(progress) => json!({
+            "state": "cancelled",
+            "downloaded": progress.downloaded_patches,
+            "applied": progress.applied_patches,
+            "total": progress.total_patches,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc::unbounded_channel;
+
+    fn send_request(port: u16, request: &str) -> (u16, serde_json::Value) {
+        let mut stream = TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let status_code = response[9..12].parse().unwrap();
+        let body = response.split("\r\n\r\n").nth(1).unwrap();
+        (status_code, serde_json::from_str(body).unwrap())
+    }
+
+    #[test]
+    fn test_control_api() {
+        let config = ControlApiConfiguration {
+            enabled: true,
+            port: Some(0),
+            token: Some("secret".to_string()),
+        };
+        let listener = bind_control_api(&config).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (patching_thread_tx, mut patching_thread_rx) = unbounded_channel();
+        let (status_tx, status_rx) = mpsc::channel();
+        let forwarded_status_rx =
+            serve_control_api(listener, &config, patching_thread_tx, status_rx);
+
+        status_tx
+            .send(PatchingStatus::InstallationInProgress(1, 3))
+            .unwrap();
+        // Statuses still reach the UI
+        assert!(matches!(
+            forwarded_status_rx.recv().unwrap(),
+            PatchingStatus::InstallationInProgress(1, 3)
+        ));
+        let (status_code, body) = send_request(
+            port,
+            "GET /status HTTP/1.1\r\nAuthorization: Bearer secret\r\n\r\n",
+        );
+        assert_eq!(status_code, 200);
+        assert_eq!(body["status"]["state"], "installing");
+        assert_eq!(body["status"]["installed"], 1);
+
+        let (status_code, _) = send_request(
+            port,
+            "POST /update HTTP/1.1\r\nAuthorization: Bearer secret\r\nContent-Length: 2\r\n\r\n{}",
+        );
+        assert_eq!(status_code, 202);
+        assert!(matches!(
+            patching_thread_rx.try_recv().unwrap(),
+            PatcherCommand::StartUpdate
+        ));
+
+        let (status_code, _) = send_request(port, "POST /cancel HTTP/1.1\r\n\r\n");
+        assert_eq!(status_code, 401);
+        let (status_code, _) = send_request(
+            port,
+            "POST /cancel HTTP/1.1\r\nAuthorization: Bearer secret\r\nOrigin: https://example.com\r\n\r\n",
+        );
+        assert_eq!(status_code, 403);
+        let (status_code, _) = send_request(
+            port,
+            "GET /update HTTP/1.1\r\nAuthorization: Bearer secret\r\n\r\n",
+        );
+        assert_eq!(status_code, 405);
+        assert!(patching_thread_rx.try_recv().is_err());
+    }
+}
@@ -0,0 +1,79 @@
+use std::backtrace::Backtrace;
+use std::panic::{self, PanicHookInfo};
+use std::path::Path;
+use std::sync::OnceLock;
+
+use tinyfiledialogs::{MessageBoxIcon, YesNo};
+
+use crate::i18n::Translator;
+use crate::patcher::{write_crash_report, LocalizationConfiguration};
+
+// Set once the window is about to be opened. Before that, and for headless
+// commands, crashes are only reported in the logs.
+static CRASH_DIALOG_LOCALIZATION: OnceLock<LocalizationConfiguration> = OnceLock::new();
+
+/// Writes a crash report whenever the patcher panics, so that crashes don't
+/// go unnoticed.
+pub fn install_crash_handler() {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        let backtrace = Backtrace::force_capture().to_string();
+        match write_crash_report(&panic_description(info), &backtrace) {
+            Ok(report_path) => {
+                log::error!("The patcher crashed, see '{}'", report_path.display());
+                if let Some(localization) = CRASH_DIALOG_LOCALIZATION.get() {
+                    show_crash_dialog(localization, &report_path);
+                }
+            }
+            Err(e) => log::error!(
+                "The patcher crashed, and the report couldn't be written: {:#}",
+                e
+            ),
+        }
+    }));
+}
+
+/// Makes crashes show a dialog offering to open the crash report, instead of
+/// the window vanishing.
+pub fn enable_crash_dialog(localization: &LocalizationConfiguration) {
+    let _ = CRASH_DIALOG_LOCALIZATION.set(localization.clone());
+}
+
+fn panic_description(info: &PanicHookInfo) -> String {
+    let payload = info.payload();
+    let message = match payload.downcast_ref::<&str>() {
+        Some(message) => message.to_string(),
+        None => match payload.downcast_ref::<String>() {
+            Some(message) => message.clone(),
+            None => "Box<dyn Any>".to_string(),
+        },
+    };
+    let thread = std::thread::current();
+    let location = match info.location() {
+        Some(location) => location.to_string(),
+        None => "an unknown location".to_string(),
+    };
+    format!(
+        "Thread '{}' panicked at {}:\n{}",
+        thread.name().unwrap_or("<unnamed>"),
+        location,
+        message
+    )
+}
+
+fn show_crash_dialog(localization: &LocalizationConfiguration, report_path: &Path) {
+    let translator = Translator::new(localization);
+    let report_path_str = report_path.display().to_string();
+    let answer = tinyfiledialogs::message_box_yes_no(
+        translator.tr("crash.title"),
+        &translator.tr_args("crash.message", &[("path", &report_path_str)]),
+        MessageBoxIcon::Error,
+        YesNo::Yes,
+    );
+    if answer == YesNo::Yes {
+        if let Err(e) = open::that(report_path) {
+            log::warn!("Failed to open the crash report: {}", e);
+        }
+    }
+}
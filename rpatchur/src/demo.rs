@@ -0,0 +1,195 @@
+use std::sync::mpsc;
+use std::time::Duration;
+
+use tokio::sync::mpsc::UnboundedReceiver;
+
+use crate::patcher::PatcherCommand;
+use crate::ui::{
+    ErrorReport, PatchOutcome, PatchResult, PatchingStatus, ReleaseNote, UpdateProgress,
+};
+
+const STEP_DELAY: Duration = Duration::from_millis(120);
+const PATCH_COUNT: usize = 5;
+const PATCH_SIZE: u64 = 6 * 1024 * 1024;
+const FILE_PROGRESS_STEPS: u64 = 4;
+const BYTES_PER_SEC: u64 = 2 * 1024 * 1024;
+// Patch whose download fails during the failing updates
+const FAILING_PATCH_INDEX: usize = 4;
+
+/// Stands in for the patching thread in demo mode: commands are answered
+/// with scripted statuses, without reaching patch servers nor touching the
+/// game's files.
+///
+/// Sequences are the same on every run, so that screenshots can be taken
+/// again. Updates succeed and fail in turn, to show both outcomes.
+pub fn demo_thread_routine(
+    mut patching_thread_rx: UnboundedReceiver<PatcherCommand>,
+    status_tx: mpsc::Sender<PatchingStatus>,
+) {
+    let _ = status_tx.send(PatchingStatus::UpdatesAvailable(PATCH_COUNT));
+    let mut update_count = 0;
+    while let Some(command) = patching_thread_rx.blocking_recv() {
+        let sequence = match command {
+            PatcherCommand::StartUpdate | PatcherCommand::RetryFailedPatches => {
+                update_count += 1;
+                update_sequence(update_count % 2 == 0)
+            }
+            PatcherCommand::CheckForUpdates => vec![PatchingStatus::UpdatesAvailable(PATCH_COUNT)],
+            PatcherCommand::Quit => break,
+            // Nothing happens outside of updates
+            _ => {
+                log::info!("Demo mode: ignoring command");
+                continue;
+            }
+        };
+        if !play_sequence(sequence, &mut patching_thread_rx, &status_tx) {
+            break;
+        }
+    }
+}
+
+/// Sends the statuses of `sequence` one step at a time, until a command
+/// cancels it.
+///
+/// Returns false once the patcher is quitting.
+fn play_sequence(
+    sequence: Vec<PatchingStatus>,
+    patching_thread_rx: &mut UnboundedReceiver<PatcherCommand>,
+    status_tx: &mpsc::Sender<PatchingStatus>,
+) -> bool {
+    let mut progress = UpdateProgress {
+        total_patches: PATCH_COUNT,
+        ..Default::default()
+    };
+    for status in sequence {
+        match patching_thread_rx.try_recv() {
+            Ok(PatcherCommand::CancelUpdate) | Ok(PatcherCommand::ForceCancelUpdate) => {
+                return status_tx.send(PatchingStatus::Cancelled(progress)).is_ok();
+            }
+            Ok(PatcherCommand::Quit) => return false,
+            _ => {}
+        }
+        match status {
+            PatchingStatus::DownloadInProgress(downloaded, ..) => {
+                progress.downloaded_patches = downloaded
+            }
+            PatchingStatus::InstallationInProgress(installed, _) => {
+                progress.applied_patches = installed
+            }
+            _ => {}
+        }
+        if status_tx.send(status).is_err() {
+            return false;
+        }
+        std::thread::sleep(STEP_DELAY);
+    }
+    true
+}
+
+/// Returns the statuses an update of the demo patches goes through.
+///
+/// Failing updates stop after the download of one of the patches failed.
+fn update_sequence(failing: bool) -> Vec<PatchingStatus> {
+    let mut sequence = vec![
+        PatchingStatus::DownloadSizeEstimated(PATCH_COUNT as u64 * PATCH_SIZE),
+        PatchingStatus::DownloadInProgress(0, PATCH_COUNT, 0),
+    ];
+    for patch_index in 1..=PATCH_COUNT {
+        let overall_downloaded_bytes = (patch_index - 1) as u64 * PATCH_SIZE;
+        for step in 1..=FILE_PROGRESS_STEPS {
+            let downloaded_bytes = PATCH_SIZE * step / FILE_PROGRESS_STEPS;
+            sequence.push(PatchingStatus::FileDownloadInProgress {
+                file_name: patch_file_name(patch_index),
+                downloaded_bytes,
+                total_bytes: PATCH_SIZE,
+                overall_downloaded_bytes: overall_downloaded_bytes + downloaded_bytes,
+            });
+        }
+        sequence.push(PatchingStatus::DownloadInProgress(
+            patch_index,
+            PATCH_COUNT,
+            BYTES_PER_SEC,
+        ));
+    }
+
+    if failing {
+        let results = (1..=PATCH_COUNT)
+            .map(|index| PatchResult {
+                index,
+                file_name: patch_file_name(index),
+                outcome: match index {
+                    FAILING_PATCH_INDEX => PatchOutcome::Failed(format!(
+                        "Patch file '{}' not found on the remote server",
+                        patch_file_name(index)
+                    )),
+                    index if index > FAILING_PATCH_INDEX => PatchOutcome::Pending,
+                    _ => PatchOutcome::Applied,
+                },
+            })
+            .collect();
+        sequence.extend((0..FAILING_PATCH_INDEX).map(|installed_patches| {
+            PatchingStatus::InstallationInProgress(installed_patches, FAILING_PATCH_INDEX - 1)
+        }));
+        sequence.push(PatchingStatus::PatchResults(results));
+        sequence.push(PatchingStatus::Error(ErrorReport::new(
+            "1 patches failed to download",
+        )));
+    } else {
+        sequence.extend((0..=PATCH_COUNT).map(|installed_patches| {
+            PatchingStatus::InstallationInProgress(installed_patches, PATCH_COUNT)
+        }));
+        let release_notes = (1..=PATCH_COUNT)
+            .map(|index| ReleaseNote {
+                index,
+                file_name: patch_file_name(index),
+                text: Some(format!(
+                    "## Update {}\n\n- New costumes\n- Balance changes",
+                    index
+                )),
+                url: None,
+            })
+            .collect();
+        sequence.push(PatchingStatus::ReleaseNotes(release_notes));
+        sequence.push(PatchingStatus::DownloadSummary(UpdateProgress {
+            downloaded_patches: PATCH_COUNT,
+            applied_patches: PATCH_COUNT,
+            total_patches: PATCH_COUNT,
+            downloaded_bytes: PATCH_COUNT as u64 * PATCH_SIZE,
+            elapsed_ms: sequence.len() as u64 * STEP_DELAY.as_millis() as u64,
+        }));
+        sequence.push(PatchingStatus::Ready);
+    }
+    sequence
+}
+
+fn patch_file_name(index: usize) -> String {
+    format!("demo_{:04}.thor", index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_update_sequence() {
+        let sequence = update_sequence(false);
+        assert!(matches!(sequence.last(), Some(PatchingStatus::Ready)));
+        let downloads: Vec<usize> = sequence
+            .iter()
+            .filter_map(|status| match status {
+                PatchingStatus::DownloadInProgress(downloaded, total, _) => {
+                    assert_eq!(*total, PATCH_COUNT);
+                    Some(*downloaded)
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(downloads, (0..=PATCH_COUNT).collect::<Vec<_>>());
+
+        let sequence = update_sequence(true);
+        assert!(matches!(sequence.last(), Some(PatchingStatus::Error(_))));
+        assert!(sequence
+            .iter()
+            .any(|status| matches!(status, PatchingStatus::PatchResults(_))));
+    }
+}
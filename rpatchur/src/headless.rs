@@ -0,0 +1,1155 @@
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use serde_json::json;
+use structopt::StructOpt;
+
+use crate::i18n::Translator;
+use crate::patcher::{
+    create_support_bundle, diff_grfs, publish_release, retrieve_patcher_state, run_health_checks,
+    run_patcher_command, squash_published_patches, CheckResult, CheckStatus, ErrorKind,
+    GrfDiffOptions, InstalledFile, LocalizationConfiguration, PatcherCommand, PatcherConfiguration,
+    PublishOptions, SquashOptions,
+};
+#[cfg(feature = "mock-server")]
+use crate::patcher::{MockPatchServer, MockServerOptions};
+use crate::ui::{
+    human_readable_duration, human_readable_size, ErrorReport, PatchOutcome, PatchingStatus,
+};
+
+// Commands which run without the patcher's window, for scripts and
+// environments where the GUI can't be used (a doc comment would replace the
+// application's description in `--help`)
+#[derive(Debug, StructOpt)]
+pub enum HeadlessCommand {
+    /// Downloads and applies the pending patches
+    Update,
+    /// Reports the number and total size of the pending patches, without
+    /// downloading them
+    Check,
+    /// Applies a THOR patch
+    ManualPatch {
+        #[structopt(parse(from_os_str))]
+        patch_file: PathBuf,
+    },
+    /// Resets the patcher's cache
+    ResetCache,
+    /// Installs the game client along with the patcher in a directory, which
+    /// is then patched by the installed patcher
+    Install {
+        #[structopt(parse(from_os_str))]
+        directory: PathBuf,
+    },
+    /// Downloads the pending patches and packs them into a bundle, to update
+    /// machines which can't reach the patch servers (e.g. at LAN events)
+    ExportBundle {
+        /// Path of the bundle to create (e.g. 'patches.rpb')
+        #[structopt(parse(from_os_str))]
+        output: PathBuf,
+        /// Exports every patch, for game clients which have never been patched
+        #[structopt(long)]
+        all: bool,
+        /// Merges consecutive patches into cumulative ones, which makes
+        /// bundles smaller when files are patched several times
+        #[structopt(long)]
+        squash: bool,
+    },
+    /// Applies the patches of an offline bundle which haven't been applied
+    /// yet
+    ApplyBundle {
+        #[structopt(parse(from_os_str))]
+        bundle_file: PathBuf,
+    },
+    /// Lists the files of the game directory left over by removed content,
+    /// which neither the base client nor any patch contains
+    Cleanup {
+        /// Deletes the leftover files
+        #[structopt(long)]
+        delete: bool,
+    },
+    /// Checks the files of a GRF, and rebuilds it from the client's archives
+    /// and the applied patches if some of them can't be read
+    RepairGrf {
+        /// Name of the GRF (e.g. 'data.grf')
+        grf_name: String,
+    },
+    /// Creates a ZIP archive containing the logs, configuration and system
+    /// information, to attach to support requests
+    SupportBundle,
+    /// Checks for the most common causes of failed updates (configuration,
+    /// patch servers, permissions, disk space, ...)
+    Doctor,
+    /// Lists the files installed by patches, with their size, checksum and
+    /// the patch they come from
+    Manifest,
+    /// Builds a THOR patch from the differences between two releases of the
+    /// client and adds it to a patch server (for operators)
+    Publish {
+        /// Directory containing the new release of the client
+        #[structopt(parse(from_os_str))]
+        client_directory: PathBuf,
+        /// Directory containing the previous release (all files are patched
+        /// if omitted)
+        #[structopt(long, parse(from_os_str))]
+        previous: Option<PathBuf>,
+        /// Directory of the patch server, which contains 'plist.txt'
+        #[structopt(short, long, parse(from_os_str), default_value = ".")]
+        output: PathBuf,
+        /// Merges the files into this GRF instead of the game directory
+        #[structopt(long)]
+        target_grf: Option<String>,
+        /// PEM-encoded Ed25519 private key the manifest is signed with
+        #[structopt(long, parse(from_os_str))]
+        signing_key: Option<PathBuf>,
+        /// Uploads the new files (e.g. 's3://bucket/patches' or
+        /// 'sftp://user@host/var/www/patches')
+        #[structopt(long)]
+        upload: Option<String>,
+        /// Release channel of the patch (e.g. 'beta'), stable by default
+        #[structopt(long)]
+        channel: Option<String>,
+        /// Makes older patchers refuse to patch the game (e.g. when the patch
+        /// uses a new format)
+        #[structopt(long)]
+        min_patcher_version: Option<String>,
+    },
+    /// Builds a THOR patch from the differences between two releases of a
+    /// GRF (for operators)
+    Diff {
+        #[structopt(parse(from_os_str))]
+        old_grf: PathBuf,
+        #[structopt(parse(from_os_str))]
+        new_grf: PathBuf,
+        /// Path of the patch to create (e.g. 'patch.thor')
+        #[structopt(short, long, parse(from_os_str))]
+        output: PathBuf,
+        /// Merges the files into this GRF instead of the client's default one
+        #[structopt(long)]
+        target_grf: Option<String>,
+    },
+    /// Merges a range of the patches of a patch server into a single
+    /// cumulative patch, which replaces them in 'plist.txt' (for operators)
+    Squash {
+        /// Directory of the patch server, which contains 'plist.txt'
+        #[structopt(short, long, parse(from_os_str), default_value = ".")]
+        output: PathBuf,
+        /// Index of the first patch to squash (the first one of the list if
+        /// omitted)
+        #[structopt(long)]
+        from: Option<usize>,
+        /// Index of the last patch to squash (the last one of the list if
+        /// omitted)
+        #[structopt(long)]
+        to: Option<usize>,
+        /// PEM-encoded Ed25519 private key the manifest is signed with
+        #[structopt(long, parse(from_os_str))]
+        signing_key: Option<PathBuf>,
+        /// Uploads the new files (e.g. 's3://bucket/patches' or
+        /// 'sftp://user@host/var/www/patches')
+        #[structopt(long)]
+        upload: Option<String>,
+    },
+    /// Serves generated patches on localhost, to try out the patcher and its
+    /// skins without a real patch server (for developers)
+    #[cfg(feature = "mock-server")]
+    MockServer {
+        /// Port to listen on, 0 picks any free port
+        #[structopt(long, default_value = "8080")]
+        port: u16,
+        /// Number of patches listed in 'plist.txt'
+        #[structopt(long, default_value = "5")]
+        patches: usize,
+        /// Size of the file added by each patch, in KiB
+        #[structopt(long, default_value = "512")]
+        patch_size: usize,
+        /// Limits the bandwidth of each download, in KiB/s
+        #[structopt(long)]
+        throttle: Option<u64>,
+        /// Makes the downloads of the patch at this index fail (can be
+        /// repeated)
+        #[structopt(long)]
+        fail: Vec<usize>,
+        /// Serves the patch at this index truncated (can be repeated)
+        #[structopt(long)]
+        corrupt: Vec<usize>,
+    },
+}
+
+impl HeadlessCommand {
+    /// Returns the command to run on the patching thread, if any.
+    fn to_patcher_command(&self) -> Option<PatcherCommand> {
+        let patcher_command = match self {
+            HeadlessCommand::Update => PatcherCommand::StartUpdate,
+            HeadlessCommand::Check => PatcherCommand::CheckForUpdates,
+            HeadlessCommand::ManualPatch { patch_file } => {
+                PatcherCommand::ManualPatchFile(patch_file.clone())
+            }
+            HeadlessCommand::ResetCache => PatcherCommand::ResetCache,
+            HeadlessCommand::Install { directory } => PatcherCommand::Install(directory.clone()),
+            HeadlessCommand::ExportBundle {
+                output,
+                all,
+                squash,
+            } => PatcherCommand::ExportBundle {
+                path: output.clone(),
+                all_patches: *all,
+                squash: *squash,
+            },
+            HeadlessCommand::ApplyBundle { bundle_file } => {
+                PatcherCommand::ApplyBundle(bundle_file.clone())
+            }
+            HeadlessCommand::Cleanup { delete: false } => PatcherCommand::FindOrphanedFiles,
+            HeadlessCommand::Cleanup { delete: true } => PatcherCommand::DeleteOrphanedFiles(None),
+            HeadlessCommand::RepairGrf { grf_name } => PatcherCommand::RepairGrf(grf_name.clone()),
+            HeadlessCommand::SupportBundle
+            | HeadlessCommand::Doctor
+            | HeadlessCommand::Manifest
+            | HeadlessCommand::Publish { .. }
+            | HeadlessCommand::Squash { .. }
+            | HeadlessCommand::Diff { .. } => return None,
+            #[cfg(feature = "mock-server")]
+            HeadlessCommand::MockServer { .. } => return None,
+        };
+        Some(patcher_command)
+    }
+
+    /// Returns true if the command needs a valid configuration file.
+    pub fn requires_configuration(&self) -> bool {
+        self.to_patcher_command().is_some()
+    }
+}
+
+/// Format of the progress printed on stdout.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    Text,
+    Json, // Newline-delimited JSON events
+}
+
+/// Exit codes of the headless commands, which let scripts branch on the
+/// outcome.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExitCode {
+    UpToDate = 0,           // Nothing had to be done, or the command succeeded
+    UnexpectedError = 1,    // Any error that doesn't fall in the categories below
+    ConfigurationError = 2, // The configuration file is missing or invalid
+    NetworkError = 3,       // Patch servers couldn't be reached
+    IntegrityError = 4,     // A downloaded patch is corrupt
+    LockContention = 5,     // Another instance of the patcher is updating the game
+    GameClientRunning = 6,  // The game has to be closed before patching it
+    PatcherOutdated = 7,    // A more recent patcher is required to patch the game
+    Cancelled = 8,          // The command was canceled before it was done
+    Patched = 10,           // Patches have been applied
+    PatchesPending = 11,    // Patches are pending (`check`) or have only been downloaded
+}
+
+impl From<ErrorKind> for ExitCode {
+    fn from(kind: ErrorKind) -> Self {
+        match kind {
+            ErrorKind::Network => ExitCode::NetworkError,
+            ErrorKind::Integrity => ExitCode::IntegrityError,
+            ErrorKind::LockContention => ExitCode::LockContention,
+            ErrorKind::GameClientRunning => ExitCode::GameClientRunning,
+            ErrorKind::PatcherOutdated => ExitCode::PatcherOutdated,
+            ErrorKind::Cancelled => ExitCode::Cancelled,
+            ErrorKind::Other => ExitCode::UnexpectedError,
+        }
+    }
+}
+
+/// Runs `command` to completion, printing its progress on stdout.
+pub fn run_headless(
+    mut config: PatcherConfiguration,
+    command: HeadlessCommand,
+    output_format: OutputFormat,
+) -> ExitCode {
+    #[cfg(windows)]
+    attach_parent_console();
+
+    // Nobody would be there to review the pending patches
+    config.patching.preview_patches = false;
+    let translator = Translator::new(&config.localization);
+
+    let patcher_command = match command.to_patcher_command() {
+        Some(patcher_command) => patcher_command,
+        None => return run_standalone_command(&command, &config.localization, output_format),
+    };
+    let (status_tx, status_rx) = mpsc::channel();
+    let patching_thread =
+        std::thread::spawn(move || run_patcher_command(config, patcher_command, status_tx));
+    let mut printer = StatusPrinter::new(translator, output_format);
+    // The channel is closed once the command is done
+    for status in status_rx {
+        printer.print(&status);
+    }
+    let (exit_code, error) = match patching_thread.join() {
+        Ok(Ok(())) => printer.outcome(&command),
+        Ok(Err(e)) => (ExitCode::UnexpectedError, Some(format!("{:#}", e))),
+        Err(_) => (
+            ExitCode::UnexpectedError,
+            Some("Patching thread panicked".to_string()),
+        ),
+    };
+    print_outcome(output_format, exit_code, error)
+}
+
+/// Runs a command which doesn't need the patching thread, nor a valid
+/// configuration.
+pub fn run_standalone_command(
+    command: &HeadlessCommand,
+    localization: &LocalizationConfiguration,
+    output_format: OutputFormat,
+) -> ExitCode {
+    #[cfg(windows)]
+    attach_parent_console();
+
+    match command {
+        HeadlessCommand::Doctor => run_doctor_command(output_format),
+        HeadlessCommand::Manifest => run_manifest_command(output_format),
+        HeadlessCommand::Publish {
+            client_directory,
+            previous,
+            output,
+            target_grf,
+            signing_key,
+            upload,
+            channel,
+            min_patcher_version,
+        } => {
+            let options = PublishOptions {
+                client_directory: client_directory.clone(),
+                previous_client_directory: previous.clone(),
+                patch_server_directory: output.clone(),
+                target_grf_name: target_grf.clone(),
+                signing_key_path: signing_key.clone(),
+                upload_url: upload.clone(),
+                channel: channel.clone(),
+                min_patcher_version: min_patcher_version.clone(),
+            };
+            run_publish_command(&options, output_format)
+        }
+        HeadlessCommand::Squash {
+            output,
+            from,
+            to,
+            signing_key,
+            upload,
+        } => {
+            let options = SquashOptions {
+                patch_server_directory: output.clone(),
+                first_index: *from,
+                last_index: *to,
+                signing_key_path: signing_key.clone(),
+                upload_url: upload.clone(),
+            };
+            run_squash_command(&options, output_format)
+        }
+        HeadlessCommand::Diff {
+            old_grf,
+            new_grf,
+            output,
+            target_grf,
+        } => {
+            let options = GrfDiffOptions {
+                old_grf_path: old_grf.clone(),
+                new_grf_path: new_grf.clone(),
+                patch_path: output.clone(),
+                target_grf_name: target_grf.clone(),
+            };
+            run_diff_command(&options, output_format)
+        }
+        #[cfg(feature = "mock-server")]
+        HeadlessCommand::MockServer {
+            port,
+            patches,
+            patch_size,
+            throttle,
+            fail,
+            corrupt,
+        } => {
+            let options = MockServerOptions {
+                port: *port,
+                patch_count: *patches,
+                patch_size: patch_size * 1024,
+                bytes_per_sec: throttle.map(|throttle| throttle * 1024),
+                failing_patches: fail.clone(),
+                corrupt_patches: corrupt.clone(),
+            };
+            run_mock_server_command(&options, output_format)
+        }
+        _ => run_support_bundle_command(localization, output_format),
+    }
+}
+
+fn run_doctor_command(output_format: OutputFormat) -> ExitCode {
+    let results = run_health_checks();
+    for result in &results {
+        match output_format {
+            OutputFormat::Text => println!("{}", doctor_line(result)),
+            OutputFormat::Json => println!(
+                "{}",
+                json!({
+                    "event": "check",
+                    "name": result.name,
+                    "status": match result.status {
+                        CheckStatus::Pass => "pass",
+                        CheckStatus::Warning => "warning",
+                        CheckStatus::Failure => "failure",
+                    },
+                    "details": result.details,
+                })
+            ),
+        }
+    }
+    let failure = results
+        .iter()
+        .find(|result| result.status == CheckStatus::Failure);
+    match failure {
+        Some(failure) => {
+            let exit_code = match failure.name {
+                "configuration" => ExitCode::ConfigurationError,
+                "patch_servers" => ExitCode::NetworkError,
+                _ => ExitCode::UnexpectedError,
+            };
+            let error = format!("Check '{}' failed", failure.name);
+            print_outcome(output_format, exit_code, Some(error))
+        }
+        None => print_outcome(output_format, ExitCode::UpToDate, None),
+    }
+}
+
+fn doctor_line(result: &CheckResult) -> String {
+    let status = match result.status {
+        CheckStatus::Pass => "PASS",
+        CheckStatus::Warning => "WARN",
+        CheckStatus::Failure => "FAIL",
+    };
+    format!("[{}] {}: {}", status, result.name, result.details)
+}
+
+fn run_manifest_command(output_format: OutputFormat) -> ExitCode {
+    let manifest = match retrieve_patcher_state() {
+        Ok(state) => state.manifest,
+        Err(e) => {
+            let error = format!("Failed to read the patcher state: {:#}", e);
+            return print_outcome(output_format, ExitCode::UnexpectedError, Some(error));
+        }
+    };
+    let game_files = manifest.files.iter().map(|file| (None, file));
+    let grf_entries = manifest.grf_entries.iter().flat_map(|(grf_name, entries)| {
+        entries
+            .iter()
+            .map(move |entry| (Some(grf_name.as_str()), entry))
+    });
+    for (grf_name, (relative_path, installed_file)) in game_files.chain(grf_entries) {
+        match output_format {
+            OutputFormat::Text => {
+                println!("{}", manifest_line(grf_name, relative_path, installed_file))
+            }
+            OutputFormat::Json => println!(
+                "{}",
+                json!({
+                    "event": "file",
+                    "grf": grf_name,
+                    "path": relative_path,
+                    "size": installed_file.size,
+                    "crc32": format!("{:08x}", installed_file.crc32),
+                    "patch": installed_file.patch,
+                })
+            ),
+        }
+    }
+    print_outcome(output_format, ExitCode::UpToDate, None)
+}
+
+fn manifest_line(
+    grf_name: Option<&str>,
+    relative_path: &str,
+    installed_file: &InstalledFile,
+) -> String {
+    let location = match grf_name {
+        Some(grf_name) => format!("{}:{}", grf_name, relative_path),
+        None => relative_path.to_string(),
+    };
+    format!(
+        "{}\t{}\t{:08x}\t{}",
+        location, installed_file.size, installed_file.crc32, installed_file.patch
+    )
+}
+
+fn run_publish_command(options: &PublishOptions, output_format: OutputFormat) -> ExitCode {
+    match publish_release(options) {
+        Ok(patch) => {
+            match output_format {
+                OutputFormat::Text => println!(
+                    "Published '{}' (index {}): {} updated, {} removed file(s)",
+                    patch.file_name, patch.index, patch.updated_files, patch.removed_files
+                ),
+                OutputFormat::Json => println!(
+                    "{}",
+                    json!({
+                        "event": "published",
+                        "index": patch.index,
+                        "file_name": patch.file_name,
+                        "updated_files": patch.updated_files,
+                        "removed_files": patch.removed_files,
+                    })
+                ),
+            }
+            print_outcome(output_format, ExitCode::UpToDate, None)
+        }
+        Err(e) => print_outcome(
+            output_format,
+            ExitCode::UnexpectedError,
+            Some(format!("Failed to publish the release: {:#}", e)),
+        ),
+    }
+}
+
+fn run_squash_command(options: &SquashOptions, output_format: OutputFormat) -> ExitCode {
+    match squash_published_patches(options) {
+        Ok(patch) => {
+            match output_format {
+                OutputFormat::Text => println!(
+                    "Squashed {} patches into '{}' (index {}): {} updated, {} removed file(s)",
+                    patch.squashed_patches,
+                    patch.file_name,
+                    patch.index,
+                    patch.updated_files,
+                    patch.removed_files
+                ),
+                OutputFormat::Json => println!(
+                    "{}",
+                    json!({
+                        "event": "squashed",
+                        "index": patch.index,
+                        "file_name": patch.file_name,
+                        "squashed_patches": patch.squashed_patches,
+                        "updated_files": patch.updated_files,
+                        "removed_files": patch.removed_files,
+                    })
+                ),
+            }
+            print_outcome(output_format, ExitCode::UpToDate, None)
+        }
+        Err(e) => print_outcome(
+            output_format,
+            ExitCode::UnexpectedError,
+            Some(format!("Failed to squash the patches: {:#}", e)),
+        ),
+    }
+}
+
+/// Serves the mock patches until the process is killed.
+#[cfg(feature = "mock-server")]
+fn run_mock_server_command(options: &MockServerOptions, output_format: OutputFormat) -> ExitCode {
+    let urls = MockPatchServer::bind(options).and_then(|server| {
+        let plist_url = server.plist_url()?;
+        let patch_url = server.patch_url()?;
+        Ok((server, plist_url, patch_url))
+    });
+    match urls {
+        Ok((server, plist_url, patch_url)) => {
+            match output_format {
+                OutputFormat::Text => println!(
+                    "Serving {} mock patches, press Ctrl+C to stop\n  plist_url: {}\n  patch_url: {}",
+                    options.patch_count, plist_url, patch_url
+                ),
+                OutputFormat::Json => println!(
+                    "{}",
+                    json!({
+                        "event": "mock_server",
+                        "patch_count": options.patch_count,
+                        "plist_url": plist_url,
+                        "patch_url": patch_url,
+                    })
+                ),
+            }
+            server.serve();
+            print_outcome(output_format, ExitCode::UpToDate, None)
+        }
+        Err(e) => print_outcome(
+            output_format,
+            ExitCode::UnexpectedError,
+            Some(format!("Failed to start the mock patch server: {:#}", e)),
+        ),
+    }
+}
+
+fn run_diff_command(options: &GrfDiffOptions, output_format: OutputFormat) -> ExitCode {
+    match diff_grfs(options) {
+        Ok(diff) => {
+            let patch_path = options.patch_path.display().to_string();
+            match output_format {
+                OutputFormat::Text => println!(
+                    "Built '{}': {} added, {} changed, {} removed file(s)",
+                    patch_path, diff.added_files, diff.changed_files, diff.removed_files
+                ),
+                OutputFormat::Json => println!(
+                    "{}",
+                    json!({
+                        "event": "diffed",
+                        "path": patch_path,
+                        "added_files": diff.added_files,
+                        "changed_files": diff.changed_files,
+                        "removed_files": diff.removed_files,
+                    })
+                ),
+            }
+            print_outcome(output_format, ExitCode::UpToDate, None)
+        }
+        Err(e) => print_outcome(
+            output_format,
+            ExitCode::UnexpectedError,
+            Some(format!("Failed to diff the GRFs: {:#}", e)),
+        ),
+    }
+}
+
+fn run_support_bundle_command(
+    localization: &LocalizationConfiguration,
+    output_format: OutputFormat,
+) -> ExitCode {
+    let translator = Translator::new(localization);
+    match create_support_bundle(None) {
+        Ok(bundle_path) => {
+            let bundle_path = bundle_path.display().to_string();
+            match output_format {
+                OutputFormat::Text => println!(
+                    "{}",
+                    translator.tr_args("status.support_bundle_created", &[("path", &bundle_path)])
+                ),
+                OutputFormat::Json => println!(
+                    "{}",
+                    json!({ "event": "support_bundle_created", "path": bundle_path })
+                ),
+            }
+            print_outcome(output_format, ExitCode::UpToDate, None)
+        }
+        Err(e) => print_outcome(
+            output_format,
+            ExitCode::UnexpectedError,
+            Some(format!("Failed to create support bundle: {:#}", e)),
+        ),
+    }
+}
+
+/// Reports the outcome of a command and returns its exit code.
+fn print_outcome(
+    output_format: OutputFormat,
+    exit_code: ExitCode,
+    error: Option<String>,
+) -> ExitCode {
+    match output_format {
+        OutputFormat::Text => {
+            if let Some(error) = &error {
+                eprintln!("Error: {}", error);
+            }
+        }
+        OutputFormat::Json => println!(
+            "{}",
+            json!({
+                "event": "finished",
+                "success": error.is_none(),
+                "error": error,
+                "exit_code": exit_code as i32,
+            })
+        ),
+    }
+    exit_code
+}
+
+/// Prints statuses as they come and keeps track of the command's outcome.
+struct StatusPrinter {
+    translator: Translator,
+    output_format: OutputFormat,
+    last_line: String,
+    last_file_progress_at: Option<Instant>,
+    error: Option<ErrorReport>,
+    updates_available: Option<usize>,
+    download_size: Option<u64>,
+    patches_applied: bool,
+    patches_downloaded: bool,
+    cancelled: bool,
+}
+
+impl StatusPrinter {
+    fn new(translator: Translator, output_format: OutputFormat) -> Self {
+        Self {
+            translator,
+            output_format,
+            last_line: String::new(),
+            last_file_progress_at: None,
+            error: None,
+            updates_available: None,
+            download_size: None,
+            patches_applied: false,
+            patches_downloaded: false,
+            cancelled: false,
+        }
+    }
+
+    fn print(&mut self, status: &PatchingStatus) {
+        match status {
+            PatchingStatus::Error(report) => self.error = Some(report.clone()),
+            PatchingStatus::UpdatesAvailable(patch_count) => {
+                self.updates_available = Some(*patch_count)
+            }
+            PatchingStatus::DownloadSizeEstimated(total_bytes) => {
+                self.download_size = Some(*total_bytes)
+            }
+            PatchingStatus::InstallationInProgress(_, nb_total) if *nb_total > 0 => {
+                self.patches_applied = true
+            }
+            PatchingStatus::PatchesDownloaded(patch_count) if *patch_count > 0 => {
+                self.patches_downloaded = true
+            }
+            PatchingStatus::Cancelled(_) => self.cancelled = true,
+            _ => {}
+        }
+        let line = match self.output_format {
+            OutputFormat::Text => self.text_line(status),
+            OutputFormat::Json => self.json_event(status).map(|event| event.to_string()),
+        };
+        // Statuses are sent repeatedly during downloads
+        if let Some(line) = line.filter(|line| *line != self.last_line) {
+            println!("{}", line);
+            self.last_line = line;
+        }
+    }
+
+    fn text_line(&self, status: &PatchingStatus) -> Option<String> {
+        let line = match status {
+            PatchingStatus::DownloadInProgress(nb_downloaded, nb_total, _) if *nb_total > 0 => {
+                self.translator.tr_args(
+                    "status.downloading",
+                    &[("downloaded", nb_downloaded), ("total", nb_total)],
+                )
+            }
+            PatchingStatus::InstallationInProgress(nb_installed, nb_total) => {
+                self.translator.tr_args(
+                    "status.installing",
+                    &[("installed", nb_installed), ("total", nb_total)],
+                )
+            }
+            PatchingStatus::PatchesDownloaded(patch_count) => self
+                .translator
+                .tr_args("status.patches_downloaded", &[("count", patch_count)]),
+            PatchingStatus::UpdatesAvailable(patch_count) => match self.download_size {
+                Some(total_bytes) => self.translator.tr_args(
+                    "status.updates_available_with_size",
+                    &[
+                        ("count", patch_count),
+                        ("size", &human_readable_size(total_bytes)),
+                    ],
+                ),
+                None => self
+                    .translator
+                    .tr_args("status.updates_available", &[("count", patch_count)]),
+            },
+            PatchingStatus::ManualPatchApplied(name) => self
+                .translator
+                .tr_args("status.manual_patch_applied", &[("name", name)]),
+            PatchingStatus::GameInstalled(patcher_path) => {
+                let install_directory = patcher_path.parent().unwrap_or(patcher_path);
+                self.translator.tr_args(
+                    "status.game_installed",
+                    &[("path", &install_directory.display())],
+                )
+            }
+            PatchingStatus::BundleExported(bundle_path) => self.translator.tr_args(
+                "status.bundle_exported",
+                &[("path", &bundle_path.display())],
+            ),
+            PatchingStatus::BundleApplied(patch_count) => self
+                .translator
+                .tr_args("status.bundle_applied", &[("count", patch_count)]),
+            PatchingStatus::FilesLocked => self
+                .translator
+                .tr("status.waiting_for_locked_files")
+                .to_string(),
+            PatchingStatus::UpdateRunningElsewhere => self
+                .translator
+                .tr("status.update_running_elsewhere")
+                .to_string(),
+            PatchingStatus::DownloadSummary(progress) => self.translator.tr_args(
+                "status.download_summary",
+                &[
+                    ("size", &human_readable_size(progress.downloaded_bytes)),
+                    ("duration", &human_readable_duration(progress.elapsed_ms)),
+                ],
+            ),
+            PatchingStatus::Cancelled(progress) => self.translator.tr_args(
+                "status.cancelled",
+                &[
+                    ("downloaded", &progress.downloaded_patches),
+                    ("applied", &progress.applied_patches),
+                    ("total", &progress.total_patches),
+                ],
+            ),
+            PatchingStatus::PatchResults(results) => results
+                .iter()
+                .filter_map(|result| match &result.outcome {
+                    PatchOutcome::Failed(error) => Some(self.translator.tr_args(
+                        "status.patch_failed",
+                        &[("file_name", &result.file_name), ("error", error)],
+                    )),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+            PatchingStatus::UnverifiedPatches(file_names) => file_names
+                .iter()
+                .map(|file_name| {
+                    self.translator
+                        .tr_args("status.unverified_patch", &[("file_name", file_name)])
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+            PatchingStatus::ReleaseNotes(notes) => notes
+                .iter()
+                .map(|note| {
+                    let heading = self
+                        .translator
+                        .tr_args("release_notes.patch", &[("file_name", &note.file_name)]);
+                    let body = note.text.as_deref().or(note.url.as_deref()).unwrap_or("");
+                    format!("{}\n{}", heading, body.trim_end())
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+            PatchingStatus::OrphanedFilesFound(files) => {
+                let total_size = files.iter().map(|file| file.size).sum();
+                let summary = self.translator.tr_args(
+                    "orphaned_files.found",
+                    &[
+                        ("count", &files.len()),
+                        ("size", &human_readable_size(total_size)),
+                    ],
+                );
+                files
+                    .iter()
+                    .map(|file| file.relative_path.clone())
+                    .chain(std::iter::once(summary))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            }
+            PatchingStatus::OrphanedFilesDeleted(file_count, freed_bytes) => {
+                self.translator.tr_args(
+                    "status.orphaned_files_deleted",
+                    &[
+                        ("count", file_count),
+                        ("size", &human_readable_size(*freed_bytes)),
+                    ],
+                )
+            }
+            PatchingStatus::GrfRepaired(grf_name, true) => self
+                .translator
+                .tr_args("status.grf_rebuilt", &[("name", grf_name)]),
+            PatchingStatus::GrfRepaired(grf_name, false) => self
+                .translator
+                .tr_args("status.grf_intact", &[("name", grf_name)]),
+            // Errors are reported once the command is done
+            _ => return None,
+        };
+        Some(line)
+    }
+
+    fn json_event(&mut self, status: &PatchingStatus) -> Option<serde_json::Value> {
+        // Per-file progress is sent for every chunk, don't flood the output
+        const FILE_PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+        let event = match status {
+            PatchingStatus::Ready => json!({ "event": "ready" }),
+            PatchingStatus::Error(report) => json!({
+                "event": "error",
+                "message": self.translator.tr(report.summary()),
+                "causes": report.causes(),
+            }),
+            PatchingStatus::DownloadInProgress(nb_downloaded, nb_total, bytes_per_sec) => json!({
+                "event": "download_progress",
+                "downloaded": nb_downloaded,
+                "total": nb_total,
+                "bytes_per_sec": bytes_per_sec,
+            }),
+            PatchingStatus::DownloadSizeEstimated(total_bytes) => json!({
+                "event": "download_size_estimated",
+                "total_bytes": total_bytes,
+            }),
+            PatchingStatus::FileDownloadInProgress {
+                file_name,
+                downloaded_bytes,
+                total_bytes,
+                overall_downloaded_bytes,
+            } => {
+                let now = Instant::now();
+                let finished = downloaded_bytes == total_bytes;
+                let throttled = self
+                    .last_file_progress_at
+                    .is_some_and(|at| now.duration_since(at) < FILE_PROGRESS_INTERVAL);
+                if throttled && !finished {
+                    return None;
+                }
+                self.last_file_progress_at = Some(now);
+                json!({
+                    "event": "file_download_progress",
+                    "file_name": file_name,
+                    "downloaded_bytes": downloaded_bytes,
+                    "total_bytes": total_bytes,
+                    "overall_downloaded_bytes": overall_downloaded_bytes,
+                })
+            }
+            PatchingStatus::InstallationInProgress(nb_installed, nb_total) => json!({
+                "event": "installation_progress",
+                "installed": nb_installed,
+                "total": nb_total,
+            }),
+            PatchingStatus::UpdatesAvailable(patch_count) => json!({
+                "event": "updates_available",
+                "count": patch_count,
+                "total_bytes": self.download_size,
+            }),
+            PatchingStatus::PatchesDownloaded(patch_count) => json!({
+                "event": "patches_downloaded",
+                "count": patch_count,
+            }),
+            PatchingStatus::PatchResults(results) => json!({
+                "event": "patch_results",
+                "patches": results
+                    .iter()
+                    .map(|result| {
+                        let error = match &result.outcome {
+                            PatchOutcome::Failed(error) => Some(error),
+                            _ => None,
+                        };
+                        json!({
+                            "index": result.index,
+                            "name": result.file_name,
+                            "outcome": result.outcome.name(),
+                            "error": error,
+                        })
+                    })
+                    .collect::<Vec<_>>(),
+            }),
+            PatchingStatus::ReleaseNotes(notes) => json!({
+                "event": "release_notes",
+                "patches": notes
+                    .iter()
+                    .map(|note| json!({
+                        "index": note.index,
+                        "name": note.file_name,
+                        "text": note.text,
+                        "url": note.url,
+                    }))
+                    .collect::<Vec<_>>(),
+            }),
+            PatchingStatus::OrphanedFilesFound(files) => json!({
+                "event": "orphaned_files",
+                "files": files
+                    .iter()
+                    .map(|file| json!({
+                        "path": file.relative_path,
+                        "size": file.size,
+                    }))
+                    .collect::<Vec<_>>(),
+            }),
+            PatchingStatus::OrphanedFilesDeleted(file_count, freed_bytes) => json!({
+                "event": "orphaned_files_deleted",
+                "count": file_count,
+                "freed_bytes": freed_bytes,
+            }),
+            PatchingStatus::UnverifiedPatches(file_names) => json!({
+                "event": "unverified_patches",
+                "patches": file_names,
+            }),
+            PatchingStatus::ManualPatchApplied(name) => json!({
+                "event": "manual_patch_applied",
+                "name": name,
+            }),
+            PatchingStatus::GameInstalled(patcher_path) => json!({
+                "event": "game_installed",
+                "patcher_path": patcher_path,
+            }),
+            PatchingStatus::BundleExported(bundle_path) => json!({
+                "event": "bundle_exported",
+                "path": bundle_path,
+            }),
+            PatchingStatus::BundleApplied(patch_count) => json!({
+                "event": "bundle_applied",
+                "count": patch_count,
+            }),
+            PatchingStatus::GrfRepaired(grf_name, rebuilt) => json!({
+                "event": "grf_repaired",
+                "name": grf_name,
+                "rebuilt": rebuilt,
+            }),
+            // Patching is retried on its own
+            PatchingStatus::FilesLocked => json!({ "event": "files_locked" }),
+            PatchingStatus::UpdateRunningElsewhere => {
+                json!({ "event": "update_running_elsewhere" })
+            }
+            PatchingStatus::DownloadSummary(progress) => json!({
+                "event": "download_summary",
+                "downloaded_bytes": progress.downloaded_bytes,
+                "duration_ms": progress.elapsed_ms,
+            }),
+            PatchingStatus::Cancelled(progress) => json!({
+                "event": "cancelled",
+                "downloaded": progress.downloaded_patches,
+                "applied": progress.applied_patches,
+                "total": progress.total_patches,
+            }),
+            // Patches aren't reviewed in headless mode, nor servers probed
+            PatchingStatus::PatchSelectionRequired(_)
+            | PatchingStatus::PatchServersProbed(_)
+            | PatchingStatus::GameClientRunning
+            | PatchingStatus::ElevationRequired => return None,
+        };
+        Some(event)
+    }
+
+    /// Returns the exit code matching the statuses received so far, along
+    /// with an error message in case of failure.
+    fn outcome(self, command: &HeadlessCommand) -> (ExitCode, Option<String>) {
+        if let Some(error) = self.error {
+            let message = self.translator.tr(error.summary()).to_string();
+            return (error.kind().into(), Some(message));
+        }
+        if self.cancelled {
+            return (ExitCode::Cancelled, None);
+        }
+        let exit_code = match command {
+            HeadlessCommand::Update if self.patches_applied => ExitCode::Patched,
+            HeadlessCommand::Update if self.patches_downloaded => ExitCode::PatchesPending,
+            HeadlessCommand::Check => match self.updates_available {
+                Some(0) => ExitCode::UpToDate,
+                Some(_) => ExitCode::PatchesPending,
+                None => {
+                    let message = "Failed to check for updates".to_string();
+                    return (ExitCode::UnexpectedError, Some(message));
+                }
+            },
+            HeadlessCommand::ManualPatch { .. } => ExitCode::Patched,
+            HeadlessCommand::ApplyBundle { .. } if self.patches_applied => ExitCode::Patched,
+            HeadlessCommand::RepairGrf { .. } if self.patches_applied => ExitCode::Patched,
+            _ => ExitCode::UpToDate,
+        };
+        (exit_code, None)
+    }
+}
+
+/// Lets the output reach the console the patcher was started from, since
+/// the executable uses the "windows" subsystem.
+#[cfg(windows)]
+fn attach_parent_console() {
+    use winapi::um::wincon::{AttachConsole, ATTACH_PARENT_PROCESS};
+    unsafe {
+        AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ui::UpdateProgress;
+
+    fn status_printer(output_format: OutputFormat) -> StatusPrinter {
+        StatusPrinter::new(Translator::new(&Default::default()), output_format)
+    }
+
+    #[test]
+    fn test_status_printer_outcome() {
+        let mut printer = status_printer(OutputFormat::Text);
+        printer.print(&PatchingStatus::InstallationInProgress(0, 0));
+        printer.print(&PatchingStatus::Ready);
+        assert_eq!(
+            printer.outcome(&HeadlessCommand::Update),
+            (ExitCode::UpToDate, None)
+        );
+
+        let mut printer = status_printer(OutputFormat::Text);
+        printer.print(&PatchingStatus::InstallationInProgress(2, 2));
+        printer.print(&PatchingStatus::Ready);
+        assert_eq!(
+            printer.outcome(&HeadlessCommand::Update),
+            (ExitCode::Patched, None)
+        );
+
+        let mut printer = status_printer(OutputFormat::Text);
+        printer.print(&PatchingStatus::Error(ErrorReport::new(
+            "Failed to apply patch",
+        )));
+        printer.print(&PatchingStatus::Ready);
+        assert_eq!(
+            printer.outcome(&HeadlessCommand::Update),
+            (
+                ExitCode::UnexpectedError,
+                Some("Failed to apply patch".to_string())
+            )
+        );
+
+        let mut printer = status_printer(OutputFormat::Text);
+        printer.print(&PatchingStatus::InstallationInProgress(1, 2));
+        printer.print(&PatchingStatus::Cancelled(UpdateProgress {
+            downloaded_patches: 2,
+            applied_patches: 1,
+            total_patches: 2,
+            ..Default::default()
+        }));
+        printer.print(&PatchingStatus::Ready);
+        assert_eq!(
+            printer.outcome(&HeadlessCommand::Update),
+            (ExitCode::Cancelled, None)
+        );
+
+        let mut printer = status_printer(OutputFormat::Text);
+        printer.print(&PatchingStatus::UpdatesAvailable(2));
+        assert_eq!(
+            printer.outcome(&HeadlessCommand::Check),
+            (ExitCode::PatchesPending, None)
+        );
+        let printer = status_printer(OutputFormat::Text);
+        assert_eq!(
+            printer.outcome(&HeadlessCommand::Check).0,
+            ExitCode::UnexpectedError
+        );
+    }
+
+    #[test]
+    fn test_pending_patches_size() {
+        let mut printer = status_printer(OutputFormat::Text);
+        let updates_available = PatchingStatus::UpdatesAvailable(2);
+        assert_eq!(
+            printer.text_line(&updates_available).unwrap(),
+            "2 update(s) available"
+        );
+        printer.print(&PatchingStatus::DownloadSizeEstimated(1_500_000));
+        assert_eq!(
+            printer.text_line(&updates_available).unwrap(),
+            "2 update(s) available (1.5 MB)"
+        );
+        assert_eq!(
+            printer.json_event(&updates_available),
+            Some(json!({ "event": "updates_available", "count": 2, "total_bytes": 1_500_000 }))
+        );
+    }
+
+    #[test]
+    fn test_json_events() {
+        let mut printer = status_printer(OutputFormat::Json);
+        assert_eq!(
+            printer.json_event(&PatchingStatus::InstallationInProgress(1, 3)),
+            Some(json!({ "event": "installation_progress", "installed": 1, "total": 3 }))
+        );
+        let file_progress = |downloaded_bytes| PatchingStatus::FileDownloadInProgress {
+            file_name: "1.thor".to_string(),
+            downloaded_bytes,
+            total_bytes: 100,
+            overall_downloaded_bytes: downloaded_bytes,
+        };
+        assert!(printer.json_event(&file_progress(10)).is_some());
+        assert!(printer.json_event(&file_progress(20)).is_none());
+        // The end of a download is never dropped
+        assert!(printer.json_event(&file_progress(100)).is_some());
+    }
+}
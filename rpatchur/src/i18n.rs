@@ -0,0 +1,326 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use crate::patcher::LocalizationConfiguration;
+
+const DEFAULT_LANGUAGE: &str = "en";
+
+/// Built-in (English) strings, used for keys that aren't translated.
+const DEFAULT_STRINGS: &[(&str, &str)] = &[
+    ("status.ready", "Ready"),
+    ("status.error", "Error"),
+    ("status.downloading", "Downloading: {downloaded}/{total}"),
+    ("status.download_speed", "{speed} MB/s"),
+    ("status.file_download", "{file_name}: {downloaded} of {total}"),
+    ("status.file_download_unknown_size", "{file_name}: {downloaded}"),
+    ("status.overall_downloaded", "{downloaded} downloaded"),
+    ("status.size_progress", "{downloaded} of {total}"),
+    ("status.eta_seconds", "about {count} seconds remaining"),
+    ("status.eta_minutes", "about {count} minutes remaining"),
+    ("status.eta_hours", "about {count} hours remaining"),
+    ("status.installing", "Installing: {installed}/{total}"),
+    ("status.manual_patch_applied", "Patch applied: {name}"),
+    ("status.game_installed", "Game installed in {path}"),
+    ("status.bundle_exported", "Bundle exported to {path}"),
+    ("status.bundle_applied", "{count} patch(es) applied from the bundle"),
+    ("status.grf_intact", "{name} is intact, nothing had to be repaired"),
+    ("status.grf_rebuilt", "{name} has been rebuilt"),
+    ("status.support_bundle_created", "Support bundle created: {path}"),
+    ("status.updates_available", "{count} update(s) available"),
+    ("status.updates_available_with_size", "{count} update(s) available ({size})"),
+    ("status.drop_patch_files", "Drop THOR or ZIP files here to apply them"),
+    ("status.waiting_for_selection", "Waiting for patch selection"),
+    ("status.waiting_for_update", "The game has to be updated first"),
+    ("status.login_required", "You have to log in first"),
+    ("status.waiting_for_game_exit", "Waiting for the game to be closed"),
+    ("status.waiting_for_locked_files", "Waiting for the game's files to be released"),
+    ("status.cancelled", "Update canceled: {downloaded}/{total} patch(es) downloaded, {applied} applied"),
+    ("status.download_summary", "Downloaded {size} in {duration}"),
+    ("status.update_running_elsewhere", "Another instance of the patcher is already updating the game, its progress is shown here"),
+    ("status.patch_failed", "Failed to download '{file_name}': {error}"),
+    ("status.unverified_patch", "Warning: '{file_name}' has no integrity data and couldn't be verified"),
+    ("status.unverified_patches", "{count} patch(es) couldn't be verified, since they have no integrity data"),
+    ("status.patches_downloaded", "{count} patch(es) downloaded, they will be applied on the next update"),
+    ("notification.updates_available", "New patches are available"),
+    ("notification.patching_finished", "Patching finished"),
+    ("notification.patching_finished_body", "The game is up to date, you can now play"),
+    ("notification.patching_failed", "Patching failed"),
+    ("notification.manual_patch_applied", "Manual patch applied"),
+    ("notification.game_client_running", "The game has to be closed"),
+    ("game_client_running.title", "The game is running"),
+    ("game_client_running.message", "Patching the game while it's running would corrupt its files. Close it to continue."),
+    ("files_locked.title", "The game's files are in use"),
+    ("files_locked.message", "The game's files can't be patched while another program uses them. Close the game and press Retry."),
+    ("cancel_confirmation.title", "Cancel the update?"),
+    ("cancel_confirmation.message", "A patch is being applied. The update will stop once it has been applied completely, the remaining patches will be applied by the next update."),
+    ("cancel_confirmation.force_message", "Force canceling interrupts the patch being applied, which leaves it partially applied until the next update applies it again."),
+    ("patcher_outdated.title", "The patcher is outdated"),
+    ("patcher_outdated.message", "This version of the patcher can't apply the latest patches. Download the latest patcher to update the game."),
+    ("agreement.title", "Terms of Service"),
+    ("agreement.message", "You have to accept the terms of service to update and play the game."),
+    ("agreement.read_full_terms", "Read the full terms"),
+    ("installer.select_directory", "Select the directory to install the game in"),
+    ("bundle.select_file", "Select the offline bundle to apply"),
+    ("bundle.file_type", "Offline bundles"),
+    ("manual_patch.select_files", "Select patches to apply"),
+    ("manual_patch.file_type", "Patch files (*.thor, *.zip)"),
+    ("maintenance.title", "The game server is in maintenance"),
+    ("maintenance.message", "Please come back later."),
+    ("error.channel_disconnected", "Channel disconnected"),
+    ("error.invalid_patch_file", "'{file_name}' is not a THOR or ZIP patch"),
+    ("error.details", "Details"),
+    ("button.copy_error", "Copy Error"),
+    ("button.open_log_file", "Open Log File"),
+    ("button.open_game_folder", "Open Game Folder"),
+    ("button.create_support_bundle", "Create Support Bundle"),
+    ("button.start_update", "Start Update"),
+    ("button.cancel_update", "Cancel Update"),
+    ("button.force_cancel_update", "Force Cancel"),
+    ("button.stop_following", "Stop Following"),
+    ("button.keep_updating", "Keep Updating"),
+    ("button.retry_failed", "Retry Failed"),
+    ("button.reset_cache", "Reset Cache"),
+    ("button.manual_patch", "Manual Patch"),
+    ("button.install", "Install the Game"),
+    ("button.apply_bundle", "Apply Bundle"),
+    ("button.play", "Play"),
+    ("button.setup", "Setup"),
+    ("button.save", "Save"),
+    ("button.cancel", "Cancel"),
+    ("button.accept", "Accept"),
+    ("button.decline", "Decline"),
+    ("button.close", "Close"),
+    ("button.minimize", "Minimize"),
+    ("button.close_game_and_continue", "Close Game and Continue"),
+    ("button.retry", "Retry"),
+    ("button.log_in", "Log In"),
+    ("button.log_out", "Log Out"),
+    ("account.username", "Username"),
+    ("account.password", "Password"),
+    ("account.token", "Session token"),
+    ("account.use_token", "Use a session token"),
+    ("account.remember_password", "Remember my password"),
+    ("account.logging_in", "Logging in..."),
+    ("account.logged_in", "Logged in as {username}"),
+    ("account.logged_in_with_token", "Logged in with a session token"),
+    ("settings.title", "Settings"),
+    ("settings.in_place", "Patch GRFs in place"),
+    ("settings.check_integrity", "Check the integrity of downloaded patches"),
+    ("settings.auto_start", "Start updating when the patcher opens"),
+    ("settings.bandwidth_limit", "Bandwidth limit (0 for unlimited)"),
+    ("settings.preview_patches", "Review pending patches before downloading them"),
+    ("settings.background_mode", "Update in the background, with a low priority"),
+    ("settings.channel", "Update channel"),
+    ("settings.language", "Language"),
+    ("settings.language_hint", "auto"),
+    ("button.download_and_apply", "Download and Apply"),
+    ("button.download_only", "Download Only"),
+    ("patch_selection.title", "Pending Patches"),
+    ("patch_selection.index", "Index"),
+    ("patch_selection.name", "Name"),
+    ("patch_selection.size", "Size"),
+    ("patch_selection.summary", "{selected} of {total} patch(es) selected"),
+    ("patch_selection.skip_warning", "Skipped patches won't be offered again once a more recent patch is applied"),
+    ("patch_results.summary", "{failed} of {total} patch(es) failed to download"),
+    ("patch_results.outcome", "Result"),
+    ("patch_results.applied", "Applied"),
+    ("patch_results.pending", "Waiting for failed patches"),
+    ("patch_results.failed", "Failed"),
+    ("release_notes.title", "What changed"),
+    ("release_notes.patch", "What changed in {file_name}:"),
+    ("release_notes.read_online", "Read the release notes online"),
+    ("button.export_json", "Export to JSON"),
+    ("button.export_csv", "Export to CSV"),
+    ("tab.patcher", "Patcher"),
+    ("tab.history", "History"),
+    ("history.empty", "No patch has been applied yet"),
+    ("history.date", "Date"),
+    ("history.duration", "Duration"),
+    ("history.result", "Result"),
+    ("history.success", "Success"),
+    ("history.failure", "Failure"),
+    ("history.export_title", "Export patch history"),
+    ("history.exported", "Exported to '{path}'"),
+    ("history.patches", "Applied patches"),
+    ("history.download_sessions", "Downloads"),
+    ("history.downloaded", "Downloaded"),
+    ("history.patch_count", "Patches"),
+    ("tab.storage", "Storage"),
+    ("storage.category", "Files"),
+    ("storage.size", "Size"),
+    ("storage.files", "Count"),
+    ("storage.downloads", "Downloaded patches"),
+    ("storage.backups", "Rollback patches"),
+    ("storage.logs", "Old logs and crash reports"),
+    ("storage.cleared", "{size} freed"),
+    ("button.clear", "Clear"),
+    ("button.find_orphaned_files", "Find Leftover Files"),
+    ("orphaned_files.title", "Leftover Files"),
+    ("orphaned_files.none", "No leftover files were found."),
+    ("orphaned_files.found", "{count} leftover file(s) found ({size})"),
+    ("orphaned_files.summary", "{selected} of {total} files selected ({size})"),
+    ("button.delete_selected", "Delete Selected"),
+    ("status.orphaned_files_deleted", "{count} leftover file(s) deleted, {size} freed"),
+    ("tab.grf_browser", "GRF Browser"),
+    ("grf_browser.grf", "GRF:"),
+    ("grf_browser.file_count", "{count} file(s)"),
+    ("grf_browser.search_hint", "Search"),
+    ("grf_browser.compressed", "{size} compressed"),
+    ("grf_browser.encrypted", "Encrypted"),
+    ("grf_browser.extract_title", "Extract file"),
+    ("grf_browser.extracted", "Extracted to '{path}'"),
+    ("grf_browser.repair_hint", "Checks the files of the GRF, and rebuilds it from the game's archives and patches if some of them are corrupt"),
+    ("button.open", "Open"),
+    ("button.extract_selected", "Extract Selected"),
+    ("tab.patch_servers", "Servers"),
+    ("patch_servers.name", "Server"),
+    ("patch_servers.status", "Status"),
+    ("patch_servers.latency", "Latency"),
+    ("patch_servers.reachable", "Reachable"),
+    ("patch_servers.unreachable", "Unreachable"),
+    ("patch_servers.unknown", "Not checked"),
+    ("patch_servers.use", "Use this server"),
+    ("patch_servers.automatic", "The first available server is used"),
+    ("button.probe_servers", "Check Again"),
+    ("button.repair", "Repair"),
+    ("tray.show", "Show"),
+    ("tray.check_for_updates", "Check for Updates"),
+    ("tray.quit", "Quit"),
+    ("crash.title", "The patcher crashed"),
+    ("crash.message", "A crash report has been written to {path}, please attach it to your support request. Open it now?"),
+];
+
+/// Key/value string bundle for the language selected at startup.
+///
+/// Translations are loaded from `<translations_directory>/<language>.yml`
+/// files, the generic language (e.g. `pt.yml`) being loaded before the
+/// regional one (e.g. `pt-BR.yml`). Missing keys fall back on the built-in
+/// English strings.
+pub struct Translator {
+    language: String,
+    strings: HashMap<String, String>,
+}
+
+impl Translator {
+    pub fn new(config: &LocalizationConfiguration) -> Self {
+        let language = config
+            .language
+            .clone()
+            .or_else(sys_locale::get_locale)
+            .map(|tag| normalize_language_tag(&tag))
+            .unwrap_or_else(|| DEFAULT_LANGUAGE.to_string());
+        let mut translator = Self::with_defaults(language);
+        if let Some(translations_directory) = &config.translations_directory {
+            translator.load_translations(Path::new(translations_directory));
+        }
+        log::info!("Using language '{}'", translator.language);
+        translator
+    }
+
+    fn with_defaults(language: String) -> Self {
+        Self {
+            language,
+            strings: DEFAULT_STRINGS
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        }
+    }
+
+    /// Overlays the translation files found in `directory` for the current
+    /// language.
+    fn load_translations(&mut self, directory: &Path) {
+        let mut candidates = vec![];
+        if let Some((primary, _)) = self.language.split_once('-') {
+            candidates.push(primary.to_string());
+        }
+        candidates.push(self.language.clone());
+        for candidate in candidates {
+            let file_path = directory.join(candidate).with_extension("yml");
+            if !file_path.exists() {
+                continue;
+            }
+            match parse_translation_file(&file_path) {
+                Ok(strings) => self.strings.extend(strings),
+                Err(e) => log::warn!("{:#}", e),
+            }
+        }
+    }
+
+    /// Returns the translation associated with `key`.
+    ///
+    /// Unknown keys are returned as is, which lets operators use either
+    /// translation keys or plain text in their configuration.
+    pub fn tr<'a>(&'a self, key: &'a str) -> &'a str {
+        self.strings.get(key).map(String::as_str).unwrap_or(key)
+    }
+
+    /// Same as `tr`, but also replaces `{name}` placeholders with the given
+    /// arguments.
+    pub fn tr_args(&self, key: &str, args: &[(&str, &dyn Display)]) -> String {
+        args.iter()
+            .fold(self.tr(key).to_string(), |acc, (name, value)| {
+                acc.replace(&format!("{{{}}}", name), &value.to_string())
+            })
+    }
+}
+
+fn parse_translation_file(file_path: &Path) -> Result<HashMap<String, String>> {
+    let file = File::open(file_path)?;
+    serde_yaml::from_reader(BufReader::new(file))
+        .with_context(|| format!("Invalid translation file '{}'", file_path.to_string_lossy()))
+}
+
+/// Turns OS-style locales (e.g. "pt_BR.UTF-8") into language tags ("pt-BR").
+fn normalize_language_tag(tag: &str) -> String {
+    let tag = tag.split('.').next().unwrap_or(tag);
+    tag.replace('_', "-")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_normalize_language_tag() {
+        assert_eq!(normalize_language_tag("pt_BR.UTF-8"), "pt-BR");
+        assert_eq!(normalize_language_tag("en-US"), "en-US");
+        assert_eq!(normalize_language_tag("fr"), "fr");
+    }
+
+    #[test]
+    fn test_translations_fallback() {
+        let temp_dir = tempdir().unwrap();
+        fs::write(
+            temp_dir.path().join("pt.yml"),
+            "button.play: Jogar\nbutton.setup: Configurar\n",
+        )
+        .unwrap();
+        fs::write(temp_dir.path().join("pt-BR.yml"), "button.setup: Opções\n").unwrap();
+        let config = LocalizationConfiguration {
+            language: Some("pt_BR".to_string()),
+            translations_directory: Some(temp_dir.path().to_string_lossy().to_string()),
+        };
+        let translator = Translator::new(&config);
+        assert_eq!(translator.language, "pt-BR");
+        assert_eq!(translator.tr("button.play"), "Jogar");
+        assert_eq!(translator.tr("button.setup"), "Opções");
+        // Built-in fallback and unknown keys
+        assert_eq!(translator.tr("button.reset_cache"), "Reset Cache");
+        assert_eq!(translator.tr("Register"), "Register");
+    }
+
+    #[test]
+    fn test_tr_args() {
+        let translator = Translator::with_defaults(DEFAULT_LANGUAGE.to_string());
+        let text = translator.tr_args("status.downloading", &[("downloaded", &3), ("total", &10)]);
+        assert_eq!(text, "Downloading: 3/10");
+    }
+}
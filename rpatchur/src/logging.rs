@@ -0,0 +1,546 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Instant;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use log::{Level, LevelFilter, Log};
+use serde_json::json;
+use tracing::field::{Field, Visit};
+use tracing::span::{self, Attributes, Id};
+use tracing::subscriber::Interest;
+use tracing::Event;
+
+use crate::patcher::{get_log_file_path, rotated_file_path};
+
+/// Size above which the log file is rotated, in bytes
+const MAX_LOG_FILE_SIZE: u64 = 5 * 1000 * 1000;
+/// Number of rotated log files that are kept around
+const MAX_ROTATED_LOG_FILES: usize = 3;
+
+/// Whether the log file is written as newline-delimited JSON
+static JSON_LOG_FILE: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    /// Spans entered on the current thread, the innermost one last
+    static ENTERED_SPANS: RefCell<Vec<u64>> = const { RefCell::new(vec![]) };
+}
+
+type Fields = Vec<(&'static str, serde_json::Value)>;
+
+/// Span in which an entry has been logged.
+#[derive(Clone)]
+struct SpanContext {
+    name: &'static str,
+    fields: Fields,
+}
+
+/// Message logged through either `log` or `tracing`.
+struct Entry<'a> {
+    level: Level,
+    target: &'a str,
+    spans: Vec<SpanContext>, // Outermost span first
+    message: String,
+    fields: Fields,
+}
+
+impl Entry<'_> {
+    fn to_text(&self, timestamp: &DateTime<Local>) -> String {
+        let mut line = format!(
+            "{} {:<5} [{}] ",
+            timestamp.format("%Y-%m-%d %H:%M:%S%.3f"),
+            self.level,
+            self.target
+        );
+        for (i, span) in self.spans.iter().enumerate() {
+            if i > 0 {
+                line.push(':');
+            }
+            line.push_str(span.name);
+            if !span.fields.is_empty() {
+                let fields: Vec<String> = span.fields.iter().map(format_field).collect();
+                line.push_str(&format!("{{{}}}", fields.join(" ")));
+            }
+        }
+        if !self.spans.is_empty() {
+            line.push_str(": ");
+        }
+        line.push_str(&self.message);
+        for field in &self.fields {
+            line.push(' ');
+            line.push_str(&format_field(field));
+        }
+        line
+    }
+
+    fn to_json(&self, timestamp: &DateTime<Local>) -> String {
+        let to_object = |fields: &Fields| -> serde_json::Map<String, serde_json::Value> {
+            fields
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.clone()))
+                .collect()
+        };
+        let spans: Vec<serde_json::Value> = self
+            .spans
+            .iter()
+            .map(|span| {
+                let mut object = to_object(&span.fields);
+                object.insert("name".to_string(), json!(span.name));
+                serde_json::Value::Object(object)
+            })
+            .collect();
+        json!({
+            "timestamp": timestamp.to_rfc3339(),
+            "level": self.level.as_str(),
+            "target": self.target,
+            "spans": spans,
+            "message": self.message,
+            "fields": to_object(&self.fields),
+        })
+        .to_string()
+    }
+}
+
+fn format_field((name, value): &(&'static str, serde_json::Value)) -> String {
+    match value {
+        serde_json::Value::String(value) => format!("{}={}", name, value),
+        value => format!("{}={}", name, value),
+    }
+}
+
+/// Destination of the log entries, shared by the `log` and `tracing`
+/// front-ends: stderr, and the log file associated with the current
+/// 'instance' of the patcher.
+struct Sink {
+    log_file: Option<Mutex<RotatingFile>>,
+}
+
+impl Sink {
+    fn write(&self, entry: &Entry) {
+        let timestamp = Local::now();
+        let text_line = entry.to_text(&timestamp);
+        eprintln!("{}", text_line);
+        if let Some(log_file) = &self.log_file {
+            let line = if JSON_LOG_FILE.load(Ordering::Relaxed) {
+                entry.to_json(&timestamp)
+            } else {
+                text_line
+            };
+            // There's nowhere left to report this kind of errors
+            let _ = lock(log_file).write_line(&line);
+        }
+    }
+}
+
+fn is_enabled(target: &str, level: Level) -> bool {
+    // Dependencies are too verbose to be of any help
+    target.starts_with(crate::PKG_NAME) && level <= log::max_level()
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Front-end for the `log` macros.
+struct Logger {
+    sink: Arc<Sink>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        is_enabled(metadata.target(), metadata.level())
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        self.sink.write(&Entry {
+            level: record.level(),
+            target: record.target(),
+            spans: vec![],
+            message: record.args().to_string(),
+            fields: vec![],
+        });
+    }
+
+    fn flush(&self) {}
+}
+
+struct SpanData {
+    context: SpanContext,
+    level: Level,
+    target: &'static str,
+    parent: Option<u64>,
+    started_at: Instant,
+    ref_count: usize,
+}
+
+/// Front-end for the `tracing` macros, which logs the events along with the
+/// spans they occurred in, as well as the duration of each span once it's
+/// closed.
+struct TracingSubscriber {
+    sink: Arc<Sink>,
+    next_span_id: AtomicU64,
+    spans: Mutex<HashMap<u64, SpanData>>,
+}
+
+impl TracingSubscriber {
+    fn new(sink: Arc<Sink>) -> Self {
+        Self {
+            sink,
+            // Span IDs can't be 0
+            next_span_id: AtomicU64::new(1),
+            spans: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn current_span() -> Option<u64> {
+        ENTERED_SPANS.with(|spans| spans.borrow().last().copied())
+    }
+
+    /// Returns the span `span_id` along with its ancestors, outermost first.
+    fn span_contexts(&self, span_id: Option<u64>) -> Vec<SpanContext> {
+        let spans = lock(&self.spans);
+        let mut contexts = vec![];
+        let mut span_id = span_id;
+        while let Some(span) = span_id.and_then(|span_id| spans.get(&span_id)) {
+            contexts.push(span.context.clone());
+            span_id = span.parent;
+        }
+        contexts.reverse();
+        contexts
+    }
+}
+
+impl tracing::Subscriber for TracingSubscriber {
+    fn register_callsite(&self, _: &'static tracing::Metadata<'static>) -> Interest {
+        // The log level can change at runtime
+        Interest::sometimes()
+    }
+
+    fn enabled(&self, metadata: &tracing::Metadata) -> bool {
+        is_enabled(metadata.target(), to_log_level(metadata.level()))
+    }
+
+    fn new_span(&self, attributes: &Attributes) -> Id {
+        let mut fields = vec![];
+        attributes.record(&mut FieldVisitor(&mut fields));
+        let parent = if let Some(parent) = attributes.parent() {
+            Some(parent.into_u64())
+        } else if attributes.is_contextual() {
+            Self::current_span()
+        } else {
+            None
+        };
+        let metadata = attributes.metadata();
+        let span_id = self.next_span_id.fetch_add(1, Ordering::Relaxed);
+        lock(&self.spans).insert(
+            span_id,
+            SpanData {
+                context: SpanContext {
+                    name: metadata.name(),
+                    fields,
+                },
+                level: to_log_level(metadata.level()),
+                target: metadata.target(),
+                parent,
+                started_at: Instant::now(),
+                ref_count: 1,
+            },
+        );
+        Id::from_u64(span_id)
+    }
+
+    fn record(&self, span: &Id, values: &span::Record) {
+        if let Some(span) = lock(&self.spans).get_mut(&span.into_u64()) {
+            let mut fields = vec![];
+            values.record(&mut FieldVisitor(&mut fields));
+            // Recorded values replace the previous ones
+            for (name, value) in fields {
+                span.context
+                    .fields
+                    .retain(|(field_name, _)| *field_name != name);
+                span.context.fields.push((name, value));
+            }
+        }
+    }
+
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+    fn event(&self, event: &Event) {
+        let mut fields = vec![];
+        event.record(&mut FieldVisitor(&mut fields));
+        let message = match fields.iter().position(|(name, _)| *name == "message") {
+            Some(i) => match fields.remove(i).1 {
+                serde_json::Value::String(message) => message,
+                message => message.to_string(),
+            },
+            None => String::new(),
+        };
+        let parent = if let Some(parent) = event.parent() {
+            Some(parent.into_u64())
+        } else if event.is_contextual() {
+            Self::current_span()
+        } else {
+            None
+        };
+        let metadata = event.metadata();
+        self.sink.write(&Entry {
+            level: to_log_level(metadata.level()),
+            target: metadata.target(),
+            spans: self.span_contexts(parent),
+            message,
+            fields,
+        });
+    }
+
+    fn enter(&self, span: &Id) {
+        ENTERED_SPANS.with(|spans| spans.borrow_mut().push(span.into_u64()));
+    }
+
+    fn exit(&self, span: &Id) {
+        let span_id = span.into_u64();
+        ENTERED_SPANS.with(|spans| {
+            let mut spans = spans.borrow_mut();
+            if let Some(i) = spans.iter().rposition(|id| *id == span_id) {
+                spans.remove(i);
+            }
+        });
+    }
+
+    fn clone_span(&self, span: &Id) -> Id {
+        if let Some(span) = lock(&self.spans).get_mut(&span.into_u64()) {
+            span.ref_count += 1;
+        }
+        span.clone()
+    }
+
+    fn try_close(&self, span: Id) -> bool {
+        let span_id = span.into_u64();
+        let span = {
+            let mut spans = lock(&self.spans);
+            match spans.get_mut(&span_id) {
+                Some(span) if span.ref_count > 1 => {
+                    span.ref_count -= 1;
+                    return false;
+                }
+                Some(_) => spans.remove(&span_id),
+                None => return false,
+            }
+        };
+        if let Some(span) = span {
+            let mut spans = self.span_contexts(span.parent);
+            spans.push(span.context);
+            self.sink.write(&Entry {
+                level: span.level,
+                target: span.target,
+                spans,
+                message: "close".to_string(),
+                fields: vec![(
+                    "elapsed_ms",
+                    json!(span.started_at.elapsed().as_millis() as u64),
+                )],
+            });
+        }
+        true
+    }
+}
+
+struct FieldVisitor<'a>(&'a mut Fields);
+
+impl Visit for FieldVisitor<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0.push((field.name(), json!(format!("{:?}", value))));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.push((field.name(), json!(value)));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.push((field.name(), json!(value)));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.push((field.name(), json!(value)));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.push((field.name(), json!(value)));
+    }
+}
+
+fn to_log_level(level: &tracing::Level) -> Level {
+    match *level {
+        tracing::Level::ERROR => Level::Error,
+        tracing::Level::WARN => Level::Warn,
+        tracing::Level::INFO => Level::Info,
+        tracing::Level::DEBUG => Level::Debug,
+        tracing::Level::TRACE => Level::Trace,
+    }
+}
+
+/// Installs the global logger and `tracing` subscriber, filtering out
+/// messages less severe than `level`.
+pub fn init_logger(level: LevelFilter) -> Result<()> {
+    let log_file = get_log_file_path().and_then(|log_file_path| {
+        RotatingFile::open(&log_file_path, MAX_LOG_FILE_SIZE)
+            .with_context(|| format!("Failed to open '{}'", log_file_path.display()))
+    });
+    let (log_file, log_file_error) = match log_file {
+        Ok(log_file) => (Some(Mutex::new(log_file)), None),
+        Err(e) => (None, Some(e)),
+    };
+    let sink = Arc::new(Sink { log_file });
+    log::set_boxed_logger(Box::new(Logger { sink: sink.clone() }))
+        .with_context(|| "Failed to initalize the logger")?;
+    log::set_max_level(level);
+    tracing::subscriber::set_global_default(TracingSubscriber::new(sink))
+        .with_context(|| "Failed to initalize the tracing subscriber")?;
+    if let Some(e) = log_file_error {
+        log::warn!("Logging to stderr only: {:#}", e);
+    }
+    Ok(())
+}
+
+/// Writes the log file as newline-delimited JSON (one object per entry), for
+/// operators who collect the patcher's telemetry.
+pub fn set_json_log_file(enabled: bool) {
+    JSON_LOG_FILE.store(enabled, Ordering::Relaxed);
+}
+
+/// Log file that's rotated once it grows larger than `max_size`.
+///
+/// Rotated files are suffixed with a number, the oldest one having the
+/// largest number.
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+}
+
+impl RotatingFile {
+    fn open(path: impl AsRef<Path>, max_size: u64) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path,
+            file,
+            size,
+            max_size,
+        })
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let line_size = line.len() as u64 + 1;
+        if self.size > 0 && self.size + line_size > self.max_size {
+            self.rotate()?;
+        }
+        writeln!(self.file, "{}", line)?;
+        self.size += line_size;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        for i in (1..MAX_ROTATED_LOG_FILES).rev() {
+            let older_file_path = rotated_file_path(&self.path, i);
+            if older_file_path.exists() {
+                fs::rename(&older_file_path, rotated_file_path(&self.path, i + 1))?;
+            }
+        }
+        fs::rename(&self.path, rotated_file_path(&self.path, 1))?;
+        *self = Self::open(&self.path, self.max_size)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotating_file() {
+        let log_directory = tempfile::tempdir().unwrap();
+        let log_file_path = log_directory.path().join("rpatchur.log");
+        let mut log_file = RotatingFile::open(&log_file_path, 20).unwrap();
+        for i in 0..10 {
+            log_file.write_line(&format!("message {}", i)).unwrap();
+        }
+        // 2 lines fit in each file, the oldest ones are removed
+        let read = |index| match index {
+            0 => fs::read_to_string(&log_file_path).unwrap(),
+            i => fs::read_to_string(rotated_file_path(&log_file_path, i)).unwrap(),
+        };
+        assert_eq!(read(0), "message 8\nmessage 9\n");
+        assert_eq!(read(1), "message 6\nmessage 7\n");
+        assert_eq!(read(3), "message 2\nmessage 3\n");
+        assert!(!rotated_file_path(&log_file_path, 4).exists());
+    }
+
+    #[test]
+    fn test_tracing_subscriber() {
+        log::set_max_level(LevelFilter::Info);
+        let log_directory = tempfile::tempdir().unwrap();
+        let log_file_path = log_directory.path().join("rpatchur.log");
+        let sink = Sink {
+            log_file: Some(Mutex::new(
+                RotatingFile::open(&log_file_path, MAX_LOG_FILE_SIZE).unwrap(),
+            )),
+        };
+        let subscriber = TracingSubscriber::new(Arc::new(sink));
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("update");
+            let _guard = span.enter();
+            let span = tracing::info_span!("download", patch = "1.thor");
+            let _guard = span.enter();
+            tracing::info!(bytes = 42_u64, "Downloaded");
+            tracing::debug!("Filtered out");
+        });
+
+        let log = fs::read_to_string(&log_file_path).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].ends_with(
+            "INFO  [rpatchur::logging::tests] update:download{patch=1.thor}: Downloaded bytes=42"
+        ));
+        assert!(lines[1].contains("update:download{patch=1.thor}: close elapsed_ms="));
+        assert!(lines[2].contains("update: close elapsed_ms="));
+    }
+
+    #[test]
+    fn test_entry_to_json() {
+        let entry = Entry {
+            level: Level::Info,
+            target: "rpatchur::patcher",
+            spans: vec![SpanContext {
+                name: "download",
+                fields: vec![("patch", json!("1.thor"))],
+            }],
+            message: "close".to_string(),
+            fields: vec![("elapsed_ms", json!(12))],
+        };
+        let mut object: serde_json::Value =
+            serde_json::from_str(&entry.to_json(&Local::now())).unwrap();
+        assert!(object["timestamp"].is_string());
+        object.as_object_mut().unwrap().remove("timestamp");
+        assert_eq!(
+            object,
+            json!({
+                "level": "INFO",
+                "target": "rpatchur::patcher",
+                "spans": [{ "name": "download", "patch": "1.thor" }],
+                "message": "close",
+                "fields": { "elapsed_ms": 12 },
+            })
+        );
+    }
+}
@@ -1,23 +1,36 @@
 #![windows_subsystem = "windows"]
 
-mod patcher;
-mod process;
+mod control;
+mod crash;
+mod demo;
+mod headless;
+mod i18n;
+mod logging;
 mod ui;
 
 use log::LevelFilter;
 use std::env;
 use std::path::PathBuf;
 use std::sync::mpsc;
+use std::time::Duration;
 
 use anyhow::{anyhow, Context, Result};
-use simple_logger::SimpleLogger;
+use rpatchur_core::{patcher, process};
 use structopt::StructOpt;
-use tinyfiledialogs as tfd;
 
+use control::{bind_control_api, serve_control_api};
+use crash::{enable_crash_dialog, install_crash_handler};
+use demo::demo_thread_routine;
+use headless::{run_headless, run_standalone_command, ExitCode, HeadlessCommand, OutputFormat};
+use logging::{init_logger, set_json_log_file};
 use patcher::{
-    patcher_thread_routine, retrieve_patcher_configuration, PatcherCommand, PatcherConfiguration,
+    claim_instance, forward_to_running_instance, get_app_bundle_directory, init_data_directory,
+    is_agreement_pending, patcher_thread_routine, retrieve_patcher_configuration,
+    retrieve_user_settings, serve_peer_cache, set_profile, simulate_network_conditions,
+    InstanceClaim, InstanceMessage, NetworkSimulation, PatcherCommand, PatcherConfiguration,
+    UserSettings,
 };
-use ui::native::{NativeUi, PatchingStatus};
+use ui::NativeUi;
 
 const PKG_NAME: &str = env!("CARGO_PKG_NAME");
 const PKG_VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -30,55 +43,278 @@ struct Opt {
     /// Sets a custom working directory
     #[structopt(short, long, parse(from_os_str))]
     working_directory: Option<PathBuf>,
+    /// Uses the configuration and files of another profile (e.g. to patch
+    /// the game for a test server)
+    #[structopt(long)]
+    profile: Option<String>,
+    /// Keeps the state, logs and downloads in the working directory instead
+    /// of the user's data directory
+    #[structopt(long)]
+    portable: bool,
+    /// Sets the verbosity of the logs (e.g. "debug"), overriding the configuration
+    #[structopt(long)]
+    log_level: Option<LevelFilter>,
+    /// Prints newline-delimited JSON events instead of text (headless commands only)
+    #[structopt(long)]
+    json: bool,
+    /// Reports the pending patches and their total size, then exits (same as `check`)
+    #[structopt(long)]
+    check_only: bool,
+    /// Starts updating the game as soon as the patcher opens
+    #[structopt(long)]
+    start_update: bool,
+    /// Plays scripted updates instead of patching the game, to preview
+    /// skins and translations without patch servers
+    #[structopt(long)]
+    demo: bool,
+    /// Holds the game at this patch index, rolling back more recent patches
+    /// if possible
+    #[structopt(long)]
+    pin: Option<usize>,
+    /// Delays the start of every download, in milliseconds (for testing)
+    #[structopt(long)]
+    simulate_latency: Option<u64>,
+    /// Interrupts downloads at random, losing this percentage of the
+    /// downloaded chunks (for testing)
+    #[structopt(long)]
+    simulate_packet_loss: Option<f64>,
+    /// Limits the speed of every download, in KB/s (for testing)
+    #[structopt(long)]
+    simulate_throttle: Option<u64>,
+    /// THOR or ZIP patch to apply once the patcher has opened (e.g. when
+    /// opening a '.thor' file with the patcher)
+    #[structopt(parse(from_os_str))]
+    patch_file: Option<PathBuf>,
+    /// Runs a command without showing the patcher's window
+    #[structopt(subcommand)]
+    command: Option<HeadlessCommand>,
 }
 
 fn main() -> Result<()> {
-    SimpleLogger::new()
-        .with_level(LevelFilter::Off)
-        .with_module_level(PKG_NAME, LevelFilter::Info)
-        .init()
-        .with_context(|| "Failed to initalize the logger")?;
-
     // Parse CLI arguments
     let cli_args = Opt::from_args();
-    if let Some(working_directory) = cli_args.working_directory {
+    // Relative to the directory the patcher has been started from
+    let patch_file = match &cli_args.patch_file {
+        Some(patch_file) => Some(env::current_dir()?.join(patch_file)),
+        None => None,
+    };
+
+    // The log file is located in the working directory. Bundled apps are
+    // started from '/' on macOS, the game is next to the bundle instead
+    let working_directory = cli_args.working_directory.or_else(get_app_bundle_directory);
+    if let Some(working_directory) = working_directory {
         env::set_current_dir(working_directory)
             .with_context(|| "Specified working directory is invalid or inaccessible")?;
     };
+    // The log file is named after the profile
+    if let Some(profile) = &cli_args.profile {
+        set_profile(profile)?;
+    }
+    init_data_directory(cli_args.portable)?;
+    init_logger(cli_args.log_level.unwrap_or(LevelFilter::Info))?;
+    install_crash_handler();
+    if cli_args.simulate_latency.is_some()
+        || cli_args.simulate_packet_loss.is_some()
+        || cli_args.simulate_throttle.is_some()
+    {
+        simulate_network_conditions(NetworkSimulation {
+            latency: cli_args.simulate_latency.map(Duration::from_millis),
+            packet_loss: cli_args.simulate_packet_loss.unwrap_or(0.0),
+            throttle: cli_args.simulate_throttle.map(|throttle| 1000 * throttle),
+        })?;
+    }
+
+    let command = match (cli_args.check_only, cli_args.command) {
+        (true, None) => Some(HeadlessCommand::Check),
+        (true, Some(_)) => return Err(anyhow!("--check-only can't be combined with a command")),
+        (false, command) => command,
+    };
+    let output_format = if cli_args.json {
+        OutputFormat::Json
+    } else {
+        OutputFormat::Text
+    };
+    // These are most needed when the configuration is broken
+    if let Some(command) = command.as_ref().filter(|c| !c.requires_configuration()) {
+        let exit_code = run_standalone_command(command, &Default::default(), output_format);
+        std::process::exit(exit_code as i32);
+    }
 
-    let config = match retrieve_patcher_configuration(None) {
+    let mut config = match retrieve_patcher_configuration(None) {
         Ok(config) => config,
         Err(e) => {
             log::error!("Failed to retrieve patcher configuration: {}", e);
+            if command.is_some() {
+                std::process::exit(ExitCode::ConfigurationError as i32);
+            }
             return Ok(());
         }
     };
+    // Layer user settings over the operator's configuration
+    let user_settings = retrieve_user_settings().unwrap_or_else(|e| {
+        log::warn!("Failed to retrieve user settings: {:#}", e);
+        UserSettings::default()
+    });
+    user_settings.apply_to(&mut config);
+    if let Some(pin_index) = cli_args.pin {
+        config.patching.pin_index = Some(pin_index);
+    }
+    set_json_log_file(config.logging.json);
+    if cli_args.log_level.is_none() {
+        if let Some(level) = &config.logging.level {
+            match level.parse() {
+                Ok(level) => log::set_max_level(level),
+                Err(_) => log::warn!("Invalid log level '{}'", level),
+            }
+        }
+    }
 
-    let (patching_thread_tx, patching_thread_rx) = mpsc::channel();
-    let config_clone = config.clone();
+    if let Some(command) = command {
+        std::process::exit(run_headless(config, command, output_format) as i32);
+    }
 
-    std::thread::spawn(move || {
-        if let Err(e) = patcher_thread_routine(config_clone, patching_thread_rx) {
-            log::error!("Patcher thread error: {}", e);
+    enable_crash_dialog(&config.localization);
+
+    // Only one window is opened per game directory, later invocations are
+    // forwarded to it. Demo windows don't patch anything, they're left out.
+    let instance_listener = if cli_args.demo {
+        None
+    } else {
+        match claim_instance() {
+            Ok(InstanceClaim::Primary(instance_listener)) => Some(instance_listener),
+            Ok(InstanceClaim::Secondary) => {
+                match forward_invocation(&config, cli_args.start_update, patch_file.clone()) {
+                    Ok(()) => return Ok(()),
+                    Err(e) => log::warn!("{:#}", e),
+                }
+                None
+            }
+            Err(e) => {
+                log::warn!("{:#}", e);
+                None
+            }
         }
-    });
+    };
+
+    let (patching_thread_tx, patching_thread_rx) = tokio::sync::mpsc::unbounded_channel();
+    let (status_tx, status_rx) = mpsc::channel();
+    // Updates wait for the operator's agreement to be accepted
+    let agreement_pending = is_agreement_pending(config.agreement.as_ref());
+    if (config.patching.auto_start || cli_args.start_update) && !agreement_pending {
+        let _ = patching_thread_tx.send(PatcherCommand::StartUpdate);
+    }
+    if let Some(patch_file) = patch_file {
+        let _ = patching_thread_tx.send(PatcherCommand::ManualPatchFile(patch_file));
+    }
+    // External tools see the statuses before the UI does
+    let status_rx = if config.control_api.enabled {
+        match bind_control_api(&config.control_api) {
+            Ok(listener) => serve_control_api(
+                listener,
+                &config.control_api,
+                patching_thread_tx.clone(),
+                status_rx,
+            ),
+            Err(e) => {
+                log::error!("Failed to start the control API: {:#}", e);
+                status_rx
+            }
+        }
+    } else {
+        status_rx
+    };
+    if config.peer_cache.enabled && !cli_args.demo {
+        if let Err(e) = serve_peer_cache(&config.peer_cache) {
+            log::warn!("Failed to share patches with the LAN: {:#}", e);
+        }
+    }
+    let config_clone = config.clone();
+
+    if cli_args.demo {
+        std::thread::spawn(move || demo_thread_routine(patching_thread_rx, status_tx));
+    } else {
+        std::thread::spawn(move || {
+            if let Err(e) = patcher_thread_routine(config_clone, patching_thread_rx, status_tx) {
+                log::error!("Patcher thread error: {}", e);
+            }
+        });
+    }
 
     let native_options = eframe::NativeOptions {
-        viewport: egui::ViewportBuilder::default()
-            .with_inner_size([config.window.width as f32, config.window.height as f32])
-            .with_resizable(config.window.resizable)
-            .with_title(&config.window.title),
+        viewport: build_viewport(&config, &user_settings),
         ..Default::default()
     };
 
-    // Create native UI
-    let native_ui = NativeUi::new(config.clone(), patching_thread_tx.clone());
-
     // Run native UI
+    let window_title = config.window.title.clone();
     eframe::run_native(
-        &config.window.title,
+        &window_title,
         native_options,
-        Box::new(|_cc| Box::new(native_ui)),
+        Box::new(move |cc| {
+            Box::new(NativeUi::new(
+                cc,
+                config,
+                user_settings,
+                patching_thread_tx,
+                status_rx,
+                instance_listener,
+            ))
+        }),
     )
     .map_err(|e| anyhow!("Failed to run native UI: {}", e))
 }
+
+/// Asks the instance of the patcher that's already running to do what the
+/// current one was started for, and to show its window.
+fn forward_invocation(
+    config: &PatcherConfiguration,
+    start_update: bool,
+    patch_file: Option<PathBuf>,
+) -> Result<()> {
+    let mut messages = vec![InstanceMessage::ShowWindow];
+    if start_update {
+        messages.push(InstanceMessage::StartUpdate);
+    }
+    if let Some(path) = patch_file {
+        messages.push(InstanceMessage::ManualPatchFile { path });
+    }
+    forward_to_running_instance(&messages)?;
+    // Unlike the running instance, the current process is allowed to bring
+    // windows to the foreground
+    process::restore_window(&config.window.title);
+    Ok(())
+}
+
+/// Configures the main window, restoring its last known geometry if any.
+fn build_viewport(
+    config: &PatcherConfiguration,
+    user_settings: &UserSettings,
+) -> egui::ViewportBuilder {
+    let window_config = &config.window;
+    let min_size = [
+        window_config.min_width.unwrap_or(0) as f32,
+        window_config.min_height.unwrap_or(0) as f32,
+    ];
+    let mut viewport = egui::ViewportBuilder::default()
+        .with_inner_size([window_config.width as f32, window_config.height as f32])
+        .with_resizable(window_config.resizable)
+        .with_decorations(window_config.decorations)
+        .with_transparent(window_config.transparent && !window_config.decorations)
+        .with_title(&window_config.title);
+    if window_config.resizable {
+        viewport = viewport.with_min_inner_size(min_size);
+    }
+    if let Some(geometry) = &user_settings.window {
+        viewport = viewport.with_position([geometry.x, geometry.y]);
+        // The size of non-resizable windows is always the operator's
+        if window_config.resizable {
+            viewport = viewport
+                .with_inner_size([
+                    geometry.width.max(min_size[0]),
+                    geometry.height.max(min_size[1]),
+                ])
+                .with_maximized(geometry.maximized);
+        }
+    }
+    viewport
+}
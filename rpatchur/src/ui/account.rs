@@ -0,0 +1,214 @@
+use std::sync::mpsc;
+
+use anyhow::Result;
+use eframe::egui;
+
+use crate::i18n::Translator;
+use crate::patcher::{
+    authenticate, delete_password, find_password, store_password, AccountConfiguration,
+    KEYCHAIN_SUPPORTED,
+};
+
+pub enum AccountPanelAction {
+    RememberAccount(Option<String>), // Account whose password is now in the keychain, if any
+}
+
+struct Session {
+    username: Option<String>, // Unknown when logged in with a token
+    token: String,
+}
+
+/// Form used to log players in, whose session token is passed to the game
+/// client.
+pub struct AccountPanel {
+    config: AccountConfiguration,
+    username: String,
+    password: String,
+    token: String,
+    use_token: bool,
+    remember_password: bool,
+    remembered_account: Option<String>,
+    session: Option<Session>,
+    login_rx: Option<mpsc::Receiver<Result<String>>>, // Set while logging in
+    error: Option<String>,
+}
+
+impl AccountPanel {
+    /// Fills the form with the password stored in the keychain, if any.
+    pub fn new(config: &AccountConfiguration, remembered_account: Option<&str>) -> Self {
+        let password = remembered_account.and_then(|account| {
+            find_password(account)
+                .map_err(|e| log::warn!("Failed to retrieve the stored password: {:#}", e))
+                .ok()
+                .flatten()
+        });
+        Self {
+            config: config.clone(),
+            username: remembered_account.unwrap_or_default().to_string(),
+            remember_password: password.is_some(),
+            password: password.unwrap_or_default(),
+            token: String::new(),
+            use_token: false,
+            remembered_account: remembered_account.map(str::to_string),
+            session: None,
+            login_rx: None,
+            error: None,
+        }
+    }
+
+    pub fn session_token(&self) -> Option<&str> {
+        self.session.as_ref().map(|session| session.token.as_str())
+    }
+
+    pub fn username(&self) -> Option<&str> {
+        self.session.as_ref()?.username.as_deref()
+    }
+
+    pub fn show(
+        &mut self,
+        ui: &mut egui::Ui,
+        translator: &Translator,
+    ) -> Option<AccountPanelAction> {
+        let mut action = None;
+        if let Some(result) = self.login_rx.as_ref().and_then(|rx| rx.try_recv().ok()) {
+            self.login_rx = None;
+            match result {
+                Ok(token) => action = self.on_logged_in(token),
+                Err(e) => {
+                    log::warn!("{:#}", e);
+                    self.error = Some(format!("{:#}", e));
+                }
+            }
+        }
+
+        ui.group(|ui| {
+            if let Some(session) = &self.session {
+                let status = match &session.username {
+                    Some(username) => {
+                        translator.tr_args("account.logged_in", &[("username", username)])
+                    }
+                    None => translator.tr("account.logged_in_with_token").to_string(),
+                };
+                ui.horizontal(|ui| {
+                    ui.label(status);
+                    if ui.button(translator.tr("button.log_out")).clicked() {
+                        self.session = None;
+                    }
+                });
+                return;
+            }
+
+            let logging_in = self.login_rx.is_some();
+            let mut submitted = false;
+            ui.add_enabled_ui(!logging_in, |ui| {
+                if self.use_token {
+                    ui.horizontal(|ui| {
+                        ui.label(translator.tr("account.token"));
+                        let response =
+                            ui.add(egui::TextEdit::singleline(&mut self.token).password(true));
+                        submitted |= is_submitted(ui, &response);
+                    });
+                } else {
+                    ui.horizontal(|ui| {
+                        ui.label(translator.tr("account.username"));
+                        let response = ui.text_edit_singleline(&mut self.username);
+                        submitted |= is_submitted(ui, &response);
+                        ui.label(translator.tr("account.password"));
+                        let response =
+                            ui.add(egui::TextEdit::singleline(&mut self.password).password(true));
+                        submitted |= is_submitted(ui, &response);
+                    });
+                }
+                ui.horizontal(|ui| {
+                    if ui.button(translator.tr("button.log_in")).clicked() {
+                        submitted = true;
+                    }
+                    // Passwords can only be remembered if the OS can keep them safe
+                    if KEYCHAIN_SUPPORTED && !self.use_token {
+                        ui.checkbox(
+                            &mut self.remember_password,
+                            translator.tr("account.remember_password"),
+                        );
+                    }
+                    if self.config.allow_token {
+                        ui.checkbox(&mut self.use_token, translator.tr("account.use_token"));
+                    }
+                });
+            });
+            if logging_in {
+                ui.label(translator.tr("account.logging_in"));
+            } else if let Some(error) = &self.error {
+                ui.colored_label(ui.visuals().error_fg_color, error);
+            }
+            if submitted {
+                self.log_in(ui.ctx());
+            }
+        });
+        action
+    }
+
+    fn log_in(&mut self, ctx: &egui::Context) {
+        self.error = None;
+        if self.use_token {
+            let token = self.token.trim();
+            if !token.is_empty() {
+                self.session = Some(Session {
+                    username: None,
+                    token: token.to_string(),
+                });
+            }
+            return;
+        }
+        if self.username.trim().is_empty() || self.password.is_empty() {
+            return;
+        }
+        // Don't freeze the UI while the login endpoint answers
+        let (login_tx, login_rx) = mpsc::channel();
+        let config = self.config.clone();
+        let username = self.username.trim().to_string();
+        let password = self.password.clone();
+        let ctx = ctx.clone();
+        std::thread::spawn(move || {
+            let _ = login_tx.send(authenticate(&config, &username, &password));
+            ctx.request_repaint();
+        });
+        self.login_rx = Some(login_rx);
+    }
+
+    fn on_logged_in(&mut self, token: String) -> Option<AccountPanelAction> {
+        let username = self.username.trim().to_string();
+        self.session = Some(Session {
+            username: Some(username.clone()),
+            token,
+        });
+
+        let account_to_remember = if KEYCHAIN_SUPPORTED && self.remember_password {
+            match store_password(&username, &self.password) {
+                Ok(()) => Some(username),
+                Err(e) => {
+                    log::warn!("{:#}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        if let Some(previous_account) = &self.remembered_account {
+            if account_to_remember.as_ref() != Some(previous_account) {
+                if let Err(e) = delete_password(previous_account) {
+                    log::warn!("{:#}", e);
+                }
+            }
+        }
+        if account_to_remember == self.remembered_account {
+            return None;
+        }
+        self.remembered_account = account_to_remember.clone();
+        Some(AccountPanelAction::RememberAccount(account_to_remember))
+    }
+}
+
+/// Whether Enter has been pressed in a text field.
+fn is_submitted(ui: &egui::Ui, response: &egui::Response) -> bool {
+    response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter))
+}
@@ -0,0 +1,62 @@
+use eframe::egui;
+
+use crate::i18n::Translator;
+use crate::patcher::AgreementConfiguration;
+
+pub enum AgreementAction {
+    Accept,
+    Decline,
+}
+
+/// Window asking players to accept the operator's agreement, which blocks
+/// the rest of the patcher until they do.
+pub struct AgreementPanel {
+    agreement: AgreementConfiguration,
+}
+
+impl AgreementPanel {
+    pub fn new(agreement: AgreementConfiguration) -> Self {
+        Self { agreement }
+    }
+
+    pub fn agreement(&self) -> &AgreementConfiguration {
+        &self.agreement
+    }
+
+    pub fn show(
+        &mut self,
+        ctx: &egui::Context,
+        translator: &Translator,
+    ) -> Option<AgreementAction> {
+        let mut action = None;
+        egui::Window::new(translator.tr("agreement.title"))
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                ui.label(translator.tr("agreement.message"));
+                if let Some(text) = &self.agreement.text {
+                    ui.add_space(5.0);
+                    egui::ScrollArea::vertical()
+                        .max_height(200.0)
+                        .show(ui, |ui| {
+                            ui.label(text);
+                        });
+                }
+                if let Some(url) = &self.agreement.url {
+                    ui.add_space(5.0);
+                    ui.hyperlink_to(translator.tr("agreement.read_full_terms"), url);
+                }
+                ui.add_space(10.0);
+                ui.horizontal(|ui| {
+                    if ui.button(translator.tr("button.accept")).clicked() {
+                        action = Some(AgreementAction::Accept);
+                    }
+                    if ui.button(translator.tr("button.decline")).clicked() {
+                        action = Some(AgreementAction::Decline);
+                    }
+                });
+            });
+        action
+    }
+}
@@ -0,0 +1,129 @@
+use std::sync::mpsc;
+use std::time::Duration;
+
+use eframe::egui;
+
+use super::skin::load_image;
+use crate::patcher::{banner_thread_routine, BannerConfiguration, BannerImage};
+
+const DEFAULT_HEIGHT: f32 = 120.0;
+const DEFAULT_INTERVAL_SECS: u64 = 5;
+
+struct Slide {
+    texture: egui::TextureHandle,
+    link: Option<String>,
+}
+
+/// Slideshow of the images advertised by the operator.
+pub struct Banner {
+    images_rx: mpsc::Receiver<Vec<BannerImage>>,
+    slides: Vec<Slide>,
+    current_slide: usize,
+    slide_shown_at: f64, // Time at which the current slide was shown, in seconds
+    height: f32,
+    interval: f64, // In seconds
+}
+
+impl Banner {
+    /// Starts fetching the images in the background.
+    pub fn new(ctx: &egui::Context, config: &BannerConfiguration) -> Self {
+        let (images_tx, images_rx) = mpsc::channel();
+        let config_clone = config.clone();
+        let ctx_clone = ctx.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = banner_thread_routine(config_clone, images_tx) {
+                log::error!("Banner thread error: {:#}", e);
+            }
+            // Show the images as soon as they're available
+            ctx_clone.request_repaint();
+        });
+        Self {
+            images_rx,
+            slides: vec![],
+            current_slide: 0,
+            slide_shown_at: 0.0,
+            height: config.height.unwrap_or(DEFAULT_HEIGHT),
+            interval: config.interval.unwrap_or(DEFAULT_INTERVAL_SECS).max(1) as f64,
+        }
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui) {
+        let now = ui.input(|i| i.time);
+        if let Ok(images) = self.images_rx.try_recv() {
+            self.load_slides(ui.ctx(), images);
+            self.slide_shown_at = now;
+        }
+        if self.slides.is_empty() {
+            return;
+        }
+
+        let elapsed = now - self.slide_shown_at;
+        if elapsed >= self.interval {
+            self.show_slide((self.current_slide + 1) % self.slides.len(), now);
+        }
+        // Wake up for the next slide, even if nothing happens in the meantime
+        let remaining = (self.interval - (now - self.slide_shown_at)).max(0.0);
+        ui.ctx()
+            .request_repaint_after(Duration::from_secs_f64(remaining));
+
+        let slide = &self.slides[self.current_slide];
+        let size = egui::vec2(ui.available_width(), self.height);
+        let image = egui::Image::new(&slide.texture)
+            .fit_to_exact_size(size)
+            .sense(egui::Sense::click());
+        let mut response = ui.add(image);
+        if let Some(link) = &slide.link {
+            response.widget_info(|| egui::WidgetInfo::labeled(egui::WidgetType::Link, link));
+            response = response.on_hover_cursor(egui::CursorIcon::PointingHand);
+            if response.clicked() {
+                if let Err(e) = open::that(link) {
+                    log::warn!("Failed to open '{}': {}", link, e);
+                }
+            }
+        }
+
+        // One dot per slide, which can be clicked to show it
+        if self.slides.len() > 1 {
+            let mut clicked_slide = None;
+            let slide_count = self.slides.len();
+            ui.horizontal(|ui| {
+                for i in 0..slide_count {
+                    let current = i == self.current_slide;
+                    let dot = if current { "\u{25cf}" } else { "\u{25cb}" };
+                    let response = ui.small_button(dot);
+                    response.widget_info(|| {
+                        let label = format!("{}/{}", i + 1, slide_count);
+                        egui::WidgetInfo::selected(egui::WidgetType::RadioButton, current, label)
+                    });
+                    if response.clicked() {
+                        clicked_slide = Some(i);
+                    }
+                }
+            });
+            if let Some(i) = clicked_slide {
+                self.show_slide(i, now);
+            }
+        }
+    }
+
+    fn show_slide(&mut self, slide_index: usize, now: f64) {
+        self.current_slide = slide_index;
+        self.slide_shown_at = now;
+    }
+
+    fn load_slides(&mut self, ctx: &egui::Context, images: Vec<BannerImage>) {
+        for (i, image) in images.into_iter().enumerate() {
+            match load_image(&image.path) {
+                Ok(color_image) => self.slides.push(Slide {
+                    texture: ctx.load_texture(
+                        format!("banner_{}", i),
+                        color_image,
+                        egui::TextureOptions::LINEAR,
+                    ),
+                    link: image.link,
+                }),
+                Err(e) => log::warn!("Failed to load banner image: {:#}", e),
+            }
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use eframe::egui;
+
+use crate::i18n::Translator;
+use crate::patcher::{create_support_bundle, get_log_file_path};
+use crate::ui::ErrorReport;
+
+// Longer summaries are truncated, the rest being available in the details
+const MAX_SUMMARY_LENGTH: usize = 120;
+
+/// Shows an error's summary in red, with its details and actions in a
+/// collapsible area.
+pub fn show_error_report(ui: &mut egui::Ui, report: &ErrorReport, translator: &Translator) {
+    let summary = translator.tr(report.summary());
+    let summary = if summary.chars().count() > MAX_SUMMARY_LENGTH {
+        let truncated: String = summary.chars().take(MAX_SUMMARY_LENGTH).collect();
+        truncated + "…"
+    } else {
+        summary.to_string()
+    };
+    ui.label(egui::RichText::new(summary).color(egui::Color32::RED));
+
+    egui::CollapsingHeader::new(translator.tr("error.details"))
+        .id_source("error_details")
+        .show(ui, |ui| {
+            let mut details = report.details();
+            if has_details(report) {
+                egui::ScrollArea::vertical()
+                    .max_height(100.0)
+                    .show(ui, |ui| {
+                        // Read-only, but selectable
+                        ui.add(
+                            egui::TextEdit::multiline(&mut details)
+                                .font(egui::TextStyle::Monospace)
+                                .desired_width(f32::INFINITY)
+                                .interactive(false),
+                        );
+                    });
+            }
+            ui.horizontal(|ui| {
+                if ui.button(translator.tr("button.copy_error")).clicked() {
+                    ui.output_mut(|o| o.copied_text = details.clone());
+                }
+                if let Ok(log_file_path) = get_log_file_path() {
+                    if log_file_path.exists()
+                        && ui.button(translator.tr("button.open_log_file")).clicked()
+                    {
+                        if let Err(e) = open::that(&log_file_path) {
+                            log::warn!("Failed to open log file: {}", e);
+                        }
+                    }
+                }
+                if ui
+                    .button(translator.tr("button.create_support_bundle"))
+                    .clicked()
+                {
+                    match create_support_bundle(Some(&details)) {
+                        Ok(bundle_path) => {
+                            log::info!("Created '{}'", bundle_path.display());
+                            // Show the bundle to the player, so they can attach it
+                            let bundle_directory = std::env::current_dir().unwrap_or_default();
+                            if let Err(e) = open::that(&bundle_directory) {
+                                log::warn!("Failed to open the working directory: {}", e);
+                            }
+                        }
+                        Err(e) => log::error!("Failed to create support bundle: {:#}", e),
+                    }
+                }
+            });
+        });
+}
+
+/// Returns whether the report's details tell more than its summary.
+fn has_details(report: &ErrorReport) -> bool {
+    !report.causes().is_empty() || report.summary().chars().count() > MAX_SUMMARY_LENGTH
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn test_has_details() {
+        let error = std::fs::File::open("/nonexistent/rpatchur.yml")
+            .context("Failed to open configuration")
+            .unwrap_err();
+        assert!(has_details(&ErrorReport::from(&error)));
+        assert!(has_details(&ErrorReport::new("error ".repeat(30))));
+        let report = ErrorReport::new("error.channel_disconnected");
+        assert!(!has_details(&report));
+    }
+}
@@ -0,0 +1,228 @@
+use eframe::egui;
+
+use super::human_readable_size;
+use crate::i18n::Translator;
+use crate::patcher::{GrfEntryInfo, GrfIndex};
+
+const ROW_HEIGHT: f32 = 18.0;
+
+/// Read-only explorer of the files of a GRF, which can be extracted one at a
+/// time.
+pub struct GrfBrowserView {
+    grf_path: String,
+    grf_index: Option<GrfIndex>,
+    directory: String, // Empty for the GRF's root
+    search: String,
+    subdirectories: Vec<String>, // Listed unless searching
+    files: Vec<GrfEntryInfo>,
+    selected_file: Option<String>, // Relative path
+    message: Option<String>,
+}
+
+impl GrfBrowserView {
+    pub fn new(grf_path: &str) -> Self {
+        let mut view = Self {
+            grf_path: grf_path.to_string(),
+            grf_index: None,
+            directory: String::new(),
+            search: String::new(),
+            subdirectories: vec![],
+            files: vec![],
+            selected_file: None,
+            message: None,
+        };
+        view.open();
+        view
+    }
+
+    /// Returns the path of the GRF to repair, once users asked for it.
+    pub fn show(
+        &mut self,
+        ui: &mut egui::Ui,
+        translator: &Translator,
+        patching_in_progress: bool,
+    ) -> Option<String> {
+        let mut grf_to_repair = None;
+        ui.horizontal(|ui| {
+            ui.label(translator.tr("grf_browser.grf"));
+            let path_edit =
+                ui.add(egui::TextEdit::singleline(&mut self.grf_path).desired_width(200.0));
+            let submitted = path_edit.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+            if ui.button(translator.tr("button.open")).clicked() || submitted {
+                self.open();
+            }
+            // GRFs which can't be opened are the ones most in need of a repair
+            let repair_button = egui::Button::new(translator.tr("button.repair"));
+            if ui
+                .add_enabled(!patching_in_progress, repair_button)
+                .on_hover_text(translator.tr("grf_browser.repair_hint"))
+                .clicked()
+            {
+                grf_to_repair = Some(self.grf_path.trim().to_string());
+            }
+        });
+        if let Some(message) = &self.message {
+            ui.label(message);
+        }
+        let file_count = match &self.grf_index {
+            Some(grf_index) => grf_index.file_count(),
+            None => return grf_to_repair,
+        };
+
+        ui.horizontal(|ui| {
+            ui.label(translator.tr_args("grf_browser.file_count", &[("count", &file_count)]));
+            let search_edit = ui.add(
+                egui::TextEdit::singleline(&mut self.search)
+                    .hint_text(translator.tr("grf_browser.search_hint"))
+                    .desired_width(150.0),
+            );
+            if search_edit.changed() {
+                self.refresh_listing();
+            }
+        });
+        if self.search.is_empty() {
+            ui.horizontal(|ui| {
+                if ui
+                    .add_enabled(!self.directory.is_empty(), egui::Button::new("\u{2b06}"))
+                    .clicked()
+                {
+                    let parent = self.directory.rsplit_once('\\').map(|(parent, _)| parent);
+                    self.directory = parent.unwrap_or_default().to_string();
+                    self.refresh_listing();
+                }
+                ui.monospace(format!("\\{}", self.directory));
+            });
+        }
+
+        let mut opened_directory = None;
+        let row_count = self.subdirectories.len() + self.files.len();
+        egui::ScrollArea::vertical()
+            .max_height(220.0)
+            .auto_shrink([false, true])
+            .show_rows(ui, ROW_HEIGHT, row_count, |ui, row_range| {
+                egui::Grid::new("grf_browser_grid")
+                    .striped(true)
+                    .min_row_height(ROW_HEIGHT)
+                    .show(ui, |ui| {
+                        for row in row_range {
+                            if let Some(subdirectory) = self.subdirectories.get(row) {
+                                if ui
+                                    .selectable_label(false, format!("\u{1f4c1} {}", subdirectory))
+                                    .double_clicked()
+                                {
+                                    opened_directory = Some(subdirectory.clone());
+                                }
+                                ui.end_row();
+                                continue;
+                            }
+                            let file = &self.files[row - self.subdirectories.len()];
+                            // Search results are listed by path, since they
+                            // come from any directory
+                            let name = if self.search.is_empty() {
+                                file.file_name()
+                            } else {
+                                file.relative_path.as_str()
+                            };
+                            let selected =
+                                self.selected_file.as_deref() == Some(file.relative_path.as_str());
+                            if ui.selectable_label(selected, name).clicked() {
+                                self.selected_file = Some(file.relative_path.clone());
+                            }
+                            ui.label(human_readable_size(file.size as u64));
+                            let compression = translator.tr_args(
+                                "grf_browser.compressed",
+                                &[("size", &human_readable_size(file.size_compressed as u64))],
+                            );
+                            ui.label(compression);
+                            if file.encrypted {
+                                ui.label(translator.tr("grf_browser.encrypted"));
+                            }
+                            ui.end_row();
+                        }
+                    });
+            });
+        if let Some(subdirectory) = opened_directory {
+            if !self.directory.is_empty() {
+                self.directory.push('\\');
+            }
+            self.directory.push_str(&subdirectory);
+            self.refresh_listing();
+        }
+
+        ui.add_space(5.0);
+        let extract_button = egui::Button::new(translator.tr("button.extract_selected"));
+        if ui
+            .add_enabled(self.selected_file.is_some(), extract_button)
+            .clicked()
+        {
+            self.extract_selected_file(translator);
+        }
+        grf_to_repair
+    }
+
+    /// Opens the GRF again once it's been repaired, since it may have been
+    /// rebuilt, and shows the outcome of the repair.
+    pub fn set_repair_message(&mut self, message: String) {
+        self.open();
+        self.message = Some(message);
+    }
+
+    fn open(&mut self) {
+        self.directory.clear();
+        self.search.clear();
+        self.selected_file = None;
+        match GrfIndex::open(self.grf_path.trim()) {
+            Ok(grf_index) => {
+                self.grf_index = Some(grf_index);
+                self.message = None;
+            }
+            Err(e) => {
+                self.grf_index = None;
+                self.message = Some(format!("{:#}", e));
+            }
+        }
+        self.refresh_listing();
+    }
+
+    fn refresh_listing(&mut self) {
+        let grf_index = match &self.grf_index {
+            Some(grf_index) => grf_index,
+            None => {
+                self.subdirectories.clear();
+                self.files.clear();
+                return;
+            }
+        };
+        if self.search.is_empty() {
+            let (subdirectories, files) = grf_index.list_directory(&self.directory);
+            self.subdirectories = subdirectories;
+            self.files = files;
+        } else {
+            self.subdirectories.clear();
+            self.files = grf_index.search(&self.search);
+        }
+    }
+
+    fn extract_selected_file(&mut self, translator: &Translator) {
+        let (grf_index, relative_path) = match (&self.grf_index, &self.selected_file) {
+            (Some(grf_index), Some(relative_path)) => (grf_index, relative_path),
+            _ => return,
+        };
+        let file_name = relative_path.rsplit('\\').next().unwrap_or(relative_path);
+        let destination_path = match tinyfiledialogs::save_file_dialog(
+            translator.tr("grf_browser.extract_title"),
+            file_name,
+        ) {
+            Some(path) => path,
+            None => return,
+        };
+        self.message = Some(
+            match grf_index.extract_file(relative_path, std::path::Path::new(&destination_path)) {
+                Ok(()) => {
+                    translator.tr_args("grf_browser.extracted", &[("path", &destination_path)])
+                }
+                Err(e) => format!("{:#}", e),
+            },
+        );
+    }
+}
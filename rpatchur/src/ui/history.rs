@@ -0,0 +1,159 @@
+use std::path::Path;
+
+use anyhow::Result;
+use chrono::{Local, TimeZone};
+use eframe::egui;
+
+use super::{human_readable_duration, human_readable_size};
+use crate::i18n::Translator;
+use crate::patcher::{
+    export_patch_history_to_csv, export_patch_history_to_json, retrieve_patch_history,
+    retrieve_patcher_state, DownloadSession, PatchHistoryEntry,
+};
+
+/// Lists the patches that have been applied and the downloads of the
+/// updates, most recent first.
+pub struct HistoryView {
+    entries: Vec<PatchHistoryEntry>,
+    download_sessions: Vec<DownloadSession>,
+    message: Option<String>,
+}
+
+impl HistoryView {
+    pub fn load() -> Self {
+        let mut view = match retrieve_patch_history() {
+            Ok(mut entries) => {
+                entries.reverse();
+                Self {
+                    entries,
+                    download_sessions: vec![],
+                    message: None,
+                }
+            }
+            Err(e) => Self {
+                entries: vec![],
+                download_sessions: vec![],
+                message: Some(format!("{:#}", e)),
+            },
+        };
+        match retrieve_patcher_state() {
+            Ok(state) => {
+                view.download_sessions = state.download_sessions.into_iter().rev().collect()
+            }
+            Err(e) => log::warn!("Failed to retrieve download sessions: {:#}", e),
+        }
+        view
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui, translator: &Translator) {
+        ui.horizontal(|ui| {
+            if ui.button(translator.tr("button.export_json")).clicked() {
+                self.export(translator, "history.json", "*.json", |path, entries| {
+                    export_patch_history_to_json(path, entries)
+                });
+            }
+            if ui.button(translator.tr("button.export_csv")).clicked() {
+                self.export(translator, "history.csv", "*.csv", |path, entries| {
+                    export_patch_history_to_csv(path, entries)
+                });
+            }
+        });
+        if let Some(message) = &self.message {
+            ui.label(message);
+        }
+        ui.add_space(5.0);
+
+        if self.entries.is_empty() && self.download_sessions.is_empty() {
+            ui.label(translator.tr("history.empty"));
+            return;
+        }
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            if !self.download_sessions.is_empty() {
+                ui.collapsing(translator.tr("history.download_sessions"), |ui| {
+                    self.show_download_sessions(ui, translator);
+                });
+                ui.add_space(5.0);
+            }
+            ui.strong(translator.tr("history.patches"));
+            egui::Grid::new("patch_history_grid")
+                .striped(true)
+                .show(ui, |ui| {
+                    ui.strong(translator.tr("history.date"));
+                    ui.strong(translator.tr("patch_selection.index"));
+                    ui.strong(translator.tr("patch_selection.name"));
+                    ui.strong(translator.tr("history.duration"));
+                    ui.strong(translator.tr("history.result"));
+                    ui.end_row();
+                    for entry in &self.entries {
+                        ui.label(format_timestamp(entry.timestamp));
+                        ui.label(entry.index.map(|i| i.to_string()).unwrap_or_default());
+                        ui.label(&entry.name);
+                        ui.label(format!("{:.1} s", entry.duration_ms as f64 / 1000.0));
+                        match &entry.error {
+                            None => ui.label(translator.tr("history.success")),
+                            Some(error) => ui
+                                .label(
+                                    egui::RichText::new(translator.tr("history.failure"))
+                                        .color(egui::Color32::RED),
+                                )
+                                .on_hover_text(error),
+                        };
+                        ui.end_row();
+                    }
+                });
+        });
+    }
+
+    fn show_download_sessions(&self, ui: &mut egui::Ui, translator: &Translator) {
+        egui::Grid::new("download_sessions_grid")
+            .striped(true)
+            .show(ui, |ui| {
+                ui.strong(translator.tr("history.date"));
+                ui.strong(translator.tr("history.downloaded"));
+                ui.strong(translator.tr("history.duration"));
+                ui.strong(translator.tr("history.patch_count"));
+                ui.end_row();
+                for session in &self.download_sessions {
+                    ui.label(format_timestamp(session.started_at));
+                    ui.label(human_readable_size(session.downloaded_bytes));
+                    ui.label(human_readable_duration(session.duration_ms));
+                    ui.label(session.patch_count.to_string());
+                    ui.end_row();
+                }
+            });
+    }
+
+    fn export<F>(
+        &mut self,
+        translator: &Translator,
+        default_file_name: &str,
+        pattern: &str,
+        export_fn: F,
+    ) where
+        F: Fn(&Path, &[PatchHistoryEntry]) -> Result<()>,
+    {
+        let export_file_path = match tinyfiledialogs::save_file_dialog_with_filter(
+            translator.tr("history.export_title"),
+            default_file_name,
+            &[pattern],
+            pattern,
+        ) {
+            Some(path) => path,
+            None => return,
+        };
+        // Export in chronological order
+        let entries: Vec<PatchHistoryEntry> = self.entries.iter().rev().cloned().collect();
+        self.message = Some(match export_fn(Path::new(&export_file_path), &entries) {
+            Ok(()) => translator.tr_args("history.exported", &[("path", &export_file_path)]),
+            Err(e) => format!("{:#}", e),
+        });
+    }
+}
+
+fn format_timestamp(timestamp: i64) -> String {
+    Local
+        .timestamp_opt(timestamp, 0)
+        .single()
+        .map(|date| date.format("%Y-%m-%d %H:%M:%S").to_string())
+        .unwrap_or_default()
+}
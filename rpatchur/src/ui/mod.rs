@@ -1,3 +1,24 @@
+mod account;
+mod agreement;
+mod banner;
+mod error;
+mod grf_browser;
+mod history;
 pub mod native;
+mod notification;
+mod orphaned_files;
+mod patch_results;
+mod patch_selection;
+mod patch_servers;
+mod release_notes;
+mod settings;
+mod skin;
+mod speed_graph;
+mod storage;
+mod tray;
 
-pub use native::{NativeUi, PatchingStatus}; 
\ No newline at end of file
+pub use native::NativeUi;
+pub use rpatchur_core::status::{
+    human_readable_duration, human_readable_size, ErrorReport, OrphanedFile, PatchOutcome,
+    PatchPreview, PatchResult, PatchServerHealth, PatchingStatus, ReleaseNote, UpdateProgress,
+};
@@ -1,135 +1,1807 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::sync::mpsc;
+use std::time::Duration;
 use eframe::egui;
-use crate::patcher::{PatcherCommand, PatcherConfiguration};
-use crate::process::start_executable;
+use tokio::sync::mpsc::UnboundedSender;
+use crate::i18n::Translator;
+use anyhow::{anyhow, Context, Result};
+use crate::patcher::{
+    accept_agreement, expand_template, get_log_file_path, get_profile, is_agreement_pending, is_portable, launch_variables_thread_routine,
+    maintenance_thread_routine, motd_thread_routine, save_user_settings, ErrorKind, InstanceListener, InstanceMessage,
+    LaunchButtonConfiguration, MaintenanceStatus, Motd, MotdSeverity, PatcherCommand,
+    PatcherConfiguration, ScriptHooks, UserSettings, WindowGeometry, WineConfiguration,
+    BUNDLE_FILE_EXTENSION,
+};
+use crate::process::{
+    restart_elevated, reveal_in_file_manager, start_executable, wait_for_processes_exit, LaunchOptions,
+};
+use super::account::{AccountPanel, AccountPanelAction};
+use super::agreement::{AgreementAction, AgreementPanel};
+use super::banner::Banner;
+use super::error::show_error_report;
+use super::grf_browser::GrfBrowserView;
+use super::history::HistoryView;
+use super::notification::show_notification;
+use super::orphaned_files::{OrphanedFilesAction, OrphanedFilesPanel};
+use super::patch_servers::{PatchServersAction, PatchServersView};
+use super::patch_results::show_patch_results;
+use super::patch_selection::{PatchSelectionAction, PatchSelectionPanel};
+use super::release_notes::ReleaseNotesPanel;
+use super::settings::{SettingsPanel, SettingsPanelAction};
+use super::skin::Skin;
+use super::speed_graph::SpeedGraph;
+use super::storage::StorageView;
+use super::tray::{SystemTray, TrayAction};
+use super::{
+    human_readable_duration, human_readable_size, ErrorReport, PatchResult, PatchingStatus,
+    UpdateProgress,
+};
+
+#[derive(PartialEq)]
+enum Tab {
+    Patcher,
+    History,
+    Storage,
+    GrfBrowser,
+    PatchServers,
+}
 
 pub struct NativeUi {
     patcher_config: PatcherConfiguration,
-    patching_thread_tx: mpsc::Sender<PatcherCommand>,
+    launch_buttons: Vec<LaunchButtonConfiguration>,
+    game_up_to_date: bool,
+    translator: Translator,
+    patching_thread_tx: UnboundedSender<PatcherCommand>,
     patching_in_progress: bool,
+    cancel_requested: bool, // Canceling again forces the cancellation
     download_progress: f32,
     download_status: String,
-    error_message: Option<String>,
+    file_progress: f32,
+    file_status: Option<String>,
+    download_total_bytes: Option<u64>,
+    downloaded_bytes: u64,
+    smoothed_bytes_per_sec: Option<f64>,
+    speed_graph: SpeedGraph,
+    error: Option<ErrorReport>,
+    patch_results: Vec<PatchResult>, // Set when the last update couldn't download some patches
+    unverified_patches: Vec<String>, // Patches the last update applied without integrity data
     status_rx: mpsc::Receiver<PatchingStatus>,
+    system_tray: Option<SystemTray>,
+    hidden_to_tray: bool,
+    quit_requested: bool,
+    settings_panel: Option<SettingsPanel>,
+    patch_selection_panel: Option<PatchSelectionPanel>,
+    agreement_panel: Option<AgreementPanel>, // Set until players accept the operator's agreement
+    release_notes_panel: Option<ReleaseNotesPanel>, // Set until players close the notes of the last update
+    orphaned_files_panel: Option<OrphanedFilesPanel>,
+    game_client_running: bool, // Patching is waiting for the game to be closed
+    files_locked: bool,        // Patching is waiting for the game to close its files
+    update_running_elsewhere: bool, // The progress shown is the one of another instance
+    applying_patches: bool,         // Patches are being applied, canceling has to be confirmed
+    cancel_confirmation: bool,      // Set until users confirm or dismiss their cancellation
+    download_summary: Option<UpdateProgress>, // Set when the running update downloaded patches
+    patcher_outdated: bool,    // The patch server requires a more recent patcher
+    game_missing: bool,        // The game has to be installed before it can be patched
+    // Receives whether to check for updates once a launched program exits
+    program_exit_rx: Option<mpsc::Receiver<bool>>,
+    current_tab: Tab,
+    history_view: Option<HistoryView>,
+    storage_view: Option<StorageView>,
+    grf_browser_view: Option<GrfBrowserView>, // Kept across tabs, GRFs take a while to open
+    patch_servers_view: Option<PatchServersView>,
+    user_settings: UserSettings,
+    window_geometry: Option<WindowGeometry>,
+    skin: Option<Skin>, // Only used by frameless windows
+    banner: Option<Banner>,
+    account_panel: Option<AccountPanel>,
+    remote_variables_rx: Option<mpsc::Receiver<HashMap<String, String>>>,
+    remote_variables: HashMap<String, String>, // Launch variables from the status endpoint
+    maintenance_rx: Option<mpsc::Receiver<MaintenanceStatus>>,
+    maintenance: MaintenanceStatus,
+    motd_rx: Option<mpsc::Receiver<Motd>>,
+    motd: Option<Motd>,
+    instance_message_rx: Option<mpsc::Receiver<InstanceMessage>>, // Requests of the instances started later
+    script_hooks: ScriptHooks,
 }
 
 impl NativeUi {
-    pub fn new(patcher_config: PatcherConfiguration, patching_thread_tx: mpsc::Sender<PatcherCommand>) -> Self {
-        let (status_tx, status_rx) = mpsc::channel();
+    pub fn new(
+        cc: &eframe::CreationContext<'_>,
+        patcher_config: PatcherConfiguration,
+        user_settings: UserSettings,
+        patching_thread_tx: UnboundedSender<PatcherCommand>,
+        status_rx: mpsc::Receiver<PatchingStatus>,
+        instance_listener: Option<InstanceListener>,
+    ) -> Self {
+        let translator = Translator::new(&patcher_config.localization);
+        let system_tray = if patcher_config.tray.enabled {
+            SystemTray::new(&patcher_config.window.title, &translator, &cc.egui_ctx)
+                .map_err(|e| log::warn!("Failed to create tray icon: {:#}", e))
+                .ok()
+        } else {
+            None
+        };
+        let skin = if patcher_config.window.decorations {
+            None
+        } else {
+            let skin_config = patcher_config.window.skin.clone().unwrap_or_default();
+            Some(Skin::new(&cc.egui_ctx, skin_config))
+        };
+        let banner = if patcher_config.banner.is_enabled() {
+            Some(Banner::new(&cc.egui_ctx, &patcher_config.banner))
+        } else {
+            None
+        };
+        let account_panel = patcher_config
+            .account
+            .as_ref()
+            .map(|account_config| AccountPanel::new(account_config, user_settings.account.as_deref()));
+        let remote_variables_rx = patcher_config
+            .variables
+            .url
+            .as_ref()
+            .map(|_| spawn_launch_variables_thread(&cc.egui_ctx, &patcher_config));
+        let maintenance_rx = patcher_config
+            .maintenance
+            .url
+            .as_ref()
+            .map(|_| spawn_maintenance_thread(&cc.egui_ctx, &patcher_config));
+        let motd_rx = patcher_config
+            .motd
+            .url
+            .as_ref()
+            .map(|_| spawn_motd_thread(&cc.egui_ctx, &patcher_config));
+        let agreement_panel = patcher_config
+            .agreement
+            .clone()
+            .filter(|agreement| is_agreement_pending(Some(agreement)))
+            .map(AgreementPanel::new);
+        let instance_message_rx =
+            instance_listener.map(|listener| spawn_instance_listener(&cc.egui_ctx, listener));
+        let script_hooks = ScriptHooks::load(&patcher_config.scripting);
+        let status_rx = wake_on_status(&cc.egui_ctx, status_rx);
+        let game_missing =
+            patcher_config.installer.is_some() && !Path::new(&patcher_config.play.path).exists();
         Self {
+            launch_buttons: patcher_config.launch_buttons(),
+            game_up_to_date: false,
             patcher_config,
             patching_thread_tx,
             patching_in_progress: false,
+            cancel_requested: false,
             download_progress: 0.0,
-            download_status: "Ready".to_string(),
-            error_message: None,
+            download_status: translator.tr("status.ready").to_string(),
+            file_progress: 0.0,
+            file_status: None,
+            download_total_bytes: None,
+            downloaded_bytes: 0,
+            smoothed_bytes_per_sec: None,
+            speed_graph: SpeedGraph::default(),
+            error: None,
+            patch_results: vec![],
+            unverified_patches: vec![],
             status_rx,
+            translator,
+            system_tray,
+            hidden_to_tray: false,
+            quit_requested: false,
+            settings_panel: None,
+            patch_selection_panel: None,
+            agreement_panel,
+            release_notes_panel: None,
+            orphaned_files_panel: None,
+            game_client_running: false,
+            files_locked: false,
+            update_running_elsewhere: false,
+            applying_patches: false,
+            cancel_confirmation: false,
+            download_summary: None,
+            patcher_outdated: false,
+            game_missing,
+            program_exit_rx: None,
+            current_tab: Tab::Patcher,
+            history_view: None,
+            storage_view: None,
+            grf_browser_view: None,
+            patch_servers_view: None,
+            window_geometry: user_settings.window.clone(),
+            user_settings,
+            skin,
+            banner,
+            account_panel,
+            remote_variables_rx,
+            remote_variables: HashMap::new(),
+            maintenance_rx,
+            maintenance: MaintenanceStatus::default(),
+            motd_rx,
+            motd: None,
+            instance_message_rx,
+            script_hooks,
         }
     }
 
     pub fn set_patching_status(&mut self, status: PatchingStatus) {
+        // Any other status means the game isn't in the way anymore
+        self.game_client_running = matches!(status, PatchingStatus::GameClientRunning);
+        self.files_locked = matches!(status, PatchingStatus::FilesLocked);
         match status {
             PatchingStatus::Ready => {
+                // Don't overwrite the outcome of the last operation
+                if !self.patching_in_progress {
+                    return;
+                }
+                self.set_patching_in_progress(false);
+                self.game_up_to_date = true;
                 self.download_progress = 0.0;
-                self.download_status = "Ready".to_string();
-                self.error_message = None;
+                self.download_status = self.translator.tr("status.ready").to_string();
+                self.append_download_summary();
+                self.file_status = None;
+                self.reset_download_estimation();
+                self.error = None;
+                self.patch_results.clear();
             }
-            PatchingStatus::Error(msg) => {
+            PatchingStatus::Error(report) => {
+                self.set_patching_in_progress(false);
                 self.download_progress = 0.0;
-                self.download_status = "Error".to_string();
-                self.error_message = Some(msg);
+                self.download_status = self.translator.tr("status.error").to_string();
+                self.file_status = None;
+                self.reset_download_estimation();
+                self.patcher_outdated = report.kind() == ErrorKind::PatcherOutdated;
+                self.error = Some(report);
             }
             PatchingStatus::DownloadInProgress(nb_downloaded, nb_total, bytes_per_sec) => {
+                self.set_patching_in_progress(true);
+                self.patch_results.clear();
+                self.unverified_patches.clear();
                 self.download_progress = (nb_downloaded as f32) / (nb_total as f32);
-                let speed = if bytes_per_sec > 0 {
-                    format!(" - {:.2} MB/s", bytes_per_sec as f32 / 1_000_000.0)
+                let mut status = self.translator.tr_args(
+                    "status.downloading",
+                    &[("downloaded", &nb_downloaded), ("total", &nb_total)],
+                );
+                if bytes_per_sec > 0 {
+                    let speed = format!("{:.2}", bytes_per_sec as f32 / 1_000_000.0);
+                    status += " - ";
+                    status += &self
+                        .translator
+                        .tr_args("status.download_speed", &[("speed", &speed)]);
+                    self.update_smoothed_speed(bytes_per_sec);
+                    self.speed_graph.push(bytes_per_sec);
+                }
+                if let Some(total_bytes) = self.download_total_bytes.filter(|&b| b > 0) {
+                    self.download_progress = self.downloaded_bytes as f32 / total_bytes as f32;
+                    status += " - ";
+                    status += &self.translator.tr_args(
+                        "status.size_progress",
+                        &[
+                            ("downloaded", &human_readable_size(self.downloaded_bytes)),
+                            ("total", &human_readable_size(total_bytes)),
+                        ],
+                    );
+                    if let Some(bytes_per_sec) = self.smoothed_bytes_per_sec {
+                        let remaining_bytes = total_bytes.saturating_sub(self.downloaded_bytes);
+                        let remaining_secs = (remaining_bytes as f64 / bytes_per_sec).ceil() as u64;
+                        status += " — ";
+                        status += &format_remaining_time(&self.translator, remaining_secs);
+                    }
+                }
+                self.download_status = status;
+            }
+            PatchingStatus::PatchSelectionRequired(patches) => {
+                self.download_status = self.translator.tr("status.waiting_for_selection").to_string();
+                self.patch_selection_panel = Some(PatchSelectionPanel::new(patches));
+            }
+            // Handled by `restart_elevated`, since the window has to be closed
+            PatchingStatus::ElevationRequired => {}
+            PatchingStatus::GameClientRunning => {
+                self.download_status = self.translator.tr("status.waiting_for_game_exit").to_string();
+                self.file_status = None;
+            }
+            PatchingStatus::FilesLocked => {
+                self.download_status = self.translator.tr("status.waiting_for_locked_files").to_string();
+                self.file_status = None;
+            }
+            PatchingStatus::PatchesDownloaded(patch_count) => {
+                self.set_patching_in_progress(false);
+                self.download_progress = 0.0;
+                self.file_status = None;
+                self.reset_download_estimation();
+                self.download_status = self
+                    .translator
+                    .tr_args("status.patches_downloaded", &[("count", &patch_count)]);
+                self.append_download_summary();
+            }
+            PatchingStatus::PatchResults(results) => {
+                self.patch_results = results;
+            }
+            PatchingStatus::UnverifiedPatches(file_names) => {
+                self.unverified_patches = file_names;
+            }
+            PatchingStatus::ReleaseNotes(notes) => {
+                self.release_notes_panel = Some(ReleaseNotesPanel::new(notes));
+            }
+            PatchingStatus::OrphanedFilesFound(files) => {
+                self.orphaned_files_panel = Some(OrphanedFilesPanel::new(files));
+            }
+            PatchingStatus::OrphanedFilesDeleted(file_count, freed_bytes) => {
+                let message = self.translator.tr_args(
+                    "status.orphaned_files_deleted",
+                    &[("count", &file_count), ("size", &human_readable_size(freed_bytes))],
+                );
+                if let Some(storage_view) = &mut self.storage_view {
+                    storage_view.set_message(message.clone());
+                }
+                self.download_status = message;
+            }
+            PatchingStatus::DownloadSizeEstimated(total_bytes) => {
+                self.download_total_bytes = Some(total_bytes);
+            }
+            PatchingStatus::FileDownloadInProgress {
+                file_name,
+                downloaded_bytes,
+                total_bytes,
+                overall_downloaded_bytes,
+            } => {
+                self.downloaded_bytes = overall_downloaded_bytes;
+                let mut status = if total_bytes > 0 {
+                    self.file_progress = downloaded_bytes as f32 / total_bytes as f32;
+                    self.translator.tr_args(
+                        "status.file_download",
+                        &[
+                            ("file_name", &file_name),
+                            ("downloaded", &human_readable_size(downloaded_bytes)),
+                            ("total", &human_readable_size(total_bytes)),
+                        ],
+                    )
                 } else {
-                    String::new()
+                    // Content length is unknown
+                    self.file_progress = 0.0;
+                    self.translator.tr_args(
+                        "status.file_download_unknown_size",
+                        &[
+                            ("file_name", &file_name),
+                            ("downloaded", &human_readable_size(downloaded_bytes)),
+                        ],
+                    )
                 };
-                self.download_status = format!("Downloading: {}/{} {}", nb_downloaded, nb_total, speed);
+                status += " - ";
+                status += &self.translator.tr_args(
+                    "status.overall_downloaded",
+                    &[("downloaded", &human_readable_size(overall_downloaded_bytes))],
+                );
+                self.file_status = Some(status);
             }
             PatchingStatus::InstallationInProgress(nb_installed, nb_total) => {
+                self.set_patching_in_progress(true);
+                self.applying_patches = nb_installed < nb_total;
                 self.download_progress = (nb_installed as f32) / (nb_total as f32);
-                self.download_status = format!("Installing: {}/{}", nb_installed, nb_total);
+                self.file_status = None;
+                self.download_status = self.translator.tr_args(
+                    "status.installing",
+                    &[("installed", &nb_installed), ("total", &nb_total)],
+                );
+            }
+            PatchingStatus::UpdatesAvailable(patch_count) => {
+                self.game_up_to_date = patch_count == 0;
+                if !self.patching_in_progress {
+                    self.download_status = self
+                        .translator
+                        .tr_args("status.updates_available", &[("count", &patch_count)]);
+                }
             }
             PatchingStatus::ManualPatchApplied(name) => {
+                self.set_patching_in_progress(false);
                 self.download_progress = 0.0;
-                self.download_status = format!("Patch applied: {}", name);
+                self.download_status = self
+                    .translator
+                    .tr_args("status.manual_patch_applied", &[("name", &name)]);
+            }
+            // Handled by `start_installed_patcher`, since the window has to be closed
+            PatchingStatus::GameInstalled(patcher_path) => {
+                self.set_patching_in_progress(false);
+                self.download_progress = 0.0;
+                self.file_status = None;
+                self.reset_download_estimation();
+                let install_directory = patcher_path.parent().unwrap_or(&patcher_path);
+                self.download_status = self
+                    .translator
+                    .tr_args("status.game_installed", &[("path", &install_directory.display())]);
+            }
+            PatchingStatus::BundleExported(bundle_path) => {
+                self.set_patching_in_progress(false);
+                self.download_progress = 0.0;
+                self.file_status = None;
+                self.reset_download_estimation();
+                self.download_status = self
+                    .translator
+                    .tr_args("status.bundle_exported", &[("path", &bundle_path.display())]);
+            }
+            PatchingStatus::BundleApplied(patch_count) => {
+                self.set_patching_in_progress(false);
+                self.download_progress = 0.0;
+                self.file_status = None;
+                self.reset_download_estimation();
+                self.download_status = self
+                    .translator
+                    .tr_args("status.bundle_applied", &[("count", &patch_count)]);
+            }
+            PatchingStatus::PatchServersProbed(health) => {
+                if let Some(patch_servers_view) = &mut self.patch_servers_view {
+                    patch_servers_view.set_health(health);
+                }
+            }
+            PatchingStatus::Cancelled(progress) => {
+                self.set_patching_in_progress(false);
+                self.download_progress = 0.0;
+                self.file_status = None;
+                self.reset_download_estimation();
+                self.download_status = self.translator.tr_args(
+                    "status.cancelled",
+                    &[
+                        ("downloaded", &progress.downloaded_patches),
+                        ("applied", &progress.applied_patches),
+                        ("total", &progress.total_patches),
+                    ],
+                );
+            }
+            PatchingStatus::DownloadSummary(progress) => {
+                self.download_summary = Some(progress);
+            }
+            PatchingStatus::UpdateRunningElsewhere => {
+                self.set_patching_in_progress(true);
+                self.update_running_elsewhere = true;
+                self.download_progress = 0.0;
+                self.file_status = None;
+                self.reset_download_estimation();
+                self.error = None;
+                self.download_status = self.translator.tr("status.update_running_elsewhere").to_string();
+            }
+            PatchingStatus::GrfRepaired(grf_name, rebuilt) => {
+                self.set_patching_in_progress(false);
+                self.download_progress = 0.0;
+                self.file_status = None;
+                self.reset_download_estimation();
+                let message_id = if rebuilt { "status.grf_rebuilt" } else { "status.grf_intact" };
+                let message = self.translator.tr_args(message_id, &[("name", &grf_name)]);
+                if let Some(grf_browser_view) = &mut self.grf_browser_view {
+                    grf_browser_view.set_repair_message(message.clone());
+                }
+                self.download_status = message;
+            }
+        }
+    }
+
+    /// Updates the exponential moving average of the download speed, to
+    /// avoid a jumpy ETA.
+    fn update_smoothed_speed(&mut self, bytes_per_sec: u64) {
+        const SMOOTHING_FACTOR: f64 = 0.3;
+        let bytes_per_sec = bytes_per_sec as f64;
+        self.smoothed_bytes_per_sec = Some(match self.smoothed_bytes_per_sec {
+            None => bytes_per_sec,
+            Some(smoothed) => {
+                SMOOTHING_FACTOR * bytes_per_sec + (1.0 - SMOOTHING_FACTOR) * smoothed
             }
+        });
+    }
+
+    /// Tells how much the update which just ended downloaded, and how long
+    /// it took.
+    fn append_download_summary(&mut self) {
+        if let Some(progress) = self.download_summary.take() {
+            self.download_status += " - ";
+            self.download_status += &self.translator.tr_args(
+                "status.download_summary",
+                &[
+                    ("size", &human_readable_size(progress.downloaded_bytes)),
+                    ("duration", &human_readable_duration(progress.elapsed_ms)),
+                ],
+            );
         }
     }
 
+    fn reset_download_estimation(&mut self) {
+        self.download_total_bytes = None;
+        self.downloaded_bytes = 0;
+        self.smoothed_bytes_per_sec = None;
+        self.speed_graph.clear();
+    }
+
     pub fn set_patching_in_progress(&mut self, value: bool) {
         self.patching_in_progress = value;
+        if !value {
+            self.cancel_requested = false;
+            self.update_running_elsewhere = false;
+            self.applying_patches = false;
+            self.cancel_confirmation = false;
+        } else {
+            self.download_summary = None;
+        }
+    }
+
+    /// Cancels the update, once users have confirmed it if a patch is being
+    /// applied.
+    fn request_cancellation(&mut self) {
+        if self.applying_patches {
+            self.cancel_confirmation = true;
+        } else {
+            self.cancel_update();
+        }
+    }
+
+    /// Cancels the update once the patch being applied has been applied,
+    /// or right away if cancellation has already been requested.
+    fn cancel_update(&mut self) {
+        let command = match self.cancel_requested {
+            false => PatcherCommand::CancelUpdate,
+            true => PatcherCommand::ForceCancelUpdate,
+        };
+        self.cancel_requested = true;
+        let _ = self.patching_thread_tx.send(command);
+    }
+
+    fn show_window(&mut self, ctx: &egui::Context) {
+        self.hidden_to_tray = false;
+        ctx.send_viewport_cmd(egui::ViewportCommand::Visible(true));
+        ctx.send_viewport_cmd(egui::ViewportCommand::Minimized(false));
+        ctx.send_viewport_cmd(egui::ViewportCommand::Focus);
+    }
+
+    /// Hides the window to the tray, or minimizes it if there's no tray icon.
+    fn hide_window(&mut self, ctx: &egui::Context) {
+        if self.system_tray.is_some() {
+            self.hidden_to_tray = true;
+            ctx.send_viewport_cmd(egui::ViewportCommand::Visible(false));
+        } else {
+            ctx.send_viewport_cmd(egui::ViewportCommand::Minimized(true));
+        }
+    }
+
+    /// Brings the window back once the program started with `return_on_exit`
+    /// has exited.
+    fn process_program_exit(&mut self, ctx: &egui::Context) {
+        let check_for_updates = match self.program_exit_rx.as_ref().map(|rx| rx.try_recv()) {
+            Some(Ok(check_for_updates)) => check_for_updates,
+            Some(Err(mpsc::TryRecvError::Disconnected)) => {
+                self.program_exit_rx = None;
+                return;
+            }
+            Some(Err(mpsc::TryRecvError::Empty)) | None => return,
+        };
+        self.program_exit_rx = None;
+        self.show_window(ctx);
+        if check_for_updates && !self.patching_in_progress {
+            let _ = self.patching_thread_tx.send(PatcherCommand::CheckForUpdates);
+        }
+    }
+
+    fn process_tray_actions(&mut self, ctx: &egui::Context) {
+        while let Some(action) = self.system_tray.as_ref().and_then(SystemTray::try_recv_action) {
+            match action {
+                TrayAction::ShowWindow => self.show_window(ctx),
+                TrayAction::Patcher(command) => {
+                    let _ = self.patching_thread_tx.send(command);
+                }
+                TrayAction::OpenGameFolder => open_game_folder(),
+                TrayAction::OpenLogFile => open_log_file(),
+                TrayAction::Quit => {
+                    self.quit_requested = true;
+                    ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+                }
+            }
+        }
+        // Quitting from the menu bar (Cmd+Q) quits like other macOS apps do
+        let cmd_q_pressed = ctx.input(|i| i.modifiers.mac_cmd && i.key_pressed(egui::Key::Q));
+        if cfg!(target_os = "macos") && cmd_q_pressed {
+            self.quit_requested = true;
+            ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+        }
+        let can_hide_to_tray = self.system_tray.is_some() && !self.hidden_to_tray;
+
+        // Closing the window only hides it, unless asked from the tray's menu
+        // or the menu bar
+        let close_requested = ctx.input(|i| i.viewport().close_requested());
+        if close_requested
+            && can_hide_to_tray
+            && !self.quit_requested
+            && self.patcher_config.window.close_to_tray
+        {
+            self.hidden_to_tray = true;
+            ctx.send_viewport_cmd(egui::ViewportCommand::CancelClose);
+            ctx.send_viewport_cmd(egui::ViewportCommand::Visible(false));
+            return;
+        }
+
+        let minimized = ctx.input(|i| i.viewport().minimized.unwrap_or(false));
+        if minimized && can_hide_to_tray && self.patcher_config.tray.minimize_to_tray {
+            self.hidden_to_tray = true;
+            ctx.send_viewport_cmd(egui::ViewportCommand::Visible(false));
+        }
+    }
+
+    fn is_launch_button_enabled(&self, button: &LaunchButtonConfiguration) -> bool {
+        (self.game_up_to_date || !button.block_until_patched)
+            && self.agreement_panel.is_none()
+            && !self.is_login_required(button)
+            && !self.is_blocked_by_maintenance(button)
+    }
+
+    fn is_blocked_by_maintenance(&self, button: &LaunchButtonConfiguration) -> bool {
+        button.block_during_maintenance && self.maintenance.maintenance
+    }
+
+    /// Keeps track of the maintenance status, checking for updates once
+    /// maintenance is over since patches are often released then.
+    fn process_maintenance_statuses(&mut self) {
+        let rx = match &self.maintenance_rx {
+            Some(rx) => rx,
+            None => return,
+        };
+        while let Ok(status) = rx.try_recv() {
+            let maintenance_lifted = self.maintenance.maintenance && !status.maintenance;
+            self.maintenance = status;
+            if maintenance_lifted && !self.patching_in_progress {
+                let _ = self.patching_thread_tx.send(PatcherCommand::CheckForUpdates);
+            }
+        }
+    }
+
+    fn is_login_required(&self, button: &LaunchButtonConfiguration) -> bool {
+        button.pass_session_token
+            && self
+                .account_panel
+                .as_ref()
+                .is_some_and(|account_panel| account_panel.session_token().is_none())
+    }
+
+    /// Returns the values of the placeholders usable in launch arguments.
+    ///
+    /// The session's values take precedence over the operator's.
+    fn launch_variables(&self) -> HashMap<String, String> {
+        let mut variables = self.patcher_config.variables.values.clone();
+        variables.extend(self.remote_variables.clone());
+        if let Some(account_panel) = &self.account_panel {
+            if let Some(token) = account_panel.session_token() {
+                variables.insert("token".to_string(), token.to_string());
+            }
+            if let Some(username) = account_panel.username() {
+                variables.insert("username".to_string(), username.to_string());
+            }
+        }
+        variables
+    }
+
+    /// Returns the path and arguments to launch `button` with, once their
+    /// placeholders have been replaced.
+    fn resolve_launch_target(
+        &self,
+        button: &LaunchButtonConfiguration,
+    ) -> Result<(String, Vec<String>)> {
+        let variables = self.launch_variables();
+        let mut arguments = button
+            .arguments
+            .iter()
+            .map(|argument| expand_template(argument, &variables))
+            .collect::<Result<Vec<_>>>()?;
+        if let Some(account_config) = &self.patcher_config.account {
+            if button.pass_session_token {
+                arguments.push(expand_template(&account_config.token_argument, &variables)?);
+            }
+        }
+        // Executables' paths are left untouched, since they're used to watch
+        // the processes
+        let path = if button.is_url() {
+            expand_template(&button.path, &variables)?
+        } else {
+            button.path.clone()
+        };
+        let arguments = self
+            .script_hooks
+            .before_launch(&button.label, &path, arguments);
+        Ok((path, arguments))
+    }
+
+    /// Remembers which account's password has been stored in the keychain.
+    fn set_remembered_account(&mut self, account: Option<String>) {
+        self.user_settings.account = account;
+        if let Err(e) = save_user_settings(&self.user_settings) {
+            log::warn!("Failed to save user settings: {:#}", e);
+        }
+    }
+
+    /// Handles the keyboard shortcuts: Enter plays, F5 starts updating and
+    /// Esc cancels whatever is going on.
+    fn process_keyboard_shortcuts(&mut self, ctx: &egui::Context) {
+        // Focused widgets (e.g. buttons reached with Tab) handle keys themselves
+        if ctx.memory(|m| m.focus().is_some()) {
+            return;
+        }
+        let (enter_pressed, f5_pressed, escape_pressed) = ctx.input_mut(|i| {
+            (
+                i.consume_key(egui::Modifiers::NONE, egui::Key::Enter),
+                i.consume_key(egui::Modifiers::NONE, egui::Key::F5),
+                i.consume_key(egui::Modifiers::NONE, egui::Key::Escape),
+            )
+        });
+        if escape_pressed {
+            if self.settings_panel.take().is_some()
+                || self.release_notes_panel.take().is_some()
+                || self.orphaned_files_panel.take().is_some()
+            {
+                return;
+            }
+            if self.cancel_confirmation {
+                self.cancel_confirmation = false;
+            } else if self.patch_selection_panel.take().is_some() {
+                self.cancel_update();
+            } else if self.patching_in_progress {
+                self.request_cancellation();
+            }
+            return;
+        }
+        // Other shortcuts only apply to the main view
+        if self.settings_panel.is_some()
+            || self.patch_selection_panel.is_some()
+            || self.agreement_panel.is_some()
+            || self.release_notes_panel.is_some()
+            || self.orphaned_files_panel.is_some()
+            || self.game_client_running
+            || self.files_locked
+            || self.patcher_outdated
+            || self.current_tab != Tab::Patcher
+        {
+            return;
+        }
+        if f5_pressed && !self.patching_in_progress {
+            let _ = self.patching_thread_tx.send(PatcherCommand::StartUpdate);
+        }
+        let play_enabled = self
+            .launch_buttons
+            .first()
+            .is_some_and(|button| self.is_launch_button_enabled(button));
+        if enter_pressed && play_enabled {
+            self.on_launch_button_clicked(ctx, 0);
+        }
+    }
+
+    fn on_launch_button_clicked(&mut self, ctx: &egui::Context, button_index: usize) {
+        let button = &self.launch_buttons[button_index];
+        let res = self
+            .resolve_launch_target(button)
+            .and_then(|(path, arguments)| {
+                launch(button, &path, &arguments, &self.patcher_config.wine)
+            });
+        match res {
+            Ok(true) => {
+                if button.return_on_exit && !button.is_url() {
+                    self.program_exit_rx = Some(watch_program_exit(ctx, button));
+                    self.hide_window(ctx);
+                } else if button.exit_on_success.unwrap_or(false) {
+                    ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+                }
+            }
+            Ok(false) => log::warn!("'{}' wasn't started", button.path),
+            Err(e) => {
+                log::error!("{:#}", e);
+                self.error = Some(ErrorReport::from(&e));
+            }
+        }
+    }
+
+    /// Hands the update over to an elevated instance of the patcher.
+    fn restart_elevated(&mut self, ctx: &egui::Context) {
+        let res = restart_elevated().and_then(|elevated| {
+            if elevated {
+                Ok(())
+            } else {
+                Err(anyhow!("Administrator privileges are required to update the game"))
+            }
+        });
+        match res {
+            Ok(()) => {
+                // Don't hide to the tray
+                self.quit_requested = true;
+                ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+            }
+            Err(e) => {
+                log::error!("{:#}", e);
+                self.error = Some(ErrorReport::from(&e));
+            }
+        }
+    }
+
+    /// Lets users pick the directory the game is installed in.
+    fn select_install_directory(&mut self) {
+        let title = self.translator.tr("installer.select_directory");
+        if let Some(install_directory) = tinyfiledialogs::select_folder_dialog(title, "") {
+            let _ = self
+                .patching_thread_tx
+                .send(PatcherCommand::Install(PathBuf::from(install_directory)));
+        }
+    }
+
+    /// Lets users pick THOR or ZIP files, which are applied one after the
+    /// other.
+    fn select_manual_patches(&mut self) {
+        let title = self.translator.tr("manual_patch.select_files");
+        let file_type = self.translator.tr("manual_patch.file_type");
+        let selected_files = tinyfiledialogs::open_file_dialog_multi(
+            title,
+            "",
+            Some((&["*.thor", "*.zip"], file_type)),
+        );
+        let mut patch_file_paths = match selected_files {
+            Some(paths) => paths,
+            None => {
+                log::info!("No patch selected");
+                return;
+            }
+        };
+        // Patch file names usually begin with a date or an index, so this
+        // should match the order in which they were released
+        patch_file_paths.sort();
+        for patch_file_path in patch_file_paths.into_iter().map(PathBuf::from) {
+            let _ = self
+                .patching_thread_tx
+                .send(PatcherCommand::ManualPatchFile(patch_file_path));
+        }
+    }
+
+    fn select_bundle(&mut self) {
+        let title = self.translator.tr("bundle.select_file");
+        let filter = format!("*.{}", BUNDLE_FILE_EXTENSION);
+        let selected_file = tinyfiledialogs::open_file_dialog(
+            title,
+            "",
+            Some((&[filter.as_str()], self.translator.tr("bundle.file_type"))),
+        );
+        if let Some(bundle_path) = selected_file {
+            let _ = self
+                .patching_thread_tx
+                .send(PatcherCommand::ApplyBundle(PathBuf::from(bundle_path)));
+        }
+    }
+
+    /// Hands the game over to the patcher installed along with it, which
+    /// patches it.
+    fn start_installed_patcher(&mut self, ctx: &egui::Context, patcher_path: &Path) {
+        let mut arguments = vec!["--start-update".to_string()];
+        if let Some(profile) = get_profile() {
+            arguments.extend(["--profile".to_string(), profile.to_string()]);
+        }
+        if is_portable() {
+            arguments.push("--portable".to_string());
+        }
+        let options = LaunchOptions {
+            working_directory: patcher_path.parent().map(Path::to_path_buf),
+            ..Default::default()
+        };
+        match start_executable(&patcher_path.to_string_lossy(), &arguments, &options) {
+            Ok(_) => {
+                // Don't hide to the tray
+                self.quit_requested = true;
+                ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+            }
+            Err(e) => {
+                log::error!("Failed to start the installed patcher: {:#}", e);
+                self.error = Some(ErrorReport::from(&e));
+            }
+        }
+    }
+
+    /// Lets users close the game, which has to be done before patching it.
+    fn show_game_client_running_window(&mut self, ctx: &egui::Context) {
+        let mut command = None;
+        egui::Window::new(self.translator.tr("game_client_running.title"))
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                ui.label(self.translator.tr("game_client_running.message"));
+                ui.add_space(10.0);
+                ui.horizontal(|ui| {
+                    if ui.button(self.translator.tr("button.close_game_and_continue")).clicked() {
+                        command = Some(PatcherCommand::CloseGameClient);
+                    }
+                    if ui.button(self.translator.tr("button.cancel")).clicked() {
+                        command = Some(PatcherCommand::CancelUpdate);
+                    }
+                });
+            });
+        if let Some(command) = command {
+            self.game_client_running = false;
+            let _ = self.patching_thread_tx.send(command);
+        }
+    }
+
+    /// Makes users confirm that they want to cancel the update while a patch
+    /// is being applied, telling them how the patch will be left.
+    fn show_cancel_confirmation_window(&mut self, ctx: &egui::Context) {
+        let (message_id, button_id) = match self.cancel_requested {
+            false => ("cancel_confirmation.message", "button.cancel_update"),
+            true => ("cancel_confirmation.force_message", "button.force_cancel_update"),
+        };
+        let (mut confirmed, mut dismissed) = (false, false);
+        egui::Window::new(self.translator.tr("cancel_confirmation.title"))
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                ui.label(self.translator.tr(message_id));
+                ui.add_space(10.0);
+                ui.horizontal(|ui| {
+                    confirmed = ui.button(self.translator.tr(button_id)).clicked();
+                    dismissed = ui.button(self.translator.tr("button.keep_updating")).clicked();
+                });
+            });
+        if confirmed {
+            self.cancel_confirmation = false;
+            self.cancel_update();
+        } else if dismissed {
+            self.cancel_confirmation = false;
+        }
+    }
+
+    /// Tells users that the patch server requires a more recent patcher, so
+    /// that they don't keep retrying.
+    fn show_patcher_outdated_window(&mut self, ctx: &egui::Context) {
+        let mut closed = false;
+        egui::Window::new(self.translator.tr("patcher_outdated.title"))
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                ui.label(self.translator.tr("patcher_outdated.message"));
+                if let Some(report) = &self.error {
+                    ui.label(report.summary());
+                }
+                ui.add_space(10.0);
+                closed = ui.button(self.translator.tr("button.close")).clicked();
+            });
+        if closed {
+            self.patcher_outdated = false;
+        }
+    }
+
+    /// Lets users retry right away once they've closed the game, instead of
+    /// waiting for the next automatic retry.
+    fn show_files_locked_window(&mut self, ctx: &egui::Context) {
+        let mut command = None;
+        egui::Window::new(self.translator.tr("files_locked.title"))
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                ui.label(self.translator.tr("files_locked.message"));
+                ui.add_space(10.0);
+                ui.horizontal(|ui| {
+                    if ui.button(self.translator.tr("button.retry")).clicked() {
+                        command = Some(PatcherCommand::RetryPatch);
+                    }
+                    if ui.button(self.translator.tr("button.cancel")).clicked() {
+                        command = Some(PatcherCommand::CancelUpdate);
+                    }
+                });
+            });
+        if let Some(command) = command {
+            self.files_locked = false;
+            let _ = self.patching_thread_tx.send(command);
+        }
+    }
+
+    /// Keeps track of the window's geometry, which is saved on exit.
+    fn track_window_geometry(&mut self, ctx: &egui::Context) {
+        let (outer_rect, inner_rect, minimized, maximized) = ctx.input(|i| {
+            let viewport = i.viewport();
+            (
+                viewport.outer_rect,
+                viewport.inner_rect,
+                viewport.minimized.unwrap_or(false),
+                viewport.maximized.unwrap_or(false),
+            )
+        });
+        // Minimized and hidden windows have meaningless positions
+        if minimized || self.hidden_to_tray {
+            return;
+        }
+        let (outer_rect, inner_rect) = match (outer_rect, inner_rect) {
+            (Some(outer_rect), Some(inner_rect)) => (outer_rect, inner_rect),
+            _ => return,
+        };
+        if maximized {
+            // Keep the "normal" geometry, to restore it when unmaximizing
+            if let Some(geometry) = &mut self.window_geometry {
+                geometry.maximized = true;
+            }
+            return;
+        }
+        self.window_geometry = Some(WindowGeometry {
+            x: outer_rect.min.x,
+            y: outer_rect.min.y,
+            width: inner_rect.width(),
+            height: inner_rect.height(),
+            maximized: false,
+        });
+    }
+
+    /// Checks which patch servers are available, unless the patching thread
+    /// is busy.
+    fn probe_patch_servers(&mut self) {
+        if self.patching_in_progress {
+            return;
+        }
+        if let Some(patch_servers_view) = &mut self.patch_servers_view {
+            patch_servers_view.set_probing();
+        }
+        let _ = self.patching_thread_tx.send(PatcherCommand::ProbePatchServers);
+    }
+
+    /// Persists the settings chosen by the user and applies them right away.
+    fn apply_user_settings(&mut self, user_settings: UserSettings) {
+        self.user_settings = user_settings.clone();
+        if let Err(e) = save_user_settings(&user_settings) {
+            log::error!("Failed to save user settings: {:#}", e);
+            self.error = Some(ErrorReport::from(&e));
+        }
+        let previous_language = self.patcher_config.localization.language.clone();
+        user_settings.apply_to(&mut self.patcher_config);
+        if self.patcher_config.localization.language != previous_language {
+            self.translator = Translator::new(&self.patcher_config.localization);
+            if !self.patching_in_progress {
+                self.download_status = self.translator.tr("status.ready").to_string();
+            }
+        }
+        let _ = self
+            .patching_thread_tx
+            .send(PatcherCommand::ApplySettings(user_settings));
+    }
+
+    /// Handles the requests of the instances of the patcher started while
+    /// this one is running.
+    fn process_instance_messages(&mut self, ctx: &egui::Context) {
+        let messages: Vec<InstanceMessage> = match &self.instance_message_rx {
+            Some(rx) => rx.try_iter().collect(),
+            None => return,
+        };
+        for message in messages {
+            let command = match message {
+                InstanceMessage::ShowWindow => {
+                    self.show_window(ctx);
+                    continue;
+                }
+                InstanceMessage::StartUpdate => PatcherCommand::StartUpdate,
+                InstanceMessage::ManualPatchFile { path } => PatcherCommand::ManualPatchFile(path),
+            };
+            let _ = self.patching_thread_tx.send(command);
+        }
+    }
+
+    /// Sends the patches and bundles dropped onto the window to the patching
+    /// thread.
+    fn process_dropped_files(&mut self, ctx: &egui::Context) {
+        let dropped_files = ctx.input(|i| i.raw.dropped_files.clone());
+        // Dropping files is equivalent to clicking "Manual Patch"
+        if self.patching_in_progress {
+            return;
+        }
+        for patch_file_path in dropped_files.into_iter().filter_map(|file| file.path) {
+            if is_patch_file(&patch_file_path) {
+                let _ = self
+                    .patching_thread_tx
+                    .send(PatcherCommand::ManualPatchFile(patch_file_path));
+            } else if has_extension(&patch_file_path, BUNDLE_FILE_EXTENSION) {
+                let _ = self
+                    .patching_thread_tx
+                    .send(PatcherCommand::ApplyBundle(patch_file_path));
+            } else {
+                let file_name = patch_file_path
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .to_string();
+                self.error = Some(ErrorReport::new(
+                    self.translator
+                        .tr_args("error.invalid_patch_file", &[("file_name", &file_name)]),
+                ));
+            }
+        }
+    }
+
+    /// Shows a desktop notification for statuses worth knowing about, when
+    /// the user isn't looking at the window.
+    fn notify_status(&self, ctx: &egui::Context, status: &PatchingStatus) {
+        let focused = ctx.input(|i| i.viewport().focused.unwrap_or(true));
+        let minimized = ctx.input(|i| i.viewport().minimized.unwrap_or(false));
+        if focused && !minimized && !self.hidden_to_tray {
+            return;
+        }
+        let (summary, body) = match status {
+            // `Ready` is also sent when idle, only notify at the end of an update
+            PatchingStatus::Ready if self.patching_in_progress => (
+                self.translator.tr("notification.patching_finished"),
+                self.translator
+                    .tr("notification.patching_finished_body")
+                    .to_string(),
+            ),
+            PatchingStatus::Error(report) => (
+                self.translator.tr("notification.patching_failed"),
+                self.translator.tr(report.summary()).to_string(),
+            ),
+            PatchingStatus::ManualPatchApplied(name) => (
+                self.translator.tr("notification.manual_patch_applied"),
+                self.translator
+                    .tr_args("status.manual_patch_applied", &[("name", name)]),
+            ),
+            PatchingStatus::GameClientRunning => (
+                self.translator.tr("notification.game_client_running"),
+                self.translator.tr("game_client_running.message").to_string(),
+            ),
+            PatchingStatus::FilesLocked => (
+                self.translator.tr("notification.game_client_running"),
+                self.translator.tr("files_locked.message").to_string(),
+            ),
+            PatchingStatus::UpdatesAvailable(patch_count) if *patch_count > 0 => (
+                self.translator.tr("notification.updates_available"),
+                self.translator
+                    .tr_args("status.updates_available", &[("count", patch_count)]),
+            ),
+            _ => return,
+        };
+        show_notification(&self.patcher_config.window.title, summary, &body);
     }
 }
 
 impl eframe::App for NativeUi {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.process_tray_actions(ctx);
+        self.process_program_exit(ctx);
+        self.process_instance_messages(ctx);
+        if let Some(rx) = &self.remote_variables_rx {
+            if let Ok(remote_variables) = rx.try_recv() {
+                self.remote_variables = remote_variables;
+            }
+        }
+        if let Some(rx) = &self.motd_rx {
+            if let Ok(motd) = rx.try_recv() {
+                self.motd = Some(motd);
+            }
+        }
+        self.process_maintenance_statuses();
+        self.process_dropped_files(ctx);
+        self.track_window_geometry(ctx);
+        self.process_keyboard_shortcuts(ctx);
+
         // Process any pending status updates
         while let Ok(status) = self.status_rx.try_recv() {
+            if let PatchingStatus::ElevationRequired = status {
+                self.restart_elevated(ctx);
+            }
+            if let PatchingStatus::GameInstalled(patcher_path) = &status {
+                self.start_installed_patcher(ctx, patcher_path);
+            }
+            self.notify_status(ctx, &status);
             self.set_patching_status(status);
         }
 
-        egui::CentralPanel::default().show(ctx, |ui| {
-            ui.heading(&self.patcher_config.window.title);
-            ui.add_space(10.0);
+        if let Some(settings_panel) = &mut self.settings_panel {
+            match settings_panel.show(ctx, &self.translator) {
+                Some(SettingsPanelAction::Save(user_settings)) => {
+                    self.settings_panel = None;
+                    self.apply_user_settings(user_settings);
+                }
+                Some(SettingsPanelAction::Cancel) => self.settings_panel = None,
+                None => {}
+            }
+        }
+
+        if let Some(agreement_panel) = &mut self.agreement_panel {
+            match agreement_panel.show(ctx, &self.translator) {
+                Some(AgreementAction::Accept) => {
+                    if let Err(e) = accept_agreement(agreement_panel.agreement()) {
+                        log::error!("Failed to record the agreement's acceptance: {:#}", e);
+                    }
+                    self.agreement_panel = None;
+                    // Updates weren't started automatically
+                    if self.patcher_config.patching.auto_start {
+                        let _ = self.patching_thread_tx.send(PatcherCommand::StartUpdate);
+                    }
+                }
+                Some(AgreementAction::Decline) => {
+                    ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+                }
+                None => {}
+            }
+        }
+
+        if let Some(patch_selection_panel) = &mut self.patch_selection_panel {
+            let command = match patch_selection_panel.show(ctx, &self.translator) {
+                Some(PatchSelectionAction::Confirm { selection, apply }) => {
+                    Some(PatcherCommand::SelectPatches { selection, apply })
+                }
+                Some(PatchSelectionAction::Cancel) => Some(PatcherCommand::CancelUpdate),
+                None => None,
+            };
+            if let Some(command) = command {
+                self.patch_selection_panel = None;
+                let _ = self.patching_thread_tx.send(command);
+            }
+        }
+
+        if let Some(release_notes_panel) = &self.release_notes_panel {
+            if release_notes_panel.show(ctx, &self.translator) {
+                self.release_notes_panel = None;
+            }
+        }
+
+        if let Some(orphaned_files_panel) = &mut self.orphaned_files_panel {
+            match orphaned_files_panel.show(ctx, &self.translator) {
+                Some(OrphanedFilesAction::Delete(paths)) => {
+                    self.orphaned_files_panel = None;
+                    let _ = self.patching_thread_tx.send(PatcherCommand::DeleteOrphanedFiles(Some(paths)));
+                }
+                Some(OrphanedFilesAction::Cancel) => self.orphaned_files_panel = None,
+                None => {}
+            }
+        }
+
+        if self.game_client_running {
+            self.show_game_client_running_window(ctx);
+        }
+        if self.files_locked {
+            self.show_files_locked_window(ctx);
+        }
+        if self.cancel_confirmation {
+            self.show_cancel_confirmation_window(ctx);
+        }
+        if self.patcher_outdated {
+            self.show_patcher_outdated_window(ctx);
+        }
+
+        let mut panel_frame = egui::Frame::central_panel(&ctx.style());
+        if let Some(skin) = self.skin.as_ref().filter(|skin| skin.has_background()) {
+            skin.paint_background(ctx);
+            panel_frame = panel_frame.fill(egui::Color32::TRANSPARENT);
+        }
+        egui::CentralPanel::default().frame(panel_frame).show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.heading(&self.patcher_config.window.title);
+                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                    // Frameless windows must at least be closable
+                    let needs_caption_buttons =
+                        self.skin.as_ref().is_some_and(|skin| !skin.has_close_button());
+                    if needs_caption_buttons {
+                        let close_button = ui.button("\u{274c}");
+                        set_accessible_label(&close_button, self.translator.tr("button.close"));
+                        if close_button.clicked() {
+                            ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+                        }
+                        let minimize_button = ui.button("\u{1f5d5}");
+                        set_accessible_label(&minimize_button, self.translator.tr("button.minimize"));
+                        if minimize_button.clicked() {
+                            ctx.send_viewport_cmd(egui::ViewportCommand::Minimized(true));
+                        }
+                    }
+                    let log_file_label = self.translator.tr("button.open_log_file");
+                    let log_file_button = ui.button("\u{1f4c4}").on_hover_text(log_file_label);
+                    set_accessible_label(&log_file_button, log_file_label);
+                    if log_file_button.clicked() {
+                        open_log_file();
+                    }
+                    let game_folder_label = self.translator.tr("button.open_game_folder");
+                    let game_folder_button = ui.button("\u{1f4c1}").on_hover_text(game_folder_label);
+                    set_accessible_label(&game_folder_button, game_folder_label);
+                    if game_folder_button.clicked() {
+                        open_game_folder();
+                    }
+                    // Changing settings in the middle of an update would be confusing
+                    let can_open_settings = !self.patching_in_progress && self.settings_panel.is_none();
+                    let settings_label = self.translator.tr("settings.title");
+                    let settings_button = ui
+                        .add_enabled(can_open_settings, egui::Button::new("\u{2699}"))
+                        .on_hover_text(settings_label);
+                    set_accessible_label(&settings_button, settings_label);
+                    if settings_button.clicked() {
+                        self.settings_panel =
+                            Some(SettingsPanel::new(&self.patcher_config, &self.user_settings));
+                    }
+                });
+            });
+            ui.horizontal(|ui| {
+                ui.selectable_value(&mut self.current_tab, Tab::Patcher, self.translator.tr("tab.patcher"));
+                let history_tab =
+                    ui.selectable_value(&mut self.current_tab, Tab::History, self.translator.tr("tab.history"));
+                if history_tab.clicked() {
+                    // Reload the history since patches might have been applied
+                    self.history_view = Some(HistoryView::load());
+                }
+                let storage_tab =
+                    ui.selectable_value(&mut self.current_tab, Tab::Storage, self.translator.tr("tab.storage"));
+                if storage_tab.clicked() {
+                    self.storage_view = Some(StorageView::load());
+                }
+                let grf_browser_tab =
+                    ui.selectable_value(&mut self.current_tab, Tab::GrfBrowser, self.translator.tr("tab.grf_browser"));
+                if grf_browser_tab.clicked() && self.grf_browser_view.is_none() {
+                    self.grf_browser_view = Some(GrfBrowserView::new(&self.patcher_config.client.default_grf_name));
+                }
+                let patch_servers_tab = ui.selectable_value(
+                    &mut self.current_tab,
+                    Tab::PatchServers,
+                    self.translator.tr("tab.patch_servers"),
+                );
+                if patch_servers_tab.clicked() && self.patch_servers_view.is_none() {
+                    let server_names =
+                        self.patcher_config.web.patch_servers.iter().map(|server| server.name.clone()).collect();
+                    self.patch_servers_view = Some(PatchServersView::new(server_names));
+                    self.probe_patch_servers();
+                }
+            });
+            ui.separator();
+            if self.current_tab == Tab::History {
+                if let Some(history_view) = &mut self.history_view {
+                    history_view.show(ui, &self.translator);
+                }
+                return;
+            }
+            if self.current_tab == Tab::Storage {
+                if let Some(storage_view) = &mut self.storage_view {
+                    storage_view.show(ui, &self.translator, self.patching_in_progress);
+                }
+                // Leftovers can only be told apart from the base client's files
+                if self.patcher_config.cleanup.base_manifest_url.is_some() {
+                    ui.add_space(10.0);
+                    let find_button = egui::Button::new(self.translator.tr("button.find_orphaned_files"));
+                    if ui.add_enabled(!self.patching_in_progress, find_button).clicked() {
+                        let _ = self.patching_thread_tx.send(PatcherCommand::FindOrphanedFiles);
+                    }
+                }
+                return;
+            }
+            if self.current_tab == Tab::PatchServers {
+                let forced_server = self.patcher_config.web.forced_patch_server.as_deref();
+                let action = self.patch_servers_view.as_ref().and_then(|patch_servers_view| {
+                    patch_servers_view.show(ui, &self.translator, self.patching_in_progress, forced_server)
+                });
+                match action {
+                    Some(PatchServersAction::Probe) => self.probe_patch_servers(),
+                    Some(PatchServersAction::Use(server_name)) => {
+                        let user_settings = UserSettings {
+                            patch_server: server_name,
+                            ..self.user_settings.clone()
+                        };
+                        self.apply_user_settings(user_settings);
+                    }
+                    None => {}
+                }
+                return;
+            }
+            if self.current_tab == Tab::GrfBrowser {
+                if let Some(grf_browser_view) = &mut self.grf_browser_view {
+                    if let Some(grf_path) = grf_browser_view.show(ui, &self.translator, self.patching_in_progress) {
+                        let _ = self.patching_thread_tx.send(PatcherCommand::RepairGrf(grf_path));
+                    }
+                }
+                return;
+            }
+            ui.add_space(5.0);
+
+            if let Some(motd) = &self.motd {
+                show_motd(ui, motd);
+                ui.add_space(5.0);
+            }
+
+            if let Some(banner) = &mut self.banner {
+                banner.show(ui);
+                ui.add_space(5.0);
+            }
+
+            if self.maintenance.maintenance {
+                let message = match &self.maintenance.message {
+                    Some(message) => message.as_str(),
+                    None => self.translator.tr("maintenance.message"),
+                };
+                ui.label(egui::RichText::new(self.translator.tr("maintenance.title")).strong().color(egui::Color32::YELLOW));
+                ui.label(message);
+                ui.add_space(5.0);
+            }
+
+            if self.update_running_elsewhere {
+                ui.label(egui::RichText::new(self.translator.tr("status.update_running_elsewhere")).color(egui::Color32::YELLOW));
+                ui.add_space(5.0);
+            }
 
             // Progress bar
-            ui.add(egui::ProgressBar::new(self.download_progress).text(&self.download_status));
-            
-            if let Some(error) = &self.error_message {
+            add_progress_bar(ui, self.download_progress, &self.download_status);
+            if let Some(file_status) = &self.file_status {
+                add_progress_bar(ui, self.file_progress, file_status);
+            }
+            if self.patching_in_progress && !self.speed_graph.is_empty() {
+                self.speed_graph.show(ui);
+            }
+
+            let hovering_files = ctx.input(|i| !i.raw.hovered_files.is_empty());
+            if hovering_files && !self.patching_in_progress {
                 ui.add_space(5.0);
-                ui.label(egui::RichText::new(error).color(egui::Color32::RED));
+                ui.label(self.translator.tr("status.drop_patch_files"));
+            }
+
+            if let Some(error) = &self.error {
+                ui.add_space(5.0);
+                show_error_report(ui, error, &self.translator);
+            }
+            if !self.patch_results.is_empty() {
+                ui.add_space(5.0);
+                show_patch_results(ui, &self.patch_results, &self.translator);
+            }
+            if !self.unverified_patches.is_empty() {
+                ui.add_space(5.0);
+                let warning = self.translator.tr_args("status.unverified_patches", &[("count", &self.unverified_patches.len())]);
+                ui.label(egui::RichText::new(warning).color(egui::Color32::YELLOW))
+                    .on_hover_text(self.unverified_patches.join("\n"));
             }
 
             ui.add_space(10.0);
 
             // Buttons
             ui.horizontal(|ui| {
-                if ui.add_enabled(!self.patching_in_progress, egui::Button::new("Start Update")).clicked() {
+                let can_start_update = !self.patching_in_progress && self.agreement_panel.is_none();
+                if self.game_missing && ui.add_enabled(can_start_update, egui::Button::new(self.translator.tr("button.install"))).clicked() {
+                    self.select_install_directory();
+                }
+                if ui.add_enabled(can_start_update, egui::Button::new(self.translator.tr("button.start_update"))).on_hover_text("F5").clicked() {
                     let _ = self.patching_thread_tx.send(PatcherCommand::StartUpdate);
                 }
 
-                if ui.add_enabled(self.patching_in_progress, egui::Button::new("Cancel Update")).clicked() {
-                    let _ = self.patching_thread_tx.send(PatcherCommand::CancelUpdate);
+                let cancel_label = match (self.update_running_elsewhere, self.cancel_requested) {
+                    // The other instance's update can't be canceled from here
+                    (true, _) => "button.stop_following",
+                    (false, false) => "button.cancel_update",
+                    (false, true) => "button.force_cancel_update",
+                };
+                if ui.add_enabled(self.patching_in_progress, egui::Button::new(self.translator.tr(cancel_label))).on_hover_text("Esc").clicked() {
+                    self.request_cancellation();
+                }
+
+                // Only the patches which failed have to be downloaded again
+                if !self.patch_results.is_empty() && ui.add_enabled(!self.patching_in_progress, egui::Button::new(self.translator.tr("button.retry_failed"))).clicked() {
+                    let _ = self.patching_thread_tx.send(PatcherCommand::RetryFailedPatches);
                 }
 
-                if ui.add_enabled(!self.patching_in_progress, egui::Button::new("Reset Cache")).clicked() {
+                if ui.add_enabled(!self.patching_in_progress, egui::Button::new(self.translator.tr("button.reset_cache"))).clicked() {
                     let _ = self.patching_thread_tx.send(PatcherCommand::ResetCache);
                 }
 
-                if ui.add_enabled(!self.patching_in_progress, egui::Button::new("Manual Patch")).clicked() {
-                    let _ = self.patching_thread_tx.send(PatcherCommand::ManualPatch);
+                if ui.add_enabled(!self.patching_in_progress, egui::Button::new(self.translator.tr("button.manual_patch"))).clicked() {
+                    self.select_manual_patches();
+                }
+
+                // For machines which can't reach the patch servers
+                if ui.add_enabled(can_start_update, egui::Button::new(self.translator.tr("button.apply_bundle"))).clicked() {
+                    self.select_bundle();
                 }
             });
 
             ui.add_space(10.0);
 
-            // Game launch buttons
-            ui.horizontal(|ui| {
-                if ui.button("Play").clicked() {
-                    let _ = start_executable(
-                        &self.patcher_config.play.path,
-                        &self.patcher_config.play.arguments,
-                    );
+            if let Some(account_panel) = &mut self.account_panel {
+                if let Some(AccountPanelAction::RememberAccount(account)) =
+                    account_panel.show(ui, &self.translator)
+                {
+                    self.set_remembered_account(account);
                 }
+                ui.add_space(10.0);
+            }
 
-                if ui.button("Setup").clicked() {
-                    let _ = start_executable(
-                        &self.patcher_config.setup.path,
-                        &self.patcher_config.setup.arguments,
-                    );
+            // Game launch buttons
+            let mut clicked_button = None;
+            ui.horizontal_wrapped(|ui| {
+                for (i, button) in self.launch_buttons.iter().enumerate() {
+                    let label = self.translator.tr(&button.label);
+                    let text = match &button.icon {
+                        Some(icon) => format!("{} {}", icon, label),
+                        None => label.to_string(),
+                    };
+                    let disabled_hover_text = if self.is_login_required(button) {
+                        self.translator.tr("status.login_required")
+                    } else if self.is_blocked_by_maintenance(button) {
+                        self.translator.tr("maintenance.title")
+                    } else {
+                        self.translator.tr("status.waiting_for_update")
+                    };
+                    let mut response = ui
+                        .add_enabled(self.is_launch_button_enabled(button), egui::Button::new(text))
+                        .on_disabled_hover_text(disabled_hover_text);
+                    if i == 0 {
+                        // "Play" is the first button
+                        response = response.on_hover_text("Enter");
+                    }
+                    // Icons aren't meant to be read out loud
+                    set_accessible_label(&response, label);
+                    if response.clicked() {
+                        clicked_button = Some(i);
+                    }
                 }
             });
+            if let Some(i) = clicked_button {
+                self.on_launch_button_clicked(ctx, i);
+            }
+        });
+
+        if let Some(skin) = &self.skin {
+            skin.handle_window_interactions(ctx);
+        }
+    }
+
+    fn clear_color(&self, _visuals: &egui::Visuals) -> [f32; 4] {
+        // Transparency is only enabled for frameless windows
+        if self.patcher_config.window.transparent && self.skin.is_some() {
+            egui::Rgba::TRANSPARENT.to_array()
+        } else {
+            // eframe's default
+            egui::Color32::from_rgba_unmultiplied(12, 12, 12, 180).to_normalized_gamma_f32()
+        }
+    }
+
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        let _ = self.patching_thread_tx.send(PatcherCommand::Quit);
+        // Remember the window's geometry for the next launch
+        if self.window_geometry != self.user_settings.window {
+            self.user_settings.window = self.window_geometry.take();
+            if let Err(e) = save_user_settings(&self.user_settings) {
+                log::warn!("Failed to save window geometry: {:#}", e);
+            }
+        }
+    }
+}
+
+/// Opens the game's directory (i.e. the working directory) in the file
+/// manager.
+fn open_game_folder() {
+    let res = std::env::current_dir()
+        .context("Failed to find the game directory")
+        .and_then(|game_directory| {
+            open::that(&game_directory)
+                .with_context(|| format!("Failed to open '{}'", game_directory.display()))
         });
+    if let Err(e) = res {
+        log::warn!("{:#}", e);
     }
 }
 
-pub enum PatchingStatus {
-    Ready,
-    Error(String),
-    DownloadInProgress(usize, usize, u64),
-    InstallationInProgress(usize, usize),
-    ManualPatchApplied(String),
-} 
\ No newline at end of file
+/// Shows the log file in the file manager, so that players can find it
+/// when asked for it.
+fn open_log_file() {
+    if let Err(e) = get_log_file_path().and_then(|log_file_path| reveal_in_file_manager(&log_file_path)) {
+        log::warn!("Failed to show the log file: {:#}", e);
+    }
+}
+
+/// Starts the executable or opens the URL associated with a launch button.
+///
+/// Windows executables are started through Wine on other platforms, if
+/// configured.
+fn launch(
+    button: &LaunchButtonConfiguration,
+    path: &str,
+    arguments: &[String],
+    wine: &WineConfiguration,
+) -> Result<bool> {
+    if button.is_url() {
+        open::that(path)
+            .map(|exit_status| exit_status.success())
+            .with_context(|| format!("Failed to open '{}'", button.path))
+    } else {
+        let mut options = LaunchOptions {
+            elevated: button.elevated,
+            working_directory: button.working_directory.as_ref().map(PathBuf::from),
+            environment: button.environment.clone(),
+        };
+        let res = match wine.wrap(path, arguments, &mut options.environment) {
+            Some((command, arguments)) => start_executable(&command, &arguments, &options),
+            None => start_executable(path, arguments, &options),
+        };
+        res.with_context(|| format!("Failed to start '{}'", button.path))
+    }
+}
+
+/// Fetches the launch variables advertised by the operator in the
+/// background.
+fn spawn_launch_variables_thread(
+    ctx: &egui::Context,
+    patcher_config: &PatcherConfiguration,
+) -> mpsc::Receiver<HashMap<String, String>> {
+    let (variables_tx, variables_rx) = mpsc::channel();
+    let config = patcher_config.variables.clone();
+    let ctx = ctx.clone();
+    std::thread::spawn(move || {
+        if let Err(e) = launch_variables_thread_routine(config, variables_tx) {
+            log::warn!("Failed to retrieve launch variables: {:#}", e);
+        }
+        ctx.request_repaint();
+    });
+    variables_rx
+}
+
+/// Fetches the message of the day in the background.
+fn spawn_motd_thread(
+    ctx: &egui::Context,
+    patcher_config: &PatcherConfiguration,
+) -> mpsc::Receiver<Motd> {
+    let (motd_tx, motd_rx) = mpsc::channel();
+    let config = patcher_config.motd.clone();
+    let ctx = ctx.clone();
+    std::thread::spawn(move || {
+        if let Err(e) = motd_thread_routine(config, motd_tx) {
+            log::warn!("Failed to retrieve the message of the day: {:#}", e);
+        }
+        ctx.request_repaint();
+    });
+    motd_rx
+}
+
+/// Shows the message of the day in a frame colored after its severity.
+fn show_motd(ui: &mut egui::Ui, motd: &Motd) {
+    let color = match motd.severity {
+        MotdSeverity::Info => egui::Color32::from_rgb(80, 150, 230),
+        MotdSeverity::Warning => egui::Color32::from_rgb(230, 170, 40),
+        MotdSeverity::Critical => egui::Color32::from_rgb(220, 50, 50),
+    };
+    egui::Frame::group(ui.style())
+        .stroke(egui::Stroke::new(2.0, color))
+        .show(ui, |ui| {
+            ui.set_width(ui.available_width());
+            let mut text = egui::RichText::new(&motd.message).color(color);
+            if motd.severity != MotdSeverity::Info {
+                text = text.strong();
+            }
+            ui.label(text);
+        });
+}
+
+/// Polls the maintenance status advertised by the operator in the
+/// background.
+fn spawn_maintenance_thread(
+    ctx: &egui::Context,
+    patcher_config: &PatcherConfiguration,
+) -> mpsc::Receiver<MaintenanceStatus> {
+    let (status_tx, status_rx) = mpsc::channel();
+    let config = patcher_config.maintenance.clone();
+    let ctx = ctx.clone();
+    std::thread::spawn(move || {
+        if let Err(e) = maintenance_thread_routine(config, status_tx, || ctx.request_repaint()) {
+            log::warn!("Failed to poll the maintenance status: {:#}", e);
+        }
+    });
+    status_rx
+}
+
+/// Forwards the statuses sent by the patcher thread, repainting the window
+/// as they arrive.
+///
+/// The window is only repainted on input otherwise, and statuses would wait
+/// in the channel until the user moves the mouse.
+fn wake_on_status(
+    ctx: &egui::Context,
+    status_rx: mpsc::Receiver<PatchingStatus>,
+) -> mpsc::Receiver<PatchingStatus> {
+    let (forwarded_status_tx, forwarded_status_rx) = mpsc::channel();
+    let ctx = ctx.clone();
+    std::thread::spawn(move || {
+        for status in status_rx {
+            if forwarded_status_tx.send(status).is_err() {
+                break;
+            }
+            ctx.request_repaint();
+        }
+    });
+    forwarded_status_rx
+}
+
+/// Listens to the requests of the instances of the patcher started later, in
+/// the background.
+fn spawn_instance_listener(
+    ctx: &egui::Context,
+    instance_listener: InstanceListener,
+) -> mpsc::Receiver<InstanceMessage> {
+    let (message_tx, message_rx) = mpsc::channel();
+    let ctx = ctx.clone();
+    instance_listener.spawn(move |message| {
+        let _ = message_tx.send(message);
+        ctx.request_repaint();
+    });
+    message_rx
+}
+
+/// Watches the program started by `button` from another thread.
+///
+/// Whether updates should be checked for is sent once the program exits.
+fn watch_program_exit(
+    ctx: &egui::Context,
+    button: &LaunchButtonConfiguration,
+) -> mpsc::Receiver<bool> {
+    // Launchers might take a while to start the game
+    const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+    let (exit_tx, exit_rx) = mpsc::channel();
+    let exe_path = button.path.clone();
+    let check_for_updates = button.check_for_updates_on_return;
+    let ctx = ctx.clone();
+    std::thread::spawn(move || {
+        if let Err(e) = wait_for_processes_exit(&exe_path, STARTUP_TIMEOUT) {
+            log::warn!("Failed to watch '{}': {:#}", exe_path, e);
+        }
+        let _ = exit_tx.send(check_for_updates);
+        ctx.request_repaint();
+    });
+    exit_rx
+}
+
+/// Overrides the label read by screen readers, for widgets whose text isn't
+/// meaningful on its own (e.g. icons).
+pub(super) fn set_accessible_label(response: &egui::Response, label: &str) {
+    response.widget_info(|| egui::WidgetInfo {
+        enabled: response.enabled,
+        ..egui::WidgetInfo::labeled(egui::WidgetType::Button, label)
+    });
+}
+
+/// Adds a progress bar, exposing its text and progress to screen readers.
+fn add_progress_bar(ui: &mut egui::Ui, progress: f32, text: &str) {
+    let response = ui.add(egui::ProgressBar::new(progress).text(text));
+    response.widget_info(|| egui::WidgetInfo {
+        value: Some(f64::from(progress)),
+        ..egui::WidgetInfo::labeled(egui::WidgetType::Other, text)
+    });
+}
+
+fn is_patch_file(path: &Path) -> bool {
+    has_extension(path, "thor") || has_extension(path, "zip")
+}
+
+fn has_extension(path: &Path, extension: &str) -> bool {
+    path.extension()
+        .map(|ext| ext.eq_ignore_ascii_case(extension))
+        .unwrap_or(false)
+}
+
+/// Formats a duration in seconds as an approximate remaining time.
+fn format_remaining_time(translator: &Translator, remaining_secs: u64) -> String {
+    if remaining_secs < 60 {
+        translator.tr_args("status.eta_seconds", &[("count", &remaining_secs)])
+    } else if remaining_secs < 60 * 60 {
+        let minutes = (remaining_secs + 30) / 60;
+        translator.tr_args("status.eta_minutes", &[("count", &minutes)])
+    } else {
+        let hours = format!("{:.1}", remaining_secs as f64 / 3600.0);
+        translator.tr_args("status.eta_hours", &[("count", &hours)])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_patch_file() {
+        assert!(is_patch_file(Path::new("patches/2021-01-01.thor")));
+        assert!(is_patch_file(Path::new("PATCH.THOR")));
+        assert!(is_patch_file(Path::new("patches/textures.zip")));
+        assert!(!is_patch_file(Path::new("data.grf")));
+        assert!(!is_patch_file(Path::new("thor")));
+    }
+
+    #[test]
+    fn test_format_remaining_time() {
+        let translator = Translator::new(&Default::default());
+        assert_eq!(
+            format_remaining_time(&translator, 42),
+            translator.tr_args("status.eta_seconds", &[("count", &42)])
+        );
+        assert_eq!(
+            format_remaining_time(&translator, 359),
+            translator.tr_args("status.eta_minutes", &[("count", &6)])
+        );
+        assert_eq!(
+            format_remaining_time(&translator, 5400),
+            translator.tr_args("status.eta_hours", &[("count", &"1.5")])
+        );
+    }
+}
@@ -0,0 +1,15 @@
+use notify_rust::Notification;
+
+/// Shows a native desktop notification.
+///
+/// Failures are only logged since notifications are purely informative.
+pub fn show_notification(app_name: &str, summary: &str, body: &str) {
+    if let Err(e) = Notification::new()
+        .appname(app_name)
+        .summary(summary)
+        .body(body)
+        .show()
+    {
+        log::warn!("Failed to show notification: {}", e);
+    }
+}
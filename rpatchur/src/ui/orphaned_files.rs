@@ -0,0 +1,96 @@
+use eframe::egui;
+
+use super::{human_readable_size, OrphanedFile};
+use crate::i18n::Translator;
+
+pub enum OrphanedFilesAction {
+    Delete(Vec<String>), // Relative paths of the files to delete
+    Cancel,
+}
+
+/// Table listing the files left over by removed content, which lets users
+/// choose the ones they want to delete.
+pub struct OrphanedFilesPanel {
+    files: Vec<OrphanedFile>,
+    selection: Vec<bool>,
+}
+
+impl OrphanedFilesPanel {
+    pub fn new(files: Vec<OrphanedFile>) -> Self {
+        let selection = vec![true; files.len()];
+        Self { files, selection }
+    }
+
+    pub fn show(
+        &mut self,
+        ctx: &egui::Context,
+        translator: &Translator,
+    ) -> Option<OrphanedFilesAction> {
+        let mut action = None;
+        egui::Window::new(translator.tr("orphaned_files.title"))
+            .collapsible(false)
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                if self.files.is_empty() {
+                    ui.label(translator.tr("orphaned_files.none"));
+                    ui.add_space(10.0);
+                    if ui.button(translator.tr("button.close")).clicked() {
+                        action = Some(OrphanedFilesAction::Cancel);
+                    }
+                    return;
+                }
+                egui::ScrollArea::vertical()
+                    .max_height(200.0)
+                    .show(ui, |ui| {
+                        egui::Grid::new("orphaned_files_grid")
+                            .striped(true)
+                            .show(ui, |ui| {
+                                for (file, selected) in
+                                    self.files.iter().zip(self.selection.iter_mut())
+                                {
+                                    ui.checkbox(selected, &file.relative_path);
+                                    ui.label(human_readable_size(file.size));
+                                    ui.end_row();
+                                }
+                            });
+                    });
+
+                let selected_files: Vec<&OrphanedFile> = self
+                    .files
+                    .iter()
+                    .zip(&self.selection)
+                    .filter(|(_, &selected)| selected)
+                    .map(|(file, _)| file)
+                    .collect();
+                let selected_size: u64 = selected_files.iter().map(|file| file.size).sum();
+                ui.add_space(5.0);
+                ui.label(translator.tr_args(
+                    "orphaned_files.summary",
+                    &[
+                        ("selected", &selected_files.len()),
+                        ("total", &self.files.len()),
+                        ("size", &human_readable_size(selected_size)),
+                    ],
+                ));
+
+                ui.add_space(10.0);
+                ui.horizontal(|ui| {
+                    let delete_button = egui::Button::new(translator.tr("button.delete_selected"));
+                    if ui
+                        .add_enabled(!selected_files.is_empty(), delete_button)
+                        .clicked()
+                    {
+                        let paths = selected_files
+                            .iter()
+                            .map(|file| file.relative_path.clone())
+                            .collect();
+                        action = Some(OrphanedFilesAction::Delete(paths));
+                    }
+                    if ui.button(translator.tr("button.cancel")).clicked() {
+                        action = Some(OrphanedFilesAction::Cancel);
+                    }
+                });
+            });
+        action
+    }
+}
@@ -0,0 +1,54 @@
+use eframe::egui;
+
+use super::{PatchOutcome, PatchResult};
+use crate::i18n::Translator;
+
+/// Lists what became of each patch of the last update, once some of them
+/// failed to download.
+pub fn show_patch_results(ui: &mut egui::Ui, results: &[PatchResult], translator: &Translator) {
+    let failed_count = results
+        .iter()
+        .filter(|result| matches!(result.outcome, PatchOutcome::Failed(_)))
+        .count();
+    let summary = translator.tr_args(
+        "patch_results.summary",
+        &[("failed", &failed_count), ("total", &results.len())],
+    );
+    egui::CollapsingHeader::new(summary)
+        .id_source("patch_results")
+        .show(ui, |ui| {
+            egui::ScrollArea::vertical()
+                .max_height(150.0)
+                .show(ui, |ui| {
+                    egui::Grid::new("patch_results_grid")
+                        .striped(true)
+                        .show(ui, |ui| {
+                            ui.strong(translator.tr("patch_selection.index"));
+                            ui.strong(translator.tr("patch_selection.name"));
+                            ui.strong(translator.tr("patch_results.outcome"));
+                            ui.end_row();
+                            for result in results {
+                                ui.label(result.index.to_string());
+                                ui.label(&result.file_name);
+                                match &result.outcome {
+                                    PatchOutcome::Applied => {
+                                        ui.label(translator.tr("patch_results.applied"))
+                                    }
+                                    PatchOutcome::Pending => {
+                                        ui.label(translator.tr("patch_results.pending"))
+                                    }
+                                    PatchOutcome::Failed(error) => ui
+                                        .label(
+                                            egui::RichText::new(
+                                                translator.tr("patch_results.failed"),
+                                            )
+                                            .color(egui::Color32::RED),
+                                        )
+                                        .on_hover_text(error),
+                                };
+                                ui.end_row();
+                            }
+                        });
+                });
+        });
+}
@@ -0,0 +1,112 @@
+use eframe::egui;
+
+use super::{human_readable_size, PatchPreview};
+use crate::i18n::Translator;
+
+pub enum PatchSelectionAction {
+    Confirm { selection: Vec<bool>, apply: bool },
+    Cancel,
+}
+
+/// Table listing the pending patches, which lets users choose the ones they
+/// want to download.
+pub struct PatchSelectionPanel {
+    patches: Vec<PatchPreview>,
+    selection: Vec<bool>,
+}
+
+impl PatchSelectionPanel {
+    pub fn new(patches: Vec<PatchPreview>) -> Self {
+        let selection = vec![true; patches.len()];
+        Self { patches, selection }
+    }
+
+    pub fn show(
+        &mut self,
+        ctx: &egui::Context,
+        translator: &Translator,
+    ) -> Option<PatchSelectionAction> {
+        let mut action = None;
+        egui::Window::new(translator.tr("patch_selection.title"))
+            .collapsible(false)
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                egui::ScrollArea::vertical()
+                    .max_height(200.0)
+                    .show(ui, |ui| {
+                        egui::Grid::new("patch_selection_grid")
+                            .striped(true)
+                            .show(ui, |ui| {
+                                ui.label("");
+                                ui.strong(translator.tr("patch_selection.index"));
+                                ui.strong(translator.tr("patch_selection.name"));
+                                ui.strong(translator.tr("patch_selection.size"));
+                                ui.end_row();
+                                for (patch, selected) in
+                                    self.patches.iter().zip(self.selection.iter_mut())
+                                {
+                                    let checked = *selected;
+                                    ui.checkbox(selected, "").widget_info(|| {
+                                        egui::WidgetInfo::selected(
+                                            egui::WidgetType::Checkbox,
+                                            checked,
+                                            &patch.file_name,
+                                        )
+                                    });
+                                    ui.label(patch.index.to_string());
+                                    ui.label(&patch.file_name);
+                                    ui.label(
+                                        patch.size.map(human_readable_size).unwrap_or_default(),
+                                    );
+                                    ui.end_row();
+                                }
+                            });
+                    });
+
+                let selected_sizes = self
+                    .patches
+                    .iter()
+                    .zip(&self.selection)
+                    .filter(|(_, &selected)| selected)
+                    .map(|(patch, _)| patch.size);
+                let selected_count = selected_sizes.clone().count();
+                ui.add_space(5.0);
+                let mut summary = translator.tr_args(
+                    "patch_selection.summary",
+                    &[
+                        ("selected", &selected_count),
+                        ("total", &self.patches.len()),
+                    ],
+                );
+                if let Some(total_size) = selected_sizes.sum::<Option<u64>>() {
+                    summary += &format!(" ({})", human_readable_size(total_size));
+                }
+                ui.label(summary);
+                // Patch indices are tracked, not individual patches
+                ui.small(translator.tr("patch_selection.skip_warning"));
+
+                ui.add_space(10.0);
+                ui.horizontal(|ui| {
+                    if ui
+                        .button(translator.tr("button.download_and_apply"))
+                        .clicked()
+                    {
+                        action = Some(PatchSelectionAction::Confirm {
+                            selection: self.selection.clone(),
+                            apply: true,
+                        });
+                    }
+                    if ui.button(translator.tr("button.download_only")).clicked() {
+                        action = Some(PatchSelectionAction::Confirm {
+                            selection: self.selection.clone(),
+                            apply: false,
+                        });
+                    }
+                    if ui.button(translator.tr("button.cancel")).clicked() {
+                        action = Some(PatchSelectionAction::Cancel);
+                    }
+                });
+            });
+        action
+    }
+}
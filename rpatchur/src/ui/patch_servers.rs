@@ -0,0 +1,113 @@
+use eframe::egui;
+
+use super::PatchServerHealth;
+use crate::i18n::Translator;
+
+pub enum PatchServersAction {
+    Probe,
+    Use(Option<String>), // Name of the server to use, picked automatically if not set
+}
+
+/// Diagnostics of the patch servers, which let players see for themselves
+/// that a mirror is down and use another one.
+pub struct PatchServersView {
+    server_names: Vec<String>,      // In the order of the configuration
+    health: Vec<PatchServerHealth>, // Empty until the servers have been probed
+    probing: bool,
+}
+
+impl PatchServersView {
+    pub fn new(server_names: Vec<String>) -> Self {
+        Self {
+            server_names,
+            health: vec![],
+            probing: false,
+        }
+    }
+
+    pub fn set_probing(&mut self) {
+        self.probing = true;
+    }
+
+    pub fn set_health(&mut self, health: Vec<PatchServerHealth>) {
+        self.health = health;
+        self.probing = false;
+    }
+
+    /// Servers can't be probed while `patching_in_progress`, since the
+    /// patching thread is busy.
+    pub fn show(
+        &self,
+        ui: &mut egui::Ui,
+        translator: &Translator,
+        patching_in_progress: bool,
+        forced_server: Option<&str>,
+    ) -> Option<PatchServersAction> {
+        let mut action = None;
+        egui::Grid::new("patch_servers_grid")
+            .striped(true)
+            .show(ui, |ui| {
+                ui.strong(translator.tr("patch_servers.name"));
+                ui.strong(translator.tr("patch_servers.status"));
+                ui.strong(translator.tr("patch_servers.latency"));
+                ui.end_row();
+                for name in &self.server_names {
+                    ui.label(name);
+                    match self.health.iter().find(|health| &health.name == name) {
+                        Some(health) => {
+                            if health.is_available() {
+                                ui.colored_label(
+                                    egui::Color32::GREEN,
+                                    translator.tr("patch_servers.reachable"),
+                                );
+                            } else {
+                                ui.colored_label(
+                                    egui::Color32::RED,
+                                    translator.tr("patch_servers.unreachable"),
+                                );
+                            }
+                            ui.label(format!("{} ms", health.latency_ms));
+                        }
+                        None => {
+                            ui.label(translator.tr("patch_servers.unknown"));
+                            ui.label("-");
+                        }
+                    }
+                    let forced = forced_server == Some(name.as_str());
+                    if ui
+                        .selectable_label(forced, translator.tr("patch_servers.use"))
+                        .clicked()
+                    {
+                        // Clicking the server in use again goes back to picking
+                        // servers automatically
+                        let server = if forced { None } else { Some(name.clone()) };
+                        action = Some(PatchServersAction::Use(server));
+                    }
+                    ui.end_row();
+                }
+            });
+        // Errors are too long to fit in the grid
+        for health in &self.health {
+            if let Some(error) = &health.error {
+                ui.small(format!("{}: {}", health.name, error));
+            }
+        }
+        ui.add_space(5.0);
+        ui.horizontal(|ui| {
+            let probe_button = egui::Button::new(translator.tr("button.probe_servers"));
+            if ui
+                .add_enabled(!patching_in_progress && !self.probing, probe_button)
+                .clicked()
+            {
+                action = Some(PatchServersAction::Probe);
+            }
+            if self.probing {
+                ui.spinner();
+            }
+            if forced_server.is_none() {
+                ui.label(translator.tr("patch_servers.automatic"));
+            }
+        });
+        action
+    }
+}
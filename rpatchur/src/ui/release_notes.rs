@@ -0,0 +1,115 @@
+use eframe::egui;
+
+use super::ReleaseNote;
+use crate::i18n::Translator;
+
+/// Window listing what changed in the patches applied by the last update.
+pub struct ReleaseNotesPanel {
+    notes: Vec<ReleaseNote>,
+}
+
+#[derive(Debug, PartialEq)]
+enum MarkdownBlock<'a> {
+    Heading(&'a str),
+    Bullet(&'a str),
+    Paragraph(&'a str),
+    Blank,
+}
+
+impl ReleaseNotesPanel {
+    pub fn new(notes: Vec<ReleaseNote>) -> Self {
+        Self { notes }
+    }
+
+    /// Returns true once players close the window.
+    pub fn show(&self, ctx: &egui::Context, translator: &Translator) -> bool {
+        let mut closed = false;
+        egui::Window::new(translator.tr("release_notes.title"))
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                egui::ScrollArea::vertical()
+                    .max_height(300.0)
+                    .show(ui, |ui| {
+                        for note in &self.notes {
+                            ui.strong(
+                                translator.tr_args(
+                                    "release_notes.patch",
+                                    &[("file_name", &note.file_name)],
+                                ),
+                            );
+                            if let Some(text) = &note.text {
+                                show_markdown(ui, text);
+                            }
+                            if let Some(url) = &note.url {
+                                ui.hyperlink_to(translator.tr("release_notes.read_online"), url);
+                            }
+                            ui.add_space(10.0);
+                        }
+                    });
+                if ui.button(translator.tr("button.close")).clicked() {
+                    closed = true;
+                }
+            });
+        closed
+    }
+}
+
+/// Renders the subset of Markdown that release notes usually need:
+/// headings, bullet lists and paragraphs.
+fn show_markdown(ui: &mut egui::Ui, text: &str) {
+    for block in parse_markdown(text) {
+        match block {
+            MarkdownBlock::Heading(heading) => {
+                ui.label(egui::RichText::new(heading).heading());
+            }
+            MarkdownBlock::Bullet(item) => {
+                ui.horizontal_wrapped(|ui| {
+                    ui.label("•");
+                    ui.label(item);
+                });
+            }
+            MarkdownBlock::Paragraph(paragraph) => {
+                ui.label(paragraph);
+            }
+            MarkdownBlock::Blank => ui.add_space(5.0),
+        }
+    }
+}
+
+fn parse_markdown(text: &str) -> Vec<MarkdownBlock<'_>> {
+    text.lines()
+        .map(|line| {
+            let line = line.trim();
+            if line.is_empty() {
+                MarkdownBlock::Blank
+            } else if line.starts_with('#') {
+                MarkdownBlock::Heading(line.trim_start_matches('#').trim_start())
+            } else if let Some(item) = line.strip_prefix("- ").or_else(|| line.strip_prefix("* ")) {
+                MarkdownBlock::Bullet(item.trim_start())
+            } else {
+                MarkdownBlock::Paragraph(line)
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_markdown() {
+        assert_eq!(
+            parse_markdown("## Fixes\n- Crash on login\n*  Typos\n\nThanks *everyone*!"),
+            vec![
+                MarkdownBlock::Heading("Fixes"),
+                MarkdownBlock::Bullet("Crash on login"),
+                MarkdownBlock::Bullet("Typos"),
+                MarkdownBlock::Blank,
+                MarkdownBlock::Paragraph("Thanks *everyone*!"),
+            ]
+        );
+    }
+}
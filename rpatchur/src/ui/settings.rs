@@ -0,0 +1,203 @@
+use eframe::egui;
+
+use crate::i18n::Translator;
+use crate::patcher::{PatcherConfiguration, UserSettings, STABLE_CHANNEL};
+
+pub enum SettingsPanelAction {
+    Save(UserSettings),
+    Cancel,
+}
+
+/// Form containing the options that users are allowed to change.
+pub struct SettingsPanel {
+    in_place: bool,
+    check_integrity: bool,
+    bandwidth_limit: u64, // In KB/s, 0 means unlimited
+    language: String,     // Empty to use the OS' language
+    auto_start: bool,
+    preview_patches: bool,
+    background_mode: bool,
+    channel: String,
+    channels: Vec<String>, // Channels to choose from, none if empty
+    // Settings that aren't edited from the panel
+    user_settings: UserSettings,
+    config: PatcherConfiguration, // Configuration the panel has been opened with
+}
+
+impl SettingsPanel {
+    pub fn new(config: &PatcherConfiguration, user_settings: &UserSettings) -> Self {
+        Self {
+            in_place: config.patching.in_place,
+            check_integrity: config.patching.check_integrity,
+            bandwidth_limit: config.patching.bandwidth_limit.unwrap_or(0),
+            language: config.localization.language.clone().unwrap_or_default(),
+            auto_start: config.patching.auto_start,
+            preview_patches: config.patching.preview_patches,
+            background_mode: config.patching.background_mode,
+            channel: config.patching.channel().to_string(),
+            channels: available_channels(&config.patching.channels),
+            user_settings: user_settings.clone(),
+            config: config.clone(),
+        }
+    }
+
+    pub fn show(
+        &mut self,
+        ctx: &egui::Context,
+        translator: &Translator,
+    ) -> Option<SettingsPanelAction> {
+        let mut action = None;
+        egui::Window::new(translator.tr("settings.title"))
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                ui.checkbox(&mut self.in_place, translator.tr("settings.in_place"));
+                ui.checkbox(
+                    &mut self.check_integrity,
+                    translator.tr("settings.check_integrity"),
+                );
+                ui.checkbox(&mut self.auto_start, translator.tr("settings.auto_start"));
+                ui.checkbox(
+                    &mut self.preview_patches,
+                    translator.tr("settings.preview_patches"),
+                );
+                ui.checkbox(
+                    &mut self.background_mode,
+                    translator.tr("settings.background_mode"),
+                );
+                if !self.channels.is_empty() {
+                    ui.horizontal(|ui| {
+                        ui.label(translator.tr("settings.channel"));
+                        egui::ComboBox::from_id_source("settings_channel")
+                            .selected_text(self.channel.as_str())
+                            .show_ui(ui, |ui| {
+                                for channel in &self.channels {
+                                    ui.selectable_value(
+                                        &mut self.channel,
+                                        channel.clone(),
+                                        channel.as_str(),
+                                    );
+                                }
+                            });
+                    });
+                }
+                ui.horizontal(|ui| {
+                    ui.label(translator.tr("settings.bandwidth_limit"));
+                    ui.add(
+                        egui::DragValue::new(&mut self.bandwidth_limit)
+                            .clamp_range(0..=u32::MAX)
+                            .suffix(" KB/s"),
+                    );
+                });
+                ui.horizontal(|ui| {
+                    ui.label(translator.tr("settings.language"));
+                    ui.add(
+                        egui::TextEdit::singleline(&mut self.language)
+                            .hint_text(translator.tr("settings.language_hint"))
+                            .desired_width(80.0),
+                    );
+                });
+                ui.add_space(10.0);
+                ui.horizontal(|ui| {
+                    if ui.button(translator.tr("button.save")).clicked() {
+                        action = Some(SettingsPanelAction::Save(self.to_user_settings()));
+                    }
+                    if ui.button(translator.tr("button.cancel")).clicked() {
+                        action = Some(SettingsPanelAction::Cancel);
+                    }
+                });
+            });
+        action
+    }
+
+    /// Returns the user settings, with the values the user changed from the
+    /// configuration. The others are left as they were.
+    fn to_user_settings(&self) -> UserSettings {
+        let config = &self.config;
+        let user_settings = self.user_settings.clone();
+        let language = self.language.trim().to_string();
+        let config_language = config.localization.language.clone().unwrap_or_default();
+        UserSettings {
+            in_place: changed_value(
+                self.in_place,
+                config.patching.in_place,
+                user_settings.in_place,
+            ),
+            check_integrity: changed_value(
+                self.check_integrity,
+                config.patching.check_integrity,
+                user_settings.check_integrity,
+            ),
+            bandwidth_limit: changed_value(
+                self.bandwidth_limit,
+                config.patching.bandwidth_limit.unwrap_or(0),
+                user_settings.bandwidth_limit,
+            ),
+            language: changed_value(language, config_language, user_settings.language),
+            auto_start: changed_value(
+                self.auto_start,
+                config.patching.auto_start,
+                user_settings.auto_start,
+            ),
+            preview_patches: changed_value(
+                self.preview_patches,
+                config.patching.preview_patches,
+                user_settings.preview_patches,
+            ),
+            background_mode: changed_value(
+                self.background_mode,
+                config.patching.background_mode,
+                user_settings.background_mode,
+            ),
+            channel: if self.channels.is_empty() {
+                None
+            } else {
+                changed_value(
+                    self.channel.clone(),
+                    config.patching.channel().to_string(),
+                    user_settings.channel,
+                )
+            },
+            ..user_settings
+        }
+    }
+}
+
+/// Returns `value` if the user changed it from `config_value`, and the value
+/// set previously otherwise.
+fn changed_value<T: PartialEq>(value: T, config_value: T, user_value: Option<T>) -> Option<T> {
+    if value != config_value {
+        Some(value)
+    } else {
+        user_value
+    }
+}
+
+/// Returns the channels players can choose from, stable first.
+fn available_channels(channels: &[String]) -> Vec<String> {
+    if channels.is_empty() {
+        return vec![];
+    }
+    let mut available_channels = vec![STABLE_CHANNEL.to_string()];
+    for channel in channels {
+        if !available_channels.contains(channel) {
+            available_channels.push(channel.clone());
+        }
+    }
+    available_channels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_changed_value() {
+        assert_eq!(changed_value(true, false, None), Some(true));
+        assert_eq!(changed_value(true, true, None), None);
+        // Values set previously are kept
+        assert_eq!(changed_value(512, 512, Some(512)), Some(512));
+        assert_eq!(changed_value(0, 512, Some(512)), Some(0));
+    }
+}
@@ -0,0 +1,150 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use eframe::egui;
+
+use crate::patcher::SkinConfiguration;
+
+#[derive(Debug, PartialEq)]
+enum SkinAction {
+    Close,
+    Minimize,
+    Drag,
+}
+
+/// Appearance of frameless windows, along with the areas which replace the
+/// window's decorations.
+pub struct Skin {
+    config: SkinConfiguration,
+    background: Option<egui::TextureHandle>,
+}
+
+impl Skin {
+    pub fn new(ctx: &egui::Context, config: SkinConfiguration) -> Self {
+        let background = config.background.as_ref().and_then(|background_path| {
+            load_image(background_path)
+                .map(|image| {
+                    ctx.load_texture("skin_background", image, egui::TextureOptions::LINEAR)
+                })
+                .map_err(|e| log::warn!("Failed to load skin background: {:#}", e))
+                .ok()
+        });
+        Self { config, background }
+    }
+
+    pub fn has_background(&self) -> bool {
+        self.background.is_some()
+    }
+
+    /// Returns true if the skin provides its own close button, in which case
+    /// the patcher doesn't draw one.
+    pub fn has_close_button(&self) -> bool {
+        self.config.close_button.is_some()
+    }
+
+    /// Paints the background image behind every panel, stretched over the
+    /// whole window.
+    pub fn paint_background(&self, ctx: &egui::Context) {
+        if let Some(texture) = &self.background {
+            let uv = egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0));
+            ctx.layer_painter(egui::LayerId::background()).image(
+                texture.id(),
+                ctx.screen_rect(),
+                uv,
+                egui::Color32::WHITE,
+            );
+        }
+    }
+
+    /// Moves, closes or minimizes the window when the corresponding areas of
+    /// the skin are used.
+    ///
+    /// Must be called once all the widgets have been added, since they take
+    /// precedence over the skin.
+    pub fn handle_window_interactions(&self, ctx: &egui::Context) {
+        if ctx.is_using_pointer() {
+            return;
+        }
+        let (pointer_pos, pressed, clicked) = ctx.input(|i| {
+            (
+                i.pointer.interact_pos(),
+                i.pointer.primary_pressed(),
+                i.pointer.primary_clicked(),
+            )
+        });
+        let pointer_pos = match pointer_pos {
+            Some(pointer_pos) => pointer_pos,
+            None => return,
+        };
+        match self.action_at(pointer_pos.x, pointer_pos.y) {
+            Some(SkinAction::Close) if clicked => {
+                ctx.send_viewport_cmd(egui::ViewportCommand::Close)
+            }
+            Some(SkinAction::Minimize) if clicked => {
+                ctx.send_viewport_cmd(egui::ViewportCommand::Minimized(true))
+            }
+            Some(SkinAction::Drag) if pressed => {
+                ctx.send_viewport_cmd(egui::ViewportCommand::StartDrag)
+            }
+            _ => {}
+        }
+    }
+
+    fn action_at(&self, x: f32, y: f32) -> Option<SkinAction> {
+        let config = &self.config;
+        if config.close_button.filter(|r| r.contains(x, y)).is_some() {
+            Some(SkinAction::Close)
+        } else if config
+            .minimize_button
+            .filter(|r| r.contains(x, y))
+            .is_some()
+        {
+            Some(SkinAction::Minimize)
+        } else if config.drag_regions.is_empty()
+            || config.drag_regions.iter().any(|r| r.contains(x, y))
+        {
+            Some(SkinAction::Drag)
+        } else {
+            None
+        }
+    }
+}
+
+pub(super) fn load_image(image_path: impl AsRef<Path>) -> Result<egui::ColorImage> {
+    let image_path = image_path.as_ref();
+    let image = image::open(image_path)
+        .with_context(|| format!("Failed to open '{}'", image_path.display()))?
+        .to_rgba8();
+    let size = [image.width() as usize, image.height() as usize];
+    Ok(egui::ColorImage::from_rgba_unmultiplied(
+        size,
+        image.as_flat_samples().as_slice(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_skin_action_at() {
+        let mut skin = Skin {
+            config: serde_yaml::from_str(
+                "close_button: { x: 750, y: 8, width: 22, height: 22 }\n\
+                 minimize_button: { x: 720, y: 8, width: 22, height: 22 }\n",
+            )
+            .unwrap(),
+            background: None,
+        };
+        assert_eq!(skin.action_at(760.0, 10.0), Some(SkinAction::Close));
+        assert_eq!(skin.action_at(730.0, 10.0), Some(SkinAction::Minimize));
+        // The whole window can be dragged by default
+        assert_eq!(skin.action_at(300.0, 400.0), Some(SkinAction::Drag));
+
+        skin.config.drag_regions =
+            serde_yaml::from_str("[{ x: 0, y: 0, width: 780, height: 40 }]").unwrap();
+        assert_eq!(skin.action_at(300.0, 20.0), Some(SkinAction::Drag));
+        assert_eq!(skin.action_at(300.0, 400.0), None);
+        assert_eq!(skin.action_at(760.0, 10.0), Some(SkinAction::Close));
+    }
+}
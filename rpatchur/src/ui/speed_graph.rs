@@ -0,0 +1,91 @@
+use std::collections::VecDeque;
+
+use eframe::egui;
+
+use super::human_readable_size;
+
+// Download speed is sampled every second
+const MAX_SAMPLES: usize = 60;
+
+/// Sparkline of the download speed over the last minute.
+#[derive(Default)]
+pub struct SpeedGraph {
+    samples: VecDeque<u64>, // Bytes per second, oldest first
+}
+
+impl SpeedGraph {
+    pub fn push(&mut self, bytes_per_sec: u64) {
+        if self.samples.len() == MAX_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(bytes_per_sec);
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    fn peak(&self) -> u64 {
+        self.samples.iter().copied().max().unwrap_or(0)
+    }
+
+    pub fn show(&self, ui: &mut egui::Ui) {
+        const GRAPH_HEIGHT: f32 = 32.0;
+        let desired_size = egui::vec2(ui.available_width(), GRAPH_HEIGHT);
+        let (rect, response) = ui.allocate_exact_size(desired_size, egui::Sense::hover());
+        let visuals = ui.visuals();
+        let painter = ui.painter_at(rect);
+        painter.rect_filled(rect, 2.0, visuals.extreme_bg_color);
+
+        let peak = self.peak().max(1) as f32;
+        let step = rect.width() / (MAX_SAMPLES - 1) as f32;
+        // Right-align the samples so that the graph scrolls to the left
+        let offset = (MAX_SAMPLES - self.samples.len()) as f32 * step;
+        let points: Vec<egui::Pos2> = self
+            .samples
+            .iter()
+            .enumerate()
+            .map(|(i, &sample)| {
+                egui::pos2(
+                    rect.left() + offset + i as f32 * step,
+                    rect.bottom() - (sample as f32 / peak) * (rect.height() - 2.0),
+                )
+            })
+            .collect();
+        painter.add(egui::Shape::line(
+            points,
+            egui::Stroke::new(1.5, visuals.selection.bg_fill),
+        ));
+
+        if let Some(&current) = self.samples.back() {
+            let description = format!(
+                "{}/s (peak: {}/s)",
+                human_readable_size(current),
+                human_readable_size(self.peak())
+            );
+            response
+                .widget_info(|| egui::WidgetInfo::labeled(egui::WidgetType::Other, &description));
+            response.on_hover_text(description);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_speed_graph_rolling_window() {
+        let mut graph = SpeedGraph::default();
+        for sample in 0..(MAX_SAMPLES as u64 + 5) {
+            graph.push(sample);
+        }
+        assert_eq!(graph.samples.len(), MAX_SAMPLES);
+        assert_eq!(graph.samples.front(), Some(&5));
+        assert_eq!(graph.peak(), MAX_SAMPLES as u64 + 4);
+    }
+}
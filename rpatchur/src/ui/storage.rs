@@ -0,0 +1,77 @@
+use eframe::egui;
+
+use super::human_readable_size;
+use crate::i18n::Translator;
+use crate::patcher::{clear_storage, retrieve_storage_usage, StorageCategory, StorageUsage};
+
+/// Shows the space taken by the patcher's files, which players can clear.
+pub struct StorageView {
+    usage: Vec<StorageUsage>,
+    message: Option<String>,
+}
+
+impl StorageView {
+    pub fn load() -> Self {
+        match retrieve_storage_usage() {
+            Ok(usage) => Self {
+                usage,
+                message: None,
+            },
+            Err(e) => Self {
+                usage: vec![],
+                message: Some(format!("{:#}", e)),
+            },
+        }
+    }
+
+    /// Files can't be cleared while `patching_in_progress`, since the update
+    /// might be using them.
+    pub fn show(&mut self, ui: &mut egui::Ui, translator: &Translator, patching_in_progress: bool) {
+        let mut cleared_category = None;
+        egui::Grid::new("storage_grid")
+            .striped(true)
+            .show(ui, |ui| {
+                ui.strong(translator.tr("storage.category"));
+                ui.strong(translator.tr("storage.size"));
+                ui.strong(translator.tr("storage.files"));
+                ui.end_row();
+                for usage in &self.usage {
+                    let category_key = format!("storage.{}", usage.category.name());
+                    ui.label(translator.tr(&category_key));
+                    ui.label(human_readable_size(usage.size));
+                    ui.label(usage.file_count.to_string());
+                    let can_clear = !patching_in_progress && usage.file_count > 0;
+                    if ui
+                        .add_enabled(can_clear, egui::Button::new(translator.tr("button.clear")))
+                        .clicked()
+                    {
+                        cleared_category = Some(usage.category);
+                    }
+                    ui.end_row();
+                }
+            });
+        if let Some(category) = cleared_category {
+            self.clear(category, translator);
+        }
+        if let Some(message) = &self.message {
+            ui.add_space(5.0);
+            ui.label(message);
+        }
+    }
+
+    pub fn set_message(&mut self, message: String) {
+        self.message = Some(message);
+    }
+
+    fn clear(&mut self, category: StorageCategory, translator: &Translator) {
+        let message = match clear_storage(category) {
+            Ok(freed_bytes) => translator.tr_args(
+                "storage.cleared",
+                &[("size", &human_readable_size(freed_bytes))],
+            ),
+            Err(e) => format!("{:#}", e),
+        };
+        *self = Self::load();
+        self.message.get_or_insert(message);
+    }
+}
@@ -0,0 +1,162 @@
+use crate::patcher::PatcherCommand;
+
+#[cfg(not(windows))]
+pub use self::unsupported::SystemTray;
+#[cfg(windows)]
+pub use self::windows::SystemTray;
+
+/// Actions that can be triggered from the tray icon's menu.
+#[cfg_attr(not(windows), allow(dead_code))]
+pub enum TrayAction {
+    ShowWindow,
+    Patcher(PatcherCommand),
+    OpenGameFolder,
+    OpenLogFile,
+    Quit,
+}
+
+#[cfg(windows)]
+mod windows {
+    use std::sync::mpsc;
+
+    use anyhow::{anyhow, Result};
+    use eframe::egui;
+    use tray_icon::menu::{Menu, MenuEvent, MenuItem, PredefinedMenuItem};
+    use tray_icon::{
+        Icon, MouseButton, MouseButtonState, TrayIcon, TrayIconBuilder, TrayIconEvent,
+    };
+
+    use super::TrayAction;
+    use crate::i18n::Translator;
+    use crate::patcher::PatcherCommand;
+    use crate::process::restore_window;
+
+    // Icon embedded by `build.rs` through winres
+    const APP_ICON_RESOURCE_ID: u16 = 1;
+
+    pub struct SystemTray {
+        _tray_icon: TrayIcon,
+        action_rx: mpsc::Receiver<TrayAction>,
+    }
+
+    impl SystemTray {
+        pub fn new(title: &str, translator: &Translator, ctx: &egui::Context) -> Result<Self> {
+            let show_item = MenuItem::new(translator.tr("tray.show"), true, None);
+            let update_item = MenuItem::new(translator.tr("button.start_update"), true, None);
+            let check_item = MenuItem::new(translator.tr("tray.check_for_updates"), true, None);
+            let cancel_item = MenuItem::new(translator.tr("button.cancel_update"), true, None);
+            let game_folder_item =
+                MenuItem::new(translator.tr("button.open_game_folder"), true, None);
+            let log_file_item = MenuItem::new(translator.tr("button.open_log_file"), true, None);
+            let quit_item = MenuItem::new(translator.tr("tray.quit"), true, None);
+            let menu = Menu::new();
+            menu.append_items(&[
+                &show_item,
+                &PredefinedMenuItem::separator(),
+                &update_item,
+                &check_item,
+                &cancel_item,
+                &PredefinedMenuItem::separator(),
+                &game_folder_item,
+                &log_file_item,
+                &PredefinedMenuItem::separator(),
+                &quit_item,
+            ])?;
+            let icon = Icon::from_resource(APP_ICON_RESOURCE_ID, None)
+                .map_err(|e| anyhow!("Failed to load tray icon: {}", e))?;
+            let tray_icon = TrayIconBuilder::new()
+                .with_menu(Box::new(menu))
+                .with_tooltip(title)
+                .with_icon(icon)
+                .build()?;
+
+            // Events are handled on the event loop's thread, which keeps
+            // running while the window is hidden (contrary to `update`)
+            let (action_tx, action_rx) = mpsc::channel();
+            let menu_action_tx = action_tx.clone();
+            let menu_ctx = ctx.clone();
+            let menu_window_title = title.to_string();
+            let (show_id, update_id, check_id, cancel_id, quit_id) = (
+                show_item.id().clone(),
+                update_item.id().clone(),
+                check_item.id().clone(),
+                cancel_item.id().clone(),
+                quit_item.id().clone(),
+            );
+            let (game_folder_id, log_file_id) =
+                (game_folder_item.id().clone(), log_file_item.id().clone());
+            MenuEvent::set_event_handler(Some(move |event: MenuEvent| {
+                let action = if event.id == show_id {
+                    TrayAction::ShowWindow
+                } else if event.id == update_id {
+                    TrayAction::Patcher(PatcherCommand::StartUpdate)
+                } else if event.id == check_id {
+                    TrayAction::Patcher(PatcherCommand::CheckForUpdates)
+                } else if event.id == cancel_id {
+                    TrayAction::Patcher(PatcherCommand::CancelUpdate)
+                } else if event.id == game_folder_id {
+                    TrayAction::OpenGameFolder
+                } else if event.id == log_file_id {
+                    TrayAction::OpenLogFile
+                } else if event.id == quit_id {
+                    TrayAction::Quit
+                } else {
+                    return;
+                };
+                if !matches!(action, TrayAction::Patcher(_)) {
+                    // The window has to be visible to process the action
+                    restore_window(&menu_window_title);
+                }
+                let _ = menu_action_tx.send(action);
+                menu_ctx.request_repaint();
+            }));
+            let icon_ctx = ctx.clone();
+            let icon_window_title = title.to_string();
+            TrayIconEvent::set_event_handler(Some(move |event: TrayIconEvent| {
+                if let TrayIconEvent::Click {
+                    button: MouseButton::Left,
+                    button_state: MouseButtonState::Up,
+                    ..
+                } = event
+                {
+                    restore_window(&icon_window_title);
+                    let _ = action_tx.send(TrayAction::ShowWindow);
+                    icon_ctx.request_repaint();
+                }
+            }));
+
+            Ok(Self {
+                _tray_icon: tray_icon,
+                action_rx,
+            })
+        }
+
+        pub fn try_recv_action(&self) -> Option<TrayAction> {
+            self.action_rx.try_recv().ok()
+        }
+    }
+}
+
+#[cfg(not(windows))]
+mod unsupported {
+    use anyhow::{anyhow, Result};
+    use eframe::egui;
+
+    use super::TrayAction;
+    use crate::i18n::Translator;
+
+    /// Tray icons are only implemented on Windows.
+    pub enum SystemTray {}
+
+    impl SystemTray {
+        pub fn new(_title: &str, _translator: &Translator, _ctx: &egui::Context) -> Result<Self> {
+            Err(anyhow!(
+                "System tray icons aren't supported on this platform"
+            ))
+        }
+
+        pub fn try_recv_action(&self) -> Option<TrayAction> {
+            match *self {}
+        }
+    }
+}